@@ -0,0 +1,112 @@
+//! Benchmarks comparing the table row codecs, both when encoding and decoding
+//! individual rows and when scanning a table stored with each codec. Run with
+//! `cargo bench --bench codec`.
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use toydb::sql::engine::{Codec, Engine as _, RowCodec as _, KV};
+use toydb::sql::execution::ResultSet;
+use toydb::sql::schema::{Column, Table};
+use toydb::sql::types::{DataType, Value};
+use toydb::storage;
+
+/// The number of rows in the benchmark tables.
+const ROWS: u64 = 10_000;
+
+/// Returns the benchmark table schema.
+fn table() -> Table {
+    let column = |name: &str, datatype| Column {
+        name: name.into(),
+        datatype,
+        primary_key: name == "id",
+        nullable: name != "id",
+        default: None,
+        unique: name == "id",
+        references: None,
+        index: false,
+        include: Vec::new(),
+    };
+    Table::new(
+        "bench".into(),
+        vec![
+            column("id", DataType::Integer),
+            column("category", DataType::Integer),
+            column("value", DataType::Float),
+            column("name", DataType::String),
+            column("active", DataType::Boolean),
+            column("note", DataType::String),
+        ],
+    )
+    .unwrap()
+}
+
+/// Returns the benchmark row with the given ID.
+fn row(i: u64) -> Vec<Value> {
+    vec![
+        Value::Integer(i as i64),
+        Value::Integer((i % 10) as i64),
+        Value::Float((i * 7919 % 1000) as f64 / 10.0),
+        Value::String(format!("name{}", i)),
+        Value::Boolean(i % 2 == 0),
+        Value::Null,
+    ]
+}
+
+fn rows(c: &mut Criterion) {
+    let table = table();
+    let row = row(123456);
+    let mut group = c.benchmark_group("codec");
+    for codec in Codec::ALL {
+        let bytes = codec.encode(&table, &row).unwrap();
+        println!("{} row size: {} bytes", codec, bytes.len());
+        group.bench_function(format!("{}_encode", codec), |b| {
+            b.iter(|| codec.encode(&table, &row).unwrap())
+        });
+        group.bench_function(format!("{}_decode", codec), |b| {
+            b.iter(|| codec.decode(&table, &bytes).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn scans(c: &mut Criterion) {
+    let mut group = c.benchmark_group("codec_scan");
+    group.throughput(Throughput::Elements(ROWS));
+    for codec in Codec::ALL {
+        let engine = KV::new(storage::engine::Memory::new());
+        let mut session = engine.session().unwrap();
+        session
+            .execute(&format!(
+                "CREATE TABLE bench (id INTEGER PRIMARY KEY, category INTEGER, value FLOAT, \
+                 name STRING, active BOOLEAN, note STRING) WITH (codec = '{}')",
+                codec
+            ))
+            .unwrap();
+        session.execute("BEGIN").unwrap();
+        for chunk in (0..ROWS).collect::<Vec<_>>().chunks(1000) {
+            let values: Vec<String> = chunk
+                .iter()
+                .map(|i| match row(*i).as_slice() {
+                    [id, category, Value::Float(value), name, active, _] => {
+                        format!(
+                            "({}, {}, {:.1}, '{}', {}, NULL)",
+                            id, category, value, name, active
+                        )
+                    }
+                    row => panic!("unexpected row {:?}", row),
+                })
+                .collect();
+            session.execute(&format!("INSERT INTO bench VALUES {}", values.join(", "))).unwrap();
+        }
+        session.execute("COMMIT").unwrap();
+
+        group.bench_function(format!("{}_scan", codec), |b| {
+            b.iter(|| match session.execute("SELECT * FROM bench").unwrap() {
+                ResultSet::Query { rows, .. } => rows.map(Result::unwrap).count(),
+                result => panic!("unexpected result {:?}", result),
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, rows, scans);
+criterion_main!(benches);
@@ -0,0 +1,67 @@
+//! Benchmarks for the SQL execution engine, running queries against an
+//! in-memory database. Run with `cargo bench --bench execution`.
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use toydb::sql::engine::{Engine as _, KV};
+use toydb::sql::execution::ResultSet;
+use toydb::storage;
+
+/// The number of rows in the benchmark table.
+const ROWS: u64 = 10_000;
+
+/// Sets up an engine with a table of ROWS rows, spread across 10 categories.
+fn setup() -> KV<storage::engine::Memory> {
+    let engine = KV::new(storage::engine::Memory::new());
+    let mut session = engine.session().unwrap();
+    session
+        .execute(
+            "CREATE TABLE bench (
+                id INTEGER PRIMARY KEY,
+                category INTEGER NOT NULL,
+                value FLOAT NOT NULL,
+                name STRING NOT NULL
+            )",
+        )
+        .unwrap();
+    session.execute("BEGIN").unwrap();
+    for chunk in (0..ROWS).collect::<Vec<_>>().chunks(1000) {
+        let values: Vec<String> = chunk
+            .iter()
+            .map(|i| {
+                format!("({}, {}, {:.1}, 'name{}')", i, i % 10, (i * 7919 % 1000) as f64 / 10.0, i)
+            })
+            .collect();
+        session.execute(&format!("INSERT INTO bench VALUES {}", values.join(", "))).unwrap();
+    }
+    session.execute("COMMIT").unwrap();
+    engine
+}
+
+fn queries(c: &mut Criterion) {
+    let engine = setup();
+    let mut session = engine.session().unwrap();
+    let mut group = c.benchmark_group("execution");
+    group.throughput(Throughput::Elements(ROWS));
+    let aggregate = "SELECT category, COUNT(*), SUM(value), AVG(value), MAX(id) FROM bench \
+                     WHERE value > 25.0 GROUP BY category";
+    for (name, parallelism, query) in [
+        ("scan", 1, "SELECT * FROM bench"),
+        ("scan_filter", 1, "SELECT id, name FROM bench WHERE value > 50.0"),
+        ("scan_project", 1, "SELECT id * 2, value / 2.0, name FROM bench"),
+        ("scan_filter_aggregate", 1, aggregate),
+        ("scan_filter_aggregate_parallel", 4, aggregate),
+        ("order_limit", 1, "SELECT id, value FROM bench ORDER BY value DESC LIMIT 10 OFFSET 10"),
+        ("hash_join", 1, "SELECT a.id, b.id FROM bench a JOIN bench b ON a.id = b.id"),
+    ] {
+        session.execute(&format!("SET parallelism = {}", parallelism)).unwrap();
+        group.bench_function(name, |b| {
+            b.iter(|| match session.execute(query).unwrap() {
+                ResultSet::Query { rows, .. } => rows.map(Result::unwrap).count(),
+                result => panic!("unexpected result {:?}", result),
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, queries);
+criterion_main!(benches);
@@ -0,0 +1,113 @@
+//! Benchmarks for the MVCC layer: transaction throughput under write
+//! contention, and scan throughput over keys with many versions. Run with
+//! `cargo bench --bench mvcc`.
+//!
+//! toyDB doesn't garbage collect old versions (see storage::mvcc), so the
+//! versions benchmarks measure the cost of retaining them: reads must skip
+//! past them, which is what garbage collection would save.
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rand::{Rng as _, SeedableRng as _};
+use toydb::error::Error;
+use toydb::storage::engine::Memory;
+use toydb::storage::mvcc::{Transaction, MVCC};
+
+/// The number of keys in the benchmark keyspace.
+const KEYS: u64 = 1_000;
+
+/// The number of transactions run by each contention benchmark iteration.
+const TXNS: u64 = 1_000;
+
+/// Returns the key with the given index.
+fn key(i: u64) -> Vec<u8> {
+    i.to_be_bytes().to_vec()
+}
+
+/// Returns an MVCC store with KEYS keys, each written by the given number of
+/// transactions.
+fn setup(versions: u64) -> MVCC<Memory> {
+    let mvcc = MVCC::new(Memory::new());
+    for v in 0..versions {
+        let txn = mvcc.begin().unwrap();
+        for i in 0..KEYS {
+            txn.set(&key(i), v.to_be_bytes().to_vec()).unwrap();
+        }
+        txn.commit().unwrap();
+    }
+    mvcc
+}
+
+/// Runs TXNS read-modify-write transactions in pairs of two concurrent
+/// transactions, where the pair writes the same key with the given probability
+/// and otherwise different keys. The second transaction of a conflicting pair
+/// fails with a serialization error, and is retried after the first commits.
+fn contention(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mvcc_contention");
+    group.throughput(Throughput::Elements(TXNS));
+    for conflict_percent in [0, 10, 50, 100] {
+        group.bench_function(format!("conflict_{}", conflict_percent), |b| {
+            b.iter_batched(
+                || (setup(1), rand::rngs::StdRng::seed_from_u64(0)),
+                |(mvcc, mut rng)| {
+                    let increment = |txn: &Transaction<Memory>, k: u64| {
+                        let value = txn.get(&key(k))?.unwrap();
+                        txn.set(&key(k), value)
+                    };
+                    for _ in 0..TXNS / 2 {
+                        let k1 = rng.gen_range(0..KEYS);
+                        let k2 = match rng.gen_range(0..100) < conflict_percent {
+                            true => k1,
+                            false => (k1 + 1) % KEYS,
+                        };
+                        let (t1, t2) = (mvcc.begin().unwrap(), mvcc.begin().unwrap());
+                        increment(&t1, k1).unwrap();
+                        match increment(&t2, k2) {
+                            Ok(()) => {
+                                t1.commit().unwrap();
+                                t2.commit().unwrap();
+                            }
+                            Err(Error::Serialization) => {
+                                t2.rollback().unwrap();
+                                t1.commit().unwrap();
+                                let retry = mvcc.begin().unwrap();
+                                increment(&retry, k2).unwrap();
+                                retry.commit().unwrap();
+                            }
+                            Err(err) => panic!("unexpected error {}", err),
+                        }
+                    }
+                    mvcc
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Scans and reads all keys with a varying number of committed versions per
+/// key.
+fn versions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mvcc_versions");
+    group.throughput(Throughput::Elements(KEYS));
+    for versions in [1, 10, 100] {
+        let mvcc = setup(versions);
+        group.bench_function(format!("scan_{}", versions), |b| {
+            b.iter(|| {
+                let txn = mvcc.begin_read_only().unwrap();
+                assert_eq!(txn.scan(..).unwrap().iter().count() as u64, KEYS);
+            })
+        });
+        group.bench_function(format!("get_{}", versions), |b| {
+            b.iter(|| {
+                let txn = mvcc.begin_read_only().unwrap();
+                for i in 0..KEYS {
+                    assert!(txn.get(&key(i)).unwrap().is_some());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, contention, versions);
+criterion_main!(benches);
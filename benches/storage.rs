@@ -0,0 +1,144 @@
+//! Benchmarks for the key/value storage engines, using a harness that's generic
+//! over storage::engine::Engine such that a new engine only needs a benchmark
+//! function calling bench_engine() to be covered. Run with
+//! `cargo bench --bench storage`.
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rand::{Rng as _, SeedableRng as _};
+use toydb::storage::engine::{BitCask, Engine, Memory};
+
+/// The number of keys written and read by each benchmark iteration.
+const KEYS: u64 = 10_000;
+
+/// The size of values in bytes.
+const VALUE_SIZE: usize = 64;
+
+/// Returns the key with the given index. Keys are big-endian encoded, so their
+/// lexicographical order matches the index order.
+fn key(i: u64) -> Vec<u8> {
+    i.to_be_bytes().to_vec()
+}
+
+/// Returns KEYS key indexes in a fixed pseudo-random order.
+fn random_order() -> Vec<u64> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let mut order: Vec<u64> = (0..KEYS).collect();
+    for i in (1..order.len()).rev() {
+        order.swap(i, rng.gen_range(0..=i));
+    }
+    order
+}
+
+/// Returns an engine with KEYS keys written in order.
+fn populate<E: Engine>(mut engine: E) -> E {
+    for i in 0..KEYS {
+        engine.set(&key(i), vec![0xff; VALUE_SIZE]).unwrap();
+    }
+    engine.flush().unwrap();
+    engine
+}
+
+/// Runs the benchmarks against an engine created by new(), which is called
+/// for every iteration that needs an empty engine. Setup isn't measured.
+fn bench_engine<E: Engine>(c: &mut Criterion, name: &str, new: impl Fn() -> E) {
+    let random = random_order();
+    let mut group = c.benchmark_group(format!("storage_{}", name));
+    group.throughput(Throughput::Elements(KEYS));
+
+    group.bench_function("set_sequential", |b| {
+        b.iter_batched(
+            &new,
+            |mut engine| {
+                for i in 0..KEYS {
+                    engine.set(&key(i), vec![0xff; VALUE_SIZE]).unwrap();
+                }
+                engine.flush().unwrap();
+                engine
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.bench_function("set_random", |b| {
+        b.iter_batched(
+            &new,
+            |mut engine| {
+                for i in &random {
+                    engine.set(&key(*i), vec![0xff; VALUE_SIZE]).unwrap();
+                }
+                engine.flush().unwrap();
+                engine
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    let mut engine = populate(new());
+    group.bench_function("get_random", |b| {
+        b.iter(|| {
+            for i in &random {
+                assert!(engine.get(&key(*i)).unwrap().is_some());
+            }
+        })
+    });
+
+    group.bench_function("scan", |b| {
+        b.iter(|| assert_eq!(engine.scan(..).map(Result::unwrap).count() as u64, KEYS))
+    });
+
+    // Takes a snapshot before every write, such that each write must preserve
+    // the snapshot's view of the in-memory index.
+    group.bench_function("set_random_snapshot", |b| {
+        b.iter(|| {
+            for i in &random {
+                let _snapshot = engine.snapshot().unwrap();
+                engine.set(&key(*i), vec![0xff; VALUE_SIZE]).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+/// Benchmarks the in-memory engine.
+fn memory(c: &mut Criterion) {
+    bench_engine(c, "memory", Memory::new);
+}
+
+/// Benchmarks the BitCask engine, including compaction of a log where every
+/// key has been overwritten once, i.e. half of the log is garbage.
+fn bitcask(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bitcask");
+    let new = || {
+        let _ = std::fs::remove_file(&path);
+        BitCask::new(path.clone()).unwrap()
+    };
+    bench_engine(c, "bitcask", new);
+    bench_engine(c, "bitcask_mmap", || new().with_mmap(true).unwrap());
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    bench_engine(c, "bitcask_io_uring", || new().with_io_uring(true).unwrap());
+
+    let mut group = c.benchmark_group("storage_bitcask");
+    group.throughput(Throughput::Elements(KEYS));
+    group.sample_size(10);
+    group.bench_function("compact", |b| {
+        b.iter_batched(
+            || {
+                let mut engine = populate(new());
+                for i in 0..KEYS {
+                    engine.set(&key(i), vec![0xee; VALUE_SIZE]).unwrap();
+                }
+                engine.flush().unwrap();
+                engine
+            },
+            |mut engine| {
+                engine.compact().unwrap();
+                engine
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, memory, bitcask);
+criterion_main!(benches);
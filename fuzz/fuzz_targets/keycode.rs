@@ -0,0 +1,21 @@
+//! Fuzzes KeyCode decoding with arbitrary input. It must return either a value
+//! or an error, and never panic. Decoded values must reencode to a canonical
+//! key that decodes and reencodes to itself.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use toydb::encoding::keycode;
+use toydb::sql::types::Value;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(values) = keycode::deserialize::<Vec<Value>>(data) {
+        let key = keycode::serialize(&values).unwrap();
+        let values: Vec<Value> = keycode::deserialize(&key).unwrap();
+        assert_eq!(keycode::serialize(&values).unwrap(), key);
+    }
+    if let Ok(tuple) = keycode::deserialize::<(Option<i64>, String, f64)>(data) {
+        let key = keycode::serialize(&tuple).unwrap();
+        let tuple: (Option<i64>, String, f64) = keycode::deserialize(&key).unwrap();
+        assert_eq!(keycode::serialize(&tuple).unwrap(), key);
+    }
+});
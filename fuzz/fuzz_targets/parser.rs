@@ -0,0 +1,12 @@
+//! Fuzzes the SQL parser with arbitrary input. It must return either a
+//! statement or an error, and never panic, hang, or overflow the stack.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use toydb::sql::parser::Parser;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(query) = std::str::from_utf8(data) {
+        _ = Parser::new(query).parse();
+    }
+});
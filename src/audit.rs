@@ -0,0 +1,299 @@
+//! Audit logging: records DDL statements and privileged operations, along with
+//! who ran them, when, and from where, to a dedicated append-only log for
+//! compliance purposes.
+//!
+//! Each node writes its own audit log in a directory of segment files, named by
+//! the sequence number of their first record. Records are JSON lines, and are
+//! synced to disk as they're written. A segment is closed once it reaches the
+//! maximum size, and the oldest segments are deleted beyond the maximum number
+//! of files.
+//!
+//! The log is tamper-evident: each record includes the SHA-256 hash of the
+//! previous record, and its own hash covering all of its fields, forming a hash
+//! chain. Modifying, inserting, reordering, or removing records (other than at
+//! the end of the log) breaks the chain, which verify() detects. The chain
+//! continues across segments, and deleted segments only cut off its start.
+
+use crate::encoding::json;
+use crate::error::{Error, Result};
+
+use sha2::{Digest as _, Sha256};
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead as _, BufReader, Write as _};
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// The hash preceding the first record.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// The field separating a record's hashed contents from its hash.
+const HASH_FIELD: &str = ",\"hash\":\"";
+
+/// An audited operation.
+pub struct Event<'a> {
+    /// The client's network address.
+    pub client: &'a str,
+    /// The client's session ID on the server.
+    pub session: u64,
+    /// The kind of operation, e.g. CREATE TABLE or PROMOTE.
+    pub action: &'a str,
+    /// The SQL statement, if any.
+    pub statement: Option<&'a str>,
+    /// The error, if the operation failed.
+    pub error: Option<&'a str>,
+}
+
+/// An audit log, see module documentation.
+pub struct Log {
+    /// The audit log directory.
+    dir: PathBuf,
+    /// The size in bytes at which a segment is closed, or 0 for no limit.
+    max_size: u64,
+    /// The maximum number of segment files to keep, or 0 to keep all.
+    max_files: usize,
+    /// The segment file to append the next record to, and its size, or None
+    /// if a new segment must be created.
+    file: Option<(File, u64)>,
+    /// The sequence number of the last record, or 0 if none.
+    seq: u64,
+    /// The hash of the last record.
+    hash: String,
+}
+
+impl Log {
+    /// Opens or creates an audit log in the given directory, with the given
+    /// maximum segment size in bytes and number of segment files (0 for no
+    /// limit). An incomplete record at the end of the last segment, e.g. after
+    /// a crash, is truncated.
+    pub fn new(dir: PathBuf, max_size: u64, max_files: usize) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let mut log =
+            Self { dir, max_size, max_files, file: None, seq: 0, hash: GENESIS_HASH.into() };
+        if let Some((_, path)) = Self::segments(&log.dir)?.pop() {
+            let content = std::fs::read_to_string(&path)?;
+            let len = content.rfind('\n').map_or(0, |i| i + 1);
+            let file = OpenOptions::new().append(true).open(&path)?;
+            if len < content.len() {
+                log::error!("Found incomplete audit record in {}, truncating", path.display());
+                file.set_len(len as u64)?;
+            }
+            if let Some(line) = content[..len].lines().last() {
+                let record = Record::parse(line)?;
+                log.seq = record.seq;
+                log.hash = record.hash.to_string();
+            }
+            if log.max_size == 0 || (len as u64) < log.max_size {
+                log.file = Some((file, len as u64));
+            }
+        }
+        Ok(log)
+    }
+
+    /// Appends a record of an event to the log, and syncs it to disk.
+    pub fn append(&mut self, event: &Event) -> Result<()> {
+        let seq = self.seq + 1;
+        let time = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .map_err(|err| Error::Internal(err.to_string()))?;
+        let optional = |s: Option<&str>| s.map_or("null".to_string(), json::quote);
+        let body = format!(
+            "{{\"seq\":{},\"time\":{},\"client\":{},\"session\":{},\"action\":{},\"statement\":{},\"error\":{},\"prev\":\"{}\"",
+            seq,
+            json::quote(&time),
+            json::quote(event.client),
+            event.session,
+            json::quote(event.action),
+            optional(event.statement),
+            optional(event.error),
+            self.hash,
+        );
+        let hash = hex::encode(Sha256::digest(body.as_bytes()));
+        let line = format!("{}{}{}\"}}\n", body, HASH_FIELD, hash);
+
+        // Create a new segment if necessary, deleting the oldest segments
+        // beyond the maximum number of files.
+        let (mut file, mut size) = match self.file.take() {
+            Some(file) => file,
+            None => {
+                let segments = Self::segments(&self.dir)?;
+                if self.max_files > 0 && segments.len() >= self.max_files {
+                    for (_, path) in &segments[..=segments.len() - self.max_files] {
+                        std::fs::remove_file(path)?;
+                    }
+                }
+                let path = self.dir.join(format!("{:020}.log", seq));
+                (OpenOptions::new().create_new(true).append(true).open(path)?, 0)
+            }
+        };
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        size += line.len() as u64;
+        (self.seq, self.hash) = (seq, hash);
+
+        // Close the segment once it's full.
+        if self.max_size == 0 || size < self.max_size {
+            self.file = Some((file, size));
+        }
+        Ok(())
+    }
+
+    /// Verifies the hash chain of the audit log in the given directory,
+    /// returning the number of verified records and the hash of the last one,
+    /// or an error describing the first broken link. The first record of the
+    /// oldest segment is trusted to follow the previous record, which may have
+    /// been deleted. Recording the last hash elsewhere allows also detecting
+    /// later changes to the end of the log.
+    pub fn verify(dir: &Path) -> Result<(u64, String)> {
+        let (mut count, mut last): (u64, Option<(u64, String)>) = (0, None);
+        for (first_seq, path) in Self::segments(dir)? {
+            let file = BufReader::new(File::open(&path)?);
+            for (i, line) in file.lines().enumerate() {
+                let line = line?;
+                let invalid = |message: String| {
+                    Error::Value(format!(
+                        "Audit log {} line {}: {}",
+                        path.display(),
+                        i + 1,
+                        message
+                    ))
+                };
+                let record = Record::parse(&line).map_err(|err| invalid(err.to_string()))?;
+                let expect_seq = last.as_ref().map_or(first_seq, |(seq, _)| seq + 1);
+                if record.seq != expect_seq {
+                    return Err(invalid(format!(
+                        "expected sequence number {}, found {}",
+                        expect_seq, record.seq
+                    )));
+                }
+                if let Some((_, hash)) = &last {
+                    if record.prev != hash {
+                        return Err(invalid("previous hash does not match".into()));
+                    }
+                }
+                if hex::encode(Sha256::digest(record.body.as_bytes())) != record.hash {
+                    return Err(invalid("hash does not match contents".into()));
+                }
+                last = Some((record.seq, record.hash.to_string()));
+                count += 1;
+            }
+        }
+        Ok((count, last.map_or(GENESIS_HASH.into(), |(_, hash)| hash)))
+    }
+
+    /// Returns the segment files in the given directory and the sequence
+    /// number of their first record, in order.
+    fn segments(dir: &Path) -> Result<Vec<(u64, PathBuf)>> {
+        let mut segments = Vec::new();
+        for file in std::fs::read_dir(dir)? {
+            let path = file?.path();
+            if path.extension() != Some(OsStr::new("log")) {
+                continue;
+            }
+            let Some(first_seq) = path.file_stem().and_then(|s| s.to_str()?.parse().ok()) else {
+                return Err(Error::Internal(format!("Invalid audit log file {}", path.display())));
+            };
+            segments.push((first_seq, path));
+        }
+        segments.sort();
+        Ok(segments)
+    }
+}
+
+/// The chain fields of an encoded audit record.
+struct Record<'a> {
+    seq: u64,
+    prev: &'a str,
+    hash: &'a str,
+    /// The hashed contents, i.e. the record up to the hash field.
+    body: &'a str,
+}
+
+impl<'a> Record<'a> {
+    /// Parses the chain fields of an encoded record. The sequence number is
+    /// the first field, and the previous hash and hash the last ones.
+    fn parse(line: &'a str) -> Result<Self> {
+        let invalid = || Error::Value("Invalid audit record".into());
+        let (body, hash) = line.rsplit_once(HASH_FIELD).ok_or_else(invalid)?;
+        let hash = hash.strip_suffix("\"}").ok_or_else(invalid)?;
+        let (_, prev) = body.rsplit_once(",\"prev\":\"").ok_or_else(invalid)?;
+        let prev = prev.strip_suffix('"').ok_or_else(invalid)?;
+        let seq = body.strip_prefix("{\"seq\":").ok_or_else(invalid)?;
+        let seq = seq[..seq.find(',').ok_or_else(invalid)?].parse().map_err(|_| invalid())?;
+        Ok(Self { seq, prev, hash, body })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(action: &str) -> Event {
+        Event {
+            client: "127.0.0.1:5000",
+            session: 1,
+            action,
+            statement: Some("CREATE TABLE \"t\" (id INTEGER PRIMARY KEY)"),
+            error: None,
+        }
+    }
+
+    #[test]
+    // Records are chained across segments and reopening, and the oldest
+    // segments are deleted beyond the maximum number of files.
+    fn append_rotate() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let mut log = Log::new(dir.path().into(), 1, 3)?;
+        log.append(&event("CREATE TABLE"))?;
+        log.append(&event("DROP TABLE"))?;
+        drop(log);
+        let mut log = Log::new(dir.path().into(), 1, 3)?;
+        log.append(&event("PROMOTE"))?;
+        log.append(&event("KILL"))?;
+
+        let segments: Vec<_> = Log::segments(dir.path())?.into_iter().map(|(s, _)| s).collect();
+        assert_eq!(segments, vec![2, 3, 4]);
+        assert_eq!(Log::verify(dir.path())?.0, 3);
+
+        // Without a size limit, records are appended to the last segment.
+        let mut log = Log::new(dir.path().into(), 0, 0)?;
+        log.append(&event("KILL"))?;
+        let content = std::fs::read_to_string(dir.path().join(format!("{:020}.log", 4)))?;
+        assert_eq!(content.lines().count(), 2);
+        assert!(content.contains(r#""action":"KILL","statement":"CREATE TABLE \"t\" (id INTEGER PRIMARY KEY)","error":null,"prev":""#));
+        assert_eq!(Log::verify(dir.path())?, (4, log.hash.clone()));
+        Ok(())
+    }
+
+    #[test]
+    // Modified, removed, and incomplete records are detected.
+    fn verify() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let mut log = Log::new(dir.path().into(), 0, 0)?;
+        for action in ["CREATE TABLE", "DROP TABLE", "KILL"] {
+            log.append(&event(action))?;
+        }
+        drop(log);
+        let path = dir.path().join(format!("{:020}.log", 1));
+        let content = std::fs::read_to_string(&path)?;
+        let lines: Vec<_> = content.lines().collect();
+
+        std::fs::write(&path, content.replace("DROP TABLE", "DROP INDEX"))?;
+        assert!(
+            matches!(Log::verify(dir.path()), Err(Error::Value(m)) if m.ends_with("line 2: hash does not match contents"))
+        );
+
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[2]))?;
+        assert!(
+            matches!(Log::verify(dir.path()), Err(Error::Value(m)) if m.ends_with("line 2: expected sequence number 2, found 3"))
+        );
+
+        // An incomplete record is truncated when opened.
+        std::fs::write(&path, format!("{}\n{}\n{}", lines[0], lines[1], &lines[2][..10]))?;
+        let mut log = Log::new(dir.path().into(), 0, 0)?;
+        log.append(&event("KILL"))?;
+        assert_eq!(Log::verify(dir.path())?.0, 3);
+        Ok(())
+    }
+}
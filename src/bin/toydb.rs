@@ -2,17 +2,55 @@
  * toydb is the toyDB server. It takes configuration via a configuration file, command-line
  * parameters, and environment variables, then starts up a toyDB TCP server that communicates with
  * SQL clients (port 9605) and Raft peers (port 9705).
+ *
+ * For local development, `toydb init` generates node configs and data directories for a local
+ * cluster, and `toydb start-local-cluster` runs and supervises one. If archive_dir is configured,
+ * committed Raft log entries are archived there, and `toydb restore` can rebuild a node from the
+ * archive up to a given log index or time. `toydb check` verifies the consistency of stopped
+ * nodes' data, and `toydb migrate` upgrades it to the current data format. `toydb dump` takes a consistent logical backup of the
+ * whole cluster via a SQL client connection, which can be loaded with `toydb load`, and
+ * `toydb import` imports PostgreSQL and SQLite dumps. `toydb bench` runs built-in benchmark
+ * workloads against a running cluster, and `toydb status` shows which of its nodes are alive.
+ * If standby_of is configured, the cluster replicates a primary cluster as a hot standby, until
+ * `toydb promote` promotes it to a primary. If cdc_sink is configured, committed row changes are
+ * exported to a file, webhook, or Kafka REST proxy. `toydb proxy` runs a proxy that gives clients a
+ * single endpoint for the cluster, routing their requests to the leader and healthy followers.
+ * `toydb validate-config` validates the configuration and prints the effective configuration.
+ * If audit_dir is configured, DDL statements and privileged operations are recorded in a
+ * tamper-evident audit log, and `toydb verify-audit` verifies its hash chain.
  */
 
 #![warn(clippy::all)]
 
-use serde_derive::Deserialize;
-use std::collections::HashMap;
+use rand::Rng as _;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{IsTerminal as _, Read as _, Write};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt as _;
+#[cfg(windows)]
+use std::os::windows::process::CommandExt as _;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::io::{AsyncBufReadExt as _, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio::task::JoinSet;
+use toydb::audit;
 use toydb::error::{Error, Result};
+use toydb::proxy::Proxy;
 use toydb::raft;
+use toydb::ratelimit::RateLimits;
 use toydb::sql;
+use toydb::sql::engine::{Engine as _, Transaction as _};
+use toydb::sql::execution::ResultSet;
+use toydb::sql::import::Import;
+use toydb::sql::parser::{format_ident, format_value, statement_end};
+use toydb::sql::schema::{Catalog as _, Table};
+use toydb::sql::types::Row;
 use toydb::storage;
-use toydb::Server;
+use toydb::{Client, Server};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -22,10 +60,143 @@ async fn main() -> Result<()> {
                 .short('c')
                 .long("config")
                 .help("Configuration file path")
-                .default_value("config/toydb.yaml"),
+                .default_value("config/toydb.yaml")
+                .global(true),
+        )
+        .subcommand(
+            clap::Command::new("init")
+                .about("Generate node configs and data directories for a local cluster")
+                .args(LocalCluster::args()),
+        )
+        .subcommand(
+            clap::Command::new("start-local-cluster")
+                .about("Start and supervise a local cluster, initializing it if necessary")
+                .args(LocalCluster::args()),
+        )
+        .subcommand(
+            clap::Command::new("restore")
+                .about(
+                    "Restore the node's data from a Raft log archive, for point-in-time recovery",
+                )
+                .args([
+                    clap::Arg::new("archive")
+                        .long("archive")
+                        .help("Raft log archive directory")
+                        .required(true),
+                    clap::Arg::new("until")
+                        .long("until")
+                        .help("Restore up to this log index or RFC 3339 time [default: all]"),
+                ]),
+        )
+        .subcommand(
+            clap::Command::new("check")
+                .about("Check the consistency of stopped nodes' data, without modifying it")
+                .arg(
+                    clap::Arg::new("configs")
+                        .help("Configuration files of the nodes to check [default: --config]")
+                        .num_args(0..),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("migrate")
+                .about("Migrate the stopped node's SQL state to the current data format"),
+        )
+        .subcommand(
+            clap::Command::new("dump")
+                .about("Dump all tables from a consistent snapshot of a running cluster")
+                .args(client_args())
+                .args([
+                    clap::Arg::new("format")
+                        .long("format")
+                        .help("Dump format")
+                        .value_parser(["sql", "binary"])
+                        .default_value("sql"),
+                    clap::Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("Output file [default: stdout]"),
+                ]),
+        )
+        .subcommand(
+            clap::Command::new("load")
+                .about("Load a dump into a running cluster")
+                .args(client_args())
+                .arg(clap::Arg::new("file").help("Dump file to load").required(true)),
+        )
+        .subcommand(
+            clap::Command::new("import")
+                .about("Import a PostgreSQL pg_dump or SQLite .dump file into a running cluster")
+                .args(client_args())
+                .arg(clap::Arg::new("file").help("Dump file to import").required(true)),
+        )
+        .subcommand(
+            clap::Command::new("status")
+                .about("Show the Raft status and node liveness of a running cluster")
+                .args(client_args()),
+        )
+        .subcommand(
+            clap::Command::new("promote")
+                .about("Promote a standby cluster to a primary, stopping replication")
+                .args(client_args()),
+        )
+        .subcommand(
+            clap::Command::new("bench")
+                .about("Run a built-in benchmark workload against a running cluster")
+                .args(client_args())
+                .args(Bench::args()),
+        )
+        .subcommand(
+            clap::Command::new("validate-config")
+                .about("Validate the configuration and print the effective configuration"),
+        )
+        .subcommand(
+            clap::Command::new("verify-audit")
+                .about("Verify the hash chain of an audit log, detecting tampering")
+                .arg(clap::Arg::new("dir").help("Audit log directory [default: audit_dir]")),
+        )
+        .subcommand(
+            clap::Command::new("proxy")
+                .about("Run a proxy that routes clients to the leader and healthy followers")
+                .args([
+                    clap::Arg::new("listen")
+                        .short('l')
+                        .long("listen")
+                        .help("Address to listen for clients on")
+                        .default_value("0.0.0.0:9604"),
+                    clap::Arg::new("interval")
+                        .long("interval")
+                        .help("Interval between node status polls, in milliseconds")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("1000"),
+                    clap::Arg::new("nodes")
+                        .help("SQL addresses of the cluster nodes")
+                        .num_args(1..)
+                        .required(true),
+                ]),
         )
         .get_matches();
+    match args.subcommand() {
+        Some(("init", args)) => return LocalCluster::from_args(args)?.init(),
+        Some(("start-local-cluster", args)) => return LocalCluster::from_args(args)?.start().await,
+        Some(("check", args)) => return check(args),
+        Some(("dump", args)) => return dump(args).await,
+        Some(("load", args)) => return load(args).await,
+        Some(("import", args)) => return import(args).await,
+        Some(("bench", args)) => return Bench::from_args(args)?.run().await,
+        Some(("status", args)) => return status(args).await,
+        Some(("promote", args)) => return promote(args).await,
+        Some(("proxy", args)) => return proxy(args).await,
+        _ => {}
+    }
     let cfg = Config::new(args.get_one::<String>("config").unwrap().as_ref())?;
+    match args.subcommand() {
+        Some(("validate-config", _)) => {
+            print!("{}", cfg);
+            return Ok(());
+        }
+        Some(("verify-audit", args)) => return verify_audit(&cfg, args),
+        _ => {}
+    }
 
     let loglevel = cfg.log_level.parse::<simplelog::LevelFilter>()?;
     let mut logconfig = simplelog::ConfigBuilder::new();
@@ -34,65 +205,1415 @@ async fn main() -> Result<()> {
     }
     simplelog::SimpleLogger::init(loglevel, logconfig.build())?;
 
-    let path = std::path::Path::new(&cfg.data_dir);
-    let raft_log = match cfg.storage_raft.as_str() {
+    match args.subcommand() {
+        Some(("restore", args)) => return restore(&cfg, args),
+        Some(("migrate", _)) => return migrate(&cfg),
+        _ => {}
+    }
+
+    let mut raft_log = open_log(&cfg)?;
+    raft_log.set_snapshot_interval(cfg.snapshot_interval);
+    if !cfg.archive_dir.is_empty() {
+        raft_log.set_archive(raft::Archive::new(cfg.archive_dir.clone().into(), cfg.sync)?)?;
+    }
+    let raft_state = open_state(&cfg)?;
+
+    let mut server = Server::new(cfg.id, cfg.peers, raft_log, raft_state).await?;
+    if !cfg.standby_of.is_empty() {
+        server = server.standby_of(&cfg.standby_of);
+    }
+    if !cfg.cdc_sink.is_empty() {
+        server = server.cdc(&cfg.cdc_sink, std::time::Duration::from_millis(cfg.cdc_interval));
+    }
+    if !cfg.audit_dir.is_empty() {
+        let dir = cfg.audit_dir.clone().into();
+        server = server.audit(audit::Log::new(dir, cfg.audit_max_size, cfg.audit_max_files)?);
+    }
+    server
+        .paranoid(cfg.paranoid)
+        .catchup_bandwidth(cfg.catchup_bandwidth)
+        .drop_retention(std::time::Duration::from_secs(cfg.drop_retention))
+        .liveness(std::time::Duration::from_millis(cfg.liveness_interval))
+        .limits(sql::engine::Limits {
+            max_row_size: cfg.max_row_size,
+            max_key_size: cfg.max_key_size,
+            max_index_entry_size: cfg.max_index_entry_size,
+        })
+        .rate_limits(RateLimits {
+            session_qps: cfg.rate_limit_session_qps,
+            session_rows: cfg.rate_limit_session_rows,
+            client_qps: cfg.rate_limit_client_qps,
+            client_rows: cfg.rate_limit_client_rows,
+        })
+        .query_queue(
+            cfg.max_concurrent_queries,
+            std::time::Duration::from_millis(cfg.queue_timeout),
+        )
+        .admin_hosts(
+            cfg.admin_hosts
+                .split(',')
+                .map(str::trim)
+                .filter(|h| !h.is_empty())
+                .map(|h| h.parse())
+                .collect::<std::result::Result<_, _>>()?,
+        )
+        .redirect(cfg.peers_sql)
+        .listen(&cfg.listen_sql, &cfg.listen_raft)
+        .await?
+        .serve()
+        .await
+}
+
+/// Opens the Raft log using the configured storage engine.
+fn open_log(cfg: &Config) -> Result<raft::Log> {
+    let path = Path::new(&cfg.data_dir);
+    match cfg.storage_raft.as_str() {
         "bitcask" | "" => raft::Log::new(
-            storage::engine::BitCask::new_compact(path.join("log"), cfg.compact_threshold)?,
+            storage::engine::BitCask::new_compact(path.join("log"), cfg.compact_threshold)?
+                .with_mmap(cfg.mmap)?
+                .with_io_uring(cfg.io_uring)?,
             cfg.sync,
-        )?,
-        "memory" => raft::Log::new(storage::engine::Memory::new(), false)?,
-        name => return Err(Error::Config(format!("Unknown Raft storage engine {}", name))),
-    };
-    let raft_state: Box<dyn raft::State> = match cfg.storage_sql.as_str() {
+        ),
+        "memory" => raft::Log::new(storage::engine::Memory::new(), false),
+        name => Err(Error::Config(format!("Unknown Raft storage engine {}", name))),
+    }
+}
+
+/// Opens the SQL state machine using the configured storage engine.
+fn open_state(cfg: &Config) -> Result<Box<dyn raft::State>> {
+    let path = Path::new(&cfg.data_dir);
+    match cfg.storage_sql.as_str() {
         "bitcask" | "" => {
             let engine =
-                storage::engine::BitCask::new_compact(path.join("state"), cfg.compact_threshold)?;
-            Box::new(sql::engine::Raft::new_state(engine)?)
+                storage::engine::BitCask::new_compact(path.join("state"), cfg.compact_threshold)?
+                    .with_mmap(cfg.mmap)?
+                    .with_io_uring(cfg.io_uring)?;
+            Ok(Box::new(sql::engine::Raft::new_state(engine)?))
         }
         "memory" => {
             let engine = storage::engine::Memory::new();
-            Box::new(sql::engine::Raft::new_state(engine)?)
+            Ok(Box::new(sql::engine::Raft::new_state(engine)?))
         }
-        name => return Err(Error::Config(format!("Unknown SQL storage engine {}", name))),
+        name => Err(Error::Config(format!("Unknown SQL storage engine {}", name))),
+    }
+}
+
+/// Restores the node's Raft log and SQL state from a log archive, up to and including the given
+/// log index or archive time. The node's data directory must not already contain data. To restore
+/// a cluster, restore every node from the same archive before starting it.
+fn restore(cfg: &Config, args: &clap::ArgMatches) -> Result<()> {
+    if cfg.storage_raft == "memory" || cfg.storage_sql == "memory" {
+        return Err(Error::Config("Can't restore into memory storage".into()));
+    }
+    let path = Path::new(&cfg.data_dir);
+    if path.join("log").exists() || path.join("state").exists() {
+        return Err(Error::Config(format!(
+            "Data directory {} already contains data, move it aside before restoring",
+            cfg.data_dir
+        )));
+    }
+
+    let archive = Path::new(args.get_one::<String>("archive").unwrap());
+    let entries = match args.get_one::<String>("until") {
+        None => raft::Archive::read(archive, |_| true)?,
+        Some(until) => match until.parse::<raft::Index>() {
+            Ok(index) => raft::Archive::read(archive, |e| e.entry.index <= index)?,
+            Err(_) => {
+                let time = OffsetDateTime::parse(until, &Rfc3339).map_err(|err| {
+                    Error::Parse(format!("Invalid index or time {}: {}", until, err))
+                })?;
+                let millis = (time.unix_timestamp_nanos() / 1_000_000).max(0) as u64;
+                raft::Archive::read(archive, |e| e.time <= millis)?
+            }
+        },
+    };
+    let Some(last) = entries.last().cloned() else {
+        return Err(Error::Value("No archived entries to restore".into()));
     };
 
-    Server::new(cfg.id, cfg.peers, raft_log, raft_state)
-        .await?
-        .listen(&cfg.listen_sql, &cfg.listen_raft)
+    let mut log = open_log(cfg)?;
+    let mut state = open_state(cfg)?;
+    let count = entries.len();
+    for archived in entries {
+        let entry = archived.entry;
+        log.append(entry.term, entry.command.clone())?;
+        // Other errors are returned to the client as results, so the entry is still applied.
+        if let Err(err @ Error::Internal(_)) = state.apply(entry) {
+            return Err(err);
+        }
+    }
+    log.commit(last.entry.index)?;
+    log.set_term(last.entry.term, None)?;
+
+    let time = OffsetDateTime::from_unix_timestamp_nanos(last.time as i128 * 1_000_000)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_else(|| format!("{} ms", last.time));
+    println!(
+        "Restored {} log entries into {}, up to index {} archived at {}",
+        count, cfg.data_dir, last.entry.index, time
+    );
+    Ok(())
+}
+
+/// Migrates the node's SQL state to the current key/value encoding version. The node must be
+/// stopped, and its data should be backed up first, since a failed migration can't be resumed.
+fn migrate(cfg: &Config) -> Result<()> {
+    if cfg.storage_sql == "memory" {
+        return Err(Error::Config("Can't migrate memory storage".into()));
+    }
+    let path = Path::new(&cfg.data_dir).join("state");
+    let kv = sql::engine::KV::new(storage::engine::BitCask::new(path)?);
+    match kv.migrate()? {
+        sql::engine::FORMAT_VERSION => println!(
+            "SQL state in {} already has format version {}",
+            cfg.data_dir,
+            sql::engine::FORMAT_VERSION
+        ),
+        version => println!(
+            "Migrated SQL state in {} from format version {} to {}",
+            cfg.data_dir,
+            version,
+            sql::engine::FORMAT_VERSION
+        ),
+    }
+    Ok(())
+}
+
+/// Verifies the hash chain of an audit log, given its directory or the configured audit_dir.
+fn verify_audit(cfg: &Config, args: &clap::ArgMatches) -> Result<()> {
+    let dir = match args.get_one::<String>("dir") {
+        Some(dir) => dir.as_str(),
+        None if cfg.audit_dir.is_empty() => {
+            return Err(Error::Config("No audit log directory given or configured".into()))
+        }
+        None => cfg.audit_dir.as_str(),
+    };
+    let (count, hash) = audit::Log::verify(Path::new(dir))?;
+    println!("Verified {} audit records in {}, last hash {}", count, dir, hash);
+    Ok(())
+}
+
+/// Checks the consistency of stopped nodes' data without modifying it, given their config files.
+/// This verifies the checksums of the Raft log and SQL state files, the consistency of the SQL
+/// tables, indexes, and foreign keys of every tenant, and that nodes at the same applied index
+/// have identical SQL state. Problems are reported, and result in an error.
+fn check(args: &clap::ArgMatches) -> Result<()> {
+    let files: Vec<&String> = match args.get_many::<String>("configs") {
+        Some(files) => files.collect(),
+        None => vec![args.get_one::<String>("config").unwrap()],
+    };
+    let verify = |path: PathBuf| -> Result<Vec<String>> {
+        if !path.exists() {
+            return Ok(vec![format!("{} does not exist", path.display())]);
+        }
+        Ok(storage::engine::BitCask::verify(&path)?
+            .into_iter()
+            .map(|err| format!("{}: {}", path.display(), err))
+            .collect())
+    };
+
+    let mut problems = 0;
+    let mut states: Vec<(raft::NodeID, raft::Index, u32)> = Vec::new();
+    for file in files {
+        let cfg = Config::new(file)?;
+        if cfg.storage_raft == "memory" || cfg.storage_sql == "memory" {
+            return Err(Error::Config("Can't check memory storage".into()));
+        }
+        println!("Checking node {} in {}", cfg.id, cfg.data_dir);
+        let path = Path::new(&cfg.data_dir);
+        let mut errors = verify(path.join("log"))?;
+        let state_errors = verify(path.join("state"))?;
+
+        // Opening a corrupt file would truncate it or error, so only check valid SQL state. Data
+        // in an outdated format can't be decoded, and must be migrated first.
+        if state_errors.is_empty() {
+            let kv = sql::engine::KV::new(storage::engine::BitCask::new(path.join("state"))?);
+            match kv.format_version()? {
+                sql::engine::FORMAT_VERSION => {
+                    let applied_index = kv
+                        .get_metadata(b"applied_index")?
+                        .map(|b| storage::bincode::deserialize(&b))
+                        .unwrap_or(Ok(0))?;
+                    let txn = kv.begin_read_only()?;
+                    let mut checksum = sql::engine::checksum(&txn)?;
+                    errors.extend(sql::engine::check(&txn)?);
+                    let tenants = txn.scan_tenants()?;
+                    txn.rollback()?;
+                    // Tenants are checked in their own keyspaces, and their checksums are
+                    // folded into the node's checksum.
+                    for tenant in tenants {
+                        let txn =
+                            kv.clone().with_tenant(Some(tenant.name.clone())).begin_read_only()?;
+                        let mut hasher = crc32fast::Hasher::new();
+                        hasher.update(&checksum.to_be_bytes());
+                        hasher.update(tenant.name.as_bytes());
+                        hasher.update(&sql::engine::checksum(&txn)?.to_be_bytes());
+                        checksum = hasher.finalize();
+                        errors.extend(
+                            sql::engine::check(&txn)?
+                                .into_iter()
+                                .map(|error| format!("Tenant {}: {}", tenant.name, error)),
+                        );
+                        txn.rollback()?;
+                    }
+                    println!(
+                        "SQL state at applied index {} has checksum {:08x}",
+                        applied_index, checksum
+                    );
+                    states.push((cfg.id, applied_index, checksum));
+                }
+                version => errors.push(format!(
+                    "SQL state has format version {}, run toydb migrate to upgrade it to {}",
+                    version,
+                    sql::engine::FORMAT_VERSION
+                )),
+            }
+        }
+        errors.extend(state_errors);
+
+        for error in &errors {
+            println!("Error: {}", error);
+        }
+        problems += errors.len();
+    }
+
+    // Compare the SQL state of nodes that have applied the same log entries.
+    for (i, (id, index, checksum)) in states.iter().enumerate() {
+        if let Some((other, ..)) = states[..i].iter().find(|(_, j, c)| j == index && c != checksum)
+        {
+            println!(
+                "Error: nodes {} and {} have different SQL state at applied index {}",
+                other, id, index
+            );
+            problems += 1;
+        }
+    }
+    if states.iter().any(|(_, index, _)| *index != states[0].1) {
+        println!("Nodes have different applied indexes, only nodes at the same index are compared");
+    }
+
+    match problems {
+        0 => {
+            println!("No problems found");
+            Ok(())
+        }
+        n => Err(Error::Value(format!("Found {} problems", n))),
+    }
+}
+
+/// Returns the command-line arguments for connecting to a cluster as a SQL client.
+fn client_args() -> [clap::Arg; 2] {
+    [
+        clap::Arg::new("host")
+            .short('H')
+            .long("host")
+            .help("Host to connect to")
+            .default_value("127.0.0.1"),
+        clap::Arg::new("port")
+            .short('p')
+            .long("port")
+            .help("Port number to connect to")
+            .value_parser(clap::value_parser!(u16))
+            .default_value("9605"),
+    ]
+}
+
+/// Connects a SQL client to the cluster given by command-line arguments.
+async fn connect(args: &clap::ArgMatches) -> Result<Client> {
+    Client::new((args.get_one::<String>("host").unwrap().as_str(), *args.get_one("port").unwrap()))
+        .await
+}
+
+/// Prints the cluster's Raft status and the liveness of each node, suggesting how to replace
+/// dead nodes. Errors if any node is dead.
+async fn status(args: &clap::ArgMatches) -> Result<()> {
+    let client = connect(args).await?;
+    let status = client.status().await?;
+    let nodes = client.liveness().await?;
+    println!(
+        "Leader {} at term {}, commit index {}",
+        status.raft.leader, status.raft.term, status.raft.commit_index
+    );
+    for node in &nodes {
+        let heartbeat = match node.since {
+            Some(since) => format!("last heartbeat {:.1}s ago", since.as_secs_f64()),
+            None => "no heartbeat".to_string(),
+        };
+        println!("Node {}: {}, {}, {} entries behind", node.node, node.state, heartbeat, node.lag);
+    }
+    let dead: Vec<_> = nodes
+        .iter()
+        .filter(|n| n.state == sql::engine::LivenessState::Dead)
+        .map(|n| n.node.to_string())
+        .collect();
+    if dead.is_empty() {
+        return Ok(());
+    }
+    println!(
+        "Dead nodes can't be removed from the cluster, since its membership is static. If they \
+         don't recover, replace them by starting a node with the same ID and an empty data \
+         directory, which is sent a snapshot by the leader."
+    );
+    Err(Error::Value(format!("Dead nodes: {}", dead.join(", "))))
+}
+
+/// Promotes a standby cluster to a primary.
+async fn promote(args: &clap::ArgMatches) -> Result<()> {
+    let index = connect(args).await?.promote().await?;
+    println!("Promoted to primary after replicating primary log index {}", index);
+    Ok(())
+}
+
+/// Runs a proxy for a running cluster, until it's killed.
+async fn proxy(args: &clap::ArgMatches) -> Result<()> {
+    let mut logconfig = simplelog::ConfigBuilder::new();
+    logconfig.add_filter_allow_str("toydb");
+    simplelog::SimpleLogger::init(simplelog::LevelFilter::Info, logconfig.build())?;
+
+    let nodes = args.get_many::<String>("nodes").unwrap().cloned().collect();
+    let interval = std::time::Duration::from_millis(*args.get_one("interval").unwrap());
+    Proxy::new(nodes)
+        .interval(interval)
+        .listen(args.get_one::<String>("listen").unwrap())
         .await?
         .serve()
         .await
 }
 
+/// The header of binary dumps, followed by length-prefixed Bincode-encoded DumpRecords.
+const DUMP_HEADER: &[u8] = b"toydb-dump-v1\n";
+
+/// The number of rows per INSERT statement or binary record in dumps.
+const DUMP_BATCH_SIZE: usize = 100;
+
+/// A binary dump record.
+#[derive(Serialize, Deserialize)]
+enum DumpRecord {
+    /// A table schema. Rows that follow belong to this table.
+    Table(Table),
+    /// A batch of table rows.
+    Rows(Vec<Row>),
+}
+
+/// Dumps all table schemas and rows from a single read-only snapshot of the cluster, as either
+/// SQL statements or a binary archive. Rows are streamed from the server as they're dumped.
+async fn dump(args: &clap::ArgMatches) -> Result<()> {
+    let client = connect(args).await?;
+    let w: Box<dyn Write> = match args.get_one::<String>("output") {
+        Some(path) => Box::new(std::fs::File::create(path)?),
+        None => Box::new(std::io::stdout()),
+    };
+    let mut w = std::io::BufWriter::new(w);
+    let binary = args.get_one::<String>("format").unwrap() == "binary";
+
+    let version = match client.execute("BEGIN READ ONLY").await? {
+        ResultSet::Begin { version, .. } => version,
+        resultset => return Err(Error::Internal(format!("Unexpected result {:?}", resultset))),
+    };
+    let mut tables = Vec::new();
+    for name in client.list_tables().await? {
+        tables.push(client.get_table(&name).await?);
+    }
+
+    if binary {
+        w.write_all(DUMP_HEADER)?;
+    } else {
+        writeln!(w, "BEGIN;")?;
+    }
+    let write_rows = |w: &mut dyn Write, table: &Table, rows: Vec<Row>| -> Result<()> {
+        if binary {
+            write_dump_record(w, &DumpRecord::Rows(rows))
+        } else {
+            Ok(w.write_all(insert_sql(table, &rows).as_bytes())?)
+        }
+    };
+    let tables = order_tables(tables);
+    let mut count = 0;
+    for table in &tables {
+        if binary {
+            write_dump_record(&mut w, &DumpRecord::Table(table.clone()))?;
+        } else {
+            writeln!(w, "{};", table)?;
+        }
+        // Rows of self-referencing tables are buffered, so they can be ordered by references.
+        let self_referencing =
+            table.columns.iter().any(|c| c.references.as_deref() == Some(table.name.as_str()));
+        let mut rows = Vec::new();
+        client
+            .execute_with(&format!("SELECT * FROM {}", format_ident(&table.name)), |_, row| {
+                count += 1;
+                rows.push(row);
+                if !self_referencing && rows.len() >= DUMP_BATCH_SIZE {
+                    write_rows(&mut w, table, std::mem::take(&mut rows))?;
+                }
+                Ok(())
+            })
+            .await?;
+        if self_referencing {
+            rows = order_rows(table, rows)?;
+        }
+        for batch in rows.chunks(DUMP_BATCH_SIZE) {
+            write_rows(&mut w, table, batch.to_vec())?;
+        }
+    }
+    if !binary {
+        writeln!(w, "COMMIT;")?;
+    }
+    w.flush()?;
+    client.execute("COMMIT").await?;
+    eprintln!("Dumped {} tables with {} rows at version {}", tables.len(), count, version);
+    Ok(())
+}
+
+/// Loads a SQL or binary dump into the cluster, in a single transaction.
+async fn load(args: &clap::ArgMatches) -> Result<()> {
+    let client = connect(args).await?;
+    let path = args.get_one::<String>("file").unwrap();
+    let mut r = std::io::BufReader::new(std::fs::File::open(path)?);
+    let (mut tables, mut rows) = (0, 0);
+    let mut execute = |resultset: ResultSet| {
+        match resultset {
+            ResultSet::CreateTable { .. } => tables += 1,
+            ResultSet::CreateTableAs { count, .. } => (tables, rows) = (tables + 1, rows + count),
+            ResultSet::Create { count } => rows += count,
+            _ => {}
+        };
+    };
+
+    let mut header = vec![0; DUMP_HEADER.len()];
+    let len = r.read(&mut header)?;
+    header.truncate(len);
+    if header == DUMP_HEADER {
+        client.execute("BEGIN").await?;
+        let mut table = None;
+        while let Some(record) = read_dump_record(&mut r)? {
+            match record {
+                DumpRecord::Table(t) => {
+                    execute(client.execute(&t.to_string()).await?);
+                    table = Some(t);
+                }
+                DumpRecord::Rows(batch) => {
+                    let Some(table) = &table else {
+                        return Err(Error::Value("Dump has rows before table schema".into()));
+                    };
+                    execute(client.execute(&insert_sql(table, &batch)).await?);
+                }
+            }
+        }
+        client.execute("COMMIT").await?;
+    } else {
+        let mut input = String::from_utf8(header)
+            .map_err(|_| Error::Value(format!("{} is not a valid dump", path)))?;
+        r.read_to_string(&mut input)?;
+        let mut input = input.as_str();
+        while let Some(end) = statement_end(input) {
+            execute(client.execute(&input[..end]).await?);
+            input = &input[end..];
+        }
+        if !input.trim().is_empty() {
+            execute(client.execute(input).await?);
+        }
+    }
+    eprintln!("Loaded {} tables with {} rows", tables, rows);
+    Ok(())
+}
+
+/// Imports a PostgreSQL or SQLite dump into the cluster in a single transaction, reporting
+/// progress as tables are loaded.
+async fn import(args: &clap::ArgMatches) -> Result<()> {
+    let path = args.get_one::<String>("file").unwrap();
+    let import = Import::parse(&std::fs::read_to_string(path)?)?;
+    for warning in &import.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    let total = import.row_count();
+    let mut rows: HashMap<String, Vec<Row>> = HashMap::new();
+    let mut tables = Vec::new();
+    for (table, table_rows) in import.tables {
+        rows.insert(table.name.clone(), table_rows);
+        tables.push(table);
+    }
+    let tables = order_tables(tables);
+
+    let client = connect(args).await?;
+    let progress = std::io::stderr().is_terminal();
+    let (tables, rows) = (&tables, &rows);
+    client
+        .with_txn(|client| async move {
+            let mut count = 0;
+            for table in tables {
+                client.execute(&table.to_string()).await?;
+                let mut table_rows = rows[&table.name].clone();
+                if table.columns.iter().any(|c| c.references.as_ref() == Some(&table.name)) {
+                    table_rows = order_rows(table, table_rows)?;
+                }
+                for batch in table_rows.chunks(DUMP_BATCH_SIZE) {
+                    client.execute(&insert_sql(table, batch)).await?;
+                    count += batch.len();
+                    if progress {
+                        eprint!("\rImporting {} ({}/{} rows)", table.name, count, total);
+                    }
+                }
+                if progress {
+                    eprint!("\r\x1b[K");
+                }
+                eprintln!("Imported {} with {} rows", table.name, table_rows.len());
+            }
+            Ok(())
+        })
+        .await?;
+
+    let skipped: Vec<String> =
+        import.skipped.iter().map(|(kind, count)| format!("{} {}", count, kind)).collect();
+    eprintln!("Imported {} tables with {} rows", tables.len(), total);
+    if !skipped.is_empty() {
+        eprintln!("Skipped unsupported statements: {}", skipped.join(", "));
+    }
+    Ok(())
+}
+
+/// Writes a length-prefixed binary dump record.
+fn write_dump_record(w: &mut dyn Write, record: &DumpRecord) -> Result<()> {
+    let bytes = storage::bincode::serialize(record)?;
+    w.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    Ok(w.write_all(&bytes)?)
+}
+
+/// Reads a length-prefixed binary dump record, or None at the end of the dump.
+fn read_dump_record(r: &mut impl std::io::Read) -> Result<Option<DumpRecord>> {
+    let mut len_buf = [0u8; 4];
+    match r.read_exact(&mut len_buf) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let mut bytes = vec![0; u32::from_be_bytes(len_buf) as usize];
+    r.read_exact(&mut bytes)?;
+    Ok(Some(storage::bincode::deserialize(&bytes)?))
+}
+
+/// Formats an INSERT statement for the given table rows.
+fn insert_sql(table: &Table, rows: &[Row]) -> String {
+    let values: Vec<String> = rows
+        .iter()
+        .map(|row| format!("  ({})", row.iter().map(format_value).collect::<Vec<_>>().join(", ")))
+        .collect();
+    format!("INSERT INTO {} VALUES\n{};\n", format_ident(&table.name), values.join(",\n"))
+}
+
+/// Orders tables such that tables come after the tables they reference.
+fn order_tables(mut tables: Vec<Table>) -> Vec<Table> {
+    let mut ordered: Vec<Table> = Vec::new();
+    while !tables.is_empty() {
+        let (ready, rest): (Vec<_>, Vec<_>) =
+            tables.into_iter().partition(|table| {
+                table.columns.iter().filter_map(|c| c.references.as_ref()).all(|target| {
+                    target == &table.name || ordered.iter().any(|t| &t.name == target)
+                })
+            });
+        if ready.is_empty() {
+            // References must exist when tables are created, so this can't happen.
+            ordered.extend(rest);
+            break;
+        }
+        ordered.extend(ready);
+        tables = rest;
+    }
+    ordered
+}
+
+/// Orders the rows of a self-referencing table such that rows come after the rows they reference.
+fn order_rows(table: &Table, mut rows: Vec<Row>) -> Result<Vec<Row>> {
+    let pk = table.get_column_index(&table.get_primary_key()?.name)?;
+    let references: Vec<usize> = table
+        .columns
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.references.as_deref() == Some(table.name.as_str()))
+        .map(|(i, _)| i)
+        .collect();
+    let mut ordered = Vec::with_capacity(rows.len());
+    let mut keys = HashSet::new();
+    while !rows.is_empty() {
+        let (ready, rest): (Vec<Row>, Vec<Row>) = rows.into_iter().partition(|row| {
+            references.iter().all(|i| {
+                row[*i] == sql::types::Value::Null || row[*i] == row[pk] || keys.contains(&row[*i])
+            })
+        });
+        if ready.is_empty() {
+            // A reference cycle, which can't have been inserted in the first place.
+            ordered.extend(rest);
+            break;
+        }
+        keys.extend(ready.iter().map(|row| row[pk].clone()));
+        ordered.extend(ready);
+        rows = rest;
+    }
+    Ok(ordered)
+}
+
+/// A node configuration. Unknown fields are rejected, to catch misspellings.
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct Config {
     id: raft::NodeID,
+    #[serde(default)]
     peers: HashMap<raft::NodeID, String>,
+    #[serde(default)]
+    peers_sql: HashMap<raft::NodeID, String>,
     listen_sql: String,
     listen_raft: String,
     log_level: String,
     data_dir: String,
     compact_threshold: f64,
+    mmap: bool,
+    io_uring: bool,
     sync: bool,
     storage_raft: String,
     storage_sql: String,
+    archive_dir: String,
+    snapshot_interval: u64,
+    paranoid: bool,
+    catchup_bandwidth: u64,
+    drop_retention: u64,
+    liveness_interval: u64,
+    max_row_size: u64,
+    max_key_size: u64,
+    max_index_entry_size: u64,
+    rate_limit_session_qps: u64,
+    rate_limit_session_rows: u64,
+    rate_limit_client_qps: u64,
+    rate_limit_client_rows: u64,
+    max_concurrent_queries: usize,
+    queue_timeout: u64,
+    admin_hosts: String,
+    standby_of: String,
+    cdc_sink: String,
+    cdc_interval: u64,
+    audit_dir: String,
+    audit_max_size: u64,
+    audit_max_files: usize,
 }
 
 impl Config {
+    /// Loads the configuration from the given file, with defaults for missing fields, and
+    /// overrides from TOYDB_ environment variables, e.g. TOYDB_LOG_LEVEL=debug or
+    /// TOYDB_PEERS__2=host:port for map fields. Errors if it's invalid, see validate().
     fn new(file: &str) -> Result<Self> {
-        Ok(config::Config::builder()
-            .set_default("id", "toydb")?
+        let cfg: Self = config::Config::builder()
+            .set_default("id", 1)?
             .set_default("listen_sql", "0.0.0.0:9605")?
             .set_default("listen_raft", "0.0.0.0:9705")?
             .set_default("log_level", "info")?
             .set_default("data_dir", "data")?
             .set_default("compact_threshold", 0.2)?
+            .set_default("mmap", false)?
+            .set_default("io_uring", false)?
             .set_default("sync", true)?
             .set_default("storage_raft", "bitcask")?
             .set_default("storage_sql", "bitcask")?
+            .set_default("archive_dir", "")?
+            .set_default("snapshot_interval", 10000)?
+            .set_default("paranoid", false)?
+            .set_default("catchup_bandwidth", 0)?
+            .set_default("drop_retention", 86400)?
+            .set_default("liveness_interval", 1000)?
+            .set_default("max_row_size", 1 << 20)?
+            .set_default("max_key_size", 4096)?
+            .set_default("max_index_entry_size", 4096)?
+            .set_default("rate_limit_session_qps", 0)?
+            .set_default("rate_limit_session_rows", 0)?
+            .set_default("rate_limit_client_qps", 0)?
+            .set_default("rate_limit_client_rows", 0)?
+            .set_default("max_concurrent_queries", 0)?
+            .set_default("queue_timeout", 5000)?
+            .set_default("admin_hosts", "")?
+            .set_default("standby_of", "")?
+            .set_default("cdc_sink", "")?
+            .set_default("cdc_interval", 1000)?
+            .set_default("audit_dir", "")?
+            .set_default("audit_max_size", 100 << 20)?
+            .set_default("audit_max_files", 10)?
             .add_source(config::File::with_name(file))
-            .add_source(config::Environment::with_prefix("TOYDB"))
+            .add_source(
+                config::Environment::with_prefix("TOYDB").prefix_separator("_").separator("__"),
+            )
             .build()?
-            .try_deserialize()?)
+            .try_deserialize()?;
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    /// Validates the configuration, including dependencies between fields, listing all problems
+    /// in the returned error.
+    fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        let mut check_addr = |field: &str, addr: &str| match addr.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {}
+            _ => problems.push(format!("{} must be a host:port address, got {:?}", field, addr)),
+        };
+        check_addr("listen_sql", &self.listen_sql);
+        check_addr("listen_raft", &self.listen_raft);
+        for (id, addr) in self.peers.iter().collect::<BTreeMap<_, _>>() {
+            check_addr(&format!("peers.{}", id), addr);
+        }
+        for (id, addr) in self.peers_sql.iter().collect::<BTreeMap<_, _>>() {
+            check_addr(&format!("peers_sql.{}", id), addr);
+        }
+        if !self.standby_of.is_empty() {
+            check_addr("standby_of", &self.standby_of);
+        }
+
+        if self.id == 0 {
+            problems.push("id must be greater than 0".into());
+        }
+        if self.peers.contains_key(&self.id) {
+            problems.push(format!("peers must not include the node's own ID {}", self.id));
+        }
+        for id in self.peers_sql.keys().collect::<BTreeSet<_>>() {
+            if *id != self.id && !self.peers.contains_key(id) {
+                problems.push(format!("peers_sql has node {}, which is not in peers", id));
+            }
+        }
+        if self.listen_sql == self.listen_raft {
+            problems.push("listen_sql and listen_raft must be different addresses".into());
+        }
+        if !self.standby_of.is_empty()
+            && (self.standby_of == self.listen_sql
+                || self.peers_sql.values().any(|addr| *addr == self.standby_of))
+        {
+            problems.push("standby_of must be a node in a different cluster".into());
+        }
+        if let Err(err) = self.log_level.parse::<simplelog::LevelFilter>() {
+            problems.push(format!("log_level {:?} is invalid: {}", self.log_level, err));
+        }
+        for (field, engine) in
+            [("storage_raft", &self.storage_raft), ("storage_sql", &self.storage_sql)]
+        {
+            if engine != "bitcask" && engine != "memory" {
+                problems.push(format!("{} must be bitcask or memory, got {:?}", field, engine));
+            }
+        }
+        if (self.storage_raft == "bitcask" || self.storage_sql == "bitcask")
+            && self.data_dir.is_empty()
+        {
+            problems.push("data_dir must be set with bitcask storage".into());
+        }
+        if !(0.0..=1.0).contains(&self.compact_threshold) {
+            problems.push(format!(
+                "compact_threshold must be between 0 and 1, got {}",
+                self.compact_threshold
+            ));
+        }
+        if self.max_concurrent_queries > 0 && self.queue_timeout == 0 {
+            problems
+                .push("queue_timeout must be greater than 0 with max_concurrent_queries".into());
+        }
+        if self.io_uring && !cfg!(all(target_os = "linux", feature = "io-uring")) {
+            problems.push("io_uring requires Linux and the io-uring build feature".into());
+        }
+        for host in self.admin_hosts.split(',').map(str::trim).filter(|h| !h.is_empty()) {
+            if host.parse::<std::net::IpAddr>().is_err() {
+                problems.push(format!("admin_hosts must be IP addresses, got {:?}", host));
+            }
+        }
+        if !self.cdc_sink.is_empty() {
+            if !["file://", "http://", "kafka://"].iter().any(|s| self.cdc_sink.starts_with(s)) {
+                problems.push(format!(
+                    "cdc_sink must be a file://, http://, or kafka:// URL, got {:?}",
+                    self.cdc_sink
+                ));
+            }
+            if self.cdc_interval == 0 {
+                problems.push("cdc_interval must be greater than 0 with cdc_sink".into());
+            }
+        }
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(Error::Config(format!("Invalid configuration: {}", problems.join("; "))))
+    }
+}
+
+/// Formats the configuration as YAML, e.g. for validate-config.
+impl std::fmt::Display for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "id: {}", self.id)?;
+        for (name, map) in [("peers", &self.peers), ("peers_sql", &self.peers_sql)] {
+            match map.is_empty() {
+                true => writeln!(f, "{}: {{}}", name)?,
+                false => writeln!(f, "{}:", name)?,
+            }
+            for (id, addr) in map.iter().collect::<BTreeMap<_, _>>() {
+                writeln!(f, "  '{}': {:?}", id, addr)?;
+            }
+        }
+        writeln!(f, "listen_sql: {:?}", self.listen_sql)?;
+        writeln!(f, "listen_raft: {:?}", self.listen_raft)?;
+        writeln!(f, "log_level: {:?}", self.log_level)?;
+        writeln!(f, "data_dir: {:?}", self.data_dir)?;
+        writeln!(f, "compact_threshold: {}", self.compact_threshold)?;
+        writeln!(f, "mmap: {}", self.mmap)?;
+        writeln!(f, "io_uring: {}", self.io_uring)?;
+        writeln!(f, "sync: {}", self.sync)?;
+        writeln!(f, "storage_raft: {:?}", self.storage_raft)?;
+        writeln!(f, "storage_sql: {:?}", self.storage_sql)?;
+        writeln!(f, "archive_dir: {:?}", self.archive_dir)?;
+        writeln!(f, "snapshot_interval: {}", self.snapshot_interval)?;
+        writeln!(f, "paranoid: {}", self.paranoid)?;
+        writeln!(f, "catchup_bandwidth: {}", self.catchup_bandwidth)?;
+        writeln!(f, "drop_retention: {}", self.drop_retention)?;
+        writeln!(f, "liveness_interval: {}", self.liveness_interval)?;
+        writeln!(f, "max_row_size: {}", self.max_row_size)?;
+        writeln!(f, "max_key_size: {}", self.max_key_size)?;
+        writeln!(f, "max_index_entry_size: {}", self.max_index_entry_size)?;
+        writeln!(f, "rate_limit_session_qps: {}", self.rate_limit_session_qps)?;
+        writeln!(f, "rate_limit_session_rows: {}", self.rate_limit_session_rows)?;
+        writeln!(f, "rate_limit_client_qps: {}", self.rate_limit_client_qps)?;
+        writeln!(f, "rate_limit_client_rows: {}", self.rate_limit_client_rows)?;
+        writeln!(f, "max_concurrent_queries: {}", self.max_concurrent_queries)?;
+        writeln!(f, "queue_timeout: {}", self.queue_timeout)?;
+        writeln!(f, "admin_hosts: {:?}", self.admin_hosts)?;
+        writeln!(f, "standby_of: {:?}", self.standby_of)?;
+        writeln!(f, "cdc_sink: {:?}", self.cdc_sink)?;
+        writeln!(f, "cdc_interval: {}", self.cdc_interval)?;
+        writeln!(f, "audit_dir: {:?}", self.audit_dir)?;
+        writeln!(f, "audit_max_size: {}", self.audit_max_size)?;
+        writeln!(f, "audit_max_files: {}", self.audit_max_files)
+    }
+}
+
+/// The Windows process creation flag for a new process group, which doesn't receive the parent's
+/// Ctrl-C.
+#[cfg(windows)]
+const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+
+/// A local development cluster, with node configs and data directories under a base directory.
+/// Node i listens on the SQL and Raft base ports plus i-1.
+struct LocalCluster {
+    base_dir: PathBuf,
+    nodes: Option<u8>,
+    sql_port: u16,
+    raft_port: u16,
+}
+
+impl LocalCluster {
+    /// The default number of nodes to initialize.
+    const DEFAULT_NODES: u8 = 3;
+
+    /// Returns the command-line arguments for local cluster commands.
+    fn args() -> [clap::Arg; 4] {
+        [
+            clap::Arg::new("base-dir")
+                .long("base-dir")
+                .help("Directory for node configs and data")
+                .default_value("cluster"),
+            clap::Arg::new("nodes")
+                .short('n')
+                .long("nodes")
+                .help("Number of nodes [default: 3, or existing nodes]")
+                .value_parser(clap::value_parser!(u8).range(1..)),
+            clap::Arg::new("sql-port")
+                .long("sql-port")
+                .help("SQL port of node 1, incremented for each node")
+                .value_parser(clap::value_parser!(u16))
+                .default_value("9601"),
+            clap::Arg::new("raft-port")
+                .long("raft-port")
+                .help("Raft port of node 1, incremented for each node")
+                .value_parser(clap::value_parser!(u16))
+                .default_value("9701"),
+        ]
+    }
+
+    /// Creates a local cluster from command-line arguments.
+    fn from_args(args: &clap::ArgMatches) -> Result<Self> {
+        Ok(Self {
+            base_dir: args.get_one::<String>("base-dir").unwrap().into(),
+            nodes: args.get_one::<u8>("nodes").copied(),
+            sql_port: *args.get_one("sql-port").unwrap(),
+            raft_port: *args.get_one("raft-port").unwrap(),
+        })
+    }
+
+    /// Returns the directory of the given node.
+    fn node_dir(&self, id: raft::NodeID) -> PathBuf {
+        self.base_dir.join(format!("toydb{}", id))
+    }
+
+    /// Returns the config file path of the given node.
+    fn config_path(&self, id: raft::NodeID) -> PathBuf {
+        self.node_dir(id).join("toydb.yaml")
+    }
+
+    /// Returns the number of nodes that have already been initialized.
+    fn initialized_nodes(&self) -> u8 {
+        (1..=u8::MAX).take_while(|id| self.config_path(*id).exists()).count() as u8
+    }
+
+    /// Returns the given base port offset for the given node.
+    fn port(base: u16, id: raft::NodeID) -> Result<u16> {
+        base.checked_add(id as u16 - 1)
+            .ok_or_else(|| Error::Config(format!("No port available for node {}", id)))
+    }
+
+    /// Generates node configs and data directories, refusing to overwrite existing nodes.
+    fn init(&self) -> Result<()> {
+        let nodes = self.nodes.unwrap_or(Self::DEFAULT_NODES);
+        for id in 1..=nodes {
+            if self.config_path(id).exists() {
+                return Err(Error::Config(format!(
+                    "Node config {} already exists",
+                    self.config_path(id).display()
+                )));
+            }
+        }
+        for id in 1..=nodes {
+            let data_dir = self.node_dir(id).join("data");
+            std::fs::create_dir_all(&data_dir)?;
+            let data_dir = std::fs::canonicalize(data_dir)?;
+
+            let (mut peers, mut peers_sql) = (String::new(), String::new());
+            for peer in (1..=nodes).filter(|peer| *peer != id) {
+                peers +=
+                    &format!("\n  '{}': 127.0.0.1:{}", peer, Self::port(self.raft_port, peer)?);
+                peers_sql +=
+                    &format!("\n  '{}': 127.0.0.1:{}", peer, Self::port(self.sql_port, peer)?);
+            }
+            if peers.is_empty() {
+                peers = " {}".into();
+                peers_sql = " {}".into();
+            }
+            std::fs::write(
+                self.config_path(id),
+                format!(
+                    "# Node {id} of a {nodes}-node local cluster, generated by toydb init.\n\
+                     id: {id}\n\
+                     peers:{peers}\n\
+                     peers_sql:{peers_sql}\n\
+                     listen_sql: 127.0.0.1:{sql_port}\n\
+                     listen_raft: 127.0.0.1:{raft_port}\n\
+                     data_dir: {data_dir}\n\
+                     sync: false\n",
+                    sql_port = Self::port(self.sql_port, id)?,
+                    raft_port = Self::port(self.raft_port, id)?,
+                    data_dir = data_dir.display(),
+                ),
+            )?;
+            println!("Initialized node {} in {}", id, self.node_dir(id).display());
+        }
+        Ok(())
+    }
+
+    /// Starts the cluster, initializing it first if necessary. Each node runs as a child process
+    /// whose output is prefixed by the node name, and which is restarted if it exits. Runs until
+    /// interrupted by Ctrl-C, then shuts down all nodes.
+    async fn start(&self) -> Result<()> {
+        let nodes = match (self.initialized_nodes(), self.nodes) {
+            (0, _) => {
+                self.init()?;
+                self.nodes.unwrap_or(Self::DEFAULT_NODES)
+            }
+            (initialized, Some(nodes)) if nodes != initialized => {
+                return Err(Error::Config(format!(
+                    "Cluster in {} has {} nodes, not {}",
+                    self.base_dir.display(),
+                    initialized,
+                    nodes
+                )))
+            }
+            (initialized, _) => initialized,
+        };
+
+        let exe = std::env::current_exe()?;
+        let mut supervisors = JoinSet::new();
+        for id in 1..=nodes {
+            supervisors.spawn(Self::supervise(id, exe.clone(), self.config_path(id)));
+        }
+        println!(
+            "Started {}-node cluster on SQL ports {}-{}, press Ctrl-C to stop",
+            nodes,
+            self.sql_port,
+            Self::port(self.sql_port, nodes)?
+        );
+
+        // Aborting the supervisors on shutdown kills their nodes.
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => Ok(()),
+            Some(result) = supervisors.join_next() => {
+                result.map_err(|err| Error::Internal(err.to_string()))?
+            }
+        }
+    }
+
+    /// Runs a node, restarting it whenever it exits. The node is killed when the future is dropped.
+    async fn supervise(id: raft::NodeID, exe: PathBuf, config: PathBuf) -> Result<()> {
+        let name = format!("toydb{}", id);
+        loop {
+            // Use a separate process group, so Ctrl-C doesn't kill the node before shutdown.
+            let mut command = std::process::Command::new(&exe);
+            command.arg("-c").arg(&config);
+            #[cfg(unix)]
+            command.process_group(0);
+            #[cfg(windows)]
+            command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+            let mut child = Command::from(command)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()?;
+            tokio::spawn(Self::forward(name.clone(), child.stdout.take().unwrap()));
+            tokio::spawn(Self::forward(name.clone(), child.stderr.take().unwrap()));
+            let status = child.wait().await?;
+            eprintln!("{} exited with {}, restarting", name, status);
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+    }
+
+    /// Prints a node's output, prefixing lines with the node name.
+    async fn forward(name: String, output: impl AsyncRead + Unpin) {
+        let mut lines = BufReader::new(output).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            println!("{} {}", name, line);
+        }
+    }
+}
+
+/// A built-in benchmark, which runs a workload against a cluster from concurrent SQL clients for
+/// a fixed duration and reports throughput and latency percentiles per operation.
+struct Bench {
+    host: String,
+    port: u16,
+    workload: String,
+    concurrency: usize,
+    duration: std::time::Duration,
+    keys: u64,
+    /// The cumulative probability of each key, for a skewed key distribution.
+    zipf: Option<Vec<f64>>,
+}
+
+/// A benchmark operation, with its randomly chosen keys.
+enum BenchOp {
+    /// Reads a single row by primary key.
+    Get(u64),
+    /// Updates a single row by primary key.
+    Put(u64),
+    /// Reads a range of Bench::SCAN_SIZE rows starting at a key.
+    Scan(u64),
+    /// Transfers a random amount between two accounts in a transaction.
+    Transfer(u64, u64),
+}
+
+impl BenchOp {
+    /// Returns the operation name, for reporting.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Get(_) => "get",
+            Self::Put(_) => "put",
+            Self::Scan(_) => "scan",
+            Self::Transfer(_, _) => "transfer",
+        }
+    }
+}
+
+impl Bench {
+    /// The initial balance of each account in the bank workload.
+    const INITIAL_BALANCE: i64 = 100;
+    /// The number of rows read by each scan operation.
+    const SCAN_SIZE: u64 = 100;
+    /// The number of rows per INSERT statement when loading initial data.
+    const LOAD_BATCH_SIZE: u64 = 1000;
+    /// The exponent of the Zipf distribution, as used by YCSB.
+    const ZIPF_EXPONENT: f64 = 0.99;
+
+    /// Returns the command-line arguments for the bench command.
+    fn args() -> [clap::Arg; 5] {
+        [
+            clap::Arg::new("workload")
+                .help(
+                    "Workload to run: kv (50% gets, 50% puts), bank (transfer transactions), or \
+                     scan (90% range scans, 10% puts)",
+                )
+                .value_parser(["kv", "bank", "scan"])
+                .required(true),
+            clap::Arg::new("concurrency")
+                .long("concurrency")
+                .help("Number of concurrent clients")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("8"),
+            clap::Arg::new("duration")
+                .short('d')
+                .long("duration")
+                .help("Duration to run for, in seconds")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("10"),
+            clap::Arg::new("keys")
+                .short('k')
+                .long("keys")
+                .help("Number of rows (or accounts) to load")
+                .value_parser(clap::value_parser!(u64).range(2..))
+                .default_value("1000"),
+            clap::Arg::new("distribution")
+                .long("distribution")
+                .help("Key access distribution, zipf skews accesses towards low keys")
+                .value_parser(["uniform", "zipf"])
+                .default_value("uniform"),
+        ]
+    }
+
+    /// Creates a benchmark from command-line arguments.
+    fn from_args(args: &clap::ArgMatches) -> Result<Self> {
+        let duration = *args.get_one::<f64>("duration").unwrap();
+        if !(duration > 0.0 && duration.is_finite()) {
+            return Err(Error::Value(format!("Invalid duration {}", duration)));
+        }
+        let keys = *args.get_one::<u64>("keys").unwrap();
+        let zipf = (args.get_one::<String>("distribution").unwrap() == "zipf").then(|| {
+            let mut cdf: Vec<f64> = Vec::with_capacity(keys as usize);
+            let mut sum = 0.0;
+            for i in 1..=keys {
+                sum += 1.0 / (i as f64).powf(Self::ZIPF_EXPONENT);
+                cdf.push(sum);
+            }
+            cdf.iter_mut().for_each(|p| *p /= sum);
+            cdf
+        });
+        Ok(Self {
+            host: args.get_one::<String>("host").unwrap().clone(),
+            port: *args.get_one("port").unwrap(),
+            workload: args.get_one::<String>("workload").unwrap().clone(),
+            concurrency: *args.get_one::<u64>("concurrency").unwrap() as usize,
+            duration: std::time::Duration::from_secs_f64(duration),
+            keys,
+            zipf,
+        })
+    }
+
+    /// Sets up the workload's table, runs the workload, and prints the results.
+    async fn run(&self) -> Result<()> {
+        let client = Client::new((self.host.as_str(), self.port)).await?;
+        self.setup(&client).await?;
+
+        let mut clients = Vec::with_capacity(self.concurrency);
+        for _ in 0..self.concurrency {
+            clients.push(Client::new((self.host.as_str(), self.port)).await?);
+        }
+        println!(
+            "Running {} workload with {} clients for {:.1}s",
+            self.workload,
+            self.concurrency,
+            self.duration.as_secs_f64()
+        );
+        let start = std::time::Instant::now();
+        let deadline = start + self.duration;
+        let results = futures::future::try_join_all(
+            clients.into_iter().map(|client| self.worker(client, deadline)),
+        )
+        .await?;
+        let elapsed = start.elapsed().as_secs_f64();
+
+        let mut latencies: BTreeMap<&str, Vec<std::time::Duration>> = BTreeMap::new();
+        let mut retries = 0;
+        for (worker_latencies, worker_retries) in results {
+            for (name, l) in worker_latencies {
+                latencies.entry(name).or_default().extend(l);
+            }
+            retries += worker_retries;
+        }
+        let mut total: Vec<_> = latencies.values().flatten().copied().collect();
+
+        println!();
+        println!(
+            "{:<10} {:>10} {:>10} {:>9} {:>9} {:>9} {:>9}",
+            "Operation", "Count", "Ops/s", "p50", "p90", "p99", "Max"
+        );
+        for (name, l) in latencies.iter_mut().chain([(&"total", &mut total)]) {
+            l.sort();
+            let percentile = |p: f64| {
+                let d = l[((l.len() - 1) as f64 * p).round() as usize];
+                format!("{:.2}ms", d.as_secs_f64() * 1000.0)
+            };
+            println!(
+                "{:<10} {:>10} {:>10.1} {:>9} {:>9} {:>9} {:>9}",
+                name,
+                l.len(),
+                l.len() as f64 / elapsed,
+                percentile(0.5),
+                percentile(0.9),
+                percentile(0.99),
+                percentile(1.0),
+            );
+        }
+        println!();
+        println!("Retried {} operations due to serialization conflicts", retries);
+
+        if self.workload == "bank" {
+            let balance = client
+                .execute("SELECT SUM(balance) FROM bench_account")
+                .await?
+                .into_value()?
+                .integer()?;
+            let expect = self.keys as i64 * Self::INITIAL_BALANCE;
+            if balance != expect {
+                return Err(Error::Value(format!(
+                    "Expected total balance {}, found {}",
+                    expect, balance
+                )));
+            }
+            let negative = client
+                .execute("SELECT COUNT(*) FROM bench_account WHERE balance < 0")
+                .await?
+                .into_value()?
+                .integer()?;
+            if negative > 0 {
+                return Err(Error::Value(format!("Found {} negative balances", negative)));
+            }
+            println!("Verified that total balance is {} with no negative balances", balance);
+        }
+        Ok(())
+    }
+
+    /// Creates and loads the workload's table, replacing any existing table.
+    async fn setup(&self, client: &Client) -> Result<()> {
+        let start = std::time::Instant::now();
+        let (table, schema, value) = match self.workload.as_str() {
+            "bank" => (
+                "bench_account",
+                "id INTEGER PRIMARY KEY, balance INTEGER NOT NULL",
+                Self::INITIAL_BALANCE.to_string(),
+            ),
+            _ => ("bench_kv", "id INTEGER PRIMARY KEY, value STRING NOT NULL", "'value'".into()),
+        };
+        if client.list_tables().await?.iter().any(|t| t == table) {
+            client.execute(&format!("DROP TABLE {}", table)).await?;
+        }
+        client.execute(&format!("CREATE TABLE {} ({})", table, schema)).await?;
+        for from in (1..=self.keys).step_by(Self::LOAD_BATCH_SIZE as usize) {
+            let to = self.keys.min(from + Self::LOAD_BATCH_SIZE - 1);
+            client
+                .execute(&format!(
+                    "INSERT INTO {} VALUES {}",
+                    table,
+                    (from..=to)
+                        .map(|id| format!("({}, {})", id, value))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+                .await?;
+        }
+        println!(
+            "Loaded {} rows into {} in {:.3}s",
+            self.keys,
+            table,
+            start.elapsed().as_secs_f64()
+        );
+        Ok(())
+    }
+
+    /// Runs operations on a client until the deadline, returning the latencies of each operation
+    /// type and the number of retries. Operations that fail with serialization conflicts are
+    /// retried after a random backoff, and their latency includes the retries.
+    async fn worker(
+        &self,
+        client: Client,
+        deadline: std::time::Instant,
+    ) -> Result<(HashMap<&'static str, Vec<std::time::Duration>>, u64)> {
+        let mut latencies: HashMap<&'static str, Vec<std::time::Duration>> = HashMap::new();
+        let mut retries = 0;
+        while std::time::Instant::now() < deadline {
+            let op = self.next_op();
+            let start = std::time::Instant::now();
+            for attempt in 1.. {
+                match self.execute(&client, &op).await {
+                    Ok(()) => break,
+                    Err(Error::Serialization | Error::Abort | Error::NotLeader(_)) => retries += 1,
+                    Err(err) => return Err(err),
+                }
+                if client.txn().is_some() {
+                    client.execute("ROLLBACK").await?;
+                }
+                let backoff = rand::thread_rng().gen_range(0..=10 * attempt.min(10));
+                tokio::time::sleep(std::time::Duration::from_millis(backoff)).await;
+            }
+            latencies.entry(op.name()).or_default().push(start.elapsed());
+        }
+        Ok((latencies, retries))
+    }
+
+    /// Returns a random key in 1..=keys, according to the key distribution.
+    fn key(&self) -> u64 {
+        let mut rng = rand::thread_rng();
+        match &self.zipf {
+            Some(cdf) => {
+                let p: f64 = rng.gen();
+                (cdf.partition_point(|c| *c < p) as u64 + 1).min(self.keys)
+            }
+            None => rng.gen_range(1..=self.keys),
+        }
+    }
+
+    /// Returns a random operation for the workload.
+    fn next_op(&self) -> BenchOp {
+        let read: f64 = rand::thread_rng().gen();
+        match self.workload.as_str() {
+            "bank" => {
+                let from = self.key();
+                let to = std::iter::repeat_with(|| self.key()).find(|to| *to != from).unwrap();
+                BenchOp::Transfer(from, to)
+            }
+            "scan" if read < 0.9 => {
+                BenchOp::Scan(self.key().min(self.keys.saturating_sub(Self::SCAN_SIZE) + 1))
+            }
+            "kv" if read < 0.5 => BenchOp::Get(self.key()),
+            _ => BenchOp::Put(self.key()),
+        }
+    }
+
+    /// Executes an operation.
+    async fn execute(&self, client: &Client, op: &BenchOp) -> Result<()> {
+        match op {
+            BenchOp::Get(id) => {
+                client.execute(&format!("SELECT value FROM bench_kv WHERE id = {}", id)).await?;
+            }
+            BenchOp::Put(id) => {
+                let value: u64 = rand::thread_rng().gen();
+                client
+                    .execute(&format!("UPDATE bench_kv SET value = '{}' WHERE id = {}", value, id))
+                    .await?;
+            }
+            BenchOp::Scan(id) => {
+                client
+                    .execute(&format!(
+                        "SELECT * FROM bench_kv WHERE id >= {} AND id < {}",
+                        id,
+                        id + Self::SCAN_SIZE
+                    ))
+                    .await?;
+            }
+            BenchOp::Transfer(from, to) => {
+                client.execute("BEGIN").await?;
+                let balance = client
+                    .execute(&format!("SELECT balance FROM bench_account WHERE id = {}", from))
+                    .await?
+                    .into_value()?
+                    .integer()?;
+                let amount = rand::thread_rng().gen_range(0..=balance.clamp(0, 10));
+                client
+                    .execute(&format!(
+                        "UPDATE bench_account SET balance = balance - {} WHERE id = {}",
+                        amount, from
+                    ))
+                    .await?;
+                client
+                    .execute(&format!(
+                        "UPDATE bench_account SET balance = balance + {} WHERE id = {}",
+                        amount, to
+                    ))
+                    .await?;
+                client.execute("COMMIT").await?;
+            }
+        }
+        Ok(())
     }
 }
@@ -1,19 +1,37 @@
 /*
  * toysql is a command-line client for toyDB. It connects to a toyDB cluster node and executes SQL
- * queries against it via a REPL interface.
+ * queries against it via a REPL interface, or non-interactively via a command argument or a SQL
+ * script given with -f. In non-interactive mode, toysql exits with status 1 if any statement
+ * failed.
  */
 
 #![warn(clippy::all)]
 
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
 use rustyline::history::DefaultHistory;
 use rustyline::validate::{ValidationContext, ValidationResult, Validator};
 use rustyline::{error::ReadlineError, Editor, Modifiers};
-use rustyline_derive::{Completer, Helper, Highlighter, Hinter};
+use rustyline_derive::{Helper, Hinter};
+use toydb::encoding::json;
 use toydb::error::{Error, Result};
 use toydb::sql::execution::ResultSet;
-use toydb::sql::parser::{Lexer, Token};
+use toydb::sql::parser::{statement_end, Keyword, Lexer, Token};
+use toydb::sql::schema::{Column, Table};
+use toydb::sql::types::{Columns, Row, Value};
 use toydb::Client;
 
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::Write as _;
+use std::pin::Pin;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use terminal_size::{terminal_size, Height};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts = clap::command!()
@@ -32,14 +50,53 @@ async fn main() -> Result<()> {
                 .help("Port number to connect to")
                 .value_parser(clap::value_parser!(u16))
                 .default_value("9605"),
+            clap::Arg::new("format")
+                .long("format")
+                .help("Output format for query results")
+                .value_parser(["table", "csv", "json", "expanded"])
+                .default_value("table"),
+            clap::Arg::new("file")
+                .short('f')
+                .long("file")
+                .help("Execute SQL statements and commands from a file, then exit")
+                .conflicts_with("command"),
+            clap::Arg::new("stop-on-error")
+                .long("stop-on-error")
+                .help("Stop executing a script at the first failed statement")
+                .action(clap::ArgAction::SetTrue),
+            clap::Arg::new("set")
+                .short('v')
+                .long("set")
+                .help("Set a variable, substituted for :name in SQL statements")
+                .value_name("NAME=VALUE")
+                .action(clap::ArgAction::Append),
         ])
         .get_matches();
 
     let mut toysql =
         ToySQL::new(opts.get_one::<String>("host").unwrap(), *opts.get_one("port").unwrap())
             .await?;
+    toysql.format = opts.get_one::<String>("format").unwrap().parse()?;
+    toysql.stop_on_error = opts.get_flag("stop-on-error");
+    for var in opts.get_many::<String>("set").unwrap_or_default() {
+        match var.split_once('=') {
+            Some((name, value)) => toysql.variables.insert(name.to_string(), value.to_string()),
+            None => {
+                return Err(Error::Parse(format!("Invalid variable {}, expected name=value", var)))
+            }
+        };
+    }
 
-    if let Some(command) = opts.get_one::<&str>("command") {
+    if let Some(path) = opts.get_one::<String>("file") {
+        if let Err(error) = toysql.execute_file(path).await {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+        if toysql.errors > 0 {
+            std::process::exit(1);
+        }
+        Ok(())
+    } else if let Some(command) = opts.get_one::<String>("command") {
         toysql.execute(command).await
     } else {
         toysql.run().await
@@ -49,9 +106,21 @@ async fn main() -> Result<()> {
 /// The ToySQL REPL
 struct ToySQL {
     client: Client,
-    editor: Editor<InputValidator, DefaultHistory>,
+    editor: Editor<InputHelper, DefaultHistory>,
     history_path: Option<std::path::PathBuf>,
     show_headers: bool,
+    format: Format,
+    stop_on_error: bool,
+    variables: BTreeMap<String, String>,
+    /// The number of failed statements in executed scripts.
+    errors: u64,
+    show_timing: bool,
+    /// Whether to display command tags after statements, e.g. UPDATE 3.
+    show_tags: bool,
+    /// Whether to page query results that don't fit in the terminal.
+    pager: bool,
+    /// The last executed SQL statement, for \watch.
+    last_query: Option<String>,
 }
 
 impl ToySQL {
@@ -59,39 +128,118 @@ impl ToySQL {
     async fn new(host: &str, port: u16) -> Result<Self> {
         Ok(Self {
             client: Client::new((host, port)).await?,
-            editor: Editor::new()?,
+            editor: Editor::with_config(
+                rustyline::Config::builder()
+                    .history_ignore_dups(true)?
+                    .max_history_size(10000)?
+                    .build(),
+            )?,
             history_path: std::env::var_os("HOME")
                 .map(|home| std::path::Path::new(&home).join(".toysql.history")),
             show_headers: false,
+            format: Format::Table,
+            stop_on_error: false,
+            variables: BTreeMap::new(),
+            errors: 0,
+            show_timing: false,
+            show_tags: false,
+            pager: true,
+            last_query: None,
         })
     }
 
     /// Executes a line of input
     async fn execute(&mut self, input: &str) -> Result<()> {
-        if input.starts_with('!') {
+        if input.starts_with('!') || input.starts_with('\\') {
             self.execute_command(input).await
         } else if !input.is_empty() {
-            self.execute_query(input).await
+            self.last_query = Some(input.to_string());
+            self.execute_query(&self.substitute(input)?).await
         } else {
             Ok(())
         }
     }
 
-    /// Handles a REPL command (prefixed by !, e.g. !help)
+    /// Handles a REPL command (prefixed by ! or \, e.g. !help or \dt)
     async fn execute_command(&mut self, input: &str) -> Result<()> {
         let mut input = input.split_ascii_whitespace();
         let command = input.next().ok_or_else(|| Error::Parse("Expected command.".to_string()))?;
 
+        let args: Vec<&str> = input.collect();
         let getargs = |n| {
-            let args: Vec<&str> = input.collect();
             if args.len() != n {
                 Err(Error::Parse(format!("{}: expected {} args, got {}", command, n, args.len())))
             } else {
-                Ok(args)
+                Ok(args.clone())
             }
         };
 
         match command {
+            "\\d" => match args.as_slice() {
+                [] => self.list_tables().await?,
+                [table] => print!("{}", describe_table(&self.client.get_table(table).await?)),
+                _ => {
+                    return Err(Error::Parse(format!(
+                        "{}: expected 0 or 1 args, got {}",
+                        command,
+                        args.len()
+                    )))
+                }
+            },
+            "\\dt" => {
+                getargs(0)?;
+                self.list_tables().await?
+            }
+            "\\format" => {
+                let format = getargs(1)?[0];
+                self.format = format.parse()?;
+                println!("Output format is {}", format);
+            }
+            "\\i" => {
+                let args = getargs(1)?;
+                self.execute_file(args[0]).await?
+            }
+            "\\l" => {
+                // toyDB clusters serve a single, unnamed database.
+                getargs(0)?;
+                println!("toydb")
+            }
+            "\\watch" => {
+                let seconds = match getargs(1)?[0].parse::<f64>() {
+                    Ok(seconds) if seconds > 0.0 => seconds,
+                    _ => return Err(Error::Parse("Interval must be a positive number".into())),
+                };
+                self.watch(Duration::from_secs_f64(seconds)).await?
+            }
+            "\\set" => match args.as_slice() {
+                [] => {
+                    for (name, value) in &self.variables {
+                        println!("{} = '{}'", name, value)
+                    }
+                }
+                [name, value @ ..] => {
+                    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                        return Err(Error::Parse(format!("Invalid variable name {}", name)));
+                    }
+                    self.variables.insert(name.to_string(), value.join(" "));
+                }
+            },
+            "\\pager" => {
+                self.pager = toggle(command, &args, self.pager)?;
+                println!("Pager {}", if self.pager { "enabled" } else { "disabled" });
+            }
+            "\\timing" => {
+                self.show_timing = toggle(command, &args, self.show_timing)?;
+                println!("Timing {}", if self.show_timing { "enabled" } else { "disabled" });
+            }
+            "\\tags" => {
+                self.show_tags = toggle(command, &args, self.show_tags)?;
+                println!("Command tags {}", if self.show_tags { "enabled" } else { "disabled" });
+            }
+            "\\unset" => {
+                let args = getargs(1)?;
+                self.variables.remove(args[0]);
+            }
             "!headers" => match getargs(1)?[0] {
                 "on" => {
                     self.show_headers = true;
@@ -110,11 +258,47 @@ The following commands are also available:
 
     !headers <on|off>  Enable or disable column headers
     !help              This help message
+    !history           Display recent Raft events on the server
+    !metrics           Display server metrics
     !status            Display server status
     !table [table]     Display table schema, if it exists
     !tables            List tables
+
+psql-style meta commands:
+
+    \d [table]         Describe a table's columns, keys and indexes, or list tables
+    \dt                List tables
+    \format <format>   Set the query output format: table, csv, json, or expanded
+    \i <file>          Execute SQL statements and commands from a file
+    \l                 List databases
+    \pager [on|off]    Page query results through $PAGER when they don't fit in the terminal,
+                       or toggle it
+    \tags [on|off]     Display command tags with affected row counts, e.g. UPDATE 3, or
+                       toggle it
+    \timing [on|off]   Display statement execution times, or toggle it
+    \set [name [value]] Set a variable, or list variables. Variables are substituted in SQL
+                       statements as :name, :'name' (string literal), or :"name" (identifier)
+    \unset <name>      Unset a variable
+    \watch <seconds>   Re-run the last statement every n seconds, until interrupted by Ctrl-C
 "#
             ),
+            "!history" => {
+                getargs(0)?;
+                if self.client.protocol_version() < 7 {
+                    return Err(Error::Value("Server does not support Raft history".into()));
+                }
+                for entry in self.client.status().await?.raft.history {
+                    println!("{}", entry);
+                }
+            }
+            "!metrics" => {
+                getargs(0)?;
+                let metrics = self.client.metrics().await?;
+                println!(
+                    "Memory: {} bytes used, {} bytes peak, {} statements exceeded their limit",
+                    metrics.memory.used, metrics.memory.peak, metrics.memory.exceeded
+                );
+            }
             "!status" => {
                 let status = self.client.status().await?;
                 let mut node_logs = status
@@ -129,6 +313,7 @@ The following commands are also available:
 Server:    {server} (leader {leader} in term {term} with {nodes} nodes)
 Raft log:  {committed} committed, {applied} applied, {raft_size} MB ({raft_storage} storage)
 Node logs: {logs}
+Replicas:  {replicas}
 MVCC:      {active_txns} active txns, {versions} versions
 Storage:   {keys} keys, {logical_size} MB logical, {nodes}x {disk_size} MB disk, {garbage_percent}% garbage ({sql_storage} engine)
 "#,
@@ -142,6 +327,14 @@ Storage:   {keys} keys, {logical_size} MB logical, {nodes}x {disk_size} MB disk,
                     raft_size =
                         format_args!("{:.3}", status.raft.storage_size as f64 / 1000.0 / 1000.0),
                     logs = node_logs.join(" "),
+                    replicas = if status.raft.diverged {
+                        format!(
+                            "DIVERGED, {} state checksum mismatches (see server logs)",
+                            status.raft.checksum_mismatches
+                        )
+                    } else {
+                        "no divergence detected".to_string()
+                    },
                     versions = status.mvcc.versions,
                     active_txns = status.mvcc.active_txns,
                     keys = status.mvcc.storage.keys,
@@ -170,57 +363,326 @@ Storage:   {keys} keys, {logical_size} MB logical, {nodes}x {disk_size} MB disk,
             }
             "!tables" => {
                 getargs(0)?;
-                for table in self.client.list_tables().await? {
-                    println!("{}", table)
-                }
+                self.list_tables().await?
             }
             c => return Err(Error::Parse(format!("Unknown command {}", c))),
         }
         Ok(())
     }
 
+    /// Executes SQL statements and commands from a file. Commands must be on separate lines, while
+    /// SQL statements are terminated by a semicolon and may span multiple lines. Failed statements
+    /// are reported and skipped, unless stop_on_error is set in which case the first error is
+    /// returned. Scripts can include other scripts via `\i`, so the future is boxed to allow the
+    /// recursion.
+    fn execute_file<'a>(
+        &'a mut self,
+        path: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move {
+            let script = std::fs::read_to_string(path)
+                .map_err(|err| Error::Value(format!("Failed to read {}: {}", path, err)))?;
+            let mut statement = String::new();
+            let mut start = 0;
+            for (i, line) in script.lines().enumerate() {
+                if statement.trim().is_empty() {
+                    statement.clear();
+                    start = i + 1;
+                    let line = line.trim();
+                    if line.starts_with('!') || line.starts_with('\\') {
+                        self.execute_script_input(path, start, line).await?;
+                        continue;
+                    }
+                }
+                statement.push_str(line);
+                statement.push('\n');
+                while let Some(end) = statement_end(&statement) {
+                    let input: String = statement.drain(..end).collect();
+                    self.execute_script_input(path, start, input.trim()).await?;
+                    start = i + 1;
+                }
+            }
+            if !statement.trim().is_empty() {
+                self.execute_script_input(path, start, statement.trim()).await?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Executes a statement or command from a script, starting at the given line.
+    async fn execute_script_input(&mut self, path: &str, line: usize, input: &str) -> Result<()> {
+        match self.execute(input).await {
+            Ok(()) => Ok(()),
+            Err(error @ Error::Internal(_)) => Err(error),
+            Err(error) => {
+                self.errors += 1;
+                // Report syntax errors at their line in the script.
+                let line = match error {
+                    Error::Syntax { line: l, .. } => line + l - 1,
+                    _ => line,
+                };
+                let error = format!("{}:{}: {}", path, line, error);
+                if self.stop_on_error {
+                    return Err(Error::Value(error));
+                }
+                eprintln!("Error: {}", error);
+                Ok(())
+            }
+        }
+    }
+
+    /// Prints the line of a statement where a syntax error occurred, with a
+    /// caret under the error position.
+    fn print_syntax_position(&self, input: &str, line: usize, column: usize) {
+        let input = self.substitute(input).unwrap_or_else(|_| input.to_string());
+        if let Some(text) = input.lines().nth(line - 1) {
+            let indent: String =
+                text.chars().take(column - 1).map(|c| if c == '\t' { c } else { ' ' }).collect();
+            println!("{}\n{}^", text, indent);
+        }
+    }
+
+    /// Substitutes variables in a SQL statement. :name is replaced by the raw value, :'name' by a
+    /// quoted string literal, and :"name" by a quoted identifier. Undefined variables and
+    /// variables inside string literals or quoted identifiers are left as is.
+    fn substitute(&self, input: &str) -> Result<String> {
+        if self.variables.is_empty() {
+            return Ok(input.to_string());
+        }
+        let re = regex::Regex::new(r#"'(?:[^']|'')*'|"(?:[^"]|"")*"|:'(\w+)'|:"(\w+)"|:(\w+)"#)?;
+        Ok(re
+            .replace_all(input, |caps: &regex::Captures| {
+                let (name, quote) = match (caps.get(1), caps.get(2), caps.get(3)) {
+                    (Some(name), _, _) => (name.as_str(), Some('\'')),
+                    (_, Some(name), _) => (name.as_str(), Some('"')),
+                    (_, _, Some(name)) => (name.as_str(), None),
+                    _ => return caps[0].to_string(),
+                };
+                match (self.variables.get(name), quote) {
+                    (Some(value), Some(q)) => {
+                        format!("{q}{}{q}", value.replace(q, &format!("{q}{q}")), q = q)
+                    }
+                    (Some(value), None) => value.clone(),
+                    (None, _) => caps[0].to_string(),
+                }
+            })
+            .into_owned())
+    }
+
+    /// Lists the tables in the database
+    async fn list_tables(&self) -> Result<()> {
+        for table in self.client.list_tables().await? {
+            println!("{}", table)
+        }
+        Ok(())
+    }
+
     /// Runs a query and displays the results
     async fn execute_query(&mut self, query: &str) -> Result<()> {
-        match self.client.execute(query).await? {
+        // Rows are output as they're streamed from the server, rather than buffering them.
+        let mut output = Output::new(self.pager_limit());
+        let mut record = 0;
+        let start = Instant::now();
+        let result = self
+            .client
+            .execute_with(query, |columns, row| {
+                if record == 0 {
+                    for line in self.format_header(columns) {
+                        output.write(line)?;
+                    }
+                }
+                record += 1;
+                for line in self.format_row(columns, record, &row) {
+                    output.write(line)?;
+                }
+                Ok(())
+            })
+            .await;
+        let elapsed = start.elapsed();
+        if let Ok(ResultSet::Query { columns, .. }) = &result {
+            if record == 0 {
+                for line in self.format_header(columns) {
+                    output.write(line)?;
+                }
+            }
+        }
+        output.finish()?;
+        let resultset = result?;
+
+        match resultset {
             ResultSet::Begin { version, read_only } => match read_only {
                 false => println!("Began transaction at new version {}", version),
                 true => println!("Began read-only transaction at version {}", version),
             },
-            ResultSet::Commit { version: id } => println!("Committed transaction {}", id),
-            ResultSet::Rollback { version: id } => println!("Rolled back transaction {}", id),
+            ResultSet::Commit { version: id } => {
+                println!("Committed transaction {}", id);
+                self.refresh_catalog().await;
+            }
+            ResultSet::Rollback { version: id } => {
+                println!("Rolled back transaction {}", id);
+                self.refresh_catalog().await;
+            }
+            ResultSet::Prepare { gid, version } => {
+                println!("Prepared transaction {} as {}", version, gid)
+            }
             ResultSet::Create { count } => println!("Created {} rows", count),
             ResultSet::Delete { count } => println!("Deleted {} rows", count),
             ResultSet::Update { count } => println!("Updated {} rows", count),
-            ResultSet::CreateTable { name } => println!("Created table {}", name),
-            ResultSet::DropTable { name } => println!("Dropped table {}", name),
+            ResultSet::CreateTable { name } => {
+                println!("Created table {}", name);
+                self.refresh_catalog().await;
+            }
+            ResultSet::CreateTableAs { name, count } => {
+                println!("Created table {} with {} rows", name, count);
+                self.refresh_catalog().await;
+            }
+            ResultSet::DropTable { name } => {
+                println!("Dropped table {}", name);
+                self.refresh_catalog().await;
+            }
+            ResultSet::CreateTrigger { name } => println!("Created trigger {}", name),
+            ResultSet::DropTrigger { name } => println!("Dropped trigger {}", name),
+            ResultSet::CreateIndex { name } => println!("Created index {}", name),
+            ResultSet::DropIndex { name } => println!("Dropped index {}", name),
+            ResultSet::CreateFunction { name } => println!("Created function {}", name),
+            ResultSet::DropFunction { name } => println!("Dropped function {}", name),
+            ResultSet::CreateTenant { name } => println!("Created tenant {}", name),
+            ResultSet::DropTenant { name } => println!("Dropped tenant {}", name),
+            ResultSet::UndropTable { name } => {
+                println!("Restored table {}", name);
+                self.refresh_catalog().await;
+            }
+            ResultSet::DeclareCursor { name } => println!("Declared cursor {}", name),
+            ResultSet::CloseCursor { name } => println!("Closed cursor {}", name),
+            ResultSet::Kill { session, query: false } => println!("Killed session {}", session),
+            ResultSet::Kill { session, query: true } => {
+                println!("Cancelled statement in session {}", session)
+            }
             ResultSet::Explain(plan) => println!("{}", plan),
-            ResultSet::Query { columns, mut rows } => {
-                if self.show_headers {
-                    println!(
-                        "{}",
-                        columns
-                            .iter()
-                            .map(|c| c.name.as_deref().unwrap_or("?"))
-                            .collect::<Vec<_>>()
-                            .join("|")
-                    );
-                }
-                while let Some(row) = rows.next().transpose()? {
-                    println!(
-                        "{}",
-                        row.into_iter().map(|v| format!("{}", v)).collect::<Vec<_>>().join("|")
-                    );
+            ResultSet::ExplainAnalyze { plan, rows, time, memory } => {
+                println!("{}", plan);
+                println!(
+                    "Rows: {}, time: {:.3}s, peak memory: {} bytes",
+                    rows,
+                    time.as_secs_f64(),
+                    memory
+                )
+            }
+            ResultSet::Set { variable } => println!("Set {}", variable),
+            ResultSet::Query { .. } => {}
+        }
+        if self.show_tags {
+            if let Some(tag) = self.client.command_tag() {
+                println!("{}", tag);
+            }
+        }
+        if self.show_timing {
+            let millis = |d: Duration| d.as_secs_f64() * 1000.0;
+            match self.client.execution_time() {
+                Some(server) => {
+                    println!("Time: {:.3} ms (server {:.3} ms)", millis(elapsed), millis(server))
                 }
+                None => println!("Time: {:.3} ms", millis(elapsed)),
             }
         }
         Ok(())
     }
 
+    /// Re-runs the last SQL statement at the given interval, until interrupted.
+    async fn watch(&mut self, interval: Duration) -> Result<()> {
+        let query = match &self.last_query {
+            Some(query) => self.substitute(query)?,
+            None => return Err(Error::Value("No statement to watch".into())),
+        };
+        // The pager would block the refresh, so disable it while watching.
+        let pager = std::mem::replace(&mut self.pager, false);
+        let mut ctrl_c = Box::pin(tokio::signal::ctrl_c());
+        let result = loop {
+            println!("Every {:.1}s: {}\n", interval.as_secs_f64(), query);
+            if let Err(err) = self.execute_query(&query).await {
+                break Err(err);
+            }
+            println!();
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {},
+                _ = &mut ctrl_c => break Ok(()),
+            }
+        };
+        self.pager = pager;
+        result
+    }
+
+    /// Returns the number of output lines that fit in the terminal before paging, or None if
+    /// paging is disabled or output isn't to a terminal. An empty $PAGER disables paging.
+    fn pager_limit(&self) -> Option<usize> {
+        if !self.pager || std::env::var("PAGER").is_ok_and(|pager| pager.is_empty()) {
+            return None;
+        }
+        // Leave room for the next prompt.
+        terminal_size().map(|(_, Height(height))| (height as usize).saturating_sub(1))
+    }
+
+    /// Formats the header lines of a query result in the current output format, if any
+    fn format_header(&self, columns: &Columns) -> Vec<String> {
+        let names = column_names(columns);
+        match self.format {
+            Format::Table if self.show_headers => vec![names.join("|")],
+            Format::Csv if self.show_headers => {
+                vec![names.iter().map(|n| csv_quote(n)).collect::<Vec<_>>().join(",")]
+            }
+            Format::Table | Format::Csv | Format::Json | Format::Expanded => vec![],
+        }
+    }
+
+    /// Formats a query result row as output lines in the current output format. The record is
+    /// the row's 1-based position in the result.
+    fn format_row(&self, columns: &Columns, record: u64, row: &Row) -> Vec<String> {
+        let names = column_names(columns);
+        match self.format {
+            Format::Table => {
+                vec![row.iter().map(|v| format!("{}", v)).collect::<Vec<_>>().join("|")]
+            }
+            Format::Csv => vec![row.iter().map(csv_value).collect::<Vec<_>>().join(",")],
+            Format::Json => {
+                vec![json::object(names.iter().copied().zip(row.iter()))]
+            }
+            Format::Expanded => {
+                let width = names.iter().map(|n| n.len()).max().unwrap_or(0);
+                std::iter::once(format!("-[ RECORD {} ]-", record))
+                    .chain(names.iter().zip(row.iter()).map(|(name, value)| {
+                        format!("{:<width$} | {}", name, value, width = width)
+                    }))
+                    .collect()
+            }
+        }
+    }
+
+    /// Refreshes the table and column names used for tab completion. This is a noop when not
+    /// running interactively. Errors are ignored, since completion is best-effort.
+    async fn refresh_catalog(&mut self) {
+        if self.editor.helper().is_none() {
+            return;
+        }
+        let mut catalog = BTreeMap::new();
+        if let Ok(tables) = self.client.list_tables().await {
+            for name in tables {
+                if let Ok(table) = self.client.get_table(&name).await {
+                    catalog.insert(name, table.columns.into_iter().map(|c| c.name).collect());
+                }
+            }
+        }
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.catalog = catalog;
+        }
+    }
+
     /// Prompts the user for input
     fn prompt(&mut self) -> Result<Option<String>> {
+        // Open transactions are marked with *, and aborted ones with ! until rolled back.
+        let state = if self.client.txn_aborted() { "!" } else { "*" };
         let prompt = match self.client.txn() {
-            Some((version, false)) => format!("toydb:{}> ", version),
-            Some((version, true)) => format!("toydb@{}> ", version),
+            Some((version, false)) => format!("toydb:{}{}> ", version, state),
+            Some((version, true)) => format!("toydb@{}{}> ", version, state),
             None => "toydb> ".into(),
         };
         match self.editor.readline(&prompt) {
@@ -242,12 +704,22 @@ Storage:   {keys} keys, {logical_size} MB logical, {nodes}x {disk_size} MB disk,
                 Err(err) => return Err(err.into()),
             };
         }
-        self.editor.set_helper(Some(InputValidator));
+        self.editor.set_helper(Some(InputHelper::new()));
         // Make sure multiline pastes are interpreted as normal inputs.
         self.editor.bind_sequence(
             rustyline::KeyEvent(rustyline::KeyCode::BracketedPasteStart, Modifiers::NONE),
             rustyline::Cmd::Noop,
         );
+        // Search history for entries starting with the current input on up/down arrows.
+        self.editor.bind_sequence(
+            rustyline::KeyEvent(rustyline::KeyCode::Up, Modifiers::NONE),
+            rustyline::Cmd::HistorySearchBackward,
+        );
+        self.editor.bind_sequence(
+            rustyline::KeyEvent(rustyline::KeyCode::Down, Modifiers::NONE),
+            rustyline::Cmd::HistorySearchForward,
+        );
+        self.refresh_catalog().await;
 
         let status = self.client.status().await?;
         println!(
@@ -259,7 +731,12 @@ Storage:   {keys} keys, {logical_size} MB logical, {nodes}x {disk_size} MB disk,
             match self.execute(&input).await {
                 Ok(()) => {}
                 error @ Err(Error::Internal(_)) => return error,
-                Err(error) => println!("Error: {}", error),
+                Err(error) => {
+                    println!("Error: {}", error);
+                    if let Error::Syntax { line, column, .. } = error {
+                        self.print_syntax_position(&input, line, column);
+                    }
+                }
             }
         }
 
@@ -270,17 +747,436 @@ Storage:   {keys} keys, {logical_size} MB logical, {nodes}x {disk_size} MB disk,
     }
 }
 
-/// A Rustyline helper for multiline editing. It parses input lines and determines if they make up a
-/// complete command or not.
-#[derive(Completer, Helper, Highlighter, Hinter)]
-struct InputValidator;
+/// Parses an optional on/off argument for a toggle command, toggling the current value if none.
+fn toggle(command: &str, args: &[&str], current: bool) -> Result<bool> {
+    match args {
+        [] => Ok(!current),
+        ["on"] => Ok(true),
+        ["off"] => Ok(false),
+        [v] => Err(Error::Parse(format!("Invalid value {}, expected on or off", v))),
+        _ => Err(Error::Parse(format!("{}: expected 0 or 1 args", command))),
+    }
+}
+
+/// Query result output formats
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    /// Values separated by |, one row per line.
+    Table,
+    /// Comma-separated values, quoted as needed per RFC 4180.
+    Csv,
+    /// One JSON object per line, keyed by column name.
+    Json,
+    /// One line per column value, with a record header per row. Useful for wide rows.
+    Expanded,
+}
+
+impl std::str::FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(Self::Table),
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "expanded" => Ok(Self::Expanded),
+            _ => Err(Error::Parse(format!(
+                "Invalid format {}, expected table, csv, json, or expanded",
+                s
+            ))),
+        }
+    }
+}
+
+/// Returns the names of result columns, using ? for unnamed columns.
+fn column_names(columns: &Columns) -> Vec<&str> {
+    columns.iter().map(|c| c.name.as_deref().unwrap_or("?")).collect()
+}
+
+/// Quotes a CSV field if it contains separators, quotes, or line breaks.
+fn csv_quote(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Formats a value as a CSV field. NULLs are empty fields.
+fn csv_value(value: &Value) -> String {
+    match value {
+        Value::Null => "".to_string(),
+        Value::String(s) => csv_quote(s),
+        v => v.to_string(),
+    }
+}
+
+/// Query result output. Lines are printed directly until they exceed the limit, at which point the
+/// output is sent through $PAGER (less by default) instead.
+struct Output {
+    /// The number of lines to print before paging, or None to never page.
+    limit: Option<usize>,
+    /// Lines held back until the output either exceeds the limit or is finished.
+    buffer: Vec<String>,
+    /// The pager process, once started. Its stdin is closed if the user quits the pager early.
+    pager: Option<Child>,
+}
+
+impl Output {
+    /// Creates a new output, paging when it exceeds the given number of lines
+    fn new(limit: Option<usize>) -> Self {
+        Self { limit, buffer: Vec::new(), pager: None }
+    }
+
+    /// Writes a line of output
+    fn write(&mut self, line: String) -> Result<()> {
+        if let Some(pager) = &mut self.pager {
+            if let Some(stdin) = &mut pager.stdin {
+                // If the user quits the pager, the rest of the output is discarded.
+                if let Err(err) = writeln!(stdin, "{}", line) {
+                    if err.kind() != std::io::ErrorKind::BrokenPipe {
+                        return Err(err.into());
+                    }
+                    pager.stdin = None;
+                }
+            }
+            return Ok(());
+        }
+        match self.limit {
+            Some(limit) => {
+                self.buffer.push(line);
+                if self.buffer.len() > limit {
+                    self.page()?;
+                }
+            }
+            None => println!("{}", line),
+        }
+        Ok(())
+    }
+
+    /// Starts the pager and writes the buffered lines to it. If the pager can't be started, the
+    /// output is printed directly instead.
+    fn page(&mut self) -> Result<()> {
+        let command = std::env::var("PAGER").unwrap_or_else(|_| "less".into());
+        self.limit = None;
+        match Command::new("sh").arg("-c").arg(&command).stdin(Stdio::piped()).spawn() {
+            Ok(child) => {
+                // Like psql, ignore Ctrl-C while paging rather than exiting, since the pager uses
+                // it too. Tokio keeps the handler registered for the rest of the process lifetime.
+                #[cfg(unix)]
+                let _ = signal(SignalKind::interrupt())?;
+                #[cfg(windows)]
+                let _ = tokio::signal::windows::ctrl_c()?;
+                self.pager = Some(child);
+            }
+            Err(err) => eprintln!("Failed to start pager {}: {}", command, err),
+        }
+        for line in std::mem::take(&mut self.buffer) {
+            self.write(line)?;
+        }
+        Ok(())
+    }
+
+    /// Finishes the output, printing any buffered lines or waiting for the pager to exit
+    fn finish(mut self) -> Result<()> {
+        for line in self.buffer.drain(..) {
+            println!("{}", line);
+        }
+        if let Some(mut pager) = self.pager.take() {
+            pager.stdin = None;
+            pager.wait()?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats a psql-style description of a table, listing its columns followed by its primary key,
+/// secondary indexes, unique constraints, and foreign keys.
+fn describe_table(table: &Table) -> String {
+    let mut rows = vec![vec![
+        "Column".to_string(),
+        "Type".to_string(),
+        "Nullable".to_string(),
+        "Default".to_string(),
+    ]];
+    for c in &table.columns {
+        rows.push(vec![
+            c.name.clone(),
+            c.datatype.to_string(),
+            if c.nullable { "" } else { "not null" }.to_string(),
+            c.default.as_ref().map(|v| v.to_string()).unwrap_or_default(),
+        ]);
+    }
+    let mut widths = vec![0; 4];
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.len())
+        }
+    }
+
+    let mut out = format!("Table \"{}\"\n", table.name);
+    for (i, row) in rows.iter().enumerate() {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!(" {:<width$} ", value, width = width))
+            .collect::<Vec<_>>()
+            .join("|");
+        out += &format!("{}\n", line.trim_end());
+        if i == 0 {
+            let rule = widths.iter().map(|w| "-".repeat(w + 2)).collect::<Vec<_>>().join("+");
+            out += &format!("{}\n", rule);
+        }
+    }
+
+    let names = |f: &dyn Fn(&Column) -> bool| {
+        table.columns.iter().filter(|c| f(c)).map(|c| c.name.clone()).collect::<Vec<_>>()
+    };
+    let primary_key = names(&|c| c.primary_key);
+    let indexes = names(&|c| c.index);
+    let unique = names(&|c| c.unique && !c.primary_key);
+    let references = table
+        .columns
+        .iter()
+        .filter_map(|c| c.references.as_ref().map(|r| format!("{} -> {}", c.name, r)))
+        .collect::<Vec<_>>();
+    for (label, items) in [
+        ("Primary key", primary_key),
+        ("Indexes", indexes),
+        ("Unique", unique),
+        ("Foreign keys", references),
+    ] {
+        if !items.is_empty() {
+            out += &format!("{}: {}\n", label, items.join(", "));
+        }
+    }
+    out
+}
+
+/// SQL keywords offered for tab completion.
+const KEYWORDS: &[&str] = &[
+    "AND",
+    "AS",
+    "ASC",
+    "BEGIN",
+    "BOOL",
+    "BOOLEAN",
+    "BY",
+    "CHAR",
+    "COMMIT",
+    "CREATE",
+    "CROSS",
+    "DEFAULT",
+    "DELETE",
+    "DESC",
+    "DOUBLE",
+    "DROP",
+    "EXPLAIN",
+    "FALSE",
+    "FLOAT",
+    "FROM",
+    "GROUP",
+    "HAVING",
+    "INDEX",
+    "INFINITY",
+    "INNER",
+    "INSERT",
+    "INT",
+    "INTEGER",
+    "INTO",
+    "IS",
+    "JOIN",
+    "KEY",
+    "LEFT",
+    "LIKE",
+    "LIMIT",
+    "NAN",
+    "NOT",
+    "NULL",
+    "OF",
+    "OFFSET",
+    "ON",
+    "ONLY",
+    "OR",
+    "ORDER",
+    "OUTER",
+    "PRIMARY",
+    "READ",
+    "REFERENCES",
+    "RIGHT",
+    "ROLLBACK",
+    "SELECT",
+    "SET",
+    "STRING",
+    "SYSTEM",
+    "TABLE",
+    "TEXT",
+    "TIME",
+    "TRANSACTION",
+    "TRUE",
+    "UNIQUE",
+    "UPDATE",
+    "VALUES",
+    "VARCHAR",
+    "WHERE",
+    "WRITE",
+];
+
+/// Keywords that are followed by a table name.
+const TABLE_KEYWORDS: &[&str] = &["FROM", "INTO", "JOIN", "TABLE", "UPDATE"];
+
+/// Commands that take a table name argument.
+const TABLE_COMMANDS: &[&str] = &["!table", "\\d"];
+
+/// A Rustyline helper for the REPL. It completes keywords and catalog names, highlights SQL
+/// syntax, and parses input lines to determine if they make up a complete command or not.
+#[derive(Helper, Hinter)]
+struct InputHelper {
+    /// Table names and their column names, for completion.
+    catalog: BTreeMap<String, Vec<String>>,
+}
+
+impl InputHelper {
+    fn new() -> Self {
+        Self { catalog: BTreeMap::new() }
+    }
+}
+
+impl Completer for InputHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let word = &line[start..];
+        let previous = line[..start].split_whitespace().last().unwrap_or("");
+
+        let mut candidates: Vec<String> = Vec::new();
+        if let Some((table, prefix)) = word.split_once('.') {
+            // Qualified column name.
+            if let Some(columns) = self.catalog.get(&table.to_lowercase()) {
+                candidates.extend(
+                    columns
+                        .iter()
+                        .filter(|c| c.starts_with(prefix))
+                        .map(|c| format!("{}.{}", table, c)),
+                );
+            }
+        } else if TABLE_COMMANDS.contains(&previous)
+            || TABLE_KEYWORDS.contains(&previous.to_uppercase().as_str())
+        {
+            candidates.extend(self.catalog.keys().filter(|t| t.starts_with(word)).cloned());
+        } else if !word.is_empty() {
+            // Match the case of the input for keywords.
+            let lowercase = word.starts_with(|c: char| c.is_lowercase());
+            candidates.extend(KEYWORDS.iter().filter(|k| k.starts_with(&word.to_uppercase())).map(
+                |k| {
+                    if lowercase {
+                        k.to_lowercase()
+                    } else {
+                        k.to_string()
+                    }
+                },
+            ));
+            let mut names: Vec<String> = self
+                .catalog
+                .iter()
+                .flat_map(|(table, columns)| std::iter::once(table).chain(columns))
+                .filter(|n| n.starts_with(word))
+                .cloned()
+                .collect();
+            names.sort();
+            names.dedup();
+            candidates.extend(names);
+        }
+
+        Ok((
+            start,
+            candidates.into_iter().map(|c| Pair { display: c.clone(), replacement: c }).collect(),
+        ))
+    }
+}
+
+impl Highlighter for InputHelper {
+    fn highlight<'l>(&self, line: &'l str, _: usize) -> Cow<'l, str> {
+        // Commands are highlighted as a whole.
+        if line.starts_with('!') || line.starts_with('\\') {
+            return Cow::Owned(format!("\x1b[35m{}\x1b[0m", line));
+        }
+
+        let mut out = String::with_capacity(line.len() * 2);
+        let mut chars = line.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            let mut end = start + c.len_utf8();
+            let color = match c {
+                '\'' | '"' => {
+                    // Consume until the closing quote. Doubled quotes are escapes, but are
+                    // handled naturally by closing and reopening the quote.
+                    for (i, next) in chars.by_ref() {
+                        end = i + next.len_utf8();
+                        if next == c {
+                            break;
+                        }
+                    }
+                    if c == '\'' {
+                        Some("32")
+                    } else {
+                        None
+                    }
+                }
+                c if c.is_ascii_digit() => {
+                    while let Some((i, next)) =
+                        chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '.')
+                    {
+                        end = i + next.len_utf8();
+                    }
+                    Some("36")
+                }
+                c if c.is_alphabetic() || c == ':' => {
+                    while let Some((i, next)) =
+                        chars.next_if(|(_, c)| c.is_alphanumeric() || *c == '_')
+                    {
+                        end = i + next.len_utf8();
+                    }
+                    if c == ':' && end > start + 1 {
+                        Some("33")
+                    } else if Keyword::from_str(&line[start..end]).is_some() {
+                        Some("1;34")
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            };
+            match color {
+                Some(color) => {
+                    out.push_str(&format!("\x1b[{}m{}\x1b[0m", color, &line[start..end]))
+                }
+                None => out.push_str(&line[start..end]),
+            }
+        }
+        Cow::Owned(out)
+    }
+
+    fn highlight_char(&self, _: &str, _: usize, _: bool) -> bool {
+        true
+    }
+}
 
-impl Validator for InputValidator {
+impl Validator for InputHelper {
     fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
         let input = ctx.input();
 
-        // Empty lines and ! commands are fine.
-        if input.is_empty() || input.starts_with('!') || input == ";" {
+        // Empty lines and ! or \ commands are fine.
+        if input.is_empty() || input.starts_with('!') || input.starts_with('\\') || input == ";" {
             return Ok(ValidationResult::Valid(None));
         }
 
@@ -0,0 +1,430 @@
+//! Change data capture (CDC): exports committed row changes to an external
+//! sink, either a file, an HTTP webhook, or a Kafka-compatible REST proxy.
+//!
+//! An Exporter scans each table's row changes made after the last exported
+//! version in a read-only transaction (see Transaction::scan_changes), encodes
+//! them as JSON objects ordered by commit version, and sends them to the sink.
+//! Once the sink has accepted them, the transaction's resolved version is
+//! stored as a checkpoint in the "cdc" key/value namespace, keyed by the sink
+//! URL, such that any node can resume the export after a leader change.
+//!
+//! Delivery is at least once: if the export fails after sending changes but
+//! before storing the checkpoint, or changes above the resolved version were
+//! sent while an older transaction was still active, they're sent again.
+//! Consumers can deduplicate changes by table, primary key, and version.
+//! Columnar tables are not exported.
+
+use crate::encoding::json;
+use crate::error::{Error, Result};
+use crate::sql::engine::{Engine, RowChange, Transaction as _};
+use crate::sql::schema::{Catalog as _, Table};
+use crate::sql::types::Row;
+
+use std::io::{Read as _, Write as _};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// The key/value namespace that stores export checkpoints, by sink URL.
+const CHECKPOINT_NAMESPACE: &str = "cdc";
+
+/// The maximum number of changes sent to a sink at once.
+const BATCH_SIZE: usize = 1000;
+
+/// The timeout for HTTP sink requests.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A CDC sink, which delivers batches of row changes encoded as JSON objects.
+pub trait Sink: Send {
+    /// Delivers the changes, only returning once the sink has durably accepted
+    /// them. The same changes may be sent again after a failure.
+    fn send(&mut self, changes: &[String]) -> Result<()>;
+}
+
+/// Opens a sink given by a URL:
+///
+/// * file:///path: appends changes to a file as JSON lines.
+/// * http://host:port/path: posts changes to a webhook as a JSON array.
+/// * kafka://host:port/topic: produces changes to a Kafka topic via a REST
+///   proxy at the given address, as JSON records.
+pub fn open(url: &str) -> Result<Box<dyn Sink>> {
+    if let Some(path) = url.strip_prefix("file://") {
+        Ok(Box::new(FileSink::new(path)?))
+    } else if url.starts_with("http://") {
+        Ok(Box::new(WebhookSink::new(url)?))
+    } else if let Some(rest) = url.strip_prefix("kafka://") {
+        Ok(Box::new(KafkaSink::new(rest)?))
+    } else {
+        Err(Error::Config(format!("Invalid CDC sink URL {}", url)))
+    }
+}
+
+/// A sink that appends changes to a file as JSON lines, syncing them to disk.
+pub struct FileSink {
+    file: std::fs::File,
+}
+
+impl FileSink {
+    /// Opens the file for appending, creating it if necessary.
+    pub fn new(path: &str) -> Result<Self> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+}
+
+impl Sink for FileSink {
+    fn send(&mut self, changes: &[String]) -> Result<()> {
+        let mut lines = String::new();
+        for change in changes {
+            lines.push_str(change);
+            lines.push('\n');
+        }
+        self.file.write_all(lines.as_bytes())?;
+        Ok(self.file.sync_data()?)
+    }
+}
+
+/// A sink that posts changes to an HTTP webhook as a JSON array. Any 2xx
+/// response status accepts them.
+pub struct WebhookSink {
+    addr: String,
+    path: String,
+}
+
+impl WebhookSink {
+    /// Creates a webhook sink for an http:// URL.
+    pub fn new(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| Error::Config(format!("Invalid webhook URL {}", url)))?;
+        let (addr, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        Ok(Self { addr: with_port(addr), path: path.to_string() })
+    }
+}
+
+impl Sink for WebhookSink {
+    fn send(&mut self, changes: &[String]) -> Result<()> {
+        let body = format!("[{}]", changes.join(","));
+        post(&self.addr, &self.path, "application/json", &body)
+    }
+}
+
+/// A sink that produces changes to a Kafka topic via a Kafka REST proxy (e.g.
+/// the Confluent REST Proxy v2 API), as JSON records.
+pub struct KafkaSink {
+    addr: String,
+    topic: String,
+}
+
+impl KafkaSink {
+    /// Creates a Kafka sink, given a REST proxy address and topic as
+    /// host:port/topic.
+    pub fn new(target: &str) -> Result<Self> {
+        match target.split_once('/') {
+            Some((addr, topic)) if !topic.is_empty() && !topic.contains('/') => {
+                Ok(Self { addr: with_port(addr), topic: topic.to_string() })
+            }
+            _ => Err(Error::Config(format!(
+                "Invalid Kafka sink {}, expected host:port/topic",
+                target
+            ))),
+        }
+    }
+}
+
+impl Sink for KafkaSink {
+    fn send(&mut self, changes: &[String]) -> Result<()> {
+        let records: Vec<_> = changes.iter().map(|c| format!("{{\"value\":{}}}", c)).collect();
+        let body = format!("{{\"records\":[{}]}}", records.join(","));
+        let path = format!("/topics/{}", self.topic);
+        post(&self.addr, &path, "application/vnd.kafka.json.v2+json", &body)
+    }
+}
+
+/// Adds the default HTTP port to an address without one.
+fn with_port(addr: &str) -> String {
+    match addr.contains(':') {
+        true => addr.to_string(),
+        false => format!("{}:80", addr),
+    }
+}
+
+/// Sends an HTTP/1.1 POST request, erroring unless the response status is 2xx.
+fn post(addr: &str, path: &str, content_type: &str, body: &str) -> Result<()> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+    let host = addr.strip_suffix(":80").unwrap_or(addr);
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        path,
+        host,
+        content_type,
+        body.len(),
+        body
+    )?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') && code.len() == 3 => Ok(()),
+        _ => Err(Error::Value(format!("CDC sink {} responded with {:?}", addr, status))),
+    }
+}
+
+/// Exports committed row changes from an engine to a sink.
+pub struct Exporter<E: Engine> {
+    engine: E,
+    /// The sink URL, used as the checkpoint key.
+    url: String,
+    sink: Box<dyn Sink>,
+}
+
+impl<E: Engine> Exporter<E> {
+    /// Creates an exporter for the sink at the given URL.
+    pub fn new(engine: E, url: &str) -> Result<Self> {
+        Ok(Self::with_sink(engine, url, open(url)?))
+    }
+
+    /// Creates an exporter for the given sink, checkpointed under the given
+    /// URL.
+    pub fn with_sink(engine: E, url: &str, sink: Box<dyn Sink>) -> Self {
+        Self { engine, url: url.to_string(), sink }
+    }
+
+    /// Returns the checkpoint, i.e. the version up to which all changes have
+    /// been exported.
+    pub fn checkpoint(&self) -> Result<u64> {
+        let txn = self.engine.begin_read_only()?;
+        let checkpoint = txn.kv_get(CHECKPOINT_NAMESPACE, self.url.as_bytes())?;
+        txn.rollback()?;
+        match checkpoint {
+            Some(bytes) => Ok(u64::from_be_bytes(
+                bytes
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| Error::Internal(format!("Invalid CDC checkpoint {:?}", bytes)))?,
+            )),
+            None => Ok(0),
+        }
+    }
+
+    /// Exports all changes since the checkpoint to the sink and advances the
+    /// checkpoint, returning the number of changes exported.
+    pub fn export(&mut self) -> Result<u64> {
+        let checkpoint = self.checkpoint()?;
+        let txn = self.engine.begin_read_only()?;
+        let mut changes = Vec::new();
+        for table in txn.scan_tables()? {
+            if table.layout == crate::sql::engine::Layout::Columnar {
+                continue;
+            }
+            for change in txn.scan_changes(&table.name, checkpoint)? {
+                changes.push((change.version, encode(&table, &change)));
+            }
+        }
+        let resolved = txn.resolved_version();
+        txn.rollback()?;
+
+        changes.sort_by_key(|(version, _)| *version);
+        let changes: Vec<_> = changes.into_iter().map(|(_, change)| change).collect();
+        for batch in changes.chunks(BATCH_SIZE) {
+            self.sink.send(batch)?;
+        }
+
+        // Only advance the checkpoint when changes were exported, to avoid
+        // writing it on every idle export.
+        if !changes.is_empty() && resolved > checkpoint {
+            let mut txn = self.engine.begin()?;
+            txn.kv_set(CHECKPOINT_NAMESPACE, self.url.as_bytes(), resolved.to_be_bytes().to_vec())?;
+            txn.commit()?;
+        }
+        Ok(changes.len() as u64)
+    }
+}
+
+/// Encodes a row change as a JSON object, with rows as objects keyed by
+/// column name.
+pub fn encode(table: &Table, change: &RowChange) -> String {
+    let row = |row: &Option<Row>| match row {
+        Some(row) => json::object(table.columns.iter().map(|c| c.name.as_str()).zip(row.iter())),
+        None => "null".to_string(),
+    };
+    format!(
+        "{{\"table\":{},\"op\":\"{}\",\"id\":{},\"version\":{},\"before\":{},\"after\":{}}}",
+        json::quote(&change.table),
+        change.op(),
+        json::value(&change.id),
+        change.version,
+        row(&change.before),
+        row(&change.after),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::engine::KV;
+    use crate::storage::engine::Memory;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// A sink that records changes in memory, and can be made to fail.
+    #[derive(Clone, Default)]
+    struct MemorySink {
+        changes: Arc<Mutex<Vec<String>>>,
+        fail: Arc<Mutex<bool>>,
+    }
+
+    impl Sink for MemorySink {
+        fn send(&mut self, changes: &[String]) -> Result<()> {
+            if *self.fail.lock()? {
+                return Err(Error::Value("sink failed".into()));
+            }
+            self.changes.lock()?.extend_from_slice(changes);
+            Ok(())
+        }
+    }
+
+    impl MemorySink {
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut *self.changes.lock().unwrap())
+        }
+    }
+
+    #[test]
+    // Changes are exported in version order once, unless the sink fails, in
+    // which case they're sent again. Transactions that are active during an
+    // export are picked up by the next one.
+    fn export() -> Result<()> {
+        let engine = KV::new(Memory::new());
+        let sink = MemorySink::default();
+        let mut exporter = Exporter::with_sink(engine.clone(), "memory", Box::new(sink.clone()));
+        let mut session = engine.session()?;
+        session.execute("CREATE TABLE a (id INTEGER PRIMARY KEY, value STRING)")?;
+        session.execute("CREATE TABLE b (id INTEGER PRIMARY KEY)")?;
+        session.execute("INSERT INTO b VALUES (1)")?;
+        session.execute("INSERT INTO a VALUES (1, 'x'), (2, 'y')")?;
+
+        assert_eq!(exporter.export()?, 3);
+        assert_eq!(
+            sink.take(),
+            vec![
+                r#"{"table":"b","op":"insert","id":1,"version":3,"before":null,"after":{"id":1}}"#,
+                r#"{"table":"a","op":"insert","id":1,"version":4,"before":null,"after":{"id":1,"value":"x"}}"#,
+                r#"{"table":"a","op":"insert","id":2,"version":4,"before":null,"after":{"id":2,"value":"y"}}"#,
+            ]
+        );
+        assert_eq!(exporter.checkpoint()?, 4);
+        assert_eq!(exporter.export()?, 0);
+
+        // A failed export is retried.
+        session.execute("UPDATE a SET value = 'z' WHERE id = 1")?;
+        *sink.fail.lock()? = true;
+        assert!(exporter.export().is_err());
+        *sink.fail.lock()? = false;
+        assert_eq!(exporter.export()?, 1);
+        assert_eq!(
+            sink.take(),
+            vec![
+                r#"{"table":"a","op":"update","id":1,"version":6,"before":{"id":1,"value":"x"},"after":{"id":1,"value":"z"}}"#
+            ]
+        );
+
+        // An active transaction holds back the checkpoint, so later changes
+        // are exported again along with its own once it commits.
+        let mut active = engine.session()?;
+        active.execute("BEGIN")?;
+        active.execute("DELETE FROM b WHERE id = 1")?;
+        session.execute("INSERT INTO b VALUES (2)")?;
+        assert_eq!(exporter.export()?, 1);
+        let checkpoint = exporter.checkpoint()?;
+        active.execute("COMMIT")?;
+        assert_eq!(exporter.export()?, 2);
+        let changes = sink.take();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0], changes[2]);
+        assert!(changes[1].contains(r#""op":"delete""#));
+        assert!(exporter.checkpoint()? > checkpoint);
+        Ok(())
+    }
+
+    /// Starts an HTTP server that accepts a single request, returning the
+    /// address and a handle that returns the request.
+    fn http_server(status: &'static str) -> Result<(String, std::thread::JoinHandle<String>)> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?.to_string();
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|l| l.strip_prefix("Content-Length: "))
+                        .map(|l| l.parse::<usize>().unwrap())
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        Ok((addr, handle))
+    }
+
+    #[test]
+    // The webhook sink posts a JSON array, and errors on non-2xx responses.
+    fn webhook() -> Result<()> {
+        let (addr, handle) = http_server("204 No Content")?;
+        let mut sink = open(&format!("http://{}/changes", addr))?;
+        sink.send(&["{\"a\":1}".to_string(), "{\"b\":2}".to_string()])?;
+        let request = handle.join().unwrap();
+        assert!(request.starts_with("POST /changes HTTP/1.1\r\n"));
+        assert!(request.contains("Content-Type: application/json\r\n"));
+        assert!(request.ends_with("\r\n\r\n[{\"a\":1},{\"b\":2}]"));
+
+        let (addr, handle) = http_server("500 Internal Server Error")?;
+        assert!(open(&format!("http://{}", addr))?.send(&[]).is_err());
+        assert!(handle.join().unwrap().starts_with("POST / HTTP/1.1\r\n"));
+        Ok(())
+    }
+
+    #[test]
+    // The Kafka sink produces records to the topic via the REST proxy API.
+    fn kafka() -> Result<()> {
+        let (addr, handle) = http_server("200 OK")?;
+        let mut sink = open(&format!("kafka://{}/changes", addr))?;
+        sink.send(&["{\"a\":1}".to_string()])?;
+        let request = handle.join().unwrap();
+        assert!(request.starts_with("POST /topics/changes HTTP/1.1\r\n"));
+        assert!(request.contains("Content-Type: application/vnd.kafka.json.v2+json\r\n"));
+        assert!(request.ends_with("\r\n\r\n{\"records\":[{\"value\":{\"a\":1}}]}"));
+
+        assert!(open("kafka://localhost:8082").is_err());
+        Ok(())
+    }
+
+    #[test]
+    // The file sink appends JSON lines.
+    fn file() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let path = dir.path().join("changes.json");
+        let url = format!("file://{}", path.display());
+        open(&url)?.send(&["{\"a\":1}".to_string()])?;
+        open(&url)?.send(&["{\"b\":2}".to_string(), "{\"c\":3}".to_string()])?;
+        assert_eq!(std::fs::read_to_string(&path)?, "{\"a\":1}\n{\"b\":2}\n{\"c\":3}\n");
+        Ok(())
+    }
+}
@@ -1,17 +1,22 @@
 use crate::error::{Error, Result};
-use crate::server::{Request, Response};
-use crate::sql::engine::Status;
-use crate::sql::execution::ResultSet;
+use crate::protocol;
+use crate::raft::{self, HistoryEntry};
+use crate::server::{Metrics, Request, Response};
+use crate::sql::engine::{NodeLiveness, Status, TransactionStatus};
+use crate::sql::execution::{CommandTag, ResultSet};
 use crate::sql::schema::Table;
+use crate::sql::types::{Columns, Row, Value};
 
 use futures::future::FutureExt as _;
 use futures::sink::SinkExt as _;
 use futures::stream::TryStreamExt as _;
 use rand::Rng as _;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::future::Future;
-use std::ops::{Deref, Drop};
+use std::ops::{Deref, Drop, RangeBounds};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::{TcpStream, ToSocketAddrs};
 use tokio::sync::{Mutex, MutexGuard};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
@@ -26,73 +31,347 @@ type Connection = tokio_serde::Framed<
 /// Number of serialization retries in with_txn()
 const WITH_TXN_RETRIES: u8 = 8;
 
+/// The maximum number of redirects to follow for a request, e.g. while the
+/// cluster elects a new leader.
+const MAX_REDIRECTS: u8 = 3;
+
 /// A toyDB client
 #[derive(Clone)]
 pub struct Client {
     conn: Arc<Mutex<Connection>>,
-    txn: Cell<Option<(u64, bool)>>,
+    txn: Cell<Option<TransactionStatus>>,
+    execution_time: Cell<Option<Duration>>,
+    last_key: RefCell<Option<Value>>,
+    command_tag: RefCell<Option<CommandTag>>,
+    timeout: Cell<Option<Duration>>,
+    stale: Cell<bool>,
+    /// The session token, shared with clones since they share the session.
+    token: Arc<AtomicU64>,
+    version: Cell<u32>,
 }
 
 impl Client {
     /// Creates a new client
     pub async fn new<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        let (conn, version) = Self::connect(addr).await?;
         Ok(Self {
-            conn: Arc::new(Mutex::new(tokio_serde::Framed::new(
-                Framed::new(TcpStream::connect(addr).await?, LengthDelimitedCodec::new()),
-                tokio_serde::formats::Bincode::default(),
-            ))),
+            conn: Arc::new(Mutex::new(conn)),
             txn: Cell::new(None),
+            execution_time: Cell::new(None),
+            last_key: RefCell::new(None),
+            command_tag: RefCell::new(None),
+            timeout: Cell::new(None),
+            stale: Cell::new(false),
+            token: Arc::new(AtomicU64::new(0)),
+            version: Cell::new(version),
         })
     }
 
+    /// Connects to a server, returning the connection and protocol version
+    async fn connect<A: ToSocketAddrs>(addr: A) -> Result<(Connection, u32)> {
+        let mut socket = TcpStream::connect(addr).await?;
+        let version = protocol::connect(&mut socket).await?;
+        let conn = tokio_serde::Framed::new(
+            Framed::new(socket, LengthDelimitedCodec::new()),
+            tokio_serde::formats::Bincode::default(),
+        );
+        Ok((conn, version))
+    }
+
+    /// Reconnects to the given address after a redirect to the leader. The
+    /// server only redirects requests outside of transactions, so there is no
+    /// transaction state to carry over, but the server-side session is lost.
+    async fn reconnect(&self, conn: &mut MutexGuard<'_, Connection>, address: &str) -> Result<()> {
+        let (new, version) = Self::connect(address).await?;
+        **conn = new;
+        self.version.set(version);
+        Ok(())
+    }
+
+    /// Returns the protocol version negotiated with the server
+    pub fn protocol_version(&self) -> u32 {
+        self.version.get()
+    }
+
+    /// Sets a timeout for subsequent requests, after which they fail with
+    /// Error::Timeout. The outcome of a timed out write is unknown, and a timed
+    /// out request in a transaction aborts it, so it must be rolled back.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.timeout.set(timeout);
+        Ok(())
+    }
+
+    /// Enables or disables stale reads. Read-only transactions, including
+    /// single SELECT statements, are then served by the connected server even
+    /// if it's a follower. They may not see the latest writes, but always see
+    /// writes made via this client, using a session token that is updated on
+    /// every request.
+    pub fn set_stale_reads(&self, enabled: bool) -> Result<()> {
+        self.stale.set(enabled);
+        Ok(())
+    }
+
+    /// Returns the session token, e.g. to pass to a different client via
+    /// set_token() such that its stale reads see this client's writes.
+    pub fn token(&self) -> u64 {
+        self.token.load(Ordering::SeqCst)
+    }
+
+    /// Sets the session token, if it's greater than the current token.
+    pub fn set_token(&self, token: u64) {
+        self.token.fetch_max(token, Ordering::SeqCst);
+    }
+
+    /// Wraps a request with the timeout and stale reads, if enabled
+    fn with_options(&self, mut request: Request) -> Request {
+        if self.stale.get() {
+            request = Request::Stale(self.token(), Box::new(request));
+        }
+        if let Some(timeout) = self.timeout.get() {
+            request = Request::Timeout(timeout, Box::new(request));
+        }
+        request
+    }
+
+    /// Receives the session token sent after a request with stale reads
+    async fn receive_token(&self, conn: &mut MutexGuard<'_, Connection>) -> Result<()> {
+        match conn.try_next().await? {
+            Some(Ok(Response::Token(token))) => {
+                self.set_token(token);
+                Ok(())
+            }
+            Some(Err(error)) => Err(error),
+            Some(response) => Err(Error::Internal(format!("Unexpected response {:?}", response))),
+            None => Err(Error::Internal("Server disconnected".into())),
+        }
+    }
+
+    /// Receives the Raft history sent after a status request
+    async fn receive_history(
+        &self,
+        conn: &mut MutexGuard<'_, Connection>,
+    ) -> Result<Vec<HistoryEntry>> {
+        match conn.try_next().await? {
+            Some(Ok(Response::History(history))) => Ok(history),
+            Some(Err(error)) => Err(error),
+            Some(response) => Err(Error::Internal(format!("Unexpected response {:?}", response))),
+            None => Err(Error::Internal("Server disconnected".into())),
+        }
+    }
+
     /// Call a server method
     async fn call(&self, request: Request) -> Result<Response> {
         let mut conn = self.conn.lock().await;
         self.call_locked(&mut conn, request).await
     }
 
-    /// Call a server method while holding the mutex lock
+    /// Call a server method while holding the mutex lock, following redirects
+    /// to the leader
     async fn call_locked(
         &self,
         conn: &mut MutexGuard<'_, Connection>,
         request: Request,
     ) -> Result<Response> {
-        conn.send(request).await?;
-        match conn.try_next().await? {
-            Some(result) => result,
-            None => Err(Error::Internal("Server disconnected".into())),
+        let stale = self.stale.get();
+        let mut redirects = 0;
+        loop {
+            conn.send(self.with_options(request.clone())).await?;
+            let mut response = match conn.try_next().await? {
+                Some(result) => result,
+                None => return Err(Error::Internal("Server disconnected".into())),
+            };
+            if matches!(request, Request::Status) {
+                let history = self.receive_history(conn).await?;
+                if let Ok(Response::Status(status)) = &mut response {
+                    status.raft.history = history;
+                }
+            }
+            if stale {
+                self.receive_token(conn).await?;
+            }
+            match response {
+                Err(Error::Redirect { address, .. }) if redirects < MAX_REDIRECTS => {
+                    self.reconnect(conn, &address).await?;
+                    redirects += 1;
+                }
+                response => return response,
+            }
         }
     }
 
     /// Executes a query
     pub async fn execute(&self, query: &str) -> Result<ResultSet> {
+        let mut rows = Vec::new();
+        let resultset = self
+            .execute_with(query, |_, row| {
+                rows.push(row);
+                Ok(())
+            })
+            .await?;
+        Ok(match resultset {
+            ResultSet::Query { columns, key_column, .. } => {
+                ResultSet::Query { columns, key_column, rows: Box::new(rows.into_iter().map(Ok)) }
+            }
+            resultset => resultset,
+        })
+    }
+
+    /// Executes a query, passing result rows to the given closure as they are streamed from the
+    /// server rather than buffering them. Rows are passed in the order the server returns them,
+    /// i.e. the ORDER BY order if given. Query result sets are returned without rows. If the
+    /// closure fails, the remaining rows are discarded and its error is returned.
+    pub async fn execute_with<F>(&self, query: &str, mut f: F) -> Result<ResultSet>
+    where
+        F: FnMut(&Columns, Row) -> Result<()>,
+    {
         let mut conn = self.conn.lock().await;
-        let mut resultset =
-            match self.call_locked(&mut conn, Request::Execute(query.into())).await? {
-                Response::Execute(rs) => rs,
-                resp => return Err(Error::Internal(format!("Unexpected response {:?}", resp))),
-            };
-        if let ResultSet::Query { columns, .. } = resultset {
-            // FIXME We buffer rows for now to avoid lifetime hassles
-            let mut rows = Vec::new();
-            while let Some(result) = conn.try_next().await? {
-                match result? {
-                    Response::Row(Some(row)) => rows.push(row),
-                    Response::Row(None) => break,
-                    response => {
+        let stale = self.stale.get();
+        let mut redirects = 0;
+        loop {
+            conn.send(self.with_options(Request::Execute(query.into()))).await?;
+            let response = conn.try_next().await?;
+            let result = self.receive_resultset(&mut conn, response, &mut f).await?;
+
+            // The server follows up with the session's transaction status, even on errors.
+            match conn.try_next().await? {
+                Some(Ok(Response::Transaction(txn))) => self.txn.set(txn),
+                Some(response) => {
+                    return Err(Error::Internal(format!("Unexpected response {:?}", response)))
+                }
+                None => return Err(Error::Internal("Server disconnected".into())),
+            }
+            if stale {
+                self.receive_token(&mut conn).await?;
+            }
+            match result {
+                Err(Error::Redirect { address, .. }) if redirects < MAX_REDIRECTS => {
+                    self.reconnect(&mut conn, &address).await?;
+                    redirects += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Executes a string of semicolon-separated statements in order, returning the result of
+    /// each, with query rows buffered as for execute(). Execution stops at the first error,
+    /// which is returned as the last result. Syntax error positions are relative to the start
+    /// of the failed statement.
+    pub async fn execute_many(&self, query: &str) -> Result<Vec<Result<ResultSet>>> {
+        let mut conn = self.conn.lock().await;
+        let stale = self.stale.get();
+        let mut redirects = 0;
+        loop {
+            conn.send(self.with_options(Request::ExecuteMany(query.into()))).await?;
+
+            // The results are followed by the session's transaction status.
+            let mut results = Vec::new();
+            loop {
+                let response = match conn.try_next().await? {
+                    Some(Ok(Response::Transaction(txn))) => {
+                        self.txn.set(txn);
+                        break;
+                    }
+                    response => response,
+                };
+                let mut rows = Vec::new();
+                let result = self
+                    .receive_resultset(&mut conn, response, &mut |_, row| {
+                        rows.push(row);
+                        Ok(())
+                    })
+                    .await?;
+                results.push(result.map(|resultset| match resultset {
+                    ResultSet::Query { columns, key_column, .. } => ResultSet::Query {
+                        columns,
+                        key_column,
+                        rows: Box::new(rows.into_iter().map(Ok)),
+                    },
+                    resultset => resultset,
+                }));
+            }
+            if stale {
+                self.receive_token(&mut conn).await?;
+            }
+            match results.as_slice() {
+                [Err(Error::Redirect { address, .. })] if redirects < MAX_REDIRECTS => {
+                    let address = address.clone();
+                    self.reconnect(&mut conn, &address).await?;
+                    redirects += 1;
+                }
+                _ => return Ok(results),
+            }
+        }
+    }
+
+    /// Receives the result of an executed query, given its initial response, passing any rows to
+    /// the given closure. The outer result contains connection errors, while the inner result
+    /// contains errors returned by the server or the closure.
+    async fn receive_resultset<F>(
+        &self,
+        conn: &mut MutexGuard<'_, Connection>,
+        response: Option<Result<Response>>,
+        f: &mut F,
+    ) -> Result<Result<ResultSet>>
+    where
+        F: FnMut(&Columns, Row) -> Result<()>,
+    {
+        self.command_tag.replace(None);
+        let resultset = match response {
+            Some(Ok(Response::Execute(rs, execution_time))) => {
+                self.execution_time.set(Some(execution_time));
+                rs
+            }
+            Some(Ok(resp)) => {
+                return Err(Error::Internal(format!("Unexpected response {:?}", resp)))
+            }
+            Some(Err(error)) => return Ok(Err(error)),
+            None => return Err(Error::Internal("Server disconnected".into())),
+        };
+        self.last_key.replace(None);
+        if let ResultSet::Query { columns, .. } = &resultset {
+            // Rows must be drained from the connection even if the closure fails.
+            let mut result = Ok(());
+            loop {
+                match conn.try_next().await? {
+                    Some(Ok(Response::Row(Some(row)))) if result.is_ok() => {
+                        result = f(columns, row)
+                    }
+                    Some(Ok(Response::Row(Some(_)))) => {}
+                    Some(Ok(Response::RowsEnd(last_key))) => {
+                        self.last_key.replace(last_key);
+                        break;
+                    }
+                    Some(Ok(response)) => {
                         return Err(Error::Internal(format!("Unexpected response {:?}", response)))
                     }
+                    Some(Err(error)) => return Ok(Err(error)),
+                    None => return Err(Error::Internal("Server disconnected".into())),
                 }
             }
-            resultset = ResultSet::Query { columns, rows: Box::new(rows.into_iter().map(Ok)) }
+            if let Err(error) = result {
+                self.receive_command_tag(conn).await?;
+                return Ok(Err(error));
+            }
         };
-        match &resultset {
-            ResultSet::Begin { version, read_only } => self.txn.set(Some((*version, *read_only))),
-            ResultSet::Commit { .. } => self.txn.set(None),
-            ResultSet::Rollback { .. } => self.txn.set(None),
-            _ => {}
+        let tag = self.receive_command_tag(conn).await?;
+        self.command_tag.replace(Some(tag));
+        Ok(Ok(resultset))
+    }
+
+    /// Receives the command tag following a statement result.
+    async fn receive_command_tag(
+        &self,
+        conn: &mut MutexGuard<'_, Connection>,
+    ) -> Result<CommandTag> {
+        match conn.try_next().await? {
+            Some(Ok(Response::Complete(tag))) => Ok(tag),
+            Some(Ok(response)) => {
+                Err(Error::Internal(format!("Unexpected response {:?}", response)))
+            }
+            Some(Err(error)) => Err(error),
+            None => Err(Error::Internal("Server disconnected".into())),
         }
-        Ok(resultset)
     }
 
     /// Fetches the table schema as SQL
@@ -111,7 +390,8 @@ impl Client {
         }
     }
 
-    /// Checks server status
+    /// Checks server status, including the connected server's recent Raft
+    /// events.
     pub async fn status(&self) -> Result<Status> {
         match self.call(Request::Status).await? {
             Response::Status(s) => Ok(s),
@@ -119,13 +399,118 @@ impl Client {
         }
     }
 
+    /// Fetches the connected server's metrics.
+    pub async fn metrics(&self) -> Result<Metrics> {
+        match self.call(Request::Metrics).await? {
+            Response::Metrics(metrics) => Ok(metrics),
+            resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
+        }
+    }
+
+    /// Fetches the liveness of all cluster nodes, as seen by the connected
+    /// server. Requires liveness heartbeats enabled on the server.
+    pub async fn liveness(&self) -> Result<Vec<NodeLiveness>> {
+        match self.call(Request::Liveness).await? {
+            Response::Liveness(nodes) => Ok(nodes),
+            resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
+        }
+    }
+
+    /// Reads up to limit committed Raft log entries from the given index, preceded by a
+    /// snapshot if they were compacted. Standby clusters use this to replicate the cluster.
+    pub async fn read_log(
+        &self,
+        from: raft::Index,
+        limit: usize,
+    ) -> Result<(Option<raft::Snapshot>, Vec<raft::Entry>)> {
+        match self.call(Request::ReadLog { from, limit }).await? {
+            Response::Log { snapshot, entries } => Ok((snapshot, entries)),
+            resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
+        }
+    }
+
+    /// Promotes a standby cluster to a primary, such that it stops replicating its primary and
+    /// allows writes. Returns the last replicated primary log index.
+    pub async fn promote(&self) -> Result<raft::Index> {
+        match self.call(Request::Promote).await? {
+            Response::Promote(index) => Ok(index),
+            resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
+        }
+    }
+
+    /// Fetches the value of a key in a key/value namespace, if it exists. Key/value pairs are
+    /// separate from SQL tables, and use the session's transaction if any.
+    pub async fn kv_get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.call(Request::KVGet { namespace: namespace.into(), key: key.into() }).await? {
+            Response::KVGet(value) => Ok(value),
+            resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
+        }
+    }
+
+    /// Sets the value of a key in a key/value namespace.
+    pub async fn kv_set(&self, namespace: &str, key: &[u8], value: Vec<u8>) -> Result<()> {
+        match self
+            .call(Request::KVSet { namespace: namespace.into(), key: key.into(), value })
+            .await?
+        {
+            Response::KVSet => Ok(()),
+            resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
+        }
+    }
+
+    /// Deletes a key in a key/value namespace, if it exists.
+    pub async fn kv_delete(&self, namespace: &str, key: &[u8]) -> Result<()> {
+        match self.call(Request::KVDelete { namespace: namespace.into(), key: key.into() }).await? {
+            Response::KVDelete => Ok(()),
+            resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
+        }
+    }
+
+    /// Scans a key range in a key/value namespace, in key order.
+    pub async fn kv_scan(
+        &self,
+        namespace: &str,
+        range: impl RangeBounds<Vec<u8>>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        match self.call(Request::KVScan { namespace: namespace.into(), range }).await? {
+            Response::KVScan(pairs) => Ok(pairs),
+            resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
+        }
+    }
+
+    /// Returns the server-side execution time of the last statement executed via execute(),
+    /// excluding the time spent streaming query result rows
+    pub fn execution_time(&self) -> Option<Duration> {
+        self.execution_time.get()
+    }
+
+    /// Returns the primary key of the last row returned by the last query, if it returned its
+    /// table's primary key column. This allows keyset pagination, where the next page is fetched
+    /// with e.g. WHERE id > last_key ORDER BY id LIMIT n, instead of using a deep OFFSET which
+    /// must read and skip all previous rows.
+    pub fn last_key(&self) -> Option<Value> {
+        self.last_key.borrow().clone()
+    }
+
+    /// Returns the command tag of the last statement executed via execute(), e.g. UPDATE 3,
+    /// including the number of rows it affected or returned. None if the statement failed.
+    pub fn command_tag(&self) -> Option<CommandTag> {
+        self.command_tag.borrow().clone()
+    }
+
     /// Returns the version and read-only state of the txn
     pub fn txn(&self) -> Option<(u64, bool)> {
-        self.txn.get()
+        self.txn.get().map(|txn| (txn.version, txn.read_only))
+    }
+
+    /// Returns whether the txn has been aborted by an error, and must be rolled back
+    pub fn txn_aborted(&self) -> bool {
+        self.txn.get().is_some_and(|txn| txn.aborted)
     }
 
     /// Runs a query in a transaction, automatically retrying serialization failures with
-    /// exponential backoff.
+    /// exponential backoff. Rate limited transactions are retried once the server allows it.
     pub async fn with_txn<W, F, R>(&self, mut with: W) -> Result<R>
     where
         W: FnMut(Client) -> F,
@@ -147,7 +532,12 @@ impl Client {
             .await;
             if result.is_err() {
                 self.execute("ROLLBACK").await.ok();
-                if matches!(result, Err(Error::Serialization) | Err(Error::Abort)) {
+                if let Err(Error::RateLimited(wait)) = result {
+                    tokio::time::sleep(wait).await;
+                    continue;
+                }
+                if matches!(result, Err(Error::Serialization | Error::Abort | Error::NotLeader(_)))
+                {
                     continue;
                 }
             }
@@ -0,0 +1,213 @@
+//! Clocks, for timestamps that are safe to compare across nodes.
+//!
+//! Physical clocks on different nodes are never perfectly synchronized, and a
+//! node's clock may even jump backwards, so wall-clock time alone can't order
+//! events across nodes. A hybrid logical clock (HLC) combines a physical clock
+//! with a logical counter: its timestamps never go backwards, and nodes
+//! include them in messages such that a receiver's clock is advanced past the
+//! sender's. Any event that causally follows another thus gets a higher
+//! timestamp, regardless of clock skew, while timestamps stay close to
+//! wall-clock time. See "Logical Physical Clocks and Consistent Snapshots in
+//! Globally Distributed Databases" by Kulkarni et al.
+//!
+//! The physical clock is abstracted by the Clock trait, such that tests can
+//! control time with a ManualClock.
+
+use ::log::warn;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Remote timestamps further ahead of the local physical clock than this, in
+/// milliseconds, are logged as clock skew warnings.
+const MAX_OFFSET: u64 = 500;
+
+/// A physical clock.
+pub trait Clock: Send + Sync {
+    /// Returns the current time in milliseconds since the Unix epoch.
+    fn now(&self) -> u64;
+}
+
+/// The system wall clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+    }
+}
+
+/// A clock that only changes when told to, for tests. Cloning it returns a
+/// handle to the same clock.
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock(Arc<AtomicU64>);
+
+impl ManualClock {
+    /// Creates a new clock at the given time, in milliseconds since the epoch.
+    pub fn new(now: u64) -> Self {
+        Self(Arc::new(AtomicU64::new(now)))
+    }
+
+    /// Sets the time, possibly backwards.
+    pub fn set(&self, now: u64) {
+        self.0.store(now, Ordering::SeqCst)
+    }
+
+    /// Advances the time by the given number of milliseconds.
+    pub fn advance(&self, millis: u64) {
+        self.0.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// A hybrid logical clock timestamp, ordered by physical time and then by the
+/// logical counter.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Timestamp {
+    /// The physical time in milliseconds since the Unix epoch.
+    pub physical: u64,
+    /// A logical counter, ordering timestamps with the same physical time.
+    pub logical: u32,
+}
+
+impl Timestamp {
+    /// Creates a new timestamp.
+    pub fn new(physical: u64, logical: u32) -> Self {
+        Self { physical, logical }
+    }
+
+    /// Returns the next timestamp after this one, at the same physical time
+    /// unless the logical counter overflows.
+    pub fn next(&self) -> Self {
+        match self.logical.checked_add(1) {
+            Some(logical) => Self::new(self.physical, logical),
+            None => Self::new(self.physical + 1, 0),
+        }
+    }
+}
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.physical, self.logical)
+    }
+}
+
+/// A hybrid logical clock. Cloning it returns a handle to the same clock.
+#[derive(Clone)]
+pub struct HybridClock {
+    clock: Arc<dyn Clock>,
+    last: Arc<Mutex<Timestamp>>,
+}
+
+impl std::fmt::Debug for HybridClock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HybridClock").field("last", &self.last()).finish()
+    }
+}
+
+impl HybridClock {
+    /// Creates a new hybrid logical clock using the given physical clock.
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        Self { clock: Arc::new(clock), last: Arc::new(Mutex::new(Timestamp::default())) }
+    }
+
+    /// Creates a new hybrid logical clock using the system clock.
+    pub fn system() -> Self {
+        Self::new(SystemClock)
+    }
+
+    /// Returns a new timestamp, greater than any previous timestamp returned
+    /// or received by the clock. This is used for local events and for
+    /// timestamps sent in messages.
+    pub fn now(&self) -> Timestamp {
+        let physical = self.clock.now();
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        *last = match physical > last.physical {
+            true => Timestamp::new(physical, 0),
+            false => last.next(),
+        };
+        *last
+    }
+
+    /// Updates the clock with a timestamp received from another node, and
+    /// returns a new timestamp greater than it and any previous timestamp.
+    pub fn update(&self, remote: Timestamp) -> Timestamp {
+        let physical = self.clock.now();
+        if remote.physical > physical.saturating_add(MAX_OFFSET) {
+            warn!(
+                "Received timestamp {} is {}ms ahead of the local clock, clocks may be skewed",
+                remote,
+                remote.physical - physical
+            );
+        }
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        let max = (*last).max(remote);
+        *last = match physical > max.physical {
+            true => Timestamp::new(physical, 0),
+            false => max.next(),
+        };
+        *last
+    }
+
+    /// Returns the last timestamp returned by the clock, without advancing it.
+    pub fn last(&self) -> Timestamp {
+        *self.last.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // Timestamps follow the physical clock, and use the logical counter when
+    // it stands still or goes backwards.
+    fn now() {
+        let physical = ManualClock::new(100);
+        let clock = HybridClock::new(physical.clone());
+        assert_eq!(clock.now(), Timestamp::new(100, 0));
+        assert_eq!(clock.now(), Timestamp::new(100, 1));
+
+        physical.advance(10);
+        assert_eq!(clock.now(), Timestamp::new(110, 0));
+
+        physical.set(50);
+        assert_eq!(clock.now(), Timestamp::new(110, 1));
+        assert_eq!(clock.last(), Timestamp::new(110, 1));
+    }
+
+    #[test]
+    // Received timestamps advance the clock past them, but timestamps from
+    // behind don't move it backwards.
+    fn update() {
+        let physical = ManualClock::new(100);
+        let clock = HybridClock::new(physical.clone());
+        assert_eq!(clock.update(Timestamp::new(200, 3)), Timestamp::new(200, 4));
+        assert_eq!(clock.now(), Timestamp::new(200, 5));
+        assert_eq!(clock.update(Timestamp::new(150, 9)), Timestamp::new(200, 6));
+
+        physical.set(300);
+        assert_eq!(clock.update(Timestamp::new(250, 0)), Timestamp::new(300, 0));
+
+        // Causality holds across clocks: a message's receiver gets a higher
+        // timestamp than its sender, even if its physical clock is behind.
+        let other = HybridClock::new(ManualClock::new(10));
+        let sent = clock.now();
+        assert!(other.update(sent) > sent);
+    }
+
+    #[test]
+    // A logical counter overflow spills into the physical time.
+    fn next_overflow() {
+        assert_eq!(Timestamp::new(1, u32::MAX).next(), Timestamp::new(2, 0));
+    }
+}
@@ -0,0 +1,135 @@
+//! An embedded toyDB database, which runs the SQL engine directly on local
+//! storage in the current process, without Raft replication, servers, or
+//! network sockets. This is useful for embedding toyDB in other applications.
+//!
+//! Statements are executed via sessions, just like with the client:
+//!
+//! ```
+//! use toydb::sql::execution::ResultSet;
+//! use toydb::Database;
+//!
+//! let db = Database::memory();
+//! db.execute("CREATE TABLE movies (id INTEGER PRIMARY KEY, title VARCHAR NOT NULL)")?;
+//! db.execute("INSERT INTO movies VALUES (1, 'Sicario'), (2, 'Stalker')")?;
+//!
+//! let count = db.with_txn(|session| {
+//!     session.execute("INSERT INTO movies VALUES (3, 'Her')")?;
+//!     match session.execute("SELECT COUNT(*) FROM movies")? {
+//!         ResultSet::Query { mut rows, .. } => Ok(rows.next().transpose()?),
+//!         _ => unreachable!(),
+//!     }
+//! })?;
+//! assert_eq!(count, Some(vec![toydb::sql::types::Value::Integer(3)]));
+//! # Ok::<(), toydb::error::Error>(())
+//! ```
+
+use crate::clock::HybridClock;
+use crate::error::{Error, Result};
+use crate::sql::engine::{Engine as _, Session, KV};
+use crate::sql::execution::ResultSet;
+use crate::sql::schema::{Catalog as _, Table};
+use crate::storage::engine::{self, BitCask, Memory};
+
+use rand::Rng as _;
+use std::path::Path;
+use std::time::Duration;
+
+/// Number of serialization retries in with_txn()
+const WITH_TXN_RETRIES: u8 = 8;
+
+/// The garbage ratio at which the BitCask file is compacted when opened.
+const COMPACT_THRESHOLD: f64 = 0.2;
+
+/// An embedded database. It can be cloned and shared between threads, with
+/// each thread executing statements in its own session.
+pub struct Database<E: engine::Engine + 'static = BitCask> {
+    engine: KV<E>,
+}
+
+// Implement Clone manually, since E isn't Clone.
+impl<E: engine::Engine + 'static> Clone for Database<E> {
+    fn clone(&self) -> Self {
+        Self { engine: self.engine.clone() }
+    }
+}
+
+impl Database<BitCask> {
+    /// Opens or creates a database in the given file, compacting it if it
+    /// contains a lot of garbage. Writes are not fsynced, so committed
+    /// transactions survive process crashes but not necessarily system crashes.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(Self::new(BitCask::new_compact(path.to_path_buf(), COMPACT_THRESHOLD)?))
+    }
+}
+
+impl Database<Memory> {
+    /// Creates a database that keeps all data in memory, e.g. for tests.
+    pub fn memory() -> Self {
+        Self::new(Memory::new())
+    }
+}
+
+impl<E: engine::Engine + 'static> Database<E> {
+    /// Creates a database using the given storage engine. Commits are
+    /// timestamped with a hybrid logical clock using the system clock.
+    pub fn new(engine: E) -> Self {
+        Self { engine: KV::new(engine).with_clock(HybridClock::system()) }
+    }
+
+    /// Starts a new session, for executing statements in explicit transactions.
+    pub fn session(&self) -> Result<Session<KV<E>>> {
+        self.engine.session()
+    }
+
+    /// Executes a statement in a new session. Transaction control statements
+    /// such as BEGIN are pointless here, use session() or with_txn() instead.
+    pub fn execute(&self, statement: &str) -> Result<ResultSet> {
+        self.session()?.execute(statement)
+    }
+
+    /// Runs a closure in a transaction, committing it if the closure succeeds
+    /// and rolling it back otherwise. Serialization failures are automatically
+    /// retried with exponential backoff.
+    pub fn with_txn<F, R>(&self, mut f: F) -> Result<R>
+    where
+        F: FnMut(&mut Session<KV<E>>) -> Result<R>,
+    {
+        for i in 0..WITH_TXN_RETRIES {
+            if i > 0 {
+                std::thread::sleep(Duration::from_millis(
+                    2_u64.pow(i as u32 - 1) * rand::thread_rng().gen_range(25..=75),
+                ));
+            }
+            let mut session = self.session()?;
+            let result = session.execute("BEGIN").and_then(|_| {
+                let result = f(&mut session)?;
+                session.execute("COMMIT")?;
+                Ok(result)
+            });
+            if result.is_err() {
+                if session.transaction().is_some() {
+                    session.execute("ROLLBACK")?;
+                }
+                if let Err(Error::Serialization) = result {
+                    continue;
+                }
+            }
+            return result;
+        }
+        Err(Error::Serialization)
+    }
+
+    /// Fetches a table schema.
+    pub fn get_table(&self, table: &str) -> Result<Table> {
+        self.session()?.read_with_txn(|txn| txn.must_read_table(table))
+    }
+
+    /// Lists the names of all tables.
+    pub fn list_tables(&self) -> Result<Vec<String>> {
+        self.session()?.read_with_txn(|txn| Ok(txn.scan_tables()?.map(|t| t.name).collect()))
+    }
+}
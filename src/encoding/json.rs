@@ -0,0 +1,57 @@
+//! A minimal JSON encoding of SQL values, for human-readable output and
+//! external integrations. Only encoding is supported.
+
+use crate::sql::types::Value;
+
+/// Quotes and escapes a JSON string.
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Formats a value as JSON. JSON has no representation of NaN or infinity, so these are null.
+pub fn value(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) if f.is_finite() => f.to_string(),
+        Value::Float(_) => "null".to_string(),
+        Value::String(s) => quote(s),
+    }
+}
+
+/// Formats column names and values as a JSON object.
+pub fn object<'a>(pairs: impl IntoIterator<Item = (&'a str, &'a Value)>) -> String {
+    let fields: Vec<_> =
+        pairs.into_iter().map(|(k, v)| format!("{}:{}", quote(k), value(v))).collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode() {
+        assert_eq!(quote("a\"b\\c\nd\u{1}"), r#""a\"b\\c\nd\u0001""#);
+        assert_eq!(value(&Value::Float(f64::NAN)), "null");
+        assert_eq!(
+            object([("id", &Value::Integer(1)), ("name", &Value::String("x".into()))]),
+            r#"{"id":1,"name":"x"}"#
+        );
+    }
+}
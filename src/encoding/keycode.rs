@@ -1,6 +1,8 @@
 //! KeyCode is a lexicographical order-preserving binary encoding for use with
-//! keys. It is designed for simplicity, not efficiency (i.e. it does not use
-//! varints or other compression methods).
+//! keys: for any two values a and b of the same type, a < b if and only if
+//! serialize(a) < serialize(b) when compared as byte strings. It is designed
+//! for simplicity, not efficiency (i.e. it does not use varints or other
+//! compression methods).
 //!
 //! Ordering is important because it allows limited scans across specific parts
 //! of the keyspace, e.g. scanning an individual table or using an index range
@@ -10,21 +12,32 @@
 //! The encoding is not self-describing: the caller must provide a concrete type
 //! to decode into, and the binary key must conform to its structure.
 //!
-//! KeyCode supports a subset of primitive data types, encoded as follows:
+//! KeyCode supports the following primitive data types, encoded as follows:
 //!
-//! bool:    0x00 for false, 0x01 for true.
-//! u64:     Big-endian binary representation.
-//! i64:     Big-endian binary representation, with sign bit flipped.
-//! f64:     Big-endian binary representation, with sign bit flipped, and rest if negative.
-//! Vec<u8>: 0x00 is escaped as 0x00ff, terminated with 0x0000.
-//! String:  Like Vec<u8>.
+//! bool:      0x00 for false, 0x01 for true.
+//! u16-u128:  Big-endian binary representation.
+//! i8-i128:   Big-endian binary representation, with sign bit flipped.
+//! f32, f64:  Big-endian binary representation, with sign bit flipped, and rest
+//!            if negative. NaNs are encoded as the canonical positive NaN.
+//! char:      Like u32, i.e. ordered by code point.
+//! Vec<u8>:   0x00 is escaped as 0x00ff, terminated with 0x0000.
+//! String:    Like Vec<u8>.
+//! Option:    0x00 for None, or 0x01 followed by the value for Some.
+//!
+//! u8 is not supported, since byte vectors would otherwise be encoded as a
+//! sequence of unescaped u8 values (see below).
 //!
 //! Additionally, several container types are supported:
 //!
-//! Tuple:  Concatenation of elements, with no surrounding structure.
-//! Array:  Like tuple.
-//! Vec:    Like tuple.
-//! Enum:   The variant's enum index as a single u8 byte.
+//! Tuple:   Concatenation of elements, with no surrounding structure.
+//! Array:   Like tuple.
+//! Vec:     Like tuple.
+//! Struct:  Like tuple, in field order. Unit and newtype structs are encoded as
+//!          nothing and the inner value respectively.
+//! Enum:    The variant's enum index as a single u8 byte, then contents.
+//!
+//! Since sequences have no length prefix or terminator, a Vec can only be
+//! decoded as the last element of a key. Maps are not supported.
 //!
 //! SQL Value enums are encoded according to the above scheme, i.e. a single
 //! byte identifying the enum variant by index, then the primitive value.
@@ -81,10 +94,10 @@ impl<'a> serde::Serializer for &'a mut Serializer {
     type SerializeSeq = Self;
     type SerializeTuple = Self;
     type SerializeTupleVariant = Self;
-    type SerializeTupleStruct = ser::Impossible<(), Error>;
+    type SerializeTupleStruct = Self;
     type SerializeMap = ser::Impossible<(), Error>;
-    type SerializeStruct = ser::Impossible<(), Error>;
-    type SerializeStructVariant = ser::Impossible<(), Error>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
 
     /// bool simply uses 1 for true and 0 for false.
     fn serialize_bool(self, v: bool) -> Result<()> {
@@ -92,16 +105,19 @@ impl<'a> serde::Serializer for &'a mut Serializer {
         Ok(())
     }
 
-    fn serialize_i8(self, _: i8) -> Result<()> {
-        unimplemented!()
+    /// i8 is encoded like i64.
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.serialize_signed(v.to_be_bytes())
     }
 
-    fn serialize_i16(self, _: i16) -> Result<()> {
-        unimplemented!()
+    /// i16 is encoded like i64.
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.serialize_signed(v.to_be_bytes())
     }
 
-    fn serialize_i32(self, _: i32) -> Result<()> {
-        unimplemented!()
+    /// i32 is encoded like i64.
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.serialize_signed(v.to_be_bytes())
     }
 
     /// i64 uses the big-endian two's completement encoding, but flips the
@@ -112,22 +128,29 @@ impl<'a> serde::Serializer for &'a mut Serializer {
     /// largest negative integer, is encoded as 01111111...11111111, ordered
     /// after all other negative integers but before positive integers.
     fn serialize_i64(self, v: i64) -> Result<()> {
-        let mut bytes = v.to_be_bytes();
-        bytes[0] ^= 1 << 7; // flip sign bit
-        self.output.extend(bytes);
-        Ok(())
+        self.serialize_signed(v.to_be_bytes())
+    }
+
+    /// i128 is encoded like i64.
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        self.serialize_signed(v.to_be_bytes())
     }
 
+    /// u8 is not supported, see module documentation.
     fn serialize_u8(self, _: u8) -> Result<()> {
-        unimplemented!()
+        Err(unsupported("u8, use serde_bytes for byte vectors"))
     }
 
-    fn serialize_u16(self, _: u16) -> Result<()> {
-        unimplemented!()
+    /// u16 is encoded like u64.
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.output.extend(v.to_be_bytes());
+        Ok(())
     }
 
-    fn serialize_u32(self, _: u32) -> Result<()> {
-        unimplemented!()
+    /// u32 is encoded like u64.
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.output.extend(v.to_be_bytes());
+        Ok(())
     }
 
     /// u64 simply uses the big-endian encoding.
@@ -136,27 +159,31 @@ impl<'a> serde::Serializer for &'a mut Serializer {
         Ok(())
     }
 
-    fn serialize_f32(self, _: f32) -> Result<()> {
-        unimplemented!()
+    /// u128 is encoded like u64.
+    fn serialize_u128(self, v: u128) -> Result<()> {
+        self.output.extend(v.to_be_bytes());
+        Ok(())
+    }
+
+    /// f32 is encoded like f64.
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        let v = if v.is_nan() { f32::NAN } else { v };
+        self.serialize_float(v.to_be_bytes())
     }
 
     /// f64 is encoded in big-endian form, but it flips the sign bit to order
     /// positive numbers after negative numbers, and also flips all other bits
-    /// for negative numbers to order them from smallest to greatest. NaN is
-    /// ordered at the end.
+    /// for negative numbers to order them from smallest to greatest. All NaN
+    /// values (regardless of sign and payload) are encoded as the canonical
+    /// positive NaN, ordered at the end.
     fn serialize_f64(self, v: f64) -> Result<()> {
-        let mut bytes = v.to_be_bytes();
-        if bytes[0] & 1 << 7 == 0 {
-            bytes[0] ^= 1 << 7; // positive, flip sign bit
-        } else {
-            bytes.iter_mut().for_each(|b| *b = !*b); // negative, flip all bits
-        }
-        self.output.extend(bytes);
-        Ok(())
+        let v = if v.is_nan() { f64::NAN } else { v };
+        self.serialize_float(v.to_be_bytes())
     }
 
-    fn serialize_char(self, _: char) -> Result<()> {
-        unimplemented!()
+    /// char is encoded as its u32 code point.
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.serialize_u32(v.into())
     }
 
     // Strings are encoded like bytes.
@@ -178,20 +205,26 @@ impl<'a> serde::Serializer for &'a mut Serializer {
         Ok(())
     }
 
+    // None is encoded as 0x00, ordered before Some.
     fn serialize_none(self) -> Result<()> {
-        unimplemented!()
+        self.output.push(0x00);
+        Ok(())
     }
 
-    fn serialize_some<T: serde::Serialize + ?Sized>(self, _: &T) -> Result<()> {
-        unimplemented!()
+    // Some is encoded as 0x01 followed by the value.
+    fn serialize_some<T: serde::Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        self.output.push(0x01);
+        value.serialize(self)
     }
 
+    // Units are encoded as nothing.
     fn serialize_unit(self) -> Result<()> {
-        unimplemented!()
+        Ok(())
     }
 
+    // Unit structs are encoded as nothing.
     fn serialize_unit_struct(self, _: &'static str) -> Result<()> {
-        unimplemented!()
+        Ok(())
     }
 
     // Enum variants are serialized using their index, as a single byte.
@@ -200,12 +233,13 @@ impl<'a> serde::Serializer for &'a mut Serializer {
         Ok(())
     }
 
+    // Newtype structs are encoded as the inner value.
     fn serialize_newtype_struct<T: serde::Serialize + ?Sized>(
         self,
         _: &'static str,
-        _: &T,
+        value: &T,
     ) -> Result<()> {
-        unimplemented!()
+        value.serialize(self)
     }
 
     // Newtype variants are serialized using the variant index and inner type.
@@ -230,12 +264,13 @@ impl<'a> serde::Serializer for &'a mut Serializer {
         Ok(self)
     }
 
+    // Tuple structs are serialized as the concatenation of the serialized elements.
     fn serialize_tuple_struct(
         self,
         _: &'static str,
         _: usize,
     ) -> Result<Self::SerializeTupleStruct> {
-        unimplemented!()
+        Ok(self)
     }
 
     // Tuple variants are serialized using the variant index and the
@@ -252,24 +287,52 @@ impl<'a> serde::Serializer for &'a mut Serializer {
     }
 
     fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap> {
-        unimplemented!()
+        Err(unsupported("maps"))
     }
 
+    // Structs are serialized as the concatenation of the serialized fields.
     fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeStruct> {
-        unimplemented!()
+        Ok(self)
     }
 
+    // Struct variants are serialized like tuple variants.
     fn serialize_struct_variant(
         self,
-        _: &'static str,
-        _: u32,
-        _: &'static str,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
         _: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        unimplemented!()
+        self.serialize_unit_variant(name, index, variant)?;
+        Ok(self)
+    }
+}
+
+impl Serializer {
+    /// Serializes a big-endian signed integer, flipping the sign bit.
+    fn serialize_signed<const N: usize>(&mut self, mut bytes: [u8; N]) -> Result<()> {
+        bytes[0] ^= 1 << 7; // flip sign bit
+        self.output.extend(bytes);
+        Ok(())
+    }
+
+    /// Serializes a big-endian float, see serialize_f64().
+    fn serialize_float<const N: usize>(&mut self, mut bytes: [u8; N]) -> Result<()> {
+        if bytes[0] & 1 << 7 == 0 {
+            bytes[0] ^= 1 << 7; // positive, flip sign bit
+        } else {
+            bytes.iter_mut().for_each(|b| *b = !*b); // negative, flip all bits
+        }
+        self.output.extend(bytes);
+        Ok(())
     }
 }
 
+/// Returns an error for an unsupported type.
+fn unsupported(what: &str) -> Error {
+    Error::Internal(format!("KeyCode does not support {}", what))
+}
+
 // Sequences simply concatenate the serialized elements, with no external structure.
 impl<'a> ser::SerializeSeq for &'a mut Serializer {
     type Ok = ();
@@ -312,6 +375,56 @@ impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
     }
 }
 
+// Tuple structs, like tuples, simply concatenate the serialized elements.
+impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: serde::Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Structs simply concatenate the serialized fields, in field order.
+impl<'a> ser::SerializeStruct for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: serde::Serialize + ?Sized>(
+        &mut self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Struct variants, like structs, simply concatenate the serialized fields.
+impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: serde::Serialize + ?Sized>(
+        &mut self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
 // Deserializes keys from byte slices into a given type. The format is not
 // self-describing, so the caller must provide a concrete type to deserialize
 // into.
@@ -339,6 +452,29 @@ impl<'de> Deserializer<'de> {
         Ok(bytes)
     }
 
+    // Chops off and returns the next N bytes as an array.
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take_bytes(N)?.try_into()?)
+    }
+
+    // Chops off the next big-endian signed integer, flipping the sign bit.
+    fn take_signed<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut bytes = self.take_array::<N>()?;
+        bytes[0] ^= 1 << 7; // flip sign bit
+        Ok(bytes)
+    }
+
+    // Chops off the next big-endian float, see Serializer::serialize_f64().
+    fn take_float<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut bytes = self.take_array::<N>()?;
+        if bytes[0] >> 7 & 1 == 1 {
+            bytes[0] ^= 1 << 7; // positive, flip sign bit
+        } else {
+            bytes.iter_mut().for_each(|b| *b = !*b); // negative, flip all bits
+        }
+        Ok(bytes)
+    }
+
     // Decodes and chops off the next encoded byte slice.
     fn decode_next_bytes(&mut self) -> Result<Vec<u8>> {
         // We can't easily share state between Iterator.scan() and
@@ -378,56 +514,59 @@ impl<'de, 'a> serde::Deserializer<'de> for &'a mut Deserializer<'de> {
         })
     }
 
-    fn deserialize_i8<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+    fn deserialize_i8<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i8(i8::from_be_bytes(self.take_signed()?))
     }
 
-    fn deserialize_i16<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+    fn deserialize_i16<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i16(i16::from_be_bytes(self.take_signed()?))
     }
 
-    fn deserialize_i32<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+    fn deserialize_i32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i32(i32::from_be_bytes(self.take_signed()?))
     }
 
     fn deserialize_i64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        let mut bytes = self.take_bytes(8)?.to_vec();
-        bytes[0] ^= 1 << 7; // flip sign bit
-        visitor.visit_i64(i64::from_be_bytes(bytes.as_slice().try_into()?))
+        visitor.visit_i64(i64::from_be_bytes(self.take_signed()?))
+    }
+
+    fn deserialize_i128<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i128(i128::from_be_bytes(self.take_signed()?))
     }
 
     fn deserialize_u8<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+        Err(unsupported("u8, use serde_bytes for byte vectors"))
     }
 
-    fn deserialize_u16<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+    fn deserialize_u16<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u16(u16::from_be_bytes(self.take_array()?))
     }
 
-    fn deserialize_u32<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+    fn deserialize_u32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u32(u32::from_be_bytes(self.take_array()?))
     }
 
     fn deserialize_u64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        visitor.visit_u64(u64::from_be_bytes(self.take_bytes(8)?.try_into()?))
+        visitor.visit_u64(u64::from_be_bytes(self.take_array()?))
+    }
+
+    fn deserialize_u128<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u128(u128::from_be_bytes(self.take_array()?))
     }
 
-    fn deserialize_f32<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+    fn deserialize_f32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f32(f32::from_be_bytes(self.take_float()?))
     }
 
     fn deserialize_f64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        let mut bytes = self.take_bytes(8)?.to_vec();
-        if bytes[0] >> 7 & 1 == 1 {
-            bytes[0] ^= 1 << 7; // positive, flip sign bit
-        } else {
-            bytes.iter_mut().for_each(|b| *b = !*b); // negative, flip all bits
-        }
-        visitor.visit_f64(f64::from_be_bytes(bytes.as_slice().try_into()?))
+        visitor.visit_f64(f64::from_be_bytes(self.take_float()?))
     }
 
-    fn deserialize_char<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+    fn deserialize_char<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let code = u32::from_be_bytes(self.take_array()?);
+        let c = char::from_u32(code)
+            .ok_or_else(|| Error::Internal(format!("Invalid char code point {:x}", code)))?;
+        visitor.visit_char(c)
     }
 
     fn deserialize_str<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
@@ -450,28 +589,32 @@ impl<'de, 'a> serde::Deserializer<'de> for &'a mut Deserializer<'de> {
         visitor.visit_byte_buf(bytes)
     }
 
-    fn deserialize_option<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.take_bytes(1)?[0] {
+            0x00 => visitor.visit_none(),
+            0x01 => visitor.visit_some(self),
+            b => Err(Error::Internal(format!("Invalid option value {:?}", b))),
+        }
     }
 
-    fn deserialize_unit<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+    fn deserialize_unit<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
     }
 
     fn deserialize_unit_struct<V: de::Visitor<'de>>(
         self,
         _: &'static str,
-        _: V,
+        visitor: V,
     ) -> Result<V::Value> {
-        unimplemented!()
+        visitor.visit_unit()
     }
 
     fn deserialize_newtype_struct<V: de::Visitor<'de>>(
         self,
         _: &'static str,
-        _: V,
+        visitor: V,
     ) -> Result<V::Value> {
-        unimplemented!()
+        visitor.visit_newtype_struct(self)
     }
 
     fn deserialize_seq<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
@@ -486,22 +629,22 @@ impl<'de, 'a> serde::Deserializer<'de> for &'a mut Deserializer<'de> {
         self,
         _: &'static str,
         _: usize,
-        _: V,
+        visitor: V,
     ) -> Result<V::Value> {
-        unimplemented!()
+        visitor.visit_seq(self)
     }
 
     fn deserialize_map<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+        Err(unsupported("maps"))
     }
 
     fn deserialize_struct<V: de::Visitor<'de>>(
         self,
         _: &'static str,
         _: &'static [&'static str],
-        _: V,
+        visitor: V,
     ) -> Result<V::Value> {
-        unimplemented!()
+        visitor.visit_seq(self)
     }
 
     fn deserialize_enum<V: de::Visitor<'de>>(
@@ -514,11 +657,11 @@ impl<'de, 'a> serde::Deserializer<'de> for &'a mut Deserializer<'de> {
     }
 
     fn deserialize_identifier<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+        Err(unsupported("identifiers"))
     }
 
     fn deserialize_ignored_any<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+        Err(Error::Internal("Must provide type, KeyCode is not self-describing".to_string()))
     }
 }
 
@@ -571,9 +714,9 @@ impl<'de> de::VariantAccess<'de> for &mut Deserializer<'de> {
     fn struct_variant<V: de::Visitor<'de>>(
         self,
         _: &'static [&'static str],
-        _: V,
+        visitor: V,
     ) -> Result<V::Value> {
-        unimplemented!()
+        visitor.visit_seq(self)
     }
 }
 
@@ -583,6 +726,7 @@ mod tests {
     use crate::sql::types::Value;
     use hex;
     use paste::paste;
+    use rand::{Rng as _, SeedableRng as _};
     use serde::{Deserialize, Serialize};
     use serde_bytes::ByteBuf;
     use std::borrow::Cow;
@@ -600,8 +744,28 @@ mod tests {
             bool,
             #[serde(borrow)] Cow<'a, str>,
         ),
+        Struct {
+            a: bool,
+            b: u64,
+        },
     }
 
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    struct Struct {
+        id: u64,
+        name: String,
+        deleted: Option<bool>,
+    }
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    struct NewType(i32);
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    struct TupleStruct(bool, char);
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    struct UnitStruct;
+
     /// Assert that serializing a value yields the expected byte sequence (as a
     /// hex-encoded string), and that deserializing it yields the original value.
     macro_rules! test_serialize_deserialize {
@@ -670,6 +834,28 @@ mod tests {
         f64_inf: f64::INFINITY => "fff0000000000000",
         // We don't test NAN here, since NAN != NAN.
 
+        f32_min: f32::MIN => "00800000",
+        f32_neg_one: -1f32 => "407fffff",
+        f32_neg_zero: -0f32 => "7fffffff",
+        f32_zero: 0f32 => "80000000",
+        f32_one: 1f32 => "bf800000",
+        f32_max: f32::MAX => "ff7fffff",
+
+        i8_min: i8::MIN => "00",
+        i8_neg_1: -1i8 => "7f",
+        i8_0: 0i8 => "80",
+        i8_max: i8::MAX => "ff",
+
+        i16_min: i16::MIN => "0000",
+        i16_neg_1: -1i16 => "7fff",
+        i16_0: 0i16 => "8000",
+        i16_max: i16::MAX => "ffff",
+
+        i32_min: i32::MIN => "00000000",
+        i32_neg_1: -1i32 => "7fffffff",
+        i32_0: 0i32 => "80000000",
+        i32_max: i32::MAX => "ffffffff",
+
         i64_min: i64::MIN => "0000000000000000",
         i64_neg_65535: -65535i64 => "7fffffffffff0001",
         i64_neg_1: -1i64 => "7fffffffffffffff",
@@ -678,11 +864,28 @@ mod tests {
         i64_65535: 65535i64 => "800000000000ffff",
         i64_max: i64::MAX => "ffffffffffffffff",
 
+        i128_min: i128::MIN => "00000000000000000000000000000000",
+        i128_neg_1: -1i128 => "7fffffffffffffffffffffffffffffff",
+        i128_0: 0i128 => "80000000000000000000000000000000",
+        i128_max: i128::MAX => "ffffffffffffffffffffffffffffffff",
+
+        u16_min: u16::MIN => "0000",
+        u16_max: u16::MAX => "ffff",
+
+        u32_min: u32::MIN => "00000000",
+        u32_max: u32::MAX => "ffffffff",
+
         u64_min: u64::MIN => "0000000000000000",
         u64_1: 1_u64 => "0000000000000001",
         u64_65535: 65535_u64 => "000000000000ffff",
         u64_max: u64::MAX => "ffffffffffffffff",
 
+        u128_min: u128::MIN => "00000000000000000000000000000000",
+        u128_max: u128::MAX => "ffffffffffffffffffffffffffffffff",
+
+        char: 'a' => "00000061",
+        char_utf8: '👋' => "0001f44b",
+
         bytes: ByteBuf::from(vec![0x01, 0xff]) => "01ff0000",
         bytes_empty: ByteBuf::new() => "0000",
         bytes_escape: ByteBuf::from(vec![0x00, 0x01, 0x02]) => "00ff01020000",
@@ -692,6 +895,16 @@ mod tests {
         string_escape: "foo\x00bar".to_string() => "666f6f00ff6261720000",
         string_utf8: "👋".to_string() => "f09f918b0000",
 
+        none: Option::<bool>::None => "00",
+        some: Some(true) => "0101",
+        some_none: Some(Option::<bool>::None) => "0100",
+
+        unit: () => "",
+        unit_struct: UnitStruct => "",
+        newtype_struct: NewType(-1) => "7fffffff",
+        tuple_struct: TupleStruct(true, 'a') => "0100000061",
+        struct_: Struct { id: 1, name: "foo".into(), deleted: None } => "0000000000000001666f6f000000",
+
         tuple: (true, u64::MAX, ByteBuf::from(vec![0x00, 0x01])) => "01ffffffffffffffff00ff010000",
         array_bool: [false, true, false] => "000100",
         vec_bool: vec![false, true, false] => "000100",
@@ -702,6 +915,7 @@ mod tests {
         enum_tuple: Key::Tuple(false, vec![0x00, 0x01], u64::MAX) => "020000ff010000ffffffffffffffff",
         enum_cow: Key::Cow(vec![0x00, 0x01].into(), false, String::from("foo").into()) => "0300ff01000000666f6f0000",
         enum_cow_borrow: Key::Cow([0x00, 0x01].as_slice().into(), false, "foo".into()) => "0300ff01000000666f6f0000",
+        enum_struct: Key::Struct { a: true, b: 1 } => "04010000000000000001",
 
         value_null: Value::Null => "00",
         value_bool: Value::Boolean(true) => "0101",
@@ -711,37 +925,203 @@ mod tests {
     }
 
     test_serialize_error! {
-        char: 'a',
-        f32: 0f32,
-        i8: 0i8,
-        i16: 0i16,
-        i32: 0i32,
-        i128: 0i128,
         u8: 0u8,
-        u16: 0u16,
-        u32: 0u32,
-        u128: 0u128,
-        some: Some(true),
-        none: Option::<bool>::None,
         vec_u8: vec![0u8],
+        map: std::collections::HashMap::from([(1u64, true)]),
     }
 
     test_deserialize_error! {
         bool_empty: "" as bool,
         bool_2: "02" as bool,
-        char: "61" as char,
-        f32: "00000000" as f32,
-        i8: "00" as i8,
-        i16: "0000" as i16,
-        i32: "00000000" as i32,
-        i128: "00000000000000000000000000000000" as i128,
-        u16: "0000" as u16,
-        u32: "00000000" as u32,
+        char_partial: "61" as char,
+        char_surrogate: "0000d800" as char,
+        char_invalid: "00110000" as char,
+        f32_partial: "0000" as f32,
+        i8_empty: "" as i8,
+        i16_partial: "00" as i16,
+        i32_partial: "0000" as i32,
+        i128_partial: "0000000000000000" as i128,
+        u8: "00" as u8,
+        u16_partial: "00" as u16,
+        u32_partial: "0000" as u32,
         u64_partial: "0000" as u64,
-        u128: "00000000000000000000000000000000" as u128,
-        option: "00" as Option::<bool>,
+        u128_partial: "0000000000000000" as u128,
+        option_empty: "" as Option::<bool>,
+        option_2: "02" as Option::<bool>,
+        option_some_empty: "01" as Option::<bool>,
+        map: "0000000000000001" as std::collections::HashMap<u64, bool>,
         string_utf8_invalid: "c0" as String,
         tuple_partial: "0001" as (bool, bool, bool),
         vec_u8: "0000" as Vec<u8>,
     }
+
+    #[test]
+    // All NaNs are encoded as the canonical positive NaN, ordered after infinity.
+    fn f64_nan() -> Result<()> {
+        let payload = f64::from_bits(f64::NAN.to_bits() | 1);
+        for nan in [f64::NAN, -f64::NAN, payload, -payload] {
+            assert_eq!(hex::encode(serialize(&nan)?), "fff8000000000000");
+            assert!(deserialize::<f64>(&serialize(&nan)?)?.is_nan());
+        }
+        assert!(serialize(&f64::NAN)? > serialize(&f64::INFINITY)?);
+        Ok(())
+    }
+
+    /// Generates random values for the order preservation tests, with a bias
+    /// towards edge cases.
+    trait Random: Sized {
+        fn random(rng: &mut rand::rngs::StdRng) -> Self;
+    }
+
+    impl Random for bool {
+        fn random(rng: &mut rand::rngs::StdRng) -> Self {
+            rng.gen()
+        }
+    }
+
+    impl Random for i64 {
+        fn random(rng: &mut rand::rngs::StdRng) -> Self {
+            match rng.gen_range(0..4) {
+                0 => [i64::MIN, -1, 0, 1, i64::MAX][rng.gen_range(0..5)],
+                1 => rng.gen_range(-1000..1000),
+                _ => rng.gen(),
+            }
+        }
+    }
+
+    impl Random for u64 {
+        fn random(rng: &mut rand::rngs::StdRng) -> Self {
+            match rng.gen_range(0..4) {
+                0 => [u64::MIN, 1, u64::MAX][rng.gen_range(0..3)],
+                1 => rng.gen_range(0..1000),
+                _ => rng.gen(),
+            }
+        }
+    }
+
+    impl Random for f64 {
+        fn random(rng: &mut rand::rngs::StdRng) -> Self {
+            match rng.gen_range(0..4) {
+                0 => [f64::NEG_INFINITY, f64::MIN, -0.0, 0.0, f64::MAX, f64::INFINITY]
+                    [rng.gen_range(0..6)],
+                1 => rng.gen_range(-1000.0..1000.0),
+                // Random bits yield values of all magnitudes, but skip NaNs.
+                _ => Some(f64::from_bits(rng.gen())).filter(|f| !f.is_nan()).unwrap_or(0.0),
+            }
+        }
+    }
+
+    impl Random for String {
+        fn random(rng: &mut rand::rngs::StdRng) -> Self {
+            // Use a small alphabet including 0x00, such that values often
+            // share prefixes and require escaping.
+            let len = rng.gen_range(0..6);
+            (0..len).map(|_| ['\x00', '\x01', 'a', 'b', 'é', '👋'][rng.gen_range(0..6)]).collect()
+        }
+    }
+
+    impl Random for ByteBuf {
+        fn random(rng: &mut rand::rngs::StdRng) -> Self {
+            let len = rng.gen_range(0..6);
+            ByteBuf::from(
+                (0..len)
+                    .map(|_| [0x00, 0x01, 0xfe, 0xff][rng.gen_range(0..4)])
+                    .collect::<Vec<u8>>(),
+            )
+        }
+    }
+
+    impl<T: Random> Random for Option<T> {
+        fn random(rng: &mut rand::rngs::StdRng) -> Self {
+            rng.gen_bool(0.8).then(|| T::random(rng))
+        }
+    }
+
+    impl<A: Random, B: Random> Random for (A, B) {
+        fn random(rng: &mut rand::rngs::StdRng) -> Self {
+            (A::random(rng), B::random(rng))
+        }
+    }
+
+    impl Random for Value {
+        fn random(rng: &mut rand::rngs::StdRng) -> Self {
+            match rng.gen_range(0..5) {
+                0 => Value::Null,
+                1 => Value::Boolean(bool::random(rng)),
+                2 => Value::Integer(i64::random(rng)),
+                3 => Value::Float(f64::random(rng)),
+                _ => Value::String(String::random(rng)),
+            }
+        }
+    }
+
+    /// Asserts that the encoding preserves the order of random values, i.e.
+    /// that a < b if and only if serialize(a) < serialize(b), and that all
+    /// values roundtrip. Takes a comparison function, since floats and SQL
+    /// values aren't Ord.
+    fn assert_order_preserving<T>(cmp: impl Fn(&T, &T) -> std::cmp::Ordering)
+    where
+        T: Random + Serialize + serde::de::DeserializeOwned + std::fmt::Debug,
+    {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(397);
+        for _ in 0..2000 {
+            let (a, b) = (T::random(&mut rng), T::random(&mut rng));
+            let (a_enc, b_enc) = (serialize(&a).unwrap(), serialize(&b).unwrap());
+            assert_eq!(cmp(&a, &b), a_enc.cmp(&b_enc), "{:?} vs {:?}", a, b);
+            for (value, encoded) in [(&a, &a_enc), (&b, &b_enc)] {
+                let decoded: T = deserialize(encoded).unwrap();
+                assert_eq!(cmp(value, &decoded), std::cmp::Ordering::Equal, "{:?}", value);
+            }
+        }
+    }
+
+    /// Generates order preservation tests for the given types.
+    macro_rules! test_order_preserving {
+        ( $( $name:ident: $type:ty, )* ) => {
+        paste! {
+        $(
+            #[test]
+            fn [< $name _order_preserving >]() {
+                assert_order_preserving::<$type>(|a, b| a.cmp(b));
+            }
+        )*
+        }
+        };
+    }
+
+    test_order_preserving! {
+        bool: bool,
+        i64: i64,
+        u64: u64,
+        string: String,
+        bytes: ByteBuf,
+        option: Option<i64>,
+        tuple: (String, i64),
+        tuple_nested: ((Option<bool>, ByteBuf), u64),
+    }
+
+    #[test]
+    // f64 isn't Ord, but excluding NaN the encoding matches total_cmp(), which
+    // also orders -0.0 before 0.0.
+    fn f64_order_preserving() {
+        assert_order_preserving::<f64>(|a, b| a.total_cmp(b));
+    }
+
+    #[test]
+    // SQL values are ordered by type first, then by value.
+    fn value_order_preserving() {
+        assert_order_preserving::<Value>(|a, b| {
+            let index = |v: &Value| match v {
+                Value::Null => 0,
+                Value::Boolean(_) => 1,
+                Value::Integer(_) => 2,
+                Value::Float(_) => 3,
+                Value::String(_) => 4,
+            };
+            match (a, b) {
+                (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+                _ => index(a).cmp(&index(b)).then_with(|| a.partial_cmp(b).unwrap()),
+            }
+        });
+    }
 }
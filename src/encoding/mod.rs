@@ -0,0 +1,4 @@
+//! Data encodings.
+
+pub mod json;
+pub mod keycode;
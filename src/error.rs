@@ -1,19 +1,56 @@
+use crate::raft::NodeID;
+
 use serde_derive::{Deserialize, Serialize};
 use std::fmt::{self, Display};
+use std::time::Duration;
 
 /// Result returning Error
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// toyDB errors. All except Internal are considered user-facing.
+/// toyDB errors. All except Internal are considered user-facing. Errors are
+/// sent to clients as is, so they can match on them.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Error {
     Abort,
     Config(String),
+    /// A violation of the named table constraint, e.g. movies_pkey.
+    Constraint {
+        name: String,
+        message: String,
+    },
     Internal(String),
+    /// The Raft node can't serve the request since it isn't the leader, with
+    /// the current leader if known. The request can be retried.
+    NotLeader(Option<NodeID>),
     Parse(String),
     ReadOnly,
     Serialization,
+    /// A SQL syntax error, at the given 1-based line and column.
+    Syntax {
+        message: String,
+        line: usize,
+        column: usize,
+    },
     Value(String),
+    /// The request did not complete before its deadline. Its outcome is
+    /// unknown, e.g. a write may still be applied.
+    Timeout,
+    /// The server is a follower, and the client should reconnect to the leader
+    /// at the given SQL address and retry.
+    Redirect {
+        leader: NodeID,
+        address: String,
+    },
+    /// The statement was cancelled by KILL QUERY or KILL SESSION. Like a
+    /// timeout, its outcome is unknown.
+    Cancelled,
+    /// The client exceeded a rate limit, and should retry the request after the
+    /// given time, like an HTTP 429 response. The request was not executed.
+    RateLimited(Duration),
+    /// The request waited too long in the server's query queue, which bounds
+    /// the number of concurrently executing requests. The request was not
+    /// executed, and can be retried later.
+    Overloaded,
 }
 
 impl std::error::Error for Error {}
@@ -24,9 +61,24 @@ impl Display for Error {
             Error::Config(s) | Error::Internal(s) | Error::Parse(s) | Error::Value(s) => {
                 write!(f, "{}", s)
             }
+            Error::Constraint { message, .. } => write!(f, "{}", message),
+            Error::Syntax { message, line, column } => {
+                write!(f, "{} at line {}, column {}", message, line, column)
+            }
             Error::Abort => write!(f, "Operation aborted"),
+            Error::NotLeader(Some(leader)) => write!(f, "Not leader, leader is node {}", leader),
+            Error::NotLeader(None) => write!(f, "No leader, retry later"),
             Error::Serialization => write!(f, "Serialization failure, retry transaction"),
             Error::ReadOnly => write!(f, "Read-only transaction"),
+            Error::Timeout => write!(f, "Request timed out"),
+            Error::Cancelled => write!(f, "Statement cancelled"),
+            Error::Overloaded => write!(f, "Server overloaded, query queue timed out"),
+            Error::RateLimited(wait) => {
+                write!(f, "Rate limit exceeded, retry in {}ms", wait.as_millis().max(1))
+            }
+            Error::Redirect { leader, address } => {
+                write!(f, "Not leader, leader is node {} at {}", leader, address)
+            }
         }
     }
 }
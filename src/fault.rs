@@ -0,0 +1,356 @@
+//! Fault injection for integration tests, enabled by the fault-injection
+//! feature. Faults are registered by name in a process-global registry, and
+//! since test clusters run in-process, they are scoped to individual nodes or
+//! storage engines. Faults apply until they're cleared.
+//!
+//! Nodes crash by returning an error from the crash point, which halts the
+//! node's event loop or state machine like any other fatal error. Tests can
+//! then restart the node on the same storage to exercise recovery.
+//!
+//! Test clusters can also replace TCP with a simulated in-process Network,
+//! whose links between nodes can be given latency, bandwidth caps, message
+//! loss and duplication, and (possibly asymmetric) partitions.
+
+use crate::error::{Error, Result};
+use crate::raft::{self, Address, Event, Message, NodeID};
+use crate::storage::engine::{self, ScanIterator, Status};
+
+use futures::future::{BoxFuture, FutureExt as _};
+use lazy_static::lazy_static;
+use rand::Rng as _;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// An injected fault.
+#[derive(Clone, Debug)]
+pub enum Fault {
+    /// Drops outbound Raft messages from and to the given nodes (None matches
+    /// any node) for which the filter returns true.
+    DropMessages { from: Option<NodeID>, to: Option<NodeID>, filter: fn(&Event) -> bool },
+    /// Like DropMessages, but delivers the messages after the given delay,
+    /// possibly reordering them.
+    DelayMessages {
+        from: Option<NodeID>,
+        to: Option<NodeID>,
+        filter: fn(&Event) -> bool,
+        delay: Duration,
+    },
+    /// Fails writes and flushes to the named storage engine with the given
+    /// probability between 0 and 1.
+    FailWrites { engine: String, probability: f64 },
+    /// Crashes the node when it reaches the named crash point. Only fires
+    /// once, and is then removed.
+    Crash { node: NodeID, point: String },
+}
+
+lazy_static! {
+    static ref FAULTS: Mutex<HashMap<String, Fault>> = Mutex::new(HashMap::new());
+}
+
+/// Injects a fault with the given name, replacing any existing fault with
+/// the same name.
+pub fn inject(name: &str, fault: Fault) {
+    FAULTS.lock().unwrap().insert(name.to_string(), fault);
+}
+
+/// Clears the fault with the given name, if any.
+pub fn clear(name: &str) {
+    FAULTS.lock().unwrap().remove(name);
+}
+
+/// Clears all faults.
+pub fn clear_all() {
+    FAULTS.lock().unwrap().clear();
+}
+
+/// Intercepts an outbound Raft message to a peer, returning true if it was
+/// dropped or delayed. Delayed messages are sent via the peer's channel later.
+pub(crate) fn intercept_message(
+    message: &Message,
+    to: NodeID,
+    tx: Option<&mpsc::Sender<Message>>,
+) -> bool {
+    let from = match message.from {
+        Address::Node(from) => from,
+        _ => return false,
+    };
+    let matches = |f: &Option<NodeID>, t: &Option<NodeID>, filter: &fn(&Event) -> bool| {
+        f.unwrap_or(from) == from && t.unwrap_or(to) == to && filter(&message.event)
+    };
+    let mut delay = None;
+    for fault in FAULTS.lock().unwrap().values() {
+        match fault {
+            Fault::DropMessages { from, to, filter } if matches(from, to, filter) => return true,
+            Fault::DelayMessages { from, to, filter, delay: d } if matches(from, to, filter) => {
+                delay = delay.max(Some(*d))
+            }
+            _ => {}
+        }
+    }
+    let (Some(delay), Some(tx)) = (delay, tx) else { return false };
+    let (tx, message) = (tx.clone(), message.clone());
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        tx.try_send(message).ok();
+    });
+    true
+}
+
+/// Returns an error if the node should crash at the given crash point.
+pub(crate) fn crash_point(node: NodeID, point: &str) -> Result<()> {
+    let mut faults = FAULTS.lock().unwrap();
+    let name = faults.iter().find_map(|(name, fault)| match fault {
+        Fault::Crash { node: n, point: p } if *n == node && p == point => Some(name.clone()),
+        _ => None,
+    });
+    match name {
+        Some(name) => {
+            faults.remove(&name);
+            Err(Error::Internal(format!("Injected crash of node {} at {}", node, point)))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Returns an error if a write to the named storage engine should fail.
+fn write_fault(engine: &str) -> Result<()> {
+    let probability = FAULTS
+        .lock()
+        .unwrap()
+        .values()
+        .filter_map(|fault| match fault {
+            Fault::FailWrites { engine: e, probability } if e == engine => Some(*probability),
+            _ => None,
+        })
+        .fold(0.0, f64::max);
+    if probability > 0.0 && rand::thread_rng().gen_bool(probability.min(1.0)) {
+        return Err(Error::Internal(format!("Injected write failure in {}", engine)));
+    }
+    Ok(())
+}
+
+/// A storage engine which wraps another engine, and fails writes as given by
+/// FailWrites faults for the engine's name.
+pub struct Engine<E: engine::Engine> {
+    /// The engine name, used to match faults.
+    name: String,
+    /// The wrapped engine.
+    inner: E,
+}
+
+impl<E: engine::Engine> Engine<E> {
+    pub fn new(name: &str, inner: E) -> Self {
+        Self { name: name.to_string(), inner }
+    }
+}
+
+impl<E: engine::Engine> std::fmt::Display for Engine<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "fault:{}", self.inner)
+    }
+}
+
+impl<E: engine::Engine> engine::Engine for Engine<E> {
+    type ScanIterator<'a>
+        = E::ScanIterator<'a>
+    where
+        E: 'a;
+    type Snapshot = E::Snapshot;
+
+    fn flush(&mut self) -> Result<()> {
+        write_fault(&self.name)?;
+        self.inner.flush()
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        write_fault(&self.name)?;
+        self.inner.delete(key)
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
+        self.inner.scan(range)
+    }
+
+    fn scan_dyn(
+        &mut self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Box<dyn ScanIterator + '_> {
+        Box::new(self.scan(range))
+    }
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        write_fault(&self.name)?;
+        self.inner.set(key, value)
+    }
+
+    fn snapshot(&mut self) -> Result<Self::Snapshot> {
+        self.inner.snapshot()
+    }
+
+    fn status(&mut self) -> Result<Status> {
+        self.inner.status()
+    }
+}
+
+/// The conditions of a directional network link between two nodes in a
+/// simulated Network. The default is a perfect link.
+#[derive(Clone, Debug, Default)]
+pub struct Link {
+    /// Drops all messages, e.g. to partition nodes. Since links are
+    /// directional, partitions can be asymmetric.
+    pub partitioned: bool,
+    /// The message latency, uniformly distributed in the given range.
+    pub latency: Range<Duration>,
+    /// The bandwidth in bytes per second, if capped. Messages are transmitted
+    /// one at a time, so large messages delay the messages behind them.
+    pub bandwidth: Option<u64>,
+    /// The probability of dropping a message, between 0 and 1.
+    pub loss: f64,
+    /// The probability of delivering a message twice, between 0 and 1.
+    pub duplicate: f64,
+}
+
+/// A simulated in-process network, which carries Raft messages between the
+/// nodes using its transports. Link conditions apply to messages sent after
+/// they're set. Messages to nodes that aren't running are dropped.
+#[derive(Clone, Default)]
+pub struct Network {
+    inner: Arc<Mutex<NetworkInner>>,
+}
+
+#[derive(Default)]
+struct NetworkInner {
+    /// Inbound message channels of running nodes.
+    nodes: HashMap<NodeID, mpsc::UnboundedSender<Message>>,
+    /// Link conditions, by (from, to). Missing links are perfect.
+    links: HashMap<(NodeID, NodeID), Link>,
+    /// When each bandwidth-capped link finishes transmitting its messages.
+    busy: HashMap<(NodeID, NodeID), Instant>,
+}
+
+impl Network {
+    /// Creates a new network with perfect links.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a transport for the given node.
+    pub fn transport(&self, id: NodeID) -> Box<dyn raft::Transport> {
+        Box::new(NetworkTransport { id, network: self.clone() })
+    }
+
+    /// Sets the conditions of the link from one node to another.
+    pub fn set_link(&self, from: NodeID, to: NodeID, link: Link) {
+        self.inner.lock().unwrap().links.insert((from, to), link);
+    }
+
+    /// Sets the conditions of the links between all of the given nodes.
+    pub fn set_links(&self, nodes: &[NodeID], link: Link) {
+        for from in nodes {
+            for to in nodes.iter().filter(|to| *to != from) {
+                self.set_link(*from, *to, link.clone());
+            }
+        }
+    }
+
+    /// Partitions messages from one node to another, in one direction only.
+    pub fn partition(&self, from: NodeID, to: NodeID) {
+        self.inner.lock().unwrap().links.entry((from, to)).or_default().partitioned = true;
+    }
+
+    /// Isolates a node, by partitioning it from all other nodes in both
+    /// directions.
+    pub fn isolate(&self, id: NodeID) {
+        let mut inner = self.inner.lock().unwrap();
+        let peers: Vec<NodeID> = inner.nodes.keys().copied().filter(|p| *p != id).collect();
+        for peer in peers {
+            inner.links.entry((id, peer)).or_default().partitioned = true;
+            inner.links.entry((peer, id)).or_default().partitioned = true;
+        }
+    }
+
+    /// Heals the network, resetting all links to perfect links.
+    pub fn heal(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.links.clear();
+        inner.busy.clear();
+    }
+
+    /// Sends a message from a node, applying the link conditions.
+    fn send(&self, from: NodeID, message: Message) {
+        let mut inner = self.inner.lock().unwrap();
+        let to = match message.to {
+            Address::Broadcast => inner.nodes.keys().copied().filter(|id| *id != from).collect(),
+            Address::Node(id) => vec![id],
+            Address::Client => return,
+        };
+        let mut rng = rand::thread_rng();
+        for to in to {
+            let Some(tx) = inner.nodes.get(&to).cloned() else { continue };
+            let link = inner.links.get(&(from, to)).cloned().unwrap_or_default();
+            if link.partitioned || link.loss > 0.0 && rng.gen_bool(link.loss.min(1.0)) {
+                continue;
+            }
+
+            // Messages are delivered after transmission (which waits for
+            // previous messages on the link) plus latency.
+            let now = Instant::now();
+            let mut deliver = now;
+            if let Some(bandwidth) = link.bandwidth.filter(|b| *b > 0) {
+                let size = bincode::serialized_size(&message).unwrap_or(0);
+                let start = inner.busy.get(&(from, to)).copied().unwrap_or(now).max(now);
+                deliver = start + Duration::from_secs_f64(size as f64 / bandwidth as f64);
+                inner.busy.insert((from, to), deliver);
+            }
+            if !link.latency.is_empty() {
+                deliver += rng.gen_range(link.latency.clone());
+            } else {
+                deliver += link.latency.start;
+            }
+            let copies =
+                if link.duplicate > 0.0 && rng.gen_bool(link.duplicate.min(1.0)) { 2 } else { 1 };
+
+            for _ in 0..copies {
+                let (tx, message) = (tx.clone(), message.clone());
+                if deliver <= now {
+                    tx.send(message).ok();
+                    continue;
+                }
+                tokio::spawn(async move {
+                    tokio::time::sleep_until(deliver.into()).await;
+                    tx.send(message).ok();
+                });
+            }
+        }
+    }
+}
+
+/// A transport for a node in a simulated Network.
+struct NetworkTransport {
+    id: NodeID,
+    network: Network,
+}
+
+impl raft::Transport for NetworkTransport {
+    fn serve(
+        self: Box<Self>,
+        mut out_rx: mpsc::UnboundedReceiver<Message>,
+        in_tx: mpsc::UnboundedSender<Message>,
+    ) -> BoxFuture<'static, Result<()>> {
+        async move {
+            self.network.inner.lock().unwrap().nodes.insert(self.id, in_tx);
+            while let Some(message) = out_rx.recv().await {
+                self.network.send(self.id, message);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
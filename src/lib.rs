@@ -2,12 +2,24 @@
 #![allow(clippy::new_without_default)]
 #![allow(clippy::unneeded_field_pattern)]
 
+pub mod audit;
+pub mod cdc;
 pub mod client;
+pub mod clock;
+pub mod database;
+pub mod encoding;
 pub mod error;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+pub mod protocol;
+pub mod proxy;
+pub mod queue;
 pub mod raft;
+pub mod ratelimit;
 pub mod server;
 pub mod sql;
 pub mod storage;
 
 pub use client::Client;
+pub use database::Database;
 pub use server::Server;
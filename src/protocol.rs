@@ -0,0 +1,212 @@
+//! Wire protocol versioning for client and Raft peer connections.
+//!
+//! Before any messages are sent, the connecting side sends a handshake with
+//! the range of protocol versions it supports, and the accepting side responds
+//! with its own range. Both then use the highest version they have in common,
+//! or fail if there is none. A handshake is 12 bytes: a 4-byte magic followed
+//! by the minimum and maximum supported version as big-endian u32s. Messages
+//! are then sent as length-prefixed frames, as before.
+//!
+//! Versions before 17 are not supported. Version 1 was the original protocol
+//! without a handshake, and versions 2 to 16 changed message encodings without
+//! version bumps (e.g. by inserting enum variants), so peers running them
+//! can't be served correctly. Connections without a handshake are rejected.
+//!
+//! Versions:
+//!
+//! 1. The original protocol, without a handshake.
+//! 2. Adds the handshake and key/value client requests.
+//! 3. Adds client request timeouts.
+//! 4. Adds stale reads with session tokens.
+//! 5. Ends query rows with the last primary key, for keyset pagination.
+//! 6. Adds redirects from followers to the leader.
+//! 7. Adds the Raft event history to status responses.
+//! 8. Adds multi-statement execution requests.
+//! 9. Adds hybrid logical clock timestamps to Raft peer messages.
+//! 10. Adds node liveness requests.
+//! 11. Adds command tags after statement results.
+//! 12. Adds Raft log reads and promotion for standby clusters.
+//! 13. Adds statement cancellation errors, for KILL.
+//! 14. Adds server metrics requests.
+//! 15. Adds rate limit errors.
+//! 16. Adds query queue timeout errors.
+//! 17. The oldest supported version. Moves Raft state checksum events after
+//!     the original Raft events.
+//!
+//! Query result rows are streamed as individual responses, in the order the
+//! server's executors produce them, and clients must pass them on in the same
+//! order. This is the ORDER BY order if given (with any WITH ORDINALITY column
+//! numbering the rows in that order), otherwise unspecified but deterministic
+//! for a given plan and data. Rows are never reordered or batched across
+//! responses, in any version.
+//!
+//! From version 17, encodings must stay compatible with MIN_VERSION: enum
+//! variants are only ever appended, and new messages, variants, and fields are
+//! only sent to peers whose negotiated version supports them, downgrading them
+//! otherwise. Raising MIN_VERSION drops support for older versions.
+//!
+//! Protocol versions only cover client and Raft peer messages. Raft log entries
+//! contain state machine commands, which aren't versioned, so all nodes in a
+//! cluster must run the same release.
+
+use crate::error::{Error, Result};
+
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+/// The current protocol version.
+pub const VERSION: u32 = 17;
+
+/// The oldest supported protocol version.
+pub const MIN_VERSION: u32 = 17;
+
+/// The handshake magic.
+const MAGIC: [u8; 4] = *b"toyd";
+
+/// Performs the connecting side of the handshake, returning the negotiated
+/// version.
+pub(crate) async fn connect(socket: &mut TcpStream) -> Result<u32> {
+    connect_max(socket, VERSION).await
+}
+
+/// Performs the connecting side of the handshake like connect(), but only
+/// offers versions up to the given maximum, e.g. to pass on a client's
+/// version to a server.
+pub(crate) async fn connect_max(socket: &mut TcpStream, max: u32) -> Result<u32> {
+    let max = max.min(VERSION);
+    socket.write_all(&encode(MIN_VERSION, max)).await?;
+    let mut response = [0; 12];
+    match socket.read_exact(&mut response).await {
+        Ok(_) => {}
+        // Versions without the handshake drop the connection.
+        Err(err)
+            if matches!(
+                err.kind(),
+                std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::ConnectionReset
+            ) =>
+        {
+            return Err(Error::Internal(
+                "Connection closed during protocol handshake, remote version unsupported".into(),
+            ))
+        }
+        Err(err) => return Err(err.into()),
+    }
+    if response[..4] != MAGIC {
+        return Err(Error::Internal("Invalid protocol handshake response".into()));
+    }
+    let (remote_min, remote_max) = decode(&response);
+    negotiate(remote_min, remote_max.min(max))
+}
+
+/// Performs the accepting side of the handshake, returning a framed stream
+/// and the negotiated version.
+pub(crate) async fn accept(
+    mut socket: TcpStream,
+) -> Result<(Framed<TcpStream, LengthDelimitedCodec>, u32)> {
+    let mut request = [0; 12];
+    socket.read_exact(&mut request[..4]).await?;
+    // Version 1 peers send a frame length instead of the handshake magic.
+    if request[..4] != MAGIC {
+        return Err(Error::Internal(
+            "Missing protocol handshake, remote version 1 unsupported".into(),
+        ));
+    }
+    socket.read_exact(&mut request[4..]).await?;
+    let (min, max) = decode(&request);
+    socket.write_all(&encode(MIN_VERSION, VERSION)).await?;
+    let version = negotiate(min, max)?;
+    Ok((Framed::new(socket, LengthDelimitedCodec::new()), version))
+}
+
+/// Negotiates a protocol version with a remote version range.
+fn negotiate(min: u32, max: u32) -> Result<u32> {
+    let version = max.min(VERSION);
+    if version < min.max(MIN_VERSION) {
+        return Err(Error::Internal(format!(
+            "No common protocol version, local supports {}-{} and remote supports {}-{}",
+            MIN_VERSION, VERSION, min, max
+        )));
+    }
+    Ok(version)
+}
+
+/// Encodes a handshake.
+fn encode(min: u32, max: u32) -> [u8; 12] {
+    let mut handshake = [0; 12];
+    handshake[..4].copy_from_slice(&MAGIC);
+    handshake[4..8].copy_from_slice(&min.to_be_bytes());
+    handshake[8..].copy_from_slice(&max.to_be_bytes());
+    handshake
+}
+
+/// Decodes a handshake into a version range, assuming a valid magic.
+fn decode(handshake: &[u8; 12]) -> (u32, u32) {
+    let min = u32::from_be_bytes(handshake[4..8].try_into().unwrap());
+    let max = u32::from_be_bytes(handshake[8..].try_into().unwrap());
+    (min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate() {
+        assert_eq!(super::negotiate(1, VERSION), Ok(VERSION));
+        assert_eq!(super::negotiate(MIN_VERSION, VERSION + 1), Ok(VERSION));
+        assert!(super::negotiate(VERSION + 1, VERSION + 2).is_err());
+        assert!(super::negotiate(1, MIN_VERSION - 1).is_err());
+    }
+
+    #[test]
+    fn encode_decode() {
+        let handshake = encode(3, 7);
+        assert_eq!(&handshake[..4], b"toyd");
+        assert_eq!(decode(&handshake), (3, 7));
+    }
+
+    #[tokio::test]
+    async fn handshake() -> Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let accept = tokio::spawn(async move { super::accept(listener.accept().await?.0).await });
+        let mut socket = TcpStream::connect(addr).await?;
+        assert_eq!(connect(&mut socket).await?, VERSION);
+        assert_eq!(accept.await??.1, VERSION);
+
+        // Versions below MIN_VERSION are rejected by both sides.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let accept = tokio::spawn(async move { super::accept(listener.accept().await?.0).await });
+        let mut socket = TcpStream::connect(addr).await?;
+        assert!(connect_max(&mut socket, MIN_VERSION - 1).await.is_err());
+        assert!(accept.await?.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handshake_legacy() -> Result<()> {
+        use futures::{SinkExt as _, StreamExt as _};
+
+        // A version 1 peer sends frames right away, and is rejected.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let accept = tokio::spawn(async move { super::accept(listener.accept().await?.0).await });
+        let mut framed = Framed::new(TcpStream::connect(addr).await?, LengthDelimitedCodec::new());
+        framed.send(vec![1, 2, 3].into()).await?;
+        assert!(accept.await?.is_err());
+
+        // A version 1 peer drops the connection on a handshake, which errors.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let accept = tokio::spawn(async move {
+            let mut framed = Framed::new(listener.accept().await?.0, LengthDelimitedCodec::new());
+            framed.next().await.transpose().map_err(Error::from)
+        });
+        let mut socket = TcpStream::connect(addr).await?;
+        assert!(connect(&mut socket).await.is_err());
+        assert!(accept.await?.is_err());
+        Ok(())
+    }
+}
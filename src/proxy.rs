@@ -0,0 +1,702 @@
+//! A proxy that gives applications a single endpoint for a toyDB cluster.
+//!
+//! The proxy accepts client connections using the regular client protocol, and
+//! routes each request to a cluster node: stale reads to a healthy follower,
+//! and everything else to the leader. It tracks the leader and follower health
+//! by periodically polling every node's status, and opens connections to nodes
+//! as needed for each client session, using the client's protocol version.
+//!
+//! Requests outside of a transaction are retried transparently if the node is
+//! unreachable or isn't the leader, until the cluster has elected a new leader
+//! or RETRY_TIMEOUT expires. If the node is lost while executing a request, it
+//! is only retried if it's read-only, since the outcome of a write is unknown.
+//! A transaction's requests always go to the node that began it, since that is
+//! where its state lives, and if that node is lost the client is disconnected,
+//! as it would be if it were connected to the node directly.
+
+use crate::error::{Error, Result};
+use crate::protocol;
+use crate::raft;
+use crate::server::{Request, Response};
+use crate::sql::engine::TransactionStatus;
+use crate::sql::execution::ResultSet;
+use crate::sql::parser::{ast, Parser};
+
+use ::log::{debug, error, info};
+use futures::sink::SinkExt as _;
+use futures::FutureExt as _;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{watch, Notify};
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_stream::StreamExt as _;
+
+/// The default interval between node status polls.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to retry a request for, e.g. while the cluster elects a new leader.
+const RETRY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The delay between request retries.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// A connection from a client.
+type ClientConnection = tokio_serde::Framed<
+    tokio_util::codec::Framed<TcpStream, tokio_util::codec::LengthDelimitedCodec>,
+    Request,
+    Result<Response>,
+    tokio_serde::formats::Bincode<Request, Result<Response>>,
+>;
+
+/// A connection to a cluster node.
+type NodeConnection = tokio_serde::Framed<
+    tokio_util::codec::Framed<TcpStream, tokio_util::codec::LengthDelimitedCodec>,
+    Result<Response>,
+    Request,
+    tokio_serde::formats::Bincode<Result<Response>, Request>,
+>;
+
+/// A toyDB proxy.
+pub struct Proxy {
+    /// The SQL addresses of the cluster nodes.
+    nodes: Vec<String>,
+    /// The interval between node status polls.
+    interval: Duration,
+    listener: Option<TcpListener>,
+}
+
+impl Proxy {
+    /// Creates a new proxy for the cluster nodes with the given SQL addresses.
+    pub fn new(nodes: Vec<String>) -> Self {
+        Self { nodes, interval: POLL_INTERVAL, listener: None }
+    }
+
+    /// Sets the interval between node status polls, which is also how long a
+    /// node has to respond before it's considered unhealthy. Defaults to 1
+    /// second.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Starts listening for clients on the given address. Must be called
+    /// before serve.
+    pub async fn listen(mut self, addr: &str) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Listening on {} (SQL proxy)", listener.local_addr()?);
+        self.listener = Some(listener);
+        Ok(self)
+    }
+
+    /// Returns the address of the listener, e.g. to find the port when
+    /// listening on port 0.
+    pub fn addr(&self) -> Result<SocketAddr> {
+        match &self.listener {
+            Some(listener) => Ok(listener.local_addr()?),
+            None => Err(Error::Internal("Must listen before getting address".into())),
+        }
+    }
+
+    /// Serves clients until the returned future is dropped. Consumes the proxy.
+    pub async fn serve(self) -> Result<()> {
+        let listener =
+            self.listener.ok_or_else(|| Error::Internal("Must listen before serving".into()))?;
+        if self.nodes.is_empty() {
+            return Err(Error::Config("No cluster nodes given".into()));
+        }
+        let nodes = Arc::new(self.nodes);
+        let (topology_tx, topology_rx) = watch::channel(Topology::default());
+        let refresh = Arc::new(Notify::new());
+        tokio::try_join!(
+            Self::monitor(nodes.clone(), self.interval, topology_tx, refresh.clone()),
+            Self::serve_clients(listener, nodes, topology_rx, refresh),
+        )?;
+        Ok(())
+    }
+
+    /// Periodically polls the status of every node, and updates the topology.
+    /// Sessions trigger an early poll via refresh when a request fails.
+    async fn monitor(
+        nodes: Arc<Vec<String>>,
+        interval: Duration,
+        topology_tx: watch::Sender<Topology>,
+        refresh: Arc<Notify>,
+    ) -> Result<()> {
+        let mut backends: Vec<Option<Backend>> = nodes.iter().map(|_| None).collect();
+        loop {
+            let statuses = futures::future::join_all(
+                nodes
+                    .iter()
+                    .zip(backends.iter_mut())
+                    .map(|(addr, backend)| Self::poll(addr, backend, interval)),
+            )
+            .await;
+            let statuses: Vec<_> = nodes.iter().cloned().zip(statuses).collect();
+            let topology = Topology::new(&statuses);
+            topology_tx.send_if_modified(|current| {
+                if *current == topology {
+                    return false;
+                }
+                match &topology.leader {
+                    Some(leader) => info!(
+                        "Routing to leader {} and {} healthy followers",
+                        leader,
+                        topology.followers.len()
+                    ),
+                    None => info!("No leader found, routing to any node"),
+                }
+                *current = topology;
+                true
+            });
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {},
+                _ = refresh.notified() => {},
+            }
+        }
+    }
+
+    /// Polls a node's Raft status, connecting to it if needed. Returns None if
+    /// the node is unreachable, has no leader, or doesn't respond in time.
+    async fn poll(
+        addr: &str,
+        backend: &mut Option<Backend>,
+        timeout: Duration,
+    ) -> Option<raft::Status> {
+        let result = tokio::time::timeout(timeout, async {
+            if backend.is_none() {
+                *backend = Some(Backend::connect(addr, protocol::VERSION).await?);
+            }
+            let backend = backend.as_mut().expect("backend not connected");
+            let response = backend.call(Request::Status).await?;
+            backend.receive().await?.ok(); // history
+            Ok(response)
+        })
+        .await
+        .unwrap_or(Err(Error::Timeout));
+        match result {
+            Ok(Ok(Response::Status(status))) => Some(status.raft),
+            Ok(Ok(response)) => {
+                error!("Unexpected status response from {}: {:?}", addr, response);
+                *backend = None;
+                None
+            }
+            Ok(Err(err)) => {
+                debug!("Status request to {} failed: {}", addr, err);
+                None
+            }
+            Err(err) => {
+                debug!("Failed to poll {}: {}", addr, err);
+                *backend = None;
+                None
+            }
+        }
+    }
+
+    /// Serves clients, with a session each.
+    async fn serve_clients(
+        listener: TcpListener,
+        nodes: Arc<Vec<String>>,
+        topology: watch::Receiver<Topology>,
+        refresh: Arc<Notify>,
+    ) -> Result<()> {
+        let mut listener = TcpListenerStream::new(listener);
+        while let Some(socket) = listener.try_next().await? {
+            let peer = socket.peer_addr()?;
+            let session = Session::new(nodes.clone(), topology.clone(), refresh.clone());
+            tokio::spawn(async move {
+                info!("Client {} connected", peer);
+                match session.handle(socket).await {
+                    Ok(()) => info!("Client {} disconnected", peer),
+                    Err(err) => error!("Client {} error: {}", peer, err),
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The cluster topology, as seen by the latest status polls.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Topology {
+    /// The SQL address of the leader, if known.
+    leader: Option<String>,
+    /// The SQL addresses of healthy followers, in node order.
+    followers: Vec<String>,
+}
+
+impl Topology {
+    /// Builds the topology from the nodes' statuses, by SQL address. Followers
+    /// get their status from the leader, so it only succeeds if they can reach
+    /// it. Statuses from older terms are ignored, e.g. from a deposed leader
+    /// that hasn't heard from the new leader yet.
+    fn new(statuses: &[(String, Option<raft::Status>)]) -> Self {
+        let Some(term) = statuses
+            .iter()
+            .filter_map(|(_, status)| status.as_ref())
+            .filter(|status| status.leader > 0)
+            .map(|status| status.term)
+            .max()
+        else {
+            return Self::default();
+        };
+        let mut topology = Self::default();
+        for (addr, status) in statuses {
+            match status {
+                Some(status) if status.term != term || status.leader == 0 => {}
+                Some(status) if status.server == status.leader => {
+                    topology.leader = Some(addr.clone())
+                }
+                Some(_) => topology.followers.push(addr.clone()),
+                None => {}
+            }
+        }
+        topology
+    }
+}
+
+/// A connection to a cluster node.
+struct Backend {
+    conn: NodeConnection,
+    /// The negotiated protocol version.
+    version: u32,
+}
+
+impl Backend {
+    /// Connects to a node, using at most the given protocol version.
+    async fn connect(addr: &str, version: u32) -> Result<Self> {
+        let mut socket = TcpStream::connect(addr).await?;
+        let version = protocol::connect_max(&mut socket, version).await?;
+        let framed = tokio_util::codec::Framed::new(socket, Default::default());
+        let conn = tokio_serde::Framed::new(framed, tokio_serde::formats::Bincode::default());
+        Ok(Self { conn, version })
+    }
+
+    /// Returns whether the node has closed the connection, without waiting.
+    /// Nodes only send responses to requests, so anything else that can be
+    /// read means the connection is unusable.
+    fn closed(&mut self) -> bool {
+        self.conn.try_next().now_or_never().is_some()
+    }
+
+    /// Sends a request, returning its first response.
+    async fn call(&mut self, request: Request) -> Result<Result<Response>> {
+        self.conn.send(request).await?;
+        self.receive().await
+    }
+
+    /// Receives a response. The outer result contains connection errors, while
+    /// the inner result contains errors returned by the node.
+    async fn receive(&mut self) -> Result<Result<Response>> {
+        match self.conn.try_next().await? {
+            Some(response) => Ok(response),
+            None => Err(Error::Internal("Node disconnected".into())),
+        }
+    }
+}
+
+/// The outcome of routing a request to a node.
+enum Outcome {
+    /// The node's responses were relayed to the client.
+    Done,
+    /// The request failed without effects and can be retried, at the given
+    /// leader address if known.
+    Retry(Error, Option<String>),
+    /// The connection to the node was lost after sending the request, so its
+    /// outcome is unknown.
+    Lost(Error),
+}
+
+/// A proxied client session.
+struct Session {
+    /// The SQL addresses of the cluster nodes.
+    nodes: Arc<Vec<String>>,
+    topology: watch::Receiver<Topology>,
+    /// Notified to poll the nodes' status early.
+    refresh: Arc<Notify>,
+    /// The client's protocol version, also used for node connections.
+    version: u32,
+    /// Connections to nodes by SQL address, opened as needed.
+    backends: HashMap<String, Backend>,
+    /// The address of the node with the session's transaction, if any.
+    txn: Option<String>,
+    /// The number of stale reads routed, to rotate them across followers.
+    stale_reads: usize,
+}
+
+impl Session {
+    /// Creates a new client session.
+    fn new(
+        nodes: Arc<Vec<String>>,
+        topology: watch::Receiver<Topology>,
+        refresh: Arc<Notify>,
+    ) -> Self {
+        Self {
+            nodes,
+            topology,
+            refresh,
+            version: protocol::VERSION,
+            backends: HashMap::new(),
+            txn: None,
+            stale_reads: 0,
+        }
+    }
+
+    /// Handles a client connection.
+    async fn handle(mut self, socket: TcpStream) -> Result<()> {
+        let (framed, version) = protocol::accept(socket).await?;
+        debug!("Using protocol version {}", version);
+        self.version = version;
+        let mut client: ClientConnection =
+            tokio_serde::Framed::new(framed, tokio_serde::formats::Bincode::default());
+        while let Some(request) = client.try_next().await? {
+            self.request(&mut client, request).await?;
+            client.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Routes a request to a node and relays its responses to the client,
+    /// retrying it on another node if possible. Returns an error if the client
+    /// connection must be closed.
+    async fn request(&mut self, client: &mut ClientConnection, request: Request) -> Result<()> {
+        if let Some(addr) = self.txn.clone() {
+            let lost = |err| Error::Internal(format!("Lost transaction on {}: {}", addr, err));
+            let backend = self.backends.get_mut(&addr).expect("transaction backend not connected");
+            let first = backend.call(request.clone()).await.map_err(lost)?;
+            let txn = Self::relay(backend, Some(client), &request, first).await.map_err(lost)?;
+            if let Some(txn) = txn {
+                self.txn = txn.map(|_| addr.clone());
+            }
+            return Ok(());
+        }
+
+        let stale = Self::unwrap(&request).1.is_some();
+        let read_only = Self::read_only(&request);
+        let deadline = Instant::now() + RETRY_TIMEOUT;
+        let mut leader = None;
+        for attempt in 0.. {
+            let addr = leader.take().unwrap_or_else(|| self.route(stale, attempt));
+            let error = match self.attempt(client, &addr, &request).await? {
+                Outcome::Done => break,
+                Outcome::Retry(error, address) => {
+                    leader = address;
+                    error
+                }
+                Outcome::Lost(error) if read_only => error,
+                Outcome::Lost(error) => {
+                    let error = Error::Internal(format!(
+                        "Lost connection to {}, outcome unknown: {}",
+                        addr, error
+                    ));
+                    return self.fail(client, &request, error).await;
+                }
+            };
+            if Instant::now() >= deadline {
+                return self.fail(client, &request, error).await;
+            }
+            debug!("Retrying request to {}: {}", addr, error);
+            self.refresh.notify_one();
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+        Ok(())
+    }
+
+    /// Sends a request to the node at the given address outside of a
+    /// transaction, and relays its responses to the client unless it can be
+    /// retried.
+    async fn attempt(
+        &mut self,
+        client: &mut ClientConnection,
+        addr: &str,
+        request: &Request,
+    ) -> Result<Outcome> {
+        // A request sent on a connection closed by the node would be lost, so
+        // reconnect instead, e.g. after the node restarted.
+        if self.backends.get_mut(addr).is_some_and(|backend| backend.closed()) {
+            self.backends.remove(addr);
+        }
+        if !self.backends.contains_key(addr) {
+            match Backend::connect(addr, self.version).await {
+                Ok(backend) if backend.version == self.version => {
+                    self.backends.insert(addr.to_string(), backend);
+                }
+                Ok(backend) => {
+                    let error = Error::Value(format!(
+                        "Node {} does not support protocol version {} (supports {})",
+                        addr, self.version, backend.version
+                    ));
+                    return Ok(Outcome::Retry(error, None));
+                }
+                Err(error) => return Ok(Outcome::Retry(error, None)),
+            }
+        }
+        let backend = self.backends.get_mut(addr).expect("backend not connected");
+        let first = match backend.call(request.clone()).await {
+            Ok(first) => first,
+            Err(error) => {
+                self.backends.remove(addr);
+                return Ok(Outcome::Lost(error));
+            }
+        };
+
+        // The node isn't the leader, so the request had no effect. Drain the
+        // remaining responses and retry it.
+        let retry = match &first {
+            Err(Error::NotLeader(leader)) => Some((Error::NotLeader(*leader), None)),
+            Err(Error::Redirect { leader, address }) => {
+                Some((Error::NotLeader(Some(*leader)), Some(address.clone())))
+            }
+            _ => None,
+        };
+        if let Some((error, leader)) = retry {
+            if Self::relay(backend, None, request, first).await.is_err() {
+                self.backends.remove(addr);
+            }
+            return Ok(Outcome::Retry(error, leader));
+        }
+
+        if let Some(txn) = Self::relay(backend, Some(client), request, first).await? {
+            self.txn = txn.map(|_| addr.to_string());
+        }
+        Ok(Outcome::Done)
+    }
+
+    /// Picks a node to route a request to outside of a transaction: stale
+    /// reads go to healthy followers in turn (or the leader if there are none),
+    /// and everything else to the leader. If there is no known leader, nodes
+    /// are tried in turn, since they forward requests to the leader once one
+    /// has been elected.
+    fn route(&mut self, stale: bool, attempt: usize) -> String {
+        let topology = self.topology.borrow();
+        if stale && !topology.followers.is_empty() {
+            self.stale_reads += 1;
+            return topology.followers[self.stale_reads % topology.followers.len()].clone();
+        }
+        match &topology.leader {
+            Some(leader) => leader.clone(),
+            None => self.nodes[attempt % self.nodes.len()].clone(),
+        }
+    }
+
+    /// Relays the responses to a request from a node to the client, given the
+    /// first response. If there is no client, the responses are discarded.
+    /// Returns the session's transaction status, if the node sent it.
+    async fn relay(
+        backend: &mut Backend,
+        mut client: Option<&mut ClientConnection>,
+        request: &Request,
+        first: Result<Response>,
+    ) -> Result<Option<Option<TransactionStatus>>> {
+        let (request, token) = Self::unwrap(request);
+        let mut txn = None;
+        match request {
+            Request::Execute(_) => {
+                Self::relay_result(backend, &mut client, first).await?;
+                let response = backend.receive().await?;
+                if let Ok(Response::Transaction(status)) = response {
+                    txn = Some(status);
+                }
+                Self::forward(&mut client, response).await?;
+            }
+            Request::ExecuteMany(_) => {
+                let mut response = first;
+                loop {
+                    if let Ok(Response::Transaction(status)) = response {
+                        txn = Some(status);
+                        Self::forward(&mut client, Ok(Response::Transaction(status))).await?;
+                        break;
+                    }
+                    Self::relay_result(backend, &mut client, response).await?;
+                    response = backend.receive().await?;
+                }
+            }
+            Request::Status => {
+                Self::forward(&mut client, first).await?;
+                let history = backend.receive().await?;
+                Self::forward(&mut client, history).await?;
+            }
+            _ => Self::forward(&mut client, first).await?,
+        }
+        if token.is_some() {
+            let token = backend.receive().await?;
+            Self::forward(&mut client, token).await?;
+        }
+        Ok(txn)
+    }
+
+    /// Relays a statement result, given its initial response, along with any
+    /// query rows and its command tag.
+    async fn relay_result(
+        backend: &mut Backend,
+        client: &mut Option<&mut ClientConnection>,
+        response: Result<Response>,
+    ) -> Result<()> {
+        let rows = matches!(response, Ok(Response::Execute(ResultSet::Query { .. }, _)));
+        let ok = response.is_ok();
+        Self::forward(client, response).await?;
+        if rows {
+            loop {
+                let response = backend.receive().await?;
+                let (failed, end) = match &response {
+                    Ok(Response::Row(Some(_))) => (false, false),
+                    Ok(_) => (false, true),
+                    Err(_) => (true, true),
+                };
+                Self::forward(client, response).await?;
+                // A row error ends the result, without a command tag.
+                if failed {
+                    return Ok(());
+                }
+                if end {
+                    break;
+                }
+            }
+        }
+        if ok {
+            let tag = backend.receive().await?;
+            Self::forward(client, tag).await?;
+        }
+        Ok(())
+    }
+
+    /// Passes a response on to the client, if any. Responses are flushed after
+    /// each request.
+    async fn forward(
+        client: &mut Option<&mut ClientConnection>,
+        response: Result<Response>,
+    ) -> Result<()> {
+        if let Some(client) = client {
+            client.feed(response).await?;
+        }
+        Ok(())
+    }
+
+    /// Responds to a request with an error, in place of a node's responses.
+    async fn fail(
+        &mut self,
+        client: &mut ClientConnection,
+        request: &Request,
+        error: Error,
+    ) -> Result<()> {
+        let (request, token) = Self::unwrap(request);
+        client.feed(Err(error)).await?;
+        match request {
+            Request::Execute(_) | Request::ExecuteMany(_) => {
+                client.feed(Ok(Response::Transaction(None))).await?
+            }
+            Request::Status => client.feed(Ok(Response::History(Vec::new()))).await?,
+            _ => {}
+        }
+        if let Some(token) = token {
+            client.feed(Ok(Response::Token(token))).await?;
+        }
+        Ok(())
+    }
+
+    /// Unwraps a request's options, returning the inner request and the stale
+    /// reads session token, if any.
+    fn unwrap(mut request: &Request) -> (&Request, Option<raft::Index>) {
+        let mut token = None;
+        loop {
+            match request {
+                Request::Timeout(_, inner) => request = inner,
+                Request::Stale(t, inner) => (token, request) = (Some(*t), inner),
+                request => return (request, token),
+            }
+        }
+    }
+
+    /// Returns whether a request only reads, such that it can be retried if
+    /// the connection is lost while executing it. Statements are parsed to
+    /// find SELECT queries.
+    fn read_only(request: &Request) -> bool {
+        match Self::unwrap(request).0 {
+            Request::Execute(query) => {
+                matches!(Parser::new(query).parse(), Ok(ast::Statement::Select { .. }))
+            }
+            Request::GetTable(_)
+            | Request::ListTables
+            | Request::Status
+            | Request::KVGet { .. }
+            | Request::KVScan { .. }
+            | Request::Liveness
+            | Request::ReadLog { .. }
+            | Request::Metrics => true,
+            Request::ExecuteMany(_)
+            | Request::KVSet { .. }
+            | Request::KVDelete { .. }
+            | Request::Promote
+            | Request::Timeout(..)
+            | Request::Stale(..) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a node status with the given server, leader, and term.
+    fn status(server: raft::NodeID, leader: raft::NodeID, term: raft::Term) -> raft::Status {
+        raft::Status {
+            server,
+            leader,
+            term,
+            node_last_index: HashMap::new(),
+            commit_index: 0,
+            apply_index: 0,
+            storage: "memory".into(),
+            storage_size: 0,
+            diverged: false,
+            checksum_mismatches: 0,
+            history: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn topology() {
+        let statuses = vec![
+            ("a".to_string(), Some(status(1, 2, 3))),
+            ("b".to_string(), Some(status(2, 2, 3))),
+            ("c".to_string(), None),
+            ("d".to_string(), Some(status(4, 2, 3))),
+        ];
+        assert_eq!(
+            Topology::new(&statuses),
+            Topology { leader: Some("b".into()), followers: vec!["a".into(), "d".into()] }
+        );
+
+        // A deposed leader in an older term is ignored.
+        let statuses = vec![
+            ("a".to_string(), Some(status(1, 1, 2))),
+            ("b".to_string(), Some(status(2, 3, 4))),
+            ("c".to_string(), Some(status(3, 3, 4))),
+        ];
+        assert_eq!(
+            Topology::new(&statuses),
+            Topology { leader: Some("c".into()), followers: vec!["b".into()] }
+        );
+
+        // Without a leader, there is no topology.
+        let statuses = vec![("a".to_string(), Some(status(1, 0, 2))), ("b".to_string(), None)];
+        assert_eq!(Topology::new(&statuses), Topology::default());
+    }
+
+    #[test]
+    fn read_only() {
+        let execute = |query: &str| Request::Execute(query.into());
+        assert!(Session::read_only(&execute("SELECT * FROM test")));
+        assert!(Session::read_only(&Request::Stale(1, Box::new(execute("SELECT 1")))));
+        assert!(!Session::read_only(&execute("SELECT * INTO copy FROM test")));
+        assert!(!Session::read_only(&execute("INSERT INTO test VALUES (1)")));
+        assert!(!Session::read_only(&execute("BEGIN")));
+        assert!(!Session::read_only(&Request::ExecuteMany("SELECT 1; SELECT 2".into())));
+        assert!(Session::read_only(&Request::Timeout(
+            Duration::from_secs(1),
+            Box::new(Request::ListTables)
+        )));
+    }
+}
@@ -0,0 +1,207 @@
+//! A query queue, which bounds the number of client requests executing
+//! concurrently, such that background jobs and bulk loads don't crowd out
+//! interactive traffic.
+//!
+//! Each request must be admitted by the queue before it executes, and releases
+//! its slot once it's done. When all slots are taken, requests wait in the
+//! queue and are admitted in order of their session's priority, and then in
+//! arrival order. Requests that wait longer than the queue timeout are rejected
+//! with Error::Overloaded without being executed, so the client can retry them
+//! later. System priority requests bypass the queue entirely, such that an
+//! administrator can always e.g. KILL runaway sessions. Only privileged
+//! sessions can use it, see Server::admin_hosts().
+
+use crate::error::{Error, Result};
+
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A request priority, set per session via the priority variable. Ordered from
+/// lowest to highest.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Background jobs and bulk loads.
+    Low,
+    /// Regular traffic.
+    #[default]
+    Normal,
+    /// Latency-sensitive interactive traffic.
+    High,
+    /// Administration, which isn't queued. Only privileged sessions in the
+    /// system tenant can use it.
+    System,
+}
+
+impl Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Low => "low",
+            Self::Normal => "normal",
+            Self::High => "high",
+            Self::System => "system",
+        })
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_lowercase().as_str() {
+            "low" => Self::Low,
+            "normal" => Self::Normal,
+            "high" => Self::High,
+            "system" => Self::System,
+            _ => return Err(Error::Value(format!("Unknown priority {}", s))),
+        })
+    }
+}
+
+/// A query queue, shared by a server's sessions.
+pub struct Queue {
+    /// The maximum number of concurrently executing requests, or 0 for no
+    /// limit. System priority requests don't count towards it.
+    concurrency: usize,
+    /// The maximum time a request may wait in the queue.
+    timeout: Duration,
+    state: Mutex<QueueState>,
+    /// Notified when a slot is released or a queued request gives up.
+    released: Condvar,
+}
+
+/// The state of a query queue.
+#[derive(Default)]
+struct QueueState {
+    /// The number of executing requests, excluding system priority.
+    running: usize,
+    /// Queued requests, by descending priority and then sequence number.
+    waiting: BTreeSet<(Reverse<Priority>, u64)>,
+    /// The next queued request's sequence number.
+    next_seq: u64,
+}
+
+/// An admitted request's slot in a query queue, released when dropped.
+pub struct Permit {
+    /// The queue to release the slot in, if the request took one.
+    queue: Option<Arc<Queue>>,
+}
+
+impl Queue {
+    /// Creates a new query queue with the given concurrency limit (0 is
+    /// unlimited) and queue timeout.
+    pub fn new(concurrency: usize, timeout: Duration) -> Self {
+        Self { concurrency, timeout, state: Mutex::default(), released: Condvar::new() }
+    }
+
+    /// Admits a request with the given priority, waiting for a slot if
+    /// necessary. Errors with Error::Overloaded if the request waits longer
+    /// than the queue timeout, or Error::Timeout if it reaches its deadline.
+    pub fn admit(
+        self: &Arc<Self>,
+        priority: Priority,
+        deadline: Option<Instant>,
+    ) -> Result<Permit> {
+        if self.concurrency == 0 || priority == Priority::System {
+            return Ok(Permit { queue: None });
+        }
+        let start = Instant::now();
+        let mut state = self.state.lock()?;
+        let ticket = (Reverse(priority), state.next_seq);
+        state.next_seq += 1;
+        state.waiting.insert(ticket);
+        loop {
+            if state.running < self.concurrency && state.waiting.first() == Some(&ticket) {
+                state.waiting.remove(&ticket);
+                state.running += 1;
+                // There may be more free slots for the next request in line.
+                self.released.notify_all();
+                return Ok(Permit { queue: Some(self.clone()) });
+            }
+            let timeout = start + self.timeout;
+            let until = deadline.map_or(timeout, |deadline| deadline.min(timeout));
+            let now = Instant::now();
+            if now >= until {
+                state.waiting.remove(&ticket);
+                self.released.notify_all();
+                return Err(match deadline {
+                    Some(deadline) if deadline <= now => Error::Timeout,
+                    _ => Error::Overloaded,
+                });
+            }
+            state = self.released.wait_timeout(state, until - now)?.0;
+        }
+    }
+
+    /// Returns the number of executing and queued requests.
+    pub fn counts(&self) -> Result<(usize, usize)> {
+        let state = self.state.lock()?;
+        Ok((state.running, state.waiting.len()))
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let Some(queue) = self.queue.take() else {
+            return;
+        };
+        if let Ok(mut state) = queue.state.lock() {
+            state.running -= 1;
+        }
+        queue.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // Requests beyond the concurrency limit are queued and admitted by priority,
+    // or time out. System priority requests bypass the queue.
+    fn queue() -> Result<()> {
+        let queue = Arc::new(Queue::new(1, Duration::from_millis(100)));
+        let permit = queue.admit(Priority::Normal, None)?;
+        let system = queue.admit(Priority::System, None)?;
+        assert_eq!(queue.counts()?, (1, 0));
+        assert_eq!(queue.admit(Priority::High, None).err(), Some(Error::Overloaded));
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert_eq!(queue.admit(Priority::High, Some(deadline)).err(), Some(Error::Timeout));
+        drop((permit, system));
+
+        // Queue a low and a high priority request, in that order. Once the slot
+        // is released, the high priority request runs first.
+        let queue = Arc::new(Queue::new(1, Duration::from_secs(10)));
+        let permit = queue.admit(Priority::Normal, None)?;
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut threads = Vec::new();
+        for priority in [Priority::Low, Priority::High] {
+            let (waiter, tx) = (queue.clone(), tx.clone());
+            threads.push(std::thread::spawn(move || {
+                let _permit = waiter.admit(priority, None).expect("admit failed");
+                tx.send(priority).expect("send failed");
+            }));
+            while queue.counts()?.1 < threads.len() {
+                std::thread::yield_now();
+            }
+        }
+        drop(permit);
+        for thread in threads {
+            thread.join().expect("thread panicked");
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![Priority::High, Priority::Low]);
+        assert_eq!(queue.counts()?, (0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn priority() -> Result<()> {
+        assert_eq!("HIGH".parse::<Priority>()?, Priority::High);
+        assert_eq!(Priority::Low.to_string(), "low");
+        assert!("urgent".parse::<Priority>().is_err());
+        assert!(Priority::Low < Priority::Normal && Priority::High < Priority::System);
+        Ok(())
+    }
+}
@@ -0,0 +1,299 @@
+use super::{Entry, Index};
+use crate::error::{Error, Result};
+use crate::storage::bincode;
+
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The number of entries in each archive segment file.
+const SEGMENT_SIZE: Index = 10000;
+
+/// An archived log entry.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ArchivedEntry {
+    /// The log entry.
+    pub entry: Entry,
+    /// The time the entry was archived, in milliseconds since the Unix epoch. Entries are archived
+    /// as they're committed, so this is approximately the commit time.
+    pub time: u64,
+}
+
+impl ArchivedEntry {
+    /// Creates a new archived entry, archived at the current time.
+    pub fn new(entry: Entry) -> Self {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        Self { entry, time: time as u64 }
+    }
+}
+
+/// A Raft log archive, which copies committed entries to segment files in a directory for
+/// point-in-time recovery. Entries are archived contiguously from index 1, and each segment
+/// file holds SEGMENT_SIZE entries and is named by the index of its first entry. The structure
+/// of an entry record is:
+///
+/// - Record length as big-endian u32.
+/// - ArchivedEntry encoded with Bincode.
+///
+/// An incomplete record at the end of the last segment, e.g. after a crash, is truncated when
+/// the archive is opened.
+pub struct Archive {
+    /// The archive directory.
+    dir: PathBuf,
+    /// The index of the last archived entry.
+    last_index: Index,
+    /// The segment file to append the next entry to, or None if a new segment must be created.
+    file: Option<File>,
+    /// Whether to sync writes to disk.
+    sync: bool,
+}
+
+impl Archive {
+    /// Opens or creates an archive in the given directory.
+    pub fn new(dir: PathBuf, sync: bool) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let mut archive = Self { dir, last_index: 0, file: None, sync };
+        if let Some((first_index, path)) = Self::segments(&archive.dir)?.pop() {
+            let (entries, len) = Self::read_segment(first_index, &path)?;
+            let file = OpenOptions::new().append(true).open(&path)?;
+            if len < file.metadata()?.len() {
+                log::error!("Found incomplete archive entry in {}, truncating", path.display());
+                file.set_len(len)?;
+            }
+            archive.last_index = first_index + entries.len() as Index - 1;
+            if entries.is_empty() || !Self::is_segment_end(archive.last_index) {
+                archive.file = Some(file);
+            }
+        }
+        Ok(archive)
+    }
+
+    /// Returns the index of the last archived entry, or 0 if none.
+    pub fn last_index(&self) -> Index {
+        self.last_index
+    }
+
+    /// Appends an entry to the archive. It must immediately follow the last archived entry.
+    pub fn append(&mut self, entry: &ArchivedEntry) -> Result<()> {
+        if entry.entry.index != self.last_index + 1 {
+            return Err(Error::Internal(format!(
+                "Can't archive index {} after {}",
+                entry.entry.index, self.last_index
+            )));
+        }
+        let mut file = match self.file.take() {
+            Some(file) => file,
+            None => OpenOptions::new()
+                .create_new(true)
+                .append(true)
+                .open(Self::segment_path(&self.dir, entry.entry.index))?,
+        };
+        let record = bincode::serialize(entry)?;
+        let mut buf = Vec::with_capacity(4 + record.len());
+        buf.extend_from_slice(&(record.len() as u32).to_be_bytes());
+        buf.extend(record);
+        file.write_all(&buf)?;
+        self.last_index = entry.entry.index;
+
+        // Close the segment file once it's full, syncing it first if enabled.
+        if Self::is_segment_end(self.last_index) {
+            if self.sync {
+                file.sync_data()?;
+            }
+        } else {
+            self.file = Some(file);
+        }
+        Ok(())
+    }
+
+    /// Flushes the archive to stable storage, if enabled.
+    pub fn flush(&mut self) -> Result<()> {
+        if let (Some(file), true) = (&self.file, self.sync) {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Reads archived entries from the given directory in index order, up to and including
+    /// the last entry for which the given predicate returns true.
+    pub fn read(
+        dir: &Path,
+        mut until: impl FnMut(&ArchivedEntry) -> bool,
+    ) -> Result<Vec<ArchivedEntry>> {
+        let mut entries: Vec<ArchivedEntry> = Vec::new();
+        for (first_index, path) in Self::segments(dir)? {
+            let expect = entries.last().map_or(1, |e| e.entry.index + 1);
+            if first_index != expect {
+                return Err(Error::Internal(format!(
+                    "Archive is missing entries {}-{}",
+                    expect,
+                    first_index - 1
+                )));
+            }
+            for entry in Self::read_segment(first_index, &path)?.0 {
+                if !until(&entry) {
+                    return Ok(entries);
+                }
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns whether the given index is the last entry in its segment.
+    fn is_segment_end(index: Index) -> bool {
+        index > 0 && (index - 1) / SEGMENT_SIZE != index / SEGMENT_SIZE
+    }
+
+    /// Returns the path of the segment file starting at the given index.
+    fn segment_path(dir: &Path, first_index: Index) -> PathBuf {
+        dir.join(format!("{:020}.segment", first_index))
+    }
+
+    /// Returns the segment files in the given directory and their first index, in index order.
+    fn segments(dir: &Path) -> Result<Vec<(Index, PathBuf)>> {
+        let mut segments = Vec::new();
+        for file in std::fs::read_dir(dir)? {
+            let path = file?.path();
+            if path.extension() != Some(OsStr::new("segment")) {
+                continue;
+            }
+            let Some(first_index) = path.file_stem().and_then(|s| s.to_str()?.parse().ok()) else {
+                return Err(Error::Internal(format!("Invalid segment file {}", path.display())));
+            };
+            segments.push((first_index, path));
+        }
+        segments.sort();
+        Ok(segments)
+    }
+
+    /// Reads the entries of a segment file, and returns them along with the length of the
+    /// complete records (which is less than the file length if the last record is incomplete).
+    fn read_segment(first_index: Index, path: &Path) -> Result<(Vec<ArchivedEntry>, u64)> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut entries: Vec<ArchivedEntry> = Vec::new();
+        let mut len = 0;
+        let mut len_buf = [0u8; 4];
+        loop {
+            let result = || -> std::result::Result<Option<Vec<u8>>, std::io::Error> {
+                if r.read(&mut len_buf[..1])? == 0 {
+                    return Ok(None);
+                }
+                r.read_exact(&mut len_buf[1..])?;
+                let mut record = vec![0; u32::from_be_bytes(len_buf) as usize];
+                r.read_exact(&mut record)?;
+                Ok(Some(record))
+            }();
+            match result {
+                Ok(Some(record)) => {
+                    let entry: ArchivedEntry = bincode::deserialize(&record)?;
+                    let expect = first_index + entries.len() as Index;
+                    if entry.entry.index != expect {
+                        return Err(Error::Internal(format!(
+                            "Archive segment {} has index {} at position of {}",
+                            path.display(),
+                            entry.entry.index,
+                            expect
+                        )));
+                    }
+                    len += 4 + record.len() as u64;
+                    entries.push(entry);
+                }
+                Ok(None) => break,
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok((entries, len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn entry(index: Index) -> ArchivedEntry {
+        ArchivedEntry {
+            entry: Entry { index, term: 1, command: Some(vec![index as u8]) },
+            time: 1000 + index,
+        }
+    }
+
+    #[test]
+    fn append_read() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let mut archive = Archive::new(dir.path().to_path_buf(), false)?;
+        assert_eq!(archive.last_index(), 0);
+
+        // Entries must be contiguous from index 1.
+        assert_eq!(
+            archive.append(&entry(2)),
+            Err(Error::Internal("Can't archive index 2 after 0".into()))
+        );
+        for index in 1..=3 {
+            archive.append(&entry(index))?;
+        }
+        archive.flush()?;
+        assert_eq!(archive.last_index(), 3);
+
+        assert_eq!(Archive::read(dir.path(), |_| true)?, vec![entry(1), entry(2), entry(3)]);
+        assert_eq!(Archive::read(dir.path(), |e| e.time <= 1002)?, vec![entry(1), entry(2)]);
+        assert_eq!(Archive::read(dir.path(), |_| false)?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn segments() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let mut archive = Archive::new(dir.path().to_path_buf(), false)?;
+        for index in 1..=SEGMENT_SIZE + 1 {
+            archive.append(&entry(index))?;
+        }
+        assert_eq!(
+            Archive::segments(dir.path())?,
+            vec![
+                (1, Archive::segment_path(dir.path(), 1)),
+                (SEGMENT_SIZE + 1, Archive::segment_path(dir.path(), SEGMENT_SIZE + 1)),
+            ]
+        );
+        let entries = Archive::read(dir.path(), |_| true)?;
+        assert_eq!(entries.len() as Index, SEGMENT_SIZE + 1);
+        assert_eq!(entries.last(), Some(&entry(SEGMENT_SIZE + 1)));
+
+        // A missing segment is an error.
+        std::fs::remove_file(Archive::segment_path(dir.path(), 1))?;
+        assert_eq!(
+            Archive::read(dir.path(), |_| true),
+            Err(Error::Internal(format!("Archive is missing entries 1-{}", SEGMENT_SIZE)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reopen_truncates_incomplete() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let mut archive = Archive::new(dir.path().to_path_buf(), false)?;
+        archive.append(&entry(1))?;
+        archive.append(&entry(2))?;
+        drop(archive);
+
+        // Write a partial record, which should be truncated when reopened.
+        let path = Archive::segment_path(dir.path(), 1);
+        let len = std::fs::metadata(&path)?.len();
+        let mut file = OpenOptions::new().append(true).open(&path)?;
+        file.write_all(&[0, 0, 0, 9, 1, 2])?;
+        drop(file);
+        assert_eq!(Archive::read(dir.path(), |_| true)?, vec![entry(1), entry(2)]);
+
+        let mut archive = Archive::new(dir.path().to_path_buf(), false)?;
+        assert_eq!(archive.last_index(), 2);
+        assert_eq!(std::fs::metadata(&path)?.len(), len);
+        archive.append(&entry(3))?;
+        assert_eq!(Archive::read(dir.path(), |_| true)?, vec![entry(1), entry(2), entry(3)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use super::{Index, NodeID, Term};
+use crate::error::Error;
+
+use ::log::error;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// The number of recent events kept in the history.
+const HISTORY_SIZE: usize = 256;
+
+/// A notable Raft event, recorded in the node history for debugging.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum HistoryEvent {
+    /// Became a follower, either of a leader in the current term, or a
+    /// leaderless follower in a new term.
+    Follower { leader: Option<NodeID> },
+    /// Became a candidate, campaigning in a new term.
+    Candidate,
+    /// Won the election and became leader.
+    Leader,
+    /// Voted for a candidate.
+    Vote { candidate: NodeID },
+    /// Started sending a snapshot to a follower.
+    SendSnapshot { peer: NodeID, index: Index },
+    /// Installed a snapshot received from the leader.
+    InstallSnapshot { leader: NodeID, index: Index },
+    /// Compacted the log into a local snapshot.
+    Compact { index: Index },
+    /// Rejected a client request.
+    Reject { error: Error },
+}
+
+impl std::fmt::Display for HistoryEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Follower { leader: Some(leader) } => write!(f, "following leader {}", leader),
+            Self::Follower { leader: None } => write!(f, "leaderless follower in new term"),
+            Self::Candidate => write!(f, "campaigning for leadership"),
+            Self::Leader => write!(f, "became leader"),
+            Self::Vote { candidate } => write!(f, "voted for {}", candidate),
+            Self::SendSnapshot { peer, index } => {
+                write!(f, "sending snapshot at index {} to {}", index, peer)
+            }
+            Self::InstallSnapshot { leader, index } => {
+                write!(f, "installed snapshot at index {} from {}", index, leader)
+            }
+            Self::Compact { index } => write!(f, "compacted log at index {}", index),
+            Self::Reject { error } => write!(f, "rejected client request: {}", error),
+        }
+    }
+}
+
+/// A recorded history event.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// The wall-clock time of the event.
+    pub time: SystemTime,
+    /// The node's term after the event.
+    pub term: Term,
+    pub event: HistoryEvent,
+}
+
+impl std::fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = OffsetDateTime::from(self.time).format(&Rfc3339).map_err(|_| std::fmt::Error)?;
+        write!(f, "{} term {}: {}", time, self.term, self.event)
+    }
+}
+
+/// An in-memory ring buffer of recent Raft events, independent of log
+/// verbosity. The node records events into it, and it can be shared with
+/// e.g. the server to return the events in status responses. Cloning it
+/// returns a handle to the same buffer.
+#[derive(Clone, Debug, Default)]
+pub struct History(Arc<Mutex<VecDeque<HistoryEntry>>>);
+
+impl History {
+    /// Creates a new, empty history.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an event, evicting the oldest one if the history is full.
+    pub fn record(&self, term: Term, event: HistoryEvent) {
+        let mut entries = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= HISTORY_SIZE {
+            entries.pop_front();
+        }
+        entries.push_back(HistoryEntry { time: SystemTime::now(), term, event });
+    }
+
+    /// Returns the recorded events, oldest first.
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+    }
+
+    /// Returns a guard that logs the history as errors if it's dropped while
+    /// panicking, for post-mortem debugging.
+    pub fn dump_on_panic(&self) -> PanicDump {
+        PanicDump(self.clone())
+    }
+}
+
+/// Logs a history when dropped during a panic, see History::dump_on_panic().
+pub struct PanicDump(History);
+
+impl Drop for PanicDump {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            error!("Raft history before panic:");
+            for entry in self.0.entries() {
+                error!("  {}", entry);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // The history keeps the most recent events, up to HISTORY_SIZE.
+    fn record() {
+        let history = History::new();
+        for term in 0..HISTORY_SIZE as Term + 10 {
+            history.record(term, HistoryEvent::Candidate);
+        }
+        let entries = history.entries();
+        assert_eq!(entries.len(), HISTORY_SIZE);
+        assert_eq!(entries.first().map(|e| e.term), Some(10));
+        assert_eq!(entries.last().map(|e| e.term), Some(HISTORY_SIZE as Term + 9));
+
+        // Handles share the same buffer.
+        history.clone().record(1, HistoryEvent::Leader);
+        assert_eq!(history.entries().last().map(|e| &e.event), Some(&HistoryEvent::Leader));
+    }
+}
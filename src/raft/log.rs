@@ -1,10 +1,12 @@
+use crate::encoding::keycode;
 use crate::error::{Error, Result};
-use crate::storage::{self, bincode, keycode};
+use crate::storage::{self, bincode};
 
-use ::log::debug;
+use ::log::{debug, error};
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 
-use super::{NodeID, Term};
+use super::{Archive, ArchivedEntry, NodeID, Term};
 
 /// A log index.
 pub type Index = u64;
@@ -29,6 +31,9 @@ pub enum Key {
     TermVote,
     /// Stores the current commit index (if any).
     CommitIndex,
+    /// Stores the latest state snapshot (if any), which replaces the compacted
+    /// log entries up to and including its index.
+    Snapshot,
 }
 
 impl Key {
@@ -47,6 +52,7 @@ enum KeyPrefix {
     Entry,
     TermVote,
     CommitIndex,
+    Snapshot,
 }
 
 impl KeyPrefix {
@@ -54,6 +60,27 @@ impl KeyPrefix {
         keycode::serialize(self)
     }
 }
+/// A snapshot of the state machine at a log index. Log compaction replaces the
+/// entries up to and including the index with the snapshot.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The index of the last entry applied to the snapshotted state.
+    pub index: Index,
+    /// The term of the last entry applied to the snapshotted state.
+    pub term: Term,
+    /// A CRC32 checksum of the snapshot data.
+    pub checksum: u32,
+    /// The snapshot data, as returned by State::snapshot().
+    pub data: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Creates a new snapshot, checksumming the data.
+    pub fn new(index: Index, term: Term, data: Vec<u8>) -> Self {
+        Self { index, term, checksum: crc32fast::hash(&data), data }
+    }
+}
+
 /// A Raft log.
 pub struct Log {
     /// The underlying storage engine. Uses a trait object instead of generics,
@@ -70,11 +97,21 @@ pub struct Log {
     commit_term: Term,
     /// Whether to sync writes to disk.
     sync: bool,
+    /// An archive to copy committed entries to, if any.
+    archive: Option<Archive>,
+    /// The latest state snapshot, if the log has been compacted. It's kept in
+    /// memory, since it's sent in chunks to followers that need it.
+    snapshot: Option<Snapshot>,
+    /// The interval at which the state is snapshotted and the log compacted,
+    /// in log entries. 0 disables compaction.
+    snapshot_interval: Index,
 }
 
 impl Log {
     /// Creates a new log, using the given storage engine.
     pub fn new(mut engine: impl storage::engine::Engine + 'static, sync: bool) -> Result<Self> {
+        let snapshot: Option<Snapshot> =
+            engine.get(&Key::Snapshot.encode()?)?.map(|v| bincode::deserialize(&v)).transpose()?;
         let (last_index, last_term) = engine
             .scan_prefix(&KeyPrefix::Entry.encode()?)
             .last()
@@ -82,12 +119,19 @@ impl Log {
             .map(|(k, v)| Self::decode_entry(&k, &v))
             .transpose()?
             .map(|e| (e.index, e.term))
+            .or(snapshot.as_ref().map(|s| (s.index, s.term)))
             .unwrap_or((0, 0));
         let (commit_index, commit_term) = engine
             .get(&Key::CommitIndex.encode()?)?
             .map(|v| bincode::deserialize(&v))
             .transpose()?
             .unwrap_or((0, 0));
+        // A snapshot installed from the leader commits its entries, but the
+        // commit index may not have been written before a crash.
+        let (commit_index, commit_term) = match &snapshot {
+            Some(s) if s.index > commit_index => (s.index, s.term),
+            _ => (commit_index, commit_term),
+        };
         Ok(Self {
             engine: Box::new(engine),
             last_index,
@@ -95,9 +139,52 @@ impl Log {
             commit_index,
             commit_term,
             sync,
+            archive: None,
+            snapshot,
+            snapshot_interval: 0,
         })
     }
 
+    /// Sets an archive to copy committed entries to, and archives any committed entries that
+    /// it's missing.
+    pub fn set_archive(&mut self, archive: Archive) -> Result<()> {
+        if archive.last_index() > self.commit_index {
+            return Err(Error::Internal(format!(
+                "Archive index {} is beyond commit index {}",
+                archive.last_index(),
+                self.commit_index
+            )));
+        }
+        if let Some(snapshot) = &self.snapshot {
+            if archive.last_index() < snapshot.index {
+                return Err(Error::Internal(format!(
+                    "Archive index {} is before snapshot index {}, compacted entries can't be \
+                     archived",
+                    archive.last_index(),
+                    snapshot.index
+                )));
+            }
+        }
+        self.archive = Some(archive);
+        self.archive_committed()
+    }
+
+    /// Copies any unarchived committed entries to the archive, if set.
+    fn archive_committed(&mut self) -> Result<()> {
+        let Some(mut archive) = self.archive.take() else { return Ok(()) };
+        let result = || -> Result<()> {
+            if archive.last_index() < self.commit_index {
+                for entry in self.scan(archive.last_index() + 1..=self.commit_index)? {
+                    archive.append(&ArchivedEntry::new(entry?))?;
+                }
+                archive.flush()?;
+            }
+            Ok(())
+        }();
+        self.archive = Some(archive);
+        result
+    }
+
     /// Decodes an entry from a log key/value pair.
     fn decode_entry(key: &[u8], value: &[u8]) -> Result<Entry> {
         if let Key::Entry(index) = Key::decode(key)? {
@@ -128,6 +215,28 @@ impl Log {
         (self.last_index, self.last_term)
     }
 
+    /// Returns the index and term of the latest snapshot, or (0, 0) if the log
+    /// hasn't been compacted. Entries up to and including it have been removed.
+    pub fn get_snapshot_index(&self) -> (Index, Term) {
+        self.snapshot.as_ref().map(|s| (s.index, s.term)).unwrap_or((0, 0))
+    }
+
+    /// Returns the latest state snapshot, if any.
+    pub fn get_snapshot(&self) -> Option<&Snapshot> {
+        self.snapshot.as_ref()
+    }
+
+    /// Returns the snapshot interval, in log entries (0 if disabled).
+    pub fn get_snapshot_interval(&self) -> Index {
+        self.snapshot_interval
+    }
+
+    /// Sets the interval at which the state machine is snapshotted and the log
+    /// compacted, in log entries. 0 disables compaction, and is the default.
+    pub fn set_snapshot_interval(&mut self, interval: Index) {
+        self.snapshot_interval = interval;
+    }
+
     /// Returns the last known term (0 if none), and cast vote (if any).
     pub fn get_term(&mut self) -> Result<(Term, Option<NodeID>)> {
         let (term, voted_for) = self
@@ -182,6 +291,7 @@ impl Log {
         self.maybe_flush()?;
         self.commit_index = entry.index;
         self.commit_term = entry.term;
+        self.archive_committed()?;
         Ok(index)
     }
 
@@ -193,12 +303,18 @@ impl Log {
             .transpose()
     }
 
-    /// Checks if the log contains an entry with the given term.
+    /// Checks if the log contains an entry with the given term. Compacted
+    /// entries are committed, and thus match any leader's entries, but the
+    /// snapshot's last entry must match its term.
     pub fn has(&mut self, index: Index, term: Term) -> Result<bool> {
-        match self.get(index)? {
-            Some(entry) => Ok(entry.term == term),
-            None if index == 0 && term == 0 => Ok(true),
-            None => Ok(false),
+        let (snapshot_index, snapshot_term) = self.get_snapshot_index();
+        match index.cmp(&snapshot_index) {
+            Ordering::Less => Ok(true),
+            Ordering::Equal => Ok(term == snapshot_term),
+            Ordering::Greater => match self.get(index)? {
+                Some(entry) => Ok(entry.term == term),
+                None => Ok(false),
+            },
         }
     }
 
@@ -225,6 +341,81 @@ impl Log {
             .map(|r| r.and_then(|(k, v)| Self::decode_entry(&k, &v))))
     }
 
+    /// Compacts the log by replacing the committed entries up to and including
+    /// the given index with a snapshot of the state machine at that index.
+    /// Snapshots at or before the current snapshot index are ignored.
+    pub fn compact(&mut self, index: Index, data: Vec<u8>) -> Result<()> {
+        if index <= self.get_snapshot_index().0 {
+            return Ok(());
+        }
+        if index > self.commit_index {
+            return Err(Error::Internal(format!(
+                "Can't compact uncommitted index {} beyond commit index {}",
+                index, self.commit_index
+            )));
+        }
+        let Some(entry) = self.get(index)? else {
+            return Err(Error::Internal(format!("Can't compact non-existant index {}", index)));
+        };
+        debug!("Compacting log up to index {} into {} byte snapshot", index, data.len());
+        self.set_snapshot(Snapshot::new(index, entry.term, data), index)
+    }
+
+    /// Installs a snapshot received from the leader, committing the log up to
+    /// its index. If the log contains the snapshot's last entry, the following
+    /// entries are kept, otherwise the entire log is replaced by the snapshot.
+    pub fn install(&mut self, snapshot: Snapshot) -> Result<()> {
+        let (index, term) = (snapshot.index, snapshot.term);
+        if index <= self.commit_index {
+            return Err(Error::Internal(format!(
+                "Can't install snapshot at index {} before commit index {}",
+                index, self.commit_index
+            )));
+        }
+
+        // If we have the snapshot's entries, commit them as usual (e.g.
+        // archiving them) and compact the log.
+        if self.has(index, term)? {
+            self.commit(index)?;
+            return self.set_snapshot(snapshot, index);
+        }
+
+        // Otherwise, we can't archive the entries we never received.
+        if let Some(archive) = self.archive.take() {
+            error!(
+                "Disabling log archive at index {}, since entries up to snapshot index {} are \
+                 not available",
+                archive.last_index(),
+                index
+            );
+        }
+        self.set_snapshot(snapshot, self.last_index.max(index))?;
+        self.engine.set(&Key::CommitIndex.encode()?, bincode::serialize(&(index, term))?)?;
+        self.maybe_flush()?;
+        self.commit_index = index;
+        self.commit_term = term;
+        self.last_index = index;
+        self.last_term = term;
+        Ok(())
+    }
+
+    /// Stores a snapshot, and removes all entries up to and including the
+    /// given index. The snapshot is written first, such that a crash can't
+    /// lose both the entries and the snapshot.
+    fn set_snapshot(&mut self, snapshot: Snapshot, until: Index) -> Result<()> {
+        self.engine.set(&Key::Snapshot.encode()?, bincode::serialize(&snapshot)?)?;
+        let keys = self
+            .scan(..=until)?
+            .map(|r| r.and_then(|e| Key::Entry(e.index).encode()))
+            .collect::<Result<Vec<_>>>()?;
+        for key in keys {
+            self.engine.delete(&key)?;
+        }
+        self.maybe_flush()?;
+        self.snapshot = Some(snapshot);
+        Ok(())
+    }
+
     /// Splices a set of entries into the log. The entries must be contiguous,
     /// and the first entry must be at most last_index+1. If an entry does not
     /// exist, append it. If an existing entry has a term mismatch, replace it
@@ -343,6 +534,129 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn commit_archive() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let archived = || -> Result<Vec<Entry>> {
+            Ok(Archive::read(dir.path(), |_| true)?.into_iter().map(|e| e.entry).collect())
+        };
+        let mut l = setup();
+        l.append(1, Some(vec![0x01]))?;
+        l.append(2, None)?;
+        l.append(2, Some(vec![0x03]))?;
+        l.commit(1)?;
+
+        // Setting an archive copies existing committed entries.
+        l.set_archive(Archive::new(dir.path().to_path_buf(), false)?)?;
+        assert_eq!(archived()?, vec![Entry { index: 1, term: 1, command: Some(vec![0x01]) }]);
+
+        // Committing archives the newly committed entries.
+        l.commit(3)?;
+        assert_eq!(
+            archived()?,
+            vec![
+                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
+                Entry { index: 2, term: 2, command: None },
+                Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+            ]
+        );
+
+        // An archive beyond the commit index is rejected.
+        let mut l = setup();
+        assert_eq!(
+            l.set_archive(Archive::new(dir.path().to_path_buf(), false)?),
+            Err(Error::Internal("Archive index 3 is beyond commit index 0".into()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn compact() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let path = dir.path().join("log");
+        let mut l = Log::new(storage::engine::BitCask::new(path.clone())?, false)?;
+        l.append(1, Some(vec![0x01]))?;
+        l.append(2, None)?;
+        l.append(2, Some(vec![0x03]))?;
+        l.append(3, Some(vec![0x04]))?;
+        l.commit(3)?;
+
+        // Compacting beyond the commit index errors.
+        assert_eq!(
+            l.compact(4, vec![0xff]),
+            Err(Error::Internal(
+                "Can't compact uncommitted index 4 beyond commit index 3".to_string()
+            ))
+        );
+
+        // Compacting replaces the entries with the snapshot.
+        l.compact(2, vec![0x02])?;
+        assert_eq!(l.get_snapshot_index(), (2, 2));
+        assert_eq!(l.get_snapshot(), Some(&Snapshot::new(2, 2, vec![0x02])));
+        assert_eq!(
+            l.scan(..)?.collect::<Result<Vec<_>>>()?,
+            vec![
+                Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+            ]
+        );
+        assert_eq!(l.get(2)?, None);
+        assert!(l.has(1, 7)?);
+        assert!(l.has(2, 2)?);
+        assert!(!l.has(2, 1)?);
+
+        // Older snapshots are ignored.
+        l.compact(1, vec![0x01])?;
+        assert_eq!(l.get_snapshot_index(), (2, 2));
+
+        // The snapshot is loaded when reopening the log, and used as the last
+        // index if all entries are compacted.
+        l.commit(4)?;
+        l.compact(4, vec![0x04])?;
+        drop(l);
+        let mut l = Log::new(storage::engine::BitCask::new(path)?, false)?;
+        assert_eq!(l.get_snapshot(), Some(&Snapshot::new(4, 3, vec![0x04])));
+        assert_eq!(l.get_last_index(), (4, 3));
+        assert_eq!(l.get_commit_index(), (4, 3));
+        assert_eq!(l.append(3, Some(vec![0x05]))?, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn install() -> Result<()> {
+        // A snapshot that matches a log entry keeps the following entries.
+        let mut l = setup();
+        l.append(1, Some(vec![0x01]))?;
+        l.append(1, Some(vec![0x02]))?;
+        l.append(2, Some(vec![0x03]))?;
+        l.commit(1)?;
+        l.install(Snapshot::new(2, 1, vec![0xff]))?;
+        assert_eq!(l.get_snapshot_index(), (2, 1));
+        assert_eq!(l.get_commit_index(), (2, 1));
+        assert_eq!(l.get_last_index(), (3, 2));
+        assert_eq!(
+            l.scan(..)?.collect::<Result<Vec<_>>>()?,
+            vec![Entry { index: 3, term: 2, command: Some(vec![0x03]) }]
+        );
+
+        // Snapshots at or before the commit index are rejected.
+        assert_eq!(
+            l.install(Snapshot::new(2, 1, vec![0xff])),
+            Err(Error::Internal(
+                "Can't install snapshot at index 2 before commit index 2".to_string()
+            ))
+        );
+
+        // A snapshot that doesn't match the log replaces it.
+        l.install(Snapshot::new(5, 3, vec![0xee]))?;
+        assert_eq!(l.get_snapshot_index(), (5, 3));
+        assert_eq!(l.get_commit_index(), (5, 3));
+        assert_eq!(l.get_last_index(), (5, 3));
+        assert_eq!(l.scan(..)?.collect::<Result<Vec<_>>>()?, vec![]);
+        assert_eq!(l.append(3, Some(vec![0x06]))?, 6);
+        Ok(())
+    }
+
     #[test]
     fn get() -> Result<()> {
         let mut l = setup();
@@ -1,4 +1,4 @@
-use super::{Entry, Index, NodeID, Status, Term};
+use super::{Entry, Index, NodeID, Snapshot, Status, Term};
 use crate::error::Result;
 
 use serde_derive::{Deserialize, Serialize};
@@ -11,8 +11,8 @@ pub enum Address {
     /// A node with the specified node ID (local or remote). Valid both as
     /// sender and recipient.
     Node(NodeID),
-    /// A local client. Can only send ClientRequest messages, and receive
-    /// ClientResponse messages.
+    /// A local client. Can only send ClientRequest and ClientCancel messages,
+    /// and receive ClientResponse messages.
     Client,
 }
 
@@ -91,8 +91,9 @@ pub enum Event {
 
     /// A client request. This can be submitted to the leader, or to a follower
     /// which will forward it to its leader. If there is no leader, or the
-    /// leader or term changes, the request is aborted with an Error::Abort
-    /// ClientResponse and the client must retry.
+    /// leader changes, the request fails with an Error::NotLeader
+    /// ClientResponse (or Error::Abort if the leader steps down while
+    /// processing it) and the client must retry.
     ClientRequest {
         /// The request ID. This is arbitrary, but must be globally unique for
         /// the duration of the request.
@@ -108,6 +109,61 @@ pub enum Event {
         /// The response, or an error.
         response: Result<Response>,
     },
+
+    /// Cancels a client request, e.g. because its deadline passed, discarding
+    /// any pending response. A mutation may still be applied. This is only
+    /// submitted by the local client and never sent between nodes, so
+    /// requests forwarded to the leader are only cancelled locally.
+    ClientCancel {
+        /// The ID of the ClientRequest to cancel.
+        id: RequestID,
+    },
+
+    /// Leaders send a state snapshot in chunks to followers that need log
+    /// entries which have been compacted.
+    InstallSnapshot {
+        /// The index of the last log entry in the snapshot.
+        index: Index,
+        /// The term of the last log entry in the snapshot.
+        term: Term,
+        /// The total snapshot size, in bytes.
+        size: u64,
+        /// A CRC32 checksum of the entire snapshot, which the follower
+        /// verifies before installing it.
+        checksum: u32,
+        /// The byte offset of the chunk in the snapshot.
+        offset: u64,
+        /// The chunk data.
+        data: Vec<u8>,
+    },
+    /// Followers acknowledge snapshot chunks with the number of contiguous
+    /// bytes received so far, where the leader resumes the transfer from. An
+    /// installed snapshot is confirmed with AcceptEntries instead.
+    AcceptSnapshot {
+        /// The index of the snapshot.
+        index: Index,
+        /// The number of bytes received.
+        offset: u64,
+    },
+
+    /// The local state machine driver took a snapshot of the state at the
+    /// given applied index, which the node compacts its log into. This is
+    /// never sent between nodes.
+    CompactLog {
+        /// The applied index of the snapshot.
+        index: Index,
+        /// The snapshot data.
+        snapshot: Vec<u8>,
+    },
+
+    /// Nodes periodically broadcast a checksum of their applied state at a
+    /// log index, which peers compare with their own to detect divergence.
+    StateChecksum {
+        /// The applied index of the checksummed state.
+        index: Index,
+        /// The state checksum.
+        checksum: u32,
+    },
 }
 
 /// A client request ID.
@@ -119,6 +175,20 @@ pub enum Request {
     Query(Vec<u8>),
     Mutate(Vec<u8>),
     Status,
+    /// Queries the local state machine once it has applied at least the given
+    /// index, without confirming leadership. The result may be stale, but
+    /// reflects all writes up to the index. Never forwarded to the leader.
+    StaleQuery {
+        command: Vec<u8>,
+        min_index: Index,
+    },
+    /// Reads up to limit committed log entries from the given index, for
+    /// shipping the log to a standby cluster. If entries at the index have
+    /// been compacted, the snapshot that replaced them is returned first.
+    ReadLog {
+        from: Index,
+        limit: usize,
+    },
 }
 
 /// A client response.
@@ -127,4 +197,5 @@ pub enum Response {
     Query(Vec<u8>),
     Mutate(Vec<u8>),
     Status(Status),
+    Log { snapshot: Option<Snapshot>, entries: Vec<Entry> },
 }
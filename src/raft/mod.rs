@@ -1,11 +1,17 @@
+mod archive;
+mod history;
 mod log;
 mod message;
 mod node;
 mod server;
 mod state;
+mod transport;
 
-pub use self::log::{Entry, Index, Log};
+pub use self::log::{Entry, Index, Log, Snapshot};
+pub use archive::{Archive, ArchivedEntry};
+pub use history::{History, HistoryEntry, HistoryEvent};
 pub use message::{Address, Event, Message, Request, RequestID, Response};
 pub use node::{Node, NodeID, Status, Term};
 pub use server::Server;
 pub use state::{Driver, Instruction, State};
+pub use transport::{Tcp, Transport};
@@ -1,5 +1,7 @@
-use super::super::{Address, Event, Message};
-use super::{rand_election_timeout, Follower, Leader, Node, NodeID, RawNode, Role, Term, Ticks};
+use super::super::{Address, Event, Instruction, Message, Request};
+use super::{
+    rand_election_timeout, Follower, HistoryEvent, Leader, Node, NodeID, RawNode, Role, Term, Ticks,
+};
 use crate::error::{Error, Result};
 
 use ::log::{debug, info};
@@ -61,6 +63,7 @@ impl RawNode<Candidate> {
             assert_eq!(term, self.term, "Can't follow leader in different term");
             info!("Lost election, following leader {} in term {}", leader, term);
             let voted_for = Some(self.id); // by definition
+            self.record(HistoryEvent::Follower { leader: Some(leader) });
             Ok(self.into_role(Follower::new(Some(leader), voted_for)))
         } else {
             // We found a new term, but we don't necessarily know who the leader
@@ -69,6 +72,7 @@ impl RawNode<Candidate> {
             info!("Discovered new term {}", term);
             self.term = term;
             self.log.set_term(term, None)?;
+            self.record(HistoryEvent::Follower { leader: None });
             Ok(self.into_role(Follower::new(None, None)))
         }
     }
@@ -76,6 +80,7 @@ impl RawNode<Candidate> {
     /// Transitions the candidate to a leader. We won the election.
     pub(super) fn into_leader(self) -> Result<RawNode<Leader>> {
         info!("Won election for term {}, becoming leader", self.term);
+        self.record(HistoryEvent::Leader);
         let peers = self.peers.clone();
         let (last_index, _) = self.log.get_last_index();
         let mut node = self.into_role(Leader::new(peers, last_index));
@@ -120,21 +125,51 @@ impl RawNode<Candidate> {
 
             // If we receive a heartbeat or entries in this term, we lost the
             // election and have a new leader. Follow it and step the message.
-            Event::Heartbeat { .. } | Event::AppendEntries { .. } => {
+            Event::Heartbeat { .. }
+            | Event::AppendEntries { .. }
+            | Event::InstallSnapshot { .. } => {
                 return self.into_follower(msg.term, Some(msg.from.unwrap()))?.step(msg);
             }
 
-            // Abort any inbound client requests while candidate.
+            // Serve stale queries from the local state machine.
+            Event::ClientRequest { id, request: Request::StaleQuery { command, min_index } } => {
+                let (address, index) = (msg.from, min_index);
+                self.state_tx.send(Instruction::StaleQuery { id, address, command, index })?;
+            }
+
+            // Reject any other inbound client requests while candidate.
             Event::ClientRequest { id, .. } => {
-                self.send(msg.from, Event::ClientResponse { id, response: Err(Error::Abort) })?;
+                self.record(HistoryEvent::Reject { error: Error::NotLeader(None) });
+                let response = Err(Error::NotLeader(None));
+                self.send(msg.from, Event::ClientResponse { id, response })?;
+            }
+
+            // Client requests were rejected above, so there's nothing to cancel.
+            Event::ClientCancel { .. } => {}
+
+            // A previous leader may respond to requests we forwarded as a
+            // follower. These were aborted when we started campaigning, so
+            // ignore them.
+            Event::ClientResponse { .. } => {}
+
+            // Pass state checksums from peers to the state machine driver,
+            // which compares them with the local state.
+            Event::StateChecksum { index, checksum } => {
+                let peer = msg.from.unwrap();
+                self.state_tx.send(Instruction::Checksum { peer, index, checksum })?;
+            }
+
+            // The state machine snapshotted its state, compact the log.
+            Event::CompactLog { index, snapshot } => {
+                self.log.compact(index, snapshot)?;
+                self.record(HistoryEvent::Compact { index });
             }
 
-            // We're not a leader in this term, nor are we forwarding requests,
-            // so we shouldn't see these.
+            // We're not a leader in this term, so we shouldn't see these.
             Event::ConfirmLeader { .. }
             | Event::AcceptEntries { .. }
-            | Event::RejectEntries { .. }
-            | Event::ClientResponse { .. } => panic!("Received unexpected message {:?}", msg),
+            | Event::AcceptSnapshot { .. }
+            | Event::RejectEntries { .. } => panic!("Received unexpected message {:?}", msg),
         }
         Ok(self.into())
     }
@@ -159,6 +194,7 @@ impl RawNode<Candidate> {
         self.role.votes.insert(self.id); // vote for ourself
         self.term = term;
         self.log.set_term(term, Some(self.id))?;
+        self.record(HistoryEvent::Candidate);
 
         let (last_index, last_term) = self.log.get_last_index();
         self.send(Address::Broadcast, Event::SolicitVote { last_index, last_term })?;
@@ -168,7 +204,7 @@ impl RawNode<Candidate> {
 
 #[cfg(test)]
 mod tests {
-    use super::super::super::{Entry, Instruction, Log, Request};
+    use super::super::super::{Entry, History, Instruction, Log, Request};
     use super::super::tests::{assert_messages, assert_node};
     use super::*;
     use crate::storage;
@@ -197,6 +233,9 @@ mod tests {
             node_tx,
             state_tx,
             role: Candidate::new(),
+            paranoid: true,
+            catchup_rate: 0,
+            history: History::new(),
         };
         node.role.votes.insert(1);
         Ok((node, node_rx, state_rx))
@@ -325,7 +364,7 @@ mod tests {
     }
 
     #[test]
-    // ClientRequest returns Error::Abort.
+    // ClientRequest returns Error::NotLeader.
     fn step_clientrequest() -> Result<()> {
         let (candidate, mut node_rx, mut state_rx) = setup()?;
         let mut node = Node::Candidate(candidate);
@@ -343,7 +382,10 @@ mod tests {
                 from: Address::Node(1),
                 to: Address::Client,
                 term: 3,
-                event: Event::ClientResponse { id: vec![0x01], response: Err(Error::Abort) },
+                event: Event::ClientResponse {
+                    id: vec![0x01],
+                    response: Err(Error::NotLeader(None)),
+                },
             }],
         );
         assert_messages(&mut state_rx, vec![]);
@@ -1,8 +1,13 @@
-use super::super::{Address, Event, Instruction, Log, Message, RequestID, Response};
-use super::{rand_election_timeout, Candidate, Node, NodeID, RawNode, Role, Term, Ticks};
+use super::super::{
+    Address, Event, Index, Instruction, Log, Message, Request, RequestID, Response, Snapshot,
+};
+use super::{
+    rand_election_timeout, Candidate, History, HistoryEvent, Node, NodeID, RawNode, Role, Term,
+    Ticks,
+};
 use crate::error::{Error, Result};
 
-use ::log::{debug, info};
+use ::log::{debug, error, info};
 use std::collections::HashSet;
 use tokio::sync::mpsc;
 
@@ -10,7 +15,7 @@ use tokio::sync::mpsc;
 #[derive(Clone, Debug, PartialEq)]
 pub struct Follower {
     /// The leader, or None if just initialized.
-    leader: Option<NodeID>,
+    pub(super) leader: Option<NodeID>,
     /// The number of ticks since the last message from the leader.
     leader_seen: Ticks,
     /// The leader_seen timeout before triggering an election.
@@ -20,6 +25,24 @@ pub struct Follower {
     // Local client requests that have been forwarded to the leader. These are
     // aborted on leader/term changes.
     pub(super) forwarded: HashSet<RequestID>,
+    /// A snapshot being received from the leader, if any. This is kept across
+    /// leader changes, such that the transfer can resume where it left off.
+    snapshot: Option<PartialSnapshot>,
+}
+
+/// A partially received snapshot.
+#[derive(Clone, Debug, PartialEq)]
+struct PartialSnapshot {
+    /// The snapshot index.
+    index: Index,
+    /// The snapshot term.
+    term: Term,
+    /// The total snapshot size.
+    size: u64,
+    /// The expected snapshot checksum.
+    checksum: u32,
+    /// The data received so far.
+    data: Vec<u8>,
 }
 
 impl Follower {
@@ -31,6 +54,7 @@ impl Follower {
             leader_seen: 0,
             election_timeout: rand_election_timeout(),
             forwarded: HashSet::new(),
+            snapshot: None,
         }
     }
 }
@@ -48,7 +72,9 @@ impl RawNode<Follower> {
     ) -> Result<Self> {
         let (term, voted_for) = log.get_term()?;
         let role = Follower::new(None, voted_for);
-        Ok(Self { id, peers, term, log, node_tx, state_tx, role })
+        let paranoid = cfg!(debug_assertions);
+        let (catchup_rate, history) = (0, History::new());
+        Ok(Self { id, peers, term, log, node_tx, state_tx, role, paranoid, catchup_rate, history })
     }
 
     /// Asserts internal invariants.
@@ -77,7 +103,7 @@ impl RawNode<Follower> {
     /// leadership in a new term.
     pub(super) fn into_candidate(mut self) -> Result<RawNode<Candidate>> {
         // Abort any forwarded requests. These must be retried with new leader.
-        self.abort_forwarded()?;
+        self.abort_forwarded(None)?;
 
         let mut node = self.into_role(Candidate::new());
         node.campaign()?;
@@ -95,14 +121,16 @@ impl RawNode<Follower> {
         assert!(term >= self.term, "Term regression {} -> {}", self.term, term);
 
         // Abort any forwarded requests. These must be retried with new leader.
-        self.abort_forwarded()?;
+        self.abort_forwarded(leader)?;
 
         if let Some(leader) = leader {
             // We found a leader in the current term.
             assert_eq!(self.role.leader, None, "Already have leader in term");
             assert_eq!(term, self.term, "Can't follow leader in different term");
             info!("Following leader {} in term {}", leader, term);
-            self.role = Follower::new(Some(leader), self.role.voted_for);
+            let snapshot = self.role.snapshot.take();
+            self.role = Follower { snapshot, ..Follower::new(Some(leader), self.role.voted_for) };
+            self.record(HistoryEvent::Follower { leader: Some(leader) });
         } else {
             // We found a new term, but we don't necessarily know who the leader
             // is yet. We'll find out when we step a message from it.
@@ -110,7 +138,9 @@ impl RawNode<Follower> {
             info!("Discovered new term {}", term);
             self.term = term;
             self.log.set_term(term, None)?;
-            self.role = Follower::new(None, None);
+            let snapshot = self.role.snapshot.take();
+            self.role = Follower { snapshot, ..Follower::new(None, None) };
+            self.record(HistoryEvent::Follower { leader: None });
         }
         Ok(self)
     }
@@ -130,6 +160,14 @@ impl RawNode<Follower> {
         // follower in it and step the message. If the message is a Heartbeat or
         // AppendEntries from the leader, stepping it will follow the leader.
         if msg.term > self.term {
+            // Heartbeats and entries come from the new leader, so let clients
+            // of forwarded requests know about it.
+            if let Event::Heartbeat { .. }
+            | Event::AppendEntries { .. }
+            | Event::InstallSnapshot { .. } = msg.event
+            {
+                self.abort_forwarded(Some(msg.from.unwrap()))?;
+            }
             return self.into_follower(None, msg.term)?.step(msg);
         }
 
@@ -178,11 +216,78 @@ impl RawNode<Follower> {
                     debug!("Rejecting log entries at base {}", base_index);
                     self.send(msg.from, Event::RejectEntries)?
                 } else {
+                    // The leader may resend entries that were committed in
+                    // the meantime, which we already have, so skip them.
+                    let (commit_index, _) = self.log.get_commit_index();
+                    let entries = entries.into_iter().filter(|e| e.index > commit_index).collect();
                     let last_index = self.log.splice(entries)?;
+                    #[cfg(feature = "fault-injection")]
+                    crate::fault::crash_point(self.id, "follower.append")?;
                     self.send(msg.from, Event::AcceptEntries { last_index })?
                 }
             }
 
+            // Receive a snapshot chunk from the leader. Chunks must arrive in
+            // order, otherwise we tell the leader where to resume from. Once
+            // complete and verified, install the snapshot in the log and state.
+            Event::InstallSnapshot { index, term, size, checksum, offset, data } => {
+                let from = msg.from.unwrap();
+                match self.role.leader {
+                    Some(leader) => assert_eq!(from, leader, "Multiple leaders in term"),
+                    None => self = self.into_follower(Some(from), msg.term)?,
+                }
+
+                // We may already have the entries, e.g. if the leader resent a
+                // chunk after we installed the snapshot.
+                let (commit_index, _) = self.log.get_commit_index();
+                if index <= commit_index {
+                    self.send(msg.from, Event::AcceptEntries { last_index: commit_index })?;
+                    return Ok(self.into());
+                }
+
+                let partial = match &mut self.role.snapshot {
+                    Some(s)
+                        if (s.index, s.term, s.size, s.checksum)
+                            == (index, term, size, checksum) =>
+                    {
+                        s
+                    }
+                    partial => partial.insert(PartialSnapshot {
+                        index,
+                        term,
+                        size,
+                        checksum,
+                        data: Vec::new(),
+                    }),
+                };
+                let received = partial.data.len() as u64;
+                if offset == received && received + data.len() as u64 <= size {
+                    partial.data.extend(data);
+                }
+                let received = partial.data.len() as u64;
+                if received < size {
+                    self.send(msg.from, Event::AcceptSnapshot { index, offset: received })?;
+                    return Ok(self.into());
+                }
+
+                let snapshot = self.role.snapshot.take().unwrap();
+                let snapshot = Snapshot::new(index, term, snapshot.data);
+                if snapshot.checksum != checksum {
+                    error!(
+                        "Snapshot at index {} has checksum {:08x}, expected {:08x}",
+                        index, snapshot.checksum, checksum
+                    );
+                    self.send(msg.from, Event::AcceptSnapshot { index, offset: 0 })?;
+                    return Ok(self.into());
+                }
+                info!("Installing snapshot at index {} from leader {}", index, from);
+                let data = snapshot.data.clone();
+                self.log.install(snapshot)?;
+                self.record(HistoryEvent::InstallSnapshot { leader: from, index });
+                self.state_tx.send(Instruction::Restore { index, snapshot: data })?;
+                self.send(msg.from, Event::AcceptEntries { last_index: index })?;
+            }
+
             // A candidate in this term is requesting our vote.
             Event::SolicitVote { last_index, last_term } => {
                 let from = msg.from.unwrap();
@@ -198,10 +303,13 @@ impl RawNode<Follower> {
                 // our log.
                 let (log_index, log_term) = self.log.get_last_index();
                 if last_term > log_term || last_term == log_term && last_index >= log_index {
+                    // The vote must be persisted before granting it, otherwise
+                    // we could vote again in this term after a crash.
                     info!("Voting for {} in term {} election", from, self.term);
-                    self.send(Address::Node(from), Event::GrantVote)?;
                     self.log.set_term(self.term, Some(from))?;
+                    self.record(HistoryEvent::Vote { candidate: from });
                     self.role.voted_for = Some(from);
+                    self.send(Address::Node(from), Event::GrantVote)?;
                 }
             }
 
@@ -209,25 +317,40 @@ impl RawNode<Follower> {
             // different leader. Ignore it.
             Event::GrantVote => {}
 
+            // Serve stale queries from the local state machine.
+            Event::ClientRequest { id, request: Request::StaleQuery { command, min_index } } => {
+                let (address, index) = (msg.from, min_index);
+                self.state_tx.send(Instruction::StaleQuery { id, address, command, index })?;
+            }
+
+            // A peer forwarded a request to us while we were leader in this
+            // term, but we've since crashed and restarted as a follower. Reject
+            // it, the client must retry.
+            Event::ClientRequest { id, .. } if msg.from != Address::Client => {
+                self.record(HistoryEvent::Reject { error: Error::NotLeader(None) });
+                let response = Err(Error::NotLeader(None));
+                self.send(msg.from, Event::ClientResponse { id, response })?
+            }
+
             // Forward client requests to the leader, or abort them if there is
             // none (the client must retry).
             Event::ClientRequest { ref id, .. } => {
-                assert_eq!(msg.from, Address::Client, "Client request from non-client");
-
                 let id = id.clone();
                 if let Some(leader) = self.role.leader {
                     debug!("Forwarding request to leader {}: {:?}", leader, msg);
                     self.role.forwarded.insert(id);
                     self.send(Address::Node(leader), msg.event)?
                 } else {
-                    self.send(msg.from, Event::ClientResponse { id, response: Err(Error::Abort) })?
+                    self.record(HistoryEvent::Reject { error: Error::NotLeader(None) });
+                    let response = Err(Error::NotLeader(None));
+                    self.send(msg.from, Event::ClientResponse { id, response })?
                 }
             }
 
-            // Returns client responses for forwarded requests.
+            // Returns client responses for forwarded requests. A previous
+            // leader may respond after we've aborted its forwarded requests, in
+            // which case the response is ignored.
             Event::ClientResponse { id, mut response } => {
-                assert!(self.is_leader(&msg.from), "Client response from non-leader");
-
                 // TODO: Get rid of this field, it should be returned at the RPC
                 // server level instead.
                 if let Ok(Response::Status(ref mut status)) = response {
@@ -238,9 +361,29 @@ impl RawNode<Follower> {
                 }
             }
 
+            // Stop waiting for a cancelled forwarded request. The leader isn't
+            // told, and will send a response which is then ignored.
+            Event::ClientCancel { id } => {
+                self.role.forwarded.remove(&id);
+            }
+
+            // Pass state checksums from peers to the state machine driver,
+            // which compares them with the local state.
+            Event::StateChecksum { index, checksum } => {
+                let peer = msg.from.unwrap();
+                self.state_tx.send(Instruction::Checksum { peer, index, checksum })?;
+            }
+
+            // The state machine snapshotted its state, compact the log.
+            Event::CompactLog { index, snapshot } => {
+                self.log.compact(index, snapshot)?;
+                self.record(HistoryEvent::Compact { index });
+            }
+
             // We're not a leader nor candidate in this term, so we shoudn't see these.
             Event::ConfirmLeader { .. }
             | Event::AcceptEntries { .. }
+            | Event::AcceptSnapshot { .. }
             | Event::RejectEntries { .. } => panic!("Received unexpected message {:?}", msg),
         };
         Ok(self.into())
@@ -257,11 +400,12 @@ impl RawNode<Follower> {
         Ok(self.into())
     }
 
-    /// Aborts all forwarded requests.
-    fn abort_forwarded(&mut self) -> Result<()> {
+    /// Aborts all forwarded requests, with the new leader if known.
+    fn abort_forwarded(&mut self, leader: Option<NodeID>) -> Result<()> {
         for id in std::mem::take(&mut self.role.forwarded) {
             debug!("Aborting forwarded request {:x?}", id);
-            self.send(Address::Client, Event::ClientResponse { id, response: Err(Error::Abort) })?;
+            let response = Err(Error::NotLeader(leader));
+            self.send(Address::Client, Event::ClientResponse { id, response })?;
         }
         Ok(())
     }
@@ -317,6 +461,9 @@ pub mod tests {
             node_tx,
             state_tx,
             role: Follower::new(Some(2), None),
+            paranoid: true,
+            catchup_rate: 0,
+            history: History::new(),
         };
         Ok((node, node_rx, state_rx))
     }
@@ -584,6 +731,25 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    // StateChecksum without a term is passed to the state machine driver.
+    fn step_statechecksum() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let mut node = follower.step(Message {
+            from: Address::Node(3),
+            to: Address::Broadcast,
+            term: 0,
+            event: Event::StateChecksum { index: 2, checksum: 0xabcd },
+        })?;
+        assert_node(&mut node).is_follower().term(3).leader(Some(2));
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(
+            &mut state_rx,
+            vec![Instruction::Checksum { peer: 3, index: 2, checksum: 0xabcd }],
+        );
+        Ok(())
+    }
+
     #[test]
     // SolicitVote is rejected if last_term is outdated.
     fn step_solicitvote_last_index_outdated() -> Result<()> {
@@ -636,6 +802,9 @@ pub mod tests {
             node_tx,
             state_tx,
             role: Follower::new(Some(2), None),
+            paranoid: true,
+            catchup_rate: 0,
+            history: History::new(),
         };
 
         let mut node = follower.step(Message {
@@ -741,6 +910,43 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    // AppendEntries skips entries that are already committed
+    fn step_appendentries_committed() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let mut node = follower.step(Message {
+            from: Address::Node(2),
+            to: Address::Node(1),
+            term: 3,
+            event: Event::AppendEntries {
+                base_index: 1,
+                base_term: 1,
+                entries: vec![
+                    Entry { index: 2, term: 1, command: Some(vec![0x02]) },
+                    Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                    Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+                ],
+            },
+        })?;
+        assert_node(&mut node).is_follower().term(3).committed(2).entries(vec![
+            Entry { index: 1, term: 1, command: Some(vec![0x01]) },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]) },
+            Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+            Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+        ]);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Node(1),
+                to: Address::Node(2),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 4 },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
     #[test]
     // AppendEntries replaces conflicting entries
     fn step_appendentries_replace() -> Result<()> {
@@ -877,6 +1083,83 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    // InstallSnapshot accepts chunks in order, verifies the checksum, and
+    // replaces the log and state with the snapshot once complete.
+    fn step_installsnapshot() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = follower.into();
+        let snapshot = Snapshot::new(5, 3, vec![0x0a, 0x0b, 0x0c]);
+        let chunk = |offset: u64, data: &[u8], checksum: u32| Message {
+            from: Address::Node(2),
+            to: Address::Node(1),
+            term: 3,
+            event: Event::InstallSnapshot {
+                index: 5,
+                term: 3,
+                size: 3,
+                checksum,
+                offset,
+                data: data.to_vec(),
+            },
+        };
+        let accept = |offset: u64| Message {
+            from: Address::Node(1),
+            to: Address::Node(2),
+            term: 3,
+            event: Event::AcceptSnapshot { index: 5, offset },
+        };
+
+        // Out-of-order chunks are ignored, and the received offset returned.
+        node = node.step(chunk(2, &[0x0c], snapshot.checksum))?;
+        assert_messages(&mut node_rx, vec![accept(0)]);
+        node = node.step(chunk(0, &[0x0a, 0x0b], snapshot.checksum))?;
+        assert_messages(&mut node_rx, vec![accept(2)]);
+
+        // A snapshot with a checksum mismatch is discarded.
+        node = node.step(chunk(2, &[0x0c], snapshot.checksum + 1))?;
+        assert_messages(&mut node_rx, vec![accept(0)]);
+        node = node.step(chunk(0, &[0x0a, 0x0b, 0x0c], snapshot.checksum + 1))?;
+        assert_messages(&mut node_rx, vec![accept(0)]);
+        assert_node(&mut node).is_follower().term(3).committed(2).last(3);
+        assert_messages(&mut state_rx, vec![]);
+
+        // A complete snapshot replaces the log and state.
+        node = node.step(chunk(0, &[0x0a, 0x0b], snapshot.checksum))?;
+        node = node.step(chunk(2, &[0x0c], snapshot.checksum))?;
+        assert_node(&mut node).is_follower().term(3).leader(Some(2)).committed(5).last(5);
+        assert_messages(
+            &mut node_rx,
+            vec![
+                accept(2),
+                Message {
+                    from: Address::Node(1),
+                    to: Address::Node(2),
+                    term: 3,
+                    event: Event::AcceptEntries { last_index: 5 },
+                },
+            ],
+        );
+        assert_messages(
+            &mut state_rx,
+            vec![Instruction::Restore { index: 5, snapshot: vec![0x0a, 0x0b, 0x0c] }],
+        );
+
+        // Chunks resent after installation are acknowledged as entries.
+        node = node.step(chunk(2, &[0x0c], snapshot.checksum))?;
+        assert_node(&mut node).is_follower().committed(5).last(5);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Node(1),
+                to: Address::Node(2),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 5 },
+            }],
+        );
+        Ok(())
+    }
+
     #[test]
     // ClientRequest is forwarded, as is the response.
     fn step_clientrequest_clientresponse() -> Result<()> {
@@ -931,7 +1214,7 @@ pub mod tests {
     }
 
     #[test]
-    // ClientRequest returns Error::Abort when there is no leader.
+    // ClientRequest returns Error::NotLeader when there is no leader.
     fn step_clientrequest_no_leader() -> Result<()> {
         let (mut follower, mut node_rx, mut state_rx) = setup()?;
         follower.role = Follower::new(None, None);
@@ -950,13 +1233,77 @@ pub mod tests {
                 from: Address::Node(1),
                 to: Address::Client,
                 term: 3,
-                event: Event::ClientResponse { id: vec![0x01], response: Err(Error::Abort) },
+                event: Event::ClientResponse {
+                    id: vec![0x01],
+                    response: Err(Error::NotLeader(None)),
+                },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // ClientRequest forwarded by a peer, e.g. to a leader that restarted as a
+    // follower in the same term, returns Error::NotLeader to the peer.
+    fn step_clientrequest_forwarded() -> Result<()> {
+        let (mut follower, mut node_rx, mut state_rx) = setup()?;
+        follower.role = Follower::new(None, None);
+        let mut node = Node::Follower(follower);
+
+        node = node.step(Message {
+            from: Address::Node(2),
+            to: Address::Node(1),
+            term: 3,
+            event: Event::ClientRequest { id: vec![0x01], request: Request::Mutate(vec![0xaf]) },
+        })?;
+        assert_node(&mut node).is_follower().term(3).leader(None).forwarded(vec![]);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Node(1),
+                to: Address::Node(2),
+                term: 3,
+                event: Event::ClientResponse {
+                    id: vec![0x01],
+                    response: Err(Error::NotLeader(None)),
+                },
             }],
         );
         assert_messages(&mut state_rx, vec![]);
         Ok(())
     }
 
+    #[test]
+    // Stale queries are served by the local state machine, not the leader.
+    fn step_clientrequest_stale() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let mut node = Node::Follower(follower);
+
+        node = node.step(Message {
+            from: Address::Client,
+            to: Address::Node(1),
+            term: 0,
+            event: Event::ClientRequest {
+                id: vec![0x01],
+                request: Request::StaleQuery { command: vec![0xaf], min_index: 2 },
+            },
+        })?;
+        assert_node(&mut node).is_follower().term(3).leader(Some(2)).forwarded(vec![]);
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(
+            &mut state_rx,
+            vec![Instruction::StaleQuery {
+                id: vec![0x01],
+                address: Address::Client,
+                command: vec![0xaf],
+                index: 2,
+            }],
+        );
+
+        Ok(())
+    }
+
     // ClientRequest is forwarded, but aborted when a new leader appears.
     #[test]
     fn step_clientrequest_aborted() -> Result<()> {
@@ -984,7 +1331,8 @@ pub mod tests {
         );
         assert_messages(&mut state_rx, vec![]);
 
-        // When a new leader appears, the proxied request is aborted.
+        // When a new leader appears, the proxied request is aborted with the
+        // new leader as a hint.
         node = node.step(Message {
             from: Address::Node(3),
             to: Address::Node(1),
@@ -999,7 +1347,10 @@ pub mod tests {
                     from: Address::Node(1),
                     to: Address::Client,
                     term: 3,
-                    event: Event::ClientResponse { id: vec![0x01], response: Err(Error::Abort) },
+                    event: Event::ClientResponse {
+                        id: vec![0x01],
+                        response: Err(Error::NotLeader(Some(3))),
+                    },
                 },
                 Message {
                     from: Address::Node(1),
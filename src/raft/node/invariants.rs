@@ -0,0 +1,221 @@
+use super::{Node, NodeID, RawNode, Role, Term};
+use crate::error::Result;
+use crate::raft::{Address, Event, Index, Message};
+
+use ::log::error;
+
+/// Node state captured before a transition, to check invariants across it
+/// in paranoid mode.
+pub struct Checkpoint {
+    term: Term,
+    voted_for: Option<NodeID>,
+    commit_index: Index,
+    commit_term: Term,
+    last_index: Index,
+    last_term: Term,
+    leader: bool,
+}
+
+impl Node {
+    /// Captures the node state before a transition, if paranoid mode is enabled.
+    pub(super) fn checkpoint(&mut self) -> Result<Option<Checkpoint>> {
+        let leader = matches!(self, Node::Leader(_));
+        match self {
+            Node::Candidate(n) => n.checkpoint(leader),
+            Node::Follower(n) => n.checkpoint(leader),
+            Node::Leader(n) => n.checkpoint(leader),
+        }
+    }
+
+    /// Checks invariants across a transition from the given checkpoint,
+    /// caused by the given message (if any).
+    pub(super) fn check(&mut self, before: &Checkpoint, msg: Option<&Message>) -> Result<()> {
+        let leader = matches!(self, Node::Leader(_));
+        match self {
+            Node::Candidate(n) => n.check(before, leader, msg),
+            Node::Follower(n) => n.check(before, leader, msg),
+            Node::Leader(n) => n.check(before, leader, msg),
+        }
+    }
+}
+
+impl<R: Role> RawNode<R> {
+    fn checkpoint(&mut self, leader: bool) -> Result<Option<Checkpoint>> {
+        if !self.paranoid {
+            return Ok(None);
+        }
+        let (_, voted_for) = self.log.get_term()?;
+        let (commit_index, commit_term) = self.log.get_commit_index();
+        let (last_index, last_term) = self.log.get_last_index();
+        Ok(Some(Checkpoint {
+            term: self.term,
+            voted_for,
+            commit_index,
+            commit_term,
+            last_index,
+            last_term,
+            leader,
+        }))
+    }
+
+    fn check(&mut self, before: &Checkpoint, leader: bool, msg: Option<&Message>) -> Result<()> {
+        let (term, voted_for) = self.log.get_term()?;
+        let (commit_index, _) = self.log.get_commit_index();
+        let (last_index, last_term) = self.log.get_last_index();
+
+        // The term is persisted, and never regresses.
+        if term != self.term {
+            self.violation(format!("term {} does not match persisted term {}", self.term, term));
+        }
+        if self.term < before.term {
+            self.violation(format!("term regression {} -> {}", before.term, self.term));
+        }
+
+        // A node can only vote once in a term.
+        if self.term == before.term && before.voted_for.is_some() && voted_for != before.voted_for {
+            self.violation(format!(
+                "vote changed from {:?} to {:?} in term {}",
+                before.voted_for, voted_for, self.term
+            ));
+        }
+
+        // The commit index never regresses, and committed entries never change.
+        if commit_index < before.commit_index {
+            self.violation(format!(
+                "commit index regression {} -> {}",
+                before.commit_index, commit_index
+            ));
+        }
+        if !self.log.has(before.commit_index, before.commit_term)? {
+            self.violation(format!(
+                "committed entry {}@{} was replaced",
+                before.commit_index, before.commit_term
+            ));
+        }
+        if commit_index > last_index {
+            self.violation(format!(
+                "commit index {} beyond last index {}",
+                commit_index, last_index
+            ));
+        }
+        if last_term > self.term {
+            self.violation(format!("last term {} beyond current term {}", last_term, self.term));
+        }
+
+        // A leader never replaces or removes entries in its log.
+        if before.leader
+            && leader
+            && before.term == self.term
+            && !(last_index >= before.last_index
+                && self.log.has(before.last_index, before.last_term)?)
+        {
+            self.violation(format!(
+                "leader replaced entry {}@{}",
+                before.last_index, before.last_term
+            ));
+        }
+
+        // Log matching: once a follower's log contains the base entry of
+        // entries from the leader, it must also contain the entries.
+        if let Some(Message {
+            term,
+            event: Event::AppendEntries { base_index, base_term, entries },
+            ..
+        }) = msg
+        {
+            if *term == self.term && !leader && self.log.has(*base_index, *base_term)? {
+                for entry in entries {
+                    if !self.log.has(entry.index, entry.term)? {
+                        self.violation(format!(
+                            "entry {}@{} at base {}@{} not in log",
+                            entry.index, entry.term, base_index, base_term
+                        ));
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that a vote is persisted before it's sent, in paranoid mode,
+    /// since a node could otherwise vote again in the term after a crash.
+    pub(super) fn check_send(&mut self, to: Address, event: &Event) -> Result<()> {
+        let vote = match (event, to) {
+            _ if !self.paranoid => return Ok(()),
+            (Event::GrantVote, Address::Node(to)) => to,
+            (Event::SolicitVote { .. }, _) => self.id,
+            _ => return Ok(()),
+        };
+        let (term, voted_for) = self.log.get_term()?;
+        if (term, voted_for) != (self.term, Some(vote)) {
+            self.violation(format!(
+                "sent vote for {} in term {} before persisting it, persisted {:?} in term {}",
+                vote, self.term, voted_for, term
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reports an invariant violation. This panics in debug builds, e.g. in
+    /// tests, to catch protocol bugs early, but only logs an error in release
+    /// builds, where the node keeps running.
+    fn violation(&self, message: String) {
+        if cfg!(debug_assertions) {
+            panic!("Raft invariant violation on node {}: {}", self.id, message);
+        }
+        error!("Raft invariant violation on node {}: {}", self.id, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::setup_rolenode;
+    use super::*;
+    use crate::raft::Entry;
+
+    #[test]
+    #[should_panic(expected = "sent vote for 2 in term 1 before persisting it")]
+    fn send_unpersisted_vote() {
+        let (mut node, _rx) = setup_rolenode().unwrap();
+        node.send(Address::Node(2), Event::GrantVote).unwrap();
+    }
+
+    #[test]
+    fn send_unpersisted_vote_not_paranoid() -> Result<()> {
+        let (mut node, _rx) = setup_rolenode()?;
+        node.paranoid = false;
+        node.send(Address::Node(2), Event::GrantVote)
+    }
+
+    #[test]
+    #[should_panic(expected = "commit index regression 2 -> 0")]
+    fn check_commit_regression() {
+        let (mut node, _rx) = setup_rolenode().unwrap();
+        node.log.set_term(1, None).unwrap();
+        let mut node: Node = node.into();
+        let mut before = node.checkpoint().unwrap().unwrap();
+        (before.commit_index, before.commit_term) = (2, 1);
+        node.check(&before, None).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "entry 1@1 at base 0@0 not in log")]
+    fn check_log_matching() {
+        let (mut node, _rx) = setup_rolenode().unwrap();
+        node.log.set_term(1, None).unwrap();
+        let mut node: Node = node.into();
+        let before = node.checkpoint().unwrap().unwrap();
+        let msg = Message {
+            from: Address::Node(2),
+            to: Address::Node(1),
+            term: 1,
+            event: Event::AppendEntries {
+                base_index: 0,
+                base_term: 0,
+                entries: vec![Entry { index: 1, term: 1, command: None }],
+            },
+        };
+        node.check(&before, Some(&msg)).unwrap();
+    }
+}
@@ -1,10 +1,23 @@
-use super::super::{Address, Event, Index, Instruction, Message, Request, Status};
-use super::{Follower, Node, NodeID, RawNode, Role, Term, Ticks, HEARTBEAT_INTERVAL};
+use super::super::{Address, Event, Index, Instruction, Message, Request, Response, Status};
+use super::{Follower, HistoryEvent, Node, NodeID, RawNode, Role, Term, Ticks, HEARTBEAT_INTERVAL};
 use crate::error::Result;
 
 use ::log::{debug, info};
 use std::collections::{HashMap, HashSet};
 
+/// The maximum size of snapshot chunks sent to followers, in bytes. This keeps
+/// messages well below the maximum network frame size.
+const SNAPSHOT_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// The maximum number of unacknowledged snapshot bytes in flight to a
+/// follower. This throttles snapshot transfers, such that they don't fill up
+/// the peer's send buffer and cause other messages to be dropped.
+const SNAPSHOT_WINDOW: u64 = 8 * SNAPSHOT_CHUNK_SIZE;
+
+/// The number of pending entries beyond which a follower is considered to be
+/// catching up, and replication to it is throttled by the catch-up rate.
+const CATCHUP_LAG: Index = 100;
+
 /// Peer replication progress.
 #[derive(Clone, Debug, PartialEq)]
 struct Progress {
@@ -12,6 +25,25 @@ struct Progress {
     next: Index,
     /// The last index known to be replicated to the peer.
     last: Index,
+    /// A snapshot transfer to the peer, if it needs compacted entries.
+    snapshot: Option<Transfer>,
+    /// The remaining catch-up bytes the peer can be sent this tick.
+    budget: u64,
+    /// The last index of an unacknowledged catch-up batch sent to the peer.
+    inflight: Option<Index>,
+}
+
+/// A snapshot transfer to a peer.
+#[derive(Clone, Debug, PartialEq)]
+struct Transfer {
+    /// The snapshot index.
+    index: Index,
+    /// The number of bytes sent.
+    sent: u64,
+    /// The number of bytes acknowledged by the peer.
+    acked: u64,
+    /// The number of bytes acknowledged as of the last heartbeat.
+    heartbeat_acked: u64,
 }
 
 // A leader serves requests and replicates the log to followers.
@@ -27,7 +59,10 @@ impl Leader {
     /// Creates a new leader role.
     pub fn new(peers: HashSet<NodeID>, last_index: Index) -> Self {
         let next = last_index + 1;
-        let progress = peers.into_iter().map(|p| (p, Progress { next, last: 0 })).collect();
+        let progress = peers
+            .into_iter()
+            .map(|p| (p, Progress { next, last: 0, snapshot: None, budget: 0, inflight: None }))
+            .collect();
         Self { progress, since_heartbeat: 0 }
     }
 }
@@ -55,6 +90,7 @@ impl RawNode<Leader> {
         info!("Discovered new term {}", term);
         self.term = term;
         self.log.set_term(term, None)?;
+        self.record(HistoryEvent::Follower { leader: None });
         self.state_tx.send(Instruction::Abort)?;
         Ok(self.into_role(Follower::new(None, None)))
     }
@@ -79,7 +115,9 @@ impl RawNode<Leader> {
 
         match msg.event {
             // There can't be two leaders in the same term.
-            Event::Heartbeat { .. } | Event::AppendEntries { .. } => {
+            Event::Heartbeat { .. }
+            | Event::AppendEntries { .. }
+            | Event::InstallSnapshot { .. } => {
                 panic!("Saw other leader {} in term {}", msg.from.unwrap(), msg.term);
             }
 
@@ -108,11 +146,47 @@ impl RawNode<Leader> {
 
                 let from = msg.from.unwrap();
                 let progress = self.role.progress.get_mut(&from).unwrap();
+                let caught_up_batch = progress.inflight.is_some_and(|i| last_index >= i);
+                if caught_up_batch {
+                    progress.inflight = None;
+                }
                 if last_index > progress.last {
                     progress.last = last_index;
                     progress.next = last_index + 1;
                     self.maybe_commit()?;
                 }
+
+                // If this acknowledges a catch-up batch, send the next one.
+                let progress = &self.role.progress[&from];
+                if caught_up_batch && progress.next <= self.log.get_last_index().0 {
+                    self.send_log(from)?;
+                }
+
+                // If this confirms an installed snapshot, finish the transfer
+                // and replicate the entries after it.
+                let progress = self.role.progress.get_mut(&from).unwrap();
+                if progress.snapshot.as_ref().is_some_and(|t| last_index >= t.index) {
+                    debug!("Finished snapshot transfer to {}", from);
+                    progress.snapshot = None;
+                    if progress.next <= self.log.get_last_index().0 {
+                        self.send_log(from)?;
+                    }
+                }
+            }
+
+            // A follower received snapshot chunks up to the given offset. Send
+            // it more chunks, or resend them from the offset if it lost some.
+            Event::AcceptSnapshot { index, offset } => {
+                let from = msg.from.unwrap();
+                let progress = self.role.progress.get_mut(&from).unwrap();
+                if let Some(transfer) = progress.snapshot.as_mut().filter(|t| t.index == index) {
+                    if offset < transfer.acked {
+                        transfer.sent = offset;
+                    }
+                    transfer.acked = offset;
+                    transfer.sent = transfer.sent.max(offset);
+                    self.send_snapshot(from)?;
+                }
             }
 
             // A follower rejected log entries we sent it, typically because it
@@ -120,11 +194,19 @@ impl RawNode<Leader> {
             // the previous entry.
             //
             // This linear probing, as described in the Raft paper, can be very
-            // slow with long divergent logs, but we keep it simple.
+            // slow with long divergent logs, but we keep it simple. However, if
+            // the follower hasn't accepted any entries after our snapshot, we
+            // skip straight to the snapshot, such that e.g. a new node with an
+            // empty log quickly receives it.
             Event::RejectEntries => {
                 let from = msg.from.unwrap();
+                let (snapshot_index, _) = self.log.get_snapshot_index();
                 self.role.progress.entry(from).and_modify(|p| {
-                    if p.next > 1 {
+                    p.inflight = None;
+                    if snapshot_index > 0 && p.last <= snapshot_index && p.next > snapshot_index + 1
+                    {
+                        p.next = snapshot_index + 1
+                    } else if p.next > 1 {
                         p.next -= 1
                     }
                 });
@@ -174,16 +256,49 @@ impl RawNode<Leader> {
                     apply_index: 0,
                     storage: engine_status.name.clone(),
                     storage_size: engine_status.size,
+                    diverged: false,
+                    checksum_mismatches: 0,
+                    history: Vec::new(),
                 });
                 self.state_tx.send(Instruction::Status { id, address: msg.from, status })?
             }
 
+            Event::ClientRequest { id, request: Request::StaleQuery { command, min_index } } => {
+                let (address, index) = (msg.from, min_index);
+                self.state_tx.send(Instruction::StaleQuery { id, address, command, index })?;
+            }
+
+            Event::ClientRequest { id, request: Request::ReadLog { from, limit } } => {
+                let response = self.read_log(from, limit);
+                self.send(msg.from, Event::ClientResponse { id, response })?;
+            }
+
+            // Drop any pending response for a cancelled client request.
+            Event::ClientCancel { id } => {
+                self.state_tx.send(Instruction::Cancel { id, address: msg.from })?;
+            }
+
             // Votes can come in after we won the election, ignore them.
             Event::SolicitVote { .. } | Event::GrantVote => {}
 
-            // Leaders never proxy client requests, so we don't expect to see
-            // responses from other nodes.
-            Event::ClientResponse { .. } => panic!("Unexpected message {:?}", msg),
+            // Pass state checksums from peers to the state machine driver,
+            // which compares them with the local state.
+            Event::StateChecksum { index, checksum } => {
+                let peer = msg.from.unwrap();
+                self.state_tx.send(Instruction::Checksum { peer, index, checksum })?;
+            }
+
+            // The state machine snapshotted its state, compact the log.
+            Event::CompactLog { index, snapshot } => {
+                self.log.compact(index, snapshot)?;
+                self.record(HistoryEvent::Compact { index });
+            }
+
+            // Leaders never proxy client requests, but a previous leader may
+            // respond to requests we forwarded as a follower. These were
+            // aborted when we stopped following it, so ignore them. The state
+            // driver doesn't know its term, so they aren't dropped above.
+            Event::ClientResponse { .. } => {}
         }
 
         Ok(self.into())
@@ -196,8 +311,13 @@ impl RawNode<Leader> {
         self.role.since_heartbeat += 1;
         if self.role.since_heartbeat >= HEARTBEAT_INTERVAL {
             self.heartbeat()?;
+            self.resume_snapshots()?;
             self.role.since_heartbeat = 0;
+            // Resend catch-up batches that weren't acknowledged, e.g. because
+            // they were dropped.
+            self.role.progress.values_mut().for_each(|p| p.inflight = None);
         }
+        self.catch_up()?;
         Ok(self.into())
     }
 
@@ -215,12 +335,34 @@ impl RawNode<Leader> {
     /// and applied to the state machine.
     pub(super) fn propose(&mut self, command: Option<Vec<u8>>) -> Result<Index> {
         let index = self.log.append(self.term, command)?;
+        #[cfg(feature = "fault-injection")]
+        crate::fault::crash_point(self.id, "leader.propose")?;
         for peer in self.peers.clone() {
             self.send_log(peer)?;
         }
         Ok(index)
     }
 
+    /// Reads up to limit committed entries from the given index, preceded by the
+    /// snapshot if the index has been compacted. Only committed entries are
+    /// returned, since uncommitted ones may be replaced by a later leader.
+    fn read_log(&mut self, from: Index, limit: usize) -> Result<Response> {
+        let (snapshot_index, _) = self.log.get_snapshot_index();
+        let snapshot = match from <= snapshot_index {
+            true => self.log.get_snapshot().cloned(),
+            false => None,
+        };
+        let from = from.max(snapshot_index + 1);
+        let (commit_index, _) = self.log.get_commit_index();
+        let mut entries = Vec::new();
+        if from <= commit_index {
+            for entry in self.log.scan(from..=commit_index)?.take(limit) {
+                entries.push(entry?);
+            }
+        }
+        Ok(Response::Log { snapshot, entries })
+    }
+
     /// Commits any new log entries that have been replicated to a quorum,
     /// and schedules them for state machine application.
     fn maybe_commit(&mut self) -> Result<Index> {
@@ -248,6 +390,9 @@ impl RawNode<Leader> {
             prev_commit_index,
             commit_index
         );
+        if commit_index == prev_commit_index {
+            return Ok(prev_commit_index);
+        }
 
         // We can only safely commit up to an entry from our own term, see
         // figure 8 in Raft paper.
@@ -260,6 +405,8 @@ impl RawNode<Leader> {
         // Commit and apply the new entries.
         if commit_index > prev_commit_index {
             self.log.commit(commit_index)?;
+            #[cfg(feature = "fault-injection")]
+            crate::fault::crash_point(self.id, "leader.commit")?;
             // TODO: Move application elsewhere, but needs access to applied index.
             let mut scan = self.log.scan((prev_commit_index + 1)..=commit_index)?;
             while let Some(entry) = scan.next().transpose()? {
@@ -269,29 +416,156 @@ impl RawNode<Leader> {
         Ok(commit_index)
     }
 
-    /// Sends pending log entries to a peer.
+    /// Returns whether a peer is catching up, i.e. whether it needs a
+    /// snapshot or lags more than CATCHUP_LAG entries behind, and replication
+    /// to it is throttled by the catch-up rate.
+    fn is_catching_up(&self, peer: NodeID) -> bool {
+        let progress = &self.role.progress[&peer];
+        let pending = (self.log.get_last_index().0 + 1).saturating_sub(progress.next);
+        self.catchup_rate > 0 && (progress.snapshot.is_some() || pending > CATCHUP_LAG)
+    }
+
+    /// Refills the catch-up budgets of peers that are catching up, splitting
+    /// the catch-up rate between them, and continues replicating to them.
+    fn catch_up(&mut self) -> Result<()> {
+        let mut peers: Vec<NodeID> =
+            self.peers.iter().copied().filter(|p| self.is_catching_up(*p)).collect();
+        if peers.is_empty() {
+            return Ok(());
+        }
+        peers.sort();
+        let budget = (self.catchup_rate / peers.len() as u64).max(1);
+        for peer in peers {
+            let progress = self.role.progress.get_mut(&peer).unwrap();
+            progress.budget = budget;
+            match progress.snapshot {
+                Some(_) => self.send_snapshot(peer)?,
+                None if progress.inflight.is_none() => self.send_log(peer)?,
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends pending log entries to a peer. If it needs entries that have been
+    /// compacted, send it the snapshot instead.
+    ///
+    /// Peers that are catching up are instead sent a single batch of entries
+    /// at a time, limited by their catch-up budget (but at least one entry).
+    /// The next batch is sent once it's acknowledged, or on the next tick.
     fn send_log(&mut self, peer: NodeID) -> Result<()> {
-        let (base_index, base_term) = match self.role.progress.get(&peer) {
-            Some(Progress { next, .. }) if *next > 1 => match self.log.get(next - 1)? {
+        let Some(&Progress { next, budget, inflight, .. }) = self.role.progress.get(&peer) else {
+            panic!("Unknown peer {}", peer)
+        };
+        let (snapshot_index, snapshot_term) = self.log.get_snapshot_index();
+        let (base_index, base_term) = match next - 1 {
+            base if base < snapshot_index => return self.send_snapshot(peer),
+            base if base == snapshot_index => (snapshot_index, snapshot_term),
+            base => match self.log.get(base)? {
                 Some(entry) => (entry.index, entry.term),
-                None => panic!("Missing base entry {}", next - 1),
+                None => panic!("Missing base entry {}", base),
             },
-            Some(_) => (0, 0),
-            None => panic!("Unknown peer {}", peer),
         };
 
-        let entries = self.log.scan((base_index + 1)..)?.collect::<Result<Vec<_>>>()?;
+        if !self.is_catching_up(peer) {
+            let entries = self.log.scan((base_index + 1)..)?.collect::<Result<Vec<_>>>()?;
+            debug!("Replicating {} entries at base {} to {}", entries.len(), base_index, peer);
+            let event = Event::AppendEntries { base_index, base_term, entries };
+            return self.send(Address::Node(peer), event);
+        }
+
+        if inflight.is_some() || budget == 0 {
+            return Ok(());
+        }
+        let (mut entries, mut size) = (Vec::new(), 0);
+        for entry in self.log.scan((base_index + 1)..)? {
+            if size >= budget {
+                break;
+            }
+            let entry = entry?;
+            size += entry.command.as_ref().map_or(0, |c| c.len() as u64);
+            entries.push(entry);
+        }
+        let progress = self.role.progress.get_mut(&peer).unwrap();
+        progress.budget = budget.saturating_sub(size);
+        progress.inflight = entries.last().map(|e| e.index);
         debug!("Replicating {} entries at base {} to {}", entries.len(), base_index, peer);
         self.send(Address::Node(peer), Event::AppendEntries { base_index, base_term, entries })?;
         Ok(())
     }
+
+    /// Sends snapshot chunks to a peer, as long as the unacknowledged bytes fit
+    /// in the window. Starts a new transfer if there is none, or if the log has
+    /// been compacted into a newer snapshot since.
+    fn send_snapshot(&mut self, peer: NodeID) -> Result<()> {
+        let Some(snapshot) = self.log.get_snapshot() else { panic!("No snapshot to send") };
+        let progress = self.role.progress.get_mut(&peer).unwrap();
+        let transfer = match &mut progress.snapshot {
+            Some(transfer) if transfer.index == snapshot.index => transfer,
+            transfer => {
+                info!("Sending snapshot at index {} to {}", snapshot.index, peer);
+                let index = snapshot.index;
+                let event = HistoryEvent::SendSnapshot { peer, index };
+                self.history.record(self.term, event);
+                transfer.insert(Transfer { index, sent: 0, acked: 0, heartbeat_acked: 0 })
+            }
+        };
+
+        // Chunks are also limited by the catch-up budget, if any.
+        let throttled = self.catchup_rate > 0;
+        let size = snapshot.data.len() as u64;
+        let mut chunks = Vec::new();
+        while transfer.sent < size
+            && transfer.sent - transfer.acked < SNAPSHOT_WINDOW
+            && (!throttled || progress.budget > 0)
+        {
+            let (offset, end) = (transfer.sent, size.min(transfer.sent + SNAPSHOT_CHUNK_SIZE));
+            if throttled {
+                progress.budget = progress.budget.saturating_sub(end - offset);
+            }
+            chunks.push(Event::InstallSnapshot {
+                index: snapshot.index,
+                term: snapshot.term,
+                size,
+                checksum: snapshot.checksum,
+                offset,
+                data: snapshot.data[offset as usize..end as usize].to_vec(),
+            });
+            transfer.sent = end;
+        }
+        for chunk in chunks {
+            self.send(Address::Node(peer), chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Resumes snapshot transfers that haven't made progress since the last
+    /// heartbeat, e.g. because chunks were dropped, by resending the chunks
+    /// after the last acknowledged offset.
+    fn resume_snapshots(&mut self) -> Result<()> {
+        let mut stalled = Vec::new();
+        for (peer, progress) in self.role.progress.iter_mut() {
+            if let Some(transfer) = progress.snapshot.as_mut() {
+                if transfer.acked == transfer.heartbeat_acked {
+                    transfer.sent = transfer.acked;
+                    stalled.push(*peer);
+                }
+                transfer.heartbeat_acked = transfer.acked;
+            }
+        }
+        for peer in stalled {
+            self.send_snapshot(peer)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::super::super::{Entry, Log};
+    use super::super::super::{Entry, History, Log, Snapshot};
     use super::super::tests::{assert_messages, assert_node};
     use super::*;
+    use crate::error::Error;
     use crate::storage;
     use pretty_assertions::assert_eq;
     use tokio::sync::mpsc;
@@ -322,6 +596,9 @@ mod tests {
             log,
             node_tx,
             state_tx,
+            paranoid: true,
+            catchup_rate: 0,
+            history: History::new(),
         };
         Ok((node, node_rx, state_rx))
     }
@@ -583,6 +860,229 @@ mod tests {
         Ok(())
     }
 
+    /// Returns the offsets of snapshot chunks sent to node 2, checking their
+    /// contents against the snapshot.
+    fn chunks(rx: &mut mpsc::UnboundedReceiver<Message>, snapshot: &Snapshot) -> Vec<u64> {
+        let mut offsets = Vec::new();
+        while let Ok(msg) = rx.try_recv() {
+            assert_eq!((msg.from, msg.to, msg.term), (Address::Node(1), Address::Node(2), 3));
+            let Event::InstallSnapshot { index, term, size, checksum, offset, data } = msg.event
+            else {
+                panic!("Unexpected message {:?}", msg)
+            };
+            assert_eq!(
+                (index, term, size, checksum),
+                (snapshot.index, snapshot.term, snapshot.data.len() as u64, snapshot.checksum)
+            );
+            assert_eq!(data, snapshot.data[offset as usize..offset as usize + data.len()]);
+            offsets.push(offset);
+        }
+        offsets
+    }
+
+    #[test]
+    // Followers that need compacted entries are sent the snapshot in chunks,
+    // throttled by the window and resumed from acknowledged offsets.
+    fn step_snapshot_transfer() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+        let size = 9 * SNAPSHOT_CHUNK_SIZE + 100;
+        let data: Vec<u8> = (0..size).map(|i| i as u8).collect();
+        let snapshot = Snapshot::new(2, 1, data.clone());
+        let window = |from: u64, to: u64| -> Vec<u64> {
+            (from..to).map(|i| i * SNAPSHOT_CHUNK_SIZE).collect()
+        };
+
+        // The local state machine's snapshot compacts the log.
+        node = node.step(Message {
+            from: Address::Node(1),
+            to: Address::Node(1),
+            term: 0,
+            event: Event::CompactLog { index: 2, snapshot: data.clone() },
+        })?;
+        assert_node(&mut node).is_leader().term(3).committed(2).last(5);
+
+        // A follower that rejects entries is first probed at the snapshot.
+        node = node.step(Message {
+            from: Address::Node(2),
+            to: Address::Node(1),
+            term: 3,
+            event: Event::RejectEntries,
+        })?;
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Node(1),
+                to: Address::Node(2),
+                term: 3,
+                event: Event::AppendEntries {
+                    base_index: 2,
+                    base_term: 1,
+                    entries: vec![
+                        Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                        Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+                        Entry { index: 5, term: 3, command: Some(vec![0x05]) },
+                    ],
+                },
+            }],
+        );
+
+        // If it doesn't have it, the snapshot is sent up to the window.
+        node = node.step(Message {
+            from: Address::Node(2),
+            to: Address::Node(1),
+            term: 3,
+            event: Event::RejectEntries,
+        })?;
+        assert_eq!(chunks(&mut node_rx, &snapshot), window(0, 8));
+
+        // Acknowledgements open up the window.
+        node = node.step(Message {
+            from: Address::Node(2),
+            to: Address::Node(1),
+            term: 3,
+            event: Event::AcceptSnapshot { index: 2, offset: 2 * SNAPSHOT_CHUNK_SIZE },
+        })?;
+        assert_eq!(chunks(&mut node_rx, &snapshot), window(8, 10));
+
+        // Stale acknowledgements are ignored.
+        node = node.step(Message {
+            from: Address::Node(2),
+            to: Address::Node(1),
+            term: 3,
+            event: Event::AcceptSnapshot { index: 1, offset: 0 },
+        })?;
+        assert_eq!(chunks(&mut node_rx, &snapshot), Vec::<u64>::new());
+
+        // If the follower lost chunks, the transfer resumes at its offset.
+        node = node.step(Message {
+            from: Address::Node(2),
+            to: Address::Node(1),
+            term: 3,
+            event: Event::AcceptSnapshot { index: 2, offset: SNAPSHOT_CHUNK_SIZE },
+        })?;
+        assert_eq!(chunks(&mut node_rx, &snapshot), window(1, 9));
+
+        // A transfer without acknowledgements for a full heartbeat interval is
+        // resumed at the last acknowledged offset.
+        for _ in 0..HEARTBEAT_INTERVAL {
+            node = node.tick()?;
+        }
+        assert!(matches!(node_rx.try_recv(), Ok(Message { event: Event::Heartbeat { .. }, .. })));
+        assert_eq!(chunks(&mut node_rx, &snapshot), Vec::<u64>::new());
+
+        for _ in 0..HEARTBEAT_INTERVAL {
+            node = node.tick()?;
+        }
+        assert!(matches!(node_rx.try_recv(), Ok(Message { event: Event::Heartbeat { .. }, .. })));
+        assert_eq!(chunks(&mut node_rx, &snapshot), window(1, 9));
+
+        // Once installed, the transfer finishes and the remaining entries are sent.
+        node = node.step(Message {
+            from: Address::Node(2),
+            to: Address::Node(1),
+            term: 3,
+            event: Event::AcceptEntries { last_index: 2 },
+        })?;
+        assert_node(&mut node).is_leader().term(3).committed(2).last(5);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Node(1),
+                to: Address::Node(2),
+                term: 3,
+                event: Event::AppendEntries {
+                    base_index: 2,
+                    base_term: 1,
+                    entries: vec![
+                        Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                        Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+                        Entry { index: 5, term: 3, command: Some(vec![0x05]) },
+                    ],
+                },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    /// Returns the base and entry indexes of AppendEntries messages sent to
+    /// the given peer, ignoring other messages.
+    fn appends(
+        rx: &mut mpsc::UnboundedReceiver<Message>,
+        peer: NodeID,
+    ) -> Vec<(Index, Vec<Index>)> {
+        let mut appends = Vec::new();
+        while let Ok(msg) = rx.try_recv() {
+            if let (Address::Node(to), Event::AppendEntries { base_index, entries, .. }) =
+                (msg.to, msg.event)
+            {
+                if to == peer {
+                    appends.push((base_index, entries.iter().map(|e| e.index).collect()));
+                }
+            }
+        }
+        appends
+    }
+
+    #[test]
+    // Followers that lag far behind are sent batches limited by the catch-up
+    // rate, one at a time, while up-to-date followers aren't throttled.
+    fn step_catchup_throttled() -> Result<()> {
+        let (mut leader, mut node_rx, _state_rx) = setup()?;
+        for _ in 0..200 {
+            leader.log.append(3, Some(vec![0xff; 10]))?;
+        }
+        leader.catchup_rate = 30;
+        for (peer, progress) in leader.role.progress.iter_mut() {
+            (progress.next, progress.last) = if *peer == 2 { (6, 5) } else { (206, 205) };
+        }
+        let mut node: Node = leader.into();
+
+        // Each tick, the lagging follower is sent a batch up to its budget.
+        node = node.tick()?;
+        assert_eq!(appends(&mut node_rx, 2), vec![(5, vec![6, 7, 8])]);
+
+        // New writes are replicated to up-to-date followers, but the lagging
+        // follower has a batch in flight.
+        node = node.step(Message {
+            from: Address::Client,
+            to: Address::Node(1),
+            term: 0,
+            event: Event::ClientRequest { id: vec![0x01], request: Request::Mutate(vec![0xaf]) },
+        })?;
+        assert_eq!(appends(&mut node_rx, 3), vec![(205, vec![206])]);
+
+        // Acknowledging the batch doesn't send more, since the budget is spent.
+        node = node.step(Message {
+            from: Address::Node(2),
+            to: Address::Node(1),
+            term: 3,
+            event: Event::AcceptEntries { last_index: 8 },
+        })?;
+        assert_eq!(appends(&mut node_rx, 2), vec![]);
+
+        node = node.tick()?;
+        assert_eq!(appends(&mut node_rx, 2), vec![(8, vec![9, 10, 11])]);
+
+        // An unacknowledged batch is resent on the next heartbeat.
+        node = node.tick()?;
+        assert!(matches!(node_rx.try_recv(), Ok(Message { event: Event::Heartbeat { .. }, .. })));
+        assert_eq!(appends(&mut node_rx, 2), vec![(8, vec![9, 10, 11])]);
+
+        // Once the follower is within the catch-up lag, it's sent the rest of
+        // the log.
+        node = node.step(Message {
+            from: Address::Node(2),
+            to: Address::Node(1),
+            term: 3,
+            event: Event::AcceptEntries { last_index: 195 },
+        })?;
+        assert_eq!(appends(&mut node_rx, 2), vec![(195, (196..=206).collect())]);
+        assert_node(&mut node).is_leader().term(3).last(206);
+        Ok(())
+    }
+
     #[test]
     // Sending a client query request will pass it to the state machine and trigger heartbeats.
     fn step_clientrequest_query() -> Result<()> {
@@ -695,6 +1195,9 @@ mod tests {
                     apply_index: 0,
                     storage: "memory".into(),
                     storage_size: 72,
+                    diverged: false,
+                    checksum_mismatches: 0,
+                    history: Vec::new(),
                 }),
             }],
         );
@@ -702,6 +1205,86 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    // A log read returns committed entries, preceded by the snapshot if they
+    // have been compacted.
+    fn step_clientrequest_readlog() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+        let mut read_log = |node: Node, from, limit| -> Result<(Node, Response)> {
+            let node = node.step(Message {
+                from: Address::Client,
+                to: Address::Node(1),
+                term: 0,
+                event: Event::ClientRequest {
+                    id: vec![0x01],
+                    request: Request::ReadLog { from, limit },
+                },
+            })?;
+            match node_rx.try_recv()? {
+                Message {
+                    to: Address::Client,
+                    event: Event::ClientResponse { response, .. },
+                    ..
+                } => Ok((node, response?)),
+                msg => panic!("Unexpected message {:?}", msg),
+            }
+        };
+        let entry = |index, command| Entry { index, term: 1, command: Some(vec![command]) };
+
+        let response;
+        (node, response) = read_log(node, 1, 10)?;
+        assert_eq!(
+            response,
+            Response::Log { snapshot: None, entries: vec![entry(1, 0x01), entry(2, 0x02)] }
+        );
+        let response;
+        (node, response) = read_log(node, 2, 1)?;
+        assert_eq!(response, Response::Log { snapshot: None, entries: vec![entry(2, 0x02)] });
+        let response;
+        (node, response) = read_log(node, 3, 10)?;
+        assert_eq!(response, Response::Log { snapshot: None, entries: vec![] });
+
+        node = node.step(Message {
+            from: Address::Node(1),
+            to: Address::Node(1),
+            term: 0,
+            event: Event::CompactLog { index: 1, snapshot: vec![0xff] },
+        })?;
+        let response;
+        (node, response) = read_log(node, 1, 10)?;
+        match response {
+            Response::Log { snapshot: Some(snapshot), entries } => {
+                assert_eq!((snapshot.index, snapshot.data), (1, vec![0xff]));
+                assert_eq!(entries, vec![entry(2, 0x02)]);
+            }
+            response => panic!("Unexpected response {:?}", response),
+        }
+        assert_node(&mut node).is_leader().term(3).committed(2).last(5);
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // A late response from a previous leader, to a request forwarded while we
+    // were a follower, is ignored.
+    fn step_clientresponse_previous_leader() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+
+        node = node.step(Message {
+            from: Address::Node(2),
+            to: Address::Node(1),
+            term: 0,
+            event: Event::ClientResponse { id: vec![0x01], response: Err(Error::Abort) },
+        })?;
+        assert_node(&mut node).is_leader().term(3).committed(2).last(5);
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
     #[test]
     fn tick() -> Result<()> {
         let (leader, mut node_rx, mut state_rx) = setup()?;
@@ -1,14 +1,18 @@
 mod candidate;
 mod follower;
+mod invariants;
 mod leader;
 
-use super::{Address, Driver, Event, Index, Instruction, Log, Message, State};
-use crate::error::Result;
+use super::{
+    Address, Driver, Event, History, HistoryEntry, HistoryEvent, Index, Instruction, Log, Message,
+    State,
+};
+use crate::error::{Error, Result};
 use candidate::Candidate;
 use follower::Follower;
 use leader::Leader;
 
-use ::log::debug;
+use ::log::{debug, info};
 use rand::Rng as _;
 use serde_derive::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
@@ -46,6 +50,16 @@ pub struct Status {
     pub apply_index: Index,
     pub storage: String,
     pub storage_size: u64,
+    /// Whether the applied state has diverged from a peer's, i.e. a state
+    /// checksum mismatch has been detected.
+    pub diverged: bool,
+    /// The number of state checksum mismatches detected with peers.
+    pub checksum_mismatches: u64,
+    /// Recent Raft events on the server, oldest first. These are sent to
+    /// clients separately from the status, and are not included in the status
+    /// forwarded from the leader.
+    #[serde(skip)]
+    pub history: Vec<HistoryEntry>,
 }
 
 /// A Raft node, with a dynamic role. The node is driven synchronously by
@@ -72,10 +86,27 @@ impl Node {
         mut state: Box<dyn State>,
         node_tx: mpsc::UnboundedSender<Message>,
     ) -> Result<Self> {
+        // If the state is behind the log's snapshot, e.g. because the node crashed while
+        // installing a snapshot or the state isn't persistent, the compacted entries can't be
+        // replayed. Restore the snapshot instead.
+        if let Some(snapshot) = log.get_snapshot() {
+            if state.get_applied_index() < snapshot.index {
+                info!("Restoring state from snapshot at index {}", snapshot.index);
+                state.restore(snapshot.index, snapshot.data.clone())?;
+            }
+        }
+
         let (state_tx, state_rx) = mpsc::unbounded_channel();
         let mut driver = Driver::new(id, state_rx, node_tx.clone());
+        driver.set_snapshot_interval(log.get_snapshot_interval());
         driver.apply_log(&mut *state, &mut log)?;
-        tokio::spawn(driver.drive(state));
+        // Apply on a separate thread, such that slow applies don't delay e.g. heartbeats. The
+        // instruction channel hands off entries in order, and the driver reports the applied
+        // index back via status requests. If the driver halts on an error, it has logged it and
+        // closed the channel, which the node detects on its next tick.
+        std::thread::Builder::new()
+            .name(format!("raft-state-{}", id))
+            .spawn(move || driver.drive(state).ok())?;
 
         let node = RawNode::new(id, peers, log, node_tx, state_tx)?;
         if node.peers.is_empty() {
@@ -94,23 +125,83 @@ impl Node {
         }
     }
 
+    /// Returns the leader known to the node, if any.
+    pub fn leader(&self) -> Option<NodeID> {
+        match self {
+            Node::Candidate(_) => None,
+            Node::Follower(n) => n.role.leader,
+            Node::Leader(n) => Some(n.id),
+        }
+    }
+
+    /// Returns a handle to the node's history of recent events.
+    pub fn history(&self) -> History {
+        match self {
+            Node::Candidate(n) => n.history.clone(),
+            Node::Follower(n) => n.history.clone(),
+            Node::Leader(n) => n.history.clone(),
+        }
+    }
+
+    /// Enables or disables paranoid mode, which checks Raft invariants across
+    /// every step and tick. This is enabled by default in debug builds.
+    pub fn set_paranoid(&mut self, paranoid: bool) {
+        match self {
+            Node::Candidate(n) => n.paranoid = paranoid,
+            Node::Follower(n) => n.paranoid = paranoid,
+            Node::Leader(n) => n.paranoid = paranoid,
+        }
+    }
+
+    /// Sets the maximum number of bytes per tick that a leader replicates to
+    /// followers that are catching up, i.e. that need a snapshot or lag far
+    /// behind the log, shared between them. This keeps recovery traffic from
+    /// starving foreground replication. 0 (the default) means unlimited.
+    pub fn set_catchup_rate(&mut self, bytes: u64) {
+        match self {
+            Node::Candidate(n) => n.catchup_rate = bytes,
+            Node::Follower(n) => n.catchup_rate = bytes,
+            Node::Leader(n) => n.catchup_rate = bytes,
+        }
+    }
+
     /// Processes a message.
-    pub fn step(self, msg: Message) -> Result<Self> {
+    pub fn step(mut self, msg: Message) -> Result<Self> {
         debug!("Stepping {:?}", msg);
-        match self {
+        let checkpoint = self.checkpoint()?.map(|c| (c, msg.clone()));
+        let mut node = match self {
             Node::Candidate(n) => n.step(msg),
             Node::Follower(n) => n.step(msg),
             Node::Leader(n) => n.step(msg),
+        }?;
+        if let Some((checkpoint, msg)) = checkpoint {
+            node.check(&checkpoint, Some(&msg))?;
         }
+        Ok(node)
     }
 
-    /// Moves time forward by a tick.
-    pub fn tick(self) -> Result<Self> {
-        match self {
+    /// Moves time forward by a tick. Errors if the state machine driver has
+    /// halted, such that the node shuts down rather than keep running without
+    /// applying entries.
+    pub fn tick(mut self) -> Result<Self> {
+        let state_closed = match &self {
+            Node::Candidate(n) => n.state_tx.is_closed(),
+            Node::Follower(n) => n.state_tx.is_closed(),
+            Node::Leader(n) => n.state_tx.is_closed(),
+        };
+        if state_closed {
+            return Err(Error::Internal("State machine driver halted".into()));
+        }
+        let checkpoint = self.checkpoint()?;
+        let mut node = match self {
             Node::Candidate(n) => n.tick(),
             Node::Follower(n) => n.tick(),
             Node::Leader(n) => n.tick(),
+        }?;
+        if let Some(checkpoint) = checkpoint {
+            node.check(&checkpoint, None)?;
         }
+        Ok(node)
     }
 }
 
@@ -147,6 +238,13 @@ pub struct RawNode<R: Role = Follower> {
     node_tx: mpsc::UnboundedSender<Message>,
     state_tx: mpsc::UnboundedSender<Instruction>,
     role: R,
+    /// Whether to check Raft invariants, see Node::set_paranoid().
+    paranoid: bool,
+    /// The catch-up replication rate in bytes per tick, see
+    /// Node::set_catchup_rate().
+    catchup_rate: u64,
+    /// Recent notable events, for debugging.
+    history: History,
 }
 
 impl<R: Role> RawNode<R> {
@@ -160,9 +258,17 @@ impl<R: Role> RawNode<R> {
             node_tx: self.node_tx,
             state_tx: self.state_tx,
             role,
+            paranoid: self.paranoid,
+            catchup_rate: self.catchup_rate,
+            history: self.history,
         }
     }
 
+    /// Records an event in the node history.
+    fn record(&self, event: HistoryEvent) {
+        self.history.record(self.term, event)
+    }
+
     /// Returns the size of the cluster.
     fn cluster_size(&self) -> u8 {
         self.peers.len() as u8 + 1
@@ -181,7 +287,8 @@ impl<R: Role> RawNode<R> {
     }
 
     /// Sends an event
-    fn send(&self, to: Address, event: Event) -> Result<()> {
+    fn send(&mut self, to: Address, event: Event) -> Result<()> {
+        self.check_send(to, &event)?;
         let msg = Message { term: self.term, from: Address::Node(self.id), to, event };
         debug!("Sending {:?}", msg);
         Ok(self.node_tx.send(msg)?)
@@ -208,23 +315,34 @@ impl<R: Role> RawNode<R> {
         match msg.from {
             // The broadcast address can't send anything.
             Address::Broadcast => panic!("Message from broadcast address"),
-            // Clients can only send ClientRequest without a term.
+            // Clients can only send ClientRequest and ClientCancel without a term.
             Address::Client => {
                 assert_eq!(msg.term, 0, "Client message with term");
                 assert!(
-                    matches!(msg.event, Event::ClientRequest { .. }),
+                    matches!(msg.event, Event::ClientRequest { .. } | Event::ClientCancel { .. }),
                     "Non-request message from client"
                 );
             }
             // Nodes must be known, and must include their term.
             Address::Node(id) => {
                 assert!(id == self.id || self.peers.contains(&id), "Unknown sender {}", id);
-                // TODO: For now, accept ClientResponse without term, since the
-                // state driver does not have access to it.
+                // TODO: For now, accept ClientResponse, StateChecksum, and
+                // CompactLog without term, since the state driver does not
+                // have access to it.
                 assert!(
-                    msg.term > 0 || matches!(msg.event, Event::ClientResponse { .. }),
+                    msg.term > 0
+                        || matches!(
+                            msg.event,
+                            Event::ClientResponse { .. }
+                                | Event::StateChecksum { .. }
+                                | Event::CompactLog { .. }
+                        ),
                     "Message without term"
                 );
+                // Only the local state driver can ask us to compact the log.
+                if let Event::CompactLog { .. } = msg.event {
+                    assert_eq!(id, self.id, "CompactLog from other node");
+                }
             }
         }
     }
@@ -246,12 +364,13 @@ fn quorum_value<T: Ord + Copy>(mut values: Vec<T>) -> T {
 #[cfg(test)]
 mod tests {
     pub use super::super::state::tests::TestState;
-    use super::super::{Entry, RequestID};
+    use super::super::{Entry, Request, RequestID};
     use super::follower::tests::{follower_leader, follower_voted_for};
     use super::*;
     use crate::storage;
     use pretty_assertions::assert_eq;
     use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
     use tokio::sync::mpsc;
 
     pub fn assert_messages<T: std::fmt::Debug + PartialEq>(
@@ -399,7 +518,7 @@ mod tests {
         NodeAsserter::new(node)
     }
 
-    fn setup_rolenode() -> Result<(RawNode<Follower>, mpsc::UnboundedReceiver<Message>)> {
+    pub fn setup_rolenode() -> Result<(RawNode<Follower>, mpsc::UnboundedReceiver<Message>)> {
         setup_rolenode_peers(vec![2, 3])
     }
 
@@ -416,6 +535,9 @@ mod tests {
             log: Log::new(storage::engine::Memory::new(), false)?,
             node_tx,
             state_tx,
+            paranoid: true,
+            catchup_rate: 0,
+            history: History::new(),
         };
         Ok((node, node_rx))
     }
@@ -478,6 +600,24 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn new_state_restore_snapshot() -> Result<()> {
+        let (node_tx, _) = mpsc::unbounded_channel();
+        let mut log = Log::new(storage::engine::Memory::new(), false)?;
+        log.append(1, Some(vec![0x01]))?;
+        log.append(1, Some(vec![0x02]))?;
+        log.append(2, Some(vec![0x03]))?;
+        log.commit(3)?;
+        log.compact(2, crate::storage::bincode::serialize(&vec![vec![0x0au8]])?)?;
+        let state = Box::new(TestState::new(1));
+
+        Node::new(1, HashSet::from([2, 3]), log, state.clone(), node_tx).await?;
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(state.list(), vec![vec![0x0a], vec![0x03]]);
+        assert_eq!(state.get_applied_index(), 3);
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     #[should_panic(expected = "applied index above commit index")]
     async fn new_state_apply_missing() {
@@ -493,6 +633,95 @@ mod tests {
         Node::new(1, HashSet::from([2, 3]), log, state.clone(), node_tx).await.unwrap();
     }
 
+    /// A state machine which records the names of the threads it applies entries on.
+    struct ThreadState {
+        applied_index: Index,
+        threads: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl State for ThreadState {
+        fn get_applied_index(&self) -> Index {
+            self.applied_index
+        }
+
+        fn apply(&mut self, entry: Entry) -> Result<Vec<u8>> {
+            if entry.command == Some(vec![0xff]) {
+                return Err(Error::Internal("apply failed".into()));
+            }
+            let name = std::thread::current().name().unwrap_or_default().to_string();
+            self.threads.lock()?.push(name);
+            self.applied_index = entry.index;
+            Ok(Vec::new())
+        }
+
+        fn query(&self, _: Vec<u8>) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn checksum(&self) -> Result<u32> {
+            Ok(0)
+        }
+
+        fn snapshot(&self) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn restore(&mut self, index: Index, _: Vec<u8>) -> Result<()> {
+            self.applied_index = index;
+            Ok(())
+        }
+    }
+
+    // Entries are applied on a dedicated thread, not the node's.
+    #[tokio::test]
+    async fn new_state_apply_thread() -> Result<()> {
+        let (node_tx, _node_rx) = mpsc::unbounded_channel();
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let state = Box::new(ThreadState { applied_index: 0, threads: threads.clone() });
+        let log = Log::new(storage::engine::Memory::new(), false)?;
+        let node = Node::new(1, HashSet::new(), log, state, node_tx).await?;
+        node.step(Message {
+            from: Address::Client,
+            to: Address::Node(1),
+            term: 0,
+            event: Event::ClientRequest { id: vec![0x01], request: Request::Mutate(vec![0x01]) },
+        })?;
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while threads.lock()?.len() < 2 && std::time::Instant::now() < deadline {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+        assert_eq!(*threads.lock()?, vec!["raft-state-1".to_string(); 2]); // noop and mutation
+        Ok(())
+    }
+
+    // If the state machine driver halts on an error, the node errors on its next tick.
+    #[tokio::test]
+    async fn new_state_apply_thread_error() -> Result<()> {
+        let (node_tx, _node_rx) = mpsc::unbounded_channel();
+        let state = Box::new(ThreadState { applied_index: 0, threads: Arc::default() });
+        let log = Log::new(storage::engine::Memory::new(), false)?;
+        let mut node = Node::new(1, HashSet::new(), log, state, node_tx).await?;
+        node = node.step(Message {
+            from: Address::Client,
+            to: Address::Node(1),
+            term: 0,
+            event: Event::ClientRequest { id: vec![0x01], request: Request::Mutate(vec![0xff]) },
+        })?;
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        loop {
+            match node.tick() {
+                Ok(n) if std::time::Instant::now() < deadline => node = n,
+                Ok(_) => panic!("node didn't halt"),
+                Err(error) => {
+                    assert_eq!(error, Error::Internal("State machine driver halted".into()));
+                    break;
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+        Ok(())
+    }
+
     #[tokio::test]
     async fn new_single() -> Result<()> {
         let (node_tx, _node_rx) = mpsc::unbounded_channel();
@@ -529,7 +758,7 @@ mod tests {
 
     #[test]
     fn send() -> Result<()> {
-        let (node, mut rx) = setup_rolenode()?;
+        let (mut node, mut rx) = setup_rolenode()?;
         node.send(Address::Node(2), Event::Heartbeat { commit_index: 1, commit_term: 1 })?;
         assert_messages(
             &mut rx,
@@ -1,15 +1,17 @@
-use super::{Address, Event, Log, Message, Node, NodeID, Request, Response, State};
+use super::{
+    Address, Event, History, Log, Message, Node, NodeID, Request, Response, State, Tcp, Transport,
+};
+use crate::clock::HybridClock;
 use crate::error::{Error, Result};
 
-use ::log::{debug, error};
-use futures::{sink::SinkExt as _, FutureExt as _};
+use ::log::debug;
+use futures::FutureExt as _;
 use std::collections::HashMap;
-use std::time::Duration;
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{mpsc, oneshot};
-use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream, UnboundedReceiverStream};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio_stream::wrappers::UnboundedReceiverStream;
 use tokio_stream::StreamExt as _;
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use uuid::Uuid;
 
 /// The interval between Raft ticks, the unit of time for e.g. heartbeats and
@@ -21,6 +23,8 @@ pub struct Server {
     node: Node,
     peers: HashMap<NodeID, String>,
     node_rx: mpsc::UnboundedReceiver<Message>,
+    leader_tx: watch::Sender<Option<NodeID>>,
+    clock: HybridClock,
 }
 
 impl Server {
@@ -32,60 +36,143 @@ impl Server {
         state: Box<dyn State>,
     ) -> Result<Self> {
         let (node_tx, node_rx) = mpsc::unbounded_channel();
-        Ok(Self {
-            node: Node::new(id, peers.keys().copied().collect(), log, state, node_tx).await?,
-            peers,
-            node_rx,
-        })
+        let node = Node::new(id, peers.keys().copied().collect(), log, state, node_tx).await?;
+        let (leader_tx, _) = watch::channel(node.leader());
+        Ok(Self { node, peers, node_rx, leader_tx, clock: HybridClock::system() })
     }
 
-    /// Connects to peers and serves requests.
+    /// Returns the local node ID.
+    pub fn id(&self) -> NodeID {
+        self.node.id()
+    }
+
+    /// Returns a receiver for the leader known to the local node, which is
+    /// updated as it changes, e.g. to redirect clients to the leader.
+    pub fn leader(&self) -> watch::Receiver<Option<NodeID>> {
+        self.leader_tx.subscribe()
+    }
+
+    /// Returns a handle to the local node's hybrid logical clock, which is
+    /// updated by timestamps on messages from peers.
+    pub fn clock(&self) -> HybridClock {
+        self.clock.clone()
+    }
+
+    /// Returns a handle to the local node's history of recent events.
+    pub fn history(&self) -> History {
+        self.node.history()
+    }
+
+    /// Enables or disables paranoid mode, see Node::set_paranoid().
+    pub fn set_paranoid(&mut self, paranoid: bool) {
+        self.node.set_paranoid(paranoid)
+    }
+
+    /// Limits the bandwidth used to catch up lagging followers, in bytes per
+    /// second, see Node::set_catchup_rate(). 0 means unlimited.
+    pub fn set_catchup_bandwidth(&mut self, bytes_per_sec: u64) {
+        let per_tick = bytes_per_sec * TICK_INTERVAL.as_millis() as u64 / 1000;
+        self.node.set_catchup_rate(if bytes_per_sec > 0 { per_tick.max(1) } else { 0 })
+    }
+
+    /// Connects to peers via TCP and serves requests.
     pub async fn serve(
-        self,
+        mut self,
         listener: TcpListener,
-        client_rx: mpsc::UnboundedReceiver<(Request, oneshot::Sender<Result<Response>>)>,
+        client_rx: mpsc::UnboundedReceiver<(
+            Request,
+            Option<Instant>,
+            oneshot::Sender<Result<Response>>,
+        )>,
     ) -> Result<()> {
-        let (tcp_in_tx, tcp_in_rx) = mpsc::unbounded_channel::<Message>();
-        let (tcp_out_tx, tcp_out_rx) = mpsc::unbounded_channel::<Message>();
-        let (task, tcp_receiver) = Self::tcp_receive(listener, tcp_in_tx).remote_handle();
-        tokio::spawn(task);
-        let (task, tcp_sender) = Self::tcp_send(self.peers, tcp_out_rx).remote_handle();
+        let transport =
+            Tcp::new(listener, std::mem::take(&mut self.peers)).with_clock(self.clock.clone());
+        self.serve_transport(Box::new(transport), client_rx).await
+    }
+
+    /// Serves requests, using the given transport to communicate with peers.
+    pub async fn serve_transport(
+        self,
+        transport: Box<dyn Transport>,
+        client_rx: mpsc::UnboundedReceiver<(
+            Request,
+            Option<Instant>,
+            oneshot::Sender<Result<Response>>,
+        )>,
+    ) -> Result<()> {
+        let (in_tx, in_rx) = mpsc::unbounded_channel::<Message>();
+        let (out_tx, out_rx) = mpsc::unbounded_channel::<Message>();
+        let (task, transport) = transport.serve(out_rx, in_tx).remote_handle();
         tokio::spawn(task);
         let (task, eventloop) =
-            Self::eventloop(self.node, self.node_rx, client_rx, tcp_in_rx, tcp_out_tx)
+            Self::eventloop(self.node, self.node_rx, client_rx, in_rx, out_tx, self.leader_tx)
                 .remote_handle();
         tokio::spawn(task);
 
-        tokio::try_join!(tcp_receiver, tcp_sender, eventloop)?;
+        tokio::try_join!(transport, eventloop)?;
         Ok(())
     }
 
-    /// Runs the event loop.
+    /// Runs the event loop. Client requests may have a deadline, after which
+    /// they fail with Error::Timeout and are cancelled. Deadlines are checked
+    /// on every tick, so they may overrun by up to TICK_INTERVAL. Leader changes
+    /// are published via leader_tx. If the node panics, its recent history is
+    /// logged.
     async fn eventloop(
         mut node: Node,
         node_rx: mpsc::UnboundedReceiver<Message>,
-        client_rx: mpsc::UnboundedReceiver<(Request, oneshot::Sender<Result<Response>>)>,
-        tcp_rx: mpsc::UnboundedReceiver<Message>,
-        tcp_tx: mpsc::UnboundedSender<Message>,
+        client_rx: mpsc::UnboundedReceiver<(
+            Request,
+            Option<Instant>,
+            oneshot::Sender<Result<Response>>,
+        )>,
+        peer_rx: mpsc::UnboundedReceiver<Message>,
+        peer_tx: mpsc::UnboundedSender<Message>,
+        leader_tx: watch::Sender<Option<NodeID>>,
     ) -> Result<()> {
+        let _dump = node.history().dump_on_panic();
         let mut node_rx = UnboundedReceiverStream::new(node_rx);
-        let mut tcp_rx = UnboundedReceiverStream::new(tcp_rx);
+        let mut peer_rx = UnboundedReceiverStream::new(peer_rx);
         let mut client_rx = UnboundedReceiverStream::new(client_rx);
 
         let mut ticker = tokio::time::interval(TICK_INTERVAL);
-        let mut requests = HashMap::<Vec<u8>, oneshot::Sender<Result<Response>>>::new();
+        let mut requests =
+            HashMap::<Vec<u8>, (oneshot::Sender<Result<Response>>, Option<Instant>)>::new();
         loop {
             tokio::select! {
-                _ = ticker.tick() => node = node.tick()?,
+                _ = ticker.tick() => {
+                    node = node.tick()?;
+                    // Cancel requests that timed out or were abandoned by the client.
+                    let now = Instant::now();
+                    let expired: Vec<_> = requests.iter()
+                        .filter(|(_, (tx, deadline))| tx.is_closed() || deadline.is_some_and(|d| d <= now))
+                        .map(|(id, _)| id.clone())
+                        .collect();
+                    for id in expired {
+                        if let Some((response_tx, _)) = requests.remove(&id) {
+                            debug!("Cancelling request {:x?}", id);
+                            response_tx.send(Err(Error::Timeout)).ok();
+                        }
+                        let to = Address::Node(node.id());
+                        node = node.step(Message{
+                            from: Address::Client,
+                            to,
+                            term: 0,
+                            event: Event::ClientCancel{id},
+                        })?;
+                    }
+                }
 
-                Some(msg) = tcp_rx.next() => node = node.step(msg)?,
+                Some(msg) = peer_rx.next() => node = node.step(msg)?,
 
                 Some(msg) = node_rx.next() => {
                     match msg {
-                        Message{to: Address::Node(_), ..} => tcp_tx.send(msg)?,
-                        Message{to: Address::Broadcast, ..} => tcp_tx.send(msg)?,
+                        // The local state driver may send messages to the node itself.
+                        Message{to: Address::Node(id), ..} if id == node.id() => node = node.step(msg)?,
+                        Message{to: Address::Node(_), ..} => peer_tx.send(msg)?,
+                        Message{to: Address::Broadcast, ..} => peer_tx.send(msg)?,
                         Message{to: Address::Client, event: Event::ClientResponse{ id, response }, ..} => {
-                            if let Some(response_tx) = requests.remove(&id) {
+                            if let Some((response_tx, _)) = requests.remove(&id) {
                                 response_tx
                                     .send(response)
                                     .map_err(|e| Error::Internal(format!("Failed to send response {:?}", e)))?;
@@ -95,7 +182,11 @@ impl Server {
                     }
                 }
 
-                Some((request, response_tx)) = client_rx.next() => {
+                Some((request, deadline, response_tx)) = client_rx.next() => {
+                    if deadline.is_some_and(|d| d <= Instant::now()) {
+                        response_tx.send(Err(Error::Timeout)).ok();
+                        continue;
+                    }
                     let id = Uuid::new_v4().as_bytes().to_vec();
                     let msg = Message{
                         from: Address::Client,
@@ -104,117 +195,12 @@ impl Server {
                         event: Event::ClientRequest{id: id.clone(), request},
                     };
                     node = node.step(msg)?;
-                    requests.insert(id, response_tx);
+                    requests.insert(id, (response_tx, deadline));
                 }
             }
-        }
-    }
 
-    /// Receives inbound messages from peers via TCP.
-    async fn tcp_receive(
-        listener: TcpListener,
-        in_tx: mpsc::UnboundedSender<Message>,
-    ) -> Result<()> {
-        let mut listener = TcpListenerStream::new(listener);
-        while let Some(socket) = listener.try_next().await? {
-            let peer = socket.peer_addr()?;
-            let peer_in_tx = in_tx.clone();
-            tokio::spawn(async move {
-                debug!("Raft peer {} connected", peer);
-                match Self::tcp_receive_peer(socket, peer_in_tx).await {
-                    Ok(()) => debug!("Raft peer {} disconnected", peer),
-                    Err(err) => error!("Raft peer {} error: {}", peer, err.to_string()),
-                };
-            });
+            let leader = node.leader();
+            leader_tx.send_if_modified(|current| std::mem::replace(current, leader) != leader);
         }
-        Ok(())
-    }
-
-    /// Receives inbound messages from a peer via TCP.
-    async fn tcp_receive_peer(
-        socket: TcpStream,
-        in_tx: mpsc::UnboundedSender<Message>,
-    ) -> Result<()> {
-        let mut stream = tokio_serde::SymmetricallyFramed::<_, Message, _>::new(
-            Framed::new(socket, LengthDelimitedCodec::new()),
-            tokio_serde::formats::SymmetricalBincode::<Message>::default(),
-        );
-        while let Some(message) = stream.try_next().await? {
-            in_tx.send(message)?;
-        }
-        Ok(())
-    }
-
-    /// Sends outbound messages to peers via TCP.
-    async fn tcp_send(
-        peers: HashMap<NodeID, String>,
-        out_rx: mpsc::UnboundedReceiver<Message>,
-    ) -> Result<()> {
-        let mut out_rx = UnboundedReceiverStream::new(out_rx);
-        let mut peer_txs: HashMap<NodeID, mpsc::Sender<Message>> = HashMap::new();
-
-        for (id, addr) in peers.into_iter() {
-            let (tx, rx) = mpsc::channel::<Message>(1000);
-            peer_txs.insert(id, tx);
-            tokio::spawn(Self::tcp_send_peer(addr, rx));
-        }
-
-        while let Some(message) = out_rx.next().await {
-            let to = match message.to {
-                Address::Broadcast => peer_txs.keys().copied().collect(),
-                Address::Node(peer) => vec![peer],
-                addr => {
-                    error!("Received outbound message for non-TCP address {:?}", addr);
-                    continue;
-                }
-            };
-            for id in to {
-                match peer_txs.get_mut(&id) {
-                    Some(tx) => match tx.try_send(message.clone()) {
-                        Ok(()) => {}
-                        Err(mpsc::error::TrySendError::Full(_)) => {
-                            debug!("Full send buffer for peer {}, discarding message", id)
-                        }
-                        Err(error) => return Err(error.into()),
-                    },
-                    None => error!("Received outbound message for unknown peer {}", id),
-                }
-            }
-        }
-        Ok(())
-    }
-
-    /// Sends outbound messages to a peer, continuously reconnecting.
-    async fn tcp_send_peer(addr: String, out_rx: mpsc::Receiver<Message>) {
-        let mut out_rx = ReceiverStream::new(out_rx);
-        loop {
-            match TcpStream::connect(&addr).await {
-                Ok(socket) => {
-                    debug!("Connected to Raft peer {}", addr);
-                    match Self::tcp_send_peer_session(socket, &mut out_rx).await {
-                        Ok(()) => break,
-                        Err(err) => error!("Failed sending to Raft peer {}: {}", addr, err),
-                    }
-                }
-                Err(err) => error!("Failed connecting to Raft peer {}: {}", addr, err),
-            }
-            tokio::time::sleep(Duration::from_millis(1000)).await;
-        }
-        debug!("Disconnected from Raft peer {}", addr);
-    }
-
-    /// Sends outbound messages to a peer via a TCP session.
-    async fn tcp_send_peer_session(
-        socket: TcpStream,
-        out_rx: &mut ReceiverStream<Message>,
-    ) -> Result<()> {
-        let mut stream = tokio_serde::SymmetricallyFramed::<_, Message, _>::new(
-            Framed::new(socket, LengthDelimitedCodec::new()),
-            tokio_serde::formats::SymmetricalBincode::<Message>::default(),
-        );
-        while let Some(message) = out_rx.next().await {
-            stream.send(message).await?;
-        }
-        Ok(())
     }
 }
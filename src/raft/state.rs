@@ -4,8 +4,18 @@ use crate::error::{Error, Result};
 use log::{debug, error};
 use std::collections::{BTreeMap, HashMap, HashSet};
 use tokio::sync::mpsc;
-use tokio_stream::wrappers::UnboundedReceiverStream;
-use tokio_stream::StreamExt as _;
+
+/// The interval between state checksums, in log entries. Nodes checksum their
+/// applied state at every multiple of this index and broadcast it to peers,
+/// which compare it with their own to detect replica divergence.
+const CHECKSUM_INTERVAL: Index = 1000;
+
+/// The number of recent local state checksums to keep for comparison with
+/// checksums from peers.
+const CHECKSUM_HISTORY: usize = 10;
+
+/// The maximum number of committed log entries to apply in a single batch.
+const APPLY_BATCH_SIZE: usize = 100;
 
 /// A Raft-managed state machine.
 pub trait State: Send {
@@ -23,8 +33,36 @@ pub trait State: Send {
     /// TODO: consider using runtime assertions instead of Error::Internal.
     fn apply(&mut self, entry: Entry) -> Result<Vec<u8>>;
 
+    /// Applies a batch of contiguous log entries to the state machine, returning
+    /// the result of each entry as for apply(). If an entry returns
+    /// Error::Internal, it is returned instead and the Raft node halts. State
+    /// machines can override this to e.g. persist the applied index once per
+    /// batch rather than once per entry.
+    fn apply_batch(&mut self, entries: Vec<Entry>) -> Result<Vec<Result<Vec<u8>>>> {
+        let mut results = Vec::with_capacity(entries.len());
+        for entry in entries {
+            match self.apply(entry) {
+                Err(error @ Error::Internal(_)) => return Err(error),
+                result => results.push(result),
+            }
+        }
+        Ok(results)
+    }
+
     /// Queries the state machine. All errors are propagated to the caller.
     fn query(&self, command: Vec<u8>) -> Result<Vec<u8>>;
+
+    /// Returns a checksum of the applied state. Replicas at the same applied
+    /// index must return the same checksum, otherwise they have diverged.
+    fn checksum(&self) -> Result<u32>;
+
+    /// Returns a snapshot of the applied state, which the Raft log is
+    /// compacted into and which can be restored on other replicas.
+    fn snapshot(&self) -> Result<Vec<u8>>;
+
+    /// Replaces the state with a snapshot taken at the given applied index,
+    /// as returned by snapshot(). The restored state must be durable.
+    fn restore(&mut self, index: Index, snapshot: Vec<u8>) -> Result<()>;
 }
 
 #[derive(Debug, PartialEq)]
@@ -34,14 +72,23 @@ pub enum Instruction {
     Abort,
     /// Apply a log entry.
     Apply { entry: Entry },
+    /// Cancel a pending notification or query from the given address, e.g.
+    /// because the client gave up waiting for it.
+    Cancel { id: Vec<u8>, address: Address },
+    /// Compare a peer's state checksum at the given index with the local state.
+    Checksum { peer: NodeID, index: Index, checksum: u32 },
     /// Notify the given address with the result of applying the entry at the given index.
     Notify { id: Vec<u8>, address: Address, index: Index },
     /// Query the state machine when the given term and index has been confirmed by vote.
     Query { id: Vec<u8>, address: Address, command: Vec<u8>, term: Term, index: Index, quorum: u8 },
     /// Extend the given server status and return it to the given address.
     Status { id: Vec<u8>, address: Address, status: Box<Status> },
+    /// Query the local state machine once the given index has been applied.
+    StaleQuery { id: Vec<u8>, address: Address, command: Vec<u8>, index: Index },
     /// Votes for queries at the given term and commit index.
     Vote { term: Term, index: Index, address: Address },
+    /// Replace the state with a snapshot at the given index, received from the leader.
+    Restore { index: Index, snapshot: Vec<u8> },
 }
 
 /// A driver query.
@@ -54,15 +101,35 @@ struct Query {
     votes: HashSet<Address>,
 }
 
+/// A driver stale query.
+struct StaleQuery {
+    id: Vec<u8>,
+    address: Address,
+    command: Vec<u8>,
+}
+
 /// Drives a state machine, taking operations from state_rx and sending results via node_tx.
 pub struct Driver {
     node_id: NodeID,
-    state_rx: UnboundedReceiverStream<Instruction>,
+    state_rx: mpsc::UnboundedReceiver<Instruction>,
     node_tx: mpsc::UnboundedSender<Message>,
     /// Notify clients when their mutation is applied. <index, (client, id)>
     notify: HashMap<Index, (Address, Vec<u8>)>,
     /// Execute client queries when they receive a quorum. <index, <id, query>>
     queries: BTreeMap<Index, BTreeMap<Vec<u8>, Query>>,
+    /// Execute stale client queries when the index is applied. <index, [query]>
+    stale_queries: BTreeMap<Index, Vec<StaleQuery>>,
+    /// The interval between state checksums, in log entries.
+    checksum_interval: Index,
+    /// Recent local state checksums. <index, checksum>
+    checksums: BTreeMap<Index, u32>,
+    /// Peer state checksums for indexes that haven't been applied yet. <index, <peer, checksum>>
+    peer_checksums: BTreeMap<Index, HashMap<NodeID, u32>>,
+    /// The number of state checksum mismatches with peers.
+    checksum_mismatches: u64,
+    /// The interval between state snapshots for log compaction, in log
+    /// entries. 0 disables snapshots.
+    snapshot_interval: Index,
 }
 
 impl Driver {
@@ -74,18 +141,62 @@ impl Driver {
     ) -> Self {
         Self {
             node_id,
-            state_rx: UnboundedReceiverStream::new(state_rx),
+            state_rx,
             node_tx,
             notify: HashMap::new(),
             queries: BTreeMap::new(),
+            stale_queries: BTreeMap::new(),
+            checksum_interval: CHECKSUM_INTERVAL,
+            checksums: BTreeMap::new(),
+            peer_checksums: BTreeMap::new(),
+            checksum_mismatches: 0,
+            snapshot_interval: 0,
         }
     }
 
-    /// Drives a state machine.
-    pub async fn drive(mut self, mut state: Box<dyn State>) -> Result<()> {
+    /// Sets the interval between state snapshots, in log entries. The state is
+    /// snapshotted when the applied index reaches a multiple of it, and sent to
+    /// the local node which compacts its log. 0 disables snapshots.
+    pub fn set_snapshot_interval(&mut self, interval: Index) {
+        self.snapshot_interval = interval;
+    }
+
+    /// Drives a state machine until the instruction channel is closed.
+    /// Consecutive apply instructions that are already queued are applied as a
+    /// single batch, up to APPLY_BATCH_SIZE entries.
+    ///
+    /// This blocks the current thread, and is run on a dedicated thread such
+    /// that slow applies don't hold up the Raft node. It can't be called from
+    /// an async context.
+    pub fn drive(mut self, mut state: Box<dyn State>) -> Result<()> {
         debug!("Starting state machine driver at applied index {}", state.get_applied_index());
-        while let Some(instruction) = self.state_rx.next().await {
-            if let Err(error) = self.execute(instruction, &mut *state) {
+        let mut next = None;
+        loop {
+            let instruction = match next.take() {
+                Some(instruction) => instruction,
+                None => match self.state_rx.blocking_recv() {
+                    Some(instruction) => instruction,
+                    None => break,
+                },
+            };
+            let result = match instruction {
+                Instruction::Apply { entry } => {
+                    let mut entries = vec![entry];
+                    while entries.len() < APPLY_BATCH_SIZE {
+                        match self.state_rx.try_recv() {
+                            Ok(Instruction::Apply { entry }) => entries.push(entry),
+                            Ok(instruction) => {
+                                next = Some(instruction);
+                                break;
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                    self.apply(&mut *state, entries).map(|_| ())
+                }
+                instruction => self.execute(instruction, &mut *state),
+            };
+            if let Err(error) = result {
                 error!("Halting state machine due to error: {}", error);
                 return Err(error);
             }
@@ -94,7 +205,7 @@ impl Driver {
         Ok(())
     }
 
-    /// Applies committed log entries to the state machine.
+    /// Applies committed log entries to the state machine, in batches.
     pub fn apply_log(&mut self, state: &mut dyn State, log: &mut Log) -> Result<Index> {
         let applied_index = state.get_applied_index();
         let (commit_index, _) = log.get_commit_index();
@@ -102,24 +213,60 @@ impl Driver {
 
         if applied_index < commit_index {
             let mut scan = log.scan((applied_index + 1)..=commit_index)?;
-            while let Some(entry) = scan.next().transpose()? {
-                self.apply(state, entry)?;
+            loop {
+                let entries = scan.by_ref().take(APPLY_BATCH_SIZE).collect::<Result<Vec<_>>>()?;
+                if entries.is_empty() {
+                    break;
+                }
+                self.apply(state, entries)?;
             }
         }
         Ok(state.get_applied_index())
     }
 
-    /// Applies an entry to the state machine.
-    pub fn apply(&mut self, state: &mut dyn State, entry: Entry) -> Result<Index> {
-        // Apply the command.
-        debug!("Applying {:?}", entry);
-        match state.apply(entry) {
-            Err(error @ Error::Internal(_)) => return Err(error),
-            result => self.notify_applied(state.get_applied_index(), result)?,
+    /// Applies contiguous entries to the state machine. They're applied in
+    /// batches that end at checksum and snapshot indexes, such that the state
+    /// can be checksummed and snapshotted there.
+    pub fn apply(&mut self, state: &mut dyn State, mut entries: Vec<Entry>) -> Result<Index> {
+        // Whether a multiple of the interval is in the index range (from, to].
+        let crosses = |interval: Index, from: Index, to: Index| {
+            interval > 0 && to / interval != from / interval
         };
+        while !entries.is_empty() {
+            let (checksum_interval, snapshot_interval) =
+                (self.checksum_interval, self.snapshot_interval);
+            let split = entries
+                .iter()
+                .position(|e| {
+                    crosses(checksum_interval, e.index - 1, e.index)
+                        || crosses(snapshot_interval, e.index - 1, e.index)
+                })
+                .map_or(entries.len(), |i| i + 1);
+            let rest = entries.split_off(split);
+            let batch = std::mem::replace(&mut entries, rest);
+
+            #[cfg(feature = "fault-injection")]
+            crate::fault::crash_point(self.node_id, "state.apply")?;
+            // Apply the commands.
+            debug!("Applying {:?}", batch);
+            let first = batch[0].index;
+            for (index, result) in (first..).zip(state.apply_batch(batch)?) {
+                self.notify_applied(index, result)?;
+            }
+            // Checksum and snapshot the state when the applied index reaches a
+            // multiple of the respective interval.
+            let index = state.get_applied_index();
+            if crosses(checksum_interval, first - 1, index) {
+                self.checksum_local(state)?;
+            }
+            if crosses(snapshot_interval, first - 1, index) {
+                self.snapshot(state)?;
+            }
+        }
         // Try to execute any pending queries, since they may have been submitted for a
         // commit_index which hadn't been applied yet.
         self.query_execute(state)?;
+        self.stale_execute(state)?;
         Ok(state.get_applied_index())
     }
 
@@ -133,7 +280,21 @@ impl Driver {
             }
 
             Instruction::Apply { entry } => {
-                self.apply(state, entry)?;
+                self.apply(state, vec![entry])?;
+            }
+
+            Instruction::Cancel { id, address } => {
+                self.notify_cancel(&id, &address);
+                self.query_cancel(&id, &address);
+                self.stale_cancel(&id, &address);
+            }
+
+            Instruction::Checksum { peer, index, checksum } => {
+                if index > state.get_applied_index() {
+                    self.peer_checksums.entry(index).or_default().insert(peer, checksum);
+                } else {
+                    self.checksum_compare(peer, index, checksum);
+                }
             }
 
             Instruction::Notify { id, address, index } => {
@@ -153,20 +314,80 @@ impl Driver {
 
             Instruction::Status { id, address, mut status } => {
                 status.apply_index = state.get_applied_index();
+                status.diverged = self.checksum_mismatches > 0;
+                status.checksum_mismatches = self.checksum_mismatches;
                 self.send(
                     address,
                     Event::ClientResponse { id, response: Ok(Response::Status(*status)) },
                 )?;
             }
 
+            Instruction::StaleQuery { id, address, command, index } => {
+                let query = StaleQuery { id, address, command };
+                self.stale_queries.entry(index).or_default().push(query);
+                self.stale_execute(state)?;
+            }
+
             Instruction::Vote { term, index, address } => {
                 self.query_vote(term, index, address);
                 self.query_execute(state)?;
             }
+
+            Instruction::Restore { index, snapshot } => {
+                state.restore(index, snapshot)?;
+                // Peer checksums at or before the snapshot can no longer be compared.
+                self.peer_checksums.retain(|i, _| *i > index);
+                self.query_execute(state)?;
+                self.stale_execute(state)?;
+            }
         }
         Ok(())
     }
 
+    /// Checksums the applied state and broadcasts it to peers, comparing it with
+    /// any checksums already received from peers for the applied index.
+    fn checksum_local(&mut self, state: &mut dyn State) -> Result<()> {
+        let index = state.get_applied_index();
+        let checksum = state.checksum()?;
+        debug!("State checksum at index {} is {:08x}", index, checksum);
+        self.checksums.insert(index, checksum);
+        while self.checksums.len() > CHECKSUM_HISTORY {
+            self.checksums.pop_first();
+        }
+
+        for (peer, peer_checksum) in self.peer_checksums.remove(&index).unwrap_or_default() {
+            self.checksum_compare(peer, index, peer_checksum);
+        }
+        self.peer_checksums.retain(|i, _| *i > index);
+        self.send(Address::Broadcast, Event::StateChecksum { index, checksum })
+    }
+
+    /// Snapshots the applied state, and sends it to the local node to compact
+    /// its log.
+    fn snapshot(&mut self, state: &mut dyn State) -> Result<()> {
+        let index = state.get_applied_index();
+        let snapshot = state.snapshot()?;
+        debug!("Snapshotted state at index {} ({} bytes)", index, snapshot.len());
+        self.send(Address::Node(self.node_id), Event::CompactLog { index, snapshot })
+    }
+
+    /// Compares a peer's state checksum with the local checksum at the given
+    /// index, if known, and alarms on mismatches.
+    fn checksum_compare(&mut self, peer: NodeID, index: Index, checksum: u32) {
+        match self.checksums.get(&index) {
+            Some(local) if *local != checksum => {
+                self.checksum_mismatches += 1;
+                error!(
+                    "State diverged from node {} at index {}: local checksum {:08x}, node {} \
+                     checksum {:08x}",
+                    peer, index, local, peer, checksum
+                );
+            }
+            Some(_) => debug!("State checksum at index {} matches node {}", index, peer),
+            None => debug!("No local state checksum at index {} to compare with", index),
+        }
+    }
+
     /// Aborts all pending notifications.
     fn notify_abort(&mut self) -> Result<()> {
         for (_, (address, id)) in std::mem::take(&mut self.notify) {
@@ -175,6 +396,11 @@ impl Driver {
         Ok(())
     }
 
+    /// Cancels a pending notification, if any. The entry is still applied.
+    fn notify_cancel(&mut self, id: &[u8], address: &Address) {
+        self.notify.retain(|_, (a, i)| !(a == address && i == id));
+    }
+
     /// Notifies a client about an applied log entry, if any.
     fn notify_applied(&mut self, index: Index, result: Result<Vec<u8>>) -> Result<()> {
         if let Some((to, id)) = self.notify.remove(&index) {
@@ -196,6 +422,16 @@ impl Driver {
         Ok(())
     }
 
+    /// Cancels a pending query, if any.
+    fn query_cancel(&mut self, id: &[u8], address: &Address) {
+        self.queries.retain(|_, queries| {
+            if queries.get(id).is_some_and(|q| &q.address == address) {
+                queries.remove(id);
+            }
+            !queries.is_empty()
+        });
+    }
+
     /// Executes any queries that are ready.
     fn query_execute(&mut self, state: &mut dyn State) -> Result<()> {
         for query in self.query_ready(state.get_applied_index()) {
@@ -249,6 +485,37 @@ impl Driver {
         }
     }
 
+    /// Cancels a pending stale query, if any.
+    fn stale_cancel(&mut self, id: &[u8], address: &Address) {
+        self.stale_queries.retain(|_, queries| {
+            queries.retain(|q| !(&q.address == address && q.id == id));
+            !queries.is_empty()
+        });
+    }
+
+    /// Executes any stale queries whose index has been applied. These don't
+    /// depend on leadership, so they're not aborted on leader changes.
+    fn stale_execute(&mut self, state: &mut dyn State) -> Result<()> {
+        let applied_index = state.get_applied_index();
+        while let Some(entry) = self.stale_queries.first_entry() {
+            if *entry.key() > applied_index {
+                break;
+            }
+            for StaleQuery { id, address, command } in entry.remove() {
+                debug!("Executing stale query {:?}", command);
+                let result = state.query(command);
+                if let Err(error @ Error::Internal(_)) = result {
+                    return Err(error);
+                }
+                self.send(
+                    address,
+                    Event::ClientResponse { id, response: result.map(Response::Query) },
+                )?;
+            }
+        }
+        Ok(())
+    }
+
     /// Sends a message.
     fn send(&self, to: Address, event: Event) -> Result<()> {
         // TODO: This needs to use the correct term.
@@ -261,13 +528,17 @@ impl Driver {
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::storage::bincode;
     use pretty_assertions::assert_eq;
     use std::sync::{Arc, Mutex};
+    use tokio_stream::wrappers::UnboundedReceiverStream;
+    use tokio_stream::StreamExt as _;
 
     #[derive(Clone, Debug)]
     pub struct TestState {
         commands: Arc<Mutex<Vec<Vec<u8>>>>,
         applied_index: Arc<Mutex<Index>>,
+        batches: Arc<Mutex<Vec<usize>>>,
     }
 
     impl TestState {
@@ -275,12 +546,18 @@ pub mod tests {
             Self {
                 commands: Arc::new(Mutex::new(Vec::new())),
                 applied_index: Arc::new(Mutex::new(applied_index)),
+                batches: Arc::new(Mutex::new(Vec::new())),
             }
         }
 
         pub fn list(&self) -> Vec<Vec<u8>> {
             self.commands.lock().unwrap().clone()
         }
+
+        /// Returns the sizes of the applied batches.
+        pub fn batches(&self) -> Vec<usize> {
+            self.batches.lock().unwrap().clone()
+        }
     }
 
     impl State for TestState {
@@ -297,11 +574,34 @@ pub mod tests {
             Ok(entry.command.unwrap_or_default())
         }
 
+        // Records the batch size, and applies the entries.
+        fn apply_batch(&mut self, entries: Vec<Entry>) -> Result<Vec<Result<Vec<u8>>>> {
+            self.batches.lock()?.push(entries.len());
+            Ok(entries.into_iter().map(|entry| self.apply(entry)).collect())
+        }
+
         // Appends the command to the internal commands list.
         fn query(&self, command: Vec<u8>) -> Result<Vec<u8>> {
             self.commands.lock()?.push(command.clone());
             Ok(command)
         }
+
+        // Returns the number of commands.
+        fn checksum(&self) -> Result<u32> {
+            Ok(self.commands.lock()?.len() as u32)
+        }
+
+        // Encodes the command list.
+        fn snapshot(&self) -> Result<Vec<u8>> {
+            bincode::serialize(&*self.commands.lock()?)
+        }
+
+        // Replaces the command list.
+        fn restore(&mut self, index: Index, snapshot: Vec<u8>) -> Result<()> {
+            *self.commands.lock()? = bincode::deserialize(&snapshot)?;
+            *self.applied_index.lock()? = index;
+            Ok(())
+        }
     }
 
     async fn setup() -> Result<(
@@ -312,7 +612,9 @@ pub mod tests {
         let state = Box::new(TestState::new(0));
         let (state_tx, state_rx) = mpsc::unbounded_channel();
         let (node_tx, node_rx) = mpsc::unbounded_channel();
-        tokio::spawn(Driver::new(1, state_rx, node_tx).drive(state.clone()));
+        let driver = Driver::new(1, state_rx, node_tx);
+        let driver_state = state.clone();
+        tokio::task::spawn_blocking(move || driver.drive(driver_state));
         Ok((state, state_tx, node_rx))
     }
 
@@ -361,6 +663,61 @@ pub mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn driver_cancel() -> Result<()> {
+        let (state, state_tx, node_rx) = setup().await?;
+
+        // Cancelled operations are dropped, but the entry is still applied. A
+        // cancellation from a different address is ignored.
+        state_tx.send(Instruction::Notify {
+            id: vec![0x01],
+            index: 1,
+            address: Address::Client,
+        })?;
+        state_tx.send(Instruction::Notify {
+            id: vec![0x02],
+            index: 2,
+            address: Address::Client,
+        })?;
+        state_tx.send(Instruction::Query {
+            id: vec![0x03],
+            address: Address::Client,
+            command: vec![0xf0],
+            term: 1,
+            index: 1,
+            quorum: 1,
+        })?;
+        state_tx.send(Instruction::Cancel { id: vec![0x01], address: Address::Client })?;
+        state_tx.send(Instruction::Cancel { id: vec![0x02], address: Address::Node(2) })?;
+        state_tx.send(Instruction::Cancel { id: vec![0x03], address: Address::Client })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 1, term: 1, command: Some(vec![0xaf]) },
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 2, term: 1, command: Some(vec![0xbf]) },
+        })?;
+        state_tx.send(Instruction::Vote { term: 1, index: 1, address: Address::Node(1) })?;
+        std::mem::drop(state_tx);
+
+        let node_rx = UnboundedReceiverStream::new(node_rx);
+        assert_eq!(
+            node_rx.collect::<Vec<_>>().await,
+            vec![Message {
+                from: Address::Node(1),
+                to: Address::Client,
+                term: 0,
+                event: Event::ClientResponse {
+                    id: vec![0x02],
+                    response: Ok(Response::Mutate(vec![0xbf]))
+                }
+            }]
+        );
+        assert_eq!(state.list(), vec![vec![0xaf], vec![0xbf]]);
+        assert_eq!(state.get_applied_index(), 2);
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn driver_apply() -> Result<()> {
         let (state, state_tx, node_rx) = setup().await?;
@@ -395,6 +752,142 @@ pub mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn driver_apply_batch() -> Result<()> {
+        let state = Box::new(TestState::new(0));
+        let (state_tx, state_rx) = mpsc::unbounded_channel();
+        let (node_tx, node_rx) = mpsc::unbounded_channel();
+        let mut driver = Driver::new(1, state_rx, node_tx);
+        driver.checksum_interval = 4;
+
+        // Queued entries are applied in batches, which end at other instructions
+        // and checksum indexes.
+        for index in 1..=2 {
+            state_tx.send(Instruction::Apply {
+                entry: Entry { index, term: 1, command: Some(vec![index as u8]) },
+            })?;
+        }
+        state_tx.send(Instruction::Notify {
+            id: vec![0x01],
+            index: 3,
+            address: Address::Client,
+        })?;
+        for index in 3..=5 {
+            state_tx.send(Instruction::Apply {
+                entry: Entry { index, term: 1, command: Some(vec![index as u8]) },
+            })?;
+        }
+        std::mem::drop(state_tx);
+        let driver_state = state.clone();
+        tokio::task::spawn_blocking(move || driver.drive(driver_state)).await??;
+
+        let node_rx = UnboundedReceiverStream::new(node_rx);
+        assert_eq!(
+            node_rx.collect::<Vec<_>>().await,
+            vec![
+                Message {
+                    from: Address::Node(1),
+                    to: Address::Client,
+                    term: 0,
+                    event: Event::ClientResponse {
+                        id: vec![0x01],
+                        response: Ok(Response::Mutate(vec![0x03]))
+                    }
+                },
+                Message {
+                    from: Address::Node(1),
+                    to: Address::Broadcast,
+                    term: 0,
+                    event: Event::StateChecksum { index: 4, checksum: 4 },
+                },
+            ]
+        );
+        assert_eq!(state.batches(), vec![2, 2, 1]);
+        assert_eq!(state.get_applied_index(), 5);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn driver_snapshot() -> Result<()> {
+        let state = Box::new(TestState::new(0));
+        let (state_tx, state_rx) = mpsc::unbounded_channel();
+        let (node_tx, node_rx) = mpsc::unbounded_channel();
+        let mut driver = Driver::new(1, state_rx, node_tx);
+        driver.set_snapshot_interval(2);
+
+        // The state is snapshotted at multiples of the interval, and sent to
+        // the local node for log compaction.
+        for index in 1..=3 {
+            state_tx.send(Instruction::Apply {
+                entry: Entry { index, term: 1, command: Some(vec![index as u8]) },
+            })?;
+        }
+        std::mem::drop(state_tx);
+        let driver_state = state.clone();
+        tokio::task::spawn_blocking(move || driver.drive(driver_state)).await??;
+
+        let node_rx = UnboundedReceiverStream::new(node_rx);
+        assert_eq!(
+            node_rx.collect::<Vec<_>>().await,
+            vec![Message {
+                from: Address::Node(1),
+                to: Address::Node(1),
+                term: 0,
+                event: Event::CompactLog {
+                    index: 2,
+                    snapshot: bincode::serialize(&vec![vec![0x01u8], vec![0x02u8]])?,
+                },
+            }]
+        );
+        assert_eq!(state.batches(), vec![2, 1]);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn driver_restore() -> Result<()> {
+        let (state, state_tx, node_rx) = setup().await?;
+
+        // Restoring a snapshot replaces the state, and runs stale queries that
+        // are now satisfied. Entries after it are applied as usual.
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 1, term: 1, command: Some(vec![0x01]) },
+        })?;
+        state_tx.send(Instruction::StaleQuery {
+            id: vec![0x01],
+            address: Address::Client,
+            command: vec![0xf1],
+            index: 3,
+        })?;
+        state_tx.send(Instruction::Restore {
+            index: 3,
+            snapshot: bincode::serialize(&vec![vec![0x0au8], vec![0x0bu8]])?,
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 4, term: 2, command: Some(vec![0x04]) },
+        })?;
+        std::mem::drop(state_tx);
+
+        let node_rx = UnboundedReceiverStream::new(node_rx);
+        assert_eq!(
+            node_rx.collect::<Vec<_>>().await,
+            vec![Message {
+                from: Address::Node(1),
+                to: Address::Client,
+                term: 0,
+                event: Event::ClientResponse {
+                    id: vec![0x01],
+                    response: Ok(Response::Query(vec![0xf1]))
+                }
+            }]
+        );
+        assert_eq!(state.list(), vec![vec![0x0a], vec![0x0b], vec![0xf1], vec![0x04]]);
+        assert_eq!(state.get_applied_index(), 4);
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn driver_query() -> Result<()> {
         let (_, state_tx, node_rx) = setup().await?;
@@ -431,6 +924,143 @@ pub mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn driver_stale_query() -> Result<()> {
+        let (_, state_tx, node_rx) = setup().await?;
+
+        // Stale queries run when their index is applied, regardless of votes
+        // and leader changes.
+        state_tx.send(Instruction::Apply { entry: Entry { index: 1, term: 1, command: None } })?;
+        state_tx.send(Instruction::StaleQuery {
+            id: vec![0x01],
+            address: Address::Client,
+            command: vec![0xf1],
+            index: 1,
+        })?;
+        state_tx.send(Instruction::StaleQuery {
+            id: vec![0x02],
+            address: Address::Client,
+            command: vec![0xf2],
+            index: 2,
+        })?;
+        state_tx.send(Instruction::Abort)?;
+        state_tx.send(Instruction::Apply { entry: Entry { index: 2, term: 2, command: None } })?;
+        std::mem::drop(state_tx);
+
+        let node_rx = UnboundedReceiverStream::new(node_rx);
+        assert_eq!(
+            node_rx.collect::<Vec<_>>().await,
+            vec![
+                Message {
+                    from: Address::Node(1),
+                    to: Address::Client,
+                    term: 0,
+                    event: Event::ClientResponse {
+                        id: vec![0x01],
+                        response: Ok(Response::Query(vec![0xf1]))
+                    }
+                },
+                Message {
+                    from: Address::Node(1),
+                    to: Address::Client,
+                    term: 0,
+                    event: Event::ClientResponse {
+                        id: vec![0x02],
+                        response: Ok(Response::Query(vec![0xf2]))
+                    }
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn driver_checksum() -> Result<()> {
+        let state = Box::new(TestState::new(0));
+        let (state_tx, state_rx) = mpsc::unbounded_channel();
+        let (node_tx, node_rx) = mpsc::unbounded_channel();
+        let mut driver = Driver::new(1, state_rx, node_tx);
+        driver.checksum_interval = 2;
+        let driver_state = state.clone();
+        tokio::task::spawn_blocking(move || driver.drive(driver_state));
+
+        // Peer checksums for unapplied indexes are compared once applied.
+        state_tx.send(Instruction::Checksum { peer: 2, index: 2, checksum: 2 })?;
+        state_tx.send(Instruction::Checksum { peer: 3, index: 2, checksum: 7 })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 1, term: 1, command: Some(vec![0x01]) },
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 2, term: 1, command: Some(vec![0x02]) },
+        })?;
+
+        // Peer checksums for applied indexes are compared immediately.
+        state_tx.send(Instruction::Checksum { peer: 2, index: 2, checksum: 9 })?;
+        state_tx.send(Instruction::Apply { entry: Entry { index: 3, term: 1, command: None } })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 4, term: 1, command: Some(vec![0x04]) },
+        })?;
+        state_tx.send(Instruction::Checksum { peer: 3, index: 4, checksum: 3 })?;
+
+        // Mismatches are reported in the status.
+        let status = Status {
+            server: 1,
+            leader: 1,
+            term: 1,
+            node_last_index: HashMap::new(),
+            commit_index: 4,
+            apply_index: 0,
+            storage: "memory".into(),
+            storage_size: 0,
+            diverged: false,
+            checksum_mismatches: 0,
+            history: Vec::new(),
+        };
+        state_tx.send(Instruction::Status {
+            id: vec![0x01],
+            address: Address::Client,
+            status: Box::new(status.clone()),
+        })?;
+        std::mem::drop(state_tx);
+
+        let node_rx = UnboundedReceiverStream::new(node_rx);
+        assert_eq!(
+            node_rx.collect::<Vec<_>>().await,
+            vec![
+                Message {
+                    from: Address::Node(1),
+                    to: Address::Broadcast,
+                    term: 0,
+                    event: Event::StateChecksum { index: 2, checksum: 2 },
+                },
+                Message {
+                    from: Address::Node(1),
+                    to: Address::Broadcast,
+                    term: 0,
+                    event: Event::StateChecksum { index: 4, checksum: 3 },
+                },
+                Message {
+                    from: Address::Node(1),
+                    to: Address::Client,
+                    term: 0,
+                    event: Event::ClientResponse {
+                        id: vec![0x01],
+                        response: Ok(Response::Status(Status {
+                            apply_index: 4,
+                            diverged: true,
+                            checksum_mismatches: 2,
+                            history: Vec::new(),
+                            ..status
+                        })),
+                    },
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
     // A query for an index submitted in a given term cannot be satisfied by votes below that term.
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn driver_query_noterm() -> Result<()> {
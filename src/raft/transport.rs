@@ -0,0 +1,199 @@
+use super::{Address, Message, NodeID};
+use crate::clock::{HybridClock, Timestamp};
+use crate::error::Result;
+use crate::protocol;
+use crate::storage::bincode;
+
+use ::log::{debug, error};
+use futures::future::BoxFuture;
+use futures::{sink::SinkExt as _, FutureExt as _};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream, UnboundedReceiverStream};
+use tokio_stream::StreamExt as _;
+use tokio_util::bytes::Bytes;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+/// A peer network transport, which carries Raft messages between nodes.
+pub trait Transport: Send {
+    /// Sends outbound messages from out_rx to peers, and delivers inbound
+    /// messages from peers to in_tx, until out_rx is closed or the transport
+    /// fails. Messages may be lost, e.g. while a peer is unreachable.
+    fn serve(
+        self: Box<Self>,
+        out_rx: mpsc::UnboundedReceiver<Message>,
+        in_tx: mpsc::UnboundedSender<Message>,
+    ) -> BoxFuture<'static, Result<()>>;
+}
+
+/// A TCP transport, which listens for inbound peer connections and
+/// continuously connects to peers at the given addresses.
+///
+/// Messages carry a timestamp from the local hybrid logical clock, and
+/// received timestamps update it, such that the clocks of all nodes follow
+/// the causality of messages between them.
+pub struct Tcp {
+    listener: TcpListener,
+    peers: HashMap<NodeID, String>,
+    clock: HybridClock,
+}
+
+impl Tcp {
+    /// Creates a new TCP transport, using a hybrid logical clock based on the
+    /// system clock.
+    pub fn new(listener: TcpListener, peers: HashMap<NodeID, String>) -> Self {
+        Self { listener, peers, clock: HybridClock::system() }
+    }
+
+    /// Sets the hybrid logical clock to timestamp messages with.
+    pub fn with_clock(mut self, clock: HybridClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Receives inbound messages from peers via TCP.
+    async fn receive(
+        listener: TcpListener,
+        in_tx: mpsc::UnboundedSender<Message>,
+        clock: HybridClock,
+    ) -> Result<()> {
+        let mut listener = TcpListenerStream::new(listener);
+        while let Some(socket) = listener.try_next().await? {
+            let peer = socket.peer_addr()?;
+            let peer_in_tx = in_tx.clone();
+            let clock = clock.clone();
+            tokio::spawn(async move {
+                debug!("Raft peer {} connected", peer);
+                match Self::receive_peer(socket, peer_in_tx, clock).await {
+                    Ok(()) => debug!("Raft peer {} disconnected", peer),
+                    Err(err) => error!("Raft peer {} error: {}", peer, err.to_string()),
+                };
+            });
+        }
+        Ok(())
+    }
+
+    /// Receives inbound messages from a peer via TCP, updating the clock with
+    /// their timestamps.
+    async fn receive_peer(
+        socket: TcpStream,
+        in_tx: mpsc::UnboundedSender<Message>,
+        clock: HybridClock,
+    ) -> Result<()> {
+        let (mut framed, version) = protocol::accept(socket).await?;
+        debug!("Using protocol version {}", version);
+        while let Some(frame) = framed.try_next().await? {
+            let (timestamp, message): (Timestamp, Message) = bincode::deserialize(&frame)?;
+            clock.update(timestamp);
+            in_tx.send(message)?;
+        }
+        Ok(())
+    }
+
+    /// Sends outbound messages to peers via TCP.
+    async fn send(
+        peers: HashMap<NodeID, String>,
+        out_rx: mpsc::UnboundedReceiver<Message>,
+        clock: HybridClock,
+    ) -> Result<()> {
+        let mut out_rx = UnboundedReceiverStream::new(out_rx);
+        let mut peer_txs: HashMap<NodeID, mpsc::Sender<Message>> = HashMap::new();
+
+        for (id, addr) in peers.into_iter() {
+            let (tx, rx) = mpsc::channel::<Message>(1000);
+            peer_txs.insert(id, tx);
+            tokio::spawn(Self::send_peer(addr, rx, clock.clone()));
+        }
+
+        while let Some(message) = out_rx.next().await {
+            let to = match message.to {
+                Address::Broadcast => peer_txs.keys().copied().collect(),
+                Address::Node(peer) => vec![peer],
+                addr => {
+                    error!("Received outbound message for non-TCP address {:?}", addr);
+                    continue;
+                }
+            };
+            for id in to {
+                #[cfg(feature = "fault-injection")]
+                if crate::fault::intercept_message(&message, id, peer_txs.get(&id)) {
+                    continue;
+                }
+                match peer_txs.get_mut(&id) {
+                    Some(tx) => match tx.try_send(message.clone()) {
+                        Ok(()) => {}
+                        Err(mpsc::error::TrySendError::Full(_)) => {
+                            debug!("Full send buffer for peer {}, discarding message", id)
+                        }
+                        Err(error) => return Err(error.into()),
+                    },
+                    None => error!("Received outbound message for unknown peer {}", id),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends outbound messages to a peer, continuously reconnecting.
+    async fn send_peer(addr: String, out_rx: mpsc::Receiver<Message>, clock: HybridClock) {
+        let mut out_rx = ReceiverStream::new(out_rx);
+        loop {
+            match Self::connect_peer(&addr).await {
+                Ok(socket) => {
+                    debug!("Connected to Raft peer {}", addr);
+                    match Self::send_peer_session(socket, &mut out_rx, &clock).await {
+                        Ok(()) => break,
+                        Err(err) => error!("Failed sending to Raft peer {}: {}", addr, err),
+                    }
+                }
+                Err(err) => error!("Failed connecting to Raft peer {}: {}", addr, err),
+            }
+            tokio::time::sleep(Duration::from_millis(1000)).await;
+        }
+        debug!("Disconnected from Raft peer {}", addr);
+    }
+
+    /// Connects to a peer and performs the protocol handshake.
+    async fn connect_peer(addr: &str) -> Result<TcpStream> {
+        let mut socket = TcpStream::connect(addr).await?;
+        let version = protocol::connect(&mut socket).await?;
+        debug!("Using protocol version {} with Raft peer {}", version, addr);
+        Ok(socket)
+    }
+
+    /// Sends outbound messages to a peer via a TCP session, timestamping them
+    /// with the clock.
+    async fn send_peer_session(
+        socket: TcpStream,
+        out_rx: &mut ReceiverStream<Message>,
+        clock: &HybridClock,
+    ) -> Result<()> {
+        let mut framed = Framed::new(socket, LengthDelimitedCodec::new());
+        while let Some(message) = out_rx.next().await {
+            let frame = bincode::serialize(&(clock.now(), message))?;
+            framed.send(Bytes::from(frame)).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Transport for Tcp {
+    fn serve(
+        self: Box<Self>,
+        out_rx: mpsc::UnboundedReceiver<Message>,
+        in_tx: mpsc::UnboundedSender<Message>,
+    ) -> BoxFuture<'static, Result<()>> {
+        async move {
+            let (task, receiver) =
+                Self::receive(self.listener, in_tx, self.clock.clone()).remote_handle();
+            tokio::spawn(task);
+            let (task, sender) = Self::send(self.peers, out_rx, self.clock).remote_handle();
+            tokio::spawn(task);
+            tokio::try_join!(receiver, sender)?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
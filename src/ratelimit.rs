@@ -0,0 +1,137 @@
+//! Rate limiting of client requests, such that a single runaway client can't
+//! starve the cluster.
+//!
+//! Requests are limited by token buckets, which refill at the limit's rate per
+//! second and hold up to one second's worth of tokens, allowing short bursts.
+//! Each request takes a query token, and is rejected if none are left. The
+//! number of rows a request writes is only known once it has executed, so rows
+//! are taken afterwards and may leave the bucket in debt, in which case further
+//! requests are rejected until it's paid off. Rejected requests aren't executed,
+//! and the client should retry them after the returned delay, like an HTTP 429
+//! Too Many Requests response.
+
+use std::time::{Duration, Instant};
+
+/// Rate limits for client requests. 0 means unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RateLimits {
+    /// Queries per second, per session.
+    pub session_qps: u64,
+    /// Rows written per second, per session.
+    pub session_rows: u64,
+    /// Queries per second, per client host across all of its sessions.
+    pub client_qps: u64,
+    /// Rows written per second, per client host across all of its sessions.
+    pub client_rows: u64,
+}
+
+impl RateLimits {
+    /// Returns whether any limit is set.
+    pub fn enabled(&self) -> bool {
+        *self != Self::default()
+    }
+}
+
+/// A token bucket.
+#[derive(Debug)]
+struct Bucket {
+    /// The available tokens, which are negative when in debt.
+    tokens: f64,
+    /// When the bucket was last refilled.
+    refilled: Instant,
+}
+
+impl Bucket {
+    /// Creates a new, full bucket.
+    fn new() -> Self {
+        Self { tokens: f64::INFINITY, refilled: Instant::now() }
+    }
+
+    /// Refills the bucket at the given rate per second, up to one second's
+    /// worth of tokens. A rate of 0 fills it up, such that it's full once a
+    /// limit is set.
+    fn refill(&mut self, rate: u64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = match rate {
+            0 => f64::INFINITY,
+            rate => (self.tokens + elapsed * rate as f64).min(rate as f64),
+        };
+        self.refilled = now;
+    }
+
+    /// Returns the time until the bucket has at least the given tokens, at the
+    /// given rate, or None if it already does.
+    fn wait(&self, tokens: f64, rate: u64) -> Option<Duration> {
+        (self.tokens < tokens)
+            .then(|| Duration::from_secs_f64((tokens - self.tokens) / rate as f64))
+    }
+}
+
+/// A rate limiter for queries and written rows, e.g. for a session or client
+/// host. Limits are given with each call, such that they can change at runtime.
+#[derive(Debug)]
+pub struct Limiter {
+    queries: Bucket,
+    rows: Bucket,
+}
+
+impl Limiter {
+    /// Creates a new rate limiter.
+    pub fn new() -> Self {
+        Self { queries: Bucket::new(), rows: Bucket::new() }
+    }
+
+    /// Checks whether a query can run under the given queries and rows per
+    /// second limits (0 is unlimited), taking a query token if it can.
+    /// Otherwise, returns the time to wait before retrying.
+    pub fn admit(&mut self, qps: u64, rows: u64) -> Result<(), Duration> {
+        let now = Instant::now();
+        self.queries.refill(qps, now);
+        self.rows.refill(rows, now);
+        let wait = [
+            (qps > 0).then(|| self.queries.wait(1.0, qps)).flatten(),
+            (rows > 0).then(|| self.rows.wait(0.0, rows)).flatten(),
+        ];
+        if let Some(wait) = wait.into_iter().flatten().max() {
+            return Err(wait);
+        }
+        if qps > 0 {
+            self.queries.tokens -= 1.0;
+        }
+        Ok(())
+    }
+
+    /// Records rows written by an admitted query under the given rows per
+    /// second limit, which may put the rows bucket in debt.
+    pub fn written(&mut self, count: u64, rows: u64) {
+        if rows > 0 {
+            self.rows.refill(rows, Instant::now());
+            self.rows.tokens -= count as f64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // Queries are admitted in bursts of up to one second's worth, and written
+    // rows can put the limiter in debt until they're paid off.
+    fn limiter() {
+        let mut limiter = Limiter::new();
+        for _ in 0..10 {
+            assert_eq!(limiter.admit(10, 0), Ok(()));
+        }
+        let wait = limiter.admit(10, 0).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(100), "{:?}", wait);
+        assert_eq!(limiter.admit(0, 0), Ok(()));
+
+        limiter.written(150, 100);
+        let wait = limiter.admit(0, 100).unwrap_err();
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+        std::thread::sleep(wait);
+        assert_eq!(limiter.admit(0, 100), Ok(()));
+        assert_eq!(limiter.admit(0, 0), Ok(()));
+    }
+}
@@ -1,26 +1,233 @@
+use crate::audit;
+use crate::cdc;
+use crate::clock::HybridClock;
 use crate::error::{Error, Result};
+use crate::protocol;
+use crate::queue::{self, Priority};
 use crate::raft;
+use crate::ratelimit::{self, RateLimits};
 use crate::sql;
-use crate::sql::engine::Engine as _;
-use crate::sql::execution::ResultSet;
+use crate::sql::engine::{Engine as _, Transaction as _};
+use crate::sql::execution::{CommandTag, ResultSet};
 use crate::sql::schema::{Catalog as _, Table};
-use crate::sql::types::Row;
+use crate::sql::types::{Row, Value};
+use crate::storage;
 
 use ::log::{debug, error, info};
 use futures::sink::SinkExt as _;
+use futures::FutureExt as _;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch, Notify};
 use tokio_stream::wrappers::TcpListenerStream;
 use tokio_stream::StreamExt as _;
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+/// The interval between background deletions of expired rows.
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The maximum number of expired rows to delete per table and transaction.
+const EXPIRE_BATCH_SIZE: u64 = 100;
+
+/// The interval between background purges of dropped tables.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The default maximum time a request may wait in the query queue.
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The default time to retain dropped tables for before purging them.
+const DROP_RETENTION: Duration = Duration::from_secs(86400);
+
+/// The interval between reads of the cluster settings from the local state.
+const SETTINGS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The number of liveness intervals without a heartbeat after which a node is considered dead.
+const LIVENESS_TIMEOUT_INTERVALS: u32 = 5;
+
+/// The interval between polls of the primary cluster's log on a standby cluster.
+const REPLICATE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The maximum number of primary log entries to replicate in a single Raft proposal.
+const REPLICATE_BATCH_SIZE: usize = 100;
+
+/// The number of query result rows read at a time before sending them to the client. Rows
+/// may be read lazily from the Raft engine, so reads block outside of the async runtime.
+const STREAM_BATCH_SIZE: usize = 100;
+
+/// A request stream to a primary cluster node, for standby replication.
+type PrimaryConnection = tokio_serde::Framed<
+    tokio_util::codec::Framed<TcpStream, tokio_util::codec::LengthDelimitedCodec>,
+    Result<Response>,
+    Request,
+    tokio_serde::formats::Bincode<Result<Response>, Request>,
+>;
 
 /// A toyDB server.
 pub struct Server {
     raft: raft::Server,
     raft_listener: Option<TcpListener>,
+    raft_transport: Option<Box<dyn raft::Transport>>,
     sql_listener: Option<TcpListener>,
+    /// The SQL addresses of peers, to redirect clients to the leader.
+    redirects: HashMap<raft::NodeID, String>,
+    /// The time to retain dropped tables for before purging them, unless overridden by the
+    /// drop_retention cluster setting.
+    drop_retention: Duration,
+    /// The size limits for rows written by clients, unless overridden by cluster settings.
+    limits: sql::engine::Limits,
+    /// The rate limits for client requests, unless overridden by cluster settings.
+    rate_limits: RateLimits,
+    /// The maximum number of concurrently executing requests, or 0 for no limit, and the maximum
+    /// time a request may wait in the query queue.
+    query_queue: (usize, Duration),
+    /// The client hosts whose sessions may use system priority.
+    admin_hosts: Arc<HashSet<IpAddr>>,
+    /// The interval between liveness heartbeats, or zero if disabled.
+    liveness: Duration,
+    /// The SQL address of a primary cluster to replicate as a standby, if any.
+    standby_of: Option<String>,
+    /// The change data capture sink URL and export interval, if any.
+    cdc: Option<(String, Duration)>,
+    /// The audit log, if any.
+    audit: Option<audit::Log>,
+}
+
+/// The server configuration and state that client sessions share.
+#[derive(Clone)]
+struct SessionConfig {
+    redirect: Option<Redirect>,
+    /// The local Raft node's history, returned after status responses.
+    history: raft::History,
+    /// The size limits for rows written by sessions, which may change via
+    /// cluster settings.
+    limits: watch::Receiver<sql::engine::Limits>,
+    /// The rate limits for requests by sessions and client hosts, which may
+    /// change via cluster settings.
+    rate_limits: watch::Receiver<RateLimits>,
+    /// The query queue, which bounds the number of concurrent requests.
+    queue: Arc<queue::Queue>,
+    /// The client hosts whose sessions may use system priority.
+    admin_hosts: Arc<HashSet<IpAddr>>,
+    /// The time without a heartbeat after which nodes are considered dead, or
+    /// zero if liveness heartbeats are disabled.
+    liveness_timeout: Duration,
+    /// The audit log, if any.
+    audit: Option<Arc<Mutex<audit::Log>>>,
+}
+
+/// Redirects clients of a follower to the leader, using the leader known to the
+/// local Raft node and the SQL addresses of peers.
+#[derive(Clone)]
+struct Redirect {
+    leader_rx: watch::Receiver<Option<raft::NodeID>>,
+    addrs: Arc<HashMap<raft::NodeID, String>>,
+}
+
+/// The client sessions connected to a server, by ID, which can be listed and
+/// killed via SHOW SESSIONS and KILL.
+#[derive(Default)]
+struct Sessions {
+    next_id: AtomicU64,
+    sessions: Mutex<BTreeMap<u64, SessionState>>,
+    /// The rate limiters of client hosts with sessions, shared by their
+    /// sessions. toyDB has no users, so clients are identified by their host.
+    clients: Mutex<HashMap<IpAddr, Arc<Mutex<ratelimit::Limiter>>>>,
+}
+
+/// The state of a client session, as tracked by Sessions.
+struct SessionState {
+    client: SocketAddr,
+    /// The session's engine, to cancel its statements.
+    engine: sql::engine::Raft,
+    /// Notified when the session is killed.
+    kill: Arc<Notify>,
+    /// The statement being executed, and when it started.
+    statement: Option<(String, Instant)>,
+    /// The version of the session's transaction, and when it began.
+    transaction: Option<(u64, Instant)>,
+}
+
+impl Sessions {
+    /// Registers a new session, returning its ID, kill notification, and the
+    /// rate limiter of its client host.
+    fn register(
+        &self,
+        client: SocketAddr,
+        engine: sql::engine::Raft,
+    ) -> Result<(u64, Arc<Notify>, Arc<Mutex<ratelimit::Limiter>>)> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let kill = Arc::new(Notify::new());
+        let state =
+            SessionState { client, engine, kill: kill.clone(), statement: None, transaction: None };
+        let mut sessions = self.sessions.lock()?;
+        sessions.insert(id, state);
+        let limiter = self
+            .clients
+            .lock()?
+            .entry(client.ip())
+            .or_insert_with(|| Arc::new(Mutex::new(ratelimit::Limiter::new())))
+            .clone();
+        Ok((id, kill, limiter))
+    }
+
+    /// Removes a session, along with its client host's rate limiter if it has
+    /// no other sessions.
+    fn remove(&self, id: u64) -> Result<()> {
+        let mut sessions = self.sessions.lock()?;
+        if let Some(state) = sessions.remove(&id) {
+            let ip = state.client.ip();
+            if !sessions.values().any(|s| s.client.ip() == ip) {
+                self.clients.lock()?.remove(&ip);
+            }
+        }
+        Ok(())
+    }
+
+    /// Updates a session's state.
+    fn update(&self, id: u64, f: impl FnOnce(&mut SessionState)) -> Result<()> {
+        if let Some(state) = self.sessions.lock()?.get_mut(&id) {
+            f(state)
+        }
+        Ok(())
+    }
+}
+
+impl sql::engine::Admin for Sessions {
+    fn sessions(&self) -> Result<Vec<sql::engine::SessionInfo>> {
+        let now = Instant::now();
+        Ok(self
+            .sessions
+            .lock()?
+            .iter()
+            .map(|(id, state)| sql::engine::SessionInfo {
+                id: *id,
+                client: state.client.to_string(),
+                statement: state.statement.clone().map(|(s, start)| (s, now - start)),
+                transaction: state.transaction.map(|(version, start)| (version, now - start)),
+            })
+            .collect())
+    }
+
+    fn kill(&self, id: u64, query: bool) -> Result<()> {
+        let sessions = self.sessions.lock()?;
+        let state = sessions
+            .get(&id)
+            .ok_or_else(|| Error::Value(format!("Session {} does not exist", id)))?;
+        // Only cancel a running statement, such that the next one isn't
+        // cancelled if the session is idle.
+        if state.statement.is_some() {
+            state.engine.set_cancelled(true)?;
+        }
+        if !query {
+            state.kill.notify_one();
+        }
+        Ok(())
+    }
 }
 
 impl Server {
@@ -34,10 +241,134 @@ impl Server {
         Ok(Server {
             raft: raft::Server::new(id, peers, raft_log, raft_state).await?,
             raft_listener: None,
+            raft_transport: None,
             sql_listener: None,
+            redirects: HashMap::new(),
+            drop_retention: DROP_RETENTION,
+            limits: sql::engine::Limits::default(),
+            rate_limits: RateLimits::default(),
+            query_queue: (0, QUEUE_TIMEOUT),
+            admin_hosts: Arc::default(),
+            liveness: Duration::ZERO,
+            standby_of: None,
+            cdc: None,
+            audit: None,
         })
     }
 
+    /// Creates a single-node server that keeps all data in memory. It becomes
+    /// leader immediately, which makes it quick to start e.g. in tests.
+    pub async fn memory() -> Result<Self> {
+        Self::new(
+            1,
+            HashMap::new(),
+            raft::Log::new(storage::engine::Memory::new(), false)?,
+            Box::new(sql::engine::Raft::new_state(storage::engine::Memory::new())?),
+        )
+        .await
+    }
+
+    /// Enables or disables paranoid mode, which checks Raft invariants at
+    /// runtime. Violations panic in debug builds, and are logged otherwise.
+    pub fn paranoid(mut self, paranoid: bool) -> Self {
+        self.raft.set_paranoid(paranoid);
+        self
+    }
+
+    /// Limits the bandwidth a leader uses to catch up followers that need a
+    /// snapshot or lag far behind, in bytes per second, such that recovery
+    /// traffic doesn't starve foreground writes. 0 means unlimited.
+    pub fn catchup_bandwidth(mut self, bytes_per_sec: u64) -> Self {
+        self.raft.set_catchup_bandwidth(bytes_per_sec);
+        self
+    }
+
+    /// Sets the time to retain dropped tables for, during which they can be
+    /// restored with UNDROP TABLE, before purging them. Defaults to 1 day.
+    pub fn drop_retention(mut self, retention: Duration) -> Self {
+        self.drop_retention = retention;
+        self
+    }
+
+    /// Sets the size limits for rows written by clients, i.e. the maximum row,
+    /// primary key, and index entry sizes. Writes exceeding them are rejected.
+    pub fn limits(mut self, limits: sql::engine::Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the rate limits for client requests, i.e. the maximum queries and
+    /// rows written per second by each session and client host. Requests
+    /// exceeding them fail with Error::RateLimited, and can be retried later.
+    pub fn rate_limits(mut self, limits: RateLimits) -> Self {
+        self.rate_limits = limits;
+        self
+    }
+
+    /// Bounds the number of concurrently executing client requests, or 0 (the
+    /// default) for no limit. Further requests wait in a queue, ordered by their
+    /// session's priority, and fail with Error::Overloaded if they wait longer
+    /// than the timeout. Background jobs run with low priority.
+    pub fn query_queue(mut self, concurrency: usize, timeout: Duration) -> Self {
+        self.query_queue = (concurrency, timeout);
+        self
+    }
+
+    /// Sets the client hosts whose sessions may use system priority, which
+    /// bypasses the query queue, e.g. to KILL runaway sessions when the server
+    /// is overloaded. toyDB has no users, so clients are identified by their
+    /// host. Defaults to none.
+    pub fn admin_hosts(mut self, hosts: HashSet<IpAddr>) -> Self {
+        self.admin_hosts = Arc::new(hosts);
+        self
+    }
+
+    /// Enables node liveness heartbeats at the given interval. The node then
+    /// periodically records a heartbeat in the replicated state, and nodes
+    /// without a heartbeat for LIVENESS_TIMEOUT_INTERVALS intervals are
+    /// reported as dead by Liveness requests. Zero (the default) disables
+    /// heartbeats and Liveness requests.
+    pub fn liveness(mut self, interval: Duration) -> Self {
+        self.liveness = interval;
+        self
+    }
+
+    /// Redirects clients to the leader, given the SQL addresses of the peers
+    /// (the local node's address is ignored if included), instead of
+    /// forwarding their requests to it. Clients reconnect to the leader and
+    /// retry, unless they're in a transaction. Status requests and stale reads
+    /// are still served locally, and log reads by standby clusters are forwarded.
+    pub fn redirect(mut self, addrs: HashMap<raft::NodeID, String>) -> Self {
+        self.redirects = addrs;
+        self
+    }
+
+    /// Runs the cluster as a hot standby of a primary cluster, given the SQL
+    /// address of one of its nodes. The standby's leader continuously pulls
+    /// the primary's committed log and applies it, and the standby only serves
+    /// read-only transactions until it's promoted with a Promote request,
+    /// after which it no longer replicates. Replication is asynchronous, so
+    /// the standby may lag behind the primary.
+    pub fn standby_of(mut self, addr: &str) -> Self {
+        self.standby_of = Some(addr.to_string());
+        self
+    }
+
+    /// Exports committed row changes to a change data capture sink at the
+    /// given interval, see cdc::open() for sink URLs. Only the leader exports
+    /// changes, and a standby cluster doesn't until it's promoted.
+    pub fn cdc(mut self, url: &str, interval: Duration) -> Self {
+        self.cdc = Some((url.to_string(), interval));
+        self
+    }
+
+    /// Records DDL statements and privileged operations in the given audit
+    /// log, see audit::Log.
+    pub fn audit(mut self, log: audit::Log) -> Self {
+        self.audit = Some(log);
+        self
+    }
+
     /// Starts listening on the given ports. Must be called before serve.
     pub async fn listen(mut self, sql_addr: &str, raft_addr: &str) -> Result<Self> {
         let (sql, raft) =
@@ -48,30 +379,405 @@ impl Server {
         Ok(self)
     }
 
+    /// Starts listening for SQL clients on the given port, using the given
+    /// transport instead of TCP to communicate with Raft peers, e.g. a
+    /// simulated network in tests. Must be called before serve, instead of
+    /// listen.
+    pub async fn listen_transport(
+        mut self,
+        sql_addr: &str,
+        transport: Box<dyn raft::Transport>,
+    ) -> Result<Self> {
+        let sql = TcpListener::bind(sql_addr).await?;
+        info!("Listening on {} (SQL)", sql.local_addr()?);
+        self.sql_listener = Some(sql);
+        self.raft_transport = Some(transport);
+        Ok(self)
+    }
+
+    /// Returns the address of the SQL listener, e.g. to find the port when
+    /// listening on port 0.
+    pub fn sql_addr(&self) -> Result<SocketAddr> {
+        match &self.sql_listener {
+            Some(listener) => Ok(listener.local_addr()?),
+            None => Err(Error::Internal("Must listen before getting address".into())),
+        }
+    }
+
     /// Serves Raft and SQL requests until the returned future is dropped. Consumes the server.
     pub async fn serve(self) -> Result<()> {
         let sql_listener = self
             .sql_listener
             .ok_or_else(|| Error::Internal("Must listen before serving".into()))?;
-        let raft_listener = self
-            .raft_listener
-            .ok_or_else(|| Error::Internal("Must listen before serving".into()))?;
         let (raft_tx, raft_rx) = mpsc::unbounded_channel();
-        let sql_engine = sql::engine::Raft::new(raft_tx);
+        let mut redirects = self.redirects;
+        redirects.remove(&self.raft.id());
+        let redirect = (!redirects.is_empty())
+            .then(|| Redirect { leader_rx: self.raft.leader(), addrs: Arc::new(redirects) });
+        let history = self.raft.history();
+        let clock = self.raft.clock();
+        let id = self.raft.id();
+        let (settings_tx, settings_rx) = watch::channel(sql::engine::Settings::default());
+        let (limits_tx, limits_rx) = watch::channel(self.limits);
+        let (rate_limits_tx, rate_limits_rx) = watch::channel(self.rate_limits);
+        let (concurrency, queue_timeout) = self.query_queue;
+        let queue = Arc::new(queue::Queue::new(concurrency, queue_timeout));
+        let raft = match (self.raft_transport, self.raft_listener) {
+            (Some(transport), _) => self.raft.serve_transport(transport, raft_rx).boxed(),
+            (None, Some(listener)) => self.raft.serve(listener, raft_rx).boxed(),
+            (None, None) => return Err(Error::Internal("Must listen before serving".into())),
+        };
 
         tokio::try_join!(
-            self.raft.serve(raft_listener, raft_rx),
-            Self::serve_sql(sql_listener, sql_engine),
+            raft,
+            Self::serve_sql(
+                sql_listener,
+                raft_tx.clone(),
+                SessionConfig {
+                    redirect,
+                    history,
+                    limits: limits_rx,
+                    rate_limits: rate_limits_rx,
+                    queue: queue.clone(),
+                    admin_hosts: self.admin_hosts,
+                    liveness_timeout: self.liveness * LIVENESS_TIMEOUT_INTERVALS,
+                    audit: self.audit.map(|log| Arc::new(Mutex::new(log))),
+                },
+                clock.clone()
+            ),
+            Self::watch_settings(
+                raft_tx.clone(),
+                clock.clone(),
+                settings_tx,
+                self.limits,
+                limits_tx,
+                self.rate_limits,
+                rate_limits_tx
+            ),
+            Self::heartbeat(raft_tx.clone(), clock.clone(), id, self.liveness),
+            Self::expire_rows(raft_tx.clone(), clock.clone(), settings_rx.clone(), queue.clone()),
+            Self::purge_dropped(
+                raft_tx.clone(),
+                self.drop_retention,
+                clock.clone(),
+                settings_rx,
+                queue
+            ),
+            Self::replicate(raft_tx.clone(), clock.clone(), self.standby_of),
+            Self::export_changes(raft_tx, clock, self.cdc),
         )?;
         Ok(())
     }
 
-    /// Serves SQL clients.
-    async fn serve_sql(listener: TcpListener, engine: sql::engine::Raft) -> Result<()> {
+    /// Periodically reads the cluster settings from the local state, and publishes any changes
+    /// to the server's background tasks, along with the resulting row size and rate limits for
+    /// sessions given the server's limits. Every node does this, such that changes take effect
+    /// across the cluster without restarts.
+    async fn watch_settings(
+        raft_tx: sql::engine::raft::RaftTx,
+        clock: HybridClock,
+        settings_tx: watch::Sender<sql::engine::Settings>,
+        limits: sql::engine::Limits,
+        limits_tx: watch::Sender<sql::engine::Limits>,
+        rate_limits: RateLimits,
+        rate_limits_tx: watch::Sender<RateLimits>,
+    ) -> Result<()> {
+        let engine = sql::engine::Raft::new(raft_tx).with_clock(clock);
+        engine.set_stale_reads(Some(0))?;
+        let mut ticker = tokio::time::interval(SETTINGS_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let result = tokio::task::block_in_place(|| {
+                let txn = engine.begin_read_only()?;
+                let settings = txn.scan_settings();
+                txn.rollback()?;
+                settings
+            });
+            match result {
+                Ok(settings) => {
+                    let settings = sql::engine::Settings::new(settings);
+                    limits_tx.send_if_modified(|current| {
+                        let limits = settings.limits(limits);
+                        std::mem::replace(current, limits) != limits
+                    });
+                    rate_limits_tx.send_if_modified(|current| {
+                        let limits = settings.rate_limits(rate_limits);
+                        std::mem::replace(current, limits) != limits
+                    });
+                    settings_tx.send_if_modified(|current| {
+                        if *current == settings {
+                            return false;
+                        }
+                        info!("Cluster settings changed: {:?}", settings);
+                        *current = settings;
+                        true
+                    });
+                }
+                Err(err) => debug!("Failed to read cluster settings: {}", err),
+            }
+        }
+    }
+
+    /// Periodically records a liveness heartbeat for the local node, unless the
+    /// interval is zero. Each heartbeat times out after the interval.
+    async fn heartbeat(
+        raft_tx: sql::engine::raft::RaftTx,
+        clock: HybridClock,
+        id: raft::NodeID,
+        interval: Duration,
+    ) -> Result<()> {
+        if interval.is_zero() {
+            return futures::future::pending().await;
+        }
+        let engine = sql::engine::Raft::new(raft_tx).with_clock(clock);
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let result = tokio::task::block_in_place(|| {
+                engine.set_deadline(Some(Instant::now() + interval))?;
+                engine.heartbeat(id)
+            });
+            if let Err(err) = result {
+                debug!("Failed to record liveness heartbeat: {}", err);
+            }
+        }
+    }
+
+    /// Periodically deletes expired rows from tables with a TTL, in batches, unless disabled by
+    /// the ttl_expiry cluster setting. Only the leader does this, to avoid conflicting deletes
+    /// from several nodes. It runs with low priority in the query queue, and skips a round if
+    /// the queue times out.
+    async fn expire_rows(
+        raft_tx: sql::engine::raft::RaftTx,
+        clock: HybridClock,
+        settings: watch::Receiver<sql::engine::Settings>,
+        queue: Arc<queue::Queue>,
+    ) -> Result<()> {
+        let engine = sql::engine::Raft::new(raft_tx).with_clock(clock);
+        // Look for tables with a TTL in the local state, to avoid appending Raft log entries
+        // for the read-only transaction.
+        engine.set_stale_reads(Some(0))?;
+        let mut session = engine.session()?;
+        let mut interval = tokio::time::interval(EXPIRE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !settings.borrow().ttl_expiry() {
+                continue;
+            }
+            let result: Result<u64> = tokio::task::block_in_place(|| {
+                let status = engine.status()?;
+                if status.raft.leader != status.raft.server || Self::is_following(&engine)? {
+                    return Ok(0);
+                }
+                let _permit = queue.admit(Priority::Low, None)?;
+                Self::for_each_tenant(&mut session, |session| {
+                    let mut count = 0;
+                    loop {
+                        match session.expire(EXPIRE_BATCH_SIZE)? {
+                            0 => return Ok(count),
+                            deleted => count += deleted,
+                        }
+                    }
+                })
+            });
+            match result {
+                Ok(0) => {}
+                Ok(count) => info!("Deleted {} expired rows", count),
+                Err(Error::Overloaded) => debug!("Skipped expiring rows, query queue is full"),
+                Err(err) => error!("Failed to delete expired rows: {}", err),
+            }
+        }
+    }
+
+    /// Periodically purges tables that were dropped longer than the retention ago, or the
+    /// drop_retention cluster setting if set. Only the leader does this, with low priority in
+    /// the query queue, like expire_rows.
+    async fn purge_dropped(
+        raft_tx: sql::engine::raft::RaftTx,
+        retention: Duration,
+        clock: HybridClock,
+        settings: watch::Receiver<sql::engine::Settings>,
+        queue: Arc<queue::Queue>,
+    ) -> Result<()> {
+        let engine = sql::engine::Raft::new(raft_tx).with_clock(clock);
+        engine.set_stale_reads(Some(0))?;
+        let mut session = engine.session()?;
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let retention = settings.borrow().drop_retention(retention);
+            let result: Result<u64> = tokio::task::block_in_place(|| {
+                let status = engine.status()?;
+                if status.raft.leader != status.raft.server || Self::is_following(&engine)? {
+                    return Ok(0);
+                }
+                let _permit = queue.admit(Priority::Low, None)?;
+                Self::for_each_tenant(&mut session, |session| {
+                    session.purge_dropped(retention.as_secs())
+                })
+            });
+            match result {
+                Ok(0) => {}
+                Ok(count) => info!("Purged {} dropped tables", count),
+                Err(Error::Overloaded) => debug!("Skipped purging tables, query queue is full"),
+                Err(err) => error!("Failed to purge dropped tables: {}", err),
+            }
+        }
+    }
+
+    /// Periodically exports row changes to the change data capture sink, if
+    /// any. Only the leader does this, like expire_rows.
+    async fn export_changes(
+        raft_tx: sql::engine::raft::RaftTx,
+        clock: HybridClock,
+        cdc: Option<(String, Duration)>,
+    ) -> Result<()> {
+        let Some((url, interval)) = cdc else {
+            return futures::future::pending().await;
+        };
+        let engine = sql::engine::Raft::new(raft_tx).with_clock(clock);
+        let mut exporter = None;
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let result: Result<u64> = tokio::task::block_in_place(|| {
+                let status = engine.status()?;
+                if status.raft.leader != status.raft.server || Self::is_following(&engine)? {
+                    return Ok(0);
+                }
+                // Open the sink lazily, such that it's retried on failure.
+                if exporter.is_none() {
+                    exporter = Some(cdc::Exporter::new(engine.clone(), &url)?);
+                }
+                exporter.as_mut().expect("exporter not set").export()
+            });
+            match result {
+                Ok(0) => {}
+                Ok(count) => debug!("Exported {} row changes to {}", count, url),
+                Err(err) => error!("Failed to export row changes to {}: {}", url, err),
+            }
+        }
+    }
+
+    /// Returns whether the cluster is a standby following a primary, which
+    /// replicates the primary's expiry and purges instead of doing its own.
+    fn is_following(engine: &sql::engine::Raft) -> Result<bool> {
+        Ok(matches!(engine.standby()?, Some(sql::engine::Standby::Following(_))))
+    }
+
+    /// Runs a background task for the system tenant and then each other tenant, switching the
+    /// session to it, and returns the sum of the results.
+    fn for_each_tenant(
+        session: &mut sql::engine::Session<sql::engine::Raft>,
+        mut f: impl FnMut(&mut sql::engine::Session<sql::engine::Raft>) -> Result<u64>,
+    ) -> Result<u64> {
+        session.set_tenant(None)?;
+        let tenants = session.read_with_txn(|txn| txn.scan_tenants())?;
+        let mut count = f(session)?;
+        for tenant in tenants {
+            session.set_tenant(Some(tenant.name))?;
+            count += f(session)?;
+        }
+        session.set_tenant(None)?;
+        Ok(count)
+    }
+
+    /// Replicates the primary cluster's log if the cluster is a standby, see
+    /// standby_of(). Only the leader does this, and it stops once the cluster
+    /// has been promoted.
+    async fn replicate(
+        raft_tx: sql::engine::raft::RaftTx,
+        clock: HybridClock,
+        primary: Option<String>,
+    ) -> Result<()> {
+        let Some(primary) = primary else {
+            return futures::future::pending().await;
+        };
+        let engine = sql::engine::Raft::new(raft_tx).with_clock(clock);
+        let mut interval = tokio::time::interval(REPLICATE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let result: Result<Option<raft::Index>> = tokio::task::block_in_place(|| {
+                let status = engine.status()?;
+                if status.raft.leader != status.raft.server {
+                    return Ok(None);
+                }
+                match engine.standby()? {
+                    Some(sql::engine::Standby::Following(index)) => Ok(Some(index)),
+                    Some(sql::engine::Standby::Promoted(_)) => Ok(None),
+                    // Mark the cluster as a standby before replicating anything.
+                    None => engine.replicate(Vec::new()).map(Some),
+                }
+            });
+            let mut index = match result {
+                Ok(Some(index)) => index,
+                Ok(None) => continue,
+                Err(err) => {
+                    error!("Failed to replicate primary {}: {}", primary, err);
+                    continue;
+                }
+            };
+            if let Err(err) = Self::replicate_from(&engine, &primary, &mut index).await {
+                error!("Failed to replicate primary {} at index {}: {}", primary, index, err);
+            }
+        }
+    }
+
+    /// Replicates the primary cluster's log after the given index until the
+    /// standby has caught up, updating the index as entries are applied.
+    async fn replicate_from(
+        engine: &sql::engine::Raft,
+        primary: &str,
+        index: &mut raft::Index,
+    ) -> Result<()> {
+        let mut stream = Self::connect_primary(primary).await?;
+        loop {
+            let request = Request::ReadLog { from: *index + 1, limit: REPLICATE_BATCH_SIZE };
+            stream.send(request).await?;
+            let (snapshot, entries) = match stream.try_next().await? {
+                Some(Ok(Response::Log { snapshot, entries })) => (snapshot, entries),
+                Some(Err(err)) => return Err(err),
+                Some(Ok(response)) => {
+                    return Err(Error::Internal(format!("Unexpected response {:?}", response)))
+                }
+                None => return Err(Error::Internal("Primary disconnected".into())),
+            };
+            if snapshot.is_none() && entries.is_empty() {
+                return Ok(());
+            }
+            *index = tokio::task::block_in_place(|| -> Result<raft::Index> {
+                if let Some(snapshot) = snapshot {
+                    info!("Restoring primary snapshot at index {}", snapshot.index);
+                    engine.replicate_snapshot(snapshot)?;
+                }
+                engine.replicate(entries)
+            })?;
+        }
+    }
+
+    /// Connects to a primary cluster node, returning a request stream. Log
+    /// reads are forwarded to the primary's leader, so any node will do.
+    async fn connect_primary(addr: &str) -> Result<PrimaryConnection> {
+        let mut socket = TcpStream::connect(addr).await?;
+        protocol::connect(&mut socket).await?;
+        let framed = tokio_util::codec::Framed::new(socket, Default::default());
+        Ok(tokio_serde::Framed::new(framed, tokio_serde::formats::Bincode::default()))
+    }
+
+    /// Serves SQL clients. Each session gets its own engine, since request
+    /// deadlines are set per engine.
+    async fn serve_sql(
+        listener: TcpListener,
+        raft_tx: sql::engine::raft::RaftTx,
+        config: SessionConfig,
+        clock: HybridClock,
+    ) -> Result<()> {
         let mut listener = TcpListenerStream::new(listener);
+        let sessions = Arc::new(Sessions::default());
         while let Some(socket) = listener.try_next().await? {
             let peer = socket.peer_addr()?;
-            let session = Session::new(engine.clone())?;
+            let engine = sql::engine::Raft::new(raft_tx.clone()).with_clock(clock.clone());
+            let session = Session::new(engine, peer, sessions.clone(), config.clone())?;
             tokio::spawn(async move {
                 info!("Client {} connected", peer);
                 match session.handle(socket).await {
@@ -85,75 +791,443 @@ impl Server {
 }
 
 /// A client request.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Request {
     Execute(String),
     GetTable(String),
     ListTables,
     Status,
+    /// Key/value requests run in the session's transaction, if any.
+    KVGet {
+        namespace: String,
+        key: Vec<u8>,
+    },
+    KVSet {
+        namespace: String,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    KVDelete {
+        namespace: String,
+        key: Vec<u8>,
+    },
+    KVScan {
+        namespace: String,
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    },
+    /// Runs the request with a timeout, after which it fails with
+    /// Error::Timeout.
+    Timeout(Duration, Box<Request>),
+    /// Runs the request with stale reads served by the local node, seeing at
+    /// least all writes up to the given session token. The responses are
+    /// followed by a Token response with the token to use for subsequent
+    /// requests, covering the request's writes.
+    Stale(raft::Index, Box<Request>),
+    /// Executes a string of semicolon-separated statements in order, stopping at the first
+    /// error. Each result is sent as for Execute, followed by a single Transaction response.
+    ExecuteMany(String),
+    /// Fetches the liveness of all cluster nodes.
+    Liveness,
+    /// Reads up to limit committed Raft log entries from the given index, for a standby
+    /// cluster to replicate.
+    ReadLog {
+        from: raft::Index,
+        limit: usize,
+    },
+    /// Promotes a standby cluster to a primary.
+    Promote,
+    /// Fetches the server's metrics.
+    Metrics,
 }
 
 /// A server response.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Response {
-    Execute(ResultSet),
+    /// The result of a statement, along with the server-side execution time. Query rows are
+    /// streamed as subsequent Row responses, and are not included in the execution time.
+    Execute(ResultSet, Duration),
+    /// A query result row, sent in result order, see protocol.
     Row(Option<Row>),
+    /// The session's transaction status, sent after every Execute result (including rows and
+    /// errors).
+    Transaction(Option<sql::engine::TransactionStatus>),
     GetTable(Table),
     ListTables(Vec<String>),
     Status(sql::engine::Status),
+    KVGet(Option<Vec<u8>>),
+    KVSet,
+    KVDelete,
+    KVScan(Vec<(Vec<u8>, Vec<u8>)>),
+    /// The session token after a Stale request.
+    Token(raft::Index),
+    /// Ends query rows instead of Row(None), with the primary key of the last row if the query
+    /// returns a single table's primary key column.
+    RowsEnd(Option<Value>),
+    /// The server's recent Raft events, sent after every Status response (including errors).
+    History(Vec<raft::HistoryEntry>),
+    Liveness(Vec<sql::engine::NodeLiveness>),
+    /// The command tag of an executed statement, sent after its Execute result and any rows,
+    /// unless the rows ended with an error.
+    Complete(CommandTag),
+    /// Committed Raft log entries, preceded by a snapshot if they were compacted.
+    Log {
+        snapshot: Option<raft::Snapshot>,
+        entries: Vec<raft::Entry>,
+    },
+    /// The last applied primary log index of a promoted standby.
+    Promote(raft::Index),
+    Metrics(Metrics),
+}
+
+/// Server metrics, local to the connected server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Metrics {
+    /// The memory used by SQL statements for buffered rows, see sql::execution::Memory.
+    pub memory: sql::execution::MemoryMetrics,
 }
 
 /// A client session coupled to a SQL session.
 pub struct Session {
     engine: sql::engine::Raft,
     sql: sql::engine::Session<sql::engine::Raft>,
+    /// The session's ID in the server's sessions.
+    id: u64,
+    /// The client's network address.
+    client: SocketAddr,
+    /// The server's sessions, where the session tracks its state.
+    sessions: Arc<Sessions>,
+    /// Notified when the session is killed.
+    kill: Arc<Notify>,
+    redirect: Option<Redirect>,
+    /// The local Raft node's history, returned after status responses.
+    history: raft::History,
+    /// The size limits for rows written by the session, which may change
+    /// via cluster settings.
+    limits: watch::Receiver<sql::engine::Limits>,
+    /// The rate limits for requests, which may change via cluster settings.
+    rate_limits: watch::Receiver<RateLimits>,
+    /// The session's rate limiter.
+    limiter: ratelimit::Limiter,
+    /// The rate limiter of the session's client host, shared with its other
+    /// sessions.
+    client_limiter: Arc<Mutex<ratelimit::Limiter>>,
+    /// The query queue, shared with the server's other sessions.
+    queue: Arc<queue::Queue>,
+    /// The session's slot in the query queue while executing a request, held
+    /// until its rows have been sent.
+    permit: Option<queue::Permit>,
+    /// The time without a heartbeat after which nodes are considered dead, or
+    /// zero if liveness heartbeats are disabled.
+    liveness_timeout: Duration,
+    /// The audit log, if any.
+    audit: Option<Arc<Mutex<audit::Log>>>,
 }
 
 impl Session {
-    /// Creates a new client session.
-    fn new(engine: sql::engine::Raft) -> Result<Self> {
-        Ok(Self { sql: engine.session()?, engine })
+    /// Creates a new client session, registering it in the server's sessions.
+    fn new(
+        engine: sql::engine::Raft,
+        client: SocketAddr,
+        sessions: Arc<Sessions>,
+        config: SessionConfig,
+    ) -> Result<Self> {
+        let mut sql = engine.session()?;
+        sql.set_admin(sessions.clone());
+        let (id, kill, client_limiter) = sessions.register(client, engine.clone())?;
+        let SessionConfig {
+            redirect,
+            history,
+            limits,
+            rate_limits,
+            queue,
+            admin_hosts,
+            liveness_timeout,
+            audit,
+        } = config;
+        sql.set_privileged(admin_hosts.contains(&client.ip()));
+        Ok(Self {
+            sql,
+            engine,
+            id,
+            client,
+            sessions,
+            kill,
+            redirect,
+            history,
+            limits,
+            rate_limits,
+            limiter: ratelimit::Limiter::new(),
+            client_limiter,
+            queue,
+            permit: None,
+            liveness_timeout,
+            audit,
+        })
+    }
+
+    /// Returns a redirect error if the request should be served by the leader
+    /// instead, see Server::redirect().
+    fn redirect(&self, request: &Request, stale: bool) -> Option<Error> {
+        let redirect = self.redirect.as_ref()?;
+        if stale
+            || matches!(
+                request,
+                Request::Status | Request::Liveness | Request::ReadLog { .. } | Request::Metrics
+            )
+        {
+            return None;
+        }
+        if self.sql.transaction().is_some() {
+            return None;
+        }
+        let leader = (*redirect.leader_rx.borrow())?;
+        let address = redirect.addrs.get(&leader)?.clone();
+        Some(Error::Redirect { leader, address })
     }
 
     /// Handles a client connection.
     async fn handle(mut self, socket: TcpStream) -> Result<()> {
-        let mut stream = tokio_serde::Framed::new(
-            Framed::new(socket, LengthDelimitedCodec::new()),
-            tokio_serde::formats::Bincode::default(),
-        );
-        while let Some(request) = stream.try_next().await? {
-            let mut response = tokio::task::block_in_place(|| self.request(request));
-            let mut rows: Box<dyn Iterator<Item = Result<Response>> + Send> =
-                Box::new(std::iter::empty());
-            if let Ok(Response::Execute(ResultSet::Query { rows: ref mut resultrows, .. })) =
-                &mut response
-            {
-                rows = Box::new(
-                    std::mem::replace(resultrows, Box::new(std::iter::empty()))
-                        .map(|result| result.map(|row| Response::Row(Some(row))))
-                        .chain(std::iter::once(Ok(Response::Row(None))))
-                        .scan(false, |err_sent, response| match (&err_sent, &response) {
-                            (true, _) => None,
-                            (_, Err(error)) => {
-                                *err_sent = true;
-                                Some(Err(error.clone()))
-                            }
-                            _ => Some(response),
-                        })
-                        .fuse(),
-                );
-            }
-            stream.send(response).await?;
-            stream.send_all(&mut tokio_stream::iter(rows.map(Ok))).await?;
+        let (framed, version) = protocol::accept(socket).await?;
+        debug!("Using protocol version {}", version);
+        let mut stream = tokio_serde::Framed::new(framed, tokio_serde::formats::Bincode::default());
+        loop {
+            let mut request = tokio::select! {
+                request = stream.try_next() => match request? {
+                    Some(request) => request,
+                    None => break,
+                },
+                _ = self.kill.notified() => {
+                    info!("Session {} killed", self.id);
+                    break;
+                }
+            };
+            let (mut timeout, mut token) = (None, None);
+            loop {
+                match request {
+                    Request::Timeout(t, r) => (timeout, request) = (Some(t), *r),
+                    Request::Stale(t, r) => (token, request) = (Some(t), *r),
+                    _ => break,
+                }
+            }
+            let statement = match &request {
+                Request::Execute(query) | Request::ExecuteMany(query) => Some(query.clone()),
+                _ => None,
+            };
+            let execute = statement.is_some();
+            let status = matches!(request, Request::Status);
+            self.start_statement(statement)?;
+            self.engine.set_deadline(timeout.map(|timeout| Instant::now() + timeout))?;
+            self.engine.set_stale_reads(token)?;
+            self.engine.set_limits(*self.limits.borrow())?;
+            let responses = match (self.redirect(&request, token.is_some()), request) {
+                (Some(error), _) => vec![Err(error)],
+                (None, Request::ExecuteMany(query)) => {
+                    tokio::task::block_in_place(|| self.execute_many(&query))
+                }
+                (None, request) => vec![tokio::task::block_in_place(|| self.request(request))],
+            };
+            self.engine.set_stale_reads(None)?;
+            for mut response in responses {
+                let mut rows = Self::stream_rows(&mut response, self.engine.clone());
+                stream.send(response).await?;
+                loop {
+                    let batch: Vec<_> = tokio::task::block_in_place(|| {
+                        rows.by_ref().take(STREAM_BATCH_SIZE).collect()
+                    });
+                    if batch.is_empty() {
+                        break;
+                    }
+                    let batch = batch.into_iter().map(Ok);
+                    stream.send_all(&mut tokio_stream::iter(batch)).await?;
+                }
+            }
+            self.end_statement()?;
+            self.permit = None;
+            if execute {
+                stream.send(Ok(Response::Transaction(self.sql.transaction()))).await?;
+            }
+            if status {
+                stream.send(Ok(Response::History(self.history.entries()))).await?;
+            }
+            if let Some(token) = token {
+                let token = tokio::task::block_in_place(|| self.engine.session_token(token));
+                stream.send(token.map(Response::Token)).await?;
+            }
+            self.engine.set_deadline(None)?;
         }
         Ok(())
     }
 
-    /// Executes a request.
+    /// Records the start of a request in the server's sessions, along with
+    /// its SQL statement if any, and resumes cancelled Raft requests. This is
+    /// done while holding the sessions lock, such that a concurrent KILL either
+    /// cancels the new statement or nothing.
+    fn start_statement(&self, statement: Option<String>) -> Result<()> {
+        self.sessions.update(self.id, |state| {
+            state.statement = statement.map(|statement| (statement, Instant::now()));
+            state.engine.set_cancelled(false).ok();
+        })
+    }
+
+    /// Records the end of a request in the server's sessions, once its rows
+    /// have been sent, along with the session's transaction.
+    fn end_statement(&self) -> Result<()> {
+        let txn = self.sql.transaction();
+        self.sessions.update(self.id, |state| {
+            let start = state.statement.take().map_or_else(Instant::now, |(_, start)| start);
+            state.engine.set_cancelled(false).ok();
+            state.transaction = match (txn, state.transaction) {
+                (Some(txn), Some((version, start))) if txn.version == version => {
+                    Some((version, start))
+                }
+                (Some(txn), _) => Some((txn.version, start)),
+                (None, _) => None,
+            };
+        })
+    }
+
+    /// Takes the rows out of a query result, returning them in order as Row
+    /// responses ending with RowsEnd. A row error is returned in place of the
+    /// end marker, ending the stream, and successful results end with a
+    /// Complete response.
+    /// If the engine is cancelled, the stream ends with Error::Cancelled.
+    fn stream_rows(
+        response: &mut Result<Response>,
+        engine: sql::engine::Raft,
+    ) -> Box<dyn Iterator<Item = Result<Response>> + Send> {
+        let Ok(Response::Execute(resultset, _)) = response else {
+            return Box::new(std::iter::empty());
+        };
+        let mut tag = resultset.command_tag(0);
+        let ResultSet::Query { key_column, rows, .. } = resultset else {
+            return Box::new(std::iter::once(Ok(Response::Complete(tag))));
+        };
+        let (key_column, mut last_key, mut count) = (*key_column, None, 0);
+        Box::new(
+            std::mem::replace(rows, Box::new(std::iter::empty()))
+                .map(move |row| match engine.cancelled() {
+                    Ok(true) => Err(Error::Cancelled),
+                    Ok(false) => row,
+                    Err(error) => Err(error),
+                })
+                .map(Some)
+                .chain(std::iter::once(None))
+                .map(move |result| match result {
+                    Some(Ok(row)) => {
+                        if let Some(key) = key_column.and_then(|i| row.get(i)) {
+                            last_key = Some(key.clone());
+                        }
+                        count += 1;
+                        (Ok(Response::Row(Some(row))), None)
+                    }
+                    Some(Err(error)) => (Err(error), None),
+                    None => {
+                        tag.rows = Some(count);
+                        let end = Ok(Response::RowsEnd(last_key.take()));
+                        (end, Some(Ok(Response::Complete(tag.clone()))))
+                    }
+                })
+                .flat_map(|(response, complete)| std::iter::once(response).chain(complete))
+                .scan(false, |err_sent, response| match (&err_sent, &response) {
+                    (true, _) => None,
+                    (_, Err(error)) => {
+                        *err_sent = true;
+                        Some(Err(error.clone()))
+                    }
+                    _ => Some(response),
+                })
+                .fuse(),
+        )
+    }
+
+    /// Executes a string of semicolon-separated statements in order, stopping
+    /// at the first error. Query rows are buffered, since later statements may
+    /// change the data that they're read from.
+    fn execute_many(&mut self, query: &str) -> Vec<Result<Response>> {
+        let mut responses = Vec::new();
+        for statement in sql::parser::split_statements(query) {
+            let response = self.request(Request::Execute(statement.into())).and_then(|response| {
+                let Response::Execute(ResultSet::Query { columns, key_column, rows }, time) =
+                    response
+                else {
+                    return Ok(response);
+                };
+                let rows = rows.collect::<Result<Vec<_>>>()?;
+                let rows = Box::new(rows.into_iter().map(Ok));
+                Ok(Response::Execute(ResultSet::Query { columns, key_column, rows }, time))
+            });
+            let failed = response.is_err();
+            responses.push(response);
+            if failed {
+                break;
+            }
+        }
+        responses
+    }
+
+    /// Executes a request, recording it in the audit log if it's a DDL
+    /// statement or privileged operation. Queries are parsed once, for
+    /// auditing, throttling, and execution.
     pub fn request(&mut self, request: Request) -> Result<Response> {
+        let statement = match &request {
+            Request::Execute(query) => Some(sql::parser::Parser::new(query).parse()),
+            _ => None,
+        };
+        let Some(audit) = self.audit.clone() else {
+            return self.execute_request(request, statement);
+        };
+        let (action, query) = match (&request, &statement) {
+            (Request::Execute(query), Some(Ok(statement))) => {
+                (audit_action(statement), Some(query.clone()))
+            }
+            (Request::Promote, _) => (Some("PROMOTE"), None),
+            _ => (None, None),
+        };
+        let result = self.execute_request(request, statement);
+        if let Some(action) = action {
+            let error = result.as_ref().err().map(|err| err.to_string());
+            let event = audit::Event {
+                client: &self.client.to_string(),
+                session: self.id,
+                action,
+                statement: query.as_deref(),
+                error: error.as_deref(),
+            };
+            if let Err(err) = audit.lock()?.append(&event) {
+                error!("Failed to write audit log: {}", err);
+            }
+        }
+        result
+    }
+
+    /// Executes a request, without auditing it. Execute requests are given
+    /// along with their parsed statement. The request must be admitted by the
+    /// rate limits, if any, and counts towards them. It must then be admitted
+    /// by the query queue, unless the session already holds a slot.
+    fn execute_request(
+        &mut self,
+        request: Request,
+        statement: Option<Result<sql::parser::ast::Statement>>,
+    ) -> Result<Response> {
         debug!("Processing request {:?}", request);
+        let rate_limits = *self.rate_limits.borrow();
+        let throttled = throttled(&request, statement.as_ref().and_then(|s| s.as_ref().ok()));
+        let limited = rate_limits.enabled() && throttled;
+        if limited {
+            self.admit(rate_limits)?;
+        }
+        if throttled && self.permit.is_none() {
+            let deadline = self.engine.deadline()?;
+            self.permit = Some(self.queue.admit(self.sql.priority(), deadline)?);
+        }
         let response = match request {
-            Request::Execute(query) => Response::Execute(self.sql.execute(&query)?),
+            Request::Execute(_) => {
+                let start = Instant::now();
+                let statement = statement
+                    .ok_or_else(|| Error::Internal("Query must be parsed by caller".into()))??;
+                let resultset = self.sql.execute_statement(statement)?;
+                Response::Execute(resultset, start.elapsed())
+            }
             Request::GetTable(table) => {
                 Response::GetTable(self.sql.read_with_txn(|txn| txn.must_read_table(&table))?)
             }
@@ -161,14 +1235,124 @@ impl Session {
                 self.sql.read_with_txn(|txn| Ok(txn.scan_tables()?.map(|t| t.name).collect()))?,
             ),
             Request::Status => Response::Status(self.engine.status()?),
+            Request::Metrics => Response::Metrics(Metrics { memory: sql::execution::metrics() }),
+            Request::Liveness if self.liveness_timeout.is_zero() => {
+                return Err(Error::Value("Node liveness heartbeats are disabled".into()))
+            }
+            Request::Liveness => Response::Liveness(self.engine.liveness(self.liveness_timeout)?),
+            Request::ReadLog { .. } | Request::Promote if self.sql.tenant().is_some() => {
+                return Err(Error::Value(
+                    "Cluster administration requires the system tenant".into(),
+                ))
+            }
+            Request::ReadLog { from, limit } => {
+                let (snapshot, entries) = self.engine.read_log(from, limit)?;
+                Response::Log { snapshot, entries }
+            }
+            Request::Promote => Response::Promote(self.engine.promote()?),
+            Request::KVGet { namespace, key } => {
+                Response::KVGet(self.sql.read_with_txn(|txn| txn.kv_get(&namespace, &key))?)
+            }
+            Request::KVSet { namespace, key, value } => {
+                self.sql.write_with_txn(|txn| txn.kv_set(&namespace, &key, value))?;
+                Response::KVSet
+            }
+            Request::KVDelete { namespace, key } => {
+                self.sql.write_with_txn(|txn| txn.kv_delete(&namespace, &key))?;
+                Response::KVDelete
+            }
+            Request::KVScan { namespace, range } => Response::KVScan(
+                self.sql.read_with_txn(|txn| txn.kv_scan(&namespace, range)?.collect())?,
+            ),
+            Request::ExecuteMany(_) => {
+                return Err(Error::Internal(
+                    "Multi-statement requests must be handled by caller".into(),
+                ))
+            }
+            Request::Timeout(..) | Request::Stale(..) => {
+                return Err(Error::Internal("Request options must be handled by caller".into()))
+            }
         };
+        if limited {
+            let rows = match &response {
+                Response::Execute(
+                    ResultSet::Create { count }
+                    | ResultSet::Update { count }
+                    | ResultSet::Delete { count }
+                    | ResultSet::CreateTableAs { count, .. },
+                    _,
+                ) => *count,
+                Response::KVSet | Response::KVDelete => 1,
+                _ => 0,
+            };
+            self.limiter.written(rows, rate_limits.session_rows);
+            self.client_limiter.lock()?.written(rows, rate_limits.client_rows);
+        }
         debug!("Returning response {:?}", response);
         Ok(response)
     }
+
+    /// Admits a request under the session's and client host's rate limits,
+    /// taking a query from both, or returns Error::RateLimited.
+    fn admit(&mut self, limits: RateLimits) -> Result<()> {
+        self.limiter.admit(limits.session_qps, limits.session_rows).map_err(Error::RateLimited)?;
+        self.client_limiter
+            .lock()?
+            .admit(limits.client_qps, limits.client_rows)
+            .map_err(Error::RateLimited)
+    }
+}
+
+/// Returns whether a request is subject to rate limits and the query queue,
+/// given the parsed statement of Execute requests, if valid. Monitoring and
+/// administration requests aren't, nor are COMMIT and ROLLBACK, such that
+/// clients can always end their transactions and release their locks, nor SET,
+/// such that they can raise their session's priority.
+fn throttled(request: &Request, statement: Option<&sql::parser::ast::Statement>) -> bool {
+    use sql::parser::ast::Statement;
+    match request {
+        Request::Execute(_) => !matches!(
+            statement,
+            Some(Statement::Commit | Statement::Rollback | Statement::Set { .. })
+        ),
+        Request::Status
+        | Request::Metrics
+        | Request::Liveness
+        | Request::ReadLog { .. }
+        | Request::Promote => false,
+        _ => true,
+    }
+}
+
+/// Returns the audit log action for a statement, or None if it's not audited.
+fn audit_action(statement: &sql::parser::ast::Statement) -> Option<&'static str> {
+    use sql::parser::ast::Statement;
+    Some(match statement {
+        Statement::CreateTable { .. } => "CREATE TABLE",
+        Statement::CreateTableAs { .. } => "CREATE TABLE AS",
+        Statement::DropTable(_) => "DROP TABLE",
+        Statement::UndropTable(_) => "UNDROP TABLE",
+        Statement::CreateIndex { .. } => "CREATE INDEX",
+        Statement::DropIndex { .. } => "DROP INDEX",
+        Statement::CreateTrigger { .. } => "CREATE TRIGGER",
+        Statement::DropTrigger { .. } => "DROP TRIGGER",
+        Statement::CreateFunction { .. } => "CREATE FUNCTION",
+        Statement::DropFunction(_) => "DROP FUNCTION",
+        Statement::CreateTenant(_) => "CREATE TENANT",
+        Statement::DropTenant(_) => "DROP TENANT",
+        Statement::SetClusterSetting { .. } => "SET CLUSTER SETTING",
+        Statement::Kill { .. } => "KILL",
+        Statement::CommitPrepared(_) => "COMMIT PREPARED",
+        Statement::RollbackPrepared(_) => "ROLLBACK PREPARED",
+        Statement::ExplainAnalyze(statement) => return audit_action(statement),
+        _ => return None,
+    })
 }
 
 impl Drop for Session {
     fn drop(&mut self) {
+        self.engine.set_cancelled(false).ok();
         tokio::task::block_in_place(|| self.sql.execute("ROLLBACK").ok());
+        self.sessions.remove(self.id).ok();
     }
 }
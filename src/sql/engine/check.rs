@@ -0,0 +1,140 @@
+use super::super::types::Value;
+use super::Transaction;
+use crate::error::Result;
+use crate::storage::bincode;
+
+use std::collections::HashMap;
+
+/// Checks the consistency of all tables as seen by the given transaction, without modifying
+/// anything, and returns a description of each problem found. This verifies that:
+///
+/// - Rows match the table schema, i.e. the number of columns, datatypes, and nullability.
+/// - Rows are stored under their primary key.
+/// - Foreign keys reference existing rows.
+/// - Unique columns don't contain duplicate values.
+/// - Every row is present in its column indexes, and every index entry points to a row with the
+///   indexed value.
+pub fn check(txn: &impl Transaction) -> Result<Vec<String>> {
+    let mut errors = Vec::new();
+    for table in txn.scan_tables()? {
+        let pk_index = table.get_column_index(&table.get_primary_key()?.name)?;
+        let mut unique: HashMap<usize, HashMap<Value, Value>> = HashMap::new();
+
+        let mut scan = txn.scan(&table.name, None)?;
+        while let Some(row) = scan.next().transpose()? {
+            if row.len() != table.columns.len() {
+                errors.push(format!(
+                    "Table {} has row with {} columns, expected {}: {:?}",
+                    table.name,
+                    row.len(),
+                    table.columns.len(),
+                    row
+                ));
+                continue;
+            }
+            let pk = &row[pk_index];
+            match txn.read(&table.name, pk)? {
+                Some(stored) if identical_rows(&stored, &row) => {}
+                _ => errors.push(format!(
+                    "Table {} row {} is not stored under its primary key",
+                    table.name, pk
+                )),
+            }
+
+            for (i, (column, value)) in table.columns.iter().zip(row.iter()).enumerate() {
+                match value.datatype() {
+                    None if !column.nullable => errors.push(format!(
+                        "Table {} row {} has NULL value for column {}",
+                        table.name, pk, column.name
+                    )),
+                    Some(datatype) if datatype != column.datatype => errors.push(format!(
+                        "Table {} row {} has {} value for {} column {}",
+                        table.name, pk, datatype, column.datatype, column.name
+                    )),
+                    _ => {}
+                }
+
+                if let Some(target) = &column.references {
+                    let dangling = match value {
+                        Value::Null => false,
+                        Value::Float(f) if f.is_nan() => false,
+                        v if target == &table.name && v == pk => false,
+                        v => txn.read(target, v)?.is_none(),
+                    };
+                    if dangling {
+                        errors.push(format!(
+                            "Table {} row {} column {} references missing row {} in table {}",
+                            table.name, pk, column.name, value, target
+                        ));
+                    }
+                }
+
+                if column.unique && !column.primary_key && value != &Value::Null {
+                    let values = unique.entry(i).or_default();
+                    if let Some(other) = values.insert(value.clone(), pk.clone()) {
+                        errors.push(format!(
+                            "Table {} rows {} and {} have duplicate value {} for unique column {}",
+                            table.name, other, pk, value, column.name
+                        ));
+                    }
+                }
+
+                if column.index && !txn.read_index(&table.name, &column.name, value)?.contains(pk) {
+                    errors.push(format!(
+                        "Table {} row {} is missing from index {} for value {}",
+                        table.name, pk, column.name, value
+                    ));
+                }
+            }
+        }
+
+        for (i, column) in table.columns.iter().enumerate().filter(|(_, c)| c.index) {
+            let mut scan = txn.scan_index(&table.name, &column.name)?;
+            while let Some((value, pks)) = scan.next().transpose()? {
+                for pk in pks {
+                    match txn.read(&table.name, &pk)? {
+                        Some(row) if row.get(i).is_some_and(|v| identical(v, &value)) => {}
+                        Some(_) => errors.push(format!(
+                            "Table {} index {} entry for value {} points to row {} with a \
+                             different value",
+                            table.name, column.name, value, pk
+                        )),
+                        None => errors.push(format!(
+                            "Table {} index {} entry for value {} points to missing row {}",
+                            table.name, column.name, value, pk
+                        )),
+                    }
+                }
+            }
+        }
+    }
+    Ok(errors)
+}
+
+/// Computes a checksum of all tables and rows visible to the given transaction, for comparing
+/// replicated state across nodes. Unlike a checksum of the underlying storage, this only depends
+/// on the logical contents, not on old MVCC versions or the serialization order of hash sets.
+pub fn checksum(txn: &impl Transaction) -> Result<u32> {
+    let mut hasher = crc32fast::Hasher::new();
+    for table in txn.scan_tables()? {
+        hasher.update(&bincode::serialize(&table)?);
+        let mut scan = txn.scan(&table.name, None)?;
+        while let Some(row) = scan.next().transpose()? {
+            hasher.update(&bincode::serialize(&row)?);
+        }
+    }
+    Ok(hasher.finalize())
+}
+
+/// Returns whether two values are identical. Unlike ==, this considers NaN identical to itself.
+fn identical(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+        (a, b) => a == b,
+    }
+}
+
+/// Returns whether two rows are identical, see identical().
+fn identical_rows(a: &[Value], b: &[Value]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| identical(a, b))
+}
@@ -0,0 +1,265 @@
+use super::super::schema::Table;
+use super::super::types::{DataType, Row, Value};
+use crate::error::{Error, Result};
+use crate::storage::bincode;
+
+use serde_derive::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+/// A table row encoding, used to store rows in the key/value store. Codecs are given the table
+/// schema, and may rely on rows matching it.
+pub trait RowCodec {
+    /// Encodes a row.
+    fn encode(&self, table: &Table, row: &[Value]) -> Result<Vec<u8>>;
+    /// Decodes a row.
+    fn decode(&self, table: &Table, bytes: &[u8]) -> Result<Row>;
+}
+
+/// The available row codecs, selectable per table via the codec table option.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Codec {
+    /// The Bincode encoding of the row values.
+    #[default]
+    Bincode,
+    /// A compact schema-aware encoding, see Compact.
+    Compact,
+}
+
+impl Codec {
+    /// All codecs.
+    pub const ALL: [Codec; 2] = [Codec::Bincode, Codec::Compact];
+
+    /// Looks up a codec by name.
+    pub fn from_name(name: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|c| c.to_string() == name.to_lowercase())
+            .ok_or_else(|| Error::Value(format!("Unknown codec {}", name)))
+    }
+}
+
+impl Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Bincode => "bincode",
+            Self::Compact => "compact",
+        })
+    }
+}
+
+impl RowCodec for Codec {
+    fn encode(&self, table: &Table, row: &[Value]) -> Result<Vec<u8>> {
+        match self {
+            Self::Bincode => Bincode.encode(table, row),
+            Self::Compact => Compact.encode(table, row),
+        }
+    }
+
+    fn decode(&self, table: &Table, bytes: &[u8]) -> Result<Row> {
+        match self {
+            Self::Bincode => Bincode.decode(table, bytes),
+            Self::Compact => Compact.decode(table, bytes),
+        }
+    }
+}
+
+/// Encodes rows as the Bincode serialization of their values, including their types.
+pub struct Bincode;
+
+impl RowCodec for Bincode {
+    fn encode(&self, _: &Table, row: &[Value]) -> Result<Vec<u8>> {
+        bincode::serialize(&row)
+    }
+
+    fn decode(&self, _: &Table, bytes: &[u8]) -> Result<Row> {
+        bincode::deserialize(bytes)
+    }
+}
+
+/// Encodes rows without value types, which are given by the column types. The format is:
+///
+/// - The number of columns, as a varint.
+/// - A NULL bitmap with a bit per column, least significant bit first.
+/// - The non-NULL values in column order: booleans as a byte, integers as zigzag varints, floats
+///   as 8 big-endian bytes, and strings as a varint length followed by UTF-8 bytes.
+///
+/// Varints use the LEB128 encoding, with 7 bits per byte and the high bit set on all but the last.
+pub struct Compact;
+
+impl RowCodec for Compact {
+    fn encode(&self, table: &Table, row: &[Value]) -> Result<Vec<u8>> {
+        if row.len() != table.columns.len() {
+            return Err(Error::Value(format!("Invalid row size for table {}", table.name)));
+        }
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, row.len() as u64);
+        let mut nulls = vec![0u8; row.len().div_ceil(8)];
+        for (i, _) in row.iter().enumerate().filter(|(_, v)| matches!(v, Value::Null)) {
+            nulls[i / 8] |= 1 << (i % 8);
+        }
+        bytes.extend(nulls);
+        for (column, value) in table.columns.iter().zip(row) {
+            match (&column.datatype, value) {
+                (_, Value::Null) => {}
+                (DataType::Boolean, Value::Boolean(b)) => bytes.push(*b as u8),
+                (DataType::Integer, Value::Integer(i)) => {
+                    write_varint(&mut bytes, ((i << 1) ^ (i >> 63)) as u64)
+                }
+                (DataType::Float, Value::Float(f)) => bytes.extend(f.to_be_bytes()),
+                (DataType::String, Value::String(s)) => {
+                    write_varint(&mut bytes, s.len() as u64);
+                    bytes.extend(s.as_bytes());
+                }
+                (datatype, value) => {
+                    return Err(Error::Value(format!(
+                        "Invalid {} value {} for {} column {}",
+                        value.datatype().map(|d| d.to_string()).unwrap_or_default(),
+                        value,
+                        datatype,
+                        column.name
+                    )))
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn decode(&self, table: &Table, mut bytes: &[u8]) -> Result<Row> {
+        let len = read_varint(&mut bytes)? as usize;
+        if len != table.columns.len() {
+            return Err(Error::Internal(format!(
+                "Row has {} columns, table {} has {}",
+                len,
+                table.name,
+                table.columns.len()
+            )));
+        }
+        let nulls = take(&mut bytes, len.div_ceil(8))?.to_vec();
+        let mut row = Vec::with_capacity(len);
+        for (i, column) in table.columns.iter().enumerate() {
+            if nulls[i / 8] & (1 << (i % 8)) != 0 {
+                row.push(Value::Null);
+                continue;
+            }
+            row.push(match column.datatype {
+                DataType::Boolean => Value::Boolean(take(&mut bytes, 1)?[0] != 0),
+                DataType::Integer => {
+                    let n = read_varint(&mut bytes)?;
+                    Value::Integer((n >> 1) as i64 ^ -((n & 1) as i64))
+                }
+                DataType::Float => {
+                    Value::Float(f64::from_be_bytes(take(&mut bytes, 8)?.try_into()?))
+                }
+                DataType::String => {
+                    let len = read_varint(&mut bytes)? as usize;
+                    Value::String(String::from_utf8(take(&mut bytes, len)?.to_vec())?)
+                }
+            });
+        }
+        if !bytes.is_empty() {
+            return Err(Error::Internal(format!("Unexpected trailing bytes in row {:?}", bytes)));
+        }
+        Ok(row)
+    }
+}
+
+/// Appends a LEB128 varint.
+fn write_varint(bytes: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        bytes.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+/// Reads a LEB128 varint, advancing the slice past it.
+fn read_varint(bytes: &mut &[u8]) -> Result<u64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = take(bytes, 1)?[0];
+        n |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(Error::Internal("Invalid varint".into()))
+}
+
+/// Takes the given number of bytes from the start of the slice, advancing it.
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+    if bytes.len() < n {
+        return Err(Error::Internal("Unexpected end of row".into()));
+    }
+    let (head, tail) = bytes.split_at(n);
+    *bytes = tail;
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::schema::Column;
+    use super::*;
+
+    /// Rows round-trip through all codecs, and the compact codec is smaller than bincode.
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let column = |name: &str, datatype| Column {
+            name: name.into(),
+            datatype,
+            primary_key: name == "id",
+            nullable: name != "id",
+            default: None,
+            unique: false,
+            index: false,
+            include: Vec::new(),
+            references: None,
+        };
+        let table = Table::new(
+            "test".into(),
+            vec![
+                column("id", DataType::Integer),
+                column("b", DataType::Boolean),
+                column("f", DataType::Float),
+                column("s", DataType::String),
+            ],
+        )?;
+        let rows = vec![
+            vec![Value::Integer(0), Value::Null, Value::Null, Value::Null],
+            vec![
+                Value::Integer(-1),
+                Value::Boolean(true),
+                Value::Float(2.5),
+                Value::String("foo".into()),
+            ],
+            vec![
+                Value::Integer(i64::MIN),
+                Value::Boolean(false),
+                Value::Float(f64::NAN),
+                Value::String("".into()),
+            ],
+            vec![
+                Value::Integer(i64::MAX),
+                Value::Null,
+                Value::Float(-0.0),
+                Value::String("😀".repeat(100)),
+            ],
+        ];
+        for row in rows {
+            let mut sizes = Vec::new();
+            for codec in Codec::ALL {
+                let bytes = codec.encode(&table, &row)?;
+                let decoded = codec.decode(&table, &bytes)?;
+                assert_eq!(format!("{:?}", decoded), format!("{:?}", row), "{}", codec);
+                sizes.push(bytes.len());
+            }
+            assert!(sizes[1] <= sizes[0], "{:?}", sizes);
+        }
+
+        assert!(Compact.encode(&table, &[Value::Integer(1)]).is_err());
+        assert!(Compact
+            .encode(&table, &[Value::String("a".into()), Value::Null, Value::Null, Value::Null])
+            .is_err());
+        assert!(Compact.decode(&table, &[0x04, 0x00, 0x02]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use super::super::types::Value;
+use crate::error::{Error, Result};
+
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+/// The maximum number of rows in a row group of a columnar table. Larger groups are split in two.
+pub const ROW_GROUP_SIZE: usize = 1024;
+
+/// A table storage layout, selectable per table via the layout table option.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Layout {
+    /// Each row is stored under its primary key, encoded with the table's row codec.
+    #[default]
+    Row,
+    /// Rows are stored in row groups of up to ROW_GROUP_SIZE rows with contiguous primary keys.
+    /// Each column of a row group is stored as a separate chunk, keyed by the group's first
+    /// primary key, such that scans only read the chunks of the columns they use. Writes
+    /// rewrite the chunks of the entire row group, and concurrent writes to the same group
+    /// conflict, so this is best suited for bulk-loaded tables that are mostly scanned.
+    Columnar,
+}
+
+impl Layout {
+    /// All layouts.
+    pub const ALL: [Layout; 2] = [Layout::Row, Layout::Columnar];
+
+    /// Looks up a layout by name.
+    pub fn from_name(name: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|l| l.to_string() == name.to_lowercase())
+            .ok_or_else(|| Error::Value(format!("Unknown layout {}", name)))
+    }
+}
+
+impl Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Row => "row",
+            Self::Columnar => "columnar",
+        })
+    }
+}
+
+/// A column chunk, holding a column's values for a row group in primary key order. It uses the
+/// most compact of a few encodings, chosen when it's written.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub enum Chunk {
+    /// The plain values.
+    Plain(Vec<Value>),
+    /// Runs of repeated values, as (value, count) pairs.
+    RunLength(Vec<(Value, u32)>),
+    /// A dictionary of distinct values, and each value's position in it.
+    Dictionary(Vec<Value>, Vec<u32>),
+}
+
+impl Chunk {
+    /// Encodes column values. Values that mostly repeat their predecessor are run-length
+    /// encoded, values with few distinct values are dictionary encoded, and others are plain.
+    pub fn encode(values: Vec<Value>) -> Self {
+        let runs = 1 + values.windows(2).filter(|w| w[0] != w[1]).count();
+        if runs * 2 <= values.len() {
+            let mut runs: Vec<(Value, u32)> = Vec::with_capacity(runs);
+            for value in values {
+                match runs.last_mut() {
+                    Some((last, count)) if *last == value => *count += 1,
+                    _ => runs.push((value, 1)),
+                }
+            }
+            return Self::RunLength(runs);
+        }
+        let mut positions: HashMap<&Value, u32> = HashMap::new();
+        let mut indexes = Vec::with_capacity(values.len());
+        for value in &values {
+            let next = positions.len() as u32;
+            indexes.push(*positions.entry(value).or_insert(next));
+        }
+        if positions.len() * 2 > values.len() {
+            return Self::Plain(values);
+        }
+        let mut dictionary = vec![Value::Null; positions.len()];
+        for (value, index) in positions {
+            dictionary[index as usize] = value.clone();
+        }
+        Self::Dictionary(dictionary, indexes)
+    }
+
+    /// Decodes the column values.
+    pub fn decode(self) -> Result<Vec<Value>> {
+        Ok(match self {
+            Self::Plain(values) => values,
+            Self::RunLength(runs) => {
+                runs.into_iter().flat_map(|(value, count)| vec![value; count as usize]).collect()
+            }
+            Self::Dictionary(dictionary, indexes) => indexes
+                .into_iter()
+                .map(|i| {
+                    dictionary
+                        .get(i as usize)
+                        .cloned()
+                        .ok_or_else(|| Error::Internal(format!("Invalid dictionary index {}", i)))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Chunks pick the expected encoding, and round-trip the values.
+    #[test]
+    fn chunk() -> Result<()> {
+        let plain: Vec<Value> = (0..10).map(Value::Integer).collect();
+        let runs: Vec<Value> = (0..10).map(|i| Value::Integer(i / 5)).collect();
+        let dictionary: Vec<Value> =
+            (0..10).map(|i| if i % 2 == 0 { Value::Null } else { "a".into() }).collect();
+        let nan = vec![Value::Float(f64::NAN); 4];
+
+        for (values, expect) in
+            [(plain, "Plain"), (runs, "RunLength"), (dictionary, "Dictionary"), (nan, "Plain")]
+        {
+            let chunk = Chunk::encode(values.clone());
+            assert!(format!("{:?}", chunk).starts_with(expect), "{:?}", chunk);
+            assert_eq!(format!("{:?}", chunk.decode()?), format!("{:?}", values));
+        }
+        Ok(())
+    }
+}
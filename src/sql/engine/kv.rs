@@ -1,31 +1,148 @@
-use super::super::schema::{Catalog, Table, Tables};
-use super::super::types::{Expression, Row, Value};
-use super::Transaction as _;
+use super::super::plan::Direction;
+use super::super::schema::{
+    Catalog, Column, DroppedTable, Function, Index, IndexStats, Table, TableStats, Tables,
+    TenantStats, Trigger, Ttl,
+};
+use super::super::types::{Expression, Range, Row, Sample, SampleMethod, Value};
+use super::{Chunk, Codec, Layout, RowCodec as _, Transaction as _, ROW_GROUP_SIZE};
+use crate::clock::{HybridClock, Timestamp};
+use crate::encoding::keycode;
 use crate::error::{Error, Result};
-use crate::storage::{self, bincode, keycode};
+use crate::storage::{self, bincode};
 
+use rand::Rng as _;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::clone::Clone;
-use std::collections::HashSet;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Bound, RangeBounds as _};
+
+/// The version of the SQL key/value encoding, stored as unversioned metadata. It must be
+/// incremented on any change to the encoding of keys or values, along with a migration in
+/// KV::migrate(). Versions:
+///
+/// 0: data written before version markers were added.
+/// 1: table schemas include a row TTL.
+/// 2: table schemas include a row codec.
+/// 3: table schemas include a storage layout.
+pub const FORMAT_VERSION: u32 = 3;
+
+/// The metadata key for the SQL key/value encoding version.
+const FORMAT_VERSION_KEY: &[u8] = b"format_version";
+
+/// The metadata key prefix for prepared transactions, by global identifier.
+const PREPARED_PREFIX: &[u8] = b"prepared/";
+
+/// The number of key range blocks that TABLESAMPLE SYSTEM splits a row table into.
+const SAMPLE_BLOCKS: u128 = 100;
+
+/// The position of a chunked table sample, see Transaction::scan_sample_chunk().
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SampleCursor {
+    /// The number of random draws made so far.
+    draws: u64,
+    /// The key of the last row read.
+    key: Vec<u8>,
+}
 
 /// A SQL engine based on an underlying MVCC key/value store.
 pub struct KV<E: storage::engine::Engine> {
     /// The underlying key/value store.
     pub(super) kv: storage::mvcc::MVCC<E>,
+    /// The size limits for rows written via SQL.
+    limits: super::Limits,
+    /// The clock to timestamp commits with, if any.
+    clock: Option<HybridClock>,
+    /// The tenant whose keyspace transactions use, or None for the system keyspace.
+    tenant: Option<String>,
 }
 
 // FIXME Implement Clone manually due to https://github.com/rust-lang/rust/issues/26925
 impl<E: storage::engine::Engine> Clone for KV<E> {
     fn clone(&self) -> Self {
-        KV { kv: self.kv.clone() }
+        KV {
+            kv: self.kv.clone(),
+            limits: self.limits,
+            clock: self.clock.clone(),
+            tenant: self.tenant.clone(),
+        }
     }
 }
 
 impl<E: storage::engine::Engine> KV<E> {
     /// Creates a new key/value-based SQL engine
     pub fn new(engine: E) -> Self {
-        Self { kv: storage::mvcc::MVCC::new(engine) }
+        Self {
+            kv: storage::mvcc::MVCC::new(engine),
+            limits: super::Limits::default(),
+            clock: None,
+            tenant: None,
+        }
+    }
+
+    /// Sets the size limits for rows written via SQL.
+    pub fn with_limits(mut self, limits: super::Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets a clock to timestamp transaction commits with, see
+    /// storage::mvcc::Transaction::with_commit_timestamp(). The Raft state
+    /// machine doesn't use one, since replicas must commit at the same
+    /// timestamp, which is instead given via Transaction::commit_at().
+    pub fn with_clock(mut self, clock: HybridClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Uses the given tenant's keyspace for transactions, or the system keyspace if None. See
+    /// Transaction::key().
+    pub fn with_tenant(mut self, tenant: Option<String>) -> Self {
+        self.tenant = tenant;
+        self
+    }
+
+    /// Creates a SQL transaction from an MVCC transaction.
+    fn transaction(
+        &self,
+        txn: storage::mvcc::Transaction<E>,
+    ) -> Result<<Self as super::Engine>::Transaction> {
+        let keyspace = match &self.tenant {
+            Some(tenant) => KeyPrefix::TenantKeys(tenant.into()).encode()?,
+            None => Vec::new(),
+        };
+        Ok(Transaction {
+            txn,
+            kv: self.kv.clone(),
+            limits: self.limits,
+            clock: self.clock.clone(),
+            tenant: self.tenant.clone(),
+            keyspace,
+        })
+    }
+
+    /// Creates a SQL transaction from a new MVCC transaction, checking that the tenant exists
+    /// as of the transaction's snapshot. Otherwise, the transaction is rolled back.
+    fn begin_transaction(
+        &self,
+        txn: storage::mvcc::Transaction<E>,
+    ) -> Result<<Self as super::Engine>::Transaction> {
+        if let Some(tenant) = &self.tenant {
+            if txn.get(&Key::Tenant(tenant.into()).encode()?)?.is_none() {
+                txn.rollback()?;
+                return Err(Error::Value(format!("Tenant {} does not exist", tenant)));
+            }
+        }
+        self.transaction(txn)
+    }
+
+    /// Errors if the engine uses a tenant keyspace, since prepared transactions are global.
+    fn check_prepared_system(&self) -> Result<()> {
+        match self.tenant {
+            Some(_) => Err(Error::Value("Prepared transactions require the system tenant".into())),
+            None => Ok(()),
+        }
     }
 
     /// Resumes a transaction from the given state
@@ -33,7 +150,19 @@ impl<E: storage::engine::Engine> KV<E> {
         &self,
         state: storage::mvcc::TransactionState,
     ) -> Result<<Self as super::Engine>::Transaction> {
-        Ok(<Self as super::Engine>::Transaction::new(self.kv.resume(state)?))
+        self.transaction(self.kv.resume(state)?)
+    }
+
+    /// Resumes a prepared transaction and removes it from the prepared transactions, such that
+    /// it can be committed or rolled back.
+    pub fn resume_prepared(&self, gid: &str) -> Result<<Self as super::Engine>::Transaction> {
+        self.check_prepared_system()?;
+        let key = [PREPARED_PREFIX, gid.as_bytes()].concat();
+        let Some(state) = self.get_metadata(&key)? else {
+            return Err(Error::Value(format!("Prepared transaction {} does not exist", gid)));
+        };
+        self.delete_metadata(&key)?;
+        self.resume(deserialize(&state)?)
     }
 
     /// Fetches an unversioned metadata value
@@ -45,21 +174,188 @@ impl<E: storage::engine::Engine> KV<E> {
     pub fn set_metadata(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
         self.kv.set_unversioned(key, value)
     }
+
+    /// Deletes an unversioned metadata value
+    pub fn delete_metadata(&self, key: &[u8]) -> Result<()> {
+        self.kv.delete_unversioned(key)
+    }
+
+    /// Scans unversioned metadata values with the given key prefix
+    pub fn scan_metadata(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.kv.scan_unversioned_prefix(prefix)
+    }
+
+    /// Returns the key/value encoding version of the stored data. Data without a version
+    /// marker is version 0, unless nothing has been written yet.
+    pub fn format_version(&self) -> Result<u32> {
+        match self.get_metadata(FORMAT_VERSION_KEY)? {
+            Some(version) => bincode::deserialize(&version),
+            None if self.kv.status()?.versions == 0 => Ok(FORMAT_VERSION),
+            None => Ok(0),
+        }
+    }
+
+    /// Checks that the stored data uses the current key/value encoding, and records the version
+    /// for new data.
+    pub fn check_format(&self) -> Result<()> {
+        match self.format_version()? {
+            FORMAT_VERSION if self.get_metadata(FORMAT_VERSION_KEY)?.is_none() => {
+                self.set_metadata(FORMAT_VERSION_KEY, bincode::serialize(&FORMAT_VERSION)?)
+            }
+            FORMAT_VERSION => Ok(()),
+            version if version < FORMAT_VERSION => Err(Error::Internal(format!(
+                "SQL data has format version {}, must be migrated to version {} with toydb migrate",
+                version, FORMAT_VERSION
+            ))),
+            version => Err(Error::Internal(format!(
+                "SQL data has format version {}, newer than the supported version {}",
+                version, FORMAT_VERSION
+            ))),
+        }
+    }
+
+    /// Migrates the stored data to the current key/value encoding, one version at a time,
+    /// returning the previous version. Values are rewritten in place without creating new MVCC
+    /// versions, so all nodes end up with the same state. This must only be run on stopped
+    /// nodes, and is not atomic: if it fails, the data must be restored from a backup.
+    pub fn migrate(&self) -> Result<u32> {
+        let from = self.format_version()?;
+        if from > FORMAT_VERSION {
+            return Err(Error::Internal(format!(
+                "SQL data has format version {}, newer than the supported version {}",
+                from, FORMAT_VERSION
+            )));
+        }
+        for version in from..FORMAT_VERSION {
+            match version {
+                0 => self.kv.rewrite_prefix(&KeyPrefix::Table.encode()?, |_, value| {
+                    let table: TableV0 = deserialize(&value)?;
+                    serialize(&TableV1 { name: table.name, columns: table.columns, ttl: None })
+                })?,
+                // Existing rows are encoded with Bincode.
+                1 => self.kv.rewrite_prefix(&KeyPrefix::Table.encode()?, |_, value| {
+                    let table: TableV1 = deserialize(&value)?;
+                    serialize(&TableV2 {
+                        name: table.name,
+                        columns: table.columns,
+                        ttl: table.ttl,
+                        codec: Codec::Bincode,
+                    })
+                })?,
+                // Existing tables use the row layout.
+                2 => self.kv.rewrite_prefix(&KeyPrefix::Table.encode()?, |_, value| {
+                    let table: TableV2 = deserialize(&value)?;
+                    serialize(&Table {
+                        name: table.name,
+                        columns: table.columns,
+                        ttl: table.ttl,
+                        codec: table.codec,
+                        layout: Layout::Row,
+                    })
+                })?,
+                version => {
+                    return Err(Error::Internal(format!("No migration from version {}", version)))
+                }
+            };
+            self.set_metadata(FORMAT_VERSION_KEY, bincode::serialize(&(version + 1))?)?;
+        }
+        self.set_metadata(FORMAT_VERSION_KEY, bincode::serialize(&FORMAT_VERSION)?)?;
+        Ok(from)
+    }
+}
+
+/// A table schema in format version 0, without a row TTL.
+#[derive(Deserialize)]
+struct TableV0 {
+    name: String,
+    columns: Vec<Column>,
+}
+
+/// A table schema in format version 1, without a row codec.
+#[derive(Deserialize, Serialize)]
+struct TableV1 {
+    name: String,
+    columns: Vec<Column>,
+    ttl: Option<Ttl>,
+}
+
+/// A table schema in format version 2, without a storage layout.
+#[derive(Deserialize, Serialize)]
+struct TableV2 {
+    name: String,
+    columns: Vec<Column>,
+    ttl: Option<Ttl>,
+    codec: Codec,
 }
 
 impl<E: storage::engine::Engine> super::Engine for KV<E> {
     type Transaction = Transaction<E>;
 
+    fn set_tenant(&mut self, tenant: Option<String>) -> Result<()> {
+        self.tenant = tenant;
+        Ok(())
+    }
+
     fn begin(&self) -> Result<Self::Transaction> {
-        Ok(Self::Transaction::new(self.kv.begin()?))
+        self.begin_transaction(self.kv.begin()?)
     }
 
     fn begin_read_only(&self) -> Result<Self::Transaction> {
-        Ok(Self::Transaction::new(self.kv.begin_read_only()?))
+        self.begin_transaction(self.kv.begin_read_only()?)
     }
 
     fn begin_as_of(&self, version: u64) -> Result<Self::Transaction> {
-        Ok(Self::Transaction::new(self.kv.begin_as_of(version)?))
+        self.begin_transaction(self.kv.begin_as_of(version)?)
+    }
+
+    fn commit_prepared(&self, gid: &str) -> Result<u64> {
+        let txn = self.resume_prepared(gid)?;
+        let version = txn.version();
+        txn.commit()?;
+        Ok(version)
+    }
+
+    fn rollback_prepared(&self, gid: &str) -> Result<u64> {
+        let txn = self.resume_prepared(gid)?;
+        let version = txn.version();
+        txn.rollback()?;
+        Ok(version)
+    }
+
+    fn scan_prepared(&self) -> Result<Vec<(String, u64)>> {
+        self.check_prepared_system()?;
+        self.scan_metadata(PREPARED_PREFIX)?
+            .into_iter()
+            .map(|(key, value)| {
+                let gid = String::from_utf8_lossy(&key[PREPARED_PREFIX.len()..]).into_owned();
+                let state: storage::mvcc::TransactionState = deserialize(&value)?;
+                Ok((gid, state.version))
+            })
+            .collect()
+    }
+}
+
+/// Filters rows by an optional predicate, passing through errors.
+fn filter_rows<I: Iterator<Item = Result<Row>>>(
+    rows: I,
+    filter: Option<Expression>,
+) -> std::iter::FilterMap<I, impl FnMut(Result<Row>) -> Option<Result<Row>>> {
+    rows.filter_map(move |r| match r {
+        Ok(row) => match filter_row(&row, filter.as_ref()) {
+            Ok(true) => Some(Ok(row)),
+            Ok(false) => None,
+            Err(err) => Some(Err(err)),
+        },
+        err => Some(err),
+    })
+}
+
+/// Evaluates an optional predicate for a row, returning whether the row passes it.
+fn filter_row(row: &Row, filter: Option<&Expression>) -> Result<bool> {
+    match filter.map(|filter| filter.evaluate(Some(row))).transpose()? {
+        None | Some(Value::Boolean(true)) => Ok(true),
+        Some(Value::Boolean(false)) | Some(Value::Null) => Ok(false),
+        Some(v) => Err(Error::Value(format!("Filter returned {}, expected boolean", v))),
     }
 }
 
@@ -76,12 +372,62 @@ fn deserialize<'a, V: Deserialize<'a>>(bytes: &'a [u8]) -> Result<V> {
 /// An SQL transaction based on an MVCC key/value transaction
 pub struct Transaction<E: storage::engine::Engine> {
     txn: storage::mvcc::Transaction<E>,
+    /// The underlying key/value store, for unversioned metadata.
+    kv: storage::mvcc::MVCC<E>,
+    limits: super::Limits,
+    clock: Option<HybridClock>,
+    /// The transaction's tenant, or None for the system keyspace.
+    tenant: Option<String>,
+    /// The key prefix of the tenant's keyspace, or empty for the system keyspace.
+    keyspace: Vec<u8>,
 }
 
 impl<E: storage::engine::Engine> Transaction<E> {
-    /// Creates a new SQL transaction from an MVCC transaction
-    fn new(txn: storage::mvcc::Transaction<E>) -> Self {
-        Self { txn }
+    /// Commits the transaction at the given timestamp, see
+    /// storage::mvcc::Transaction::with_commit_timestamp().
+    pub fn commit_at(self, timestamp: Timestamp) -> Result<()> {
+        let key = self.key(Key::SchemaVersion)?;
+        self.into_committable()?.with_commit_timestamp(timestamp).commit_unchanged(&key)
+    }
+
+    /// Returns the MVCC transaction for committing, once it's checked that the tenant hasn't
+    /// been deleted (or recreated) since the transaction began, which would otherwise leave
+    /// its writes behind in the deleted keyspace. If it has, the transaction is rolled back.
+    fn into_committable(self) -> Result<storage::mvcc::Transaction<E>> {
+        if let (Some(tenant), false) = (&self.tenant, self.txn.read_only()) {
+            if let Err(error) = self.txn.check_unchanged(&Key::Tenant(tenant.into()).encode()?) {
+                self.txn.rollback()?;
+                return Err(error);
+            }
+        }
+        Ok(self.txn)
+    }
+
+    /// Encodes a key in the transaction's keyspace, i.e. prefixed by the tenant's keyspace
+    /// prefix, if any. Tenants thus can't access keys of other tenants or the system keyspace.
+    fn key(&self, key: Key) -> Result<Vec<u8>> {
+        Ok([self.keyspace.as_slice(), &key.encode()?].concat())
+    }
+
+    /// Encodes a key prefix in the transaction's keyspace, see key().
+    fn prefix(&self, prefix: KeyPrefix) -> Result<Vec<u8>> {
+        Ok([self.keyspace.as_slice(), &prefix.encode()?].concat())
+    }
+
+    /// Decodes a key in the transaction's keyspace, erroring if it belongs to another keyspace.
+    fn decode<'k>(&self, key: &'k [u8]) -> Result<Key<'k>> {
+        match key.strip_prefix(self.keyspace.as_slice()) {
+            Some(key) => Key::decode(key),
+            None => Err(Error::Internal(format!("Key {:?} is outside the keyspace", key))),
+        }
+    }
+
+    /// Errors if the transaction uses a tenant keyspace, for operations on the whole cluster.
+    fn check_system(&self, operation: &str) -> Result<()> {
+        match self.tenant {
+            Some(_) => Err(Error::Value(format!("{} requires the system tenant", operation))),
+            None => Ok(()),
+        }
     }
 
     /// Returns the transaction's serialized state.
@@ -89,11 +435,260 @@ impl<E: storage::engine::Engine> Transaction<E> {
         self.txn.state()
     }
 
+    /// Returns the schema version as seen by the transaction, which is incremented by every
+    /// schema change.
+    pub(super) fn schema_version(&self) -> Result<u64> {
+        self.txn.get(&self.key(Key::SchemaVersion)?)?.map(|v| deserialize(&v)).unwrap_or(Ok(0))
+    }
+
+    /// Increments the schema version. Concurrent schema changes will conflict on it. Schema
+    /// changes are refused while transactions are prepared, since they couldn't commit after it.
+    fn bump_schema_version(&mut self) -> Result<()> {
+        if !self.kv.scan_unversioned_prefix(PREPARED_PREFIX)?.is_empty() {
+            return Err(Error::Value(
+                "Can't change the schema while transactions are prepared".into(),
+            ));
+        }
+        let version = self.schema_version()? + 1;
+        self.txn.set(&self.key(Key::SchemaVersion)?, serialize(&version)?)
+    }
+
+    /// Scans up to limit rows of a table in a primary key range that pass the filter, only
+    /// reading the given columns of columnar tables. Also returns the primary key of the last
+    /// row if the scan stopped at the limit, from which to scan the rest of the range.
+    /// Columnar tables are scanned in full.
+    pub fn scan_chunk(
+        &self,
+        table: &str,
+        range: Range,
+        filter: Option<Expression>,
+        columns: Option<&[String]>,
+        limit: usize,
+    ) -> Result<(Vec<Row>, Option<Value>)> {
+        let table = self.must_read_table(table)?;
+        if table.layout == Layout::Columnar {
+            let columns = match columns {
+                Some(columns) => {
+                    columns.iter().map(|c| table.get_column_index(c)).collect::<Result<_>>()?
+                }
+                None => (0..table.columns.len()).collect::<Vec<_>>(),
+            };
+            let rows = self.group_scan(&table, range, &columns)?;
+            return Ok((
+                filter_rows(rows.into_iter().map(Ok), filter).collect::<Result<_>>()?,
+                None,
+            ));
+        }
+        let prefix = self.prefix(KeyPrefix::Row((&table.name).into()))?;
+        let range =
+            encode_range(&prefix, range, |id| self.key(Key::Row((&table.name).into(), id)))?;
+        let mut rows = Vec::new();
+        for r in self.txn.scan(range)?.iter() {
+            let row = table.codec.decode(&table, &r?.1)?;
+            if filter_row(&row, filter.as_ref())? {
+                rows.push(row);
+                if rows.len() == limit {
+                    let next = table.get_row_key(&rows[limit - 1])?;
+                    return Ok((rows, Some(next)));
+                }
+            }
+        }
+        Ok((rows, None))
+    }
+
+    /// Scans up to limit rows of a table in the order of an ordered index, or in reverse
+    /// order, after the given index entry key. Also returns the key of the last index entry
+    /// if the scan stopped at the limit, from which to scan the rest of the index.
+    pub fn scan_index_order_chunk(
+        &self,
+        table: &str,
+        index: &str,
+        reverse: bool,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<(Vec<Row>, Option<Vec<u8>>)> {
+        let table = self.must_read_table(table)?;
+        if self.read_ordered_index(&table.name, index)?.is_none() {
+            return Err(Error::Value(format!(
+                "Index {} does not exist on table {}",
+                index, table.name
+            )));
+        }
+        let prefix =
+            self.prefix(KeyPrefix::OrderedIndexEntry((&table.name).into(), index.into()))?;
+        let (start, end) = prefix_bounds(&prefix);
+        let range = match after {
+            Some(key) if reverse => (start, Bound::Excluded(key)),
+            Some(key) => (Bound::Excluded(key), end),
+            None => (start, end),
+        };
+        let mut scan = self.txn.scan(range)?;
+        let entries = match reverse {
+            true => scan.iter().rev().take(limit).collect::<Result<Vec<_>>>()?,
+            false => scan.iter().take(limit).collect::<Result<Vec<_>>>()?,
+        };
+        let next = match entries.last() {
+            Some((key, _)) if entries.len() == limit => Some(key.clone()),
+            _ => None,
+        };
+        let rows = entries
+            .into_iter()
+            .map(|(_, value)| {
+                let id = deserialize::<Value>(&value)?;
+                self.read(&table.name, &id)?.ok_or_else(|| {
+                    Error::Internal(format!("Missing row {} for index {}", id, index))
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok((rows, next))
+    }
+
+    /// Scans up to limit rows of a sample of a table that pass the filter, continuing from the
+    /// given cursor. Also returns a cursor to continue from if the scan stopped at the limit.
+    /// Resuming a sample replays its random draws, so the sample must have a seed.
+    ///
+    /// SYSTEM samples split row tables into SAMPLE_BLOCKS primary key ranges of similar key
+    /// space between the first and last row, drawing once per block, and BERNOULLI samples
+    /// draw once per row. Columnar tables are sampled in full, see sample_system() and
+    /// sample_bernoulli().
+    pub fn scan_sample_chunk(
+        &self,
+        table: &str,
+        sample: &Sample,
+        filter: Option<Expression>,
+        cursor: Option<SampleCursor>,
+        limit: usize,
+    ) -> Result<(Vec<Row>, Option<SampleCursor>)> {
+        let table = self.must_read_table(table)?;
+        if table.layout == Layout::Columnar {
+            let rows = match sample.method {
+                SampleMethod::System => self.sample_system(&table, sample)?,
+                SampleMethod::Bernoulli => self.sample_bernoulli(&table, sample)?,
+            };
+            return Ok((
+                filter_rows(rows.into_iter().map(Ok), filter).collect::<Result<_>>()?,
+                None,
+            ));
+        }
+        if cursor.is_some() && sample.seed.is_none() {
+            return Err(Error::Internal("Can't resume a sample without a seed".into()));
+        }
+        let (mut draws, after) = cursor.map_or((0, None), |c| (c.draws, Some(c.key)));
+        let mut rng = sample.rng();
+        for _ in 0..draws {
+            rng.gen_bool(sample.probability());
+        }
+
+        // Adds a sampled row to the chunk if it passes the filter, returning a cursor once the
+        // chunk is full.
+        let mut rows = Vec::new();
+        let add = |rows: &mut Vec<Row>, draws, key, value: &[u8]| -> Result<_> {
+            let row = table.codec.decode(&table, value)?;
+            if filter_row(&row, filter.as_ref())? {
+                rows.push(row);
+            }
+            Ok((rows.len() == limit).then_some(SampleCursor { draws, key }))
+        };
+
+        let prefix = self.prefix(KeyPrefix::Row((&table.name).into()))?;
+        match sample.method {
+            SampleMethod::System => {
+                let mut scan = self.txn.scan_prefix(&prefix)?;
+                let mut iter = scan.iter();
+                let Some((first, _)) = iter.next().transpose()? else { return Ok((rows, None)) };
+                let last =
+                    iter.next_back().transpose()?.map_or_else(|| first.clone(), |(key, _)| key);
+                let splits = split_keys(&first, &last, SAMPLE_BLOCKS);
+                let starts = std::iter::once(first).chain(splits.clone()).map(Bound::Included);
+                let ends = splits.into_iter().map(Bound::Excluded).chain([Bound::Included(last)]);
+                // Skip the blocks drawn by earlier chunks, and continue the one the cursor is in.
+                let skip = draws.saturating_sub(1) as usize;
+                for (i, (mut start, end)) in starts.zip(ends).enumerate().skip(skip) {
+                    match &after {
+                        Some(key) if i + 1 == draws as usize => {
+                            start = Bound::Excluded(key.clone())
+                        }
+                        _ => {
+                            draws += 1;
+                            if !rng.gen_bool(sample.probability()) {
+                                continue;
+                            }
+                        }
+                    }
+                    for r in self.txn.scan((start, end))?.iter() {
+                        let (key, value) = r?;
+                        if let Some(cursor) = add(&mut rows, draws, key, &value)? {
+                            return Ok((rows, Some(cursor)));
+                        }
+                    }
+                }
+            }
+            SampleMethod::Bernoulli => {
+                let (start, end) = prefix_bounds(&prefix);
+                let start = after.map_or(start, Bound::Excluded);
+                for r in self.txn.scan((start, end))?.iter() {
+                    let (key, value) = r?;
+                    draws += 1;
+                    if rng.gen_bool(sample.probability()) {
+                        if let Some(cursor) = add(&mut rows, draws, key, &value)? {
+                            return Ok((rows, Some(cursor)));
+                        }
+                    }
+                }
+            }
+        }
+        Ok((rows, None))
+    }
+
+    /// Errors if the table is referenced by another table.
+    fn check_unreferenced(&self, table: &Table) -> Result<()> {
+        if let Some((t, cs)) = self.table_references(&table.name, false)?.first() {
+            return Err(Error::Constraint {
+                name: format!("{}_{}_fkey", t, cs[0]),
+                message: format!(
+                    "Table {} is referenced by table {} column {}",
+                    table.name, t, cs[0]
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads a dropped table, if it exists.
+    fn read_dropped_table(&self, table: &str) -> Result<Option<DroppedTable>> {
+        self.txn
+            .get(&self.key(Key::DroppedTable(table.into()))?)?
+            .map(|v| deserialize(&v))
+            .transpose()
+    }
+
+    /// Returns the number of keys with the given prefix, and their total key and value size.
+    fn prefix_size(&self, prefix: KeyPrefix) -> Result<(u64, u64)> {
+        self.txn.scan_prefix(&self.prefix(prefix)?)?.iter().try_fold((0, 0), |(count, size), r| {
+            let (key, value) = r?;
+            Ok((count + 1, size + (key.len() + value.len()) as u64))
+        })
+    }
+
+    /// Deletes all keys with the given prefix.
+    fn delete_prefix(&mut self, prefix: &[u8]) -> Result<()> {
+        let keys = self
+            .txn
+            .scan_prefix(prefix)?
+            .iter()
+            .map(|r| r.map(|(k, _)| k))
+            .collect::<Result<Vec<_>>>()?;
+        for key in keys {
+            self.txn.delete(&key)?;
+        }
+        Ok(())
+    }
+
     /// Loads an index entry
     fn index_load(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Value>> {
         Ok(self
             .txn
-            .get(&Key::Index(table.into(), column.into(), value.into()).encode()?)?
+            .get(&self.key(Key::Index(table.into(), column.into(), value.into()))?)?
             .map(|v| deserialize(&v))
             .transpose()?
             .unwrap_or_default())
@@ -107,143 +702,618 @@ impl<E: storage::engine::Engine> Transaction<E> {
         value: &Value,
         index: HashSet<Value>,
     ) -> Result<()> {
-        let key = Key::Index(table.into(), column.into(), value.into()).encode()?;
+        let key = self.key(Key::Index(table.into(), column.into(), value.into()))?;
         if index.is_empty() {
             self.txn.delete(&key)
         } else {
             self.txn.set(&key, serialize(&index)?)
         }
     }
-}
 
-impl<E: storage::engine::Engine> super::Transaction for Transaction<E> {
-    fn version(&self) -> u64 {
-        self.txn.version()
+    /// Loads a covering index entry, mapping primary keys to included column values.
+    fn cover_load(
+        &self,
+        table: &str,
+        column: &str,
+        value: &Value,
+    ) -> Result<HashMap<Value, Vec<Value>>> {
+        Ok(self
+            .txn
+            .get(&self.key(Key::IndexCover(table.into(), column.into(), value.into()))?)?
+            .map(|v| deserialize(&v))
+            .transpose()?
+            .unwrap_or_default())
     }
 
-    fn read_only(&self) -> bool {
-        self.txn.read_only()
+    /// Saves a covering index entry.
+    fn cover_save(
+        &mut self,
+        table: &str,
+        column: &str,
+        value: &Value,
+        cover: HashMap<Value, Vec<Value>>,
+    ) -> Result<()> {
+        let key = self.key(Key::IndexCover(table.into(), column.into(), value.into()))?;
+        if cover.is_empty() {
+            self.txn.delete(&key)
+        } else {
+            self.txn.set(&key, serialize(&cover)?)
+        }
     }
 
-    fn commit(self) -> Result<()> {
-        self.txn.commit()
+    /// Adds a row to the index of the given column, and its included columns if any.
+    fn index_add(&mut self, table: &Table, i: usize, id: &Value, row: &[Value]) -> Result<()> {
+        let column = &table.columns[i];
+        let mut index = self.index_load(&table.name, &column.name, &row[i])?;
+        index.insert(id.clone());
+        self.index_save(&table.name, &column.name, &row[i], index)?;
+        if !column.include.is_empty() {
+            let mut cover = self.cover_load(&table.name, &column.name, &row[i])?;
+            cover.insert(id.clone(), included(table, column, row)?);
+            self.cover_save(&table.name, &column.name, &row[i], cover)?;
+        }
+        Ok(())
     }
 
-    fn rollback(self) -> Result<()> {
-        self.txn.rollback()
+    /// Removes a row from the index of the given column, and its included columns if any.
+    fn index_remove(&mut self, table: &Table, i: usize, id: &Value, row: &[Value]) -> Result<()> {
+        let column = &table.columns[i];
+        let mut index = self.index_load(&table.name, &column.name, &row[i])?;
+        index.remove(id);
+        self.index_save(&table.name, &column.name, &row[i], index)?;
+        if !column.include.is_empty() {
+            let mut cover = self.cover_load(&table.name, &column.name, &row[i])?;
+            cover.remove(id);
+            self.cover_save(&table.name, &column.name, &row[i], cover)?;
+        }
+        Ok(())
     }
 
-    fn create(&mut self, table: &str, row: Row) -> Result<()> {
-        let table = self.must_read_table(table)?;
-        table.validate_row(&row, self)?;
-        let id = table.get_row_key(&row)?;
-        if self.read(&table.name, &id)?.is_some() {
-            return Err(Error::Value(format!(
-                "Primary key {} already exists for table {}",
-                id, table.name
-            )));
+    /// Reads an index created with CREATE INDEX, if it exists.
+    fn read_ordered_index(&self, table: &str, index: &str) -> Result<Option<Index>> {
+        self.txn
+            .get(&self.key(Key::OrderedIndex(table.into(), index.into()))?)?
+            .map(|v| deserialize(&v))
+            .transpose()
+    }
+
+    /// Replaces a row's entries in the given indexes created with CREATE INDEX, removing the
+    /// entries of the old row, if any, and adding entries for the new row, if any.
+    fn ordered_index_write(
+        &mut self,
+        table: &Table,
+        indexes: &[Index],
+        id: &Value,
+        old: Option<&[Value]>,
+        new: Option<&[Value]>,
+    ) -> Result<()> {
+        for index in indexes {
+            let key = |row| ordered_index_key(table, index, id, row).and_then(|k| self.key(k));
+            let old = old.map(key).transpose()?;
+            let new = new.map(key).transpose()?;
+            if old == new {
+                continue;
+            }
+            if let Some(old) = old {
+                self.txn.delete(&old)?;
+            }
+            if let Some(new) = new {
+                self.txn.set(&new, serialize(id)?)?;
+            }
         }
-        self.txn.set(&Key::Row((&table.name).into(), (&id).into()).encode()?, serialize(&row)?)?;
+        Ok(())
+    }
 
-        // Update indexes
-        for (i, column) in table.columns.iter().enumerate().filter(|(_, c)| c.index) {
-            let mut index = self.index_load(&table.name, &column.name, &row[i])?;
-            index.insert(id.clone());
-            self.index_save(&table.name, &column.name, &row[i], index)?;
+    /// Finds the row group of a columnar table that a primary key belongs in, returning the
+    /// group's first primary key: the last group starting at or before the key, or else the
+    /// first group. Returns None if the table is empty.
+    fn group_find(&self, table: &Table, id: &Value) -> Result<Option<Value>> {
+        let pk = &table.get_primary_key()?.name;
+        let prefix = self.prefix(KeyPrefix::ColumnChunk((&table.name).into(), pk.into()))?;
+        let range = encode_range(&prefix, (Bound::Unbounded, Bound::Included(id.clone())), |v| {
+            self.key(Key::ColumnChunk((&table.name).into(), pk.into(), v))
+        })?;
+        let mut entry = self.txn.scan(range)?.iter().next_back().transpose()?;
+        if entry.is_none() {
+            entry = self.txn.scan_prefix(&prefix)?.iter().next().transpose()?;
+        }
+        entry
+            .map(|(key, _)| match self.decode(&key)? {
+                Key::ColumnChunk(_, _, first) => Ok(first.into_owned()),
+                _ => Err(Error::Internal("Invalid column chunk key".into())),
+            })
+            .transpose()
+    }
+
+    /// Loads a column's values in a columnar table's row group, in primary key order.
+    fn chunk_load(&self, table: &Table, column: usize, first: &Value) -> Result<Vec<Value>> {
+        let column = &table.columns[column];
+        let key = Key::ColumnChunk((&table.name).into(), (&column.name).into(), first.into());
+        let chunk = self.txn.get(&self.key(key)?)?.ok_or_else(|| {
+            Error::Internal(format!("Missing chunk {}.{} at {}", table.name, column.name, first))
+        })?;
+        deserialize::<Chunk>(&chunk)?.decode()
+    }
+
+    /// Loads the values of all columns in a columnar table's row group, by column index.
+    fn group_load(&self, table: &Table, first: &Value) -> Result<Vec<Vec<Value>>> {
+        (0..table.columns.len()).map(|i| self.chunk_load(table, i, first)).collect()
+    }
+
+    /// Writes the values of all columns in a columnar table's row group, in primary key order,
+    /// replacing the group previously starting at the given primary key, if any. Groups larger
+    /// than ROW_GROUP_SIZE are split into evenly sized groups.
+    fn group_save(
+        &mut self,
+        table: &Table,
+        first: Option<Value>,
+        mut columns: Vec<Vec<Value>>,
+    ) -> Result<()> {
+        let pk = table.get_column_index(&table.get_primary_key()?.name)?;
+        if let Some(first) = first.filter(|first| columns[pk].first() != Some(first)) {
+            for column in &table.columns {
+                self.txn.delete(&self.key(Key::ColumnChunk(
+                    (&table.name).into(),
+                    (&column.name).into(),
+                    (&first).into(),
+                ))?)?;
+            }
+        }
+        let len = columns[pk].len();
+        let size = len.div_ceil(len.div_ceil(ROW_GROUP_SIZE).max(1)).max(1);
+        for start in (0..len).step_by(size).rev() {
+            let group: Vec<_> = columns.iter_mut().map(|values| values.split_off(start)).collect();
+            let first = group[pk][0].clone();
+            for (column, values) in table.columns.iter().zip(group) {
+                self.txn.set(
+                    &self.key(Key::ColumnChunk(
+                        (&table.name).into(),
+                        (&column.name).into(),
+                        (&first).into(),
+                    ))?,
+                    serialize(&Chunk::encode(values))?,
+                )?;
+            }
         }
         Ok(())
     }
 
-    fn delete(&mut self, table: &str, id: &Value) -> Result<()> {
-        let table = self.must_read_table(table)?;
-        for (t, cs) in self.table_references(&table.name, true)? {
-            let t = self.must_read_table(&t)?;
-            let cs = cs
-                .into_iter()
-                .map(|c| Ok((t.get_column_index(&c)?, c)))
-                .collect::<Result<Vec<_>>>()?;
-            let mut scan = self.scan(&t.name, None)?;
-            while let Some(row) = scan.next().transpose()? {
-                for (i, c) in &cs {
-                    if &row[*i] == id && (table.name != t.name || id != &table.get_row_key(&row)?) {
-                        return Err(Error::Value(format!(
-                            "Primary key {} is referenced by table {} column {}",
-                            id, t.name, c
-                        )));
-                    }
+    /// Inserts or replaces a row of a columnar table, or removes it if None, rewriting its row
+    /// group. The primary key column is searched before loading the other columns.
+    fn group_write(&mut self, table: &Table, id: &Value, row: Option<Row>) -> Result<()> {
+        let pk = table.get_column_index(&table.get_primary_key()?.name)?;
+        let Some(first) = self.group_find(table, id)? else {
+            return match row {
+                Some(row) => {
+                    self.group_save(table, None, row.into_iter().map(|v| vec![v]).collect())
                 }
+                None => Ok(()),
+            };
+        };
+        let ids = self.chunk_load(table, pk, &first)?;
+        let position = ids.binary_search_by(|v| v.partial_cmp(id).unwrap_or(Ordering::Equal));
+        let mut columns = match (position, &row) {
+            (Err(_), None) => return Ok(()),
+            // Appending to a full group starts a new one, so that ordered inserts fill groups.
+            (Err(i), Some(_)) if i >= ROW_GROUP_SIZE => {
+                let columns = row.into_iter().flatten().map(|v| vec![v]).collect();
+                return self.group_save(table, None, columns);
+            }
+            _ => self.group_load(table, &first)?,
+        };
+        for (i, values) in columns.iter_mut().enumerate() {
+            match (position, &row) {
+                (Ok(p), Some(row)) => values[p] = row[i].clone(),
+                (Ok(p), None) => {
+                    values.remove(p);
+                }
+                (Err(p), Some(row)) => values.insert(p, row[i].clone()),
+                (Err(_), None) => {}
             }
         }
+        self.group_save(table, Some(first), columns)
+    }
 
-        let indexes: Vec<_> = table.columns.iter().enumerate().filter(|(_, c)| c.index).collect();
-        if !indexes.is_empty() {
-            if let Some(row) = self.read(&table.name, id)? {
-                for (i, column) in indexes {
-                    let mut index = self.index_load(&table.name, &column.name, &row[i])?;
-                    index.remove(id);
-                    self.index_save(&table.name, &column.name, &row[i], index)?;
+    /// Reads a row of a columnar table, if it exists. The primary key column is searched before
+    /// loading the other columns.
+    fn group_read(&self, table: &Table, id: &Value) -> Result<Option<Row>> {
+        let pk = table.get_column_index(&table.get_primary_key()?.name)?;
+        let Some(first) = self.group_find(table, id)? else { return Ok(None) };
+        let ids = self.chunk_load(table, pk, &first)?;
+        let Ok(i) = ids.binary_search_by(|v| v.partial_cmp(id).unwrap_or(Ordering::Equal)) else {
+            return Ok(None);
+        };
+        let columns = self.group_load(table, &first)?;
+        Ok(Some(columns.into_iter().map(|mut values| values.swap_remove(i)).collect()))
+    }
+
+    /// Scans the rows of a columnar table in a primary key range, only reading the chunks of the
+    /// given columns and the primary key. Other columns are NULL.
+    fn group_scan(&self, table: &Table, range: Range, columns: &[usize]) -> Result<Vec<Row>> {
+        let pk = table.get_column_index(&table.get_primary_key()?.name)?;
+        let mut columns = columns.to_vec();
+        columns.push(pk);
+        columns.sort_unstable();
+        columns.dedup();
+
+        // Scan the groups from the one containing the start of the range, if any.
+        let start = match &range.0 {
+            Bound::Included(id) | Bound::Excluded(id) => match self.group_find(table, id)? {
+                Some(first) => Bound::Included(first),
+                None => return Ok(Vec::new()),
+            },
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let mut groups: Vec<Vec<(usize, Vec<Value>)>> = Vec::new();
+        for i in columns.iter().copied() {
+            let column = &table.columns[i];
+            let prefix =
+                self.prefix(KeyPrefix::ColumnChunk((&table.name).into(), (&column.name).into()))?;
+            let keys = encode_range(&prefix, (start.clone(), range.1.clone()), |v| {
+                self.key(Key::ColumnChunk((&table.name).into(), (&column.name).into(), v))
+            })?;
+            let chunks = self.txn.scan(keys)?.iter().collect::<Result<Vec<_>>>()?;
+            for (g, (_, chunk)) in chunks.into_iter().enumerate() {
+                if groups.len() <= g {
+                    groups.push(Vec::with_capacity(columns.len()));
                 }
+                groups[g].push((i, deserialize::<Chunk>(&chunk)?.decode()?));
             }
         }
-        self.txn.delete(&Key::Row(table.name.into(), id.into()).encode()?)
-    }
 
-    fn read(&self, table: &str, id: &Value) -> Result<Option<Row>> {
-        self.txn
-            .get(&Key::Row(table.into(), id.into()).encode()?)?
-            .map(|v| deserialize(&v))
-            .transpose()
+        let mut rows = Vec::new();
+        for group in groups {
+            if group.len() != columns.len() {
+                return Err(Error::Internal(format!("Missing chunks in table {}", table.name)));
+            }
+            rows.extend(group_rows(table, group)?.into_iter().filter(|r| range.contains(&r[pk])));
+        }
+        Ok(rows)
     }
 
-    fn read_index(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Value>> {
-        if !self.must_read_table(table)?.get_column(column)?.index {
-            return Err(Error::Value(format!("No index on {}.{}", table, column)));
+    /// Reads a SYSTEM sample of a columnar table, drawing once per row group. Only the sampled
+    /// row groups are read.
+    fn sample_system(&self, table: &Table, sample: &Sample) -> Result<Vec<Row>> {
+        let mut rng = sample.rng();
+        let pk = table.get_primary_key()?;
+        let prefix =
+            self.prefix(KeyPrefix::ColumnChunk((&table.name).into(), (&pk.name).into()))?;
+        let mut scan = self.txn.scan_prefix(&prefix)?;
+        let firsts = scan
+            .iter()
+            .map(|r| match self.decode(&r?.0)? {
+                Key::ColumnChunk(_, _, first) => Ok(first.into_owned()),
+                _ => Err(Error::Internal("Invalid column chunk key".into())),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut rows = Vec::new();
+        for first in firsts {
+            if rng.gen_bool(sample.probability()) {
+                let columns = self.group_load(table, &first)?.into_iter().enumerate();
+                rows.extend(group_rows(table, columns.collect())?);
+            }
         }
-        self.index_load(table, column, value)
+        Ok(rows)
     }
 
-    fn scan(&self, table: &str, filter: Option<Expression>) -> Result<super::Scan> {
-        let table = self.must_read_table(table)?;
-        Ok(Box::new(
-            self.txn
-                .scan_prefix(&KeyPrefix::Row((&table.name).into()).encode()?)?
-                .iter()
-                .map(|r| r.and_then(|(_, v)| deserialize(&v)))
-                .filter_map(move |r| match r {
-                    Ok(row) => match &filter {
-                        Some(filter) => match filter.evaluate(Some(&row)) {
-                            Ok(Value::Boolean(b)) if b => Some(Ok(row)),
-                            Ok(Value::Boolean(_)) | Ok(Value::Null) => None,
-                            Ok(v) => Some(Err(Error::Value(format!(
-                                "Filter returned {}, expected boolean",
-                                v
-                            )))),
-                            Err(err) => Some(Err(err)),
-                        },
-                        None => Some(Ok(row)),
-                    },
-                    err => Some(err),
-                })
-                .collect::<Vec<_>>()
-                .into_iter(),
-        ))
+    /// Reads a BERNOULLI sample of a columnar table, drawing once per row.
+    fn sample_bernoulli(&self, table: &Table, sample: &Sample) -> Result<Vec<Row>> {
+        let mut rng = sample.rng();
+        let columns: Vec<_> = (0..table.columns.len()).collect();
+        let rows = self.group_scan(table, (Bound::Unbounded, Bound::Unbounded), &columns)?;
+        Ok(rows.into_iter().filter(|_| rng.gen_bool(sample.probability())).collect())
     }
+}
 
-    fn scan_index(&self, table: &str, column: &str) -> Result<super::IndexScan> {
-        let table = self.must_read_table(table)?;
-        let column = table.get_column(column)?;
-        if !column.index {
-            return Err(Error::Value(format!("No index for {}.{}", table.name, column.name)));
+/// Splits the key range from start to end into up to the given number of blocks of equal key
+/// space, by interpolating the 16 bytes following their common prefix. Returns the boundaries
+/// between the blocks, in order.
+fn split_keys(start: &[u8], end: &[u8], blocks: u128) -> Vec<Vec<u8>> {
+    let common = start.iter().zip(end).take_while(|(a, b)| a == b).count();
+    let position = |key: &[u8]| {
+        let tail = &key[common..];
+        let mut bytes = [0; 16];
+        let len = tail.len().min(16);
+        bytes[..len].copy_from_slice(&tail[..len]);
+        u128::from_be_bytes(bytes)
+    };
+    let (low, high) = (position(start), position(end));
+    let step = high.saturating_sub(low) / blocks;
+    if step == 0 {
+        return Vec::new();
+    }
+    (1..blocks).map(|i| [&start[..common], &(low + step * i).to_be_bytes()].concat()).collect()
+}
+
+/// Encodes the key of a row's entry in an index created with CREATE INDEX. The entries are
+/// ordered by the KeyCode encoding of each indexed value followed by the primary key. Value
+/// encodings are prefix-free, so inverting the bytes of descending values reverses their order.
+fn ordered_index_key(
+    table: &Table,
+    index: &Index,
+    id: &Value,
+    row: &[Value],
+) -> Result<Key<'static>> {
+    let mut key = Vec::new();
+    for (column, direction) in &index.columns {
+        let value = keycode::serialize(&row[table.get_column_index(column)?])?;
+        match direction {
+            Direction::Ascending => key.extend(value),
+            Direction::Descending => key.extend(value.into_iter().map(|b| !b)),
         }
-        Ok(Box::new(
-            self.txn
+    }
+    key.extend(keycode::serialize(id)?);
+    Ok(Key::OrderedIndexEntry(table.name.clone().into(), index.name.clone().into(), key.into()))
+}
+
+/// Returns the values of a row's columns included in the given column's index.
+fn included(table: &Table, column: &Column, row: &[Value]) -> Result<Vec<Value>> {
+    column.include.iter().map(|c| Ok(row[table.get_column_index(c)?].clone())).collect()
+}
+
+/// Assembles the rows of a columnar table's row group from the values of the given columns, by
+/// column index. Other columns are NULL.
+fn group_rows(table: &Table, columns: Vec<(usize, Vec<Value>)>) -> Result<Vec<Row>> {
+    let len = columns.first().map_or(0, |(_, values)| values.len());
+    let mut rows = vec![vec![Value::Null; table.columns.len()]; len];
+    for (i, values) in columns {
+        if values.len() != len {
+            return Err(Error::Internal(format!("Mismatched chunks in table {}", table.name)));
+        }
+        for (row, value) in rows.iter_mut().zip(values) {
+            row[i] = value;
+        }
+    }
+    Ok(rows)
+}
+
+impl<E: storage::engine::Engine> super::Transaction for Transaction<E> {
+    fn version(&self) -> u64 {
+        self.txn.version()
+    }
+
+    fn read_only(&self) -> bool {
+        self.txn.read_only()
+    }
+
+    fn limits(&self) -> super::Limits {
+        self.limits
+    }
+
+    // Transactions that were planned against a stale schema can't commit.
+    fn commit(self) -> Result<()> {
+        match self.clock.as_ref().map(|clock| clock.now()) {
+            Some(timestamp) => self.commit_at(timestamp),
+            None => {
+                let key = self.key(Key::SchemaVersion)?;
+                self.into_committable()?.commit_unchanged(&key)
+            }
+        }
+    }
+
+    fn rollback(self) -> Result<()> {
+        self.txn.rollback()
+    }
+
+    // The schema is checked like on commit, and can't change while the transaction is
+    // prepared, so it will be able to commit.
+    fn prepare(self, gid: &str) -> Result<()> {
+        let key = [PREPARED_PREFIX, gid.as_bytes()].concat();
+        let result = if self.tenant.is_some() {
+            Err(Error::Value("Prepared transactions require the system tenant".into()))
+        } else if self.txn.read_only() {
+            Err(Error::Value("Can't prepare a read-only transaction".into()))
+        } else if self.kv.get_unversioned(&key)?.is_some() {
+            Err(Error::Value(format!("Prepared transaction {} already exists", gid)))
+        } else {
+            self.check_schema()
+        };
+        if let Err(error) = result {
+            self.txn.rollback()?;
+            return Err(error);
+        }
+        self.kv.set_unversioned(&key, serialize(self.txn.state())?)
+    }
+
+    fn check_schema(&self) -> Result<()> {
+        if self.txn.read_only() {
+            return Ok(());
+        }
+        self.txn.check_unchanged(&self.key(Key::SchemaVersion)?)
+    }
+
+    fn savepoint(&mut self) -> Result<()> {
+        self.txn.savepoint()
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        self.txn.rollback_to_savepoint()
+    }
+
+    fn create(&mut self, table: &str, row: Row) -> Result<()> {
+        let table = self.must_read_table(table)?;
+        table.validate_row(&row, self)?;
+        let id = table.get_row_key(&row)?;
+        if self.read(&table.name, &id)?.is_some() {
+            return Err(Error::Constraint {
+                name: format!("{}_pkey", table.name),
+                message: format!("Primary key {} already exists for table {}", id, table.name),
+            });
+        }
+        match table.layout {
+            Layout::Row => self.txn.set(
+                &self.key(Key::Row((&table.name).into(), (&id).into()))?,
+                table.codec.encode(&table, &row)?,
+            )?,
+            Layout::Columnar => self.group_write(&table, &id, Some(row.clone()))?,
+        }
+
+        // Update indexes
+        for i in (0..table.columns.len()).filter(|i| table.columns[*i].index) {
+            self.index_add(&table, i, &id, &row)?;
+        }
+        let indexes = self.scan_indexes(&table.name)?;
+        self.ordered_index_write(&table, &indexes, &id, None, Some(&row))
+    }
+
+    fn delete(&mut self, table: &str, id: &Value) -> Result<()> {
+        let table = self.must_read_table(table)?;
+        for (t, cs) in self.table_references(&table.name, true)? {
+            let t = self.must_read_table(&t)?;
+            let cs = cs
+                .into_iter()
+                .map(|c| Ok((t.get_column_index(&c)?, c)))
+                .collect::<Result<Vec<_>>>()?;
+            let mut scan = self.scan(&t.name, None)?;
+            while let Some(row) = scan.next().transpose()? {
+                for (i, c) in &cs {
+                    if &row[*i] == id && (table.name != t.name || id != &table.get_row_key(&row)?) {
+                        return Err(Error::Constraint {
+                            name: format!("{}_{}_fkey", t.name, c),
+                            message: format!(
+                                "Primary key {} is referenced by table {} column {}",
+                                id, t.name, c
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+
+        let indexes: Vec<_> =
+            (0..table.columns.len()).filter(|i| table.columns[*i].index).collect();
+        let ordered = self.scan_indexes(&table.name)?;
+        if !indexes.is_empty() || !ordered.is_empty() {
+            if let Some(row) = self.read(&table.name, id)? {
+                for i in indexes {
+                    self.index_remove(&table, i, id, &row)?;
+                }
+                self.ordered_index_write(&table, &ordered, id, Some(&row), None)?;
+            }
+        }
+        match table.layout {
+            Layout::Row => self.txn.delete(&self.key(Key::Row(table.name.into(), id.into()))?),
+            Layout::Columnar => self.group_write(&table, id, None),
+        }
+    }
+
+    fn read(&self, table: &str, id: &Value) -> Result<Option<Row>> {
+        let table = self.must_read_table(table)?;
+        if table.layout == Layout::Columnar {
+            return self.group_read(&table, id);
+        }
+        self.txn
+            .get(&self.key(Key::Row((&table.name).into(), id.into()))?)?
+            .map(|v| table.codec.decode(&table, &v))
+            .transpose()
+    }
+
+    fn read_index(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Value>> {
+        if !self.must_read_table(table)?.get_column(column)?.index {
+            return Err(Error::Value(format!("No index on {}.{}", table, column)));
+        }
+        self.index_load(table, column, value)
+    }
+
+    fn scan(&self, table: &str, filter: Option<Expression>) -> Result<super::Scan> {
+        let table = self.must_read_table(table)?;
+        if table.layout == Layout::Columnar {
+            let columns: Vec<_> = (0..table.columns.len()).collect();
+            let rows = self.group_scan(&table, (Bound::Unbounded, Bound::Unbounded), &columns)?;
+            return Ok(Box::new(filter_rows(rows.into_iter().map(Ok), filter)));
+        }
+        let mut scan = self.txn.scan_prefix(&self.prefix(KeyPrefix::Row((&table.name).into()))?)?;
+        let rows = scan.iter().map(|r| r.and_then(|(_, v)| table.codec.decode(&table, &v)));
+        Ok(Box::new(filter_rows(rows, filter).collect::<Vec<_>>().into_iter()))
+    }
+
+    fn scan_columns(
+        &self,
+        table: &str,
+        filter: Option<Expression>,
+        columns: &[String],
+    ) -> Result<super::Scan> {
+        let table = self.must_read_table(table)?;
+        if table.layout != Layout::Columnar {
+            return self.scan(&table.name, filter);
+        }
+        let columns =
+            columns.iter().map(|c| table.get_column_index(c)).collect::<Result<Vec<_>>>()?;
+        let rows = self.group_scan(&table, (Bound::Unbounded, Bound::Unbounded), &columns)?;
+        Ok(Box::new(filter_rows(rows.into_iter().map(Ok), filter)))
+    }
+
+    // Only the raw values are read from storage here, deserialization and filtering is done
+    // lazily by the partition iterators. Columnar tables are read eagerly, and filtered lazily.
+    fn scan_partitions(
+        &self,
+        table: &str,
+        filter: Option<Expression>,
+        columns: Option<&[String]>,
+        partitions: usize,
+    ) -> Result<Vec<super::Scan>> {
+        let table = self.must_read_table(table)?;
+        if table.layout == Layout::Columnar {
+            let columns = match columns {
+                Some(columns) => {
+                    columns.iter().map(|c| table.get_column_index(c)).collect::<Result<_>>()?
+                }
+                None => (0..table.columns.len()).collect::<Vec<_>>(),
+            };
+            let rows = self.group_scan(&table, (Bound::Unbounded, Bound::Unbounded), &columns)?;
+            return Ok(super::partition(rows, partitions)
+                .into_iter()
+                .map(|rows| {
+                    Box::new(filter_rows(rows.into_iter().map(Ok), filter.clone())) as super::Scan
+                })
+                .collect());
+        }
+        let values = self
+            .txn
+            .scan_prefix(&self.prefix(KeyPrefix::Row((&table.name).into()))?)?
+            .iter()
+            .map(|r| r.map(|(_, v)| v))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(super::partition(values, partitions)
+            .into_iter()
+            .map(|values| {
+                let table = table.clone();
+                let rows = values.into_iter().map(move |v| table.codec.decode(&table, &v));
+                Box::new(filter_rows(rows, filter.clone())) as super::Scan
+            })
+            .collect())
+    }
+
+    fn scan_sample(
+        &self,
+        table: &str,
+        sample: &Sample,
+        filter: Option<Expression>,
+    ) -> Result<super::Scan> {
+        let (rows, _) = self.scan_sample_chunk(table, sample, filter, None, usize::MAX)?;
+        Ok(Box::new(rows.into_iter().map(Ok)))
+    }
+
+    fn scan_index_order(&self, table: &str, index: &str, reverse: bool) -> Result<super::Scan> {
+        let (rows, _) = self.scan_index_order_chunk(table, index, reverse, None, usize::MAX)?;
+        Ok(Box::new(rows.into_iter().map(Ok)))
+    }
+
+    fn scan_index(&self, table: &str, column: &str) -> Result<super::IndexScan> {
+        let table = self.must_read_table(table)?;
+        let column = table.get_column(column)?;
+        if !column.index {
+            return Err(Error::Value(format!("No index for {}.{}", table.name, column.name)));
+        }
+        Ok(Box::new(
+            self.txn
                 .scan_prefix(
-                    &KeyPrefix::Index((&table.name).into(), (&column.name).into()).encode()?,
+                    &self.prefix(KeyPrefix::Index((&table.name).into(), (&column.name).into()))?,
                 )?
                 .iter()
                 .map(|r| -> Result<(Value, HashSet<Value>)> {
                     let (k, v) = r?;
-                    let value = match Key::decode(&k)? {
+                    let value = match self.decode(&k)? {
                         Key::Index(_, _, pk) => pk.into_owned(),
                         _ => return Err(Error::Internal("Invalid index key".into())),
                     };
@@ -254,6 +1324,95 @@ impl<E: storage::engine::Engine> super::Transaction for Transaction<E> {
         ))
     }
 
+    fn scan_range(&self, table: &str, range: Range) -> Result<super::Scan> {
+        let table = self.must_read_table(table)?;
+        if table.layout == Layout::Columnar {
+            let columns: Vec<_> = (0..table.columns.len()).collect();
+            return Ok(Box::new(self.group_scan(&table, range, &columns)?.into_iter().map(Ok)));
+        }
+        let prefix = self.prefix(KeyPrefix::Row((&table.name).into()))?;
+        let range =
+            encode_range(&prefix, range, |id| self.key(Key::Row((&table.name).into(), id)))?;
+        let mut scan = self.txn.scan(range)?;
+        let rows = scan.iter().map(|r| r.and_then(|(_, v)| table.codec.decode(&table, &v)));
+        Ok(Box::new(rows.collect::<Vec<_>>().into_iter()))
+    }
+
+    fn scan_index_range(
+        &self,
+        table: &str,
+        column: &str,
+        range: Range,
+    ) -> Result<super::IndexScan> {
+        let table = self.must_read_table(table)?;
+        let column = table.get_column(column)?;
+        if !column.index {
+            return Err(Error::Value(format!("No index for {}.{}", table.name, column.name)));
+        }
+        let prefix = self.prefix(KeyPrefix::Index((&table.name).into(), (&column.name).into()))?;
+        let range = encode_range(&prefix, range, |value| {
+            self.key(Key::Index((&table.name).into(), (&column.name).into(), value))
+        })?;
+        Ok(Box::new(
+            self.txn
+                .scan(range)?
+                .iter()
+                .map(|r| -> Result<(Value, HashSet<Value>)> {
+                    let (k, v) = r?;
+                    let value = match self.decode(&k)? {
+                        Key::Index(_, _, value) => value.into_owned(),
+                        _ => return Err(Error::Internal("Invalid index key".into())),
+                    };
+                    Ok((value, deserialize(&v)?))
+                })
+                .collect::<Vec<_>>()
+                .into_iter(),
+        ))
+    }
+
+    fn scan_index_cover(&self, table: &str, column: &str, range: Range) -> Result<super::Scan> {
+        let table = self.must_read_table(table)?;
+        let ci = table.get_column_index(column)?;
+        let column = &table.columns[ci];
+        if column.include.is_empty() {
+            return Err(Error::Value(format!(
+                "No covering index for {}.{}",
+                table.name, column.name
+            )));
+        }
+        let pk = table.get_column_index(&table.get_primary_key()?.name)?;
+        let include =
+            column.include.iter().map(|c| table.get_column_index(c)).collect::<Result<Vec<_>>>()?;
+        let prefix =
+            self.prefix(KeyPrefix::IndexCover((&table.name).into(), (&column.name).into()))?;
+        let range = encode_range(&prefix, range, |value| {
+            self.key(Key::IndexCover((&table.name).into(), (&column.name).into(), value))
+        })?;
+
+        // Build partial rows from the index entries, ordered by primary key within each entry.
+        let mut rows = Vec::new();
+        for r in self.txn.scan(range)?.iter() {
+            let (k, v) = r?;
+            let value = match self.decode(&k)? {
+                Key::IndexCover(_, _, value) => value.into_owned(),
+                _ => return Err(Error::Internal("Invalid covering index key".into())),
+            };
+            let mut entries: Vec<(Value, Vec<Value>)> =
+                deserialize::<HashMap<_, _>>(&v)?.into_iter().collect();
+            entries.sort_by(|a, b| a.0.sort_cmp(&b.0).unwrap_or(Ordering::Equal));
+            for (id, values) in entries {
+                let mut row = vec![Value::Null; table.columns.len()];
+                row[ci] = value.clone();
+                row[pk] = id;
+                for (i, value) in include.iter().zip(values) {
+                    row[*i] = value;
+                }
+                rows.push(Ok(row));
+            }
+        }
+        Ok(Box::new(rows.into_iter()))
+    }
+
     fn update(&mut self, table: &str, id: &Value, row: Row) -> Result<()> {
         let table = self.must_read_table(table)?;
         // If the primary key changes we do a delete and create, otherwise we replace the row
@@ -264,25 +1423,139 @@ impl<E: storage::engine::Engine> super::Transaction for Transaction<E> {
         }
 
         // Update indexes, knowing that the primary key has not changed
-        let indexes: Vec<_> = table.columns.iter().enumerate().filter(|(_, c)| c.index).collect();
-        if !indexes.is_empty() {
+        let indexes: Vec<_> =
+            (0..table.columns.len()).filter(|i| table.columns[*i].index).collect();
+        let ordered = self.scan_indexes(&table.name)?;
+        if !indexes.is_empty() || !ordered.is_empty() {
             let old = self.read(&table.name, id)?.unwrap();
-            for (i, column) in indexes {
-                if old[i] == row[i] {
+            for i in indexes {
+                let column = &table.columns[i];
+                if old[i] == row[i]
+                    && included(&table, column, &old)? == included(&table, column, &row)?
+                {
                     continue;
                 }
-                let mut index = self.index_load(&table.name, &column.name, &old[i])?;
-                index.remove(id);
-                self.index_save(&table.name, &column.name, &old[i], index)?;
-
-                let mut index = self.index_load(&table.name, &column.name, &row[i])?;
-                index.insert(id.clone());
-                self.index_save(&table.name, &column.name, &row[i], index)?;
+                self.index_remove(&table, i, id, &old)?;
+                self.index_add(&table, i, id, &row)?;
             }
+            self.ordered_index_write(&table, &ordered, id, Some(&old), Some(&row))?;
         }
 
         table.validate_row(&row, self)?;
-        self.txn.set(&Key::Row(table.name.into(), id.into()).encode()?, serialize(&row)?)
+        if table.layout == Layout::Columnar {
+            return self.group_write(&table, id, Some(row));
+        }
+        let value = table.codec.encode(&table, &row)?;
+        self.txn.set(&self.key(Key::Row(table.name.into(), id.into()))?, value)
+    }
+
+    // Rows written concurrently with the backfill index themselves, since the index is already
+    // visible to writers. A batch that reads a row version which is concurrently rewritten will
+    // conflict with the writer on the row's index entry.
+    fn backfill_index(
+        &mut self,
+        table: &str,
+        index: &str,
+        after: Option<Value>,
+        limit: usize,
+    ) -> Result<Option<Value>> {
+        let table = self.must_read_table(table)?;
+        let Some(index) = self.read_ordered_index(&table.name, index)? else {
+            return Err(Error::Value(format!(
+                "Index {} does not exist on table {}",
+                index, table.name
+            )));
+        };
+        if !index.write_only {
+            return Err(Error::Value(format!("Index {} is already public", index.name)));
+        }
+        let range = (after.map_or(Bound::Unbounded, Bound::Excluded), Bound::Unbounded);
+        let (rows, next) = self.scan_chunk(&table.name, range, None, None, limit)?;
+        for row in rows {
+            let id = table.get_row_key(&row)?;
+            self.ordered_index_write(&table, std::slice::from_ref(&index), &id, None, Some(&row))?;
+        }
+        Ok(next)
+    }
+
+    fn kv_get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.txn.get(&self.key(Key::KV(namespace.into(), key.into()))?)
+    }
+
+    fn kv_set(&mut self, namespace: &str, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.txn.set(&self.key(Key::KV(namespace.into(), key.into()))?, value)
+    }
+
+    fn kv_delete(&mut self, namespace: &str, key: &[u8]) -> Result<()> {
+        self.txn.delete(&self.key(Key::KV(namespace.into(), key.into()))?)
+    }
+
+    fn kv_scan(
+        &self,
+        namespace: &str,
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    ) -> Result<super::KVScan> {
+        let encode = |key: &Vec<u8>| self.key(Key::KV(namespace.into(), key.into()));
+        let prefix = self.prefix(KeyPrefix::KV(namespace.into()))?;
+        let start = match &range.0 {
+            Bound::Included(key) => Bound::Included(encode(key)?),
+            Bound::Excluded(key) => Bound::Excluded(encode(key)?),
+            Bound::Unbounded => Bound::Included(prefix.clone()),
+        };
+        let end = match &range.1 {
+            Bound::Included(key) => Bound::Included(encode(key)?),
+            Bound::Excluded(key) => Bound::Excluded(encode(key)?),
+            // The prefix ends with the 0x0000 terminator of the namespace
+            // string, so incrementing the last byte skips past all its keys.
+            Bound::Unbounded => Bound::Excluded([&prefix[..prefix.len() - 1], &[0x01]].concat()),
+        };
+        Ok(Box::new(
+            self.txn
+                .scan((start, end))?
+                .iter()
+                .map(|r| -> Result<(Vec<u8>, Vec<u8>)> {
+                    let (k, v) = r?;
+                    match self.decode(&k)? {
+                        Key::KV(_, key) => Ok((key.into_owned(), v)),
+                        _ => Err(Error::Internal("Invalid key/value key".into())),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .into_iter(),
+        ))
+    }
+
+    // Columnar tables store rows in row groups, whose versions don't map to row changes.
+    fn scan_changes(&self, table: &str, since: u64) -> Result<Vec<super::RowChange>> {
+        let table = self.must_read_table(table)?;
+        if table.layout == Layout::Columnar {
+            return Err(Error::Value(format!(
+                "Change data capture is not supported for columnar table {}",
+                table.name
+            )));
+        }
+        let decode =
+            |value: Option<Vec<u8>>| value.map(|v| table.codec.decode(&table, &v)).transpose();
+        self.txn
+            .scan_changes(&self.prefix(KeyPrefix::Row((&table.name).into()))?, since)?
+            .into_iter()
+            .map(|change| {
+                let Key::Row(_, id) = self.decode(&change.key)? else {
+                    return Err(Error::Internal(format!("Expected row key, got {:?}", change.key)));
+                };
+                Ok(super::RowChange {
+                    table: table.name.clone(),
+                    id: id.into_owned(),
+                    version: change.version,
+                    before: decode(change.before)?,
+                    after: decode(change.after)?,
+                })
+            })
+            .collect()
+    }
+
+    fn resolved_version(&self) -> u64 {
+        self.txn.resolved_version()
     }
 }
 
@@ -291,39 +1564,375 @@ impl<E: storage::engine::Engine> Catalog for Transaction<E> {
         if self.read_table(&table.name)?.is_some() {
             return Err(Error::Value(format!("Table {} already exists", table.name)));
         }
+        // A dropped table with the same name would share the new table's keys.
+        if self.read_dropped_table(&table.name)?.is_some() {
+            self.purge_table(&table.name)?;
+        }
         table.validate(self)?;
-        self.txn.set(&Key::Table((&table.name).into()).encode()?, serialize(&table)?)
+        self.bump_schema_version()?;
+        self.txn.set(&self.key(Key::Table((&table.name).into()))?, serialize(&table)?)
     }
 
     fn delete_table(&mut self, table: &str) -> Result<()> {
         let table = self.must_read_table(table)?;
-        if let Some((t, cs)) = self.table_references(&table.name, false)?.first() {
-            return Err(Error::Value(format!(
-                "Table {} is referenced by table {} column {}",
-                table.name, t, cs[0]
-            )));
-        }
+        self.check_unreferenced(&table)?;
         let mut scan = self.scan(&table.name, None)?;
         while let Some(row) = scan.next().transpose()? {
             self.delete(&table.name, &table.get_row_key(&row)?)?
         }
-        self.txn.delete(&Key::Table(table.name.into()).encode()?)
+        for trigger in self.scan_triggers(&table.name)? {
+            self.txn.delete(&self.key(Key::Trigger((&table.name).into(), trigger.name.into()))?)?;
+        }
+        for index in self.scan_indexes(&table.name)? {
+            self.txn
+                .delete(&self.key(Key::OrderedIndex((&table.name).into(), index.name.into()))?)?;
+        }
+        self.bump_schema_version()?;
+        self.txn.delete(&self.key(Key::Table(table.name.into()))?)
     }
 
     fn read_table(&self, table: &str) -> Result<Option<Table>> {
-        self.txn.get(&Key::Table(table.into()).encode()?)?.map(|v| deserialize(&v)).transpose()
+        self.txn.get(&self.key(Key::Table(table.into()))?)?.map(|v| deserialize(&v)).transpose()
     }
 
     fn scan_tables(&self) -> Result<Tables> {
         Ok(Box::new(
             self.txn
-                .scan_prefix(&KeyPrefix::Table.encode()?)?
+                .scan_prefix(&self.prefix(KeyPrefix::Table)?)?
                 .iter()
                 .map(|r| r.and_then(|(_, v)| deserialize(&v)))
                 .collect::<Result<Vec<_>>>()?
                 .into_iter(),
         ))
     }
+
+    fn drop_table(&mut self, table: &str, time: u64) -> Result<()> {
+        let table = self.must_read_table(table)?;
+        self.check_unreferenced(&table)?;
+        self.bump_schema_version()?;
+        self.txn.delete(&self.key(Key::Table((&table.name).into()))?)?;
+        let key = self.key(Key::DroppedTable((&table.name).into()))?;
+        self.txn.set(&key, serialize(&DroppedTable { table, time })?)
+    }
+
+    fn undrop_table(&mut self, table: &str) -> Result<()> {
+        let Some(DroppedTable { table, .. }) = self.read_dropped_table(table)? else {
+            return Err(Error::Value(format!("Table {} has not been dropped", table)));
+        };
+        // Referenced tables may have been dropped since.
+        table.validate(self)?;
+        self.bump_schema_version()?;
+        self.txn.delete(&self.key(Key::DroppedTable((&table.name).into()))?)?;
+        self.txn.set(&self.key(Key::Table((&table.name).into()))?, serialize(&table)?)?;
+
+        // Referenced rows may have been deleted since.
+        let references: Vec<_> = (0..table.columns.len())
+            .filter_map(|i| Some((i, table.columns[i].references.as_ref()?)))
+            .filter(|(_, target)| *target != &table.name)
+            .collect();
+        if references.is_empty() {
+            return Ok(());
+        }
+        let mut scan = self.scan(&table.name, None)?;
+        while let Some(row) = scan.next().transpose()? {
+            for (i, target) in &references {
+                let dangling = match &row[*i] {
+                    Value::Null => false,
+                    Value::Float(f) if f.is_nan() => false,
+                    value => self.read(target, value)?.is_none(),
+                };
+                if dangling {
+                    return Err(Error::Constraint {
+                        name: format!("{}_{}_fkey", table.name, table.columns[*i].name),
+                        message: format!(
+                            "Referenced primary key {} in table {} does not exist",
+                            row[*i], target
+                        ),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn purge_table(&mut self, table: &str) -> Result<()> {
+        let Some(DroppedTable { table, .. }) = self.read_dropped_table(table)? else {
+            return Err(Error::Value(format!("Table {} has not been dropped", table)));
+        };
+        self.delete_prefix(&self.prefix(KeyPrefix::Row((&table.name).into()))?)?;
+        for column in &table.columns {
+            let (t, c) = (&table.name, &column.name);
+            self.delete_prefix(&self.prefix(KeyPrefix::Index(t.into(), c.into()))?)?;
+            self.delete_prefix(&self.prefix(KeyPrefix::IndexCover(t.into(), c.into()))?)?;
+            self.delete_prefix(&self.prefix(KeyPrefix::ColumnChunk(t.into(), c.into()))?)?;
+        }
+        self.delete_prefix(&self.prefix(KeyPrefix::Trigger((&table.name).into()))?)?;
+        for index in self.scan_indexes(&table.name)? {
+            let (t, i) = (&table.name, &index.name);
+            self.delete_prefix(&self.prefix(KeyPrefix::OrderedIndexEntry(t.into(), i.into()))?)?;
+        }
+        self.delete_prefix(&self.prefix(KeyPrefix::OrderedIndex((&table.name).into()))?)?;
+        self.txn.delete(&self.key(Key::DroppedTable(table.name.into()))?)
+    }
+
+    fn scan_dropped_tables(&self) -> Result<Vec<DroppedTable>> {
+        self.txn
+            .scan_prefix(&self.prefix(KeyPrefix::DroppedTable)?)?
+            .iter()
+            .map(|r| r.and_then(|(_, v)| deserialize(&v)))
+            .collect()
+    }
+
+    fn table_stats(&self, table: &str) -> Result<TableStats> {
+        let table = self.must_read_table(table)?;
+        let name = &table.name;
+        let (rows, size) = match table.layout {
+            Layout::Row => self.prefix_size(KeyPrefix::Row(name.into()))?,
+            // Count the rows in the primary key column's chunks.
+            Layout::Columnar => {
+                let pk = &table.get_primary_key()?.name;
+                let mut rows = 0;
+                for r in self
+                    .txn
+                    .scan_prefix(&self.prefix(KeyPrefix::ColumnChunk(name.into(), pk.into()))?)?
+                    .iter()
+                {
+                    let (_, value) = r?;
+                    rows += deserialize::<Chunk>(&value)?.decode()?.len() as u64;
+                }
+                let mut size = 0;
+                for column in &table.columns {
+                    let prefix = KeyPrefix::ColumnChunk(name.into(), (&column.name).into());
+                    size += self.prefix_size(prefix)?.1;
+                }
+                (rows, size)
+            }
+        };
+        let mut indexes = Vec::new();
+        for column in table.columns.iter().filter(|c| c.index) {
+            let (entries, mut size) =
+                self.prefix_size(KeyPrefix::Index(name.into(), (&column.name).into()))?;
+            size += self.prefix_size(KeyPrefix::IndexCover(name.into(), (&column.name).into()))?.1;
+            indexes.push(IndexStats { column: column.name.clone(), entries, size });
+        }
+        Ok(TableStats { rows, size, indexes })
+    }
+
+    fn create_trigger(&mut self, trigger: Trigger) -> Result<()> {
+        trigger.validate(self)?;
+        self.bump_schema_version()?;
+        let key = self.key(Key::Trigger((&trigger.table).into(), (&trigger.name).into()))?;
+        self.txn.set(&key, serialize(&trigger)?)
+    }
+
+    fn delete_trigger(&mut self, table: &str, trigger: &str) -> Result<()> {
+        let key = self.key(Key::Trigger(table.into(), trigger.into()))?;
+        if self.txn.get(&key)?.is_none() {
+            return Err(Error::Value(format!(
+                "Trigger {} does not exist on table {}",
+                trigger, table
+            )));
+        }
+        self.bump_schema_version()?;
+        self.txn.delete(&key)
+    }
+
+    fn scan_triggers(&self, table: &str) -> Result<Vec<Trigger>> {
+        self.txn
+            .scan_prefix(&self.prefix(KeyPrefix::Trigger(table.into()))?)?
+            .iter()
+            .map(|r| r.and_then(|(_, v)| deserialize(&v)))
+            .collect()
+    }
+
+    fn create_index(&mut self, index: Index) -> Result<()> {
+        index.validate(self)?;
+        let table = self.must_read_table(&index.table)?;
+        self.bump_schema_version()?;
+        if !index.write_only {
+            let rows = self.scan(&table.name, None)?.collect::<Result<Vec<_>>>()?;
+            for row in rows {
+                let id = table.get_row_key(&row)?;
+                let index = std::slice::from_ref(&index);
+                self.ordered_index_write(&table, index, &id, None, Some(&row))?;
+            }
+        }
+        let key = self.key(Key::OrderedIndex((&index.table).into(), (&index.name).into()))?;
+        self.txn.set(&key, serialize(&index)?)
+    }
+
+    fn delete_index(&mut self, table: &str, index: &str) -> Result<()> {
+        if self.read_ordered_index(table, index)?.is_none() {
+            return Err(Error::Value(format!("Index {} does not exist on table {}", index, table)));
+        }
+        self.bump_schema_version()?;
+        self.delete_prefix(
+            &self.prefix(KeyPrefix::OrderedIndexEntry(table.into(), index.into()))?,
+        )?;
+        self.txn.delete(&self.key(Key::OrderedIndex(table.into(), index.into()))?)
+    }
+
+    fn publish_index(&mut self, table: &str, index: &str) -> Result<()> {
+        let Some(mut index) = self.read_ordered_index(table, index)? else {
+            return Err(Error::Value(format!("Index {} does not exist on table {}", index, table)));
+        };
+        if !index.write_only {
+            return Err(Error::Value(format!("Index {} is already public", index.name)));
+        }
+        self.bump_schema_version()?;
+        index.write_only = false;
+        let key = self.key(Key::OrderedIndex((&index.table).into(), (&index.name).into()))?;
+        self.txn.set(&key, serialize(&index)?)
+    }
+
+    fn scan_indexes(&self, table: &str) -> Result<Vec<Index>> {
+        self.txn
+            .scan_prefix(&self.prefix(KeyPrefix::OrderedIndex(table.into()))?)?
+            .iter()
+            .map(|r| r.and_then(|(_, v)| deserialize(&v)))
+            .collect()
+    }
+
+    fn create_function(&mut self, function: Function) -> Result<()> {
+        function.validate(self)?;
+        self.bump_schema_version()?;
+        self.txn.set(&self.key(Key::Function((&function.name).into()))?, serialize(&function)?)
+    }
+
+    fn delete_function(&mut self, function: &str) -> Result<()> {
+        if self.read_function(function)?.is_none() {
+            return Err(Error::Value(format!("Function {} does not exist", function)));
+        }
+        if let Some(f) = self
+            .scan_functions()?
+            .into_iter()
+            .find(|f| f.dependencies.iter().any(|d| d == function))
+        {
+            return Err(Error::Value(format!(
+                "Function {} is used by function {}",
+                function, f.name
+            )));
+        }
+        self.bump_schema_version()?;
+        self.txn.delete(&self.key(Key::Function(function.into()))?)
+    }
+
+    fn read_function(&self, function: &str) -> Result<Option<Function>> {
+        self.txn
+            .get(&self.key(Key::Function(function.into()))?)?
+            .map(|v| deserialize(&v))
+            .transpose()
+    }
+
+    fn scan_functions(&self) -> Result<Vec<Function>> {
+        self.txn
+            .scan_prefix(&self.prefix(KeyPrefix::Function)?)?
+            .iter()
+            .map(|r| r.and_then(|(_, v)| deserialize(&v)))
+            .collect()
+    }
+
+    fn set_setting(&mut self, name: &str, value: Option<Value>) -> Result<()> {
+        self.check_system("SET CLUSTER SETTING")?;
+        let setting = super::Setting::get(name)?;
+        let key = self.key(Key::Setting(name.into()))?;
+        match value {
+            Some(value) => {
+                setting.validate(&value)?;
+                self.txn.set(&key, serialize(&value)?)
+            }
+            None => self.txn.delete(&key),
+        }
+    }
+
+    fn scan_settings(&self) -> Result<Vec<(String, Value)>> {
+        self.check_system("Reading cluster settings")?;
+        self.txn
+            .scan_prefix(&self.prefix(KeyPrefix::Setting)?)?
+            .iter()
+            .map(|r| {
+                r.and_then(|(k, v)| match self.decode(&k)? {
+                    Key::Setting(name) => Ok((name.into_owned(), deserialize(&v)?)),
+                    key => Err(Error::Internal(format!("Expected Setting key, got {:?}", key))),
+                })
+            })
+            .collect()
+    }
+
+    fn create_tenant(&mut self, tenant: &str) -> Result<()> {
+        self.check_system("Managing tenants")?;
+        if tenant.is_empty() {
+            return Err(Error::Value("Tenant name can't be empty".into()));
+        }
+        let key = self.key(Key::Tenant(tenant.into()))?;
+        if self.txn.get(&key)?.is_some() {
+            return Err(Error::Value(format!("Tenant {} already exists", tenant)));
+        }
+        self.txn.set(&key, Vec::new())
+    }
+
+    fn delete_tenant(&mut self, tenant: &str) -> Result<()> {
+        self.check_system("Managing tenants")?;
+        let key = self.key(Key::Tenant(tenant.into()))?;
+        if self.txn.get(&key)?.is_none() {
+            return Err(Error::Value(format!("Tenant {} does not exist", tenant)));
+        }
+        self.delete_prefix(&self.prefix(KeyPrefix::TenantKeys(tenant.into()))?)?;
+        self.txn.delete(&key)
+    }
+
+    fn scan_tenants(&self) -> Result<Vec<TenantStats>> {
+        self.check_system("Listing tenants")?;
+        let names = self
+            .txn
+            .scan_prefix(&self.prefix(KeyPrefix::Tenant)?)?
+            .iter()
+            .map(|r| {
+                r.and_then(|(k, _)| match self.decode(&k)? {
+                    Key::Tenant(name) => Ok(name.into_owned()),
+                    key => Err(Error::Internal(format!("Expected Tenant key, got {:?}", key))),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        names
+            .into_iter()
+            .map(|name| {
+                let (keys, size) = self.prefix_size(KeyPrefix::TenantKeys((&name).into()))?;
+                Ok(TenantStats { name, keys, size })
+            })
+            .collect()
+    }
+}
+
+/// A range of encoded keys.
+type KeyRange = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+/// Encodes a value range as a key range, given the key prefix and a key encoder for values. NULL
+/// values sort first, and are excluded from ranges without a start bound.
+fn encode_range<F>(prefix: &[u8], range: Range, encode: F) -> Result<KeyRange>
+where
+    F: Fn(Cow<Value>) -> Result<Vec<u8>>,
+{
+    let start = match range.0 {
+        Bound::Included(value) => Bound::Included(encode(value.into())?),
+        Bound::Excluded(value) => Bound::Excluded(encode(value.into())?),
+        Bound::Unbounded => Bound::Excluded(encode(Value::Null.into())?),
+    };
+    let end = match range.1 {
+        Bound::Included(value) => Bound::Included(encode(value.into())?),
+        Bound::Excluded(value) => Bound::Excluded(encode(value.into())?),
+        // The prefix ends with the 0x0000 terminator of a string, so
+        // incrementing the last byte skips past all its keys.
+        Bound::Unbounded => Bound::Excluded([&prefix[..prefix.len() - 1], &[0x01]].concat()),
+    };
+    Ok((start, end))
+}
+
+/// Returns the key range of the keys under a prefix, which must end with the 0x0000 terminator
+/// of a string.
+fn prefix_bounds(prefix: &[u8]) -> KeyRange {
+    let end = [&prefix[..prefix.len() - 1], &[0x01]].concat();
+    (Bound::Included(prefix.to_vec()), Bound::Excluded(end))
 }
 
 /// SQL keys, using the KeyCode order-preserving encoding. Uses table and column
@@ -338,6 +1947,48 @@ enum Key<'a> {
     Index(Cow<'a, str>, Cow<'a, str>, Cow<'a, Value>),
     /// A table row, by table name and primary key value.
     Row(Cow<'a, str>, Cow<'a, Value>),
+    /// A key/value pair, by namespace and key. These are separate from SQL
+    /// tables, and only accessed via the key/value API.
+    KV(
+        Cow<'a, str>,
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+    ),
+    /// A covering index entry with included column values, by table name,
+    /// index name, and index value.
+    IndexCover(Cow<'a, str>, Cow<'a, str>, Cow<'a, Value>),
+    /// A trigger, by table name and trigger name.
+    Trigger(Cow<'a, str>, Cow<'a, str>),
+    /// A function, by function name.
+    Function(Cow<'a, str>),
+    /// The schema version, incremented by every schema change.
+    SchemaVersion,
+    /// A column chunk of a columnar table's row group, by table name, column name, and the
+    /// group's first primary key value.
+    ColumnChunk(Cow<'a, str>, Cow<'a, str>, Cow<'a, Value>),
+    /// A dropped table schema and drop time by table name, retained until the table is purged.
+    /// Its rows, indexes, and triggers are kept under their usual keys until then.
+    DroppedTable(Cow<'a, str>),
+    /// An index created with CREATE INDEX, by table name and index name.
+    OrderedIndex(Cow<'a, str>, Cow<'a, str>),
+    /// An entry of an index created with CREATE INDEX, by table name, index name, and the
+    /// entry's sort key (see ordered_index_key()). The value is the row's primary key.
+    OrderedIndexEntry(
+        Cow<'a, str>,
+        Cow<'a, str>,
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+    ),
+    /// A cluster setting value, by setting name.
+    Setting(Cow<'a, str>),
+    /// A tenant, by tenant name. The value is empty.
+    Tenant(Cow<'a, str>),
+    /// A tenant's keyspace, by tenant name. Only used to align variant indexes with KeyPrefix,
+    /// since the tenant's keys are appended to the encoded prefix, see Transaction::key().
+    #[allow(dead_code)]
+    TenantKeys(Cow<'a, str>),
 }
 
 impl<'a> Key<'a> {
@@ -345,7 +1996,7 @@ impl<'a> Key<'a> {
         keycode::serialize(&self)
     }
 
-    fn decode(bytes: &[u8]) -> Result<Self> {
+    fn decode(bytes: &'a [u8]) -> Result<Self> {
         keycode::deserialize(bytes)
     }
 }
@@ -360,6 +2011,31 @@ enum KeyPrefix<'a> {
     Index(Cow<'a, str>, Cow<'a, str>),
     /// An entire table's rows, by table name.
     Row(Cow<'a, str>),
+    /// An entire key/value namespace.
+    KV(Cow<'a, str>),
+    /// An entire covering index, by table and index name.
+    IndexCover(Cow<'a, str>, Cow<'a, str>),
+    /// All triggers of a table, by table name.
+    Trigger(Cow<'a, str>),
+    /// All functions.
+    Function,
+    /// The schema version. Only used to align variant indexes with Key.
+    #[allow(dead_code)]
+    SchemaVersion,
+    /// All chunks of a columnar table's column, by table and column name.
+    ColumnChunk(Cow<'a, str>, Cow<'a, str>),
+    /// All dropped tables.
+    DroppedTable,
+    /// All indexes created with CREATE INDEX on a table, by table name.
+    OrderedIndex(Cow<'a, str>),
+    /// All entries of an index created with CREATE INDEX, by table and index name.
+    OrderedIndexEntry(Cow<'a, str>, Cow<'a, str>),
+    /// All cluster settings.
+    Setting,
+    /// All tenants.
+    Tenant,
+    /// All keys in a tenant's keyspace, by tenant name.
+    TenantKeys(Cow<'a, str>),
 }
 
 impl<'a> KeyPrefix<'a> {
@@ -367,3 +2043,553 @@ impl<'a> KeyPrefix<'a> {
         keycode::serialize(&self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Engine as _, Session};
+    use super::*;
+    use crate::sql::execution::ResultSet;
+    use crate::sql::types::DataType;
+
+    /// Data written in format version 0 is migrated to the current version, including
+    /// historical versions.
+    #[test]
+    fn migrate() -> Result<()> {
+        #[derive(Serialize)]
+        struct TableV0 {
+            name: String,
+            columns: Vec<Column>,
+        }
+
+        let kv = KV::new(storage::engine::Memory::new());
+        let columns = vec![Column {
+            name: "id".into(),
+            datatype: DataType::Integer,
+            primary_key: true,
+            nullable: false,
+            default: None,
+            unique: true,
+            references: None,
+            index: false,
+            include: Vec::new(),
+        }];
+        let table = |name: &str| Table::new(name.into(), columns.clone()).unwrap();
+
+        // Write table a twice, and table b which is then deleted.
+        for name in ["a", "b", "a"] {
+            let txn = kv.kv.begin()?;
+            let table = TableV0 { name: name.into(), columns: columns.clone() };
+            txn.set(&Key::Table(name.into()).encode()?, serialize(&table)?)?;
+            txn.commit()?;
+        }
+        let txn = kv.kv.begin()?;
+        txn.delete(&Key::Table("b".into()).encode()?)?;
+        let row = vec![Value::Integer(1)];
+        txn.set(&Key::Row("a".into(), (&row[0]).into()).encode()?, serialize(&row)?)?;
+        txn.commit()?;
+
+        assert_eq!(kv.format_version()?, 0);
+        assert!(kv.check_format().is_err());
+        assert!(kv.begin_read_only()?.scan_tables().is_err());
+
+        assert_eq!(kv.migrate()?, 0);
+        assert_eq!(kv.format_version()?, FORMAT_VERSION);
+        kv.check_format()?;
+        assert_eq!(kv.begin_read_only()?.scan_tables()?.collect::<Vec<_>>(), vec![table("a")]);
+        assert_eq!(kv.begin_read_only()?.read("a", &Value::Integer(1))?, Some(row));
+        assert_eq!(
+            kv.begin_as_of(3)?.scan_tables()?.collect::<Vec<_>>(),
+            vec![table("a"), table("b")]
+        );
+
+        // Migrating again is a noop.
+        assert_eq!(kv.migrate()?, FORMAT_VERSION);
+        Ok(())
+    }
+
+    /// Columnar tables fill row groups with ordered inserts, split full groups on other inserts,
+    /// and remove empty groups.
+    #[test]
+    fn columnar_groups() -> Result<()> {
+        let kv = KV::new(storage::engine::Memory::new());
+        let mut table = Table::new(
+            "t".into(),
+            vec![Column {
+                name: "id".into(),
+                datatype: DataType::Integer,
+                primary_key: true,
+                nullable: false,
+                default: None,
+                unique: true,
+                references: None,
+                index: false,
+                include: Vec::new(),
+            }],
+        )?;
+        table.layout = Layout::Columnar;
+        let mut txn = kv.begin()?;
+        txn.create_table(table.clone())?;
+
+        // Returns the size of each row group.
+        let groups = |txn: &Transaction<_>| -> Result<Vec<usize>> {
+            let prefix = KeyPrefix::ColumnChunk("t".into(), "id".into()).encode()?;
+            let chunks = txn.txn.scan_prefix(&prefix)?.iter().collect::<Result<Vec<_>>>()?;
+            chunks.into_iter().map(|(_, v)| Ok(deserialize::<Chunk>(&v)?.decode()?.len())).collect()
+        };
+
+        let size = ROW_GROUP_SIZE as i64;
+        for id in 0..=size {
+            txn.create("t", vec![Value::Integer(id * 2)])?;
+        }
+        assert_eq!(groups(&txn)?, vec![ROW_GROUP_SIZE, 1]);
+
+        txn.create("t", vec![Value::Integer(1)])?;
+        txn.create("t", vec![Value::Integer(-1)])?;
+        let half = ROW_GROUP_SIZE / 2;
+        assert_eq!(groups(&txn)?, vec![half + 2, half, 1]);
+        assert_eq!(txn.read("t", &Value::Integer(-1))?, Some(vec![Value::Integer(-1)]));
+        assert_eq!(txn.read("t", &Value::Integer(3))?, None);
+
+        txn.delete("t", &Value::Integer(2 * size))?;
+        txn.delete("t", &Value::Integer(-1))?;
+        assert_eq!(groups(&txn)?, vec![half + 1, half]);
+        assert_eq!(txn.scan("t", None)?.count(), ROW_GROUP_SIZE + 1);
+        txn.commit()
+    }
+
+    /// Dropped tables retain their data until they're purged, which removes all of their keys.
+    #[test]
+    fn drop_purge() -> Result<()> {
+        let kv = KV::new(storage::engine::Memory::new());
+        let mut session = kv.session()?;
+        session.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, value STRING INDEX)")?;
+        session.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")?;
+        session.execute("DROP TABLE t")?;
+
+        // Returns the number of row and index keys of the table.
+        let keys = || -> Result<usize> {
+            let txn = kv.kv.begin_read_only()?;
+            let mut count = 0;
+            for prefix in [KeyPrefix::Row("t".into()), KeyPrefix::Index("t".into(), "value".into())]
+            {
+                count += txn.scan_prefix(&prefix.encode()?)?.iter().count();
+            }
+            Ok(count)
+        };
+
+        assert_eq!(kv.begin_read_only()?.read_table("t")?, None);
+        assert_eq!(keys()?, 4);
+
+        // Only tables dropped before the retention are purged.
+        assert_eq!(session.purge_dropped(3600)?, 0);
+        assert_eq!(session.purge_dropped(0)?, 1);
+        assert_eq!(keys()?, 0);
+        assert_eq!(kv.begin_read_only()?.scan_dropped_tables()?, Vec::new());
+        assert!(session.execute("UNDROP TABLE t").is_err());
+        Ok(())
+    }
+
+    /// Write-only indexes are maintained by writes but not used by queries until published.
+    /// Backfill batches conflict with concurrent writes of the rows they index.
+    #[test]
+    fn index_backfill() -> Result<()> {
+        let kv = KV::new(storage::engine::Memory::new());
+        let mut session = kv.session()?;
+        session.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, value INTEGER)")?;
+        session.execute("INSERT INTO t VALUES (1, 1), (2, 2), (3, 3), (4, 4), (5, 5)")?;
+
+        let mut txn = kv.begin()?;
+        txn.create_index(Index {
+            name: "t_value".into(),
+            table: "t".into(),
+            columns: vec![("value".into(), Direction::Ascending)],
+            write_only: true,
+        })?;
+        txn.commit()?;
+
+        let int = Value::Integer;
+        let ids = || -> Result<Vec<Value>> {
+            let txn = kv.begin_read_only()?;
+            let rows = txn.scan_index_order("t", "t_value", false)?;
+            rows.map(|r| r.map(|r| r[0].clone())).collect()
+        };
+        let explain = |session: &mut Session<KV<storage::engine::Memory>>| match session
+            .execute("EXPLAIN SELECT * FROM t ORDER BY value")?
+        {
+            ResultSet::Explain(node) => Ok(node.to_string()),
+            result => Err(Error::Internal(format!("Unexpected result {:?}", result))),
+        };
+        assert_eq!(ids()?, Vec::new());
+        assert!(explain(&mut session)?.starts_with("Order:"));
+
+        // A concurrent writer indexes its own writes, and conflicts with the batch.
+        let mut writer = kv.begin()?;
+        writer.update("t", &int(1), vec![int(1), int(10)])?;
+        writer.create("t", vec![int(6), int(6)])?;
+        let mut txn = kv.begin()?;
+        assert_eq!(txn.backfill_index("t", "t_value", None, 2), Err(Error::Serialization));
+        txn.rollback()?;
+        writer.commit()?;
+        assert_eq!(ids()?, vec![int(6), int(1)]);
+
+        let mut after = None;
+        let mut batches = Vec::new();
+        loop {
+            let mut txn = kv.begin()?;
+            after = txn.backfill_index("t", "t_value", after, 2)?;
+            txn.commit()?;
+            batches.push(after.clone());
+            if after.is_none() {
+                break;
+            }
+        }
+        assert_eq!(batches, vec![Some(int(2)), Some(int(4)), Some(int(6)), None]);
+        assert_eq!(ids()?, vec![int(2), int(3), int(4), int(5), int(6), int(1)]);
+
+        let mut txn = kv.begin()?;
+        txn.publish_index("t", "t_value")?;
+        assert!(txn.publish_index("t", "t_value").is_err());
+        assert!(txn.backfill_index("t", "t_value", None, 2).is_err());
+        txn.commit()?;
+        assert_eq!(explain(&mut session)?, "IndexOrderScan: t index t_value");
+        Ok(())
+    }
+
+    /// Prepared transactions keep their writes and write locks until they're committed or
+    /// rolled back by another session, and block schema changes meanwhile.
+    #[test]
+    fn prepare() -> Result<()> {
+        let kv = KV::new(storage::engine::Memory::new());
+        let mut session = kv.session()?;
+        session.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, value STRING)")?;
+
+        let mut a = kv.session()?;
+        a.execute("BEGIN")?;
+        a.execute("INSERT INTO t VALUES (1, 'a')")?;
+        let version = match a.execute("PREPARE TRANSACTION 'a'")? {
+            ResultSet::Prepare { gid, version } if gid == "a" => version,
+            result => return Err(Error::Internal(format!("Unexpected result {:?}", result))),
+        };
+        assert_eq!(a.transaction(), None);
+        assert_eq!(kv.scan_prepared()?, vec![("a".to_string(), version)]);
+
+        let mut b = kv.session()?;
+        b.execute("BEGIN")?;
+        b.execute("INSERT INTO t VALUES (2, 'b')")?;
+        assert_eq!(
+            b.execute("PREPARE TRANSACTION 'a'"),
+            Err(Error::Value("Prepared transaction a already exists".into()))
+        );
+        assert_eq!(b.transaction(), None);
+
+        let count =
+            |session: &mut Session<_>| session.execute("SELECT COUNT(*) FROM t")?.into_value();
+        assert_eq!(count(&mut session)?, Value::Integer(0));
+        assert_eq!(session.execute("INSERT INTO t VALUES (1, 'x')"), Err(Error::Serialization));
+        assert_eq!(
+            session.execute("CREATE TABLE u (id INTEGER PRIMARY KEY)"),
+            Err(Error::Value("Can't change the schema while transactions are prepared".into()))
+        );
+        assert_eq!(
+            session.execute("COMMIT PREPARED 'b'"),
+            Err(Error::Value("Prepared transaction b does not exist".into()))
+        );
+
+        assert_eq!(session.execute("COMMIT PREPARED 'a'")?, ResultSet::Commit { version });
+        assert_eq!(count(&mut session)?, Value::Integer(1));
+        assert_eq!(kv.scan_prepared()?, Vec::new());
+        assert!(session.execute("COMMIT PREPARED 'a'").is_err());
+
+        b.execute("BEGIN")?;
+        b.execute("DELETE FROM t")?;
+        b.execute("PREPARE TRANSACTION 'b'")?;
+        session.execute("ROLLBACK PREPARED 'b'")?;
+        assert_eq!(count(&mut session)?, Value::Integer(1));
+        session.execute("CREATE TABLE u (id INTEGER PRIMARY KEY)")?;
+        Ok(())
+    }
+
+    /// Tenants have separate keyspaces, which they can't read or write outside of, and
+    /// dropping a tenant deletes its data and fails its in-flight transactions.
+    #[test]
+    fn tenants() -> Result<()> {
+        let kv = KV::new(storage::engine::Memory::new());
+        let mut system = kv.session()?;
+        system.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)")?;
+        system.execute("INSERT INTO t VALUES (1)")?;
+        system.execute("CREATE TENANT a")?;
+        system.execute("CREATE TENANT b")?;
+        assert_eq!(
+            system.execute("CREATE TENANT a"),
+            Err(Error::Value("Tenant a already exists".into()))
+        );
+
+        let mut a = kv.session()?;
+        a.execute("SET tenant = 'a'")?;
+        assert_eq!(a.tenant(), Some("a"));
+        assert_eq!(
+            a.execute("SELECT * FROM t"),
+            Err(Error::Value("Table t does not exist".into()))
+        );
+        a.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)")?;
+        a.execute("INSERT INTO t VALUES (1), (2)")?;
+        assert_eq!(
+            a.execute("SET tenant = 'missing'"),
+            Err(Error::Value("Tenant missing does not exist".into()))
+        );
+        assert_eq!(a.tenant(), Some("a"));
+
+        let count =
+            |session: &mut Session<_>| session.execute("SELECT COUNT(*) FROM t")?.into_value();
+        assert_eq!(count(&mut system)?, Value::Integer(1));
+        assert_eq!(count(&mut a)?, Value::Integer(2));
+        let mut b = kv.session()?;
+        b.execute("SET tenant = 'b'")?;
+        assert!(count(&mut b).is_err());
+
+        // Keys outside of the tenant's keyspace can't be decoded, and administration requires
+        // the system tenant.
+        let txn = kv.clone().with_tenant(Some("a".into())).begin()?;
+        assert!(txn.decode(&Key::Table("t".into()).encode()?).is_err());
+        assert!(txn.decode(&Key::Tenant("a".into()).encode()?).is_err());
+        txn.rollback()?;
+        for query in [
+            "CREATE TENANT c",
+            "SELECT * FROM system.tenants",
+            "SET CLUSTER SETTING ttl_expiry = FALSE",
+            "SHOW SESSIONS",
+        ] {
+            assert!(a.execute(query).is_err(), "{}", query);
+        }
+        a.execute("BEGIN")?;
+        assert!(a.execute("SET tenant = NULL").is_err());
+        assert!(a.execute("PREPARE TRANSACTION 'a'").is_err());
+        assert_eq!(a.transaction(), None);
+
+        let tenants = kv.begin_read_only()?.scan_tenants()?;
+        assert_eq!(tenants.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+        assert!(tenants[0].keys > 0 && tenants[0].size > 0);
+        assert_eq!((tenants[1].keys, tenants[1].size), (0, 0));
+
+        // Dropping a tenant fails its in-flight transactions.
+        a.execute("BEGIN")?;
+        a.execute("INSERT INTO t VALUES (3)")?;
+        system.execute("DROP TENANT a")?;
+        assert_eq!(a.execute("COMMIT"), Err(Error::Serialization));
+        assert_eq!(count(&mut a), Err(Error::Value("Tenant a does not exist".into())));
+        system.execute("CREATE TENANT a")?;
+        assert!(count(&mut a).is_err());
+        assert_eq!(count(&mut system)?, Value::Integer(1));
+        Ok(())
+    }
+
+    /// Chunked scans read up to the limit of rows passing the filter and return the key to
+    /// continue from. Columnar tables are read in full.
+    #[test]
+    fn scan_chunk() -> Result<()> {
+        let kv = KV::new(storage::engine::Memory::new());
+        let mut session = kv.session()?;
+        session.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, value INTEGER)")?;
+        session.execute("CREATE TABLE c (id INTEGER PRIMARY KEY) WITH (layout = 'columnar')")?;
+        session.execute("INSERT INTO t VALUES (1, 1), (2, 2), (3, 3), (4, 4), (5, 5)")?;
+        session.execute("INSERT INTO c VALUES (1), (2), (3)")?;
+
+        let txn = kv.begin_read_only()?;
+        let ids = |(rows, next): (Vec<Row>, Option<Value>)| {
+            (rows.into_iter().map(|r| r[0].clone()).collect::<Vec<_>>(), next)
+        };
+        let all = (Bound::Unbounded, Bound::Unbounded);
+        let int = Value::Integer;
+
+        assert_eq!(
+            ids(txn.scan_chunk("t", all.clone(), None, None, 2)?),
+            (vec![int(1), int(2)], Some(int(2)))
+        );
+        let rest = (Bound::Excluded(int(2)), Bound::Unbounded);
+        assert_eq!(
+            ids(txn.scan_chunk("t", rest.clone(), None, None, 2)?),
+            (vec![int(3), int(4)], Some(int(4)))
+        );
+        assert_eq!(
+            ids(txn.scan_chunk("t", (Bound::Excluded(int(4)), Bound::Unbounded), None, None, 2)?),
+            (vec![int(5)], None)
+        );
+
+        // The limit applies to the rows that pass the filter.
+        let filter = Expression::GreaterThan(
+            Expression::Field(1, None).into(),
+            Expression::Constant(int(2)).into(),
+        );
+        assert_eq!(
+            ids(txn.scan_chunk("t", all.clone(), Some(filter.clone()), None, 2)?),
+            (vec![int(3), int(4)], Some(int(4)))
+        );
+        assert_eq!(
+            ids(txn.scan_chunk("t", rest, Some(filter), None, 3)?),
+            (vec![int(3), int(4), int(5)], Some(int(5)))
+        );
+
+        assert_eq!(
+            ids(txn.scan_chunk("c", all, None, None, 2)?),
+            (vec![int(1), int(2), int(3)], None)
+        );
+        Ok(())
+    }
+
+    /// SYSTEM samples read whole blocks of rows, BERNOULLI samples individual rows, and both are
+    /// repeatable with a seed.
+    #[test]
+    fn scan_sample() -> Result<()> {
+        let kv = KV::new(storage::engine::Memory::new());
+        let mut session = kv.session()?;
+        session.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)")?;
+        session.execute("CREATE TABLE c (id INTEGER PRIMARY KEY) WITH (layout = 'columnar')")?;
+        let values = (1..=2000).map(|i| format!("({})", i)).collect::<Vec<_>>().join(", ");
+        for table in ["t", "c"] {
+            session.execute(&format!("INSERT INTO {} VALUES {}", table, values))?;
+        }
+
+        let txn = kv.begin_read_only()?;
+        let ids = |table: &str, method: SampleMethod, percent: f64| -> Result<Vec<i64>> {
+            let sample = Sample { method, percent, seed: Some(7) };
+            txn.scan_sample(table, &sample, None)?
+                .map(|r| match r?[0] {
+                    Value::Integer(id) => Ok(id),
+                    ref v => Err(Error::Internal(format!("Unexpected id {}", v))),
+                })
+                .collect()
+        };
+
+        for table in ["t", "c"] {
+            for method in [SampleMethod::System, SampleMethod::Bernoulli] {
+                assert_eq!(ids(table, method, 0.0)?, Vec::<i64>::new());
+                assert_eq!(ids(table, method, 100.0)?, (1..=2000).collect::<Vec<_>>());
+                // Columnar tables only have a couple of row groups to sample from.
+                let sample = ids(table, method, 50.0)?;
+                if (table, method) != ("c", SampleMethod::System) {
+                    assert!(sample.len() > 500 && sample.len() < 1500, "{} rows", sample.len());
+                }
+                assert!(sample.windows(2).all(|w| w[0] < w[1]));
+                assert_eq!(ids(table, method, 50.0)?, sample);
+            }
+        }
+
+        // SYSTEM samples of row tables contain runs of consecutive rows, BERNOULLI ones don't.
+        let runs = |ids: Vec<i64>| ids.windows(2).filter(|w| w[1] != w[0] + 1).count() + 1;
+        assert!(runs(ids("t", SampleMethod::System, 50.0)?) <= 100);
+        assert!(runs(ids("t", SampleMethod::Bernoulli, 50.0)?) > 100);
+        Ok(())
+    }
+
+    /// Chunked index order scans and samples return the same rows as whole ones.
+    #[test]
+    fn scan_chunk_resume() -> Result<()> {
+        let kv = KV::new(storage::engine::Memory::new());
+        let mut session = kv.session()?;
+        session.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, value INTEGER)")?;
+        let values = (1..=500).map(|i| format!("({}, {})", i, i % 7)).collect::<Vec<_>>();
+        session.execute(&format!("INSERT INTO t VALUES {}", values.join(", ")))?;
+        session.execute("CREATE INDEX t_value ON t (value, id)")?;
+
+        let txn = kv.begin_read_only()?;
+        for reverse in [false, true] {
+            let expect: Vec<Row> =
+                txn.scan_index_order("t", "t_value", reverse)?.collect::<Result<_>>()?;
+            let mut rows: Vec<Row> = Vec::new();
+            let mut after = None;
+            loop {
+                let (chunk, next) =
+                    txn.scan_index_order_chunk("t", "t_value", reverse, after, 7)?;
+                assert!(chunk.len() == 7 || next.is_none());
+                rows.extend(chunk);
+                let Some(next) = next else { break };
+                after = Some(next);
+            }
+            assert_eq!(rows, expect);
+        }
+
+        let filter = Expression::GreaterThan(
+            Expression::Field(1, None).into(),
+            Expression::Constant(Value::Integer(2)).into(),
+        );
+        for method in [SampleMethod::System, SampleMethod::Bernoulli] {
+            let sample = Sample { method, percent: 50.0, seed: Some(7) };
+            for filter in [None, Some(filter.clone())] {
+                let expect = txn.scan_sample("t", &sample, filter.clone())?;
+                let expect = expect.collect::<Result<Vec<_>>>()?;
+                assert!(!expect.is_empty());
+                let mut rows = Vec::new();
+                let mut cursor = None;
+                loop {
+                    let (chunk, next) =
+                        txn.scan_sample_chunk("t", &sample, filter.clone(), cursor, 1)?;
+                    rows.extend(chunk);
+                    let Some(next) = next else { break };
+                    cursor = Some(next);
+                }
+                assert_eq!(rows, expect);
+            }
+        }
+
+        // Unseeded samples can't be resumed.
+        let sample = Sample { method: SampleMethod::Bernoulli, percent: 50.0, seed: None };
+        let (_, cursor) =
+            txn.scan_sample_chunk("t", &Sample { seed: Some(1), ..sample }, None, None, 1)?;
+        assert!(txn.scan_sample_chunk("t", &sample, None, cursor, 1).is_err());
+        Ok(())
+    }
+
+    /// Writes exceeding the size limits are rejected, naming the table and column.
+    #[test]
+    fn limits() -> Result<()> {
+        let limits =
+            super::super::Limits { max_row_size: 32, max_key_size: 10, max_index_entry_size: 8 };
+        let kv = KV::new(storage::engine::Memory::new()).with_limits(limits);
+        let mut session = kv.session()?;
+        session
+            .execute("CREATE TABLE t (id STRING PRIMARY KEY, value STRING INDEX, data STRING)")?;
+        session.execute("INSERT INTO t VALUES ('a', 'b', 'c')")?;
+
+        let value_error = |message: &str| Err(Error::Value(message.into()));
+        assert_eq!(
+            session.execute("INSERT INTO t VALUES ('abcdefghij', 'b', 'c')").map(|_| ()),
+            value_error(
+                "Primary key size 13 bytes exceeds maximum of 10 bytes for table t column id"
+            )
+        );
+        assert_eq!(
+            session.execute("INSERT INTO t VALUES ('b', 'abcdefgh', 'c')").map(|_| ()),
+            value_error(
+                "Index entry size 11 bytes exceeds maximum of 8 bytes for table t column value"
+            )
+        );
+        assert_eq!(
+            session.execute("UPDATE t SET data = 'abcdefghijklmnopqrstuvwxyz'").map(|_| ()),
+            value_error("Row size 35 bytes exceeds maximum of 32 bytes for table t")
+        );
+
+        // 0 disables a limit.
+        let kv = kv.with_limits(super::super::Limits { max_row_size: 0, ..limits });
+        let mut session = kv.session()?;
+        session.execute("UPDATE t SET data = 'abcdefghijklmnopqrstuvwxyz'")?;
+        Ok(())
+    }
+
+    /// New data is marked with the current format version.
+    #[test]
+    fn format_new() -> Result<()> {
+        let kv = KV::new(storage::engine::Memory::new());
+        assert_eq!(kv.format_version()?, FORMAT_VERSION);
+        assert_eq!(kv.get_metadata(FORMAT_VERSION_KEY)?, None);
+        kv.check_format()?;
+        assert_eq!(
+            kv.get_metadata(FORMAT_VERSION_KEY)?,
+            Some(bincode::serialize(&FORMAT_VERSION)?)
+        );
+
+        let txn = kv.begin()?;
+        txn.commit()?;
+        assert_eq!(kv.format_version()?, FORMAT_VERSION);
+        Ok(())
+    }
+}
@@ -1,17 +1,38 @@
 //! The SQL engine provides fundamental CRUD storage operations.
+mod check;
+mod codec;
+mod columnar;
 mod kv;
 pub mod raft;
-pub use kv::KV;
-pub use raft::{Raft, Status};
+mod settings;
+pub use check::{check, checksum};
+pub use codec::{Bincode, Codec, Compact, RowCodec};
+pub use columnar::{Chunk, Layout, ROW_GROUP_SIZE};
+pub use kv::{FORMAT_VERSION, KV};
+pub use raft::{LivenessState, NodeLiveness, Raft, Standby, Status};
+pub use settings::{Setting, Settings, SETTINGS};
 
-use super::execution::ResultSet;
+use super::execution::{Memory, ResultSet};
 use super::parser::{ast, Parser};
-use super::plan::Plan;
-use super::schema::Catalog;
-use super::types::{Expression, Row, Value};
+use super::plan::{Node, Plan, RULES};
+use super::schema::{Catalog, Table};
+use super::types::{Column, Columns, Expression, Range, Row, Rows, Sample, Value};
+use crate::encoding::keycode;
 use crate::error::{Error, Result};
+use crate::queue::Priority;
 
-use std::collections::HashSet;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The number of rows indexed by each transaction when backfilling an index online.
+const INDEX_BACKFILL_BATCH_SIZE: usize = 1000;
+
+/// The number of times an index backfill batch is retried if it conflicts with a concurrent
+/// write, with exponential backoff.
+const INDEX_BACKFILL_RETRIES: u32 = 5;
 
 /// The SQL engine interface
 pub trait Engine: Clone {
@@ -27,9 +48,41 @@ pub trait Engine: Clone {
     /// Begins a read-only transaction as of a historical version.
     fn begin_as_of(&self, version: u64) -> Result<Self::Transaction>;
 
+    /// Commits a transaction prepared with Transaction::prepare(), returning its version.
+    fn commit_prepared(&self, gid: &str) -> Result<u64>;
+
+    /// Rolls back a prepared transaction, returning its version.
+    fn rollback_prepared(&self, gid: &str) -> Result<u64>;
+
+    /// Returns the global identifiers and versions of the prepared transactions.
+    fn scan_prepared(&self) -> Result<Vec<(String, u64)>>;
+
+    /// Enables or disables write batching, where transactions buffer their writes and submit
+    /// them in batches rather than one by one. This only affects engines with remote storage,
+    /// and defers write errors until the writes are submitted, see raft::Transaction.
+    fn set_write_batching(&self, _enabled: bool) -> Result<()> {
+        Ok(())
+    }
+
+    /// Sets the tenant whose keyspace subsequent transactions operate on, or None for the
+    /// system tenant. The tenant must exist when transactions begin.
+    fn set_tenant(&mut self, tenant: Option<String>) -> Result<()>;
+
     /// Begins a session for executing individual statements
     fn session(&self) -> Result<Session<Self>> {
-        Ok(Session { engine: self.clone(), txn: None })
+        Ok(Session {
+            engine: self.clone(),
+            txn: None,
+            aborted: false,
+            disabled_rules: HashSet::new(),
+            parallelism: 1,
+            memory_limit: 0,
+            cursors: HashMap::new(),
+            admin: None,
+            tenant: None,
+            priority: Priority::default(),
+            privileged: false,
+        })
     }
 }
 
@@ -39,14 +92,36 @@ pub trait Transaction: Catalog {
     fn version(&self) -> u64;
     /// Whether the transaction is read-only
     fn read_only(&self) -> bool;
+    /// The size limits for rows written by the transaction
+    fn limits(&self) -> Limits;
 
     /// Commits the transaction
     fn commit(self) -> Result<()>;
     /// Rolls back the transaction
     fn rollback(self) -> Result<()>;
+    /// Prepares a read-write transaction for a two-phase commit under the given global
+    /// identifier, after which it can only be committed or rolled back via the engine, e.g. by
+    /// another session or after a leader change. Its writes are retained, and a commit can't
+    /// fail due to conflicts. If preparing fails, the transaction is rolled back.
+    fn prepare(self, gid: &str) -> Result<()>;
+    /// Checks that the schema hasn't been changed by a concurrent transaction since a read-write
+    /// transaction began, returning a serialization error otherwise. Committing such a
+    /// transaction fails too, since it may have been planned against a stale schema.
+    fn check_schema(&self) -> Result<()>;
+    /// Sets a savepoint, replacing any previous one. The session sets one before each
+    /// statement in a transaction, making the statement atomic.
+    fn savepoint(&mut self) -> Result<()>;
+    /// Undoes the writes made since the savepoint, e.g. by a failed statement, leaving the
+    /// transaction's earlier writes in place.
+    fn rollback_to_savepoint(&mut self) -> Result<()>;
 
     /// Creates a new table row
     fn create(&mut self, table: &str, row: Row) -> Result<()>;
+    /// Creates new table rows in bulk, e.g. for CREATE TABLE AS. Engines with remote storage
+    /// submit them in batches rather than one by one.
+    fn create_rows(&mut self, table: &str, rows: Vec<Row>) -> Result<()> {
+        rows.into_iter().try_for_each(|row| self.create(table, row))
+    }
     /// Deletes a table row
     fn delete(&mut self, table: &str, id: &Value) -> Result<()>;
     /// Reads a table row, if it exists
@@ -55,10 +130,81 @@ pub trait Transaction: Catalog {
     fn read_index(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Value>>;
     /// Scans a table's rows
     fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Scan>;
+    /// Scans a table's rows, only reading the given columns (which must include any used by the
+    /// filter) and the primary key if the table layout allows it. Other columns may be NULL.
+    fn scan_columns(
+        &self,
+        table: &str,
+        filter: Option<Expression>,
+        _columns: &[String],
+    ) -> Result<Scan> {
+        self.scan(table, filter)
+    }
+    /// Scans a table's rows as up to the given number of partitions, each covering a contiguous
+    /// primary key range, such that they can be processed in parallel. If columns are given,
+    /// only these are read as for scan_columns().
+    fn scan_partitions(
+        &self,
+        table: &str,
+        filter: Option<Expression>,
+        columns: Option<&[String]>,
+        partitions: usize,
+    ) -> Result<Vec<Scan>> {
+        let rows = match columns {
+            Some(columns) => self.scan_columns(table, filter, columns)?,
+            None => self.scan(table, filter)?,
+        };
+        let rows = rows.collect::<Result<Vec<_>>>()?;
+        Ok(partition(rows, partitions)
+            .into_iter()
+            .map(|rows| Box::new(rows.into_iter().map(Ok)) as Scan)
+            .collect())
+    }
+    /// Scans a random sample of a table's rows, applying the filter to the sampled rows. SYSTEM
+    /// samples skip unsampled blocks of rows without reading them.
+    fn scan_sample(&self, table: &str, sample: &Sample, filter: Option<Expression>)
+        -> Result<Scan>;
+    /// Scans a table's rows in the order of an index created with CREATE INDEX, or in reverse
+    fn scan_index_order(&self, table: &str, index: &str, reverse: bool) -> Result<Scan>;
     /// Scans a column's index entries
     fn scan_index(&self, table: &str, column: &str) -> Result<IndexScan>;
+    /// Scans a table's rows in a primary key range
+    fn scan_range(&self, table: &str, range: Range) -> Result<Scan>;
+    /// Scans a column's index entries in a value range
+    fn scan_index_range(&self, table: &str, column: &str, range: Range) -> Result<IndexScan>;
+    /// Scans a covering index in a value range, returning partial rows with only the indexed,
+    /// primary key, and included columns set, ordered by value and primary key
+    fn scan_index_cover(&self, table: &str, column: &str, range: Range) -> Result<Scan>;
     /// Updates a table row
     fn update(&mut self, table: &str, id: &Value, row: Row) -> Result<()>;
+    /// Writes index entries for up to limit rows following the given primary key, or from the
+    /// start of the table, when backfilling a write-only index. Returns the primary key of the
+    /// last row if the limit was reached, from which to continue. Columnar tables are indexed
+    /// in full.
+    fn backfill_index(
+        &mut self,
+        table: &str,
+        index: &str,
+        after: Option<Value>,
+        limit: usize,
+    ) -> Result<Option<Value>>;
+
+    /// Reads a key/value pair from a key/value namespace, if it exists
+    fn kv_get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>>;
+    /// Sets a key/value pair in a key/value namespace
+    fn kv_set(&mut self, namespace: &str, key: &[u8], value: Vec<u8>) -> Result<()>;
+    /// Deletes a key/value pair from a key/value namespace, if it exists
+    fn kv_delete(&mut self, namespace: &str, key: &[u8]) -> Result<()>;
+    /// Scans a key range of a key/value namespace
+    fn kv_scan(&self, namespace: &str, range: (Bound<Vec<u8>>, Bound<Vec<u8>>)) -> Result<KVScan>;
+
+    /// Returns the committed changes to a table's rows that are visible to the transaction and
+    /// were made after the given version, ordered by version and primary key. This reads all
+    /// row versions of the table, and is used for change data capture.
+    fn scan_changes(&self, table: &str, since: u64) -> Result<Vec<RowChange>>;
+    /// Returns the last version up to which all transactions have completed, as seen by the
+    /// transaction. No changes up to it can become visible later.
+    fn resolved_version(&self) -> u64;
 }
 
 /// An SQL session, which handles transaction control and simplified query execution
@@ -67,15 +213,73 @@ pub struct Session<E: Engine> {
     engine: E,
     /// The current session transaction, if any
     txn: Option<E::Transaction>,
+    /// Whether the current transaction has been aborted by an error, and must be rolled back
+    aborted: bool,
+    /// Optimizer rules disabled via the optimizer_disabled_rules variable
+    disabled_rules: HashSet<String>,
+    /// The number of workers to run table scans with, via the parallelism variable
+    parallelism: usize,
+    /// The memory limit of each statement in bytes, or 0 if unlimited, via memory_limit
+    memory_limit: u64,
+    /// Cursors declared in the current transaction, by name
+    cursors: HashMap<String, Cursor>,
+    /// Administration of the hosting server's sessions, for SHOW SESSIONS and KILL
+    admin: Option<Arc<dyn Admin>>,
+    /// The tenant whose keyspace the session operates on, via the tenant variable, or None
+    /// for the system tenant
+    tenant: Option<String>,
+    /// The priority of the session's requests in the server's query queue, via the priority
+    /// variable
+    priority: Priority,
+    /// Whether the session may use system priority, see Session::set_privileged()
+    privileged: bool,
+}
+
+/// A cursor over the remaining rows of a query, which can be fetched incrementally
+struct Cursor {
+    columns: Columns,
+    rows: Rows,
 }
 
 impl<E: Engine + 'static> Session<E> {
+    /// Sets the administration interface of the server hosting the session, which executes
+    /// SHOW SESSIONS and KILL. Without it, these statements error.
+    pub fn set_admin(&mut self, admin: Arc<dyn Admin>) {
+        self.admin = Some(admin)
+    }
+
+    /// Allows or disallows the session to use system priority, which bypasses the server's
+    /// query queue. Sessions aren't privileged by default, see Server::admin_hosts().
+    pub fn set_privileged(&mut self, privileged: bool) {
+        self.privileged = privileged
+    }
+
     /// Executes a query, managing transaction status for the session
     pub fn execute(&mut self, query: &str) -> Result<ResultSet> {
+        self.execute_statement(Parser::new(query).parse()?)
+    }
+
+    /// Executes a parsed statement, managing transaction status for the session
+    pub fn execute_statement(&mut self, statement: ast::Statement) -> Result<ResultSet> {
+        // An aborted transaction only accepts COMMIT or ROLLBACK, both of which roll it back.
+        if self.aborted {
+            return match statement {
+                ast::Statement::Commit | ast::Statement::Rollback => {
+                    let txn = self.txn.take().unwrap();
+                    self.aborted = false;
+                    self.cursors.clear();
+                    let version = txn.version();
+                    txn.rollback()?;
+                    Ok(ResultSet::Rollback { version })
+                }
+                _ => Err(Error::Value("Transaction aborted, must roll back".into())),
+            };
+        }
+
         // FIXME We should match on self.txn as well, but get this error:
         // error[E0009]: cannot bind by-move and by-ref in the same pattern
         // ...which seems like an arbitrary compiler limitation
-        match Parser::new(query).parse()? {
+        match statement {
             ast::Statement::Begin { .. } if self.txn.is_some() => {
                 Err(Error::Value("Already in a transaction".into()))
             }
@@ -100,37 +304,212 @@ impl<E: Engine + 'static> Session<E> {
                 self.txn = Some(txn);
                 Ok(result)
             }
-            ast::Statement::Commit | ast::Statement::Rollback if self.txn.is_none() => {
+            ast::Statement::Commit | ast::Statement::Rollback | ast::Statement::Prepare(_)
+                if self.txn.is_none() =>
+            {
                 Err(Error::Value("Not in a transaction".into()))
             }
             ast::Statement::Commit => {
                 let txn = self.txn.take().unwrap();
+                self.cursors.clear();
                 let version = txn.version();
                 txn.commit()?;
                 Ok(ResultSet::Commit { version })
             }
             ast::Statement::Rollback => {
                 let txn = self.txn.take().unwrap();
+                self.cursors.clear();
                 let version = txn.version();
                 txn.rollback()?;
                 Ok(ResultSet::Rollback { version })
             }
-            ast::Statement::Explain(statement) => self.read_with_txn(|txn| {
-                Ok(ResultSet::Explain(Plan::build(*statement, txn)?.optimize(txn)?.0))
-            }),
-            statement if self.txn.is_some() => Plan::build(statement, self.txn.as_mut().unwrap())?
-                .optimize(self.txn.as_mut().unwrap())?
-                .execute(self.txn.as_mut().unwrap()),
-            statement @ ast::Statement::Select { .. } => {
+            // A prepared transaction is detached from the session, and resolved via the engine.
+            ast::Statement::Prepare(gid) => {
+                let txn = self.txn.take().unwrap();
+                self.cursors.clear();
+                let version = txn.version();
+                txn.prepare(&gid)?;
+                Ok(ResultSet::Prepare { gid, version })
+            }
+            ast::Statement::CommitPrepared(_) | ast::Statement::RollbackPrepared(_)
+                if self.txn.is_some() =>
+            {
+                Err(Error::Value("Can't resolve a prepared transaction in a transaction".into()))
+            }
+            ast::Statement::CommitPrepared(gid) => {
+                Ok(ResultSet::Commit { version: self.engine.commit_prepared(&gid)? })
+            }
+            ast::Statement::RollbackPrepared(gid) => {
+                Ok(ResultSet::Rollback { version: self.engine.rollback_prepared(&gid)? })
+            }
+            ast::Statement::ShowPrepared => {
+                let rows: Vec<Row> = self
+                    .engine
+                    .scan_prepared()?
+                    .into_iter()
+                    .map(|(gid, version)| vec![Value::String(gid), Value::Integer(version as i64)])
+                    .collect();
+                Ok(ResultSet::Query {
+                    columns: ["gid", "version"]
+                        .iter()
+                        .map(|c| Column { name: Some(c.to_string()) })
+                        .collect(),
+                    key_column: None,
+                    rows: Box::new(rows.into_iter().map(Ok)),
+                })
+            }
+            ast::Statement::Set { variable, value } => self.set(variable, value),
+            ast::Statement::SetClusterSetting { name, value } => {
+                let value = value.map(|value| match value {
+                    ast::Expression::Literal(ast::Literal::Integer(i)) => Value::Integer(i),
+                    ast::Expression::Literal(ast::Literal::Boolean(b)) => Value::Boolean(b),
+                    _ => Value::Null,
+                });
+                self.write_with_txn(|txn| txn.set_setting(&name, value))?;
+                Ok(ResultSet::Set { variable: name })
+            }
+            ast::Statement::CreateTenant(name) => {
+                self.write_with_txn(|txn| txn.create_tenant(&name))?;
+                Ok(ResultSet::CreateTenant { name })
+            }
+            ast::Statement::DropTenant(name) => {
+                self.write_with_txn(|txn| txn.delete_tenant(&name))?;
+                Ok(ResultSet::DropTenant { name })
+            }
+            ast::Statement::Explain(statement) => {
+                let (disabled, parallelism) = (self.disabled_rules.clone(), self.parallelism);
+                self.read_with_txn(|txn| {
+                    let plan = Plan::build(*statement, txn)?
+                        .optimize(txn, &disabled)?
+                        .parallelize(parallelism)?;
+                    Ok(ResultSet::Explain(plan.0))
+                })
+            }
+            // EXPLAIN ANALYZE executes the statement, in the session's transaction or an
+            // implicit one like other statements, discarding any query rows.
+            ast::Statement::ExplainAnalyze(statement) => {
+                let (disabled, parallelism) = (self.disabled_rules.clone(), self.parallelism);
+                let memory = Memory::new(self.memory_limit);
+                let read_only = matches!(*statement, ast::Statement::Select { .. });
+                let analyze = |txn: &mut E::Transaction| {
+                    let start = Instant::now();
+                    let plan = Plan::build(*statement, txn)?
+                        .optimize(txn, &disabled)?
+                        .parallelize(parallelism)?;
+                    let node = plan.0.clone();
+                    let rows = match plan.execute(txn, &memory)? {
+                        ResultSet::Query { mut rows, .. } => {
+                            rows.try_fold(0, |n, row| row.map(|_| n + 1))?
+                        }
+                        ResultSet::Create { count }
+                        | ResultSet::Update { count }
+                        | ResultSet::Delete { count }
+                        | ResultSet::CreateTableAs { count, .. } => count,
+                        _ => 0,
+                    };
+                    Ok(ResultSet::ExplainAnalyze {
+                        plan: node,
+                        rows,
+                        time: start.elapsed(),
+                        memory: memory.peak(),
+                    })
+                };
+                match read_only && self.txn.is_none() {
+                    true => self.read_with_txn(analyze),
+                    false => self.write_with_txn(analyze),
+                }
+            }
+            ast::Statement::DeclareCursor { .. }
+            | ast::Statement::Fetch { .. }
+            | ast::Statement::CloseCursor(_)
+                if self.txn.is_none() =>
+            {
+                Err(Error::Value("Cursors can only be used in a transaction".into()))
+            }
+            ast::Statement::DeclareCursor { name, .. } if self.cursors.contains_key(&name) => {
+                Err(Error::Value(format!("Cursor {} already exists", name)))
+            }
+            ast::Statement::DeclareCursor { name, query } => {
+                let ResultSet::Query { columns, rows, .. } = self.execute_in_txn(*query)? else {
+                    return Err(Error::Internal("Expected query result for cursor".into()));
+                };
+                self.cursors.insert(name.clone(), Cursor { columns, rows });
+                Ok(ResultSet::DeclareCursor { name })
+            }
+            ast::Statement::Fetch { cursor, count } => {
+                let Some(Cursor { columns, rows }) = self.cursors.get_mut(&cursor) else {
+                    return Err(Error::Value(format!("Cursor {} does not exist", cursor)));
+                };
+                let count = count.map_or(usize::MAX, |count| count as usize);
+                let rows = rows.by_ref().take(count).collect::<Result<Vec<_>>>()?;
+                Ok(ResultSet::Query {
+                    columns: columns.clone(),
+                    key_column: None,
+                    rows: Box::new(rows.into_iter().map(Ok)),
+                })
+            }
+            ast::Statement::CloseCursor(name) => match self.cursors.remove(&name) {
+                Some(_) => Ok(ResultSet::CloseCursor { name }),
+                None => Err(Error::Value(format!("Cursor {} does not exist", name))),
+            },
+            // Session administration doesn't involve the session's transaction.
+            ast::Statement::ShowSessions => {
+                let columns = [
+                    "id",
+                    "client",
+                    "statement",
+                    "statement_seconds",
+                    "transaction",
+                    "transaction_seconds",
+                ];
+                let rows: Vec<Row> = self
+                    .admin()?
+                    .sessions()?
+                    .into_iter()
+                    .map(|session| {
+                        let (statement, statement_time) = session.statement.unzip();
+                        let (transaction, transaction_time) = session.transaction.unzip();
+                        let seconds = |time: Option<Duration>| {
+                            time.map_or(Value::Null, |t| Value::Float(t.as_secs_f64()))
+                        };
+                        vec![
+                            Value::Integer(session.id as i64),
+                            Value::String(session.client),
+                            statement.map_or(Value::Null, Value::String),
+                            seconds(statement_time),
+                            transaction.map_or(Value::Null, |v| Value::Integer(v as i64)),
+                            seconds(transaction_time),
+                        ]
+                    })
+                    .collect();
+                Ok(ResultSet::Query {
+                    columns: columns.iter().map(|c| Column { name: Some(c.to_string()) }).collect(),
+                    key_column: None,
+                    rows: Box::new(rows.into_iter().map(Ok)),
+                })
+            }
+            ast::Statement::Kill { session, query } => {
+                self.admin()?.kill(session, query)?;
+                Ok(ResultSet::Kill { session, query })
+            }
+            statement if self.txn.is_some() => self.execute_in_txn(statement),
+            statement @ ast::Statement::CreateIndex { .. } => self.create_index_online(statement),
+            statement @ (ast::Statement::Select { .. } | ast::Statement::ShowTableStatus) => {
                 let mut txn = self.engine.begin_read_only()?;
-                let result =
-                    Plan::build(statement, &mut txn)?.optimize(&mut txn)?.execute(&mut txn);
+                let result = Plan::build(statement, &mut txn)?
+                    .optimize(&mut txn, &self.disabled_rules)?
+                    .parallelize(self.parallelism)?
+                    .execute(&mut txn, &Memory::new(self.memory_limit));
                 txn.rollback()?;
                 result
             }
             statement => {
                 let mut txn = self.engine.begin()?;
-                match Plan::build(statement, &mut txn)?.optimize(&mut txn)?.execute(&mut txn) {
+                match Plan::build(statement, &mut txn)?
+                    .optimize(&mut txn, &self.disabled_rules)?
+                    .parallelize(self.parallelism)?
+                    .execute(&mut txn, &Memory::new(self.memory_limit))
+                {
                     Ok(result) => {
                         txn.commit()?;
                         Ok(result)
@@ -144,6 +523,265 @@ impl<E: Engine + 'static> Session<E> {
         }
     }
 
+    /// Returns the server administration interface, or an error if there is none. Tenants
+    /// can't see or kill other sessions, which may belong to other tenants.
+    fn admin(&self) -> Result<&dyn Admin> {
+        if self.tenant.is_some() {
+            return Err(Error::Value("Session administration requires the system tenant".into()));
+        }
+        self.admin
+            .as_deref()
+            .ok_or_else(|| Error::Value("Session administration is not supported".into()))
+    }
+
+    /// Executes a statement in the session's transaction, which must exist. Statements are
+    /// atomic: if one fails, its writes are rolled back but the transaction can continue.
+    fn execute_in_txn(&mut self, statement: ast::Statement) -> Result<ResultSet> {
+        let txn = self.txn.as_mut().unwrap();
+        let memory = Memory::new(self.memory_limit);
+        let result = txn
+            .savepoint()
+            .and_then(|_| txn.check_schema())
+            .and_then(|_| Plan::build(statement, txn))
+            .and_then(|plan| plan.optimize(txn, &self.disabled_rules))
+            .and_then(|plan| plan.parallelize(self.parallelism))
+            .and_then(|plan| plan.execute(txn, &memory));
+        // Serialization failures and aborted or timed out Raft operations leave
+        // the transaction unable to complete its work, so it must be rolled back.
+        // Other failed statements only roll back their own writes.
+        let abort = matches!(
+            result,
+            Err(Error::Serialization
+                | Error::Abort
+                | Error::NotLeader(_)
+                | Error::Timeout
+                | Error::Cancelled)
+        );
+        if abort || result.is_err() && txn.rollback_to_savepoint().is_err() {
+            self.aborted = true;
+        }
+        result
+    }
+
+    /// Deletes up to batch_size expired rows from each table with a TTL, in a separate
+    /// transaction per table, returning the number of deleted rows. Expired rows are already
+    /// filtered out of reads, so this only reclaims their storage.
+    pub fn expire(&mut self, batch_size: u64) -> Result<u64> {
+        let tables = self.read_with_txn(|txn| {
+            Ok(txn.scan_tables()?.filter(|t| t.ttl.is_some()).collect::<Vec<_>>())
+        })?;
+        let mut count = 0;
+        for table in tables {
+            let Some(expired) = table.ttl_expired()? else { continue };
+            let node = Node::Delete {
+                table: table.name.clone(),
+                source: Box::new(Node::Limit {
+                    source: Box::new(Node::Scan {
+                        table: table.name,
+                        alias: None,
+                        filter: Some(expired),
+                        columns: None,
+                    }),
+                    limit: batch_size,
+                }),
+            };
+            let mut txn = self.engine.begin()?;
+            match Plan(node, Vec::new()).execute(&mut txn, &Memory::default()) {
+                Ok(ResultSet::Delete { count: deleted }) => {
+                    txn.commit()?;
+                    count += deleted;
+                }
+                Ok(result) => {
+                    txn.rollback()?;
+                    return Err(Error::Internal(format!("Unexpected result {:?}", result)));
+                }
+                Err(error) => {
+                    txn.rollback()?;
+                    return Err(error);
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Purges tables that were dropped at least retention seconds ago, in a separate transaction
+    /// per table, returning the number of purged tables.
+    pub fn purge_dropped(&mut self, retention: u64) -> Result<u64> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| Error::Internal(err.to_string()))?
+            .as_secs();
+        let dropped = self.read_with_txn(|txn| txn.scan_dropped_tables())?;
+        let mut count = 0;
+        for dropped in dropped.into_iter().filter(|d| d.time.saturating_add(retention) <= now) {
+            let mut txn = self.engine.begin()?;
+            match txn.purge_table(&dropped.table.name) {
+                Ok(()) => {
+                    txn.commit()?;
+                    count += 1;
+                }
+                Err(error) => {
+                    txn.rollback()?;
+                    return Err(error);
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Creates an index without blocking writes to the table, when CREATE INDEX is executed
+    /// outside of an explicit transaction. The index is first created as write-only, such that
+    /// all later writes maintain its entries while queries ignore it. Transactions that began
+    /// before this can't commit writes, since the schema changed. The existing rows are then
+    /// backfilled in batches, each in a separate transaction which indexes the rows visible at
+    /// its snapshot. A batch that conflicts with a concurrent write of the same rows is retried,
+    /// and the writer's own index entries reconcile any later changes. Finally, the index is
+    /// published for use by queries. If the backfill fails, the index is dropped again.
+    fn create_index_online(&mut self, statement: ast::Statement) -> Result<ResultSet> {
+        let index = self.write_with_txn(|txn| {
+            let Node::CreateIndex { mut index } = Plan::build(statement, txn)?.0 else {
+                return Err(Error::Internal("Expected CreateIndex plan".into()));
+            };
+            index.write_only = true;
+            txn.create_index(index.clone())?;
+            Ok(index)
+        })?;
+        let (table, name) = (index.table.as_str(), index.name.as_str());
+        let mut after = None;
+        let mut retries = 0;
+        let result = loop {
+            let batch = |txn: &mut E::Transaction| {
+                txn.backfill_index(table, name, after.clone(), INDEX_BACKFILL_BATCH_SIZE)
+            };
+            match self.write_with_txn(batch) {
+                Ok(None) => break self.write_with_txn(|txn| txn.publish_index(table, name)),
+                Ok(next) => (after, retries) = (next, 0),
+                Err(Error::Serialization) if retries < INDEX_BACKFILL_RETRIES => {
+                    std::thread::sleep(Duration::from_millis(50 * 2_u64.pow(retries)));
+                    retries += 1;
+                }
+                Err(error) => break Err(error),
+            }
+        };
+        if let Err(error) = result {
+            // If the index can't be dropped either, it remains write-only until dropped.
+            let _ = self.write_with_txn(|txn| txn.delete_index(table, name));
+            return Err(error);
+        }
+        Ok(ResultSet::CreateIndex { name: index.name })
+    }
+
+    /// Sets a session variable. The variables are:
+    ///
+    /// - optimizer_disabled_rules: a comma-separated list of optimizer rules to skip, for debugging.
+    /// - memory_limit: the memory each statement may use for buffered rows, in bytes, or 0 for
+    ///   no limit. See execution::Memory.
+    /// - parallelism: the number of workers to run table scans and aggregations with.
+    /// - write_batching: whether to batch transaction writes, see Engine::set_write_batching().
+    /// - tenant: the tenant whose keyspace to operate on, or NULL for the system tenant. The
+    ///   tenant must exist, and can't be changed in a transaction.
+    /// - priority: the priority of the session's requests in the server's query queue, one of
+    ///   low, normal, high, or system. System priority requires a privileged session in the
+    ///   system tenant.
+    fn set(&mut self, variable: String, value: ast::Expression) -> Result<ResultSet> {
+        match (variable.as_str(), value) {
+            ("optimizer_disabled_rules", ast::Expression::Literal(ast::Literal::String(value))) => {
+                let mut rules = HashSet::new();
+                for rule in
+                    value.split(',').map(|r| r.trim().to_lowercase()).filter(|r| !r.is_empty())
+                {
+                    if !RULES.contains(&rule.as_str()) {
+                        return Err(Error::Value(format!("Unknown optimizer rule {}", rule)));
+                    }
+                    rules.insert(rule);
+                }
+                self.disabled_rules = rules;
+            }
+            ("optimizer_disabled_rules", _) => {
+                return Err(Error::Value(format!("{} must be a string", variable)))
+            }
+            ("memory_limit", ast::Expression::Literal(ast::Literal::Integer(limit)))
+                if limit >= 0 =>
+            {
+                self.memory_limit = limit as u64
+            }
+            ("memory_limit", _) => {
+                return Err(Error::Value(format!("{} must be a non-negative integer", variable)))
+            }
+            ("parallelism", ast::Expression::Literal(ast::Literal::Integer(workers)))
+                if workers > 0 =>
+            {
+                self.parallelism = workers as usize
+            }
+            ("parallelism", _) => {
+                return Err(Error::Value(format!("{} must be a positive integer", variable)))
+            }
+            ("write_batching", ast::Expression::Literal(ast::Literal::Boolean(enabled))) => {
+                self.engine.set_write_batching(enabled)?
+            }
+            ("write_batching", _) => {
+                return Err(Error::Value(format!("{} must be a boolean", variable)))
+            }
+            ("tenant", ast::Expression::Literal(ast::Literal::String(tenant))) => {
+                self.set_tenant(Some(tenant))?
+            }
+            ("tenant", ast::Expression::Literal(ast::Literal::Null)) => self.set_tenant(None)?,
+            ("tenant", _) => return Err(Error::Value(format!("{} must be a string", variable))),
+            ("priority", ast::Expression::Literal(ast::Literal::String(priority))) => {
+                let priority = priority.parse()?;
+                if priority == Priority::System && !self.privileged {
+                    return Err(Error::Value(
+                        "System priority requires a privileged session".into(),
+                    ));
+                }
+                if priority == Priority::System && self.tenant.is_some() {
+                    return Err(Error::Value("System priority requires the system tenant".into()));
+                }
+                self.priority = priority
+            }
+            ("priority", _) => return Err(Error::Value(format!("{} must be a string", variable))),
+            _ => return Err(Error::Value(format!("Unknown variable {}", variable))),
+        }
+        Ok(ResultSet::Set { variable })
+    }
+
+    /// Switches the session to a tenant, or the system tenant if None. Begins a transaction to
+    /// check that the tenant exists, and switches back on errors.
+    pub fn set_tenant(&mut self, tenant: Option<String>) -> Result<()> {
+        if self.txn.is_some() {
+            return Err(Error::Value("Can't change tenant in a transaction".into()));
+        }
+        if tenant.is_some() && self.priority == Priority::System {
+            return Err(Error::Value("Can't switch to a tenant with system priority".into()));
+        }
+        self.engine.set_tenant(tenant.clone())?;
+        if let Err(error) = self.engine.begin_read_only().and_then(|txn| txn.rollback()) {
+            self.engine.set_tenant(self.tenant.clone())?;
+            return Err(error);
+        }
+        self.tenant = tenant;
+        Ok(())
+    }
+
+    /// Returns the session's tenant, or None for the system tenant.
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    /// Returns the priority of the session's requests in the server's query queue.
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Returns the status of the session's transaction, if any.
+    pub fn transaction(&self) -> Option<TransactionStatus> {
+        self.txn.as_ref().map(|txn| TransactionStatus {
+            version: txn.version(),
+            read_only: txn.read_only(),
+            aborted: self.aborted,
+        })
+    }
+
     /// Runs a read-only closure in the session's transaction, or a new
     /// transaction if none is active.
     ///
@@ -160,6 +798,181 @@ impl<E: Engine + 'static> Session<E> {
         txn.rollback()?;
         result
     }
+
+    /// Runs a read-write closure in the session's transaction, or a new
+    /// transaction if none is active which is committed if the closure succeeds.
+    pub fn write_with_txn<R, F>(&mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut E::Transaction) -> Result<R>,
+    {
+        if self.aborted {
+            return Err(Error::Value("Transaction aborted, must roll back".into()));
+        }
+        if let Some(ref mut txn) = self.txn {
+            let result = f(txn);
+            if matches!(
+                result,
+                Err(Error::Serialization
+                    | Error::Abort
+                    | Error::NotLeader(_)
+                    | Error::Timeout
+                    | Error::Cancelled)
+            ) {
+                self.aborted = true;
+            }
+            return result;
+        }
+        let mut txn = self.engine.begin()?;
+        match f(&mut txn) {
+            Ok(result) => {
+                txn.commit()?;
+                Ok(result)
+            }
+            Err(error) => {
+                txn.rollback()?;
+                Err(error)
+            }
+        }
+    }
+}
+
+/// A change to a table row by a committed transaction, see Transaction::scan_changes().
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RowChange {
+    /// The table name
+    pub table: String,
+    /// The row's primary key
+    pub id: Value,
+    /// The version of the transaction that made the change
+    pub version: u64,
+    /// The row before the change, if it existed
+    pub before: Option<Row>,
+    /// The row after the change, unless it was deleted
+    pub after: Option<Row>,
+}
+
+impl RowChange {
+    /// Returns the kind of change.
+    pub fn op(&self) -> ChangeOp {
+        match (&self.before, &self.after) {
+            (None, _) => ChangeOp::Insert,
+            (Some(_), Some(_)) => ChangeOp::Update,
+            (Some(_), None) => ChangeOp::Delete,
+        }
+    }
+}
+
+/// The kind of a row change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeOp {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl std::fmt::Display for ChangeOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Insert => "insert",
+            Self::Update => "update",
+            Self::Delete => "delete",
+        })
+    }
+}
+
+/// A client session hosted by a server, as listed by SHOW SESSIONS.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    /// The server-assigned session ID, used by KILL.
+    pub id: u64,
+    /// The client's network address.
+    pub client: String,
+    /// The statement currently being executed, if any, and for how long.
+    pub statement: Option<(String, Duration)>,
+    /// The version of the session's transaction, if any, and how long ago it began.
+    pub transaction: Option<(u64, Duration)>,
+}
+
+/// Administration of the client sessions hosted by a server, which executes SHOW SESSIONS and
+/// KILL statements, see Session::set_admin().
+pub trait Admin: Send + Sync {
+    /// Lists the server's client sessions, ordered by ID.
+    fn sessions(&self) -> Result<Vec<SessionInfo>>;
+    /// Cancels a session's current statement, and also disconnects the session and rolls back
+    /// its transaction unless query is true.
+    fn kill(&self, id: u64, query: bool) -> Result<()>;
+}
+
+/// The status of a session transaction
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransactionStatus {
+    /// The transaction's version
+    pub version: u64,
+    /// Whether the transaction is read-only
+    pub read_only: bool,
+    /// Whether the transaction has been aborted by an error, and must be rolled back
+    pub aborted: bool,
+}
+
+/// Size limits for rows written via SQL, checked by the INSERT and UPDATE executors before
+/// writing a row. Sizes are in bytes, and 0 means unlimited.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Limits {
+    /// The maximum size of an encoded row, using the table's codec
+    pub max_row_size: u64,
+    /// The maximum size of an encoded primary key value
+    pub max_key_size: u64,
+    /// The maximum size of an encoded secondary index value
+    pub max_index_entry_size: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self { max_row_size: 1 << 20, max_key_size: 4096, max_index_entry_size: 4096 }
+    }
+}
+
+impl Limits {
+    /// Checks that a table row is within the limits.
+    pub fn check(&self, table: &Table, row: &[Value]) -> Result<()> {
+        let exceeds = |size: usize, max: u64| max > 0 && size as u64 > max;
+        let size = table.codec.encode(table, row)?.len();
+        if exceeds(size, self.max_row_size) {
+            return Err(Error::Value(format!(
+                "Row size {} bytes exceeds maximum of {} bytes for table {}",
+                size, self.max_row_size, table.name
+            )));
+        }
+        for (column, value) in table.columns.iter().zip(row) {
+            if column.primary_key {
+                let size = keycode::serialize(value)?.len();
+                if exceeds(size, self.max_key_size) {
+                    return Err(Error::Value(format!(
+                        "Primary key size {} bytes exceeds maximum of {} bytes for table {} column {}",
+                        size, self.max_key_size, table.name, column.name
+                    )));
+                }
+            } else if column.index {
+                let size = keycode::serialize(value)?.len();
+                if exceeds(size, self.max_index_entry_size) {
+                    return Err(Error::Value(format!(
+                        "Index entry size {} bytes exceeds maximum of {} bytes for table {} column {}",
+                        size, self.max_index_entry_size, table.name, column.name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits items into up to n contiguous partitions of roughly equal size.
+fn partition<T>(items: Vec<T>, n: usize) -> Vec<Vec<T>> {
+    let size = items.len().div_ceil(n.max(1)).max(1);
+    let mut items = items.into_iter();
+    std::iter::from_fn(|| Some(items.by_ref().take(size).collect::<Vec<_>>()))
+        .take_while(|partition| !partition.is_empty())
+        .collect()
 }
 
 /// A row scan iterator
@@ -167,3 +980,6 @@ pub type Scan = Box<dyn DoubleEndedIterator<Item = Result<Row>> + Send>;
 
 /// An index scan iterator
 pub type IndexScan = Box<dyn DoubleEndedIterator<Item = Result<(Value, HashSet<Value>)>> + Send>;
+
+/// A key/value scan iterator
+pub type KVScan = Box<dyn DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>> + Send>;
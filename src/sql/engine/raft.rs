@@ -1,14 +1,50 @@
-use super::super::schema::{Catalog, Table, Tables};
-use super::super::types::{Expression, Row, Value};
-use super::{Engine as _, IndexScan, Scan, Transaction as _};
+use super::super::schema::{
+    Catalog, DroppedTable, Function, Index, Table, TableStats, Tables, TenantStats, Trigger,
+};
+use super::super::types::{Expression, Range, Row, Sample, Value};
+use super::kv::SampleCursor;
+use super::{Engine as _, IndexScan, KVScan, Limits, Scan, Transaction as _};
+use crate::clock::{HybridClock, Timestamp};
 use crate::error::{Error, Result};
 use crate::raft::{self, Entry};
 use crate::storage::{self, bincode, mvcc::TransactionState};
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, oneshot};
 
+/// The maximum number of buffered writes submitted in a single Raft proposal.
+const WRITE_BATCH_SIZE: usize = 1000;
+
+/// The maximum number of rows read by each Raft read of a table scan, see ChunkedScan.
+const SCAN_CHUNK_SIZE: usize = 1000;
+
+/// The number of times a mutation is retried if there is no leader or the leader changes
+/// while processing it, with exponential backoff. Client sessions make this safe, since
+/// the state machine applies a retried mutation only once.
+const MUTATE_RETRIES: u32 = 5;
+
+/// The maximum number of client sessions tracked by the state machine. Beyond this, the
+/// least recently used session is evicted, and retries of its last mutation are no longer
+/// deduplicated.
+const MAX_SESSIONS: usize = 10_000;
+
+/// The metadata key prefix for client sessions, followed by the big-endian session ID.
+const SESSION_PREFIX: &[u8] = b"session/";
+
+/// The metadata key prefix for node liveness heartbeats, followed by the big-endian node ID.
+const LIVENESS_PREFIX: &[u8] = b"liveness/";
+
+/// The number of committed log entries a node can be missing before it's considered lagging.
+const LIVENESS_LAG: raft::Index = 100;
+
+/// The metadata key for the cluster's standby state, see Standby.
+const STANDBY_KEY: &[u8] = b"standby";
+
 /// A Raft state machine mutation.
 ///
 /// TODO: use Cows for these.
@@ -32,6 +68,88 @@ enum Mutation {
     CreateTable { txn: TransactionState, schema: Table },
     /// Deletes a table
     DeleteTable { txn: TransactionState, table: String },
+
+    /// Sets a key/value pair
+    KVSet { txn: TransactionState, namespace: String, key: Vec<u8>, value: Vec<u8> },
+    /// Deletes a key/value pair
+    KVDelete { txn: TransactionState, namespace: String, key: Vec<u8> },
+
+    /// Creates a trigger
+    CreateTrigger { txn: TransactionState, trigger: Trigger },
+    /// Deletes a trigger
+    DeleteTrigger { txn: TransactionState, table: String, trigger: String },
+
+    /// Creates a function
+    CreateFunction { txn: TransactionState, function: Function },
+    /// Deletes a function
+    DeleteFunction { txn: TransactionState, function: String },
+
+    /// Applies a batch of writes in order, stopping at the first error. If commit is set,
+    /// the transaction is then committed, or rolled back if a write failed.
+    Batch { txn: TransactionState, writes: Vec<Mutation>, commit: bool },
+
+    /// Applies a serialized mutation as request seq in the given client session, unless it
+    /// was already applied, in which case the original result is returned.
+    Request { session: u64, seq: u64, command: Vec<u8> },
+
+    /// Drops a table, retaining its data
+    DropTable { txn: TransactionState, table: String, time: u64 },
+    /// Restores a dropped table
+    UndropTable { txn: TransactionState, table: String },
+    /// Purges a dropped table
+    PurgeTable { txn: TransactionState, table: String },
+
+    /// Applies a batch of writes like Batch, then commits the transaction at the given
+    /// hybrid logical clock timestamp, or rolls it back if a write failed.
+    CommitAt { txn: TransactionState, writes: Vec<Mutation>, timestamp: Timestamp },
+
+    /// Records a liveness heartbeat for a node
+    Heartbeat { node: raft::NodeID, timestamp: Timestamp },
+
+    /// Applies a primary cluster's committed log entries, making this a standby cluster
+    Replicate { entries: Vec<Entry> },
+    /// Replaces the state with a primary cluster's snapshot at the given primary index
+    ReplicateSnapshot { index: raft::Index, data: Vec<u8> },
+    /// Promotes a standby cluster to a primary
+    Promote,
+
+    /// Sets a savepoint in the given transaction, replacing any existing one
+    Savepoint(TransactionState),
+    /// Rolls back the given transaction's writes since its savepoint
+    RollbackSavepoint(TransactionState),
+
+    /// Creates an index, indexing existing rows unless it is write-only
+    CreateIndex { txn: TransactionState, index: Index },
+    /// Deletes an index
+    DeleteIndex { txn: TransactionState, table: String, index: String },
+    /// Makes a write-only index public
+    PublishIndex { txn: TransactionState, table: String, index: String },
+    /// Indexes a batch of rows for a write-only index, returning the primary key to continue from
+    BackfillIndex {
+        txn: TransactionState,
+        table: String,
+        index: String,
+        after: Option<Value>,
+        limit: usize,
+    },
+
+    /// Applies a batch of writes like Batch, then prepares the transaction for a two-phase
+    /// commit under the given global identifier, or rolls it back if a write failed.
+    Prepare { txn: TransactionState, writes: Vec<Mutation>, gid: String },
+    /// Commits a prepared transaction at the given hybrid logical clock timestamp
+    CommitPrepared { gid: String, timestamp: Timestamp },
+    /// Rolls back a prepared transaction
+    RollbackPrepared { gid: String },
+
+    /// Sets or resets a cluster setting
+    SetSetting { txn: TransactionState, name: String, value: Option<Value> },
+
+    /// Creates a tenant
+    CreateTenant { txn: TransactionState, tenant: String },
+    /// Deletes a tenant and its data
+    DeleteTenant { txn: TransactionState, tenant: String },
+    /// Applies a mutation in a tenant's keyspace
+    Tenant { tenant: String, mutation: Box<Mutation> },
 }
 
 /// A Raft state machine query.
@@ -55,6 +173,107 @@ enum Query {
     ScanTables { txn: TransactionState },
     /// Reads a table
     ReadTable { txn: TransactionState, table: String },
+
+    /// Reads a key/value pair
+    KVGet { txn: TransactionState, namespace: String, key: Vec<u8> },
+    /// Scans a key/value range
+    KVScan { txn: TransactionState, namespace: String, range: (Bound<Vec<u8>>, Bound<Vec<u8>>) },
+
+    /// Begins a read-only transaction, optionally as of a version. Used for
+    /// stale reads, which don't go through the Raft log.
+    Begin { as_of: Option<u64> },
+
+    /// Scans a table's rows in a primary key range
+    ScanRange { txn: TransactionState, table: String, range: Range },
+    /// Scans an index in a value range
+    ScanIndexRange { txn: TransactionState, table: String, column: String, range: Range },
+    /// Scans a covering index in a value range
+    ScanIndexCover { txn: TransactionState, table: String, column: String, range: Range },
+
+    /// Scans a table's triggers
+    ScanTriggers { txn: TransactionState, table: String },
+
+    /// Reads a function
+    ReadFunction { txn: TransactionState, function: String },
+    /// Scans the functions
+    ScanFunctions { txn: TransactionState },
+
+    /// Checks that the schema hasn't changed since the transaction began
+    CheckSchema { txn: TransactionState },
+
+    /// Scans a table's rows, only reading the given columns
+    ScanColumns {
+        txn: TransactionState,
+        table: String,
+        filter: Option<Expression>,
+        columns: Vec<String>,
+    },
+
+    /// Scans the dropped tables
+    ScanDroppedTables { txn: TransactionState },
+
+    /// Computes a table's storage statistics
+    TableStats { txn: TransactionState, table: String },
+
+    /// Fetches the last liveness heartbeat of each node
+    Heartbeats,
+
+    /// Fetches the cluster's standby state
+    Standby,
+
+    /// Scans a table's row changes after a version
+    ScanChanges { txn: TransactionState, table: String, since: u64 },
+
+    /// Scans up to limit rows of a table in a primary key range, returning the rows and the
+    /// primary key to continue from, if any. Only reads the given columns of columnar tables.
+    ScanChunk {
+        txn: TransactionState,
+        table: String,
+        range: Range,
+        filter: Option<Expression>,
+        columns: Option<Vec<String>>,
+        limit: usize,
+    },
+
+    /// Scans a random sample of a table's rows
+    ScanSample { txn: TransactionState, table: String, sample: Sample, filter: Option<Expression> },
+
+    /// Scans a table's indexes
+    ScanIndexes { txn: TransactionState, table: String },
+    /// Scans a table's rows in index order
+    ScanIndexOrder { txn: TransactionState, table: String, index: String, reverse: bool },
+
+    /// Scans the prepared transactions
+    ScanPrepared,
+
+    /// Scans the cluster settings
+    ScanSettings { txn: TransactionState },
+
+    /// Scans the tenants
+    ScanTenants { txn: TransactionState },
+    /// Runs a query in a tenant's keyspace
+    Tenant { tenant: String, query: Box<Query> },
+
+    /// Scans up to limit rows of a table in index order after the given index entry key,
+    /// returning the rows and the index entry key to continue from, if any.
+    ScanIndexOrderChunk {
+        txn: TransactionState,
+        table: String,
+        index: String,
+        reverse: bool,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    },
+    /// Scans up to limit rows of a seeded table sample from the given cursor, returning the
+    /// rows and the cursor to continue from, if any.
+    ScanSampleChunk {
+        txn: TransactionState,
+        table: String,
+        sample: Sample,
+        filter: Option<Expression>,
+        cursor: Option<SampleCursor>,
+        limit: usize,
+    },
 }
 
 /// Status for the Raft SQL engine.
@@ -64,31 +283,191 @@ pub struct Status {
     pub mvcc: storage::mvcc::Status,
 }
 
-/// A client for the local Raft node.
+/// The liveness state of a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LivenessState {
+    /// The node heartbeats and is replicating the log.
+    Alive,
+    /// The node heartbeats, but its log is far behind the leader's.
+    Lagging,
+    /// The node hasn't heartbeated recently, or at all.
+    Dead,
+}
+
+impl std::fmt::Display for LivenessState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Alive => "alive",
+            Self::Lagging => "lagging",
+            Self::Dead => "dead",
+        })
+    }
+}
+
+/// The liveness of a cluster node, see Raft::liveness().
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeLiveness {
+    pub node: raft::NodeID,
+    pub state: LivenessState,
+    /// The timestamp of the node's last heartbeat, if any.
+    pub heartbeat: Option<Timestamp>,
+    /// The time since the node's last heartbeat, by the reporting node's clock.
+    pub since: Option<Duration>,
+    /// The number of committed log entries the node is missing, as known by the leader.
+    pub lag: raft::Index,
+}
+
+/// The standby state of a cluster that replicates a primary cluster's log, see
+/// Raft::replicate().
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Standby {
+    /// The cluster follows the primary, and has applied its log up to the given
+    /// primary index. It only allows read-only transactions.
+    Following(raft::Index),
+    /// The cluster was promoted to a primary, after applying the primary's log
+    /// up to the given index.
+    Promoted(raft::Index),
+}
+
+impl std::fmt::Display for Standby {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Following(index) => write!(f, "following primary at index {}", index),
+            Self::Promoted(index) => write!(f, "promoted at primary index {}", index),
+        }
+    }
+}
+
+/// A channel for submitting requests to the local Raft node, with an optional
+/// deadline, receiving the response via the oneshot sender.
+pub type RaftTx = mpsc::UnboundedSender<(
+    raft::Request,
+    Option<Instant>,
+    oneshot::Sender<Result<raft::Response>>,
+)>;
+
+/// Request options for a Raft client.
+#[derive(Default)]
+struct Options {
+    /// The deadline for requests.
+    deadline: Option<Instant>,
+    /// Whether requests are cancelled, see Raft::set_cancelled().
+    cancelled: bool,
+    /// If given, read-only transactions are served by the local node once it
+    /// has applied this index.
+    stale: Option<raft::Index>,
+    /// Whether a mutation has been submitted since the last session token.
+    mutated: bool,
+    /// Whether to buffer transaction writes, see Transaction.
+    batch_writes: bool,
+    /// The size limits for rows written via SQL.
+    limits: Limits,
+    /// The tenant whose keyspace requests use, or None for the system keyspace.
+    tenant: Option<String>,
+}
+
+/// A client session, which numbers mutations so the state machine can deduplicate retries.
+struct Session {
+    /// The random session ID.
+    id: u64,
+    /// The sequence number of the last submitted mutation.
+    seq: u64,
+    /// A mutation that timed out, which may have been applied. If it's retried as the next
+    /// mutation, it's resubmitted with the same sequence number.
+    pending: Option<Vec<u8>>,
+}
+
+/// A client for the local Raft node. Clones share the options and session.
 #[derive(Clone)]
 struct Client {
-    tx: mpsc::UnboundedSender<(raft::Request, oneshot::Sender<Result<raft::Response>>)>,
+    tx: RaftTx,
+    options: Arc<Mutex<Options>>,
+    session: Arc<Mutex<Session>>,
+    /// The clock to timestamp commits with.
+    clock: HybridClock,
 }
 
 impl Client {
-    /// Creates a new Raft client.
-    fn new(
-        tx: mpsc::UnboundedSender<(raft::Request, oneshot::Sender<Result<raft::Response>>)>,
-    ) -> Self {
-        Self { tx }
+    /// Creates a new Raft client, with a new session.
+    fn new(tx: RaftTx) -> Self {
+        let session = Session { id: rand::random(), seq: 0, pending: None };
+        Self {
+            tx,
+            options: Arc::new(Mutex::new(Options::default())),
+            session: Arc::new(Mutex::new(session)),
+            clock: HybridClock::system(),
+        }
     }
 
-    /// Executes a request against the Raft cluster.
+    /// Executes a request against the Raft cluster, failing with
+    /// Error::Timeout if it doesn't complete before the deadline, or with
+    /// Error::Cancelled if requests are cancelled.
     fn execute(&self, request: raft::Request) -> Result<raft::Response> {
+        let (deadline, cancelled) = {
+            let options = self.options.lock()?;
+            (options.deadline, options.cancelled)
+        };
+        if cancelled {
+            return Err(Error::Cancelled);
+        }
+        if deadline.is_some_and(|d| d <= Instant::now()) {
+            return Err(Error::Timeout);
+        }
         let (response_tx, response_rx) = oneshot::channel();
-        self.tx.send((request, response_tx))?;
+        self.tx.send((request, deadline, response_tx))?;
         futures::executor::block_on(response_rx)?
     }
 
     /// Mutates the Raft state machine, deserializing the response into the
-    /// return type.
+    /// return type. The mutation is retried if there is no leader or the leader
+    /// changes, and applied at most once.
     fn mutate<V: DeserializeOwned>(&self, mutation: Mutation) -> Result<V> {
-        match self.execute(raft::Request::Mutate(bincode::serialize(&mutation)?))? {
+        let mutation = {
+            let mut options = self.options.lock()?;
+            options.mutated = true;
+            match options.tenant.clone() {
+                Some(tenant) => Mutation::Tenant { tenant, mutation: Box::new(mutation) },
+                None => mutation,
+            }
+        };
+        let command = bincode::serialize(&mutation)?;
+
+        // Hold the session lock while the mutation is submitted, so mutations
+        // from clones are applied in sequence order.
+        let mut session = self.session.lock()?;
+        if session.pending.take().as_ref() != Some(&command) {
+            session.seq += 1;
+        }
+        let seq = session.seq;
+        let request = raft::Request::Mutate(bincode::serialize(&Mutation::Request {
+            session: session.id,
+            seq,
+            command: command.clone(),
+        })?);
+        let mut result = self.execute(request.clone());
+        for i in 0..MUTATE_RETRIES {
+            if !matches!(result, Err(Error::Abort | Error::NotLeader(_))) {
+                break;
+            }
+            // Release the session lock while backing off, so clones can proceed.
+            drop(session);
+            std::thread::sleep(Duration::from_millis(50 * 2_u64.pow(i)));
+            session = self.session.lock()?;
+            // If a clone submitted a later mutation meanwhile, the state machine
+            // rejects this one as stale, so don't retry it.
+            if session.seq != seq {
+                break;
+            }
+            result = self.execute(request.clone());
+        }
+        if let Err(Error::Timeout | Error::Cancelled) = result {
+            if session.seq == seq {
+                session.pending = Some(command);
+            }
+        }
+        drop(session);
+
+        match result? {
             raft::Response::Mutate(response) => Ok(bincode::deserialize(&response)?),
             resp => Err(Error::Internal(format!("Unexpected Raft mutation response {:?}", resp))),
         }
@@ -97,12 +476,31 @@ impl Client {
     /// Queries the Raft state machine, deserializing the response into the
     /// return type.
     fn query<V: DeserializeOwned>(&self, query: Query) -> Result<V> {
+        let query = self.tenant_query(query)?;
         match self.execute(raft::Request::Query(bincode::serialize(&query)?))? {
             raft::Response::Query(response) => Ok(bincode::deserialize(&response)?),
             resp => Err(Error::Internal(format!("Unexpected Raft query response {:?}", resp))),
         }
     }
 
+    /// Queries the local Raft state machine once it has applied the given
+    /// index, deserializing the response into the return type.
+    fn query_stale<V: DeserializeOwned>(&self, query: Query, min_index: raft::Index) -> Result<V> {
+        let command = bincode::serialize(&self.tenant_query(query)?)?;
+        match self.execute(raft::Request::StaleQuery { command, min_index })? {
+            raft::Response::Query(response) => Ok(bincode::deserialize(&response)?),
+            resp => Err(Error::Internal(format!("Unexpected Raft query response {:?}", resp))),
+        }
+    }
+
+    /// Wraps a query to run in the tenant's keyspace, if any.
+    fn tenant_query(&self, query: Query) -> Result<Query> {
+        Ok(match self.options.lock()?.tenant.clone() {
+            Some(tenant) => Query::Tenant { tenant, query: Box::new(query) },
+            None => query,
+        })
+    }
+
     /// Fetches Raft node status.
     fn status(&self) -> Result<raft::Status> {
         match self.execute(raft::Request::Status)? {
@@ -120,12 +518,152 @@ pub struct Raft {
 
 impl Raft {
     /// Creates a new Raft-based SQL engine.
-    pub fn new(
-        tx: mpsc::UnboundedSender<(raft::Request, oneshot::Sender<Result<raft::Response>>)>,
-    ) -> Self {
+    pub fn new(tx: RaftTx) -> Self {
         Self { client: Client::new(tx) }
     }
 
+    /// Sets the clock to timestamp transaction commits with, which should be
+    /// the local Raft node's clock such that timestamps follow the cluster's
+    /// causality. Defaults to a hybrid logical clock using the system clock.
+    pub fn with_clock(mut self, clock: HybridClock) -> Self {
+        self.client.clock = clock;
+        self
+    }
+
+    /// Sets a deadline for subsequent Raft requests, after which they fail
+    /// with Error::Timeout. This applies to all clones of the engine and their
+    /// transactions, so use a separate engine per session.
+    pub fn set_deadline(&self, deadline: Option<Instant>) -> Result<()> {
+        self.client.options.lock()?.deadline = deadline;
+        Ok(())
+    }
+
+    /// Returns the deadline for Raft requests, if any, see set_deadline().
+    pub fn deadline(&self) -> Result<Option<Instant>> {
+        Ok(self.client.options.lock()?.deadline)
+    }
+
+    /// Cancels or resumes subsequent Raft requests. While cancelled, they fail
+    /// with Error::Cancelled, which is used to cancel a session's statement
+    /// from another session via a clone of its engine. Like the deadline, this
+    /// applies to all clones of the engine.
+    pub fn set_cancelled(&self, cancelled: bool) -> Result<()> {
+        self.client.options.lock()?.cancelled = cancelled;
+        Ok(())
+    }
+
+    /// Returns whether Raft requests are cancelled, see set_cancelled().
+    pub fn cancelled(&self) -> Result<bool> {
+        Ok(self.client.options.lock()?.cancelled)
+    }
+
+    /// Sets the size limits for rows written via SQL by subsequent transactions.
+    /// Like the deadline, this applies to all clones of the engine.
+    pub fn set_limits(&self, limits: Limits) -> Result<()> {
+        self.client.options.lock()?.limits = limits;
+        Ok(())
+    }
+
+    /// Enables stale reads for subsequent read-only transactions, given a
+    /// session token (a Raft index). These are served by the local node without
+    /// contacting the leader, once it has applied the token index, so they see
+    /// all writes up to it but may not see later ones. Like the deadline, this
+    /// applies to all clones of the engine.
+    pub fn set_stale_reads(&self, token: Option<raft::Index>) -> Result<()> {
+        self.client.options.lock()?.stale = token;
+        Ok(())
+    }
+
+    /// Returns a session token covering all writes submitted via the engine,
+    /// given the previous token. If there were writes since the previous call,
+    /// this asks the leader for its last log index, which is at least the index
+    /// of any write it has applied.
+    pub fn session_token(&self, token: raft::Index) -> Result<raft::Index> {
+        if !std::mem::take(&mut self.client.options.lock()?.mutated) {
+            return Ok(token);
+        }
+        let status = self.client.status()?;
+        let last_index = status.node_last_index.get(&status.leader).copied().unwrap_or_default();
+        Ok(token.max(last_index).max(status.commit_index))
+    }
+
+    /// Records a liveness heartbeat for the given node in the replicated state, timestamped
+    /// by the engine's clock. Nodes do this periodically, see Server::liveness().
+    pub fn heartbeat(&self, node: raft::NodeID) -> Result<()> {
+        self.client.mutate(Mutation::Heartbeat { node, timestamp: self.client.clock.now() })
+    }
+
+    /// Returns the liveness of all cluster nodes, ordered by node ID. A node is dead if its
+    /// last heartbeat is older than the given timeout by the engine's clock, and lagging if
+    /// its log is missing more than LIVENESS_LAG committed entries.
+    pub fn liveness(&self, timeout: Duration) -> Result<Vec<NodeLiveness>> {
+        let status = self.client.status()?;
+        let heartbeats: HashMap<raft::NodeID, Timestamp> =
+            self.client.query::<Vec<_>>(Query::Heartbeats)?.into_iter().collect();
+        let now = self.client.clock.now();
+        let mut nodes: Vec<_> = status
+            .node_last_index
+            .into_iter()
+            .map(|(node, last_index)| {
+                let heartbeat = heartbeats.get(&node).copied();
+                let since = heartbeat
+                    .map(|h| Duration::from_millis(now.physical.saturating_sub(h.physical)));
+                let lag = status.commit_index.saturating_sub(last_index);
+                let state = match since {
+                    Some(since) if since <= timeout && lag > LIVENESS_LAG => LivenessState::Lagging,
+                    Some(since) if since <= timeout => LivenessState::Alive,
+                    _ => LivenessState::Dead,
+                };
+                NodeLiveness { node, state, heartbeat, since, lag }
+            })
+            .collect();
+        nodes.sort_by_key(|n| n.node);
+        Ok(nodes)
+    }
+
+    /// Reads up to limit committed entries from the local Raft log starting at the given
+    /// index, for a standby cluster to replicate. If the entries have been compacted, the
+    /// snapshot is returned first, followed by the entries after it.
+    pub fn read_log(
+        &self,
+        from: raft::Index,
+        limit: usize,
+    ) -> Result<(Option<raft::Snapshot>, Vec<Entry>)> {
+        match self.client.execute(raft::Request::ReadLog { from, limit })? {
+            raft::Response::Log { snapshot, entries } => Ok((snapshot, entries)),
+            resp => Err(Error::Internal(format!("Unexpected Raft log response {:?}", resp))),
+        }
+    }
+
+    /// Applies a primary cluster's committed log entries, as returned by its read_log(). This
+    /// makes the cluster a standby of the primary, which only allows read-only transactions
+    /// until it's promoted. Entries that were already applied are skipped. Returns the last
+    /// applied primary index.
+    pub fn replicate(&self, entries: Vec<Entry>) -> Result<raft::Index> {
+        self.client.mutate(Mutation::Replicate { entries })
+    }
+
+    /// Replaces the state with a primary cluster's snapshot, when the primary has compacted
+    /// the entries a standby cluster needs. Returns the snapshot's primary index.
+    pub fn replicate_snapshot(&self, snapshot: raft::Snapshot) -> Result<raft::Index> {
+        if crc32fast::hash(&snapshot.data) != snapshot.checksum {
+            return Err(Error::Value(format!("Invalid checksum for snapshot {}", snapshot.index)));
+        }
+        self.client
+            .mutate(Mutation::ReplicateSnapshot { index: snapshot.index, data: snapshot.data })
+    }
+
+    /// Promotes a standby cluster to a primary, stopping replication and rolling back the
+    /// primary's in-flight transactions. Returns the last applied primary index.
+    pub fn promote(&self) -> Result<raft::Index> {
+        self.client.mutate(Mutation::Promote)
+    }
+
+    /// Returns the cluster's standby state, if it has replicated a primary.
+    pub fn standby(&self) -> Result<Option<Standby>> {
+        self.client.query(Query::Standby)
+    }
+
     /// Creates an underlying state machine for a Raft engine.
     pub fn new_state<E: storage::engine::Engine>(engine: E) -> Result<State<E>> {
         State::new(engine)
@@ -140,6 +678,17 @@ impl Raft {
 impl super::Engine for Raft {
     type Transaction = Transaction;
 
+    fn set_write_batching(&self, enabled: bool) -> Result<()> {
+        self.client.options.lock()?.batch_writes = enabled;
+        Ok(())
+    }
+
+    // Like the deadline, this applies to all clones of the engine.
+    fn set_tenant(&mut self, tenant: Option<String>) -> Result<()> {
+        self.client.options.lock()?.tenant = tenant;
+        Ok(())
+    }
+
     fn begin(&self) -> Result<Self::Transaction> {
         Transaction::begin(self.client.clone(), false, None)
     }
@@ -151,20 +700,308 @@ impl super::Engine for Raft {
     fn begin_as_of(&self, version: u64) -> Result<Self::Transaction> {
         Transaction::begin(self.client.clone(), true, Some(version))
     }
+
+    fn commit_prepared(&self, gid: &str) -> Result<u64> {
+        let timestamp = self.client.clock.now();
+        self.client.mutate(Mutation::CommitPrepared { gid: gid.to_string(), timestamp })
+    }
+
+    fn rollback_prepared(&self, gid: &str) -> Result<u64> {
+        self.client.mutate(Mutation::RollbackPrepared { gid: gid.to_string() })
+    }
+
+    fn scan_prepared(&self) -> Result<Vec<(String, u64)>> {
+        self.client.query(Query::ScanPrepared)
+    }
 }
 
 /// A Raft-based SQL transaction.
-#[derive(Clone)]
+///
+/// With write batching enabled, row and key/value writes are buffered and submitted as a
+/// single Raft proposal before the next row read, schema change, or commit, or once
+/// WRITE_BATCH_SIZE writes are buffered, instead of a Raft round trip per write. Reads thus
+/// see the transaction's own writes, but write errors such as constraint violations and write
+/// conflicts are only returned when the writes are submitted, possibly by a later statement.
+/// If this happens on commit the transaction is rolled back, otherwise it must be rolled back.
 pub struct Transaction {
     client: Client,
     state: TransactionState,
+    /// For stale read-only transactions, the index to read at or after.
+    stale: Option<raft::Index>,
+    /// The size limits for rows written via SQL.
+    limits: Limits,
+    /// Buffered writes, not yet submitted.
+    writes: Mutex<Vec<Mutation>>,
+    /// Whether submitting buffered writes failed, possibly after applying some of them. The
+    /// transaction must then be rolled back, and other operations fail with Error::Abort.
+    failed: AtomicBool,
+    /// The statement savepoint, see savepoint().
+    savepoint: Mutex<Savepoint>,
+}
+
+/// The state of a Raft transaction's savepoint. Setting it in the state machine is deferred
+/// until the next write, so statements that don't write don't need a Raft round trip.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Savepoint {
+    /// No savepoint has been requested.
+    None,
+    /// A savepoint was requested, but there have been no writes since.
+    Pending,
+    /// The savepoint was set (or buffered) before subsequent writes.
+    Set,
 }
 
 impl Transaction {
-    /// Starts a transaction in the given mode.
+    /// Starts a transaction in the given mode. Read-only transactions don't
+    /// allocate a version nor register with the state machine, so they're begun
+    /// via a Raft read rather than a log proposal, or locally if stale reads
+    /// are enabled.
     fn begin(client: Client, read_only: bool, as_of: Option<u64>) -> Result<Self> {
-        let state = client.mutate(Mutation::Begin { read_only, as_of })?;
-        Ok(Self { client, state })
+        let (stale, limits) = {
+            let options = client.options.lock()?;
+            (options.stale.filter(|_| read_only), options.limits)
+        };
+        let state = match stale {
+            Some(index) => client.query_stale(Query::Begin { as_of }, index)?,
+            None if read_only => client.query(Query::Begin { as_of })?,
+            None => client.mutate(Mutation::Begin { read_only, as_of })?,
+        };
+        Ok(Self {
+            client,
+            state,
+            stale,
+            limits,
+            writes: Mutex::new(Vec::new()),
+            failed: false.into(),
+            savepoint: Mutex::new(Savepoint::None),
+        })
+    }
+
+    /// Queries the state machine, locally for stale transactions. Buffered writes are
+    /// submitted first, so the query sees them.
+    fn query<V: DeserializeOwned>(&self, query: Query) -> Result<V> {
+        self.flush(None)?;
+        self.query_catalog(query)
+    }
+
+    /// Queries the schema catalog. Schema changes aren't buffered, so this doesn't need to
+    /// submit buffered row writes first.
+    fn query_catalog<V: DeserializeOwned>(&self, query: Query) -> Result<V> {
+        match self.stale {
+            Some(index) => self.client.query_stale(query, index),
+            None => self.client.query(query),
+        }
+    }
+
+    /// Buffers a write if write batching is enabled, submitting the buffer once it's full.
+    /// Otherwise, submits it along with any previously buffered writes.
+    /// A pending savepoint is set before the write.
+    fn write(&self, mutation: Mutation) -> Result<()> {
+        let savepoint = self.take_savepoint()?;
+        if !self.client.options.lock()?.batch_writes {
+            return match savepoint {
+                Some(savepoint) => self.flush(Some(Mutation::Batch {
+                    txn: self.state.clone(),
+                    writes: vec![savepoint, mutation],
+                    commit: false,
+                })),
+                None => self.flush(Some(mutation)),
+            };
+        }
+        if self.state.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let full = {
+            let mut writes = self.writes.lock()?;
+            writes.extend(savepoint);
+            writes.push(mutation);
+            writes.len() >= WRITE_BATCH_SIZE
+        };
+        if full {
+            self.flush(None)?;
+        }
+        Ok(())
+    }
+
+    /// Returns a mutation setting the pending savepoint, if any, which must precede the next
+    /// write. The savepoint is then considered set.
+    fn take_savepoint(&self) -> Result<Option<Mutation>> {
+        let mut savepoint = self.savepoint.lock()?;
+        let pending = *savepoint == Savepoint::Pending && !self.state.read_only;
+        if pending {
+            *savepoint = Savepoint::Set;
+        }
+        Ok(pending.then(|| Mutation::Savepoint(self.state.clone())))
+    }
+
+    /// Submits any buffered writes followed by the given mutation as a single Raft proposal.
+    /// Read-only transactions error on writes without submitting them.
+    fn flush(&self, mutation: Option<Mutation>) -> Result<()> {
+        if self.failed.load(Ordering::SeqCst) {
+            return Err(Error::Abort);
+        }
+        if self.state.read_only && mutation.is_some() {
+            return Err(Error::ReadOnly);
+        }
+        let mut batch = std::mem::take(&mut *self.writes.lock()?);
+        let buffered = !batch.is_empty();
+        batch.extend(mutation);
+        let result = match batch.len() {
+            0 => Ok(()),
+            1 => self.client.mutate(batch.remove(0)),
+            _ => self.client.mutate(Mutation::Batch {
+                txn: self.state.clone(),
+                writes: batch,
+                commit: false,
+            }),
+        };
+        if result.is_err() && buffered {
+            self.failed.store(true, Ordering::SeqCst);
+        }
+        result
+    }
+}
+
+/// A table scan, read in chunks of up to SCAN_CHUNK_SIZE rows with a separate Raft read each,
+/// instead of a single read of the whole scan which would hold up the state machine and buffer
+/// every row at once. The first chunk is read when the scan is created, and the next one once
+/// its rows have been consumed.
+///
+/// Each chunk resumes the transaction in the state machine, which re-validates it (a
+/// read-write transaction must still be active), and reads at the transaction's MVCC
+/// snapshot, so the chunks are consistent. Rows written by the transaction after the scan
+/// started may be seen by later chunks, like with a lazy storage iterator.
+struct ChunkedScan {
+    client: Client,
+    state: TransactionState,
+    stale: Option<raft::Index>,
+    table: String,
+    /// The kind of scan and the position to read the next chunk from, or None if the scan is
+    /// done.
+    next: Option<ChunkedScanKind>,
+    /// The rows read but not yet returned.
+    rows: VecDeque<Row>,
+}
+
+/// The kind of a chunked scan, with the position of its next chunk.
+enum ChunkedScanKind {
+    /// Rows in a primary key range, see Query::ScanChunk.
+    Range { range: Range, filter: Option<Expression>, columns: Option<Vec<String>> },
+    /// Rows in index order, see Query::ScanIndexOrderChunk.
+    IndexOrder { index: String, reverse: bool, after: Option<Vec<u8>> },
+    /// A seeded table sample, see Query::ScanSampleChunk.
+    Sample { sample: Sample, filter: Option<Expression>, cursor: Option<SampleCursor> },
+}
+
+impl ChunkedScan {
+    /// Creates a chunked scan, submitting any buffered writes and reading the first chunk.
+    fn new(txn: &Transaction, table: &str, kind: ChunkedScanKind) -> Result<Self> {
+        txn.flush(None)?;
+        let mut scan = Self {
+            client: txn.client.clone(),
+            state: txn.state.clone(),
+            stale: txn.stale,
+            table: table.to_string(),
+            next: Some(kind),
+            rows: Default::default(),
+        };
+        scan.fetch()?;
+        Ok(scan)
+    }
+
+    /// Reads the next chunk, if any.
+    fn fetch(&mut self) -> Result<()> {
+        let Some(kind) = self.next.take() else { return Ok(()) };
+        let (txn, table, limit) = (self.state.clone(), self.table.clone(), SCAN_CHUNK_SIZE);
+        let (rows, next) = match kind {
+            ChunkedScanKind::Range { range, filter, columns } => {
+                let end = range.1.clone();
+                let (rows, next): (_, Option<Value>) = self.read(Query::ScanChunk {
+                    txn,
+                    table,
+                    range,
+                    filter: filter.clone(),
+                    columns: columns.clone(),
+                    limit,
+                })?;
+                let next = next.map(|key| ChunkedScanKind::Range {
+                    range: (Bound::Excluded(key), end),
+                    filter,
+                    columns,
+                });
+                (rows, next)
+            }
+            ChunkedScanKind::IndexOrder { index, reverse, after } => {
+                let (rows, next): (_, Option<Vec<u8>>) = self.read(Query::ScanIndexOrderChunk {
+                    txn,
+                    table,
+                    index: index.clone(),
+                    reverse,
+                    after,
+                    limit,
+                })?;
+                let next = next.map(|key| ChunkedScanKind::IndexOrder {
+                    index,
+                    reverse,
+                    after: Some(key),
+                });
+                (rows, next)
+            }
+            ChunkedScanKind::Sample { sample, filter, cursor } => {
+                let (rows, next): (_, Option<SampleCursor>) =
+                    self.read(Query::ScanSampleChunk {
+                        txn,
+                        table,
+                        sample: sample.clone(),
+                        filter: filter.clone(),
+                        cursor,
+                        limit,
+                    })?;
+                let next = next.map(|cursor| ChunkedScanKind::Sample {
+                    sample,
+                    filter,
+                    cursor: Some(cursor),
+                });
+                (rows, next)
+            }
+        };
+        self.rows.extend::<Vec<Row>>(rows);
+        self.next = next;
+        Ok(())
+    }
+
+    /// Reads a chunk, from the local state machine for stale reads.
+    fn read<V: DeserializeOwned>(&self, query: Query) -> Result<V> {
+        match self.stale {
+            Some(index) => self.client.query_stale(query, index),
+            None => self.client.query(query),
+        }
+    }
+}
+
+impl Iterator for ChunkedScan {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The last chunk may be empty, if the previous one ended at the limit.
+        while self.rows.is_empty() && self.next.is_some() {
+            if let Err(error) = self.fetch() {
+                return Some(Err(error));
+            }
+        }
+        self.rows.pop_front().map(Ok)
+    }
+}
+
+impl DoubleEndedIterator for ChunkedScan {
+    // Reverse scans read the rest of the scan, since they need its last rows.
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while self.next.is_some() {
+            if let Err(error) = self.fetch() {
+                return Some(Err(error));
+            }
+        }
+        self.rows.pop_back().map(Ok)
     }
 }
 
@@ -177,24 +1014,101 @@ impl super::Transaction for Transaction {
         self.state.read_only
     }
 
+    fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    // Read-only transactions are never registered with the state machine, so
+    // there is nothing to commit or roll back.
     fn commit(self) -> Result<()> {
-        self.client.mutate(Mutation::Commit(self.state.clone()))
+        if self.state.read_only {
+            return Ok(());
+        }
+        if self.failed.load(Ordering::SeqCst) {
+            self.client.mutate::<()>(Mutation::Rollback(self.state.clone()))?;
+            return Err(Error::Abort);
+        }
+        let writes = std::mem::take(&mut *self.writes.lock()?);
+        let timestamp = self.client.clock.now();
+        self.client.mutate(Mutation::CommitAt { txn: self.state.clone(), writes, timestamp })
     }
 
     fn rollback(self) -> Result<()> {
+        if self.state.read_only {
+            return Ok(());
+        }
         self.client.mutate(Mutation::Rollback(self.state.clone()))
     }
 
+    // Like commit, buffered writes are submitted along with the prepare.
+    fn prepare(self, gid: &str) -> Result<()> {
+        if self.state.read_only {
+            return Err(Error::Value("Can't prepare a read-only transaction".into()));
+        }
+        if self.failed.load(Ordering::SeqCst) {
+            self.client.mutate::<()>(Mutation::Rollback(self.state.clone()))?;
+            return Err(Error::Abort);
+        }
+        let writes = std::mem::take(&mut *self.writes.lock()?);
+        let gid = gid.to_string();
+        self.client.mutate(Mutation::Prepare { txn: self.state.clone(), writes, gid })
+    }
+
+    fn check_schema(&self) -> Result<()> {
+        if self.state.read_only {
+            return Ok(());
+        }
+        self.query_catalog(Query::CheckSchema { txn: self.state.clone() })
+    }
+
+    fn savepoint(&mut self) -> Result<()> {
+        *self.savepoint.lock()? = Savepoint::Pending;
+        Ok(())
+    }
+
+    // If the savepoint is still buffered, the buffered writes since it are simply discarded.
+    // Otherwise, all buffered writes came after it and are discarded too.
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        match std::mem::replace(&mut *self.savepoint.lock()?, Savepoint::None) {
+            Savepoint::None => Err(Error::Internal("No savepoint set".into())),
+            Savepoint::Pending => Ok(()),
+            Savepoint::Set => {
+                if self.failed.load(Ordering::SeqCst) {
+                    return Err(Error::Abort);
+                }
+                let mut writes = self.writes.lock()?;
+                if let Some(i) = writes.iter().rposition(|w| matches!(w, Mutation::Savepoint(_))) {
+                    writes.truncate(i);
+                    return Ok(());
+                }
+                writes.clear();
+                drop(writes);
+                self.client.mutate(Mutation::RollbackSavepoint(self.state.clone()))
+            }
+        }
+    }
+
     fn create(&mut self, table: &str, row: Row) -> Result<()> {
-        self.client.mutate(Mutation::Create {
-            txn: self.state.clone(),
-            table: table.to_string(),
-            row,
-        })
+        self.write(Mutation::Create { txn: self.state.clone(), table: table.to_string(), row })
+    }
+
+    // The rows are submitted in batches of WRITE_BATCH_SIZE, regardless of write batching.
+    fn create_rows(&mut self, table: &str, rows: Vec<Row>) -> Result<()> {
+        let mut rows = rows.into_iter().peekable();
+        while rows.peek().is_some() {
+            let mut writes: Vec<_> = self.take_savepoint()?.into_iter().collect();
+            writes.extend(rows.by_ref().take(WRITE_BATCH_SIZE).map(|row| Mutation::Create {
+                txn: self.state.clone(),
+                table: table.to_string(),
+                row,
+            }));
+            self.flush(Some(Mutation::Batch { txn: self.state.clone(), writes, commit: false }))?;
+        }
+        Ok(())
     }
 
     fn delete(&mut self, table: &str, id: &Value) -> Result<()> {
-        self.client.mutate(Mutation::Delete {
+        self.write(Mutation::Delete {
             txn: self.state.clone(),
             table: table.to_string(),
             id: id.clone(),
@@ -202,7 +1116,7 @@ impl super::Transaction for Transaction {
     }
 
     fn read(&self, table: &str, id: &Value) -> Result<Option<Row>> {
-        self.client.query(Query::Read {
+        self.query(Query::Read {
             txn: self.state.clone(),
             table: table.to_string(),
             id: id.clone(),
@@ -210,7 +1124,7 @@ impl super::Transaction for Transaction {
     }
 
     fn read_index(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Value>> {
-        self.client.query(Query::ReadIndex {
+        self.query(Query::ReadIndex {
             txn: self.state.clone(),
             table: table.to_string(),
             column: column.to_string(),
@@ -219,85 +1133,415 @@ impl super::Transaction for Transaction {
     }
 
     fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Scan> {
+        let range = (Bound::Unbounded, Bound::Unbounded);
+        let kind = ChunkedScanKind::Range { range, filter, columns: None };
+        Ok(Box::new(ChunkedScan::new(self, table, kind)?))
+    }
+
+    fn scan_columns(
+        &self,
+        table: &str,
+        filter: Option<Expression>,
+        columns: &[String],
+    ) -> Result<Scan> {
+        let range = (Bound::Unbounded, Bound::Unbounded);
+        let kind = ChunkedScanKind::Range { range, filter, columns: Some(columns.to_vec()) };
+        Ok(Box::new(ChunkedScan::new(self, table, kind)?))
+    }
+
+    fn scan_sample(
+        &self,
+        table: &str,
+        sample: &Sample,
+        filter: Option<Expression>,
+    ) -> Result<Scan> {
+        // Chunks replay the sample's random draws, so they need a seed.
+        let mut sample = sample.clone();
+        sample.seed.get_or_insert_with(rand::random);
+        let kind = ChunkedScanKind::Sample { sample, filter, cursor: None };
+        Ok(Box::new(ChunkedScan::new(self, table, kind)?))
+    }
+
+    fn scan_index_order(&self, table: &str, index: &str, reverse: bool) -> Result<Scan> {
+        let kind = ChunkedScanKind::IndexOrder { index: index.to_string(), reverse, after: None };
+        Ok(Box::new(ChunkedScan::new(self, table, kind)?))
+    }
+
+    fn scan_index(&self, table: &str, column: &str) -> Result<IndexScan> {
         Ok(Box::new(
-            self.client
-                .query::<Vec<_>>(Query::Scan {
-                    txn: self.state.clone(),
-                    table: table.to_string(),
-                    filter,
-                })?
-                .into_iter()
-                .map(Ok),
+            self.query::<Vec<_>>(Query::ScanIndex {
+                txn: self.state.clone(),
+                table: table.to_string(),
+                column: column.to_string(),
+            })?
+            .into_iter()
+            .map(Ok),
         ))
     }
 
-    fn scan_index(&self, table: &str, column: &str) -> Result<IndexScan> {
+    fn scan_range(&self, table: &str, range: Range) -> Result<Scan> {
+        let kind = ChunkedScanKind::Range { range, filter: None, columns: None };
+        Ok(Box::new(ChunkedScan::new(self, table, kind)?))
+    }
+
+    fn scan_index_range(&self, table: &str, column: &str, range: Range) -> Result<IndexScan> {
         Ok(Box::new(
-            self.client
-                .query::<Vec<_>>(Query::ScanIndex {
-                    txn: self.state.clone(),
-                    table: table.to_string(),
-                    column: column.to_string(),
-                })?
-                .into_iter()
-                .map(Ok),
+            self.query::<Vec<_>>(Query::ScanIndexRange {
+                txn: self.state.clone(),
+                table: table.to_string(),
+                column: column.to_string(),
+                range,
+            })?
+            .into_iter()
+            .map(Ok),
+        ))
+    }
+
+    fn scan_index_cover(&self, table: &str, column: &str, range: Range) -> Result<Scan> {
+        Ok(Box::new(
+            self.query::<Vec<_>>(Query::ScanIndexCover {
+                txn: self.state.clone(),
+                table: table.to_string(),
+                column: column.to_string(),
+                range,
+            })?
+            .into_iter()
+            .map(Ok),
         ))
     }
 
     fn update(&mut self, table: &str, id: &Value, row: Row) -> Result<()> {
-        self.client.mutate(Mutation::Update {
+        self.write(Mutation::Update {
             txn: self.state.clone(),
             table: table.to_string(),
             id: id.clone(),
             row,
         })
     }
-}
 
-impl Catalog for Transaction {
-    fn create_table(&mut self, table: Table) -> Result<()> {
-        self.client.mutate(Mutation::CreateTable { txn: self.state.clone(), schema: table })
+    fn backfill_index(
+        &mut self,
+        table: &str,
+        index: &str,
+        after: Option<Value>,
+        limit: usize,
+    ) -> Result<Option<Value>> {
+        self.flush(None)?;
+        self.client.mutate(Mutation::BackfillIndex {
+            txn: self.state.clone(),
+            table: table.to_string(),
+            index: index.to_string(),
+            after,
+            limit,
+        })
     }
 
-    fn delete_table(&mut self, table: &str) -> Result<()> {
-        self.client
-            .mutate(Mutation::DeleteTable { txn: self.state.clone(), table: table.to_string() })
+    fn kv_get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.query(Query::KVGet {
+            txn: self.state.clone(),
+            namespace: namespace.to_string(),
+            key: key.to_vec(),
+        })
     }
 
-    fn read_table(&self, table: &str) -> Result<Option<Table>> {
-        self.client.query(Query::ReadTable { txn: self.state.clone(), table: table.to_string() })
+    fn kv_set(&mut self, namespace: &str, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.write(Mutation::KVSet {
+            txn: self.state.clone(),
+            namespace: namespace.to_string(),
+            key: key.to_vec(),
+            value,
+        })
     }
 
-    fn scan_tables(&self) -> Result<Tables> {
+    fn kv_delete(&mut self, namespace: &str, key: &[u8]) -> Result<()> {
+        self.write(Mutation::KVDelete {
+            txn: self.state.clone(),
+            namespace: namespace.to_string(),
+            key: key.to_vec(),
+        })
+    }
+
+    fn kv_scan(&self, namespace: &str, range: (Bound<Vec<u8>>, Bound<Vec<u8>>)) -> Result<KVScan> {
         Ok(Box::new(
-            self.client.query::<Vec<_>>(Query::ScanTables { txn: self.state.clone() })?.into_iter(),
+            self.query::<Vec<_>>(Query::KVScan {
+                txn: self.state.clone(),
+                namespace: namespace.to_string(),
+                range,
+            })?
+            .into_iter()
+            .map(Ok),
         ))
     }
-}
+
+    fn scan_changes(&self, table: &str, since: u64) -> Result<Vec<super::RowChange>> {
+        self.query(Query::ScanChanges { txn: self.state.clone(), table: table.to_string(), since })
+    }
+
+    fn resolved_version(&self) -> u64 {
+        self.state.resolved_version()
+    }
+}
+
+impl Catalog for Transaction {
+    fn create_table(&mut self, table: Table) -> Result<()> {
+        self.flush(Some(Mutation::CreateTable { txn: self.state.clone(), schema: table }))
+    }
+
+    fn delete_table(&mut self, table: &str) -> Result<()> {
+        self.flush(Some(Mutation::DeleteTable {
+            txn: self.state.clone(),
+            table: table.to_string(),
+        }))
+    }
+
+    fn drop_table(&mut self, table: &str, time: u64) -> Result<()> {
+        self.flush(Some(Mutation::DropTable {
+            txn: self.state.clone(),
+            table: table.to_string(),
+            time,
+        }))
+    }
+
+    fn undrop_table(&mut self, table: &str) -> Result<()> {
+        self.flush(Some(Mutation::UndropTable {
+            txn: self.state.clone(),
+            table: table.to_string(),
+        }))
+    }
+
+    fn purge_table(&mut self, table: &str) -> Result<()> {
+        self.flush(Some(Mutation::PurgeTable { txn: self.state.clone(), table: table.to_string() }))
+    }
+
+    fn scan_dropped_tables(&self) -> Result<Vec<DroppedTable>> {
+        self.query_catalog(Query::ScanDroppedTables { txn: self.state.clone() })
+    }
+
+    fn table_stats(&self, table: &str) -> Result<TableStats> {
+        self.query(Query::TableStats { txn: self.state.clone(), table: table.to_string() })
+    }
+
+    fn read_table(&self, table: &str) -> Result<Option<Table>> {
+        self.query_catalog(Query::ReadTable { txn: self.state.clone(), table: table.to_string() })
+    }
+
+    fn scan_tables(&self) -> Result<Tables> {
+        Ok(Box::new(
+            self.query_catalog::<Vec<_>>(Query::ScanTables { txn: self.state.clone() })?
+                .into_iter(),
+        ))
+    }
+
+    fn create_trigger(&mut self, trigger: Trigger) -> Result<()> {
+        self.flush(Some(Mutation::CreateTrigger { txn: self.state.clone(), trigger }))
+    }
+
+    fn delete_trigger(&mut self, table: &str, trigger: &str) -> Result<()> {
+        self.flush(Some(Mutation::DeleteTrigger {
+            txn: self.state.clone(),
+            table: table.to_string(),
+            trigger: trigger.to_string(),
+        }))
+    }
+
+    fn scan_triggers(&self, table: &str) -> Result<Vec<Trigger>> {
+        self.query_catalog(Query::ScanTriggers {
+            txn: self.state.clone(),
+            table: table.to_string(),
+        })
+    }
+
+    fn create_index(&mut self, index: Index) -> Result<()> {
+        self.flush(Some(Mutation::CreateIndex { txn: self.state.clone(), index }))
+    }
+
+    fn delete_index(&mut self, table: &str, index: &str) -> Result<()> {
+        self.flush(Some(Mutation::DeleteIndex {
+            txn: self.state.clone(),
+            table: table.to_string(),
+            index: index.to_string(),
+        }))
+    }
+
+    fn publish_index(&mut self, table: &str, index: &str) -> Result<()> {
+        self.flush(Some(Mutation::PublishIndex {
+            txn: self.state.clone(),
+            table: table.to_string(),
+            index: index.to_string(),
+        }))
+    }
+
+    fn scan_indexes(&self, table: &str) -> Result<Vec<Index>> {
+        self.query_catalog(Query::ScanIndexes { txn: self.state.clone(), table: table.to_string() })
+    }
+
+    fn create_function(&mut self, function: Function) -> Result<()> {
+        self.flush(Some(Mutation::CreateFunction { txn: self.state.clone(), function }))
+    }
+
+    fn delete_function(&mut self, function: &str) -> Result<()> {
+        self.flush(Some(Mutation::DeleteFunction {
+            txn: self.state.clone(),
+            function: function.to_string(),
+        }))
+    }
+
+    fn read_function(&self, function: &str) -> Result<Option<Function>> {
+        self.query_catalog(Query::ReadFunction {
+            txn: self.state.clone(),
+            function: function.to_string(),
+        })
+    }
+
+    fn scan_functions(&self) -> Result<Vec<Function>> {
+        self.query_catalog(Query::ScanFunctions { txn: self.state.clone() })
+    }
+
+    fn set_setting(&mut self, name: &str, value: Option<Value>) -> Result<()> {
+        self.flush(Some(Mutation::SetSetting {
+            txn: self.state.clone(),
+            name: name.to_string(),
+            value,
+        }))
+    }
+
+    fn scan_settings(&self) -> Result<Vec<(String, Value)>> {
+        self.query_catalog(Query::ScanSettings { txn: self.state.clone() })
+    }
+
+    fn create_tenant(&mut self, tenant: &str) -> Result<()> {
+        self.flush(Some(Mutation::CreateTenant { txn: self.state.clone(), tenant: tenant.into() }))
+    }
+
+    fn delete_tenant(&mut self, tenant: &str) -> Result<()> {
+        self.flush(Some(Mutation::DeleteTenant { txn: self.state.clone(), tenant: tenant.into() }))
+    }
+
+    fn scan_tenants(&self) -> Result<Vec<TenantStats>> {
+        self.query_catalog(Query::ScanTenants { txn: self.state.clone() })
+    }
+}
+
+/// A client session in the state machine, with the result of its last applied mutation.
+#[derive(Serialize, Deserialize)]
+struct ClientSession {
+    /// The sequence number of the last applied mutation.
+    seq: u64,
+    /// The log index of the last applied mutation.
+    index: raft::Index,
+    /// The result of the last applied mutation.
+    result: Result<Vec<u8>>,
+}
 
 /// The Raft state machine for the Raft-based SQL engine, using a KV SQL engine
+///
+/// Mutations are submitted in client sessions and numbered, so that a mutation retried after
+/// a timeout or leader change is only applied once. The last result of each session is stored
+/// as metadata, and returned for retries.
 pub struct State<E: storage::engine::Engine> {
     /// The underlying KV SQL engine
     engine: super::KV<E>,
     /// The last applied index
     applied_index: u64,
+    /// The client session IDs by the log index of their last applied mutation, for eviction.
+    sessions: BTreeMap<raft::Index, u64>,
+    /// The standby state, if the cluster has replicated a primary cluster.
+    standby: Option<Standby>,
 }
 
 impl<E: storage::engine::Engine> State<E> {
     /// Creates a new Raft state maching using the given storage engine.
     pub fn new(engine: E) -> Result<Self> {
         let engine = super::KV::new(engine);
+        engine.check_format()?;
         let applied_index = engine
             .get_metadata(b"applied_index")?
             .map(|b| bincode::deserialize(&b))
             .unwrap_or(Ok(0))?;
-        Ok(State { engine, applied_index })
+        let mut state = State { engine, applied_index, sessions: BTreeMap::new(), standby: None };
+        state.load_sessions()?;
+        state.load_standby()?;
+        Ok(state)
+    }
+
+    /// Loads the stored standby state.
+    fn load_standby(&mut self) -> Result<()> {
+        self.standby =
+            self.engine.get_metadata(STANDBY_KEY)?.map(|v| bincode::deserialize(&v)).transpose()?;
+        Ok(())
+    }
+
+    /// Stores the standby state.
+    fn set_standby(&mut self, standby: Standby) -> Result<()> {
+        self.engine.set_metadata(STANDBY_KEY, bincode::serialize(&standby)?)?;
+        self.standby = Some(standby);
+        Ok(())
+    }
+
+    /// Loads the client session index from the stored sessions.
+    fn load_sessions(&mut self) -> Result<()> {
+        self.sessions.clear();
+        for (key, value) in self.engine.scan_metadata(SESSION_PREFIX)? {
+            let id = key[SESSION_PREFIX.len()..]
+                .try_into()
+                .map(u64::from_be_bytes)
+                .map_err(|_| Error::Internal(format!("Invalid session key {:?}", key)))?;
+            let session: ClientSession = bincode::deserialize(&value)?;
+            self.sessions.insert(session.index, id);
+        }
+        Ok(())
+    }
+
+    /// Applies a mutation in a client session, unless it was already applied, in which case
+    /// the original result is returned. Mutations older than the last one are rejected with
+    /// Error::Value, since they may or may not have been applied. Unlike Error::Abort, which
+    /// Raft returns when the leader changes, this is final and the client doesn't retry it.
+    fn mutate_session(&mut self, id: u64, seq: u64, command: Vec<u8>) -> Result<Vec<u8>> {
+        let key = [SESSION_PREFIX, &id.to_be_bytes()].concat();
+        let session: Option<ClientSession> =
+            self.engine.get_metadata(&key)?.map(|v| bincode::deserialize(&v)).transpose()?;
+        match &session {
+            Some(session) if seq == session.seq => return session.result.clone(),
+            Some(session) if seq < session.seq => {
+                return Err(Error::Value(format!(
+                    "Mutation {} in session {} is older than the last applied mutation {}",
+                    seq, id, session.seq
+                )))
+            }
+            _ => {}
+        }
+
+        let result = bincode::deserialize(&command).and_then(|m| self.mutate(m));
+        if let Err(Error::Internal(_)) = result {
+            return result;
+        }
+        // Entries are applied in order, so this is the index of the current entry.
+        let index = self.applied_index + 1;
+        let previous = session;
+        let session = ClientSession { seq, index, result: result.clone() };
+        self.engine.set_metadata(&key, bincode::serialize(&session)?)?;
+        if let Some(previous) = previous {
+            self.sessions.remove(&previous.index);
+        }
+        self.sessions.insert(index, id);
+
+        // Evict the least recently used session. Each entry applies a single session
+        // mutation, so indexes are unique and all nodes evict the same session.
+        if self.sessions.len() > MAX_SESSIONS {
+            if let Some((_, evict)) = self.sessions.pop_first() {
+                self.engine.delete_metadata(&[SESSION_PREFIX, &evict.to_be_bytes()].concat())?;
+            }
+        }
+        result
     }
 
     /// Mutates the state machine.
     fn mutate(&mut self, mutation: Mutation) -> Result<Vec<u8>> {
         match mutation {
             Mutation::Begin { read_only, as_of } => {
+                if !read_only && matches!(self.standby, Some(Standby::Following(_))) {
+                    return Err(Error::ReadOnly);
+                }
                 let txn = if !read_only {
                     self.engine.begin()?
                 } else if let Some(version) = as_of {
@@ -326,57 +1570,749 @@ impl<E: storage::engine::Engine> State<E> {
             Mutation::DeleteTable { txn, table } => {
                 bincode::serialize(&self.engine.resume(txn)?.delete_table(&table)?)
             }
-        }
-    }
-}
 
-impl<E: storage::engine::Engine> raft::State for State<E> {
-    fn get_applied_index(&self) -> u64 {
-        self.applied_index
-    }
+            Mutation::KVSet { txn, namespace, key, value } => {
+                bincode::serialize(&self.engine.resume(txn)?.kv_set(&namespace, &key, value)?)
+            }
+            Mutation::KVDelete { txn, namespace, key } => {
+                bincode::serialize(&self.engine.resume(txn)?.kv_delete(&namespace, &key)?)
+            }
 
-    fn apply(&mut self, entry: Entry) -> Result<Vec<u8>> {
-        assert_eq!(entry.index, self.applied_index + 1, "entry index not after applied index");
-
-        let result = match &entry.command {
-            Some(command) => match self.mutate(bincode::deserialize(command)?) {
-                error @ Err(Error::Internal(_)) => return error, // don't record as applied
-                result => result,
-            },
-            None => Ok(Vec::new()),
-        };
-        self.applied_index = entry.index;
-        self.engine.set_metadata(b"applied_index", bincode::serialize(&entry.index)?)?;
-        result
+            Mutation::CreateTrigger { txn, trigger } => {
+                bincode::serialize(&self.engine.resume(txn)?.create_trigger(trigger)?)
+            }
+            Mutation::DeleteTrigger { txn, table, trigger } => {
+                bincode::serialize(&self.engine.resume(txn)?.delete_trigger(&table, &trigger)?)
+            }
+
+            Mutation::CreateIndex { txn, index } => {
+                bincode::serialize(&self.engine.resume(txn)?.create_index(index)?)
+            }
+            Mutation::DeleteIndex { txn, table, index } => {
+                bincode::serialize(&self.engine.resume(txn)?.delete_index(&table, &index)?)
+            }
+            Mutation::PublishIndex { txn, table, index } => {
+                bincode::serialize(&self.engine.resume(txn)?.publish_index(&table, &index)?)
+            }
+            Mutation::BackfillIndex { txn, table, index, after, limit } => bincode::serialize(
+                &self.engine.resume(txn)?.backfill_index(&table, &index, after, limit)?,
+            ),
+
+            Mutation::Prepare { txn, writes, gid } => {
+                self.mutate_batch(&txn, writes, true)?;
+                bincode::serialize(&self.engine.resume(txn)?.prepare(&gid)?)
+            }
+            Mutation::CommitPrepared { gid, timestamp } => {
+                let txn = self.engine.resume_prepared(&gid)?;
+                let version = txn.version();
+                txn.commit_at(timestamp)?;
+                bincode::serialize(&version)
+            }
+            Mutation::RollbackPrepared { gid } => {
+                bincode::serialize(&self.engine.rollback_prepared(&gid)?)
+            }
+
+            Mutation::CreateFunction { txn, function } => {
+                bincode::serialize(&self.engine.resume(txn)?.create_function(function)?)
+            }
+            Mutation::DeleteFunction { txn, function } => {
+                bincode::serialize(&self.engine.resume(txn)?.delete_function(&function)?)
+            }
+            Mutation::SetSetting { txn, name, value } => {
+                bincode::serialize(&self.engine.resume(txn)?.set_setting(&name, value)?)
+            }
+            Mutation::CreateTenant { txn, tenant } => {
+                bincode::serialize(&self.engine.resume(txn)?.create_tenant(&tenant)?)
+            }
+            Mutation::DeleteTenant { txn, tenant } => {
+                bincode::serialize(&self.engine.resume(txn)?.delete_tenant(&tenant)?)
+            }
+            // Swap in the tenant's engine while applying, like the standby state is lifted
+            // for replicated mutations.
+            Mutation::Tenant { tenant, mutation } => {
+                let engine = self.engine.clone();
+                self.engine = engine.clone().with_tenant(Some(tenant));
+                let result = self.mutate(*mutation);
+                self.engine = engine;
+                result
+            }
+
+            Mutation::Batch { txn, writes, commit } => {
+                self.mutate_batch(&txn, writes, commit)?;
+                match commit {
+                    true => bincode::serialize(&self.engine.resume(txn)?.commit()?),
+                    false => bincode::serialize(&()),
+                }
+            }
+
+            Mutation::Request { session, seq, command } => {
+                self.mutate_session(session, seq, command)
+            }
+
+            Mutation::DropTable { txn, table, time } => {
+                bincode::serialize(&self.engine.resume(txn)?.drop_table(&table, time)?)
+            }
+            Mutation::UndropTable { txn, table } => {
+                bincode::serialize(&self.engine.resume(txn)?.undrop_table(&table)?)
+            }
+            Mutation::PurgeTable { txn, table } => {
+                bincode::serialize(&self.engine.resume(txn)?.purge_table(&table)?)
+            }
+
+            Mutation::CommitAt { txn, writes, timestamp } => {
+                self.mutate_batch(&txn, writes, true)?;
+                bincode::serialize(&self.engine.resume(txn)?.commit_at(timestamp)?)
+            }
+
+            // A delayed heartbeat never moves the last heartbeat backwards.
+            Mutation::Heartbeat { node, timestamp } => {
+                let key = [LIVENESS_PREFIX, &node.to_be_bytes()].concat();
+                let last: Option<Timestamp> = self
+                    .engine
+                    .get_metadata(&key)?
+                    .map(|v| bincode::deserialize(&v))
+                    .transpose()?;
+                if last < Some(timestamp) {
+                    self.engine.set_metadata(&key, bincode::serialize(&timestamp)?)?;
+                }
+                bincode::serialize(&())
+            }
+
+            // The primary index is recorded up to the last applied entry, even if a later
+            // entry is missing.
+            Mutation::Replicate { entries } => {
+                let mut index = match self.standby {
+                    Some(Standby::Following(index)) => index,
+                    Some(Standby::Promoted(_)) => {
+                        return Err(Error::Value(
+                            "Cluster has been promoted, can't replicate".into(),
+                        ))
+                    }
+                    None => 0,
+                };
+                let mut result = Ok(());
+                for entry in entries {
+                    if entry.index <= index {
+                        continue;
+                    } else if entry.index > index + 1 {
+                        result = Err(Error::Value(format!(
+                            "Missing primary log entries {}-{}",
+                            index + 1,
+                            entry.index - 1
+                        )));
+                        break;
+                    }
+                    if let Some(command) = entry.command {
+                        self.mutate_replicated(command)?;
+                    }
+                    index = entry.index;
+                }
+                self.set_standby(Standby::Following(index))?;
+                result?;
+                bincode::serialize(&index)
+            }
+
+            // The primary's heartbeats are removed, since its nodes aren't part of this
+            // cluster. The snapshot also replaces the applied index, which is rewritten
+            // once the entry is applied.
+            Mutation::ReplicateSnapshot { index, data } => {
+                if let Some(Standby::Promoted(_)) = self.standby {
+                    return Err(Error::Value("Cluster has been promoted, can't replicate".into()));
+                }
+                self.engine.kv.import(bincode::deserialize(&data)?)?;
+                for (key, _) in self.engine.scan_metadata(LIVENESS_PREFIX)? {
+                    self.engine.delete_metadata(&key)?;
+                }
+                self.load_sessions()?;
+                self.set_standby(Standby::Following(index))?;
+                bincode::serialize(&index)
+            }
+
+            // Transactions that were in flight on the primary will never complete, so they're
+            // rolled back. Prepared transactions are kept for their coordinator to resolve.
+            Mutation::Promote => {
+                let Some(Standby::Following(index)) = self.standby else {
+                    return Err(Error::Value("Cluster is not a standby".into()));
+                };
+                let prepared: HashSet<u64> =
+                    self.engine.scan_prepared()?.into_iter().map(|(_, version)| version).collect();
+                for version in self.engine.kv.scan_active()?.difference(&prepared).copied() {
+                    let txn =
+                        TransactionState { version, read_only: false, active: HashSet::new() };
+                    self.engine.kv.resume(txn)?.rollback()?;
+                }
+                self.set_standby(Standby::Promoted(index))?;
+                bincode::serialize(&index)
+            }
+
+            Mutation::Savepoint(txn) => bincode::serialize(&self.engine.resume(txn)?.savepoint()?),
+            Mutation::RollbackSavepoint(txn) => {
+                bincode::serialize(&self.engine.resume(txn)?.rollback_to_savepoint()?)
+            }
+        }
     }
 
-    fn query(&self, command: Vec<u8>) -> Result<Vec<u8>> {
-        match bincode::deserialize(&command)? {
+    /// Runs a query against the given engine, i.e. in its keyspace.
+    fn query_in(&self, engine: &super::KV<E>, query: Query) -> Result<Vec<u8>> {
+        match query {
             Query::Read { txn, table, id } => {
-                bincode::serialize(&self.engine.resume(txn)?.read(&table, &id)?)
+                bincode::serialize(&engine.resume(txn)?.read(&table, &id)?)
             }
             Query::ReadIndex { txn, table, column, value } => {
-                bincode::serialize(&self.engine.resume(txn)?.read_index(&table, &column, &value)?)
+                bincode::serialize(&engine.resume(txn)?.read_index(&table, &column, &value)?)
             }
-            // FIXME These need to stream rows somehow
+            // Table scans are read with ScanChunk, these are kept for nodes running older versions.
             Query::Scan { txn, table, filter } => bincode::serialize(
-                &self.engine.resume(txn)?.scan(&table, filter)?.collect::<Result<Vec<_>>>()?,
+                &engine.resume(txn)?.scan(&table, filter)?.collect::<Result<Vec<_>>>()?,
+            ),
+            Query::ScanColumns { txn, table, filter, columns } => bincode::serialize(
+                &engine
+                    .resume(txn)?
+                    .scan_columns(&table, filter, &columns)?
+                    .collect::<Result<Vec<_>>>()?,
             ),
             Query::ScanIndex { txn, table, column } => bincode::serialize(
-                &self
-                    .engine
+                &engine.resume(txn)?.scan_index(&table, &column)?.collect::<Result<Vec<_>>>()?,
+            ),
+            Query::ScanRange { txn, table, range } => bincode::serialize(
+                &engine.resume(txn)?.scan_range(&table, range)?.collect::<Result<Vec<_>>>()?,
+            ),
+            Query::ScanIndexRange { txn, table, column, range } => bincode::serialize(
+                &engine
                     .resume(txn)?
-                    .scan_index(&table, &column)?
+                    .scan_index_range(&table, &column, range)?
                     .collect::<Result<Vec<_>>>()?,
             ),
-            Query::Status => bincode::serialize(&self.engine.kv.status()?),
+            Query::ScanIndexCover { txn, table, column, range } => bincode::serialize(
+                &engine
+                    .resume(txn)?
+                    .scan_index_cover(&table, &column, range)?
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+            Query::Status => bincode::serialize(&engine.kv.status()?),
 
             Query::ReadTable { txn, table } => {
-                bincode::serialize(&self.engine.resume(txn)?.read_table(&table)?)
+                bincode::serialize(&engine.resume(txn)?.read_table(&table)?)
             }
             Query::ScanTables { txn } => {
-                bincode::serialize(&self.engine.resume(txn)?.scan_tables()?.collect::<Vec<_>>())
+                bincode::serialize(&engine.resume(txn)?.scan_tables()?.collect::<Vec<_>>())
+            }
+            Query::ScanDroppedTables { txn } => {
+                bincode::serialize(&engine.resume(txn)?.scan_dropped_tables()?)
+            }
+            Query::TableStats { txn, table } => {
+                bincode::serialize(&engine.resume(txn)?.table_stats(&table)?)
+            }
+            Query::ScanTriggers { txn, table } => {
+                bincode::serialize(&engine.resume(txn)?.scan_triggers(&table)?)
+            }
+            Query::ReadFunction { txn, function } => {
+                bincode::serialize(&engine.resume(txn)?.read_function(&function)?)
+            }
+            Query::ScanFunctions { txn } => {
+                bincode::serialize(&engine.resume(txn)?.scan_functions()?)
+            }
+            Query::ScanSettings { txn } => {
+                bincode::serialize(&engine.resume(txn)?.scan_settings()?)
+            }
+            Query::CheckSchema { txn } => bincode::serialize(&engine.resume(txn)?.check_schema()?),
+
+            Query::KVGet { txn, namespace, key } => {
+                bincode::serialize(&engine.resume(txn)?.kv_get(&namespace, &key)?)
+            }
+            Query::KVScan { txn, namespace, range } => bincode::serialize(
+                &engine.resume(txn)?.kv_scan(&namespace, range)?.collect::<Result<Vec<_>>>()?,
+            ),
+
+            Query::Heartbeats => {
+                let mut heartbeats = Vec::new();
+                for (key, value) in engine.scan_metadata(LIVENESS_PREFIX)? {
+                    let node = match key[LIVENESS_PREFIX.len()..] {
+                        [node] => node,
+                        _ => {
+                            return Err(Error::Internal(format!("Invalid liveness key {:?}", key)))
+                        }
+                    };
+                    heartbeats.push((node, bincode::deserialize::<Timestamp>(&value)?));
+                }
+                bincode::serialize(&heartbeats)
+            }
+
+            Query::Begin { as_of: Some(version) } => {
+                bincode::serialize(engine.begin_as_of(version)?.state())
+            }
+            Query::Begin { as_of: None } => bincode::serialize(engine.begin_read_only()?.state()),
+
+            Query::Standby => bincode::serialize(&self.standby),
+
+            Query::ScanChanges { txn, table, since } => {
+                bincode::serialize(&engine.resume(txn)?.scan_changes(&table, since)?)
+            }
+            Query::ScanChunk { txn, table, range, filter, columns, limit } => {
+                bincode::serialize(&engine.resume(txn)?.scan_chunk(
+                    &table,
+                    range,
+                    filter,
+                    columns.as_deref(),
+                    limit,
+                )?)
+            }
+            Query::ScanIndexes { txn, table } => {
+                bincode::serialize(&engine.resume(txn)?.scan_indexes(&table)?)
+            }
+            // Index order scans and samples are read with ScanIndexOrderChunk and
+            // ScanSampleChunk, these are kept for nodes running older versions.
+            Query::ScanIndexOrder { txn, table, index, reverse } => bincode::serialize(
+                &engine
+                    .resume(txn)?
+                    .scan_index_order(&table, &index, reverse)?
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+            Query::ScanSample { txn, table, sample, filter } => bincode::serialize(
+                &engine
+                    .resume(txn)?
+                    .scan_sample(&table, &sample, filter)?
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+
+            Query::ScanIndexOrderChunk { txn, table, index, reverse, after, limit } => {
+                bincode::serialize(
+                    &engine
+                        .resume(txn)?
+                        .scan_index_order_chunk(&table, &index, reverse, after, limit)?,
+                )
+            }
+            Query::ScanSampleChunk { txn, table, sample, filter, cursor, limit } => {
+                bincode::serialize(
+                    &engine
+                        .resume(txn)?
+                        .scan_sample_chunk(&table, &sample, filter, cursor, limit)?,
+                )
+            }
+
+            Query::ScanPrepared => bincode::serialize(&engine.scan_prepared()?),
+
+            Query::ScanTenants { txn } => bincode::serialize(&engine.resume(txn)?.scan_tenants()?),
+            Query::Tenant { tenant, query } => {
+                self.query_in(&engine.clone().with_tenant(Some(tenant)), *query)
             }
         }
     }
+
+    /// Applies a command from a primary cluster's log. The primary's heartbeats are skipped,
+    /// since its nodes aren't part of this cluster. Errors other than internal errors were
+    /// also returned by the primary, so they're ignored.
+    fn mutate_replicated(&mut self, command: Vec<u8>) -> Result<()> {
+        let mutation: Mutation = bincode::deserialize(&command)?;
+        if let Mutation::Request { command, .. } = &mutation {
+            if let Ok(Mutation::Heartbeat { .. }) = bincode::deserialize(command) {
+                return Ok(());
+            }
+        }
+        // Lift the standby state while applying, to allow read-write transactions.
+        let standby = self.standby.take();
+        let result = self.mutate(mutation);
+        self.standby = standby;
+        match result {
+            Err(error @ Error::Internal(_)) => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies a batch of writes in order, stopping at the first error. If the transaction is
+    /// to be committed afterwards, it's rolled back on errors.
+    fn mutate_batch(
+        &mut self,
+        txn: &TransactionState,
+        writes: Vec<Mutation>,
+        commit: bool,
+    ) -> Result<()> {
+        for write in writes {
+            match self.mutate(write) {
+                Ok(_) => {}
+                Err(error @ Error::Internal(_)) => return Err(error),
+                Err(error) if commit => {
+                    self.engine.resume(txn.clone())?.rollback()?;
+                    return Err(error);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<E: storage::engine::Engine> raft::State for State<E> {
+    fn get_applied_index(&self) -> u64 {
+        self.applied_index
+    }
+
+    fn apply(&mut self, entry: Entry) -> Result<Vec<u8>> {
+        self.apply_batch(vec![entry])?.remove(0)
+    }
+
+    // The applied index is only written once for the batch, after its commands.
+    fn apply_batch(&mut self, entries: Vec<Entry>) -> Result<Vec<Result<Vec<u8>>>> {
+        let applied_index = self.applied_index;
+        let mut results = Vec::with_capacity(entries.len());
+        let mut error = None;
+        for entry in entries {
+            assert_eq!(entry.index, self.applied_index + 1, "entry index not after applied index");
+            let result = match &entry.command {
+                Some(command) => match bincode::deserialize(command).and_then(|m| self.mutate(m)) {
+                    Err(err @ Error::Internal(_)) => {
+                        error = Some(err); // don't record as applied
+                        break;
+                    }
+                    result => result,
+                },
+                None => Ok(Vec::new()),
+            };
+            self.applied_index = entry.index;
+            results.push(result);
+        }
+        if self.applied_index > applied_index {
+            self.engine.set_metadata(b"applied_index", bincode::serialize(&self.applied_index)?)?;
+        }
+        match error {
+            Some(error) => Err(error),
+            None => Ok(results),
+        }
+    }
+
+    fn query(&self, command: Vec<u8>) -> Result<Vec<u8>> {
+        self.query_in(&self.engine, bincode::deserialize(&command)?)
+    }
+
+    fn checksum(&self) -> Result<u32> {
+        let txn = self.engine.begin_read_only()?;
+        let checksum = super::checksum(&txn)?;
+        txn.rollback()?;
+        Ok(checksum)
+    }
+
+    // The snapshot contains the raw MVCC storage, including the applied index
+    // and any active transactions.
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        bincode::serialize(&self.engine.kv.export()?)
+    }
+
+    fn restore(&mut self, index: u64, snapshot: Vec<u8>) -> Result<()> {
+        self.engine.kv.import(bincode::deserialize(&snapshot)?)?;
+        self.engine.set_metadata(b"applied_index", bincode::serialize(&index)?)?;
+        self.applied_index = index;
+        self.load_sessions()?;
+        self.load_standby()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raft::State as _;
+    use crate::storage::engine::Memory;
+
+    /// Applies a mutation in a client session at the next index.
+    fn apply(
+        state: &mut State<Memory>,
+        session: u64,
+        seq: u64,
+        mutation: Mutation,
+    ) -> Result<Vec<u8>> {
+        let command = bincode::serialize(&mutation)?;
+        let request = Mutation::Request { session, seq, command };
+        let index = state.get_applied_index() + 1;
+        state.apply(Entry { index, term: 1, command: Some(bincode::serialize(&request)?) })
+    }
+
+    #[test]
+    // Retried session mutations are applied once, and return the original result.
+    fn session_dedup() -> Result<()> {
+        let mut state = Raft::new_state(Memory::new())?;
+        let begin = Mutation::Begin { read_only: false, as_of: None };
+
+        let txn = apply(&mut state, 1, 1, begin.clone())?;
+        assert_eq!(apply(&mut state, 1, 1, begin.clone())?, txn);
+        let other = apply(&mut state, 2, 1, begin.clone())?;
+        assert_ne!(other, txn);
+
+        // Errors are also returned for retries.
+        let txn: TransactionState = bincode::deserialize(&txn)?;
+        let namespace = "ns".to_string();
+        let set = Mutation::KVSet { txn: txn.clone(), namespace, key: vec![1], value: vec![1] };
+        apply(&mut state, 1, 2, set.clone())?;
+        let commit = Mutation::Commit(txn.clone());
+        apply(&mut state, 1, 3, commit.clone())?;
+        let write = Mutation::Create { txn, table: "missing".to_string(), row: vec![] };
+        let error = apply(&mut state, 1, 4, write.clone()).unwrap_err();
+        assert_eq!(apply(&mut state, 1, 4, write), Err(error));
+
+        // Older mutations in the session are not applied.
+        assert!(matches!(apply(&mut state, 1, 2, set), Err(Error::Value(_))));
+
+        // The sessions are kept across restarts.
+        let txn = apply(&mut state, 2, 2, begin.clone())?;
+        let mut state = State { sessions: BTreeMap::new(), ..state };
+        state.load_sessions()?;
+        assert_eq!(state.sessions.len(), 2);
+        assert_eq!(apply(&mut state, 2, 2, begin)?, txn);
+        Ok(())
+    }
+
+    #[test]
+    // The client releases the session lock while backing off after a Raft abort, and doesn't
+    // retry the mutation once a clone has submitted a later one.
+    fn client_mutate_retry() -> Result<()> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = Client::new(tx);
+        let clone = client.clone();
+        let begin = Mutation::Begin { read_only: true, as_of: None };
+        let seq = |request: raft::Request| -> Result<u64> {
+            match request {
+                raft::Request::Mutate(command) => match bincode::deserialize(&command)? {
+                    Mutation::Request { seq, .. } => Ok(seq),
+                    _ => panic!("unexpected mutation"),
+                },
+                request => panic!("unexpected request {:?}", request),
+            }
+        };
+
+        let first = std::thread::spawn({
+            let begin = begin.clone();
+            move || client.mutate::<()>(begin)
+        });
+        let (request, _, response_tx) = rx.blocking_recv().expect("no request");
+        assert_eq!(seq(request)?, 1);
+
+        // The clone blocks on the session lock until the first mutation is aborted.
+        let second = std::thread::spawn(move || clone.mutate::<()>(begin));
+        std::thread::sleep(Duration::from_millis(10));
+        response_tx.send(Err(Error::Abort)).unwrap();
+        let (request, _, response_tx) = rx.blocking_recv().expect("no request");
+        assert_eq!(seq(request)?, 2);
+        response_tx.send(Ok(raft::Response::Mutate(bincode::serialize(&())?))).unwrap();
+
+        assert_eq!(second.join().unwrap(), Ok(()));
+        assert_eq!(first.join().unwrap(), Err(Error::Abort));
+        assert!(rx.try_recv().is_err());
+        Ok(())
+    }
+
+    #[test]
+    // The least recently used session is evicted beyond MAX_SESSIONS, and its retries are no
+    // longer deduplicated.
+    fn session_evict() -> Result<()> {
+        let mut state = Raft::new_state(Memory::new())?;
+        let begin = Mutation::Begin { read_only: true, as_of: None };
+        apply(&mut state, 1, 1, begin.clone())?;
+        apply(&mut state, 2, 1, begin.clone())?;
+        apply(&mut state, 1, 2, begin.clone())?;
+        for id in 3..=MAX_SESSIONS as u64 + 1 {
+            apply(&mut state, id, 1, begin.clone())?;
+        }
+        assert_eq!(state.sessions.len(), MAX_SESSIONS);
+        assert_eq!(state.sessions.first_key_value(), Some((&3, &1)));
+
+        // Session 1 was used more recently than session 2, so only session 2 was evicted.
+        assert!(matches!(apply(&mut state, 1, 1, begin.clone()), Err(Error::Value(_))));
+        assert!(apply(&mut state, 2, 0, begin).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    // CommitAt applies the writes and commits at the given timestamp, or rolls back if a write
+    // fails. Commit timestamps increase even if the given ones don't.
+    fn commit_at() -> Result<()> {
+        let mut state = Raft::new_state(Memory::new())?;
+        let begin = Mutation::Begin { read_only: false, as_of: None };
+        let namespace = "ns".to_string();
+
+        let t1: TransactionState = bincode::deserialize(&apply(&mut state, 1, 1, begin.clone())?)?;
+        let t2: TransactionState = bincode::deserialize(&apply(&mut state, 1, 2, begin.clone())?)?;
+        let t3: TransactionState = bincode::deserialize(&apply(&mut state, 1, 3, begin)?)?;
+
+        let set = Mutation::KVSet {
+            txn: t1.clone(),
+            namespace: namespace.clone(),
+            key: vec![1],
+            value: vec![1],
+        };
+        let timestamp = Timestamp::new(100, 0);
+        apply(
+            &mut state,
+            1,
+            4,
+            Mutation::CommitAt { txn: t1.clone(), writes: vec![set], timestamp },
+        )?;
+
+        let timestamp = Timestamp::new(50, 0);
+        apply(&mut state, 1, 5, Mutation::CommitAt { txn: t2.clone(), writes: vec![], timestamp })?;
+
+        let write = Mutation::Create { txn: t3.clone(), table: "missing".to_string(), row: vec![] };
+        let timestamp = Timestamp::new(200, 0);
+        let commit = Mutation::CommitAt { txn: t3.clone(), writes: vec![write], timestamp };
+        assert!(apply(&mut state, 1, 6, commit).is_err());
+
+        let mvcc = &state.engine.kv;
+        assert_eq!(mvcc.commit_timestamp(t1.version)?, Some(Timestamp::new(100, 0)));
+        assert_eq!(mvcc.commit_timestamp(t2.version)?, Some(Timestamp::new(100, 1)));
+        assert_eq!(mvcc.commit_timestamp(t3.version)?, None);
+        assert!(state.engine.resume(t3).is_err()); // rolled back
+
+        let txn = state.engine.begin_read_only()?;
+        assert_eq!(txn.kv_get(&namespace, &[1])?, Some(vec![1]));
+        Ok(())
+    }
+
+    #[test]
+    // Prepare applies the writes and prepares the transaction, or rolls back if a write fails.
+    // A prepared transaction can then be committed by its global identifier.
+    fn prepare() -> Result<()> {
+        let mut state = Raft::new_state(Memory::new())?;
+        let begin = Mutation::Begin { read_only: false, as_of: None };
+        let namespace = "ns".to_string();
+        let scan_prepared = |state: &State<Memory>| -> Result<Vec<(String, u64)>> {
+            bincode::deserialize(&state.query(bincode::serialize(&Query::ScanPrepared)?)?)
+        };
+
+        let t1: TransactionState = bincode::deserialize(&apply(&mut state, 1, 1, begin.clone())?)?;
+        let t2: TransactionState = bincode::deserialize(&apply(&mut state, 1, 2, begin)?)?;
+
+        let set = Mutation::KVSet {
+            txn: t1.clone(),
+            namespace: namespace.clone(),
+            key: vec![1],
+            value: vec![1],
+        };
+        let gid = "a".to_string();
+        apply(&mut state, 1, 3, Mutation::Prepare { txn: t1.clone(), writes: vec![set], gid })?;
+
+        let write = Mutation::Create { txn: t2.clone(), table: "missing".to_string(), row: vec![] };
+        let gid = "b".to_string();
+        let prepare = Mutation::Prepare { txn: t2.clone(), writes: vec![write], gid };
+        assert!(apply(&mut state, 1, 4, prepare).is_err());
+        assert!(state.engine.resume(t2).is_err()); // rolled back
+        assert_eq!(scan_prepared(&state)?, vec![("a".to_string(), t1.version)]);
+
+        let timestamp = Timestamp::new(100, 0);
+        let commit = Mutation::CommitPrepared { gid: "a".to_string(), timestamp };
+        assert_eq!(apply(&mut state, 1, 5, commit)?, bincode::serialize(&t1.version)?);
+        assert_eq!(state.engine.kv.commit_timestamp(t1.version)?, Some(timestamp));
+        assert_eq!(scan_prepared(&state)?, Vec::new());
+
+        let txn = state.engine.begin_read_only()?;
+        assert_eq!(txn.kv_get(&namespace, &[1])?, Some(vec![1]));
+        Ok(())
+    }
+
+    #[test]
+    // Heartbeats record each node's latest timestamp, and delayed heartbeats are ignored.
+    fn heartbeat() -> Result<()> {
+        let mut state = Raft::new_state(Memory::new())?;
+        let heartbeat =
+            |node, physical| Mutation::Heartbeat { node, timestamp: Timestamp::new(physical, 0) };
+        apply(&mut state, 1, 1, heartbeat(1, 100))?;
+        apply(&mut state, 1, 2, heartbeat(2, 150))?;
+        apply(&mut state, 1, 3, heartbeat(1, 200))?;
+        apply(&mut state, 1, 4, heartbeat(2, 120))?;
+
+        let heartbeats: Vec<(raft::NodeID, Timestamp)> =
+            bincode::deserialize(&state.query(bincode::serialize(&Query::Heartbeats)?)?)?;
+        assert_eq!(heartbeats, vec![(1, Timestamp::new(200, 0)), (2, Timestamp::new(150, 0))]);
+        Ok(())
+    }
+
+    #[test]
+    // A standby applies the primary's log entries once and in order, skips its heartbeats, and
+    // only allows read-only transactions until promoted, which rolls back in-flight ones but
+    // keeps prepared ones.
+    fn replicate() -> Result<()> {
+        let mut primary = Raft::new_state(Memory::new())?;
+        let mut standby = Raft::new_state(Memory::new())?;
+        let begin = Mutation::Begin { read_only: false, as_of: None };
+        let namespace = "ns".to_string();
+        let set = |txn: &TransactionState, key| Mutation::KVSet {
+            txn: txn.clone(),
+            namespace: namespace.clone(),
+            key: vec![key],
+            value: vec![key],
+        };
+        let query = |state: &State<Memory>, query: Query| -> Result<Vec<u8>> {
+            state.query(bincode::serialize(&query)?)
+        };
+
+        // Commit a write to key 1 on the primary, leave a write to key 2 in flight, and
+        // prepare a write to key 3, recording the primary's log entries.
+        let mut entries = Vec::new();
+        let mut propose = |mutation: Option<Mutation>| -> Result<Vec<u8>> {
+            let index = primary.get_applied_index() + 1;
+            let command = match mutation {
+                Some(mutation) => Some(bincode::serialize(&Mutation::Request {
+                    session: 1,
+                    seq: index,
+                    command: bincode::serialize(&mutation)?,
+                })?),
+                None => None,
+            };
+            let entry = Entry { index, term: 1, command };
+            entries.push(entry.clone());
+            primary.apply(entry)
+        };
+        let t1: TransactionState = bincode::deserialize(&propose(Some(begin.clone()))?)?;
+        propose(Some(set(&t1, 1)))?;
+        propose(Some(Mutation::Commit(t1)))?;
+        propose(None)?;
+        propose(Some(Mutation::Heartbeat { node: 1, timestamp: Timestamp::new(100, 0) }))?;
+        let t2: TransactionState = bincode::deserialize(&propose(Some(begin.clone()))?)?;
+        propose(Some(set(&t2, 2)))?;
+        let t4: TransactionState = bincode::deserialize(&propose(Some(begin.clone()))?)?;
+        let writes = vec![set(&t4, 3)];
+        propose(Some(Mutation::Prepare { txn: t4.clone(), writes, gid: "a".to_string() }))?;
+
+        // Entries are applied once, and gaps are rejected.
+        let replicate = |entries: &[Entry]| Mutation::Replicate { entries: entries.to_vec() };
+        assert_eq!(
+            apply(&mut standby, 2, 1, replicate(&entries[..3]))?,
+            bincode::serialize(&3_u64)?
+        );
+        assert_eq!(
+            apply(&mut standby, 2, 2, replicate(&entries[1..]))?,
+            bincode::serialize(&9_u64)?
+        );
+        let gap = Entry { index: 11, term: 1, command: None };
+        assert!(apply(&mut standby, 2, 3, Mutation::Replicate { entries: vec![gap] }).is_err());
+        assert_eq!(
+            query(&standby, Query::Standby)?,
+            bincode::serialize(&Some(Standby::Following(9)))?
+        );
+
+        // The standby sees the primary's writes and transactions, but not its heartbeats, and
+        // only allows read-only transactions.
+        let txn = standby.engine.begin_read_only()?;
+        assert_eq!(txn.kv_get(&namespace, &[1])?, Some(vec![1]));
+        assert_eq!(txn.kv_get(&namespace, &[2])?, None);
+        assert_eq!(standby.engine.kv.scan_active()?, HashSet::from([t2.version, t4.version]));
+        assert_eq!(query(&standby, Query::Heartbeats)?, bincode::serialize(&Vec::<()>::new())?);
+        assert_eq!(apply(&mut standby, 2, 4, begin.clone()), Err(Error::ReadOnly));
+
+        // Promotion rolls back the in-flight transaction, allows writes, and stops replication.
+        assert_eq!(apply(&mut standby, 2, 5, Mutation::Promote)?, bincode::serialize(&9_u64)?);
+        assert_eq!(standby.engine.kv.scan_active()?, HashSet::from([t4.version]));
+        let t3: TransactionState = bincode::deserialize(&apply(&mut standby, 2, 6, begin)?)?;
+        apply(&mut standby, 2, 7, set(&t3, 2))?;
+        apply(&mut standby, 2, 8, Mutation::Commit(t3))?;
+        assert!(apply(&mut standby, 2, 9, replicate(&entries)).is_err());
+        assert!(apply(&mut standby, 2, 10, Mutation::Promote).is_err());
+        assert_eq!(
+            query(&standby, Query::Standby)?,
+            bincode::serialize(&Some(Standby::Promoted(9)))?
+        );
+
+        // The prepared transaction can be committed on the promoted standby.
+        let timestamp = Timestamp::new(200, 0);
+        apply(&mut standby, 2, 11, Mutation::CommitPrepared { gid: "a".to_string(), timestamp })?;
+        let txn = standby.engine.begin_read_only()?;
+        assert_eq!(txn.kv_get(&namespace, &[3])?, Some(vec![3]));
+        Ok(())
+    }
 }
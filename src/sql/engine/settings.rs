@@ -0,0 +1,181 @@
+use super::super::types::{DataType, Value};
+use super::Limits;
+use crate::error::{Error, Result};
+use crate::ratelimit::RateLimits;
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// A cluster setting, i.e. a cluster-wide configuration knob that can be changed at runtime via
+/// SET CLUSTER SETTING. Settings are stored in the catalog, and thus replicated like other
+/// transactional writes. Servers periodically read them from their local state, such that
+/// changes take effect on all nodes without restarts. Unset settings use the node's configured
+/// value, or the setting's default.
+pub struct Setting {
+    /// The setting name
+    pub name: &'static str,
+    /// The setting's datatype
+    pub datatype: DataType,
+    /// A description of the setting
+    pub description: &'static str,
+}
+
+/// The known cluster settings, ordered by name.
+pub const SETTINGS: &[Setting] = &[
+    Setting {
+        name: "drop_retention",
+        datatype: DataType::Integer,
+        description: "Seconds to retain dropped tables for before purging them",
+    },
+    Setting {
+        name: "max_index_entry_size",
+        datatype: DataType::Integer,
+        description: "Maximum size of an encoded secondary index value in bytes, or 0 for no limit",
+    },
+    Setting {
+        name: "max_key_size",
+        datatype: DataType::Integer,
+        description: "Maximum size of an encoded primary key value in bytes, or 0 for no limit",
+    },
+    Setting {
+        name: "max_row_size",
+        datatype: DataType::Integer,
+        description: "Maximum size of an encoded row in bytes, or 0 for no limit",
+    },
+    Setting {
+        name: "rate_limit_client_qps",
+        datatype: DataType::Integer,
+        description: "Maximum queries per second per client host, or 0 for no limit",
+    },
+    Setting {
+        name: "rate_limit_client_rows",
+        datatype: DataType::Integer,
+        description: "Maximum rows written per second per client host, or 0 for no limit",
+    },
+    Setting {
+        name: "rate_limit_session_qps",
+        datatype: DataType::Integer,
+        description: "Maximum queries per second per session, or 0 for no limit",
+    },
+    Setting {
+        name: "rate_limit_session_rows",
+        datatype: DataType::Integer,
+        description: "Maximum rows written per second per session, or 0 for no limit",
+    },
+    Setting {
+        name: "ttl_expiry",
+        datatype: DataType::Boolean,
+        description: "Whether to delete expired rows of tables with a TTL in the background",
+    },
+];
+
+impl Setting {
+    /// Looks up a setting by name, erroring if it does not exist.
+    pub fn get(name: &str) -> Result<&'static Setting> {
+        SETTINGS
+            .iter()
+            .find(|s| s.name == name)
+            .ok_or_else(|| Error::Value(format!("Unknown cluster setting {}", name)))
+    }
+
+    /// Validates a value for the setting.
+    pub fn validate(&self, value: &Value) -> Result<()> {
+        match (&self.datatype, value) {
+            (DataType::Integer, Value::Integer(i)) if *i >= 0 => Ok(()),
+            (DataType::Integer, _) => {
+                Err(Error::Value(format!("{} must be a non-negative integer", self.name)))
+            }
+            (DataType::Boolean, Value::Boolean(_)) => Ok(()),
+            (DataType::Boolean, _) => Err(Error::Value(format!("{} must be a boolean", self.name))),
+            (datatype, _) => {
+                Err(Error::Internal(format!("Unsupported setting datatype {}", datatype)))
+            }
+        }
+    }
+}
+
+/// The cluster settings that have been set, by name.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings(BTreeMap<String, Value>);
+
+impl Settings {
+    /// Creates a new set of cluster settings.
+    pub fn new(settings: impl IntoIterator<Item = (String, Value)>) -> Self {
+        Self(settings.into_iter().collect())
+    }
+
+    /// Returns an integer setting, if set.
+    fn integer(&self, name: &str) -> Option<u64> {
+        match self.0.get(name) {
+            Some(Value::Integer(i)) => Some(*i as u64),
+            _ => None,
+        }
+    }
+
+    /// Returns the time to retain dropped tables for, or the given default.
+    pub fn drop_retention(&self, default: Duration) -> Duration {
+        self.integer("drop_retention").map_or(default, Duration::from_secs)
+    }
+
+    /// Returns the size limits for rows, using the given defaults for unset limits.
+    pub fn limits(&self, default: Limits) -> Limits {
+        Limits {
+            max_row_size: self.integer("max_row_size").unwrap_or(default.max_row_size),
+            max_key_size: self.integer("max_key_size").unwrap_or(default.max_key_size),
+            max_index_entry_size: self
+                .integer("max_index_entry_size")
+                .unwrap_or(default.max_index_entry_size),
+        }
+    }
+
+    /// Returns the client rate limits, using the given defaults for unset limits.
+    pub fn rate_limits(&self, default: RateLimits) -> RateLimits {
+        RateLimits {
+            session_qps: self.integer("rate_limit_session_qps").unwrap_or(default.session_qps),
+            session_rows: self.integer("rate_limit_session_rows").unwrap_or(default.session_rows),
+            client_qps: self.integer("rate_limit_client_qps").unwrap_or(default.client_qps),
+            client_rows: self.integer("rate_limit_client_rows").unwrap_or(default.client_rows),
+        }
+    }
+
+    /// Returns whether expired rows are deleted in the background. Defaults to true.
+    pub fn ttl_expiry(&self) -> bool {
+        !matches!(self.0.get("ttl_expiry"), Some(Value::Boolean(false)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // Settings are validated against their datatype, and unset settings use
+    // the given defaults.
+    fn settings() -> Result<()> {
+        assert!(Setting::get("unknown").is_err());
+        let setting = Setting::get("max_row_size")?;
+        assert!(setting.validate(&Value::Integer(0)).is_ok());
+        assert!(setting.validate(&Value::Integer(-1)).is_err());
+        assert!(setting.validate(&Value::Boolean(true)).is_err());
+        assert!(Setting::get("ttl_expiry")?.validate(&Value::Integer(1)).is_err());
+
+        let defaults = Limits::default();
+        let settings = Settings::default();
+        assert_eq!(settings.limits(defaults), defaults);
+        assert_eq!(settings.drop_retention(Duration::from_secs(7)), Duration::from_secs(7));
+        assert!(settings.ttl_expiry());
+
+        let settings = Settings::new([
+            ("drop_retention".to_string(), Value::Integer(60)),
+            ("max_key_size".to_string(), Value::Integer(10)),
+            ("rate_limit_session_qps".to_string(), Value::Integer(0)),
+            ("ttl_expiry".to_string(), Value::Boolean(false)),
+        ]);
+        assert_eq!(settings.limits(defaults), Limits { max_key_size: 10, ..defaults });
+        let rate_limits = RateLimits { session_qps: 5, client_rows: 7, ..Default::default() };
+        assert_eq!(settings.rate_limits(rate_limits), RateLimits { session_qps: 0, ..rate_limits });
+        assert_eq!(settings.drop_retention(Duration::from_secs(7)), Duration::from_secs(60));
+        assert!(!settings.ttl_expiry());
+        Ok(())
+    }
+}
@@ -1,60 +1,114 @@
 use super::super::engine::Transaction;
 use super::super::plan::Aggregate;
-use super::super::types::{Column, Value};
-use super::{Executor, ResultSet};
+use super::super::types::{Column, Columns, Value};
+use super::{batch, Batch, Batches, Memory, QueryExecutor, Reservation};
 use crate::error::{Error, Result};
 
+use std::any::Any;
 use std::cmp::Ordering;
 use std::collections::HashMap;
 
 /// An aggregation executor
 pub struct Aggregation<T: Transaction> {
-    source: Box<dyn Executor<T>>,
+    source: Box<dyn QueryExecutor<T>>,
     aggregates: Vec<Aggregate>,
-    accumulators: HashMap<Vec<Value>, Vec<Box<dyn Accumulator>>>,
+    memory: Memory,
 }
 
 impl<T: Transaction> Aggregation<T> {
-    pub fn new(source: Box<dyn Executor<T>>, aggregates: Vec<Aggregate>) -> Box<Self> {
-        Box::new(Self { source, aggregates, accumulators: HashMap::new() })
+    pub fn new(
+        source: Box<dyn QueryExecutor<T>>,
+        aggregates: Vec<Aggregate>,
+        memory: Memory,
+    ) -> Box<Self> {
+        Box::new(Self { source, aggregates, memory })
     }
 }
 
-impl<T: Transaction> Executor<T> for Aggregation<T> {
-    #[allow(clippy::or_fun_call)]
-    fn execute(mut self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+impl<T: Transaction> QueryExecutor<T> for Aggregation<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let (columns, mut batches) = self.source.execute(txn)?;
+        let mut groups = Groups::new(self.aggregates, &self.memory);
+        while let Some(batch) = batches.next().transpose()? {
+            groups.accumulate(batch)?;
+        }
+        Ok(groups.finish(columns))
+    }
+}
+
+/// Aggregate accumulators grouped by the GROUP BY values, which follow the aggregate values in
+/// each input row. Partial groups accumulated separately can be merged.
+pub struct Groups {
+    aggregates: Vec<Aggregate>,
+    accumulators: HashMap<Vec<Value>, Vec<Box<dyn Accumulator>>>,
+    /// The memory used by the groups.
+    reservation: Reservation,
+}
+
+impl Groups {
+    pub fn new(aggregates: Vec<Aggregate>, memory: &Memory) -> Self {
+        Self { aggregates, accumulators: HashMap::new(), reservation: memory.reserve() }
+    }
+
+    /// Estimates the memory used by a group, i.e. its values and accumulators.
+    fn group_size(&self, bucket: &[Value]) -> u64 {
+        super::memory::row_size(bucket)
+            + (self.aggregates.len() * std::mem::size_of::<Value>() * 2) as u64
+    }
+
+    /// Accumulates a batch of rows.
+    pub fn accumulate(&mut self, batch: Batch) -> Result<()> {
         let agg_count = self.aggregates.len();
-        match self.source.execute(txn)? {
-            ResultSet::Query { columns, mut rows } => {
-                while let Some(mut row) = rows.next().transpose()? {
-                    self.accumulators
-                        .entry(row.split_off(self.aggregates.len()))
-                        .or_insert(self.aggregates.iter().map(<dyn Accumulator>::from).collect())
-                        .iter_mut()
-                        .zip(row)
-                        .try_for_each(|(acc, value)| acc.accumulate(&value))?
+        for mut row in batch {
+            let bucket = row.split_off(agg_count);
+            if !self.accumulators.contains_key(&bucket) {
+                self.reservation.grow(self.group_size(&bucket))?;
+            }
+            self.accumulators
+                .entry(bucket)
+                .or_insert_with(|| self.aggregates.iter().map(<dyn Accumulator>::from).collect())
+                .iter_mut()
+                .zip(row)
+                .try_for_each(|(acc, value)| acc.accumulate(&value))?
+        }
+        Ok(())
+    }
+
+    /// Merges another set of partial groups for the same aggregates into this one.
+    pub fn merge(&mut self, other: Groups) -> Result<()> {
+        for (bucket, others) in other.accumulators {
+            match self.accumulators.get_mut(&bucket) {
+                Some(accs) => {
+                    accs.iter_mut().zip(others).try_for_each(|(acc, other)| acc.merge(&*other))?
                 }
-                // If there were no rows and no group-by columns, return a row of empty accumulators:
-                // SELECT COUNT(*) FROM t WHERE FALSE
-                if self.accumulators.is_empty() && self.aggregates.len() == columns.len() {
-                    self.accumulators.insert(
-                        Vec::new(),
-                        self.aggregates.iter().map(<dyn Accumulator>::from).collect(),
-                    );
+                None => {
+                    self.reservation.grow(self.group_size(&bucket))?;
+                    self.accumulators.insert(bucket, others);
                 }
-                Ok(ResultSet::Query {
-                    columns: columns
-                        .into_iter()
-                        .enumerate()
-                        .map(|(i, c)| if i < agg_count { Column { name: None } } else { c })
-                        .collect(),
-                    rows: Box::new(self.accumulators.into_iter().map(|(bucket, accs)| {
-                        Ok(accs.into_iter().map(|acc| acc.aggregate()).chain(bucket).collect())
-                    })),
-                })
             }
-            r => Err(Error::Internal(format!("Unexpected result {:?}", r))),
         }
+        Ok(())
+    }
+
+    /// Returns the final aggregates, given the input columns.
+    pub fn finish(mut self, columns: Columns) -> (Columns, Batches) {
+        let agg_count = self.aggregates.len();
+        // If there were no rows and no group-by columns, return a row of empty accumulators:
+        // SELECT COUNT(*) FROM t WHERE FALSE
+        if self.accumulators.is_empty() && agg_count == columns.len() {
+            self.accumulators
+                .insert(Vec::new(), self.aggregates.iter().map(<dyn Accumulator>::from).collect());
+        }
+        (
+            columns
+                .into_iter()
+                .enumerate()
+                .map(|(i, c)| if i < agg_count { Column { name: None } } else { c })
+                .collect(),
+            self.reservation.hold(batch(self.accumulators.into_iter().map(|(bucket, accs)| {
+                Ok(accs.into_iter().map(|acc| acc.aggregate()).chain(bucket).collect())
+            }))),
+        )
     }
 }
 
@@ -65,9 +119,15 @@ pub trait Accumulator: std::fmt::Debug + Send {
 
     // Calculates a final aggregate
     fn aggregate(&self) -> Value;
+
+    // Merges a partial accumulator of the same kind into this one
+    fn merge(&mut self, other: &dyn Accumulator) -> Result<()>;
+
+    // Returns the accumulator as Any, for downcasting
+    fn as_any(&self) -> &dyn Any;
 }
 
-impl dyn Accumulator {
+impl dyn Accumulator + '_ {
     fn from(aggregate: &Aggregate) -> Box<dyn Accumulator> {
         match aggregate {
             Aggregate::Average => Box::new(Average::new()),
@@ -77,6 +137,13 @@ impl dyn Accumulator {
             Aggregate::Sum => Box::new(Sum::new()),
         }
     }
+
+    // Downcasts the accumulator to a concrete type
+    fn downcast<A: Accumulator + 'static>(&self) -> Result<&A> {
+        self.as_any()
+            .downcast_ref()
+            .ok_or_else(|| Error::Internal(format!("Can't merge accumulator {:?}", self)))
+    }
 }
 
 // Count non-null values
@@ -103,6 +170,15 @@ impl Accumulator for Count {
     fn aggregate(&self) -> Value {
         Value::Integer(self.count as i64)
     }
+
+    fn merge(&mut self, other: &dyn Accumulator) -> Result<()> {
+        self.count += other.downcast::<Self>()?.count;
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
 }
 
 // Average value
@@ -132,6 +208,16 @@ impl Accumulator for Average {
             _ => Value::Null,
         }
     }
+
+    fn merge(&mut self, other: &dyn Accumulator) -> Result<()> {
+        let other = other.downcast::<Self>()?;
+        self.count.merge(&other.count)?;
+        self.sum.merge(&other.sum)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
 }
 
 // Maximum value
@@ -167,6 +253,17 @@ impl Accumulator for Max {
             None => Value::Null,
         }
     }
+
+    fn merge(&mut self, other: &dyn Accumulator) -> Result<()> {
+        match &other.downcast::<Self>()?.max {
+            Some(max) => self.accumulate(max),
+            None => Ok(()),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
 }
 
 // Minimum value
@@ -202,6 +299,17 @@ impl Accumulator for Min {
             None => Value::Null,
         }
     }
+
+    fn merge(&mut self, other: &dyn Accumulator) -> Result<()> {
+        match &other.downcast::<Self>()?.min {
+            Some(min) => self.accumulate(min),
+            None => Ok(()),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
 }
 
 // Sum of values
@@ -234,4 +342,15 @@ impl Accumulator for Sum {
             None => Value::Null,
         }
     }
+
+    fn merge(&mut self, other: &dyn Accumulator) -> Result<()> {
+        match &other.downcast::<Self>()?.sum {
+            Some(sum) => self.accumulate(sum),
+            None => Ok(()),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
 }
@@ -1,120 +1,54 @@
 use super::super::engine::Transaction;
-use super::super::types::{Expression, Rows};
-use super::{Executor, ResultSet, Row, Value};
+use super::super::types::{Column, Columns, Expression};
+use super::memory::row_size;
+use super::{batch, unbatch, Batch, Batches, Memory, QueryExecutor, Row, Value};
 use crate::error::{Error, Result};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// A nested loop join executor, which checks each row in the left source against every row in
-/// the right source using the given predicate.
+/// the right source using the given predicate. Full joins also return the unmatched right rows,
+/// with NULLs for the left columns, once all left rows have been joined.
 pub struct NestedLoopJoin<T: Transaction> {
-    left: Box<dyn Executor<T>>,
-    right: Box<dyn Executor<T>>,
+    left: Box<dyn QueryExecutor<T>>,
+    right: Box<dyn QueryExecutor<T>>,
     predicate: Option<Expression>,
     outer: bool,
+    full: bool,
+    memory: Memory,
 }
 
 impl<T: Transaction> NestedLoopJoin<T> {
     pub fn new(
-        left: Box<dyn Executor<T>>,
-        right: Box<dyn Executor<T>>,
+        left: Box<dyn QueryExecutor<T>>,
+        right: Box<dyn QueryExecutor<T>>,
         predicate: Option<Expression>,
         outer: bool,
+        full: bool,
+        memory: Memory,
     ) -> Box<Self> {
-        Box::new(Self { left, right, predicate, outer })
+        Box::new(Self { left, right, predicate, outer, full, memory })
     }
-}
-
-impl<T: Transaction> Executor<T> for NestedLoopJoin<T> {
-    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
-        if let ResultSet::Query { mut columns, rows } = self.left.execute(txn)? {
-            if let ResultSet::Query { columns: rcolumns, rows: rrows } = self.right.execute(txn)? {
-                let right_width = rcolumns.len();
-                columns.extend(rcolumns);
-                // FIXME Since making the iterators or sources clonable is non-trivial (requiring
-                // either avoiding Rust standard iterators or making sources generic), we simply
-                // fetch the entire right result as a vector.
-                return Ok(ResultSet::Query {
-                    rows: Box::new(NestedLoopRows::new(
-                        rows,
-                        rrows.collect::<Result<Vec<_>>>()?,
-                        right_width,
-                        self.predicate,
-                        self.outer,
-                    )),
-                    columns,
-                });
-            }
-        }
-        Err(Error::Internal("Unexpected result set".into()))
-    }
-}
-
-struct NestedLoopRows {
-    left: Rows,
-    left_row: Option<Result<Row>>,
-    right: Box<dyn Iterator<Item = Row> + Send>,
-    right_vec: Vec<Row>,
-    right_empty: Vec<Value>,
-    right_hit: bool,
-    predicate: Option<Expression>,
-    outer: bool,
-}
 
-impl NestedLoopRows {
-    fn new(
-        mut left: Rows,
-        right: Vec<Row>,
-        right_width: usize,
-        predicate: Option<Expression>,
+    /// Joins a left row with the matching right rows, or with a row of NULLs for outer joins
+    /// if there are no matches. Matching right rows are flagged in matched, if given.
+    fn join(
+        left_row: Row,
+        right: &[Row],
+        right_empty: &[Value],
+        predicate: Option<&Expression>,
         outer: bool,
-    ) -> Self {
-        Self {
-            left_row: left.next(),
-            left,
-            right: Box::new(right.clone().into_iter()),
-            right_vec: right,
-            right_empty: std::iter::repeat(Value::Null).take(right_width).collect(),
-            right_hit: false,
-            predicate,
-            outer,
-        }
-    }
-
-    // Tries to get the next joined row, with error handling.
-    fn try_next(&mut self) -> Result<Option<Row>> {
-        // While there is a valid left row, look for a right-hand match to return.
-        while let Some(Ok(left_row)) = self.left_row.clone() {
-            // If there is a hit in the remaining right rows, return it.
-            if let Some(row) = self.try_next_hit(&left_row)? {
-                self.right_hit = true;
-                return Ok(Some(row));
-            }
-
-            // Otherwise, continue with the next left row and reset the right source.
-            self.left_row = self.left.next();
-            self.right = Box::new(self.right_vec.clone().into_iter());
-
-            // If this is an outer join, when we reach the end of the right items without a hit,
-            // we should return a row with nulls for the right fields.
-            if self.outer && !self.right_hit {
-                let mut row = left_row;
-                row.extend(self.right_empty.clone());
-                return Ok(Some(row));
-            }
-            self.right_hit = false;
-        }
-        self.left_row.clone().transpose()
-    }
-
-    /// Tries to find the next combined row that matches the predicate in the remaining right rows.
-    fn try_next_hit(&mut self, left_row: &[Value]) -> Result<Option<Row>> {
-        for right_row in &mut self.right {
-            let mut row = left_row.to_vec();
-            row.extend(right_row);
-            if let Some(predicate) = &self.predicate {
+        matched: &mut [bool],
+    ) -> Result<Vec<Row>> {
+        let mut rows = Vec::new();
+        for (i, right_row) in right.iter().enumerate() {
+            let count = rows.len();
+            let mut row = Vec::with_capacity(left_row.len() + right_row.len());
+            row.extend_from_slice(&left_row);
+            row.extend_from_slice(right_row);
+            if let Some(predicate) = predicate {
                 match predicate.evaluate(Some(&row))? {
-                    Value::Boolean(true) => return Ok(Some(row)),
+                    Value::Boolean(true) => rows.push(row),
                     Value::Boolean(false) => {}
                     Value::Null => {}
                     value => {
@@ -125,78 +59,325 @@ impl NestedLoopRows {
                     }
                 }
             } else {
-                return Ok(Some(row));
+                rows.push(row);
             }
+            if rows.len() > count {
+                if let Some(matched) = matched.get_mut(i) {
+                    *matched = true;
+                }
+            }
+        }
+        if outer && rows.is_empty() {
+            rows.push(left_row.into_iter().chain(right_empty.iter().cloned()).collect());
         }
-        Ok(None)
+        Ok(rows)
     }
 }
 
-impl Iterator for NestedLoopRows {
-    type Item = Result<Row>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        self.try_next().transpose()
+impl<T: Transaction> QueryExecutor<T> for NestedLoopJoin<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let (mut columns, left) = self.left.execute(txn)?;
+        let (rcolumns, right) = self.right.execute(txn)?;
+        let left_empty: Vec<Value> = vec![Value::Null; columns.len()];
+        let right_empty: Vec<Value> = vec![Value::Null; rcolumns.len()];
+        columns.extend(rcolumns);
+        // FIXME Since making the iterators or sources clonable is non-trivial (requiring
+        // either avoiding Rust standard iterators or making sources generic), we simply
+        // fetch the entire right result as a vector.
+        let right: Vec<Row> = right.collect::<Result<Vec<_>>>()?.into_iter().flatten().collect();
+        let mut reservation = self.memory.reserve();
+        reservation.grow(right.iter().map(|r| row_size(r)).sum())?;
+        let (predicate, outer) = (self.predicate, self.outer);
+        // Only full joins track the matched right rows, which are otherwise never unmatched.
+        let mut matched = vec![false; if self.full { right.len() } else { 0 }];
+        let mut finished = false;
+        // Joins can produce many rows for each left row, so the output is rebatched rather
+        // than joining entire left batches at once.
+        let mut left = unbatch(left);
+        let mut pending = Vec::new().into_iter();
+        let rows = std::iter::from_fn(move || loop {
+            if let Some(row) = pending.next() {
+                return Some(Ok(row));
+            }
+            match left.next() {
+                Some(Ok(l)) => {
+                    match Self::join(
+                        l,
+                        &right,
+                        &right_empty,
+                        predicate.as_ref(),
+                        outer,
+                        &mut matched,
+                    ) {
+                        Ok(rows) => pending = rows.into_iter(),
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+                Some(Err(err)) => return Some(Err(err)),
+                None if !finished => {
+                    finished = true;
+                    pending = (matched.iter().zip(&right))
+                        .filter(|(matched, _)| !**matched)
+                        .map(|(_, r)| left_empty.iter().chain(r).cloned().collect())
+                        .collect::<Vec<_>>()
+                        .into_iter();
+                }
+                None => return None,
+            }
+        });
+        Ok((columns, reservation.hold(batch(rows))))
     }
 }
 
-/// A hash join executor
+/// A hash join executor. Full joins also return the unmatched right rows, with NULLs for the left
+/// columns, once all left rows have been joined.
 pub struct HashJoin<T: Transaction> {
-    left: Box<dyn Executor<T>>,
+    left: Box<dyn QueryExecutor<T>>,
     left_field: usize,
-    right: Box<dyn Executor<T>>,
+    right: Box<dyn QueryExecutor<T>>,
     right_field: usize,
     outer: bool,
+    full: bool,
+    memory: Memory,
 }
 
 impl<T: Transaction> HashJoin<T> {
     pub fn new(
-        left: Box<dyn Executor<T>>,
+        left: Box<dyn QueryExecutor<T>>,
         left_field: usize,
-        right: Box<dyn Executor<T>>,
+        right: Box<dyn QueryExecutor<T>>,
         right_field: usize,
         outer: bool,
+        full: bool,
+        memory: Memory,
     ) -> Box<Self> {
-        Box::new(Self { left, left_field, right, right_field, outer })
+        Box::new(Self { left, left_field, right, right_field, outer, full, memory })
     }
 }
 
-impl<T: Transaction> Executor<T> for HashJoin<T> {
-    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
-        if let ResultSet::Query { mut columns, rows } = self.left.execute(txn)? {
-            if let ResultSet::Query { columns: rcolumns, rows: rrows } = self.right.execute(txn)? {
-                let (l, r, outer) = (self.left_field, self.right_field, self.outer);
-                let right: HashMap<Value, Row> = rrows
-                    .map(|res| match res {
-                        Ok(row) if row.len() <= r => {
-                            Err(Error::Internal(format!("Right index {} out of bounds", r)))
-                        }
-                        Ok(row) => Ok((row[r].clone(), row)),
-                        Err(err) => Err(err),
-                    })
-                    .collect::<Result<_>>()?;
-                let empty = std::iter::repeat(Value::Null).take(rcolumns.len());
-                columns.extend(rcolumns);
-                let rows = Box::new(rows.filter_map(move |res| match res {
-                    Ok(row) if row.len() <= l => {
-                        Some(Err(Error::Value(format!("Left index {} out of bounds", l))))
+impl<T: Transaction> QueryExecutor<T> for HashJoin<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let (mut columns, left) = self.left.execute(txn)?;
+        let (rcolumns, rbatches) = self.right.execute(txn)?;
+        let (l, r, outer, full) = (self.left_field, self.right_field, self.outer, self.full);
+        // NULL keys never match (not even other NULLs), so they're not indexed. Full joins keep
+        // them aside, since they're returned as unmatched rows.
+        let mut right: HashMap<Value, Vec<Row>> = HashMap::new();
+        let mut right_nulls: Vec<Row> = Vec::new();
+        let mut reservation = self.memory.reserve();
+        for batch in rbatches {
+            for row in batch? {
+                if row.len() <= r {
+                    return Err(Error::Internal(format!("Right index {} out of bounds", r)));
+                }
+                if row[r] != Value::Null || full {
+                    reservation.grow_row(&row)?;
+                }
+                if row[r] != Value::Null {
+                    right.entry(row[r].clone()).or_default().push(row);
+                } else if full {
+                    right_nulls.push(row);
+                }
+            }
+        }
+        let left_empty: Vec<Value> = vec![Value::Null; columns.len()];
+        let empty: Vec<Value> = vec![Value::Null; rcolumns.len()];
+        columns.extend(rcolumns);
+        let mut left = left;
+        let mut matched: HashSet<Value> = HashSet::new();
+        let mut unmatched: Option<Batches> = None;
+        let batches = std::iter::from_fn(move || {
+            if let Some(unmatched) = unmatched.as_mut() {
+                return unmatched.next();
+            }
+            let Some(next) = left.next() else {
+                if !full {
+                    return None;
+                }
+                // Once the left rows are exhausted, return the unmatched right rows.
+                let rows = std::mem::take(&mut right)
+                    .into_iter()
+                    .filter(|(key, _)| !matched.contains(key))
+                    .flat_map(|(_, rows)| rows)
+                    .chain(std::mem::take(&mut right_nulls))
+                    .map(|row| Ok(left_empty.iter().cloned().chain(row).collect()))
+                    .collect::<Vec<_>>();
+                return unmatched.insert(batch(rows.into_iter())).next();
+            };
+            let join = |batch: Batch| {
+                let mut rows = Vec::new();
+                for row in batch {
+                    if row.len() <= l {
+                        return Err(Error::Value(format!("Left index {} out of bounds", l)));
                     }
-                    Ok(mut row) => match right.get(&row[l]) {
-                        Some(hit) => {
-                            row.extend(hit.clone());
-                            Some(Ok(row))
-                        }
-                        None if outer => {
-                            row.extend(empty.clone());
-                            Some(Ok(row))
+                    match right.get(&row[l]) {
+                        Some(hits) => {
+                            if full && !matched.contains(&row[l]) {
+                                reservation.grow_row(std::slice::from_ref(&row[l]))?;
+                                matched.insert(row[l].clone());
+                            }
+                            rows.extend(
+                                hits.iter().map(|hit| row.iter().chain(hit).cloned().collect()),
+                            )
                         }
-                        None => None,
-                    },
-                    Err(err) => Some(Err(err)),
-                }));
-                return Ok(ResultSet::Query { columns, rows });
+                        None if outer => rows.push(row.into_iter().chain(empty.clone()).collect()),
+                        None => {}
+                    }
+                }
+                Ok(rows)
+            };
+            Some(next.and_then(join))
+        });
+        Ok((columns, Box::new(batches)))
+    }
+}
+
+/// A hash semi-join executor, which returns the left rows that have a right row whose leading
+/// columns equal the left row's key values, or for anti-joins, the left rows that don't. NULL keys
+/// never match. For null-aware anti-joins the last key has NOT IN semantics: a left row is only
+/// returned if there are no right rows matching its other keys, or if its last key is not NULL,
+/// not in the matching right rows, and none of them have a NULL last key.
+pub struct SemiJoin<T: Transaction> {
+    left: Box<dyn QueryExecutor<T>>,
+    left_keys: Vec<Expression>,
+    right: Box<dyn QueryExecutor<T>>,
+    anti: bool,
+    null_aware: bool,
+    memory: Memory,
+}
+
+impl<T: Transaction> SemiJoin<T> {
+    pub fn new(
+        left: Box<dyn QueryExecutor<T>>,
+        left_keys: Vec<Expression>,
+        right: Box<dyn QueryExecutor<T>>,
+        anti: bool,
+        null_aware: bool,
+        memory: Memory,
+    ) -> Box<Self> {
+        Box::new(Self { left, left_keys, right, anti, null_aware, memory })
+    }
+}
+
+impl<T: Transaction> QueryExecutor<T> for SemiJoin<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let (columns, left) = self.left.execute(txn)?;
+        let (_, rbatches) = self.right.execute(txn)?;
+        let (keys, anti, null_aware) = (self.left_keys, self.anti, self.null_aware);
+        if null_aware && keys.is_empty() {
+            return Err(Error::Internal("Null-aware semi-join without keys".into()));
+        }
+        // Hash the right rows by their keys. For null-aware joins, the last key is instead
+        // collected into a set of values for each group, noting whether any of them were NULL.
+        let mut right: HashMap<Vec<Value>, (HashSet<Value>, bool)> = HashMap::new();
+        let mut reservation = self.memory.reserve();
+        for batch in rbatches {
+            for mut row in batch? {
+                if row.len() < keys.len() {
+                    return Err(Error::Internal(format!("Right key {} out of bounds", row.len())));
+                }
+                row.truncate(keys.len());
+                let value = if null_aware { row.pop() } else { None };
+                if row.contains(&Value::Null) {
+                    continue;
+                }
+                reservation.grow(row_size(&row) + row_size(value.as_slice()))?;
+                let (values, null) = right.entry(row).or_default();
+                match value {
+                    Some(Value::Null) => *null = true,
+                    Some(value) => _ = values.insert(value),
+                    None => {}
+                }
             }
         }
-        Err(Error::Internal("Unexpected result set".into()))
+        let batches = left.map(move |batch| {
+            let mut rows = Vec::new();
+            for row in batch? {
+                let mut lkeys =
+                    keys.iter().map(|e| e.evaluate(Some(&row))).collect::<Result<Vec<_>>>()?;
+                let value = if null_aware { lkeys.pop() } else { None };
+                let hit = match lkeys.contains(&Value::Null) {
+                    true => None,
+                    false => right.get(&lkeys),
+                };
+                let keep = match (hit, value) {
+                    (Some((values, null)), Some(value)) => {
+                        value != Value::Null && !null && !values.contains(&value)
+                    }
+                    (None, Some(_)) => true,
+                    (hit, None) => hit.is_some() != anti,
+                };
+                if keep {
+                    rows.push(row)
+                }
+            }
+            Ok(rows)
+        });
+        Ok((columns, reservation.hold(Box::new(batches))))
+    }
+}
+
+/// A scalar subquery join executor, which appends the single value returned by the right source
+/// for each left row's keys, or the default value if there is none. The right rows must contain
+/// the keys followed by the value, and there can be at most one row per key.
+pub struct ScalarJoin<T: Transaction> {
+    left: Box<dyn QueryExecutor<T>>,
+    left_keys: Vec<Expression>,
+    right: Box<dyn QueryExecutor<T>>,
+    default: Value,
+    memory: Memory,
+}
+
+impl<T: Transaction> ScalarJoin<T> {
+    pub fn new(
+        left: Box<dyn QueryExecutor<T>>,
+        left_keys: Vec<Expression>,
+        right: Box<dyn QueryExecutor<T>>,
+        default: Value,
+        memory: Memory,
+    ) -> Box<Self> {
+        Box::new(Self { left, left_keys, right, default, memory })
+    }
+}
+
+impl<T: Transaction> QueryExecutor<T> for ScalarJoin<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let (mut columns, left) = self.left.execute(txn)?;
+        let (rcolumns, rbatches) = self.right.execute(txn)?;
+        let (keys, default) = (self.left_keys, self.default);
+        if rcolumns.len() != keys.len() + 1 {
+            return Err(Error::Value("Scalar subquery must return a single column".into()));
+        }
+        // Hash the right values by their keys, skipping NULL keys which can't match.
+        let mut right: HashMap<Vec<Value>, Value> = HashMap::new();
+        let mut reservation = self.memory.reserve();
+        for batch in rbatches {
+            for mut row in batch? {
+                reservation.grow_row(&row)?;
+                let value = row.pop().ok_or_else(|| Error::Internal("Empty right row".into()))?;
+                if row.contains(&Value::Null) {
+                    continue;
+                }
+                if right.insert(row, value).is_some() {
+                    return Err(Error::Value("Scalar subquery returned more than one row".into()));
+                }
+            }
+        }
+        columns.push(Column { name: None });
+        let batches = left.map(move |batch| {
+            let mut rows = Vec::new();
+            for mut row in batch? {
+                let lkeys =
+                    keys.iter().map(|e| e.evaluate(Some(&row))).collect::<Result<Vec<_>>>()?;
+                let value = match lkeys.contains(&Value::Null) {
+                    true => None,
+                    false => right.get(&lkeys),
+                };
+                row.push(value.cloned().unwrap_or_else(|| default.clone()));
+                rows.push(row);
+            }
+            Ok(rows)
+        });
+        Ok((columns, reservation.hold(Box::new(batches))))
     }
 }
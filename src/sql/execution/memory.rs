@@ -0,0 +1,192 @@
+use super::super::types::Value;
+use super::Batches;
+use crate::error::{Error, Result};
+
+use serde_derive::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The memory currently used by all statements in the process, in bytes.
+static USED: AtomicU64 = AtomicU64::new(0);
+/// The peak memory used by all statements in the process, in bytes.
+static PEAK: AtomicU64 = AtomicU64::new(0);
+/// The number of statements that exceeded their memory limit.
+static EXCEEDED: AtomicU64 = AtomicU64::new(0);
+
+/// Aggregate memory usage of statement executors in the process.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemoryMetrics {
+    /// The memory currently used by executing statements, in bytes.
+    pub used: u64,
+    /// The peak memory used by concurrently executing statements, in bytes.
+    pub peak: u64,
+    /// The number of statements that failed by exceeding their memory limit.
+    pub exceeded: u64,
+}
+
+/// Returns the aggregate memory usage of statement executors in the process.
+pub fn metrics() -> MemoryMetrics {
+    MemoryMetrics {
+        used: USED.load(Ordering::Relaxed),
+        peak: PEAK.load(Ordering::Relaxed),
+        exceeded: EXCEEDED.load(Ordering::Relaxed),
+    }
+}
+
+/// Estimates the memory used by a row or set of values, in bytes, including string contents.
+pub fn row_size(row: &[Value]) -> u64 {
+    let heap: usize = row
+        .iter()
+        .map(|v| match v {
+            Value::String(s) => s.len(),
+            _ => 0,
+        })
+        .sum();
+    (std::mem::size_of_val(row) + heap) as u64
+}
+
+/// Tracks the memory used by a statement's executors for rows they buffer, e.g. for sorts,
+/// aggregates, and joins, and enforces the statement's memory limit. Clones share the usage.
+/// Streamed rows and the storage engine's memory are not tracked.
+#[derive(Clone, Debug, Default)]
+pub struct Memory(Arc<Usage>);
+
+#[derive(Debug, Default)]
+struct Usage {
+    /// The memory limit in bytes, or 0 if unlimited.
+    limit: u64,
+    used: AtomicU64,
+    peak: AtomicU64,
+}
+
+impl Memory {
+    /// Creates a memory tracker with the given limit in bytes, or 0 for no limit.
+    pub fn new(limit: u64) -> Self {
+        Self(Arc::new(Usage { limit, ..Default::default() }))
+    }
+
+    /// Returns the memory currently used, in bytes.
+    pub fn used(&self) -> u64 {
+        self.0.used.load(Ordering::Relaxed)
+    }
+
+    /// Returns the peak memory used, in bytes.
+    pub fn peak(&self) -> u64 {
+        self.0.peak.load(Ordering::Relaxed)
+    }
+
+    /// Creates an empty reservation, which executors grow as they buffer rows.
+    pub fn reserve(&self) -> Reservation {
+        Reservation { memory: self.clone(), size: 0 }
+    }
+
+    /// Allocates memory, erroring if this exceeds the limit. The memory is allocated either
+    /// way, and must be freed by the caller.
+    fn allocate(&self, bytes: u64) -> Result<()> {
+        let used = self.0.used.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.0.peak.fetch_max(used, Ordering::Relaxed);
+        let total = USED.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        PEAK.fetch_max(total, Ordering::Relaxed);
+        if self.0.limit > 0 && used > self.0.limit {
+            EXCEEDED.fetch_add(1, Ordering::Relaxed);
+            return Err(Error::Value(format!(
+                "Statement exceeded the memory limit of {} bytes",
+                self.0.limit
+            )));
+        }
+        Ok(())
+    }
+
+    /// Frees allocated memory.
+    fn free(&self, bytes: u64) {
+        self.0.used.fetch_sub(bytes, Ordering::Relaxed);
+        USED.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Memory reserved by an executor, which is freed when the reservation is dropped.
+#[derive(Debug)]
+pub struct Reservation {
+    memory: Memory,
+    size: u64,
+}
+
+impl Reservation {
+    /// Grows the reservation, erroring if this exceeds the memory limit.
+    pub fn grow(&mut self, bytes: u64) -> Result<()> {
+        self.size += bytes;
+        self.memory.allocate(bytes)
+    }
+
+    /// Grows the reservation by the size of a row or set of values.
+    pub fn grow_row(&mut self, row: &[Value]) -> Result<()> {
+        self.grow(row_size(row))
+    }
+
+    /// Shrinks the reservation, e.g. when a buffered row is discarded.
+    pub fn shrink(&mut self, bytes: u64) {
+        let bytes = bytes.min(self.size);
+        self.size -= bytes;
+        self.memory.free(bytes)
+    }
+
+    /// Merges another reservation from the same tracker into this one, e.g. from a worker.
+    pub fn merge(&mut self, mut other: Reservation) {
+        self.size += std::mem::take(&mut other.size);
+    }
+
+    /// Holds the reservation until the given batches are dropped, e.g. for buffered rows that
+    /// they return.
+    pub fn hold(self, batches: Batches) -> Batches {
+        Box::new(batches.inspect(move |_| {
+            let _ = &self;
+        }))
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.memory.free(self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // Reservations track the used and peak memory, and free it when dropped. Exceeding the
+    // limit errors, but the memory is still reserved until the reservation is dropped.
+    fn reserve() -> Result<()> {
+        let memory = Memory::new(100);
+        let mut a = memory.reserve();
+        let mut b = memory.clone().reserve();
+        a.grow(40)?;
+        b.grow(50)?;
+        assert_eq!((memory.used(), memory.peak()), (90, 90));
+        a.shrink(30);
+        assert_eq!((memory.used(), memory.peak()), (60, 90));
+        drop(a);
+        assert_eq!((memory.used(), memory.peak()), (50, 90));
+        assert_eq!(
+            b.grow(51),
+            Err(Error::Value("Statement exceeded the memory limit of 100 bytes".into()))
+        );
+        assert_eq!((memory.used(), memory.peak()), (101, 101));
+        drop(b);
+        assert_eq!((memory.used(), memory.peak()), (0, 101));
+
+        // Unlimited trackers never error.
+        Memory::default().reserve().grow(u32::MAX as u64)?;
+        Ok(())
+    }
+
+    #[test]
+    // Row sizes include string contents.
+    fn row_size() {
+        let value = std::mem::size_of::<Value>() as u64;
+        assert_eq!(super::row_size(&[]), 0);
+        assert_eq!(super::row_size(&[Value::Integer(1), Value::Null]), 2 * value);
+        assert_eq!(super::row_size(&[Value::String("abc".into())]), value + 3);
+    }
+}
@@ -1,16 +1,26 @@
 mod aggregation;
 mod join;
+mod memory;
 mod mutation;
+mod parallel;
 mod query;
 mod schema;
 mod source;
 
 use aggregation::Aggregation;
-use join::{HashJoin, NestedLoopJoin};
+use join::{HashJoin, NestedLoopJoin, ScalarJoin, SemiJoin};
+pub use memory::{metrics, Memory, MemoryMetrics, Reservation};
 use mutation::{Delete, Insert, Update};
-use query::{Filter, Limit, Offset, Order, Projection};
-use schema::{CreateTable, DropTable};
-use source::{IndexLookup, KeyLookup, Nothing, Scan};
+use parallel::Parallel;
+use query::{Distinct, Filter, Limit, Offset, Order, Ordinality, Projection, TopK};
+use schema::{
+    CreateFunction, CreateIndex, CreateTable, CreateTableAs, CreateTrigger, DropFunction,
+    DropIndex, DropTable, DropTrigger, UndropTable,
+};
+use source::{
+    IndexLookup, IndexOnlyScan, IndexOrderScan, IndexRangeScan, KeyLookup, KeyRangeScan, Nothing,
+    SampleScan, Scan, TableFunction, Values,
+};
 
 use super::engine::Transaction;
 use super::plan::Node;
@@ -19,6 +29,17 @@ use crate::error::{Error, Result};
 
 use derivative::Derivative;
 use serde_derive::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The maximum number of rows in a batch passed between query executors. Batches start out with
+/// a single row and double in size up to this, see batch().
+pub const BATCH_SIZE: usize = 1024;
+
+/// A batch of rows
+pub type Batch = Vec<Row>;
+
+/// A batch iterator
+pub type Batches = Box<dyn Iterator<Item = Result<Batch>> + Send>;
 
 /// A plan executor
 pub trait Executor<T: Transaction> {
@@ -26,51 +47,205 @@ pub trait Executor<T: Transaction> {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet>;
 }
 
+/// A query plan executor, which passes rows to its parent in batches
+pub trait QueryExecutor<T: Transaction> {
+    /// Executes the executor, consuming it and returning the result columns and row batches
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)>;
+}
+
 impl<T: Transaction + 'static> dyn Executor<T> {
-    /// Builds an executor for a plan node, consuming it
-    pub fn build(node: Node) -> Box<dyn Executor<T>> {
-        match node {
-            Node::Aggregation { source, aggregates } => {
-                Aggregation::new(Self::build(*source), aggregates)
-            }
+    /// Builds an executor for a plan node, consuming it. Executors track the memory of rows
+    /// they buffer in the given tracker.
+    pub fn build(node: Node, memory: &Memory) -> Result<Box<dyn Executor<T>>> {
+        Ok(match node {
             Node::CreateTable { schema } => CreateTable::new(schema),
-            Node::Delete { table, source } => Delete::new(table, Self::build(*source)),
+            Node::CreateTableAs { table, source, datatypes } => CreateTableAs::new(
+                table,
+                <dyn QueryExecutor<T>>::build(*source, memory)?,
+                datatypes,
+                memory.clone(),
+            ),
+            Node::Delete { table, source } => {
+                Delete::new(table, <dyn QueryExecutor<T>>::build(*source, memory)?, memory.clone())
+            }
             Node::DropTable { table } => DropTable::new(table),
-            Node::Filter { source, predicate } => Filter::new(Self::build(*source), predicate),
-            Node::HashJoin { left, left_field, right, right_field, outer } => HashJoin::new(
-                Self::build(*left),
+            Node::CreateTrigger { trigger } => CreateTrigger::new(trigger),
+            Node::DropTrigger { table, name } => DropTrigger::new(table, name),
+            Node::CreateIndex { index } => CreateIndex::new(index),
+            Node::DropIndex { table, name } => DropIndex::new(table, name),
+            Node::CreateFunction { function } => CreateFunction::new(function),
+            Node::DropFunction { name } => DropFunction::new(name),
+            Node::UndropTable { table } => UndropTable::new(table),
+            Node::Insert { table, columns, expressions } => {
+                Insert::new(table, columns, expressions, memory.clone())
+            }
+            Node::Update { table, source, expressions } => Update::new(
+                table,
+                <dyn QueryExecutor<T>>::build(*source, memory)?,
+                expressions.into_iter().map(|(i, _, e)| (i, e)).collect(),
+                memory.clone(),
+            ),
+            node => Query::new(<dyn QueryExecutor<T>>::build(node, memory)?),
+        })
+    }
+}
+
+impl<T: Transaction + 'static> dyn QueryExecutor<T> {
+    /// Builds a query executor for a plan node, consuming it. Executors track the memory of
+    /// rows they buffer in the given tracker.
+    pub fn build(node: Node, memory: &Memory) -> Result<Box<dyn QueryExecutor<T>>> {
+        Ok(match node {
+            Node::Aggregation { source, aggregates } => {
+                Aggregation::new(Self::build(*source, memory)?, aggregates, memory.clone())
+            }
+            Node::Filter { source, predicate } => {
+                Filter::new(Self::build(*source, memory)?, predicate)
+            }
+            Node::HashJoin { left, left_field, right, right_field, outer, full } => HashJoin::new(
+                Self::build(*left, memory)?,
                 left_field.0,
-                Self::build(*right),
+                Self::build(*right, memory)?,
                 right_field.0,
                 outer,
+                full,
+                memory.clone(),
             ),
             Node::IndexLookup { table, alias: _, column, values } => {
                 IndexLookup::new(table, column, values)
             }
-            Node::Insert { table, columns, expressions } => {
-                Insert::new(table, columns, expressions)
+            Node::IndexOnlyScan { table, alias: _, column, ranges, reverse, limit } => {
+                IndexOnlyScan::new(table, column, ranges, reverse, limit)
+            }
+            Node::IndexRangeScan { table, alias: _, column, ranges, reverse, limit } => {
+                IndexRangeScan::new(table, column, ranges, reverse, limit)
             }
             Node::KeyLookup { table, alias: _, keys } => KeyLookup::new(table, keys),
-            Node::Limit { source, limit } => Limit::new(Self::build(*source), limit),
-            Node::NestedLoopJoin { left, left_size: _, right, predicate, outer } => {
-                NestedLoopJoin::new(Self::build(*left), Self::build(*right), predicate, outer)
+            Node::KeyRangeScan { table, alias: _, ranges, reverse } => {
+                KeyRangeScan::new(table, ranges, reverse)
+            }
+            Node::Limit { source, limit } => Limit::new(Self::build(*source, memory)?, limit),
+            Node::NestedLoopJoin { left, left_size: _, right, predicate, outer, full } => {
+                NestedLoopJoin::new(
+                    Self::build(*left, memory)?,
+                    Self::build(*right, memory)?,
+                    predicate,
+                    outer,
+                    full,
+                    memory.clone(),
+                )
             }
             Node::Nothing => Nothing::new(),
-            Node::Offset { source, offset } => Offset::new(Self::build(*source), offset),
-            Node::Order { source, orders } => Order::new(Self::build(*source), orders),
+            Node::Values { alias: _, columns, rows } => Values::new(columns, rows),
+            Node::TableFunction { source, function, args, alias: _, column } => {
+                TableFunction::new(Self::build(*source, memory)?, function, args, column)
+            }
+            Node::Offset { source, offset } => Offset::new(Self::build(*source, memory)?, offset),
+            Node::Order { source, orders } => {
+                Order::new(Self::build(*source, memory)?, orders, memory.clone())
+            }
+            Node::TopK { source, orders, limit } => {
+                TopK::new(Self::build(*source, memory)?, orders, limit, memory.clone())
+            }
+            Node::Distinct { source, on } => {
+                Distinct::new(Self::build(*source, memory)?, on, memory.clone())
+            }
+            Node::Ordinality { source } => Ordinality::new(Self::build(*source, memory)?),
+            Node::Parallel { source, workers } => Parallel::new(*source, workers, memory.clone())?,
             Node::Projection { source, expressions } => {
-                Projection::new(Self::build(*source), expressions)
+                Projection::new(Self::build(*source, memory)?, expressions)
             }
-            Node::Scan { table, filter, alias: _ } => Scan::new(table, filter),
-            Node::Update { table, source, expressions } => Update::new(
-                table,
-                Self::build(*source),
-                expressions.into_iter().map(|(i, _, e)| (i, e)).collect(),
+            Node::Scan { table, filter, columns, alias: _ } => Scan::new(table, filter, columns),
+            Node::IndexOrderScan { table, alias: _, index, reverse } => {
+                IndexOrderScan::new(table, index, reverse)
+            }
+            Node::SampleScan { table, alias: _, sample, filter } => {
+                SampleScan::new(table, sample, filter)
+            }
+            Node::SemiJoin { left, left_keys, right, anti, null_aware } => SemiJoin::new(
+                Self::build(*left, memory)?,
+                left_keys,
+                Self::build(*right, memory)?,
+                anti,
+                null_aware,
+                memory.clone(),
             ),
-        }
+            Node::ScalarJoin { left, left_keys, right, default } => ScalarJoin::new(
+                Self::build(*left, memory)?,
+                left_keys,
+                Self::build(*right, memory)?,
+                default,
+                memory.clone(),
+            ),
+            Node::CreateFunction { .. }
+            | Node::CreateTable { .. }
+            | Node::CreateTableAs { .. }
+            | Node::CreateTrigger { .. }
+            | Node::CreateIndex { .. }
+            | Node::Delete { .. }
+            | Node::DropFunction { .. }
+            | Node::UndropTable { .. }
+            | Node::DropTable { .. }
+            | Node::DropTrigger { .. }
+            | Node::DropIndex { .. }
+            | Node::Insert { .. }
+            | Node::Update { .. } => {
+                return Err(Error::Internal(format!("Unexpected query node {}", node)))
+            }
+        })
+    }
+}
+
+/// A query executor, which returns the rows of a query executor as a result set
+struct Query<T: Transaction> {
+    source: Box<dyn QueryExecutor<T>>,
+}
+
+impl<T: Transaction> Query<T> {
+    fn new(source: Box<dyn QueryExecutor<T>>) -> Box<Self> {
+        Box::new(Self { source })
     }
 }
 
+impl<T: Transaction> Executor<T> for Query<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let (columns, batches) = self.source.execute(txn)?;
+        Ok(ResultSet::Query { columns, key_column: None, rows: unbatch(batches) })
+    }
+}
+
+/// Groups rows into batches. The first batch contains a single row, and each
+/// following batch twice as many up to BATCH_SIZE, such that the first rows are
+/// returned without reading ahead, and a LIMIT reads at most about twice the
+/// rows it returns. An error is returned instead of the batch it occurred in.
+pub fn batch<I: Iterator<Item = Result<Row>> + Send + 'static>(mut rows: I) -> Batches {
+    let mut size = 1;
+    Box::new(std::iter::from_fn(move || {
+        let mut batch = Batch::with_capacity(rows.size_hint().0.clamp(1, size));
+        for row in rows.by_ref() {
+            match row {
+                Ok(row) => batch.push(row),
+                Err(err) => return Some(Err(err)),
+            }
+            if batch.len() >= size {
+                break;
+            }
+        }
+        size = (size * 2).min(BATCH_SIZE);
+        (!batch.is_empty()).then_some(Ok(batch))
+    }))
+}
+
+/// Flattens row batches into a row iterator.
+pub fn unbatch(batches: Batches) -> Rows {
+    Box::new(batches.flat_map(|batch| {
+        let (rows, error) = match batch {
+            Ok(rows) => (rows, None),
+            Err(error) => (Vec::new(), Some(Err(error))),
+        };
+        rows.into_iter().map(Ok).chain(error)
+    }))
+}
+
 /// An executor result set
 #[derive(Derivative, Serialize, Deserialize)]
 #[derivative(Debug, PartialEq)]
@@ -111,6 +286,9 @@ pub enum ResultSet {
     // Query result
     Query {
         columns: Columns,
+        // Index of the primary key column, if any, for keyset pagination. Not serialized.
+        #[serde(skip)]
+        key_column: Option<usize>,
         #[derivative(Debug = "ignore")]
         #[derivative(PartialEq = "ignore")]
         #[serde(skip, default = "ResultSet::empty_rows")]
@@ -118,6 +296,76 @@ pub enum ResultSet {
     },
     // Explain result
     Explain(Node),
+    // Explain analyze result, with the returned or affected rows, execution time, and peak
+    // memory use in bytes
+    ExplainAnalyze {
+        plan: Node,
+        rows: u64,
+        time: Duration,
+        memory: u64,
+    },
+    // Session variable set
+    Set {
+        variable: String,
+    },
+    // Trigger created
+    CreateTrigger {
+        name: String,
+    },
+    // Trigger dropped
+    DropTrigger {
+        name: String,
+    },
+    // Index created
+    CreateIndex {
+        name: String,
+    },
+    // Index dropped
+    DropIndex {
+        name: String,
+    },
+    // Function created
+    CreateFunction {
+        name: String,
+    },
+    // Function dropped
+    DropFunction {
+        name: String,
+    },
+    // Dropped table restored
+    UndropTable {
+        name: String,
+    },
+    // Cursor declared
+    DeclareCursor {
+        name: String,
+    },
+    // Cursor closed
+    CloseCursor {
+        name: String,
+    },
+    // Session killed, or its statement cancelled
+    Kill {
+        session: u64,
+        query: bool,
+    },
+    // Table created from a query, with the number of rows inserted
+    CreateTableAs {
+        name: String,
+        count: u64,
+    },
+    // Transaction prepared for a two-phase commit under the given global identifier
+    Prepare {
+        gid: String,
+        version: u64,
+    }, // Tenant created
+    CreateTenant {
+        name: String,
+    },
+    // Tenant dropped, along with its data
+    DropTenant {
+        name: String,
+    },
 }
 
 impl ResultSet {
@@ -139,4 +387,80 @@ impl ResultSet {
     pub fn into_value(self) -> Result<Value> {
         self.into_row()?.into_iter().next().ok_or_else(|| Error::Value("No value returned".into()))
     }
+
+    /// Returns the command tag of the result set. Query rows are streamed, so the number of
+    /// returned rows must be given by the caller once they have been consumed. It is ignored for
+    /// other result sets.
+    pub fn command_tag(&self, returned: u64) -> CommandTag {
+        let (command, rows) = match self {
+            ResultSet::Begin { .. } => ("BEGIN", None),
+            ResultSet::Commit { .. } => ("COMMIT", None),
+            ResultSet::Rollback { .. } => ("ROLLBACK", None),
+            ResultSet::Create { count } => ("INSERT", Some(*count)),
+            ResultSet::Delete { count } => ("DELETE", Some(*count)),
+            ResultSet::Update { count } => ("UPDATE", Some(*count)),
+            ResultSet::CreateTable { .. } => ("CREATE TABLE", None),
+            ResultSet::DropTable { .. } => ("DROP TABLE", None),
+            ResultSet::Query { .. } => ("SELECT", Some(returned)),
+            ResultSet::Explain(_) | ResultSet::ExplainAnalyze { .. } => ("EXPLAIN", None),
+            ResultSet::Set { .. } => ("SET", None),
+            ResultSet::CreateTrigger { .. } => ("CREATE TRIGGER", None),
+            ResultSet::DropTrigger { .. } => ("DROP TRIGGER", None),
+            ResultSet::CreateIndex { .. } => ("CREATE INDEX", None),
+            ResultSet::DropIndex { .. } => ("DROP INDEX", None),
+            ResultSet::CreateFunction { .. } => ("CREATE FUNCTION", None),
+            ResultSet::DropFunction { .. } => ("DROP FUNCTION", None),
+            ResultSet::UndropTable { .. } => ("UNDROP TABLE", None),
+            ResultSet::DeclareCursor { .. } => ("DECLARE CURSOR", None),
+            ResultSet::CloseCursor { .. } => ("CLOSE CURSOR", None),
+            ResultSet::Kill { .. } => ("KILL", None),
+            ResultSet::CreateTableAs { count, .. } => ("SELECT", Some(*count)),
+            ResultSet::Prepare { .. } => ("PREPARE TRANSACTION", None),
+            ResultSet::CreateTenant { .. } => ("CREATE TENANT", None),
+            ResultSet::DropTenant { .. } => ("DROP TENANT", None),
+        };
+        CommandTag { command: command.to_string(), rows }
+    }
+}
+
+/// A command tag, identifying the kind of an executed statement along with the number of rows
+/// it affected or returned, if any. Displayed as e.g. UPDATE 3 or CREATE TABLE.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CommandTag {
+    pub command: String,
+    pub rows: Option<u64>,
+}
+
+impl std::fmt::Display for CommandTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.rows {
+            Some(rows) => write!(f, "{} {}", self.command, rows),
+            None => write!(f, "{}", self.command),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    // Batches double in size up to BATCH_SIZE, and only read the rows they return.
+    fn batch() -> Result<()> {
+        let read = Arc::new(AtomicUsize::new(0));
+        let counter = read.clone();
+        let rows = (0..3000).map(move |i| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Ok(vec![Value::Integer(i)])
+        });
+        let mut batches = super::batch(rows);
+        assert_eq!(batches.next().transpose()?.map(|b| b.len()), Some(1));
+        assert_eq!(read.load(Ordering::Relaxed), 1);
+        let sizes: Vec<_> = batches.map(|b| b.map(|b| b.len())).collect::<Result<_>>()?;
+        assert_eq!(sizes, vec![2, 4, 8, 16, 32, 64, 128, 256, 512, 1024, 953]);
+        assert_eq!(read.load(Ordering::Relaxed), 3000);
+        Ok(())
+    }
 }
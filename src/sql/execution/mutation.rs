@@ -1,21 +1,39 @@
 use super::super::engine::Transaction;
-use super::super::schema::Table;
+use super::super::parser::{ast, Parser};
+use super::super::plan::Plan;
+use super::super::schema::{Table, Trigger, TriggerEvent, TriggerTiming};
 use super::super::types::{Expression, Row, Value};
-use super::{Executor, ResultSet};
+use super::{Executor, Memory, QueryExecutor, ResultSet};
 use crate::error::{Error, Result};
 
+use std::cell::Cell;
 use std::collections::{HashMap, HashSet};
 
+/// The maximum nesting depth of trigger statements, e.g. when a trigger writes to a table whose
+/// triggers write back to the first table.
+const MAX_TRIGGER_DEPTH: usize = 16;
+
+thread_local! {
+    /// The nesting depth of trigger statements currently executing on this thread.
+    static TRIGGER_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
 /// An INSERT executor
 pub struct Insert {
     table: String,
     columns: Vec<String>,
     rows: Vec<Vec<Expression>>,
+    memory: Memory,
 }
 
 impl Insert {
-    pub fn new(table: String, columns: Vec<String>, rows: Vec<Vec<Expression>>) -> Box<Self> {
-        Box::new(Self { table, columns, rows })
+    pub fn new(
+        table: String,
+        columns: Vec<String>,
+        rows: Vec<Vec<Expression>>,
+        memory: Memory,
+    ) -> Box<Self> {
+        Box::new(Self { table, columns, rows, memory })
     }
 
     // Builds a row from a set of column names and values, padding it with default values.
@@ -56,9 +74,11 @@ impl Insert {
     }
 }
 
-impl<T: Transaction> Executor<T> for Insert {
+impl<T: Transaction + 'static> Executor<T> for Insert {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         let table = txn.must_read_table(&self.table)?;
+        let triggers = Triggers::load(txn, &table, TriggerEvent::Insert, &self.memory)?;
+        let limits = txn.limits();
         let mut count = 0;
         for expressions in self.rows {
             let mut row =
@@ -68,7 +88,10 @@ impl<T: Transaction> Executor<T> for Insert {
             } else {
                 row = Self::make_row(&table, &self.columns, row)?;
             }
-            txn.create(&table.name, row)?;
+            limits.check(&table, &row)?;
+            triggers.fire(txn, &table, TriggerTiming::Before, None, Some(&row))?;
+            txn.create(&table.name, row.clone())?;
+            triggers.fire(txn, &table, TriggerTiming::After, None, Some(&row))?;
             count += 1;
         }
         Ok(ResultSet::Create { count })
@@ -78,78 +101,165 @@ impl<T: Transaction> Executor<T> for Insert {
 /// An UPDATE executor
 pub struct Update<T: Transaction> {
     table: String,
-    source: Box<dyn Executor<T>>,
+    source: Box<dyn QueryExecutor<T>>,
     expressions: Vec<(usize, Expression)>,
+    memory: Memory,
 }
 
 impl<T: Transaction> Update<T> {
     pub fn new(
         table: String,
-        source: Box<dyn Executor<T>>,
+        source: Box<dyn QueryExecutor<T>>,
         expressions: Vec<(usize, Expression)>,
+        memory: Memory,
     ) -> Box<Self> {
-        Box::new(Self { table, source, expressions })
+        Box::new(Self { table, source, expressions, memory })
     }
 }
 
-impl<T: Transaction> Executor<T> for Update<T> {
+impl<T: Transaction + 'static> Executor<T> for Update<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
-        match self.source.execute(txn)? {
-            ResultSet::Query { mut rows, .. } => {
-                let table = txn.must_read_table(&self.table)?;
-
-                // The iterator will see our changes, such that the same item may be iterated over
-                // multiple times. We keep track of the primary keys here to avoid that, althought
-                // it may cause ballooning memory usage for large updates.
-                //
-                // FIXME This is not safe for primary key updates, which may still be processed
-                // multiple times - it should be possible to come up with a pathological case that
-                // loops forever (e.g. UPDATE test SET id = id + 1).
-                let mut updated = HashSet::new();
-                while let Some(row) = rows.next().transpose()? {
-                    let id = table.get_row_key(&row)?;
-                    if updated.contains(&id) {
-                        continue;
-                    }
-                    let mut new = row.clone();
-                    for (field, expr) in &self.expressions {
-                        new[*field] = expr.evaluate(Some(&row))?;
-                    }
-                    txn.update(&table.name, &id, new)?;
-                    updated.insert(id);
+        let (_, mut batches) = self.source.execute(txn)?;
+        let table = txn.must_read_table(&self.table)?;
+        let triggers = Triggers::load(txn, &table, TriggerEvent::Update, &self.memory)?;
+        let limits = txn.limits();
+
+        // The iterator may see our changes (e.g. with chunked Raft scans), such that the same
+        // item may be iterated over multiple times. We keep track of the primary keys here to
+        // avoid that, including new keys of rows whose primary key was updated, although it
+        // may cause ballooning memory usage for large updates, which is accounted for.
+        let mut updated = HashSet::new();
+        let mut count = 0;
+        let mut reservation = self.memory.reserve();
+        while let Some(batch) = batches.next().transpose()? {
+            for row in batch {
+                let id = table.get_row_key(&row)?;
+                if updated.contains(&id) {
+                    continue;
+                }
+                let mut new = row.clone();
+                for (field, expr) in &self.expressions {
+                    new[*field] = expr.evaluate(Some(&row))?;
+                }
+                limits.check(&table, &new)?;
+                triggers.fire(txn, &table, TriggerTiming::Before, Some(&row), Some(&new))?;
+                txn.update(&table.name, &id, new.clone())?;
+                triggers.fire(txn, &table, TriggerTiming::After, Some(&row), Some(&new))?;
+                let new_id = table.get_row_key(&new)?;
+                if new_id != id {
+                    reservation.grow_row(std::slice::from_ref(&new_id))?;
+                    updated.insert(new_id);
                 }
-                Ok(ResultSet::Update { count: updated.len() as u64 })
+                reservation.grow_row(std::slice::from_ref(&id))?;
+                updated.insert(id);
+                count += 1;
             }
-            r => Err(Error::Internal(format!("Unexpected response {:?}", r))),
         }
+        Ok(ResultSet::Update { count })
     }
 }
 
 /// A DELETE executor
 pub struct Delete<T: Transaction> {
     table: String,
-    source: Box<dyn Executor<T>>,
+    source: Box<dyn QueryExecutor<T>>,
+    memory: Memory,
 }
 
 impl<T: Transaction> Delete<T> {
-    pub fn new(table: String, source: Box<dyn Executor<T>>) -> Box<Self> {
-        Box::new(Self { table, source })
+    pub fn new(table: String, source: Box<dyn QueryExecutor<T>>, memory: Memory) -> Box<Self> {
+        Box::new(Self { table, source, memory })
     }
 }
 
-impl<T: Transaction> Executor<T> for Delete<T> {
+impl<T: Transaction + 'static> Executor<T> for Delete<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         let table = txn.must_read_table(&self.table)?;
+        let triggers = Triggers::load(txn, &table, TriggerEvent::Delete, &self.memory)?;
         let mut count = 0;
-        match self.source.execute(txn)? {
-            ResultSet::Query { mut rows, .. } => {
-                while let Some(row) = rows.next().transpose()? {
-                    txn.delete(&table.name, &table.get_row_key(&row)?)?;
-                    count += 1
+        let (_, mut batches) = self.source.execute(txn)?;
+        while let Some(batch) = batches.next().transpose()? {
+            for row in batch {
+                triggers.fire(txn, &table, TriggerTiming::Before, Some(&row), None)?;
+                txn.delete(&table.name, &table.get_row_key(&row)?)?;
+                triggers.fire(txn, &table, TriggerTiming::After, Some(&row), None)?;
+                count += 1
+            }
+        }
+        Ok(ResultSet::Delete { count })
+    }
+}
+
+/// The triggers fired by a table mutation, with their statements parsed once per mutation, and
+/// the memory tracker of the mutating statement which their statements share.
+struct Triggers(Vec<(Trigger, ast::Statement)>, Memory);
+
+impl Triggers {
+    /// Loads a table's triggers for the given event.
+    fn load<T: Transaction>(
+        txn: &T,
+        table: &Table,
+        event: TriggerEvent,
+        memory: &Memory,
+    ) -> Result<Self> {
+        let triggers = txn
+            .scan_triggers(&table.name)?
+            .into_iter()
+            .filter(|t| t.event == event)
+            .map(|t| {
+                let statement = Parser::new(&t.statement).parse()?;
+                Ok((t, statement))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self(triggers, memory.clone()))
+    }
+
+    /// Fires the triggers with the given timing for a row, executing their statements in the
+    /// transaction. Query results are consumed, such that their errors abort the mutation.
+    fn fire<T: Transaction + 'static>(
+        &self,
+        txn: &mut T,
+        table: &Table,
+        timing: TriggerTiming,
+        old: Option<&[Value]>,
+        new: Option<&[Value]>,
+    ) -> Result<()> {
+        for (trigger, statement) in self.0.iter().filter(|(t, _)| t.timing == timing) {
+            let mut statement = statement.clone();
+            trigger.bind(&mut statement, table, old, new)?;
+            let _nested = Nested::enter()?;
+            let plan = Plan::build(statement, txn)?.optimize(txn, &HashSet::new())?;
+            if let ResultSet::Query { rows, .. } = plan.execute(txn, &self.1)? {
+                for row in rows {
+                    row?;
                 }
-                Ok(ResultSet::Delete { count })
             }
-            r => Err(Error::Internal(format!("Unexpected result {:?}", r))),
         }
+        Ok(())
+    }
+}
+
+/// A guard for a nested trigger statement, which tracks the trigger nesting depth.
+struct Nested;
+
+impl Nested {
+    /// Enters a nested trigger statement, or errors if it exceeds MAX_TRIGGER_DEPTH.
+    fn enter() -> Result<Self> {
+        TRIGGER_DEPTH.with(|depth| {
+            if depth.get() >= MAX_TRIGGER_DEPTH {
+                return Err(Error::Value(format!(
+                    "Trigger nesting exceeds maximum depth of {}",
+                    MAX_TRIGGER_DEPTH
+                )));
+            }
+            depth.set(depth.get() + 1);
+            Ok(Self)
+        })
+    }
+}
+
+impl Drop for Nested {
+    fn drop(&mut self) {
+        TRIGGER_DEPTH.with(|depth| depth.set(depth.get() - 1))
     }
 }
@@ -0,0 +1,140 @@
+use super::super::engine::Transaction;
+use super::super::plan::{Aggregate, Node};
+use super::super::types::{Column, Columns, Expression};
+use super::aggregation::Groups;
+use super::memory::row_size;
+use super::query::{filter, project, projection_columns};
+use super::{batch, Batch, Batches, Memory, QueryExecutor, Reservation};
+use crate::error::{Error, Result};
+
+/// A parallel executor, which partitions a table scan by primary key range across a set of worker
+/// threads. Each worker applies any filters and projections to its partition, and either returns
+/// the resulting rows, which are concatenated in key order, or partial aggregates, which are merged.
+pub struct Parallel {
+    table: String,
+    filter: Option<Expression>,
+    columns: Option<Vec<String>>,
+    stages: Vec<Stage>,
+    aggregates: Option<Vec<Aggregate>>,
+    workers: usize,
+    memory: Memory,
+}
+
+/// A processing stage run by each worker
+enum Stage {
+    Filter(Expression),
+    Projection(Vec<Expression>, Vec<Option<String>>),
+}
+
+impl Stage {
+    /// Applies the stage to a batch of rows.
+    fn apply(&self, batch: Batch) -> Result<Batch> {
+        match self {
+            Self::Filter(predicate) => filter(predicate, batch),
+            Self::Projection(expressions, _) => project(expressions, batch),
+        }
+    }
+}
+
+impl Parallel {
+    /// Creates a parallel executor for a plan node, which must be a scan with any number of
+    /// filters and projections above it, optionally topped by an aggregation.
+    pub fn new(mut node: Node, workers: usize, memory: Memory) -> Result<Box<Self>> {
+        let mut aggregates = None;
+        if let Node::Aggregation { source, aggregates: a } = node {
+            aggregates = Some(a);
+            node = *source;
+        }
+        let mut stages = Vec::new();
+        loop {
+            node = match node {
+                Node::Filter { source, predicate } => {
+                    stages.push(Stage::Filter(predicate));
+                    *source
+                }
+                Node::Projection { source, expressions } => {
+                    let (expressions, labels) = expressions.into_iter().unzip();
+                    stages.push(Stage::Projection(expressions, labels));
+                    *source
+                }
+                Node::Scan { table, alias: _, filter, columns } => {
+                    stages.reverse();
+                    return Ok(Box::new(Self {
+                        table,
+                        filter,
+                        columns,
+                        stages,
+                        aggregates,
+                        workers,
+                        memory,
+                    }));
+                }
+                node => return Err(Error::Internal(format!("Can't parallelize node {}", node))),
+            }
+        }
+    }
+}
+
+impl<T: Transaction> QueryExecutor<T> for Parallel {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let table = txn.must_read_table(&self.table)?;
+        let mut columns: Columns =
+            table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect();
+        for stage in &self.stages {
+            if let Stage::Projection(expressions, labels) = stage {
+                columns = projection_columns(columns, expressions, labels);
+            }
+        }
+        let partitions =
+            txn.scan_partitions(&self.table, self.filter, self.columns.as_deref(), self.workers)?;
+
+        // Run a worker thread per partition, and collect their results in partition order.
+        let (stages, aggregates, memory) = (&self.stages, &self.aggregates, &self.memory);
+        let results = std::thread::scope(|scope| {
+            let workers: Vec<_> = partitions
+                .into_iter()
+                .map(|partition| {
+                    scope.spawn(move || -> Result<(Vec<Batch>, Reservation, Option<Groups>)> {
+                        let mut batches = Vec::new();
+                        let mut reservation = memory.reserve();
+                        let mut groups = aggregates.clone().map(|a| Groups::new(a, memory));
+                        for batch in batch(partition) {
+                            let batch = stages.iter().try_fold(batch?, |b, s| s.apply(b))?;
+                            match groups.as_mut() {
+                                Some(groups) => groups.accumulate(batch)?,
+                                None => {
+                                    reservation.grow(batch.iter().map(|r| row_size(r)).sum())?;
+                                    batches.push(batch)
+                                }
+                            }
+                        }
+                        Ok((batches, reservation, groups))
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|w| w.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                .collect::<Result<Vec<_>>>()
+        })?;
+
+        match self.aggregates {
+            Some(aggregates) => {
+                let mut groups = Groups::new(aggregates, &self.memory);
+                for partial in results.into_iter().filter_map(|(_, _, groups)| groups) {
+                    groups.merge(partial)?;
+                }
+                Ok(groups.finish(columns))
+            }
+            None => {
+                let mut reservation = self.memory.reserve();
+                let mut batches = Vec::new();
+                for (partial, worker, _) in results {
+                    reservation.merge(worker);
+                    batches.extend(partial);
+                }
+                Ok((columns, reservation.hold(Box::new(batches.into_iter().map(Ok)))))
+            }
+        }
+    }
+}
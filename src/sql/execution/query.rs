@@ -1,191 +1,382 @@
 use super::super::engine::Transaction;
-use super::super::plan::Direction;
-use super::super::types::{Column, Expression, Row, Value};
-use super::{Executor, ResultSet};
+use super::super::plan::{Direction, Nulls};
+use super::super::types::{Column, Columns, Expression, Row, Value};
+use super::memory::row_size;
+use super::{batch, Batch, Batches, Memory, QueryExecutor};
 use crate::error::{Error, Result};
 
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+
 /// A filter executor
 pub struct Filter<T: Transaction> {
-    source: Box<dyn Executor<T>>,
+    source: Box<dyn QueryExecutor<T>>,
     predicate: Expression,
 }
 
 impl<T: Transaction> Filter<T> {
-    pub fn new(source: Box<dyn Executor<T>>, predicate: Expression) -> Box<Self> {
+    pub fn new(source: Box<dyn QueryExecutor<T>>, predicate: Expression) -> Box<Self> {
         Box::new(Self { source, predicate })
     }
 }
 
-impl<T: Transaction> Executor<T> for Filter<T> {
-    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
-        if let ResultSet::Query { columns, rows } = self.source.execute(txn)? {
-            let predicate = self.predicate;
-            Ok(ResultSet::Query {
-                columns,
-                rows: Box::new(rows.filter_map(move |r| {
-                    r.and_then(|row| match predicate.evaluate(Some(&row))? {
-                        Value::Boolean(true) => Ok(Some(row)),
-                        Value::Boolean(false) => Ok(None),
-                        Value::Null => Ok(None),
-                        value => Err(Error::Value(format!(
-                            "Filter returned {}, expected boolean",
-                            value
-                        ))),
-                    })
-                    .transpose()
-                })),
-            })
-        } else {
-            Err(Error::Internal("Unexpected result".into()))
+impl<T: Transaction> QueryExecutor<T> for Filter<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let (columns, batches) = self.source.execute(txn)?;
+        let predicate = self.predicate;
+        Ok((columns, Box::new(batches.map(move |batch| filter(&predicate, batch?)))))
+    }
+}
+
+/// Filters a batch of rows by a predicate.
+pub fn filter(predicate: &Expression, batch: Batch) -> Result<Batch> {
+    let mut rows = Vec::with_capacity(batch.len());
+    for row in batch {
+        match predicate.evaluate(Some(&row))? {
+            Value::Boolean(true) => rows.push(row),
+            Value::Boolean(false) | Value::Null => {}
+            value => {
+                return Err(Error::Value(format!("Filter returned {}, expected boolean", value)))
+            }
         }
     }
+    Ok(rows)
 }
 
 /// A projection executor
 pub struct Projection<T: Transaction> {
-    source: Box<dyn Executor<T>>,
+    source: Box<dyn QueryExecutor<T>>,
     expressions: Vec<(Expression, Option<String>)>,
 }
 
 impl<T: Transaction> Projection<T> {
     pub fn new(
-        source: Box<dyn Executor<T>>,
+        source: Box<dyn QueryExecutor<T>>,
         expressions: Vec<(Expression, Option<String>)>,
     ) -> Box<Self> {
         Box::new(Self { source, expressions })
     }
 }
 
-impl<T: Transaction> Executor<T> for Projection<T> {
-    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
-        if let ResultSet::Query { columns, rows } = self.source.execute(txn)? {
-            let (expressions, labels): (Vec<Expression>, Vec<Option<String>>) =
-                self.expressions.into_iter().unzip();
-            let columns = expressions
-                .iter()
-                .enumerate()
-                .map(|(i, e)| {
-                    if let Some(Some(label)) = labels.get(i) {
-                        Column { name: Some(label.clone()) }
-                    } else if let Expression::Field(i, _) = e {
-                        columns.get(*i).cloned().unwrap_or(Column { name: None })
-                    } else {
-                        Column { name: None }
-                    }
-                })
-                .collect();
-            let rows = Box::new(rows.map(move |r| {
-                r.and_then(|row| {
-                    expressions.iter().map(|e| e.evaluate(Some(&row))).collect::<Result<_>>()
-                })
-            }));
-            Ok(ResultSet::Query { columns, rows })
-        } else {
-            Err(Error::Internal("Unexpected result".into()))
-        }
+impl<T: Transaction> QueryExecutor<T> for Projection<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let (columns, batches) = self.source.execute(txn)?;
+        let (expressions, labels): (Vec<Expression>, Vec<Option<String>>) =
+            self.expressions.into_iter().unzip();
+        let columns = projection_columns(columns, &expressions, &labels);
+        Ok((columns, Box::new(batches.map(move |batch| project(&expressions, batch?)))))
     }
 }
 
+/// Returns the columns of a projection, given its source columns.
+pub fn projection_columns(
+    columns: Columns,
+    expressions: &[Expression],
+    labels: &[Option<String>],
+) -> Columns {
+    expressions
+        .iter()
+        .enumerate()
+        .map(|(i, e)| {
+            if let Some(Some(label)) = labels.get(i) {
+                Column { name: Some(label.clone()) }
+            } else if let Expression::Field(i, _) = e {
+                columns.get(*i).cloned().unwrap_or(Column { name: None })
+            } else {
+                Column { name: None }
+            }
+        })
+        .collect()
+}
+
+/// Projects a batch of rows by evaluating the given expressions.
+pub fn project(expressions: &[Expression], batch: Batch) -> Result<Batch> {
+    batch
+        .into_iter()
+        .map(|row| expressions.iter().map(|e| e.evaluate(Some(&row))).collect())
+        .collect()
+}
+
 /// An ORDER BY executor
 pub struct Order<T: Transaction> {
-    source: Box<dyn Executor<T>>,
-    order: Vec<(Expression, Direction)>,
+    source: Box<dyn QueryExecutor<T>>,
+    order: Vec<(Expression, Direction, Nulls)>,
+    memory: Memory,
 }
 
 impl<T: Transaction> Order<T> {
-    pub fn new(source: Box<dyn Executor<T>>, order: Vec<(Expression, Direction)>) -> Box<Self> {
-        Box::new(Self { source, order })
-    }
-}
-
-impl<T: Transaction> Executor<T> for Order<T> {
-    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
-        match self.source.execute(txn)? {
-            ResultSet::Query { columns, mut rows } => {
-                // FIXME Since we can't return errors from the sort_by closure, we have to
-                // pre-evaluate all values. This means that we can't short-circuit evaluation,
-                // and have to temporarily store evaluated values, which is bad for performance
-                // and memory usage respectively
-                struct Item {
-                    row: Row,
-                    values: Vec<Value>,
+    pub fn new(
+        source: Box<dyn QueryExecutor<T>>,
+        order: Vec<(Expression, Direction, Nulls)>,
+        memory: Memory,
+    ) -> Box<Self> {
+        Box::new(Self { source, order, memory })
+    }
+}
+
+impl<T: Transaction> QueryExecutor<T> for Order<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let (columns, mut batches) = self.source.execute(txn)?;
+
+        // FIXME Since we can't return errors from the sort_by closure, we have to
+        // pre-evaluate all values. This means that we can't short-circuit evaluation,
+        // and have to temporarily store evaluated values, which is bad for performance
+        // and memory usage respectively
+        struct Item {
+            row: Row,
+            values: Vec<Value>,
+        }
+
+        let mut items = Vec::new();
+        let mut reservation = self.memory.reserve();
+        while let Some(batch) = batches.next().transpose()? {
+            for row in batch {
+                let mut values = Vec::new();
+                for (expr, _, _) in self.order.iter() {
+                    values.push(expr.evaluate(Some(&row))?);
                 }
+                reservation.grow(row_size(&row) + row_size(&values))?;
+                items.push(Item { row, values })
+            }
+        }
+
+        items.sort_by(|a, b| compare(&self.order, &a.values, &b.values));
+
+        Ok((columns, reservation.hold(batch(items.into_iter().map(|i| Ok(i.row))))))
+    }
+}
+
+/// Compares the sort values of two rows in the given order.
+fn compare(order: &[(Expression, Direction, Nulls)], a: &[Value], b: &[Value]) -> Ordering {
+    for (i, (_, direction, nulls)) in order.iter().enumerate() {
+        let nulls_first = *nulls == Nulls::First;
+        let ordering = match (&a[i], &b[i]) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) if nulls_first => Ordering::Less,
+            (Value::Null, _) => Ordering::Greater,
+            (_, Value::Null) if nulls_first => Ordering::Greater,
+            (_, Value::Null) => Ordering::Less,
+            (a, b) => match a.sort_cmp(b) {
+                Some(o) if *direction == Direction::Ascending => o,
+                Some(o) => o.reverse(),
+                None => Ordering::Equal,
+            },
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
 
-                let mut items = Vec::new();
-                while let Some(row) = rows.next().transpose()? {
-                    let mut values = Vec::new();
-                    for (expr, _) in self.order.iter() {
-                        values.push(expr.evaluate(Some(&row))?);
+/// A top-K executor for ORDER BY with LIMIT, which keeps only the first rows in sort order in a
+/// bounded heap instead of sorting all rows.
+pub struct TopK<T: Transaction> {
+    source: Box<dyn QueryExecutor<T>>,
+    order: Vec<(Expression, Direction, Nulls)>,
+    limit: u64,
+    memory: Memory,
+}
+
+impl<T: Transaction> TopK<T> {
+    pub fn new(
+        source: Box<dyn QueryExecutor<T>>,
+        order: Vec<(Expression, Direction, Nulls)>,
+        limit: u64,
+        memory: Memory,
+    ) -> Box<Self> {
+        Box::new(Self { source, order, limit, memory })
+    }
+}
+
+/// A heap item for the top-K executor. Items are ordered by their sort values, with ties broken
+/// by input position like the stable sort of the Order executor.
+struct TopKItem<'a> {
+    order: &'a [(Expression, Direction, Nulls)],
+    values: Vec<Value>,
+    position: usize,
+    row: Row,
+}
+
+impl<'a> Ord for TopKItem<'a> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(self.order, &self.values, &other.values).then(self.position.cmp(&other.position))
+    }
+}
+
+impl<'a> PartialOrd for TopKItem<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> PartialEq for TopKItem<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a> Eq for TopKItem<'a> {}
+
+impl<T: Transaction> QueryExecutor<T> for TopK<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let (columns, mut batches) = self.source.execute(txn)?;
+        let limit = self.limit as usize;
+
+        // The greatest item in the max-heap is the last row in sort order, which is replaced
+        // whenever a smaller row arrives once the heap is full.
+        let mut heap = BinaryHeap::new();
+        let mut position = 0;
+        let mut reservation = self.memory.reserve();
+        let size = |item: &TopKItem| row_size(&item.row) + row_size(&item.values);
+        while let Some(batch) = batches.next().transpose()? {
+            for row in batch {
+                let values = self
+                    .order
+                    .iter()
+                    .map(|(expr, _, _)| expr.evaluate(Some(&row)))
+                    .collect::<Result<_>>()?;
+                let item = TopKItem { order: &self.order, values, position, row };
+                position += 1;
+                if heap.len() < limit {
+                    reservation.grow(size(&item))?;
+                    heap.push(item);
+                } else if let Some(mut last) = heap.peek_mut() {
+                    if item < *last {
+                        reservation.shrink(size(&last));
+                        reservation.grow(size(&item))?;
+                        *last = item;
                     }
-                    items.push(Item { row, values })
                 }
+            }
+        }
 
-                let order = &self.order;
-                items.sort_by(|a, b| {
-                    for (i, (_, order)) in order.iter().enumerate() {
-                        let value_a = &a.values[i];
-                        let value_b = &b.values[i];
-                        match value_a.partial_cmp(value_b) {
-                            Some(std::cmp::Ordering::Equal) => {}
-                            Some(o) => {
-                                return if *order == Direction::Ascending { o } else { o.reverse() }
-                            }
-                            None => {}
-                        }
-                    }
-                    std::cmp::Ordering::Equal
-                });
+        let rows: Vec<Row> = heap.into_sorted_vec().into_iter().map(|item| item.row).collect();
+        Ok((columns, reservation.hold(batch(rows.into_iter().map(Ok)))))
+    }
+}
+
+/// A DISTINCT executor, which streams the first row for each distinct set of values of the given
+/// expressions, deduplicating them via a hash set. NULLs are considered equal.
+pub struct Distinct<T: Transaction> {
+    source: Box<dyn QueryExecutor<T>>,
+    on: Vec<Expression>,
+    memory: Memory,
+}
 
-                Ok(ResultSet::Query {
-                    columns,
-                    rows: Box::new(items.into_iter().map(|i| Ok(i.row))),
-                })
+impl<T: Transaction> Distinct<T> {
+    pub fn new(
+        source: Box<dyn QueryExecutor<T>>,
+        on: Vec<Expression>,
+        memory: Memory,
+    ) -> Box<Self> {
+        Box::new(Self { source, on, memory })
+    }
+}
+
+impl<T: Transaction> QueryExecutor<T> for Distinct<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let (columns, batches) = self.source.execute(txn)?;
+        let on = self.on;
+        let mut seen = HashSet::new();
+        let mut reservation = self.memory.reserve();
+        let batches = batches.map(move |batch| {
+            let mut rows = Vec::new();
+            for row in batch? {
+                let values: Vec<Value> =
+                    on.iter().map(|e| e.evaluate(Some(&row))).collect::<Result<_>>()?;
+                let size = row_size(&values);
+                if seen.insert(values) {
+                    reservation.grow(size)?;
+                    rows.push(row);
+                }
             }
-            r => Err(Error::Internal(format!("Unexpected result {:?}", r))),
-        }
+            Ok(rows)
+        });
+        Ok((columns, Box::new(batches)))
     }
 }
 
 /// A LIMIT executor
 pub struct Limit<T: Transaction> {
-    source: Box<dyn Executor<T>>,
+    source: Box<dyn QueryExecutor<T>>,
     limit: u64,
 }
 
 impl<T: Transaction> Limit<T> {
-    pub fn new(source: Box<dyn Executor<T>>, limit: u64) -> Box<Self> {
+    pub fn new(source: Box<dyn QueryExecutor<T>>, limit: u64) -> Box<Self> {
         Box::new(Self { source, limit })
     }
 }
 
-impl<T: Transaction> Executor<T> for Limit<T> {
-    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
-        if let ResultSet::Query { columns, rows } = self.source.execute(txn)? {
-            Ok(ResultSet::Query { columns, rows: Box::new(rows.take(self.limit as usize)) })
-        } else {
-            Err(Error::Internal("Unexpected result".into()))
-        }
+impl<T: Transaction> QueryExecutor<T> for Limit<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let (columns, batches) = self.source.execute(txn)?;
+        let mut remaining = self.limit as usize;
+        let batches = batches.map_while(move |batch| match batch {
+            _ if remaining == 0 => None,
+            Ok(mut batch) => {
+                batch.truncate(remaining);
+                remaining -= batch.len();
+                Some(Ok(batch))
+            }
+            Err(err) => Some(Err(err)),
+        });
+        Ok((columns, Box::new(batches)))
+    }
+}
+
+/// A WITH ORDINALITY executor, which appends the 1-based position of each row
+pub struct Ordinality<T: Transaction> {
+    source: Box<dyn QueryExecutor<T>>,
+}
+
+impl<T: Transaction> Ordinality<T> {
+    pub fn new(source: Box<dyn QueryExecutor<T>>) -> Box<Self> {
+        Box::new(Self { source })
+    }
+}
+
+impl<T: Transaction> QueryExecutor<T> for Ordinality<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let (mut columns, batches) = self.source.execute(txn)?;
+        columns.push(Column { name: Some("ordinality".into()) });
+        let mut position = 0;
+        let batches = batches.map(move |batch| {
+            let mut batch = batch?;
+            for row in batch.iter_mut() {
+                position += 1;
+                row.push(Value::Integer(position));
+            }
+            Ok(batch)
+        });
+        Ok((columns, Box::new(batches)))
     }
 }
 
 /// An OFFSET executor
 pub struct Offset<T: Transaction> {
-    source: Box<dyn Executor<T>>,
+    source: Box<dyn QueryExecutor<T>>,
     offset: u64,
 }
 
 impl<T: Transaction> Offset<T> {
-    pub fn new(source: Box<dyn Executor<T>>, offset: u64) -> Box<Self> {
+    pub fn new(source: Box<dyn QueryExecutor<T>>, offset: u64) -> Box<Self> {
         Box::new(Self { source, offset })
     }
 }
 
-impl<T: Transaction> Executor<T> for Offset<T> {
-    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
-        if let ResultSet::Query { columns, rows } = self.source.execute(txn)? {
-            Ok(ResultSet::Query { columns, rows: Box::new(rows.skip(self.offset as usize)) })
-        } else {
-            Err(Error::Internal("Unexpected result".into()))
-        }
+impl<T: Transaction> QueryExecutor<T> for Offset<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let (columns, batches) = self.source.execute(txn)?;
+        let mut remaining = self.offset as usize;
+        let batches = batches.map(move |batch| {
+            let mut batch = batch?;
+            let skip = remaining.min(batch.len());
+            batch.drain(..skip);
+            remaining -= skip;
+            Ok(batch)
+        });
+        Ok((columns, Box::new(batches)))
     }
 }
@@ -1,7 +1,10 @@
 use super::super::engine::Transaction;
-use super::super::schema::Table;
-use super::{Executor, ResultSet};
-use crate::error::Result;
+use super::super::schema::{Column, Function, Index, Table, Trigger};
+use super::super::types::{Columns, DataType, Row, Value};
+use super::{Executor, Memory, QueryExecutor, ResultSet};
+use crate::error::{Error, Result};
+
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// A CREATE TABLE executor
 pub struct CreateTable {
@@ -22,7 +25,131 @@ impl<T: Transaction> Executor<T> for CreateTable {
     }
 }
 
-/// A DROP TABLE executor
+/// A CREATE TABLE AS executor, which creates a table from the rows of a query. The first column
+/// is the primary key. The column datatypes are those known from the query plan. If any aren't
+/// known, the rows are buffered to infer them from the values, where INTEGER and FLOAT values
+/// yield a FLOAT column, and columns without any non-NULL values are STRING.
+pub struct CreateTableAs<T: Transaction> {
+    table: String,
+    source: Box<dyn QueryExecutor<T>>,
+    datatypes: Vec<Option<DataType>>,
+    memory: Memory,
+}
+
+impl<T: Transaction> CreateTableAs<T> {
+    pub fn new(
+        table: String,
+        source: Box<dyn QueryExecutor<T>>,
+        datatypes: Vec<Option<DataType>>,
+        memory: Memory,
+    ) -> Box<Self> {
+        Box::new(Self { table, source, datatypes, memory })
+    }
+
+    /// Builds the table schema from the query columns and their datatypes.
+    fn schema(name: String, columns: Columns, datatypes: Vec<Option<DataType>>) -> Result<Table> {
+        let columns: Vec<Column> = columns
+            .into_iter()
+            .zip(datatypes)
+            .enumerate()
+            .map(|(i, (column, datatype))| {
+                let Some(column) = column.name else {
+                    return Err(Error::Value(format!(
+                        "Column {} of table {} has no name, use AS to name it",
+                        i + 1,
+                        name
+                    )));
+                };
+                Ok(Column {
+                    name: column,
+                    datatype: datatype.unwrap_or(DataType::String),
+                    primary_key: i == 0,
+                    nullable: i > 0,
+                    default: (i > 0).then_some(Value::Null),
+                    unique: i == 0,
+                    references: None,
+                    index: false,
+                    include: Vec::new(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        for (i, column) in columns.iter().enumerate() {
+            if columns[..i].iter().any(|c| c.name == column.name) {
+                return Err(Error::Value(format!(
+                    "Duplicate column {} in table {}, use AS to rename it",
+                    column.name, name
+                )));
+            }
+        }
+        Table::new(name, columns)
+    }
+
+    /// Converts INTEGER values in FLOAT columns to FLOAT.
+    fn convert(table: &Table, mut row: Row) -> Row {
+        for (value, column) in row.iter_mut().zip(&table.columns) {
+            if let (Value::Integer(i), DataType::Float) = (&value, &column.datatype) {
+                *value = Value::Float(*i as f64);
+            }
+        }
+        row
+    }
+}
+
+impl<T: Transaction> Executor<T> for CreateTableAs<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let Self { table: name, source, mut datatypes, memory } = *self;
+        if txn.read_table(&name)?.is_some() {
+            return Err(Error::Value(format!("Table {} already exists", name)));
+        }
+        let (columns, mut batches) = source.execute(txn)?;
+        datatypes.resize(columns.len(), None);
+
+        // If the datatypes are known, stream the rows into the table. Otherwise, buffer them
+        // and infer the missing datatypes from the values.
+        let mut buffered = Vec::new();
+        let mut reservation = memory.reserve();
+        if datatypes.iter().any(|d| d.is_none()) {
+            let mut inferred = vec![None; columns.len()];
+            while let Some(batch) = batches.next().transpose()? {
+                for row in &batch {
+                    reservation.grow_row(row)?;
+                    for (datatype, value) in inferred.iter_mut().zip(row) {
+                        *datatype = match (datatype.take(), value.datatype()) {
+                            (Some(DataType::Integer), Some(DataType::Float))
+                            | (Some(DataType::Float), Some(DataType::Integer)) => {
+                                Some(DataType::Float)
+                            }
+                            (Some(a), Some(b)) if a != b => {
+                                return Err(Error::Value(format!(
+                                    "Can't create table {} with {} and {} values in a column",
+                                    name, a, b
+                                )))
+                            }
+                            (a, b) => a.or(b),
+                        };
+                    }
+                }
+                buffered.push(Ok(batch));
+            }
+            for (datatype, inferred) in datatypes.iter_mut().zip(inferred) {
+                datatype.get_or_insert_with(|| inferred.unwrap_or(DataType::String));
+            }
+        }
+
+        let table = Self::schema(name.clone(), columns, datatypes)?;
+        txn.create_table(table.clone())?;
+        let mut count = 0;
+        for batch in buffered.into_iter().chain(batches) {
+            let rows: Vec<_> = batch?.into_iter().map(|row| Self::convert(&table, row)).collect();
+            count += rows.len() as u64;
+            txn.create_rows(&name, rows)?;
+        }
+        Ok(ResultSet::CreateTableAs { name, count })
+    }
+}
+
+/// A DROP TABLE executor. The table's data is retained until it's purged, see
+/// Catalog::drop_table().
 pub struct DropTable {
     table: String,
 }
@@ -35,7 +162,144 @@ impl DropTable {
 
 impl<T: Transaction> Executor<T> for DropTable {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
-        txn.delete_table(&self.table)?;
+        // The time is determined here rather than in the engine, such that all Raft nodes
+        // record the same time.
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| Error::Internal(err.to_string()))?
+            .as_secs();
+        txn.drop_table(&self.table, time)?;
         Ok(ResultSet::DropTable { name: self.table })
     }
 }
+
+/// An UNDROP TABLE executor
+pub struct UndropTable {
+    table: String,
+}
+
+impl UndropTable {
+    pub fn new(table: String) -> Box<Self> {
+        Box::new(Self { table })
+    }
+}
+
+impl<T: Transaction> Executor<T> for UndropTable {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.undrop_table(&self.table)?;
+        Ok(ResultSet::UndropTable { name: self.table })
+    }
+}
+
+/// A CREATE TRIGGER executor
+pub struct CreateTrigger {
+    trigger: Trigger,
+}
+
+impl CreateTrigger {
+    pub fn new(trigger: Trigger) -> Box<Self> {
+        Box::new(Self { trigger })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CreateTrigger {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let name = self.trigger.name.clone();
+        txn.create_trigger(self.trigger)?;
+        Ok(ResultSet::CreateTrigger { name })
+    }
+}
+
+/// A DROP TRIGGER executor
+pub struct DropTrigger {
+    table: String,
+    name: String,
+}
+
+impl DropTrigger {
+    pub fn new(table: String, name: String) -> Box<Self> {
+        Box::new(Self { table, name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for DropTrigger {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.delete_trigger(&self.table, &self.name)?;
+        Ok(ResultSet::DropTrigger { name: self.name })
+    }
+}
+
+/// A CREATE INDEX executor
+pub struct CreateIndex {
+    index: Index,
+}
+
+impl CreateIndex {
+    pub fn new(index: Index) -> Box<Self> {
+        Box::new(Self { index })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CreateIndex {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let name = self.index.name.clone();
+        txn.create_index(self.index)?;
+        Ok(ResultSet::CreateIndex { name })
+    }
+}
+
+/// A DROP INDEX executor
+pub struct DropIndex {
+    table: String,
+    name: String,
+}
+
+impl DropIndex {
+    pub fn new(table: String, name: String) -> Box<Self> {
+        Box::new(Self { table, name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for DropIndex {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.delete_index(&self.table, &self.name)?;
+        Ok(ResultSet::DropIndex { name: self.name })
+    }
+}
+
+/// A CREATE FUNCTION executor
+pub struct CreateFunction {
+    function: Function,
+}
+
+impl CreateFunction {
+    pub fn new(function: Function) -> Box<Self> {
+        Box::new(Self { function })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CreateFunction {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let name = self.function.name.clone();
+        txn.create_function(self.function)?;
+        Ok(ResultSet::CreateFunction { name })
+    }
+}
+
+/// A DROP FUNCTION executor
+pub struct DropFunction {
+    name: String,
+}
+
+impl DropFunction {
+    pub fn new(name: String) -> Box<Self> {
+        Box::new(Self { name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for DropFunction {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.delete_function(&self.name)?;
+        Ok(ResultSet::DropFunction { name: self.name })
+    }
+}
@@ -1,29 +1,62 @@
-use super::super::engine::Transaction;
-use super::super::types::{Column, Expression, Row, Value};
-use super::{Executor, ResultSet};
-use crate::error::Result;
+use super::super::engine::{self, Transaction};
+use super::super::plan;
+use super::super::types::{Column, Columns, Expression, Range, Row, Rows, Sample, Value};
+use super::{batch, unbatch, Batches, QueryExecutor};
+use crate::error::{Error, Result};
 
+use std::cmp::Ordering;
 use std::collections::HashSet;
 
-/// A table scan executor
+/// A table scan executor, optionally only reading some columns
 pub struct Scan {
     table: String,
     filter: Option<Expression>,
+    columns: Option<Vec<String>>,
 }
 
 impl Scan {
-    pub fn new(table: String, filter: Option<Expression>) -> Box<Self> {
-        Box::new(Self { table, filter })
+    pub fn new(
+        table: String,
+        filter: Option<Expression>,
+        columns: Option<Vec<String>>,
+    ) -> Box<Self> {
+        Box::new(Self { table, filter, columns })
     }
 }
 
-impl<T: Transaction> Executor<T> for Scan {
-    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+impl<T: Transaction> QueryExecutor<T> for Scan {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
         let table = txn.must_read_table(&self.table)?;
-        Ok(ResultSet::Query {
-            columns: table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
-            rows: Box::new(txn.scan(&table.name, self.filter)?),
-        })
+        Ok((
+            table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
+            batch(match self.columns {
+                Some(columns) => txn.scan_columns(&table.name, self.filter, &columns)?,
+                None => txn.scan(&table.name, self.filter)?,
+            }),
+        ))
+    }
+}
+
+/// A table sample scan executor
+pub struct SampleScan {
+    table: String,
+    sample: Sample,
+    filter: Option<Expression>,
+}
+
+impl SampleScan {
+    pub fn new(table: String, sample: Sample, filter: Option<Expression>) -> Box<Self> {
+        Box::new(Self { table, sample, filter })
+    }
+}
+
+impl<T: Transaction> QueryExecutor<T> for SampleScan {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let table = txn.must_read_table(&self.table)?;
+        Ok((
+            table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
+            batch(txn.scan_sample(&table.name, &self.sample, self.filter)?),
+        ))
     }
 }
 
@@ -39,8 +72,8 @@ impl KeyLookup {
     }
 }
 
-impl<T: Transaction> Executor<T> for KeyLookup {
-    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+impl<T: Transaction> QueryExecutor<T> for KeyLookup {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
         let table = txn.must_read_table(&self.table)?;
 
         // FIXME Is there a way to pass the txn into an iterator closure instead?
@@ -50,10 +83,10 @@ impl<T: Transaction> Executor<T> for KeyLookup {
             .filter_map(|key| txn.read(&table.name, &key).transpose())
             .collect::<Result<Vec<Row>>>()?;
 
-        Ok(ResultSet::Query {
-            columns: table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
-            rows: Box::new(rows.into_iter().map(Ok)),
-        })
+        Ok((
+            table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
+            batch(rows.into_iter().map(Ok)),
+        ))
     }
 }
 
@@ -70,8 +103,8 @@ impl IndexLookup {
     }
 }
 
-impl<T: Transaction> Executor<T> for IndexLookup {
-    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+impl<T: Transaction> QueryExecutor<T> for IndexLookup {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
         let table = txn.must_read_table(&self.table)?;
 
         let mut pks: HashSet<Value> = HashSet::new();
@@ -85,11 +118,161 @@ impl<T: Transaction> Executor<T> for IndexLookup {
             .filter_map(|pk| txn.read(&table.name, &pk).transpose())
             .collect::<Result<Vec<Row>>>()?;
 
-        Ok(ResultSet::Query {
-            columns: table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
-            rows: Box::new(rows.into_iter().map(Ok)),
-        })
+        Ok((
+            table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
+            batch(rows.into_iter().map(Ok)),
+        ))
+    }
+}
+
+/// A primary key range scan executor, which returns rows in primary key order, or in reverse
+pub struct KeyRangeScan {
+    table: String,
+    ranges: Vec<Range>,
+    reverse: bool,
+}
+
+impl KeyRangeScan {
+    pub fn new(table: String, ranges: Vec<Range>, reverse: bool) -> Box<Self> {
+        Box::new(Self { table, ranges, reverse })
+    }
+}
+
+impl<T: Transaction> QueryExecutor<T> for KeyRangeScan {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let table = txn.must_read_table(&self.table)?;
+        let scans = self
+            .ranges
+            .into_iter()
+            .map(|range| txn.scan_range(&table.name, range))
+            .collect::<Result<Vec<_>>>()?;
+        Ok((
+            table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
+            batch(concat(scans, self.reverse)),
+        ))
+    }
+}
+
+/// An executor for scans in the order of an index created with CREATE INDEX, or in reverse
+pub struct IndexOrderScan {
+    table: String,
+    index: String,
+    reverse: bool,
+}
+
+impl IndexOrderScan {
+    pub fn new(table: String, index: String, reverse: bool) -> Box<Self> {
+        Box::new(Self { table, index, reverse })
+    }
+}
+
+impl<T: Transaction> QueryExecutor<T> for IndexOrderScan {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let table = txn.must_read_table(&self.table)?;
+        Ok((
+            table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
+            batch(txn.scan_index_order(&table.name, &self.index, self.reverse)?),
+        ))
+    }
+}
+
+/// An index range scan executor, which returns rows in index order, or in reverse, optionally
+/// only reading up to a limit
+pub struct IndexRangeScan {
+    table: String,
+    column: String,
+    ranges: Vec<Range>,
+    reverse: bool,
+    limit: Option<u64>,
+}
+
+impl IndexRangeScan {
+    pub fn new(
+        table: String,
+        column: String,
+        ranges: Vec<Range>,
+        reverse: bool,
+        limit: Option<u64>,
+    ) -> Box<Self> {
+        Box::new(Self { table, column, ranges, reverse, limit })
+    }
+}
+
+impl<T: Transaction> QueryExecutor<T> for IndexRangeScan {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let table = txn.must_read_table(&self.table)?;
+
+        let mut pks = Vec::new();
+        for range in self.ranges {
+            for entry in txn.scan_index_range(&self.table, &self.column, range)? {
+                let mut entry_pks: Vec<_> = entry?.1.into_iter().collect();
+                entry_pks.sort_by(|a, b| a.sort_cmp(b).unwrap_or(Ordering::Equal));
+                pks.extend(entry_pks);
+            }
+        }
+        if self.reverse {
+            pks.reverse();
+        }
+        if let Some(limit) = self.limit {
+            pks.truncate(limit as usize);
+        }
+
+        let rows = pks
+            .into_iter()
+            .filter_map(|pk| txn.read(&table.name, &pk).transpose())
+            .collect::<Result<Vec<Row>>>()?;
+
+        Ok((
+            table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
+            batch(rows.into_iter().map(Ok)),
+        ))
+    }
+}
+
+/// An index-only scan executor, which reads rows from a covering index in index order, or in
+/// reverse, optionally up to a limit. Columns that are not covered by the index are NULL, and must
+/// not be used by the query.
+pub struct IndexOnlyScan {
+    table: String,
+    column: String,
+    ranges: Vec<Range>,
+    reverse: bool,
+    limit: Option<u64>,
+}
+
+impl IndexOnlyScan {
+    pub fn new(
+        table: String,
+        column: String,
+        ranges: Vec<Range>,
+        reverse: bool,
+        limit: Option<u64>,
+    ) -> Box<Self> {
+        Box::new(Self { table, column, ranges, reverse, limit })
+    }
+}
+
+impl<T: Transaction> QueryExecutor<T> for IndexOnlyScan {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let table = txn.must_read_table(&self.table)?;
+        let scans = self
+            .ranges
+            .into_iter()
+            .map(|range| txn.scan_index_cover(&table.name, &self.column, range))
+            .collect::<Result<Vec<_>>>()?;
+        Ok((
+            table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
+            batch(concat(scans, self.reverse).take(self.limit.unwrap_or(u64::MAX) as usize)),
+        ))
+    }
+}
+
+/// Concatenates the scans of a sorted set of ranges, in reverse order if requested.
+fn concat(mut scans: Vec<engine::Scan>, reverse: bool) -> impl Iterator<Item = Result<Row>> {
+    if reverse {
+        scans = scans.into_iter().rev().map(|scan| Box::new(scan.rev()) as engine::Scan).collect();
     }
+    scans.into_iter().flatten()
 }
 
 /// An executor that produces a single empty row
@@ -101,11 +284,102 @@ impl Nothing {
     }
 }
 
-impl<T: Transaction> Executor<T> for Nothing {
-    fn execute(self: Box<Self>, _: &mut T) -> Result<ResultSet> {
-        Ok(ResultSet::Query {
-            columns: Vec::new(),
-            rows: Box::new(std::iter::once(Ok(Row::new()))),
-        })
+impl<T: Transaction> QueryExecutor<T> for Nothing {
+    fn execute(self: Box<Self>, _: &mut T) -> Result<(Columns, Batches)> {
+        Ok((Vec::new(), Box::new(std::iter::once(Ok(vec![Row::new()])))))
+    }
+}
+
+/// A VALUES executor, which evaluates the given rows of constant expressions.
+pub struct Values {
+    columns: Vec<String>,
+    rows: Vec<Vec<Expression>>,
+}
+
+impl Values {
+    pub fn new(columns: Vec<String>, rows: Vec<Vec<Expression>>) -> Box<Self> {
+        Box::new(Self { columns, rows })
+    }
+}
+
+impl<T: Transaction> QueryExecutor<T> for Values {
+    fn execute(self: Box<Self>, _: &mut T) -> Result<(Columns, Batches)> {
+        let columns = self.columns.into_iter().map(|name| Column { name: Some(name) }).collect();
+        let rows =
+            self.rows.into_iter().map(|exprs| exprs.iter().map(|e| e.evaluate(None)).collect());
+        Ok((columns, batch(rows)))
+    }
+}
+
+/// A table function executor, which calls the function for each source row with the arguments
+/// evaluated against it, returning the row extended with each of the function's values.
+pub struct TableFunction<T: Transaction> {
+    source: Box<dyn QueryExecutor<T>>,
+    function: plan::TableFunction,
+    args: Vec<Expression>,
+    column: String,
+}
+
+impl<T: Transaction> TableFunction<T> {
+    pub fn new(
+        source: Box<dyn QueryExecutor<T>>,
+        function: plan::TableFunction,
+        args: Vec<Expression>,
+        column: String,
+    ) -> Box<Self> {
+        Box::new(Self { source, function, args, column })
+    }
+}
+
+impl<T: Transaction> QueryExecutor<T> for TableFunction<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<(Columns, Batches)> {
+        let (mut columns, source) = self.source.execute(txn)?;
+        columns.push(Column { name: Some(self.column) });
+        let (function, args) = (self.function, self.args);
+        let rows = unbatch(source).flat_map(move |row| -> Rows {
+            let rows = row.and_then(|row| {
+                let args = args.iter().map(|e| e.evaluate(Some(&row))).collect::<Result<_>>()?;
+                let values = match function {
+                    plan::TableFunction::GenerateSeries => generate_series(args)?,
+                };
+                Ok(values.map(move |value| Ok(row.iter().cloned().chain([value]).collect())))
+            });
+            match rows {
+                Ok(rows) => Box::new(rows),
+                Err(err) => Box::new(std::iter::once(Err(err))),
+            }
+        });
+        Ok((columns, batch(rows)))
+    }
+}
+
+/// Generates the integers from start to stop (inclusive) with an optional step, which can be
+/// negative. Returns no values if any argument is NULL.
+fn generate_series(args: Vec<Value>) -> Result<Box<dyn Iterator<Item = Value> + Send>> {
+    let mut ints = Vec::new();
+    for arg in args {
+        match arg {
+            Value::Integer(i) => ints.push(i),
+            Value::Null => return Ok(Box::new(std::iter::empty())),
+            v => {
+                return Err(Error::Value(format!(
+                    "generate_series requires integer arguments, got {}",
+                    v
+                )))
+            }
+        }
+    }
+    let (start, stop, step) = match ints.as_slice() {
+        [start, stop] => (*start, *stop, 1),
+        [start, stop, step] => (*start, *stop, *step),
+        _ => return Err(Error::Internal("generate_series takes 2 or 3 arguments".into())),
+    };
+    if step == 0 {
+        return Err(Error::Value("generate_series step can't be zero".into()));
     }
+    Ok(Box::new(
+        std::iter::successors(Some(start), move |i| i.checked_add(step))
+            .take_while(move |i| if step > 0 { *i <= stop } else { *i >= stop })
+            .map(Value::Integer),
+    ))
 }
@@ -0,0 +1,1465 @@
+//! Imports SQL dumps from other databases, i.e. PostgreSQL's pg_dump (with COPY or INSERT data)
+//! and SQLite's .dump. Supported schema statements are translated to toyDB table schemas, and
+//! table data is converted to toyDB rows. Unsupported statements are skipped, and lossy
+//! translations are reported as warnings.
+//!
+//! Since toyDB has no ALTER TABLE, constraints added after a table is created (as pg_dump does)
+//! are folded into the table schema, which is why the entire dump is parsed before anything is
+//! loaded. Tables must have a single-column primary key, and foreign keys must reference it.
+use super::engine::{Codec, Layout};
+use super::parser::format_ident;
+use super::schema::{Column, Table};
+use super::types::{DataType, Row, Value};
+use crate::error::{Error, Result};
+
+use std::collections::{BTreeMap, HashSet};
+
+/// An imported SQL dump.
+#[derive(Debug, Default, PartialEq)]
+pub struct Import {
+    /// Imported tables and their rows, in the order they were defined.
+    pub tables: Vec<(Table, Vec<Row>)>,
+    /// Warnings about skipped tables and data, and lossy translations.
+    pub warnings: Vec<String>,
+    /// The number of skipped statements, by statement kind.
+    pub skipped: BTreeMap<String, usize>,
+}
+
+impl Import {
+    /// Parses a SQL dump.
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut importer = Importer::default();
+        let mut scanner = Scanner::new(input);
+        while let Some(tokens) = scanner.next_statement()? {
+            importer.statement(StatementParser::new(tokens), &mut scanner)?;
+        }
+        Ok(importer.finish())
+    }
+
+    /// Returns the total number of imported rows.
+    pub fn row_count(&self) -> usize {
+        self.tables.iter().map(|(_, rows)| rows.len()).sum()
+    }
+}
+
+/// A table being imported.
+struct ImportTable {
+    table: Table,
+    rows: Vec<Row>,
+    /// Primary key column names, from column and table constraints.
+    primary_key: Vec<String>,
+    /// Foreign keys, as column name, referenced table, and referenced column (if given).
+    references: Vec<(String, String, Option<String>)>,
+    /// Columns with non-null constraints.
+    not_null: HashSet<String>,
+}
+
+/// Imports dump statements.
+#[derive(Default)]
+struct Importer {
+    tables: Vec<ImportTable>,
+    warnings: Vec<String>,
+    skipped: BTreeMap<String, usize>,
+    /// Tables that data was skipped for, to only warn once.
+    unknown_tables: HashSet<String>,
+}
+
+impl Importer {
+    /// Imports a statement. COPY data is read from the scanner.
+    fn statement(&mut self, mut p: StatementParser, scanner: &mut Scanner) -> Result<()> {
+        let kind = p.kind();
+        if p.next_if_words(&["CREATE", "TABLE"])
+            || p.next_if_words(&["CREATE", "TEMP", "TABLE"])
+            || p.next_if_words(&["CREATE", "TEMPORARY", "TABLE"])
+            || p.next_if_words(&["CREATE", "UNLOGGED", "TABLE"])
+        {
+            self.create_table(p, kind)
+        } else if p.next_if_words(&["ALTER", "TABLE"]) {
+            self.alter_table(p, kind)
+        } else if p.next_if_words(&["CREATE", "INDEX"]) {
+            self.create_index(p, false)
+        } else if p.next_if_words(&["CREATE", "UNIQUE", "INDEX"]) {
+            self.create_index(p, true)
+        } else if p.next_if_word("INSERT") {
+            self.insert(p)
+        } else if p.next_if_word("COPY") {
+            self.copy(p, scanner)
+        } else {
+            self.skip(kind);
+            Ok(())
+        }
+    }
+
+    /// Records a skipped statement.
+    fn skip(&mut self, kind: String) {
+        *self.skipped.entry(kind).or_default() += 1;
+    }
+
+    /// Imports a CREATE TABLE statement.
+    fn create_table(&mut self, mut p: StatementParser, kind: String) -> Result<()> {
+        p.next_if_words(&["IF", "NOT", "EXISTS"]);
+        let name = p.next_name()?;
+        if name.starts_with("sqlite_") {
+            // SQLite internal tables, e.g. sqlite_sequence. Their data is skipped too.
+            self.unknown_tables.insert(name);
+            self.skip(kind);
+            return Ok(());
+        }
+        if table_index(&self.tables, &name).is_some() {
+            return Err(Error::Value(format!("Table {} is defined twice", name)));
+        }
+        if !p.next_if_symbol('(') {
+            self.warnings.push(format!("Skipped table {}: CREATE TABLE AS is not supported", name));
+            return Ok(());
+        }
+        let mut t = ImportTable {
+            table: Table {
+                name,
+                columns: Vec::new(),
+                ttl: None,
+                codec: Codec::default(),
+                layout: Layout::default(),
+            },
+            rows: Vec::new(),
+            primary_key: Vec::new(),
+            references: Vec::new(),
+            not_null: HashSet::new(),
+        };
+        loop {
+            if !Self::table_constraint(&mut t, &mut p, &mut self.warnings)? {
+                Self::column(&mut t, &mut p, &mut self.warnings)?;
+            }
+            if p.next_if_symbol(')') {
+                break;
+            }
+            p.expect_symbol(',')?;
+        }
+        self.tables.push(t);
+        Ok(())
+    }
+
+    /// Imports a column definition.
+    fn column(
+        t: &mut ImportTable,
+        p: &mut StatementParser,
+        warnings: &mut Vec<String>,
+    ) -> Result<()> {
+        let name = p.next_ident()?;
+        let mut column = Column {
+            name: name.clone(),
+            datatype: p.next_datatype(),
+            primary_key: false,
+            nullable: true,
+            default: None,
+            unique: false,
+            references: None,
+            index: false,
+            include: Vec::new(),
+        };
+        loop {
+            if p.next_if_word("CONSTRAINT") {
+                p.next_ident()?;
+            } else if p.next_if_words(&["PRIMARY", "KEY"]) {
+                if !t.primary_key.contains(&name) {
+                    t.primary_key.push(name.clone());
+                }
+                let _ = p.next_if_word("ASC") || p.next_if_word("DESC");
+                p.next_if_word("AUTOINCREMENT");
+            } else if p.next_if_words(&["NOT", "NULL"]) {
+                t.not_null.insert(name.clone());
+            } else if p.next_if_word("NULL") {
+                // Columns are nullable by default.
+            } else if p.next_if_word("UNIQUE") {
+                column.unique = true;
+            } else if p.next_if_word("DEFAULT") {
+                match p.next_literal() {
+                    Ok(literal) => match convert(literal, &column.datatype) {
+                        Ok(value) => column.default = Some(value),
+                        Err(err) => warnings.push(format!(
+                            "Dropped default for column {}.{}: {}",
+                            t.table.name, name, err
+                        )),
+                    },
+                    Err(_) => {
+                        let start = p.pos;
+                        p.skip_expression();
+                        warnings.push(format!(
+                            "Dropped default {} for column {}.{}: only literals are supported",
+                            p.text(start),
+                            t.table.name,
+                            name
+                        ));
+                    }
+                }
+            } else if p.next_if_word("REFERENCES") {
+                let (target, target_column) = p.next_reference()?;
+                t.references.push((name.clone(), target, target_column));
+            } else if p.at_end() || p.peek_symbol(',') || p.peek_symbol(')') {
+                break;
+            } else {
+                // CHECK, COLLATE, GENERATED, and other unsupported constraints.
+                let start = p.pos;
+                p.pos += 1;
+                p.skip_expression();
+                warnings.push(format!(
+                    "Ignored constraint {} on column {}.{}",
+                    p.text(start),
+                    t.table.name,
+                    name
+                ));
+            }
+        }
+        t.table.columns.push(column);
+        Ok(())
+    }
+
+    /// Imports a table constraint, if any, returning true if one was found.
+    fn table_constraint(
+        t: &mut ImportTable,
+        p: &mut StatementParser,
+        warnings: &mut Vec<String>,
+    ) -> Result<bool> {
+        let start = p.pos;
+        if p.next_if_word("CONSTRAINT") {
+            p.next_ident()?;
+        }
+        if p.next_if_words(&["PRIMARY", "KEY"]) {
+            for column in p.next_ident_list()? {
+                if !t.primary_key.contains(&column) {
+                    t.primary_key.push(column);
+                }
+            }
+            p.skip_clause();
+        } else if p.next_if_word("UNIQUE") {
+            let columns = p.next_ident_list()?;
+            p.skip_clause();
+            Self::unique(t, columns, warnings);
+        } else if p.next_if_words(&["FOREIGN", "KEY"]) {
+            let columns = p.next_ident_list()?;
+            p.expect_word("REFERENCES")?;
+            let (target, target_column) = p.next_reference()?;
+            match columns.as_slice() {
+                [column] => t.references.push((column.clone(), target, target_column)),
+                _ => warnings.push(format!(
+                    "Ignored foreign key on table {}: multi-column foreign keys are not supported",
+                    t.table.name
+                )),
+            }
+        } else if p.next_if_word("CHECK") {
+            let start = p.pos;
+            p.skip_clause();
+            warnings.push(format!(
+                "Ignored constraint CHECK {} on table {}",
+                p.text(start),
+                t.table.name
+            ));
+        } else {
+            p.pos = start;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Applies a unique constraint.
+    fn unique(t: &mut ImportTable, columns: Vec<String>, warnings: &mut Vec<String>) {
+        match columns.as_slice() {
+            [name] => match column_index(&t.table, name) {
+                Some(i) => t.table.columns[i].unique = true,
+                None => warnings.push(format!(
+                    "Ignored unique constraint on unknown column {}.{}",
+                    t.table.name, name
+                )),
+            },
+            _ => warnings.push(format!(
+                "Ignored unique constraint on table {}: multi-column constraints are not supported",
+                t.table.name
+            )),
+        }
+    }
+
+    /// Imports an ALTER TABLE statement, which can only add constraints.
+    fn alter_table(&mut self, mut p: StatementParser, kind: String) -> Result<()> {
+        p.next_if_words(&["IF", "EXISTS"]);
+        p.next_if_word("ONLY");
+        let name = p.next_name()?;
+        if !p.next_if_word("ADD") || !p.peek_constraint() {
+            self.skip(kind);
+            return Ok(());
+        }
+        let Some(i) = table_index(&self.tables, &name) else {
+            self.warnings.push(format!("Ignored constraint on undefined table {}", name));
+            return Ok(());
+        };
+        let t = &mut self.tables[i];
+        Self::table_constraint(t, &mut p, &mut self.warnings)?;
+        Ok(())
+    }
+
+    /// Imports a CREATE INDEX statement. Only single-column indexes are supported.
+    fn create_index(&mut self, mut p: StatementParser, unique: bool) -> Result<()> {
+        p.next_if_word("CONCURRENTLY");
+        p.next_if_words(&["IF", "NOT", "EXISTS"]);
+        if !p.peek_word("ON") {
+            p.next_name()?;
+        }
+        p.expect_word("ON")?;
+        p.next_if_word("ONLY");
+        let name = p.next_name()?;
+        if p.next_if_word("USING") {
+            p.next_ident()?;
+        }
+        let Some(i) = table_index(&self.tables, &name) else {
+            self.warnings.push(format!("Ignored index on undefined table {}", name));
+            return Ok(());
+        };
+        let t = &mut self.tables[i];
+        let Ok(columns) = p.next_ident_list() else {
+            self.warnings.push(format!(
+                "Ignored index on table {}: only column indexes are supported",
+                name
+            ));
+            return Ok(());
+        };
+        if unique {
+            Self::unique(t, columns, &mut self.warnings);
+        } else if let [column] = columns.as_slice() {
+            let Some(i) = column_index(&t.table, column) else {
+                self.warnings.push(format!("Ignored index on unknown column {}.{}", name, column));
+                return Ok(());
+            };
+            t.table.columns[i].index = true;
+            if p.next_if_word("INCLUDE") {
+                for include in p.next_ident_list()? {
+                    match column_index(&t.table, &include) {
+                        Some(j) if j != i => {
+                            let included = t.table.columns[j].name.clone();
+                            if !t.table.columns[i].include.contains(&included) {
+                                t.table.columns[i].include.push(included);
+                            }
+                        }
+                        Some(_) => {}
+                        None => self.warnings.push(format!(
+                            "Ignored included column {}.{} for index on {}",
+                            name, include, column
+                        )),
+                    }
+                }
+            }
+        } else {
+            self.warnings.push(format!(
+                "Ignored index on table {}: multi-column indexes are not supported",
+                name
+            ));
+        }
+        Ok(())
+    }
+
+    /// Imports an INSERT statement.
+    fn insert(&mut self, mut p: StatementParser) -> Result<()> {
+        if p.next_if_word("OR") {
+            p.next_ident()?;
+        }
+        p.expect_word("INTO")?;
+        let name = p.next_name()?;
+        let columns = if p.peek_symbol('(') { Some(p.next_ident_list()?) } else { None };
+        if !p.next_if_word("VALUES") {
+            return Err(Error::Value(format!("Unsupported INSERT into table {}", name)));
+        }
+        let mut rows = Vec::new();
+        loop {
+            p.expect_symbol('(')?;
+            let mut literals = Vec::new();
+            loop {
+                literals.push(p.next_literal()?);
+                if p.next_if_symbol(')') {
+                    break;
+                }
+                p.expect_symbol(',')?;
+            }
+            rows.push(literals);
+            if !p.next_if_symbol(',') {
+                break;
+            }
+        }
+        if !p.at_end() {
+            return Err(Error::Value(format!("Unsupported INSERT into table {}", name)));
+        }
+        self.rows(&name, columns, rows)
+    }
+
+    /// Imports a COPY statement, reading its text-format data from the scanner.
+    fn copy(&mut self, mut p: StatementParser, scanner: &mut Scanner) -> Result<()> {
+        let name = p.next_name()?;
+        let columns = if p.peek_symbol('(') { Some(p.next_ident_list()?) } else { None };
+        if !p.next_if_words(&["FROM", "STDIN"]) || !p.at_end() {
+            return Err(Error::Value(format!(
+                "Unsupported COPY into table {}, only text data from stdin is supported",
+                name
+            )));
+        }
+        let mut rows = Vec::new();
+        scanner.skip_line();
+        loop {
+            match scanner.next_line() {
+                Some("\\.") => break,
+                Some(line) => rows.push(line.split('\t').map(unescape_copy).collect()),
+                None => return Err(Error::Value(format!("Unterminated COPY data for {}", name))),
+            }
+        }
+        self.rows(&name, columns, rows)
+    }
+
+    /// Converts and adds rows to a table, given as literals for the given columns, or all
+    /// columns if None.
+    fn rows(
+        &mut self,
+        name: &str,
+        columns: Option<Vec<String>>,
+        rows: Vec<Vec<Literal>>,
+    ) -> Result<()> {
+        let Some(t) = table_index(&self.tables, name).map(|i| &mut self.tables[i]) else {
+            if self.unknown_tables.insert(name.to_string()) {
+                self.warnings.push(format!("Skipped data for undefined table {}", name));
+            }
+            return Ok(());
+        };
+        let indexes = match columns {
+            Some(columns) => columns
+                .iter()
+                .map(|c| {
+                    column_index(&t.table, c).ok_or_else(|| {
+                        Error::Value(format!("Column {} not found in table {}", c, name))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?,
+            None => (0..t.table.columns.len()).collect(),
+        };
+        for literals in rows {
+            if literals.len() != indexes.len() {
+                return Err(Error::Value(format!(
+                    "Row for table {} has {} values, expected {}",
+                    name,
+                    literals.len(),
+                    indexes.len()
+                )));
+            }
+            let mut row: Row =
+                t.table.columns.iter().map(|c| c.default.clone().unwrap_or(Value::Null)).collect();
+            for (literal, i) in literals.into_iter().zip(&indexes) {
+                let column = &t.table.columns[*i];
+                row[*i] = convert(literal, &column.datatype).map_err(|err| {
+                    Error::Value(format!("Invalid value for {}.{}: {}", name, column.name, err))
+                })?;
+            }
+            t.rows.push(row);
+        }
+        Ok(())
+    }
+
+    /// Finishes the import, validating the table schemas.
+    fn finish(mut self) -> Import {
+        let mut tables = Vec::new();
+        for mut t in std::mem::take(&mut self.tables) {
+            let name = t.table.name.clone();
+            match t.primary_key.as_slice() {
+                [pk] => match column_index(&t.table, pk) {
+                    Some(i) => {
+                        let column = &mut t.table.columns[i];
+                        column.primary_key = true;
+                        column.unique = true;
+                        column.index = false;
+                        column.include.clear();
+                        t.not_null.insert(column.name.clone());
+                    }
+                    None => {
+                        self.warnings.push(format!(
+                            "Skipped table {}: primary key column {} does not exist",
+                            name, pk
+                        ));
+                        continue;
+                    }
+                },
+                [] => {
+                    self.warnings.push(format!("Skipped table {}: no primary key", name));
+                    continue;
+                }
+                pk => {
+                    self.warnings.push(format!(
+                        "Skipped table {}: primary key ({}) is not supported, must be a single column",
+                        name,
+                        pk.join(", ")
+                    ));
+                    continue;
+                }
+            }
+            let pk = t.table.columns.iter().find(|c| c.primary_key).map(|c| c.name.clone());
+            for column in t.table.columns.iter_mut() {
+                // Primary keys are always stored in indexes.
+                column.include.retain(|c| Some(c) != pk.as_ref());
+                column.nullable = !t.not_null.contains(&column.name);
+                if column.nullable && column.default.is_none() {
+                    column.default = Some(Value::Null);
+                } else if !column.nullable && column.default == Some(Value::Null) {
+                    column.default = None;
+                }
+            }
+            tables.push(t);
+        }
+
+        // Only keep foreign keys that reference the primary key of an imported table.
+        let primary_keys: Vec<Table> = tables
+            .iter()
+            .map(|t| Table {
+                name: t.table.name.clone(),
+                columns: t.table.columns.iter().filter(|c| c.primary_key).cloned().collect(),
+                ttl: None,
+                codec: Codec::default(),
+                layout: Layout::default(),
+            })
+            .collect();
+        for t in tables.iter_mut() {
+            for (column, target, target_column) in std::mem::take(&mut t.references) {
+                let Some(target) = primary_keys.iter().find(|pk| pk.name == target).or_else(|| {
+                    primary_keys.iter().find(|pk| pk.name.eq_ignore_ascii_case(&target))
+                }) else {
+                    self.warnings.push(format!(
+                        "Ignored foreign key {}.{}: table {} was not imported",
+                        t.table.name, column, target
+                    ));
+                    continue;
+                };
+                if target_column.is_some_and(|c| column_index(target, &c).is_none()) {
+                    self.warnings.push(format!(
+                        "Ignored foreign key {}.{}: only references to primary keys are supported",
+                        t.table.name, column
+                    ));
+                    continue;
+                }
+                match column_index(&t.table, &column) {
+                    Some(i) => t.table.columns[i].references = Some(target.name.clone()),
+                    None => self.warnings.push(format!(
+                        "Ignored foreign key on unknown column {}.{}",
+                        t.table.name, column
+                    )),
+                }
+            }
+        }
+
+        Import {
+            tables: tables.into_iter().map(|t| (t.table, t.rows)).collect(),
+            warnings: self.warnings,
+            skipped: self.skipped,
+        }
+    }
+}
+
+/// Returns the index of the named table. SQLite names are case-insensitive, so this falls back
+/// to a case-insensitive match.
+fn table_index(tables: &[ImportTable], name: &str) -> Option<usize> {
+    tables
+        .iter()
+        .position(|t| t.table.name == name)
+        .or_else(|| tables.iter().position(|t| t.table.name.eq_ignore_ascii_case(name)))
+}
+
+/// Returns the index of the named column, falling back to a case-insensitive match.
+fn column_index(table: &Table, name: &str) -> Option<usize> {
+    table
+        .columns
+        .iter()
+        .position(|c| c.name == name)
+        .or_else(|| table.columns.iter().position(|c| c.name.eq_ignore_ascii_case(name)))
+}
+
+/// A literal value from a dump, before conversion to a column's datatype.
+#[derive(Clone, Debug, PartialEq)]
+enum Literal {
+    Null,
+    Boolean(bool),
+    Number(String),
+    String(String),
+}
+
+/// Converts a literal to a value of the given datatype.
+fn convert(literal: Literal, datatype: &DataType) -> Result<Value> {
+    let invalid = |s: &str| Error::Value(format!("can't convert {} to {}", s, datatype));
+    Ok(match (literal, datatype) {
+        (Literal::Null, _) => Value::Null,
+        (Literal::Boolean(b), DataType::Boolean) => Value::Boolean(b),
+        (Literal::Boolean(b), DataType::Integer) => Value::Integer(b as i64),
+        (Literal::Boolean(b), DataType::Float) => Value::Float(b as i64 as f64),
+        (Literal::Boolean(b), DataType::String) => Value::String(b.to_string()),
+        (Literal::Number(n) | Literal::String(n), DataType::Integer) => {
+            Value::Integer(n.trim().parse().map_err(|_| invalid(&n))?)
+        }
+        (Literal::Number(n) | Literal::String(n), DataType::Float) => {
+            Value::Float(n.trim().parse().map_err(|_| invalid(&n))?)
+        }
+        (Literal::Number(n) | Literal::String(n), DataType::Boolean) => {
+            match n.trim().to_lowercase().as_str() {
+                "t" | "true" | "y" | "yes" | "on" | "1" => Value::Boolean(true),
+                "f" | "false" | "n" | "no" | "off" | "0" => Value::Boolean(false),
+                _ => return Err(invalid(&n)),
+            }
+        }
+        (Literal::Number(n) | Literal::String(n), DataType::String) => Value::String(n),
+    })
+}
+
+/// Unescapes a COPY text-format value, where \N is NULL.
+fn unescape_copy(value: &str) -> Literal {
+    if value == "\\N" {
+        return Literal::Null;
+    }
+    let mut s = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => s.extend(chars.next().map(unescape)),
+            c => s.push(c),
+        }
+    }
+    Literal::String(s)
+}
+
+/// Decodes SQLite unistr() escapes, i.e. \\, \XXXX, \uXXXX, \+XXXXXX, and \UXXXXXXXX.
+fn unistr(s: &str) -> Result<String> {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('\\') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        let (skip, len) = match rest.chars().next() {
+            Some('\\') => {
+                result.push('\\');
+                rest = &rest[1..];
+                continue;
+            }
+            Some('u') => (1, 4),
+            Some('+') => (1, 6),
+            Some('U') => (1, 8),
+            _ => (0, 4),
+        };
+        let c = rest
+            .get(skip..skip + len)
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .and_then(char::from_u32)
+            .ok_or_else(|| Error::Parse(format!("Invalid unistr() escape in {}", s)))?;
+        result.push(c);
+        rest = &rest[skip + len..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Unescapes a backslash-escaped character.
+fn unescape(c: char) -> char {
+    match c {
+        'b' => '\x08',
+        'f' => '\x0c',
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        'v' => '\x0b',
+        c => c,
+    }
+}
+
+/// A dump token. Unlike toyDB's lexer, this accepts the syntax of other databases' dumps.
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// An unquoted word, as written.
+    Word(String),
+    /// A quoted identifier.
+    Ident(String),
+    /// A string literal.
+    String(String),
+    /// A numeric literal.
+    Number(String),
+    /// Any other character.
+    Symbol(char),
+}
+
+/// Splits a dump into statement tokens, skipping comments.
+struct Scanner<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input, pos: 0 }
+    }
+
+    /// Returns the tokens of the next statement, excluding the terminating semicolon. Trigger
+    /// bodies, which contain semicolons, are included in the statement.
+    fn next_statement(&mut self) -> Result<Option<Vec<Token>>> {
+        let mut tokens = Vec::new();
+        let mut in_trigger = false;
+        while let Some(token) = self.next_token()? {
+            if token == Token::Symbol(';') {
+                let ends_trigger =
+                    matches!(tokens.last(), Some(Token::Word(w)) if w.eq_ignore_ascii_case("END"));
+                if !in_trigger || ends_trigger {
+                    return Ok(Some(tokens));
+                }
+            }
+            if let Token::Word(w) = &token {
+                in_trigger = in_trigger
+                    || (w.eq_ignore_ascii_case("BEGIN")
+                        && tokens.iter().take(4).any(
+                            |t| matches!(t, Token::Word(w) if w.eq_ignore_ascii_case("TRIGGER")),
+                        ));
+            }
+            tokens.push(token);
+        }
+        Ok((!tokens.is_empty()).then_some(tokens))
+    }
+
+    /// Skips the rest of the current line.
+    fn skip_line(&mut self) {
+        self.pos = match self.input[self.pos..].find('\n') {
+            Some(end) => self.pos + end + 1,
+            None => self.input.len(),
+        };
+    }
+
+    /// Returns the next line, for COPY data.
+    fn next_line(&mut self) -> Option<&'a str> {
+        if self.pos >= self.input.len() {
+            return None;
+        }
+        let rest = &self.input[self.pos..];
+        let line = rest.split('\n').next().unwrap_or_default();
+        self.pos += line.len() + 1;
+        Some(line.strip_suffix('\r').unwrap_or(line))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn peek_at(&self, n: usize) -> Option<char> {
+        self.input[self.pos..].chars().nth(n)
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// Returns the next token, skipping whitespace and comments.
+    fn next_token(&mut self) -> Result<Option<Token>> {
+        loop {
+            match (self.peek(), self.peek_at(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.next_char();
+                }
+                (Some('-'), Some('-')) => {
+                    let end =
+                        self.input[self.pos..].find('\n').unwrap_or(self.input.len() - self.pos);
+                    self.pos += end;
+                }
+                // psql meta-commands, e.g. \connect or \restrict.
+                (Some('\\'), _) if self.input[..self.pos].ends_with('\n') || self.pos == 0 => {
+                    self.skip_line();
+                }
+                (Some('/'), Some('*')) => match self.input[self.pos + 2..].find("*/") {
+                    Some(end) => self.pos += end + 4,
+                    None => return Err(Error::Value("Unterminated comment".into())),
+                },
+                _ => break,
+            }
+        }
+        let Some(c) = self.next_char() else {
+            return Ok(None);
+        };
+        Ok(Some(match c {
+            '\'' => Token::String(self.next_quoted('\'', '\'')?),
+            '"' => Token::Ident(self.next_quoted('"', '"')?),
+            '`' => Token::Ident(self.next_quoted('`', '`')?),
+            '[' if self.peek() != Some(']') => Token::Ident(self.next_quoted('[', ']')?),
+            '$' if self.peek().is_some_and(|c| c == '$' || c.is_alphabetic()) => {
+                let Some(end) = self.input[self.pos..].find('$') else {
+                    return Ok(Some(Token::Symbol('$')));
+                };
+                let tag = format!("${}$", &self.input[self.pos..self.pos + end]);
+                self.pos += end + 1;
+                let Some(len) = self.input[self.pos..].find(&tag) else {
+                    return Err(Error::Value("Unterminated dollar-quoted string".into()));
+                };
+                let s = self.input[self.pos..self.pos + len].to_string();
+                self.pos += len + tag.len();
+                Token::String(s)
+            }
+            c if c.is_ascii_digit()
+                || (c == '.' && self.peek().is_some_and(|c| c.is_ascii_digit())) =>
+            {
+                let mut n = c.to_string();
+                while let Some(c) = self.peek() {
+                    if c.is_ascii_digit() || c == '.' {
+                        n.push(c);
+                    } else if (c == 'e' || c == 'E')
+                        && self
+                            .peek_at(1)
+                            .is_some_and(|c| c.is_ascii_digit() || c == '-' || c == '+')
+                    {
+                        n.push(c);
+                        self.next_char();
+                        n.push(self.peek().unwrap_or_default());
+                    } else {
+                        break;
+                    }
+                    self.next_char();
+                }
+                Token::Number(n)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                // Escape strings (E'') and blobs (X'').
+                if self.peek() == Some('\'') && matches!(c, 'e' | 'E') {
+                    self.next_char();
+                    return Ok(Some(Token::String(self.next_escaped()?)));
+                }
+                // Blobs are imported as hex strings.
+                if self.peek() == Some('\'') && matches!(c, 'x' | 'X') {
+                    self.next_char();
+                    return Ok(Some(Token::String(self.next_quoted('\'', '\'')?)));
+                }
+                let mut word = c.to_string();
+                while let Some(c) =
+                    self.peek().filter(|c| c.is_alphanumeric() || *c == '_' || *c == '$')
+                {
+                    word.push(c);
+                    self.next_char();
+                }
+                Token::Word(word)
+            }
+            c => Token::Symbol(c),
+        }))
+    }
+
+    /// Returns a quoted string, where a doubled end quote is an escaped quote.
+    fn next_quoted(&mut self, start: char, end: char) -> Result<String> {
+        let mut s = String::new();
+        loop {
+            match self.next_char() {
+                Some(c) if c == end && self.peek() == Some(end) && start == end => {
+                    self.next_char();
+                    s.push(c);
+                }
+                Some(c) if c == end => return Ok(s),
+                Some(c) => s.push(c),
+                None => {
+                    return Err(Error::Value(format!("Unterminated quoted string {}{}", start, s)))
+                }
+            }
+        }
+    }
+
+    /// Returns a PostgreSQL escape string, with backslash escapes.
+    fn next_escaped(&mut self) -> Result<String> {
+        let mut s = String::new();
+        loop {
+            match self.next_char() {
+                Some('\\') => s.extend(self.next_char().map(unescape)),
+                Some('\'') if self.peek() == Some('\'') => {
+                    self.next_char();
+                    s.push('\'');
+                }
+                Some('\'') => return Ok(s),
+                Some(c) => s.push(c),
+                None => return Err(Error::Value(format!("Unterminated quoted string E'{}", s))),
+            }
+        }
+    }
+}
+
+/// Parses the tokens of a single statement.
+struct StatementParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl StatementParser {
+    fn new(tokens: Vec<Token>) -> Self {
+        Self { tokens, pos: 0 }
+    }
+
+    /// Returns the kind of statement, i.e. its leading keywords, for skipped statements.
+    fn kind(&self) -> String {
+        const MODIFIERS: [&str; 6] = ["OR", "REPLACE", "UNIQUE", "TEMP", "TEMPORARY", "UNLOGGED"];
+        let mut words = self.tokens.iter().map_while(|t| match t {
+            Token::Word(w) => Some(w.to_uppercase()),
+            _ => None,
+        });
+        match words.next() {
+            Some(first) if matches!(first.as_str(), "CREATE" | "ALTER" | "DROP") => {
+                match words.find(|w| !MODIFIERS.contains(&w.as_str())) {
+                    Some(object) => format!("{} {}", first, object),
+                    None => first,
+                }
+            }
+            Some(first) => first,
+            None => "unknown".into(),
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek_word(&self, word: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some(Token::Word(w)) if w.eq_ignore_ascii_case(word))
+    }
+
+    fn peek_symbol(&self, symbol: char) -> bool {
+        self.tokens.get(self.pos) == Some(&Token::Symbol(symbol))
+    }
+
+    /// Returns true if the next tokens start a table constraint.
+    fn peek_constraint(&self) -> bool {
+        ["CONSTRAINT", "PRIMARY", "UNIQUE", "FOREIGN", "CHECK"].iter().any(|w| self.peek_word(w))
+    }
+
+    fn next_if_word(&mut self, word: &str) -> bool {
+        let found = self.peek_word(word);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    /// Consumes the given sequence of words, if they're next.
+    fn next_if_words(&mut self, words: &[&str]) -> bool {
+        let start = self.pos;
+        if words.iter().all(|w| self.next_if_word(w)) {
+            return true;
+        }
+        self.pos = start;
+        false
+    }
+
+    fn next_if_symbol(&mut self, symbol: char) -> bool {
+        let found = self.peek_symbol(symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<()> {
+        if !self.next_if_word(word) {
+            return Err(self.unexpected(word));
+        }
+        Ok(())
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<()> {
+        if !self.next_if_symbol(symbol) {
+            return Err(self.unexpected(&symbol.to_string()));
+        }
+        Ok(())
+    }
+
+    fn unexpected(&self, expected: &str) -> Error {
+        match self.tokens.get(self.pos) {
+            Some(token) => Error::Parse(format!("Expected {}, found {:?}", expected, token)),
+            None => Error::Parse(format!("Expected {}, found end of statement", expected)),
+        }
+    }
+
+    /// Returns the next identifier. Unquoted identifiers are lowercased, like toyDB does.
+    fn next_ident(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Word(w)) => Ok(w.to_lowercase()),
+            Some(Token::Ident(i)) => Ok(i),
+            _ => {
+                self.pos -= 1;
+                Err(self.unexpected("identifier"))
+            }
+        }
+    }
+
+    /// Returns the next possibly schema-qualified name, without the schema.
+    fn next_name(&mut self) -> Result<String> {
+        let mut name = self.next_ident()?;
+        while self.next_if_symbol('.') {
+            name = self.next_ident()?;
+        }
+        Ok(name)
+    }
+
+    /// Returns a parenthesized identifier list, ignoring index column options.
+    fn next_ident_list(&mut self) -> Result<Vec<String>> {
+        self.expect_symbol('(')?;
+        let mut idents = Vec::new();
+        loop {
+            idents.push(self.next_ident()?);
+            if self.peek_symbol('(') {
+                return Err(self.unexpected("identifier"));
+            }
+            while !self.peek_symbol(',') && !self.peek_symbol(')') && !self.at_end() {
+                self.skip_clause_token();
+            }
+            if self.next_if_symbol(')') {
+                return Ok(idents);
+            }
+            self.expect_symbol(',')?;
+        }
+    }
+
+    /// Returns the target of a REFERENCES clause, and skips any actions.
+    fn next_reference(&mut self) -> Result<(String, Option<String>)> {
+        let table = self.next_name()?;
+        let column = match self.peek_symbol('(') {
+            true => match self.next_ident_list()?.as_slice() {
+                [column] => Some(column.clone()),
+                _ => return Err(Error::Parse(format!("Invalid reference to table {}", table))),
+            },
+            false => None,
+        };
+        loop {
+            if self.next_if_word("ON") {
+                self.next_ident()?;
+                if !self.next_if_word("CASCADE") && !self.next_if_word("RESTRICT") {
+                    let _ = self.next_if_words(&["SET", "NULL"])
+                        || self.next_if_words(&["SET", "DEFAULT"])
+                        || self.next_if_words(&["NO", "ACTION"]);
+                }
+            } else if self.next_if_word("MATCH") {
+                self.next_ident()?;
+            } else if self.next_if_words(&["NOT", "DEFERRABLE"]) || self.next_if_word("DEFERRABLE")
+            {
+            } else if self.next_if_word("INITIALLY") {
+                self.next_ident()?;
+            } else {
+                return Ok((table, column));
+            }
+        }
+    }
+
+    /// Returns the next datatype, using SQLite's type affinity rules for its name. Type
+    /// arguments and array brackets are skipped.
+    fn next_datatype(&mut self) -> DataType {
+        let mut name = String::new();
+        while let Some(Token::Word(w)) = self.tokens.get(self.pos) {
+            const CONSTRAINTS: [&str; 12] = [
+                "CONSTRAINT",
+                "PRIMARY",
+                "NOT",
+                "NULL",
+                "UNIQUE",
+                "DEFAULT",
+                "REFERENCES",
+                "CHECK",
+                "COLLATE",
+                "GENERATED",
+                "AS",
+                "AUTOINCREMENT",
+            ];
+            if CONSTRAINTS.iter().any(|c| w.eq_ignore_ascii_case(c)) {
+                break;
+            }
+            name.push_str(&w.to_uppercase());
+            name.push(' ');
+            self.pos += 1;
+            if self.peek_symbol('(') {
+                self.skip_clause_token();
+            }
+            while self.peek_symbol('[') || self.peek_symbol(']') {
+                self.pos += 1;
+            }
+        }
+        if name.starts_with("INTERVAL") || name.starts_with("POINT") {
+            DataType::String
+        } else if name.contains("INT") || name.contains("SERIAL") {
+            DataType::Integer
+        } else if name.contains("CHAR") || name.contains("CLOB") || name.contains("TEXT") {
+            DataType::String
+        } else if ["REAL", "FLOA", "DOUB", "NUMERIC", "DECIMAL"].iter().any(|t| name.contains(t)) {
+            DataType::Float
+        } else if name.contains("BOOL") {
+            DataType::Boolean
+        } else {
+            DataType::String
+        }
+    }
+
+    /// Returns the next literal value. Casts are ignored, and SQLite's replace(), char(), and
+    /// unistr() functions are evaluated, since its dumps use them to encode newlines.
+    fn next_literal(&mut self) -> Result<Literal> {
+        let start = self.pos;
+        let literal = match self.next() {
+            Some(Token::Symbol('(')) => {
+                let literal = self.next_literal()?;
+                self.expect_symbol(')')?;
+                literal
+            }
+            Some(Token::Symbol(sign @ ('-' | '+'))) => match self.next() {
+                Some(Token::Number(n)) if sign == '-' => Literal::Number(format!("-{}", n)),
+                Some(Token::Number(n)) => Literal::Number(n),
+                _ => {
+                    self.pos = start;
+                    return Err(self.unexpected("literal"));
+                }
+            },
+            Some(Token::Number(n)) => Literal::Number(n),
+            Some(Token::String(s)) => Literal::String(s),
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("NULL") => Literal::Null,
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("TRUE") => Literal::Boolean(true),
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("FALSE") => Literal::Boolean(false),
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("CHAR") && self.peek_symbol('(') => {
+                let mut s = String::new();
+                self.expect_symbol('(')?;
+                loop {
+                    let code = match self.next_literal()? {
+                        Literal::Number(n) => n.parse::<u32>().ok().and_then(char::from_u32),
+                        _ => None,
+                    };
+                    s.push(code.ok_or_else(|| Error::Parse("Invalid char() argument".into()))?);
+                    if self.next_if_symbol(')') {
+                        break;
+                    }
+                    self.expect_symbol(',')?;
+                }
+                Literal::String(s)
+            }
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("UNISTR") && self.peek_symbol('(') => {
+                self.expect_symbol('(')?;
+                let Literal::String(s) = self.next_literal()? else {
+                    return Err(Error::Parse("Invalid unistr() argument".into()));
+                };
+                self.expect_symbol(')')?;
+                Literal::String(unistr(&s)?)
+            }
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("REPLACE") && self.peek_symbol('(') => {
+                let mut args = Vec::new();
+                self.expect_symbol('(')?;
+                for i in 0..3 {
+                    if i > 0 {
+                        self.expect_symbol(',')?;
+                    }
+                    match self.next_literal()? {
+                        Literal::String(s) => args.push(s),
+                        _ => return Err(Error::Parse("Invalid replace() argument".into())),
+                    }
+                }
+                self.expect_symbol(')')?;
+                Literal::String(args[0].replace(&args[1], &args[2]))
+            }
+            _ => {
+                self.pos = start;
+                return Err(self.unexpected("literal"));
+            }
+        };
+        // Skip PostgreSQL casts, e.g. 'foo'::character varying.
+        while self.peek_symbol(':') && self.tokens.get(self.pos + 1) == Some(&Token::Symbol(':')) {
+            self.pos += 2;
+            self.next_datatype();
+        }
+        Ok(literal)
+    }
+
+    /// Skips a token, or a parenthesized group of tokens.
+    fn skip_clause_token(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.next() {
+            match token {
+                Token::Symbol('(') => depth += 1,
+                Token::Symbol(')') => depth -= 1,
+                _ => {}
+            }
+            if depth <= 0 {
+                return;
+            }
+        }
+    }
+
+    /// Skips an expression, up to the next column constraint keyword or top-level comma or
+    /// closing parenthesis.
+    fn skip_expression(&mut self) {
+        while !self.at_end() && !self.peek_symbol(',') && !self.peek_symbol(')') {
+            const STOP: [&str; 6] =
+                ["CONSTRAINT", "PRIMARY", "NOT", "NULL", "UNIQUE", "REFERENCES"];
+            if STOP.iter().any(|w| self.peek_word(w)) {
+                return;
+            }
+            self.skip_clause_token();
+        }
+    }
+
+    /// Skips a clause, up to the next top-level comma or closing parenthesis.
+    fn skip_clause(&mut self) {
+        while !self.at_end() && !self.peek_symbol(',') && !self.peek_symbol(')') {
+            self.skip_clause_token();
+        }
+    }
+
+    /// Returns the statement text from the given token position to the current position.
+    fn text(&self, start: usize) -> String {
+        let end = self.pos.min(self.tokens.len());
+        let mut text = String::new();
+        let mut prev: Option<&Token> = None;
+        for token in &self.tokens[start.min(end)..end] {
+            // Separate words, and operators from words and parentheses.
+            let space = match (prev, token) {
+                (None, _) => false,
+                (Some(Token::Symbol(p)), Token::Symbol('(')) => !"(.:".contains(*p),
+                (_, Token::Symbol(',' | ')' | '(' | '.' | ':')) => false,
+                (Some(Token::Symbol('(' | '.' | ':')), _) => false,
+                (Some(Token::Symbol(_)), Token::Symbol(_)) => false,
+                _ => true,
+            };
+            if space {
+                text.push(' ');
+            }
+            match token {
+                Token::Word(w) | Token::Number(w) => text.push_str(w),
+                Token::Ident(i) => text.push_str(&format_ident(i)),
+                Token::String(s) => text.push_str(&format!("'{}'", s.replace('\'', "''"))),
+                Token::Symbol(c) => text.push(*c),
+            }
+            prev = Some(token);
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// Returns the imported table schemas as SQL, and their rows.
+    fn tables(import: &Import) -> Vec<(String, Vec<Row>)> {
+        import.tables.iter().map(|(t, rows)| (t.to_string(), rows.clone())).collect()
+    }
+
+    #[test]
+    fn postgres() -> Result<()> {
+        let import = Import::parse(
+            r#"
+\restrict abc
+-- PostgreSQL database dump
+SET statement_timeout = 0;
+SELECT pg_catalog.set_config('search_path', '', false);
+CREATE TABLE public.genres (
+    id integer NOT NULL,
+    name character varying(50) NOT NULL
+);
+ALTER TABLE public.genres OWNER TO postgres;
+CREATE TABLE public.movies (
+    id bigint NOT NULL,
+    title text DEFAULT 'untitled'::text NOT NULL,
+    genre_id integer,
+    rating double precision DEFAULT 0,
+    released date,
+    created timestamp with time zone DEFAULT now(),
+    sequel_of bigint,
+    CONSTRAINT positive CHECK ((rating >= (0)::double precision))
+);
+CREATE FUNCTION public.f() RETURNS integer LANGUAGE sql AS $$ SELECT 1; $$;
+COPY public.genres (id, name) FROM stdin;
+1	Sci-Fi
+2	
+\.
+COPY public.movies (id, title, genre_id, rating, released, sequel_of) FROM stdin;
+2	Stalker\tII	1	Infinity	1979-05-25	1
+1	Stalker\\\n	\N	-Infinity	\N	\N
+\.
+INSERT INTO public.genres VALUES (3, E'it\'s');
+ALTER TABLE ONLY public.genres ADD CONSTRAINT genres_pkey PRIMARY KEY (id);
+ALTER TABLE ONLY public.movies ADD CONSTRAINT movies_pkey PRIMARY KEY (id);
+CREATE INDEX movies_released ON public.movies USING btree (released) INCLUDE (title, id);
+CREATE INDEX movies_lower ON public.movies USING btree (lower(title));
+ALTER TABLE ONLY public.movies
+    ADD CONSTRAINT movies_genre_id_fkey FOREIGN KEY (genre_id) REFERENCES public.genres(id);
+ALTER TABLE ONLY public.movies
+    ADD CONSTRAINT movies_sequel_of_fkey FOREIGN KEY (sequel_of) REFERENCES public.movies(id);
+\unrestrict abc
+"#,
+        )?;
+        assert_eq!(
+            tables(&import),
+            vec![
+                (
+                    "CREATE TABLE genres (\n  id INTEGER PRIMARY KEY,\n  name STRING NOT NULL\n)"
+                        .into(),
+                    vec![
+                        vec![Value::Integer(1), Value::String("Sci-Fi".into())],
+                        vec![Value::Integer(2), Value::String("".into())],
+                        vec![Value::Integer(3), Value::String("it's".into())],
+                    ]
+                ),
+                (
+                    "CREATE TABLE movies (
+  id INTEGER PRIMARY KEY,
+  title STRING NOT NULL DEFAULT 'untitled',
+  genre_id INTEGER DEFAULT NULL REFERENCES genres,
+  rating FLOAT DEFAULT 0.0,
+  released STRING DEFAULT NULL INDEX INCLUDE (title),
+  created STRING DEFAULT NULL,
+  sequel_of INTEGER DEFAULT NULL REFERENCES movies
+)"
+                    .into(),
+                    vec![
+                        vec![
+                            Value::Integer(2),
+                            Value::String("Stalker\tII".into()),
+                            Value::Integer(1),
+                            Value::Float(f64::INFINITY),
+                            Value::String("1979-05-25".into()),
+                            Value::Null,
+                            Value::Integer(1),
+                        ],
+                        vec![
+                            Value::Integer(1),
+                            Value::String("Stalker\\\n".into()),
+                            Value::Null,
+                            Value::Float(f64::NEG_INFINITY),
+                            Value::Null,
+                            Value::Null,
+                            Value::Null,
+                        ],
+                    ]
+                ),
+            ]
+        );
+        assert_eq!(
+            import.warnings,
+            vec![
+                "Dropped default now() for column movies.created: only literals are supported",
+                "Ignored constraint CHECK ((rating >= (0)::double precision)) on table movies",
+                "Ignored index on table movies: only column indexes are supported",
+            ]
+        );
+        assert_eq!(
+            import.skipped,
+            BTreeMap::from([
+                ("ALTER TABLE".to_string(), 1),
+                ("CREATE FUNCTION".to_string(), 1),
+                ("SELECT".to_string(), 1),
+                ("SET".to_string(), 1),
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sqlite() -> Result<()> {
+        let import = Import::parse(
+            r#"
+PRAGMA foreign_keys=OFF;
+BEGIN TRANSACTION;
+CREATE TABLE artists (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL, country VARCHAR(2) DEFAULT 'NO', active BOOLEAN DEFAULT 1);
+INSERT INTO artists VALUES(1,'Björk','IS',1);
+INSERT INTO artists VALUES(2,unistr('multi\u000aline'),'NO',0);
+INSERT INTO artists VALUES(3,replace('a\nb','\n',char(10)),'NO',NULL);
+CREATE TABLE IF NOT EXISTS "Albums" ([AlbumId] INTEGER NOT NULL, `Title` NVARCHAR(160), artist INT, price NUMERIC(10,2), cover BLOB, PRIMARY KEY (albumid), FOREIGN KEY (artist) REFERENCES Artists (id) ON DELETE NO ACTION);
+INSERT INTO Albums VALUES(1,'Debut',1,12.5,X'beef');
+INSERT INTO Albums(AlbumId, artist) VALUES(2,2);
+CREATE TABLE nopk (a, b);
+INSERT INTO nopk VALUES(1,2);
+CREATE TABLE pairs (a INT, b INT, PRIMARY KEY (a, b));
+CREATE TABLE IF NOT EXISTS sqlite_sequence(name,seq);
+DELETE FROM sqlite_sequence;
+INSERT INTO sqlite_sequence VALUES('artists',3);
+CREATE TRIGGER trg AFTER INSERT ON artists BEGIN UPDATE artists SET name = name; SELECT 1; END;
+CREATE INDEX albums_artist ON "Albums" (artist);
+COMMIT;
+"#,
+        )?;
+        assert_eq!(
+            tables(&import),
+            vec![
+                (
+                    "CREATE TABLE artists (
+  id INTEGER PRIMARY KEY,
+  name STRING NOT NULL,
+  country STRING DEFAULT 'NO',
+  active BOOLEAN DEFAULT TRUE
+)"
+                    .into(),
+                    vec![
+                        vec![
+                            Value::Integer(1),
+                            Value::String("Björk".into()),
+                            Value::String("IS".into()),
+                            Value::Boolean(true),
+                        ],
+                        vec![
+                            Value::Integer(2),
+                            Value::String("multi\nline".into()),
+                            Value::String("NO".into()),
+                            Value::Boolean(false),
+                        ],
+                        vec![
+                            Value::Integer(3),
+                            Value::String("a\nb".into()),
+                            Value::String("NO".into()),
+                            Value::Null,
+                        ],
+                    ]
+                ),
+                (
+                    r#"CREATE TABLE "Albums" (
+  "AlbumId" INTEGER PRIMARY KEY,
+  "Title" STRING DEFAULT NULL,
+  artist INTEGER DEFAULT NULL REFERENCES artists INDEX,
+  price FLOAT DEFAULT NULL,
+  cover STRING DEFAULT NULL
+)"#
+                    .into(),
+                    vec![
+                        vec![
+                            Value::Integer(1),
+                            Value::String("Debut".into()),
+                            Value::Integer(1),
+                            Value::Float(12.5),
+                            Value::String("beef".into()),
+                        ],
+                        vec![
+                            Value::Integer(2),
+                            Value::Null,
+                            Value::Integer(2),
+                            Value::Null,
+                            Value::Null,
+                        ],
+                    ]
+                ),
+            ]
+        );
+        assert_eq!(
+            import.warnings,
+            vec![
+                "Skipped table nopk: no primary key",
+                "Skipped table pairs: primary key (a, b) is not supported, must be a single column",
+            ]
+        );
+        assert_eq!(import.row_count(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            Import::parse("CREATE TABLE t (id INTEGER PRIMARY KEY); INSERT INTO t VALUES ('x');"),
+            Err(Error::Value("Invalid value for t.id: can't convert x to INTEGER".into()))
+        );
+        assert_eq!(
+            Import::parse("CREATE TABLE t (id INTEGER PRIMARY KEY); COPY t (id) FROM stdin;\n1\n"),
+            Err(Error::Value("Unterminated COPY data for t".into()))
+        );
+        assert_eq!(
+            Import::parse("INSERT INTO t VALUES ('unterminated);"),
+            Err(Error::Value("Unterminated quoted string 'unterminated);".into()))
+        );
+    }
+}
@@ -1,4 +1,5 @@
-use super::super::types::DataType;
+use super::super::schema::{TriggerEvent, TriggerTiming};
+use super::super::types::{DataType, SampleMethod};
 use crate::error::Result;
 
 use std::collections::BTreeMap;
@@ -14,17 +15,75 @@ pub enum Statement {
     },
     Commit,
     Rollback,
+    /// PREPARE TRANSACTION 'gid', the first phase of a two-phase commit.
+    Prepare(String),
+    CommitPrepared(String),
+    RollbackPrepared(String),
+    ShowPrepared,
     Explain(Box<Statement>),
+    ExplainAnalyze(Box<Statement>),
 
     CreateTable {
         name: String,
         columns: Vec<Column>,
+        /// Table options given as WITH (name = value, ...).
+        options: Vec<(String, Expression)>,
+    },
+    /// CREATE TABLE name AS SELECT ..., or equivalently SELECT ... INTO name.
+    CreateTableAs {
+        name: String,
+        query: Box<Statement>,
     },
     DropTable(String),
+    UndropTable(String),
+    CreateTrigger {
+        name: String,
+        table: String,
+        timing: TriggerTiming,
+        event: TriggerEvent,
+        statement: Box<Statement>,
+        /// The SQL text of the statement, which is stored in the catalog.
+        sql: String,
+    },
+    DropTrigger {
+        name: String,
+        table: String,
+    },
+    CreateIndex {
+        /// The index name, if given.
+        name: Option<String>,
+        table: String,
+        columns: Vec<(String, Order)>,
+    },
+    DropIndex {
+        name: String,
+        table: String,
+    },
+    CreateFunction {
+        name: String,
+        args: Vec<(String, DataType)>,
+        returns: DataType,
+        body: String,
+    },
+    DropFunction(String),
+    /// CREATE TENANT name, which creates an empty keyspace for the tenant.
+    CreateTenant(String),
+    /// DROP TENANT name, which deletes the tenant and all of its data.
+    DropTenant(String),
+    ShowTableStatus,
+    ShowSessions,
+    /// KILL [SESSION | QUERY] id. KILL QUERY only cancels the session's current
+    /// statement.
+    Kill {
+        session: u64,
+        query: bool,
+    },
 
     Delete {
         table: String,
         r#where: Option<Expression>,
+        order: Vec<(Expression, Order, Option<Nulls>)>,
+        limit: Option<Expression>,
     },
     Insert {
         table: String,
@@ -35,18 +94,147 @@ pub enum Statement {
         table: String,
         set: BTreeMap<String, Expression>,
         r#where: Option<Expression>,
+        order: Vec<(Expression, Order, Option<Nulls>)>,
+        limit: Option<Expression>,
     },
 
     Select {
+        hints: Vec<Hint>,
+        /// DISTINCT, if given, with any DISTINCT ON expressions. Empty for plain DISTINCT.
+        distinct: Option<Vec<Expression>>,
         select: Vec<(Expression, Option<String>)>,
         from: Vec<FromItem>,
         r#where: Option<Expression>,
         group_by: Vec<Expression>,
         having: Option<Expression>,
-        order: Vec<(Expression, Order)>,
+        order: Vec<(Expression, Order, Option<Nulls>)>,
         offset: Option<Expression>,
         limit: Option<Expression>,
+        /// WITH ORDINALITY, which appends the 1-based position of each result row.
+        ordinality: bool,
     },
+
+    Set {
+        variable: String,
+        value: Expression,
+    },
+    /// SET CLUSTER SETTING name = value, or = DEFAULT to reset the setting.
+    SetClusterSetting {
+        name: String,
+        value: Option<Expression>,
+    },
+
+    /// DECLARE name CURSOR FOR SELECT ...
+    DeclareCursor {
+        name: String,
+        query: Box<Statement>,
+    },
+    /// FETCH [count | ALL] FROM cursor. The count is None for ALL.
+    Fetch {
+        cursor: String,
+        count: Option<u64>,
+    },
+    CloseCursor(String),
+}
+
+impl Statement {
+    /// Transforms every expression in the statement, including in FROM items and subqueries,
+    /// by applying a closure after descending into it.
+    pub fn transform_expressions<F>(&mut self, f: &mut F) -> Result<()>
+    where
+        F: FnMut(Expression) -> Result<Expression>,
+    {
+        let mut visit = |expr: &mut Expression| {
+            expr.transform_mut(&mut Ok, &mut |e| match e {
+                Expression::Subquery(mut statement) => {
+                    statement.transform_expressions(f)?;
+                    Ok(Expression::Subquery(statement))
+                }
+                e => f(e),
+            })
+        };
+        match self {
+            Self::Explain(statement) | Self::ExplainAnalyze(statement) => {
+                statement.transform_expressions(f)?
+            }
+            Self::DeclareCursor { query, .. } | Self::CreateTableAs { query, .. } => {
+                query.transform_expressions(f)?
+            }
+            Self::Delete { r#where, order, limit, .. } => {
+                r#where.iter_mut().try_for_each(&mut visit)?;
+                order.iter_mut().map(|(e, _, _)| e).try_for_each(&mut visit)?;
+                limit.iter_mut().try_for_each(&mut visit)?;
+            }
+            Self::Insert { values, .. } => values.iter_mut().flatten().try_for_each(&mut visit)?,
+            Self::Update { set, r#where, order, limit, .. } => {
+                set.values_mut().chain(r#where.iter_mut()).try_for_each(&mut visit)?;
+                order.iter_mut().map(|(e, _, _)| e).try_for_each(&mut visit)?;
+                limit.iter_mut().try_for_each(&mut visit)?;
+            }
+            Self::Select {
+                distinct,
+                select,
+                from,
+                r#where,
+                group_by,
+                having,
+                order,
+                offset,
+                limit,
+                ..
+            } => {
+                distinct.iter_mut().flatten().try_for_each(&mut visit)?;
+                select.iter_mut().map(|(e, _)| e).try_for_each(&mut visit)?;
+                from.iter_mut().try_for_each(|item| item.for_each_expression(&mut visit))?;
+                r#where.iter_mut().try_for_each(&mut visit)?;
+                group_by.iter_mut().try_for_each(&mut visit)?;
+                having.iter_mut().try_for_each(&mut visit)?;
+                order.iter_mut().map(|(e, _, _)| e).try_for_each(&mut visit)?;
+                offset.iter_mut().chain(limit.iter_mut()).try_for_each(&mut visit)?;
+            }
+            Self::Set { value, .. } => visit(value)?,
+            Self::SetClusterSetting { value, .. } => value.iter_mut().try_for_each(&mut visit)?,
+            // Trigger statements are bound when the trigger fires.
+            Self::Begin { .. }
+            | Self::Commit
+            | Self::Rollback
+            | Self::Prepare(_)
+            | Self::CommitPrepared(_)
+            | Self::RollbackPrepared(_)
+            | Self::ShowPrepared
+            | Self::CreateTable { .. }
+            | Self::DropTable(_)
+            | Self::UndropTable(_)
+            | Self::CreateTrigger { .. }
+            | Self::DropTrigger { .. }
+            | Self::CreateIndex { .. }
+            | Self::DropIndex { .. }
+            | Self::CreateFunction { .. }
+            | Self::DropFunction(_)
+            | Self::CreateTenant(_)
+            | Self::DropTenant(_)
+            | Self::ShowTableStatus
+            | Self::ShowSessions
+            | Self::Kill { .. }
+            | Self::Fetch { .. }
+            | Self::CloseCursor(_) => {}
+        }
+        Ok(())
+    }
+}
+
+/// An optimizer hint
+#[derive(Clone, Debug, PartialEq)]
+pub enum Hint {
+    /// Don't use index or primary key lookups for the given tables (or
+    /// aliases), or any tables if none are given.
+    NoIndex(Vec<String>),
+    /// Use hash joins where possible, even if disabled for the session.
+    HashJoin,
+    /// Don't use hash joins.
+    NoHashJoin,
+    /// Keep joins in the order they're written.
+    NoJoinOrder,
 }
 
 /// A FROM item
@@ -55,13 +243,61 @@ pub enum FromItem {
     Table {
         name: String,
         alias: Option<String>,
+        sample: Option<TableSample>,
     },
+    /// A join, either on a predicate, on the USING columns, or on all common columns if NATURAL.
     Join {
         left: Box<FromItem>,
         right: Box<FromItem>,
         r#type: JoinType,
         predicate: Option<Expression>,
+        using: Vec<String>,
+        natural: bool,
+    },
+    /// A VALUES list, with optional alias and column names.
+    Values {
+        rows: Vec<Vec<Expression>>,
+        alias: Option<String>,
+        columns: Vec<String>,
     },
+    /// A table function call, with optional alias and column name. The arguments can reference
+    /// earlier FROM items, as with LATERAL.
+    Function {
+        name: String,
+        args: Vec<Expression>,
+        alias: Option<String>,
+        columns: Vec<String>,
+    },
+}
+
+impl FromItem {
+    /// Calls a closure on each expression in the FROM item, including nested items.
+    fn for_each_expression<F>(&mut self, f: &mut F) -> Result<()>
+    where
+        F: FnMut(&mut Expression) -> Result<()>,
+    {
+        match self {
+            Self::Table { sample, .. } => sample
+                .iter_mut()
+                .flat_map(|sample| std::iter::once(&mut sample.percent).chain(&mut sample.seed))
+                .try_for_each(f),
+            Self::Join { left, right, predicate, .. } => {
+                left.for_each_expression(f)?;
+                right.for_each_expression(f)?;
+                predicate.iter_mut().try_for_each(f)
+            }
+            Self::Values { rows, .. } => rows.iter_mut().flatten().try_for_each(f),
+            Self::Function { args, .. } => args.iter_mut().try_for_each(f),
+        }
+    }
+}
+
+/// A TABLESAMPLE clause, with the percentage of rows to sample and an optional REPEATABLE seed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableSample {
+    pub method: SampleMethod,
+    pub percent: Expression,
+    pub seed: Option<Expression>,
 }
 
 /// A JOIN type
@@ -71,6 +307,7 @@ pub enum JoinType {
     Inner,
     Left,
     Right,
+    Full,
 }
 
 /// A column
@@ -83,6 +320,7 @@ pub struct Column {
     pub default: Option<Expression>,
     pub unique: bool,
     pub index: bool,
+    pub include: Vec<String>,
     pub references: Option<String>,
 }
 
@@ -93,6 +331,13 @@ pub enum Order {
     Descending,
 }
 
+/// Sort positions of NULL values
+#[derive(Clone, Debug, PartialEq)]
+pub enum Nulls {
+    First,
+    Last,
+}
+
 /// Expressions
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expression {
@@ -101,6 +346,10 @@ pub enum Expression {
     Literal(Literal),
     Function(String, Vec<Expression>),
     Operation(Operation),
+    Subquery(Box<Statement>),
+    Row(Vec<Expression>),
+    /// A wildcard in a SELECT list, e.g. * or t.*, optionally qualified by a table name.
+    Wildcard(Option<String>),
 }
 
 impl From<Literal> for Expression {
@@ -134,9 +383,14 @@ pub enum Operation {
     Or(Box<Expression>, Box<Expression>),
 
     // Comparison operators
+    Between(Box<Expression>, Box<Expression>, Box<Expression>),
     Equal(Box<Expression>, Box<Expression>),
+    Exists(Box<Expression>),
     GreaterThan(Box<Expression>, Box<Expression>),
     GreaterThanOrEqual(Box<Expression>, Box<Expression>),
+    In(Box<Expression>, Box<Expression>),
+    InList(Box<Expression>, Vec<Expression>),
+    IsDistinctFrom(Box<Expression>, Box<Expression>),
     IsNull(Box<Expression>),
     LessThan(Box<Expression>, Box<Expression>),
     LessThanOrEqual(Box<Expression>, Box<Expression>),
@@ -189,6 +443,8 @@ impl Expression {
             | Self::Operation(Exponentiate(lhs, rhs))
             | Self::Operation(GreaterThan(lhs, rhs))
             | Self::Operation(GreaterThanOrEqual(lhs, rhs))
+            | Self::Operation(In(lhs, rhs))
+            | Self::Operation(IsDistinctFrom(lhs, rhs))
             | Self::Operation(LessThan(lhs, rhs))
             | Self::Operation(LessThanOrEqual(lhs, rhs))
             | Self::Operation(Like(lhs, rhs))
@@ -202,6 +458,7 @@ impl Expression {
             }
 
             Self::Operation(Assert(expr))
+            | Self::Operation(Exists(expr))
             | Self::Operation(Factorial(expr))
             | Self::Operation(IsNull(expr))
             | Self::Operation(Negate(expr))
@@ -209,13 +466,31 @@ impl Expression {
                 Self::replace_with(expr, |e| e.transform(before, after))?
             }
 
-            Self::Function(_, exprs) => {
+            Self::Operation(Between(expr, lower, upper)) => {
+                Self::replace_with(expr, |e| e.transform(before, after))?;
+                Self::replace_with(lower, |e| e.transform(before, after))?;
+                Self::replace_with(upper, |e| e.transform(before, after))?;
+            }
+
+            Self::Operation(InList(expr, list)) => {
+                Self::replace_with(expr, |e| e.transform(before, after))?;
+                for expr in list {
+                    Self::replace_with(expr, |e| e.transform(before, after))?;
+                }
+            }
+
+            Self::Function(_, exprs) | Self::Row(exprs) => {
                 for expr in exprs {
                     Self::replace_with(expr, |e| e.transform(before, after))?;
                 }
             }
 
-            Self::Literal(_) | Self::Field(_, _) | Self::Column(_) => {}
+            // Subqueries have their own scope, and are not descended into.
+            Self::Literal(_)
+            | Self::Field(_, _)
+            | Self::Column(_)
+            | Self::Subquery(_)
+            | Self::Wildcard(_) => {}
         };
         after(self)
     }
@@ -241,6 +516,8 @@ impl Expression {
                 | Self::Operation(Exponentiate(lhs, rhs))
                 | Self::Operation(GreaterThan(lhs, rhs))
                 | Self::Operation(GreaterThanOrEqual(lhs, rhs))
+                | Self::Operation(In(lhs, rhs))
+                | Self::Operation(IsDistinctFrom(lhs, rhs))
                 | Self::Operation(LessThan(lhs, rhs))
                 | Self::Operation(LessThanOrEqual(lhs, rhs))
                 | Self::Operation(Like(lhs, rhs))
@@ -251,12 +528,21 @@ impl Expression {
                 | Self::Operation(Subtract(lhs, rhs)) => lhs.walk(visitor) && rhs.walk(visitor),
 
                 Self::Operation(Assert(expr))
+                | Self::Operation(Exists(expr))
                 | Self::Operation(Factorial(expr))
                 | Self::Operation(IsNull(expr))
                 | Self::Operation(Negate(expr))
                 | Self::Operation(Not(expr)) => expr.walk(visitor),
 
-                Self::Function(_, exprs) => {
+                Self::Operation(Between(expr, lower, upper)) => {
+                    expr.walk(visitor) && lower.walk(visitor) && upper.walk(visitor)
+                }
+
+                Self::Operation(InList(expr, list)) => {
+                    expr.walk(visitor) && list.iter().all(|e| e.walk(visitor))
+                }
+
+                Self::Function(_, exprs) | Self::Row(exprs) => {
                     for expr in exprs {
                         if !expr.walk(visitor) {
                             return false;
@@ -265,7 +551,11 @@ impl Expression {
                     true
                 }
 
-                Self::Literal(_) | Self::Field(_, _) | Self::Column(_) => true,
+                Self::Literal(_)
+                | Self::Field(_, _)
+                | Self::Column(_)
+                | Self::Subquery(_)
+                | Self::Wildcard(_) => true,
             }
     }
 }
@@ -30,6 +30,8 @@ pub enum Token {
     CloseParen,
     Comma,
     Semicolon,
+    /// An optimizer hint, given as a /*+ ... */ comment.
+    Hint(String),
 }
 
 impl std::fmt::Display for Token {
@@ -59,6 +61,7 @@ impl std::fmt::Display for Token {
             Token::CloseParen => ")",
             Token::Comma => ",",
             Token::Semicolon => ";",
+            Token::Hint(hint) => return write!(f, "/*+ {} */", hint),
         })
     }
 }
@@ -72,28 +75,45 @@ impl From<Keyword> for Token {
 /// Lexer keywords
 #[derive(Clone, Debug, PartialEq)]
 pub enum Keyword {
+    After,
+    All,
     And,
     As,
     Asc,
     Begin,
+    Before,
+    Between,
     Bool,
     Boolean,
     By,
     Char,
+    Close,
     Commit,
     Create,
     Cross,
+    Cursor,
     Default,
+    Declare,
     Delete,
     Desc,
+    Distinct,
     Double,
     Drop,
+    Each,
+    Exists,
     Explain,
+    Execute,
     False,
+    Fetch,
     Float,
     From,
+    For,
+    Full,
+    Function,
     Group,
     Having,
+    In,
+    Include,
     Index,
     Infinity,
     Inner,
@@ -104,10 +124,13 @@ pub enum Keyword {
     Is,
     Join,
     Key,
+    Kill,
+    Lateral,
     Left,
     Like,
     Limit,
     NaN,
+    Natural,
     Not,
     Null,
     Of,
@@ -120,22 +143,31 @@ pub enum Keyword {
     Primary,
     Read,
     References,
+    Restore,
+    Returns,
     Right,
     Rollback,
+    Row,
     Select,
     Set,
+    Show,
     String,
     System,
     Table,
+    Tablesample,
     Text,
     Time,
     Transaction,
     True,
+    Trigger,
+    Undrop,
     Unique,
     Update,
+    Using,
     Values,
     Varchar,
     Where,
+    With,
     Write,
 }
 
@@ -143,28 +175,45 @@ impl Keyword {
     #[allow(clippy::should_implement_trait)]
     pub fn from_str(ident: &str) -> Option<Self> {
         Some(match ident.to_uppercase().as_ref() {
+            "AFTER" => Self::After,
+            "ALL" => Self::All,
             "AS" => Self::As,
             "ASC" => Self::Asc,
             "AND" => Self::And,
             "BEGIN" => Self::Begin,
+            "BEFORE" => Self::Before,
+            "BETWEEN" => Self::Between,
             "BOOL" => Self::Bool,
             "BOOLEAN" => Self::Boolean,
             "BY" => Self::By,
             "CHAR" => Self::Char,
+            "CLOSE" => Self::Close,
             "COMMIT" => Self::Commit,
             "CREATE" => Self::Create,
             "CROSS" => Self::Cross,
+            "CURSOR" => Self::Cursor,
             "DEFAULT" => Self::Default,
+            "DECLARE" => Self::Declare,
             "DELETE" => Self::Delete,
             "DESC" => Self::Desc,
+            "DISTINCT" => Self::Distinct,
             "DOUBLE" => Self::Double,
             "DROP" => Self::Drop,
+            "EACH" => Self::Each,
+            "EXISTS" => Self::Exists,
+            "EXECUTE" => Self::Execute,
             "EXPLAIN" => Self::Explain,
             "FALSE" => Self::False,
+            "FETCH" => Self::Fetch,
             "FLOAT" => Self::Float,
+            "FOR" => Self::For,
             "FROM" => Self::From,
+            "FULL" => Self::Full,
+            "FUNCTION" => Self::Function,
             "GROUP" => Self::Group,
             "HAVING" => Self::Having,
+            "IN" => Self::In,
+            "INCLUDE" => Self::Include,
             "INDEX" => Self::Index,
             "INFINITY" => Self::Infinity,
             "INNER" => Self::Inner,
@@ -175,10 +224,13 @@ impl Keyword {
             "IS" => Self::Is,
             "JOIN" => Self::Join,
             "KEY" => Self::Key,
+            "KILL" => Self::Kill,
+            "LATERAL" => Self::Lateral,
             "LEFT" => Self::Left,
             "LIKE" => Self::Like,
             "LIMIT" => Self::Limit,
             "NAN" => Self::NaN,
+            "NATURAL" => Self::Natural,
             "NOT" => Self::Not,
             "NULL" => Self::Null,
             "OF" => Self::Of,
@@ -191,22 +243,31 @@ impl Keyword {
             "PRIMARY" => Self::Primary,
             "READ" => Self::Read,
             "REFERENCES" => Self::References,
+            "RESTORE" => Self::Restore,
+            "RETURNS" => Self::Returns,
             "RIGHT" => Self::Right,
             "ROLLBACK" => Self::Rollback,
+            "ROW" => Self::Row,
             "SELECT" => Self::Select,
             "SET" => Self::Set,
+            "SHOW" => Self::Show,
             "STRING" => Self::String,
             "SYSTEM" => Self::System,
             "TABLE" => Self::Table,
+            "TABLESAMPLE" => Self::Tablesample,
             "TEXT" => Self::Text,
             "TIME" => Self::Time,
             "TRANSACTION" => Self::Transaction,
+            "TRIGGER" => Self::Trigger,
             "TRUE" => Self::True,
+            "UNDROP" => Self::Undrop,
             "UNIQUE" => Self::Unique,
             "UPDATE" => Self::Update,
+            "USING" => Self::Using,
             "VALUES" => Self::Values,
             "VARCHAR" => Self::Varchar,
             "WHERE" => Self::Where,
+            "WITH" => Self::With,
             "WRITE" => Self::Write,
             _ => return None,
         })
@@ -214,28 +275,45 @@ impl Keyword {
 
     pub fn to_str(&self) -> &str {
         match self {
+            Self::After => "AFTER",
+            Self::All => "ALL",
             Self::As => "AS",
             Self::Asc => "ASC",
             Self::And => "AND",
             Self::Begin => "BEGIN",
+            Self::Before => "BEFORE",
+            Self::Between => "BETWEEN",
             Self::Bool => "BOOL",
             Self::Boolean => "BOOLEAN",
             Self::By => "BY",
             Self::Char => "CHAR",
+            Self::Close => "CLOSE",
             Self::Commit => "COMMIT",
             Self::Create => "CREATE",
             Self::Cross => "CROSS",
+            Self::Cursor => "CURSOR",
             Self::Default => "DEFAULT",
+            Self::Declare => "DECLARE",
             Self::Delete => "DELETE",
             Self::Desc => "DESC",
+            Self::Distinct => "DISTINCT",
             Self::Double => "DOUBLE",
             Self::Drop => "DROP",
+            Self::Each => "EACH",
+            Self::Exists => "EXISTS",
+            Self::Execute => "EXECUTE",
             Self::Explain => "EXPLAIN",
             Self::False => "FALSE",
+            Self::Fetch => "FETCH",
             Self::Float => "FLOAT",
+            Self::For => "FOR",
             Self::From => "FROM",
+            Self::Full => "FULL",
+            Self::Function => "FUNCTION",
             Self::Group => "GROUP",
             Self::Having => "HAVING",
+            Self::In => "IN",
+            Self::Include => "INCLUDE",
             Self::Index => "INDEX",
             Self::Infinity => "INFINITY",
             Self::Inner => "INNER",
@@ -246,10 +324,13 @@ impl Keyword {
             Self::Is => "IS",
             Self::Join => "JOIN",
             Self::Key => "KEY",
+            Self::Kill => "KILL",
+            Self::Lateral => "LATERAL",
             Self::Left => "LEFT",
             Self::Like => "LIKE",
             Self::Limit => "LIMIT",
             Self::NaN => "NAN",
+            Self::Natural => "NATURAL",
             Self::Not => "NOT",
             Self::Null => "NULL",
             Self::Of => "OF",
@@ -262,22 +343,31 @@ impl Keyword {
             Self::Primary => "PRIMARY",
             Self::Read => "READ",
             Self::References => "REFERENCES",
+            Self::Restore => "RESTORE",
+            Self::Returns => "RETURNS",
             Self::Right => "RIGHT",
             Self::Rollback => "ROLLBACK",
+            Self::Row => "ROW",
             Self::Select => "SELECT",
             Self::Set => "SET",
+            Self::Show => "SHOW",
             Self::String => "STRING",
             Self::System => "SYSTEM",
             Self::Table => "TABLE",
+            Self::Tablesample => "TABLESAMPLE",
             Self::Text => "TEXT",
             Self::Time => "TIME",
             Self::Transaction => "TRANSACTION",
+            Self::Trigger => "TRIGGER",
             Self::True => "TRUE",
+            Self::Undrop => "UNDROP",
             Self::Unique => "UNIQUE",
             Self::Update => "UPDATE",
+            Self::Using => "USING",
             Self::Values => "VALUES",
             Self::Varchar => "VARCHAR",
             Self::Where => "WHERE",
+            Self::With => "WITH",
             Self::Write => "WRITE",
         }
     }
@@ -289,9 +379,31 @@ impl std::fmt::Display for Keyword {
     }
 }
 
+/// A position in the input string, as 1-based line and column (in characters)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
 /// A lexer tokenizes an input string as an iterator
+#[derive(Clone)]
 pub struct Lexer<'a> {
     iter: Peekable<Chars<'a>>,
+    /// The position of the next character.
+    cursor: Position,
+    /// The start position of the last scanned token.
+    start: Position,
+    /// The byte offset of the next character.
+    cursor_offset: usize,
+    /// The byte offset of the last scanned token.
+    start_offset: usize,
 }
 
 impl<'a> Iterator for Lexer<'a> {
@@ -312,7 +424,38 @@ impl<'a> Lexer<'a> {
     /// Creates a new lexer for the given input string
     #[allow(dead_code)]
     pub fn new(input: &'a str) -> Lexer<'a> {
-        Lexer { iter: input.chars().peekable() }
+        let start = Position { line: 1, column: 1 };
+        Lexer {
+            iter: input.chars().peekable(),
+            cursor: start,
+            start,
+            cursor_offset: 0,
+            start_offset: 0,
+        }
+    }
+
+    /// Returns the start position of the last scanned token, or of the error
+    /// or end of input if scanning failed.
+    pub fn position(&self) -> Position {
+        self.start
+    }
+
+    /// Returns the byte offset of the last scanned token in the input string.
+    pub fn offset(&self) -> usize {
+        self.start_offset
+    }
+
+    /// Grabs the next character, keeping track of the position
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.iter.next()?;
+        self.cursor_offset += c.len_utf8();
+        if c == '\n' {
+            self.cursor.line += 1;
+            self.cursor.column = 1;
+        } else {
+            self.cursor.column += 1;
+        }
+        Some(c)
     }
 
     /// Consumes any whitespace characters
@@ -323,13 +466,13 @@ impl<'a> Lexer<'a> {
     /// Grabs the next character if it matches the predicate function
     fn next_if<F: Fn(char) -> bool>(&mut self, predicate: F) -> Option<char> {
         self.iter.peek().filter(|&c| predicate(*c))?;
-        self.iter.next()
+        self.next_char()
     }
 
     /// Grabs the next single-character token if the tokenizer function returns one
     fn next_if_token<F: Fn(char) -> Option<Token>>(&mut self, tokenizer: F) -> Option<Token> {
         let token = self.iter.peek().and_then(|&c| tokenizer(c))?;
-        self.iter.next();
+        self.next_char();
         Some(token)
     }
 
@@ -343,16 +486,50 @@ impl<'a> Lexer<'a> {
     }
 
     /// Scans the input for the next token if any, ignoring leading whitespace
+    /// and comments
     fn scan(&mut self) -> Result<Option<Token>> {
-        self.consume_whitespace();
-        match self.iter.peek() {
-            Some('\'') => self.scan_string(),
-            Some('"') => self.scan_ident_quoted(),
-            Some(c) if c.is_ascii_digit() => Ok(self.scan_number()),
-            Some(c) if c.is_alphabetic() => Ok(self.scan_ident()),
-            Some(_) => Ok(self.scan_symbol()),
-            None => Ok(None),
+        loop {
+            self.consume_whitespace();
+            self.start = self.cursor;
+            self.start_offset = self.cursor_offset;
+            let mut lookahead = self.iter.clone();
+            match (lookahead.next(), lookahead.next()) {
+                (Some('/'), Some('*')) => match self.scan_comment()? {
+                    Some(hint) => return Ok(Some(hint)),
+                    None => continue,
+                },
+                (Some('-'), Some('-')) => {
+                    self.next_while(|c| c != '\n');
+                    continue;
+                }
+                _ => {}
+            }
+            return match self.iter.peek() {
+                Some('\'') => self.scan_string(),
+                Some('"') => self.scan_ident_quoted(),
+                Some(c) if c.is_ascii_digit() => Ok(self.scan_number()),
+                Some(c) if c.is_alphabetic() => Ok(self.scan_ident()),
+                Some(_) => Ok(self.scan_symbol()),
+                None => Ok(None),
+            };
+        }
+    }
+
+    /// Scans a /* ... */ comment, returning a hint token if it is a /*+ ... */
+    /// hint comment
+    fn scan_comment(&mut self) -> Result<Option<Token>> {
+        self.next_char();
+        self.next_char();
+        let hint = self.next_if(|c| c == '+').is_some();
+        let mut text = String::new();
+        loop {
+            match self.next_char() {
+                Some('*') if self.next_if(|c| c == '/').is_some() => break,
+                Some(c) => text.push(c),
+                None => return Err(Error::Parse("Unexpected end of comment".into())),
+            }
         }
+        Ok(Some(Token::Hint(text.trim().to_string())).filter(|_| hint))
     }
 
     /// Scans the input for the next ident or keyword token, if any
@@ -373,7 +550,7 @@ impl<'a> Lexer<'a> {
         }
         let mut ident = String::new();
         loop {
-            match self.iter.next() {
+            match self.next_char() {
                 Some('"') if self.next_if(|c| c == '"').is_some() => ident.push('"'),
                 Some('"') => break,
                 Some(c) => ident.push(c),
@@ -386,6 +563,13 @@ impl<'a> Lexer<'a> {
     /// Scans the input for the next number token, if any
     fn scan_number(&mut self) -> Option<Token> {
         let mut num = self.next_while(|c| c.is_ascii_digit())?;
+        if num == "0" && self.next_if(|c| c == 'x' || c == 'X').is_some() {
+            num.push('x');
+            while let Some(c) = self.next_if(|c| c.is_ascii_hexdigit()) {
+                num.push(c)
+            }
+            return Some(Token::Number(num));
+        }
         if let Some(sep) = self.next_if(|c| c == '.') {
             num.push(sep);
             while let Some(dec) = self.next_if(|c| c.is_ascii_digit()) {
@@ -411,7 +595,7 @@ impl<'a> Lexer<'a> {
         }
         let mut s = String::new();
         loop {
-            match self.iter.next() {
+            match self.next_char() {
                 Some('\'') if self.next_if(|c| c == '\'').is_some() => s.push('\''),
                 Some('\'') => break,
                 Some(c) => s.push(c),
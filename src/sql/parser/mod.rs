@@ -1,36 +1,74 @@
 pub mod ast;
 mod lexer;
-pub use lexer::{Keyword, Lexer, Token};
+pub use lexer::{Keyword, Lexer, Position, Token};
 
-use super::types::DataType;
+use super::schema::{TriggerEvent, TriggerTiming};
+use super::types::{DataType, SampleMethod, Value};
 use crate::error::{Error, Result};
 
-use lazy_static::lazy_static;
-use regex::Regex;
 use std::collections::BTreeMap;
 
+/// The maximum length of a statement, in bytes.
+pub const MAX_STATEMENT_LENGTH: usize = 1_000_000;
+
+/// The maximum nesting depth of expressions and joins. Deeper nesting could
+/// overflow the stack when parsing, planning, or evaluating the statement.
+pub const MAX_DEPTH: usize = 64;
+
 /// An SQL parser
 pub struct Parser<'a> {
-    lexer: std::iter::Peekable<Lexer<'a>>,
+    lexer: Lexer<'a>,
+    /// The input string.
+    input: &'a str,
+    /// A token peeked from the lexer, if any.
+    peeked: Option<Option<Result<Token>>>,
+    /// The input length, in bytes.
+    length: usize,
+    /// The current nesting depth.
+    depth: usize,
 }
 
 impl<'a> Parser<'a> {
     /// Creates a new parser for the given string input
     pub fn new(query: &str) -> Parser {
-        Parser { lexer: Lexer::new(query).peekable() }
+        Parser {
+            lexer: Lexer::new(query),
+            input: query,
+            peeked: None,
+            length: query.len(),
+            depth: 0,
+        }
     }
 
-    /// Parses the input string into an AST statement
+    /// Parses the input string into an AST statement. Parse errors are returned
+    /// as syntax errors at the position of the token that caused them.
     pub fn parse(&mut self) -> Result<ast::Statement> {
-        let statement = self.parse_statement()?;
-        self.next_if_token(Token::Semicolon);
-        self.next_expect(None)?;
-        Ok(statement)
+        if self.length > MAX_STATEMENT_LENGTH {
+            return Err(Error::Parse(format!(
+                "Statement length {} exceeds maximum of {} bytes",
+                self.length, MAX_STATEMENT_LENGTH
+            )));
+        }
+        let result = self.parse_statement().and_then(|statement| {
+            self.next_if_token(Token::Semicolon);
+            self.next_expect(None)?;
+            Ok(statement)
+        });
+        result.map_err(|err| match err {
+            Error::Parse(message) => {
+                let Position { line, column } = self.lexer.position();
+                Error::Syntax { message, line, column }
+            }
+            err => err,
+        })
     }
 
     /// Grabs the next lexer token, or throws an error if none is found.
     fn next(&mut self) -> Result<Token> {
-        self.lexer.next().unwrap_or_else(|| Err(Error::Parse("Unexpected end of input".into())))
+        self.peeked
+            .take()
+            .unwrap_or_else(|| self.lexer.next())
+            .unwrap_or_else(|| Err(Error::Parse("Unexpected end of input".into())))
     }
 
     /// Grabs the next lexer token, and returns it if it was expected or
@@ -58,6 +96,15 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Grabs the next lexer token, and returns it if it was a string literal giving a prepared
+    /// transaction's global identifier.
+    fn next_gid(&mut self) -> Result<String> {
+        match self.next()? {
+            Token::String(gid) => Ok(gid),
+            token => Err(Error::Parse(format!("Expected transaction identifier, found {}", token))),
+        }
+    }
+
     /// Grabs the next lexer token if it satisfies the predicate function
     fn next_if<F: Fn(&Token) -> bool>(&mut self, predicate: F) -> Option<Token> {
         self.peek().unwrap_or(None).filter(|t| predicate(t))?;
@@ -72,6 +119,9 @@ impl<'a> Parser<'a> {
             .and_then(|token| O::from(&token))
             .filter(|op| op.prec() >= min_prec)
         {
+            if !operator.followed_by(&self.peek_ahead(2)?) {
+                return Ok(None);
+            }
             self.next()?;
             Ok(Some(operator.augment(self)?))
         } else {
@@ -89,11 +139,31 @@ impl<'a> Parser<'a> {
         self.next_if(|t| t == &token)
     }
 
+    /// Peeks up to n tokens after the next lexer token, stopping at any lexer error. Errors are
+    /// left for the regular parsing to report at their position.
+    fn peek_ahead(&mut self, n: usize) -> Result<Vec<Token>> {
+        if self.peek()?.is_none() {
+            return Ok(Vec::new());
+        }
+        Ok(self.lexer.clone().take(n).map_while(|t| t.ok()).collect())
+    }
+
     /// Peeks the next lexer token if any, but converts it from
     /// Option<Result<Token>> to Result<Option<Token>> which is
     /// more convenient to work with (the Iterator trait requires Option<T>).
     fn peek(&mut self) -> Result<Option<Token>> {
-        self.lexer.peek().cloned().transpose()
+        let lexer = &mut self.lexer;
+        self.peeked.get_or_insert_with(|| lexer.next()).clone().transpose()
+    }
+
+    /// Increments the nesting depth, erroring if it exceeds MAX_DEPTH. Callers
+    /// reset the depth when they're done.
+    fn nest(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(Error::Parse(format!("Nesting exceeds maximum depth of {}", MAX_DEPTH)));
+        }
+        Ok(())
     }
 
     /// Parses an SQL statement
@@ -102,33 +172,93 @@ impl<'a> Parser<'a> {
             Some(Token::Keyword(Keyword::Begin)) => self.parse_transaction(),
             Some(Token::Keyword(Keyword::Commit)) => self.parse_transaction(),
             Some(Token::Keyword(Keyword::Rollback)) => self.parse_transaction(),
+            Some(Token::Ident(ident)) if ident == "prepare" => self.parse_transaction(),
 
             Some(Token::Keyword(Keyword::Create)) => self.parse_ddl(),
             Some(Token::Keyword(Keyword::Drop)) => self.parse_ddl(),
+            Some(Token::Keyword(Keyword::Undrop)) => self.parse_ddl(),
+            Some(Token::Keyword(Keyword::Restore)) => self.parse_ddl(),
 
             Some(Token::Keyword(Keyword::Delete)) => self.parse_statement_delete(),
             Some(Token::Keyword(Keyword::Insert)) => self.parse_statement_insert(),
-            Some(Token::Keyword(Keyword::Select)) => self.parse_statement_select(),
+            Some(Token::Keyword(Keyword::Select)) => self.parse_statement_select_into(),
             Some(Token::Keyword(Keyword::Update)) => self.parse_statement_update(),
 
+            Some(Token::Keyword(Keyword::Set)) => self.parse_statement_set(),
+            Some(Token::Keyword(Keyword::Show)) => self.parse_statement_show(),
+            Some(Token::Keyword(Keyword::Kill)) => self.parse_statement_kill(),
+
             Some(Token::Keyword(Keyword::Explain)) => self.parse_statement_explain(),
 
+            Some(Token::Keyword(Keyword::Declare)) => self.parse_cursor(),
+            Some(Token::Keyword(Keyword::Fetch)) => self.parse_cursor(),
+            Some(Token::Keyword(Keyword::Close)) => self.parse_cursor(),
+
             Some(token) => Err(Error::Parse(format!("Unexpected token {}", token))),
             None => Err(Error::Parse("Unexpected end of input".into())),
         }
     }
 
-    /// Parses a DDL statement
+    /// Parses a cursor statement
+    fn parse_cursor(&mut self) -> Result<ast::Statement> {
+        match self.next()? {
+            Token::Keyword(Keyword::Declare) => {
+                let name = self.next_ident()?;
+                self.next_expect(Some(Keyword::Cursor.into()))?;
+                self.next_expect(Some(Keyword::For.into()))?;
+                let query = Box::new(self.parse_statement_select()?);
+                Ok(ast::Statement::DeclareCursor { name, query })
+            }
+            Token::Keyword(Keyword::Fetch) => {
+                let count = match self.peek()? {
+                    Some(Token::Keyword(Keyword::All)) => {
+                        self.next()?;
+                        None
+                    }
+                    Some(Token::Number(n)) => {
+                        self.next()?;
+                        Some(n.parse::<u64>()?)
+                    }
+                    _ => Some(1),
+                };
+                self.next_expect(Some(Keyword::From.into()))?;
+                Ok(ast::Statement::Fetch { cursor: self.next_ident()?, count })
+            }
+            Token::Keyword(Keyword::Close) => Ok(ast::Statement::CloseCursor(self.next_ident()?)),
+            token => Err(Error::Parse(format!("Unexpected token {}", token))),
+        }
+    }
+
+    /// Parses a DDL statement. TENANT is not a keyword.
     fn parse_ddl(&mut self) -> Result<ast::Statement> {
         match self.next()? {
             Token::Keyword(Keyword::Create) => match self.next()? {
                 Token::Keyword(Keyword::Table) => self.parse_ddl_create_table(),
+                Token::Keyword(Keyword::Trigger) => self.parse_ddl_create_trigger(),
+                Token::Keyword(Keyword::Index) => self.parse_ddl_create_index(),
+                Token::Keyword(Keyword::Function) => self.parse_ddl_create_function(),
+                Token::Ident(ident) if ident == "tenant" => {
+                    Ok(ast::Statement::CreateTenant(self.next_ident()?))
+                }
                 token => Err(Error::Parse(format!("Unexpected token {}", token))),
             },
             Token::Keyword(Keyword::Drop) => match self.next()? {
                 Token::Keyword(Keyword::Table) => self.parse_ddl_drop_table(),
+                Token::Keyword(Keyword::Trigger) => self.parse_ddl_drop_trigger(),
+                Token::Keyword(Keyword::Index) => self.parse_ddl_drop_index(),
+                Token::Keyword(Keyword::Function) => {
+                    Ok(ast::Statement::DropFunction(self.next_ident()?))
+                }
+                Token::Ident(ident) if ident == "tenant" => {
+                    Ok(ast::Statement::DropTenant(self.next_ident()?))
+                }
                 token => Err(Error::Parse(format!("Unexpected token {}", token))),
             },
+            // RESTORE TABLE is an alias for UNDROP TABLE.
+            Token::Keyword(Keyword::Undrop) | Token::Keyword(Keyword::Restore) => {
+                self.next_expect(Some(Keyword::Table.into()))?;
+                Ok(ast::Statement::UndropTable(self.next_ident()?))
+            }
             token => Err(Error::Parse(format!("Unexpected token {}", token))),
         }
     }
@@ -137,6 +267,10 @@ impl<'a> Parser<'a> {
     /// already been consumed.
     fn parse_ddl_create_table(&mut self) -> Result<ast::Statement> {
         let name = self.next_ident()?;
+        if self.next_if_token(Keyword::As.into()).is_some() {
+            let query = Box::new(self.parse_statement_select()?);
+            return Ok(ast::Statement::CreateTableAs { name, query });
+        }
         self.next_expect(Some(Token::OpenParen))?;
 
         let mut columns = Vec::new();
@@ -147,7 +281,21 @@ impl<'a> Parser<'a> {
             }
         }
         self.next_expect(Some(Token::CloseParen))?;
-        Ok(ast::Statement::CreateTable { name, columns })
+
+        let mut options = Vec::new();
+        if self.next_if_token(Keyword::With.into()).is_some() {
+            self.next_expect(Some(Token::OpenParen))?;
+            loop {
+                let option = self.next_ident()?;
+                self.next_expect(Some(Token::Equal))?;
+                options.push((option, self.parse_expression(0)?));
+                if self.next_if_token(Token::Comma).is_none() {
+                    break;
+                }
+            }
+            self.next_expect(Some(Token::CloseParen))?;
+        }
+        Ok(ast::Statement::CreateTable { name, columns, options })
     }
 
     /// Parses a DROP TABLE DDL statement. The DROP TABLE prefix has
@@ -156,28 +304,148 @@ impl<'a> Parser<'a> {
         Ok(ast::Statement::DropTable(self.next_ident()?))
     }
 
+    /// Parses a CREATE TRIGGER DDL statement. The CREATE TRIGGER prefix has
+    /// already been consumed.
+    fn parse_ddl_create_trigger(&mut self) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        let timing = match self.next()? {
+            Token::Keyword(Keyword::Before) => TriggerTiming::Before,
+            Token::Keyword(Keyword::After) => TriggerTiming::After,
+            token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+        };
+        let event = match self.next()? {
+            Token::Keyword(Keyword::Insert) => TriggerEvent::Insert,
+            Token::Keyword(Keyword::Update) => TriggerEvent::Update,
+            Token::Keyword(Keyword::Delete) => TriggerEvent::Delete,
+            token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+        };
+        self.next_expect(Some(Token::Keyword(Keyword::On)))?;
+        let table = self.next_ident()?;
+        self.next_expect(Some(Token::Keyword(Keyword::For)))?;
+        self.next_expect(Some(Token::Keyword(Keyword::Each)))?;
+        self.next_expect(Some(Token::Keyword(Keyword::Row)))?;
+        self.next_expect(Some(Token::Keyword(Keyword::Execute)))?;
+        self.next_expect(Some(Token::OpenParen))?;
+
+        // Peek the tokens around the statement to find its SQL text in the input.
+        self.peek()?;
+        let start = self.lexer.offset();
+        let statement = self.parse_statement()?;
+        self.peek()?;
+        let sql = self.input[start..self.lexer.offset()].trim_end().to_string();
+        self.next_expect(Some(Token::CloseParen))?;
+
+        Ok(ast::Statement::CreateTrigger {
+            name,
+            table,
+            timing,
+            event,
+            statement: Box::new(statement),
+            sql,
+        })
+    }
+
+    /// Parses a DROP TRIGGER DDL statement. The DROP TRIGGER prefix has
+    /// already been consumed.
+    fn parse_ddl_drop_trigger(&mut self) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        self.next_expect(Some(Token::Keyword(Keyword::On)))?;
+        Ok(ast::Statement::DropTrigger { name, table: self.next_ident()? })
+    }
+
+    /// Parses a CREATE INDEX DDL statement. The CREATE INDEX prefix has
+    /// already been consumed.
+    fn parse_ddl_create_index(&mut self) -> Result<ast::Statement> {
+        let name = match self.next_if_token(Keyword::On.into()) {
+            Some(_) => None,
+            None => {
+                let name = self.next_ident()?;
+                self.next_expect(Some(Keyword::On.into()))?;
+                Some(name)
+            }
+        };
+        let table = self.next_ident()?;
+        self.next_expect(Some(Token::OpenParen))?;
+        let mut columns = Vec::new();
+        loop {
+            let column = self.next_ident()?;
+            let order = match self.next_if_token(Keyword::Desc.into()) {
+                Some(_) => ast::Order::Descending,
+                None => {
+                    self.next_if_token(Keyword::Asc.into());
+                    ast::Order::Ascending
+                }
+            };
+            columns.push((column, order));
+            if self.next_if_token(Token::Comma).is_none() {
+                break;
+            }
+        }
+        self.next_expect(Some(Token::CloseParen))?;
+        Ok(ast::Statement::CreateIndex { name, table, columns })
+    }
+
+    /// Parses a DROP INDEX DDL statement. The DROP INDEX prefix has
+    /// already been consumed.
+    fn parse_ddl_drop_index(&mut self) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        self.next_expect(Some(Keyword::On.into()))?;
+        Ok(ast::Statement::DropIndex { name, table: self.next_ident()? })
+    }
+
+    /// Parses a CREATE FUNCTION DDL statement. The CREATE FUNCTION prefix has
+    /// already been consumed.
+    fn parse_ddl_create_function(&mut self) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        self.next_expect(Some(Token::OpenParen))?;
+        let mut args = Vec::new();
+        if self.next_if_token(Token::CloseParen).is_none() {
+            loop {
+                args.push((self.next_ident()?, self.parse_datatype()?));
+                if self.next_if_token(Token::Comma).is_none() {
+                    break;
+                }
+            }
+            self.next_expect(Some(Token::CloseParen))?;
+        }
+        self.next_expect(Some(Keyword::Returns.into()))?;
+        let returns = self.parse_datatype()?;
+        self.next_expect(Some(Keyword::As.into()))?;
+        let body = match self.next()? {
+            Token::String(body) => body,
+            token => return Err(Error::Parse(format!("Expected function body, found {}", token))),
+        };
+        Ok(ast::Statement::CreateFunction { name, args, returns, body })
+    }
+
+    /// Parses a datatype
+    fn parse_datatype(&mut self) -> Result<DataType> {
+        Ok(match self.next()? {
+            Token::Keyword(Keyword::Bool) => DataType::Boolean,
+            Token::Keyword(Keyword::Boolean) => DataType::Boolean,
+            Token::Keyword(Keyword::Char) => DataType::String,
+            Token::Keyword(Keyword::Double) => DataType::Float,
+            Token::Keyword(Keyword::Float) => DataType::Float,
+            Token::Keyword(Keyword::Int) => DataType::Integer,
+            Token::Keyword(Keyword::Integer) => DataType::Integer,
+            Token::Keyword(Keyword::String) => DataType::String,
+            Token::Keyword(Keyword::Text) => DataType::String,
+            Token::Keyword(Keyword::Varchar) => DataType::String,
+            token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+        })
+    }
+
     /// Parses a column specification
     fn parse_ddl_columnspec(&mut self) -> Result<ast::Column> {
         let mut column = ast::Column {
             name: self.next_ident()?,
-            datatype: match self.next()? {
-                Token::Keyword(Keyword::Bool) => DataType::Boolean,
-                Token::Keyword(Keyword::Boolean) => DataType::Boolean,
-                Token::Keyword(Keyword::Char) => DataType::String,
-                Token::Keyword(Keyword::Double) => DataType::Float,
-                Token::Keyword(Keyword::Float) => DataType::Float,
-                Token::Keyword(Keyword::Int) => DataType::Integer,
-                Token::Keyword(Keyword::Integer) => DataType::Integer,
-                Token::Keyword(Keyword::String) => DataType::String,
-                Token::Keyword(Keyword::Text) => DataType::String,
-                Token::Keyword(Keyword::Varchar) => DataType::String,
-                token => return Err(Error::Parse(format!("Unexpected token {}", token))),
-            },
+            datatype: self.parse_datatype()?,
             primary_key: false,
             nullable: None,
             default: None,
             unique: false,
             index: false,
+            include: Vec::new(),
             references: None,
         };
         while let Some(Token::Keyword(keyword)) = self.next_if_keyword() {
@@ -207,7 +475,19 @@ impl<'a> Parser<'a> {
                 }
                 Keyword::Default => column.default = Some(self.parse_expression(0)?),
                 Keyword::Unique => column.unique = true,
-                Keyword::Index => column.index = true,
+                Keyword::Index => {
+                    column.index = true;
+                    if self.next_if_token(Keyword::Include.into()).is_some() {
+                        self.next_expect(Some(Token::OpenParen))?;
+                        loop {
+                            column.include.push(self.next_ident()?);
+                            if self.next_if_token(Token::Comma).is_none() {
+                                break;
+                            }
+                        }
+                        self.next_expect(Some(Token::CloseParen))?;
+                    }
+                }
                 Keyword::References => column.references = Some(self.next_ident()?),
                 keyword => return Err(Error::Parse(format!("Unexpected keyword {}", keyword))),
             }
@@ -220,16 +500,26 @@ impl<'a> Parser<'a> {
         self.next_expect(Some(Keyword::Delete.into()))?;
         self.next_expect(Some(Keyword::From.into()))?;
         let table = self.next_ident()?;
-        Ok(ast::Statement::Delete { table, r#where: self.parse_clause_where()? })
+        Ok(ast::Statement::Delete {
+            table,
+            r#where: self.parse_clause_where()?,
+            order: self.parse_clause_order()?,
+            limit: self.parse_clause_limit()?,
+        })
     }
 
     /// Parses a delete statement
     fn parse_statement_explain(&mut self) -> Result<ast::Statement> {
         self.next_expect(Some(Keyword::Explain.into()))?;
+        let analyze = self.next_if_token(Token::Ident("analyze".into())).is_some();
         if let Some(Token::Keyword(Keyword::Explain)) = self.peek()? {
             return Err(Error::Parse("Cannot nest EXPLAIN statements".into()));
         }
-        Ok(ast::Statement::Explain(Box::new(self.parse_statement()?)))
+        let statement = Box::new(self.parse_statement()?);
+        match analyze {
+            true => Ok(ast::Statement::ExplainAnalyze(statement)),
+            false => Ok(ast::Statement::Explain(statement)),
+        }
     }
 
     /// Parses an insert statement
@@ -254,6 +544,13 @@ impl<'a> Parser<'a> {
         };
 
         self.next_expect(Some(Keyword::Values.into()))?;
+        let values = self.parse_values()?;
+
+        Ok(ast::Statement::Insert { table, columns, values })
+    }
+
+    /// Parses a list of VALUES rows, e.g. (1, 'a'), (2, 'b')
+    fn parse_values(&mut self) -> Result<Vec<Vec<ast::Expression>>> {
         let mut values = Vec::new();
         loop {
             self.next_expect(Some(Token::OpenParen))?;
@@ -271,30 +568,111 @@ impl<'a> Parser<'a> {
                 break;
             }
         }
-
-        Ok(ast::Statement::Insert { table, columns, values })
+        Ok(values)
     }
 
     /// Parses a select statement
     fn parse_statement_select(&mut self) -> Result<ast::Statement> {
-        Ok(ast::Statement::Select {
-            select: self.parse_clause_select()?,
+        Ok(self.parse_select(false)?.0)
+    }
+
+    /// Parses a top-level select statement, which may be SELECT ... INTO table to create a
+    /// table from the result, like CREATE TABLE AS.
+    fn parse_statement_select_into(&mut self) -> Result<ast::Statement> {
+        Ok(match self.parse_select(true)? {
+            (query, Some(name)) => ast::Statement::CreateTableAs { name, query: Box::new(query) },
+            (query, None) => query,
+        })
+    }
+
+    /// Parses a select statement, along with an INTO table name following the SELECT clause if
+    /// into is true.
+    fn parse_select(&mut self, into: bool) -> Result<(ast::Statement, Option<String>)> {
+        self.next_expect(Some(Keyword::Select.into()))?;
+        let hints = self.parse_hints()?;
+        let distinct = self.parse_clause_distinct()?;
+        let select = self.parse_clause_select()?;
+        let into = match into && self.next_if_token(Keyword::Into.into()).is_some() {
+            true => Some(self.next_ident()?),
+            false => None,
+        };
+        let statement = ast::Statement::Select {
+            hints,
+            distinct,
+            select,
             from: self.parse_clause_from()?,
             r#where: self.parse_clause_where()?,
             group_by: self.parse_clause_group_by()?,
             having: self.parse_clause_having()?,
             order: self.parse_clause_order()?,
-            limit: if self.next_if_token(Keyword::Limit.into()).is_some() {
-                Some(self.parse_expression(0)?)
-            } else {
-                None
-            },
+            limit: self.parse_clause_limit()?,
             offset: if self.next_if_token(Keyword::Offset.into()).is_some() {
                 Some(self.parse_expression(0)?)
             } else {
                 None
             },
-        })
+            ordinality: self.parse_clause_ordinality()?,
+        };
+        Ok((statement, into))
+    }
+
+    /// Parses a session variable assignment, or a cluster setting assignment via SET CLUSTER
+    /// SETTING, where CLUSTER and SETTING are not keywords.
+    fn parse_statement_set(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Some(Keyword::Set.into()))?;
+        if self.peek()? == Some(Token::Ident("cluster".into()))
+            && self.peek_ahead(1)? == vec![Token::Ident("setting".into())]
+        {
+            self.next()?;
+            self.next()?;
+            let name = self.next_ident()?;
+            self.next_expect(Some(Token::Equal))?;
+            let value = match self.next_if_token(Keyword::Default.into()) {
+                Some(_) => None,
+                None => Some(self.parse_expression(0)?),
+            };
+            return Ok(ast::Statement::SetClusterSetting { name, value });
+        }
+        let variable = self.next_ident()?;
+        self.next_expect(Some(Token::Equal))?;
+        Ok(ast::Statement::Set { variable, value: self.parse_expression(0)? })
+    }
+
+    /// Parses a SHOW statement, either SHOW TABLE STATUS, SHOW SESSIONS, or SHOW PREPARED
+    /// TRANSACTIONS, where STATUS, SESSIONS, PREPARED, and TRANSACTIONS are not keywords to
+    /// allow using them as identifiers.
+    fn parse_statement_show(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Some(Keyword::Show.into()))?;
+        match self.next()? {
+            Token::Keyword(Keyword::Table) => match self.next()? {
+                Token::Ident(ident) if ident == "status" => Ok(ast::Statement::ShowTableStatus),
+                token => Err(Error::Parse(format!("Unexpected token {}", token))),
+            },
+            Token::Ident(ident) if ident == "sessions" => Ok(ast::Statement::ShowSessions),
+            Token::Ident(ident) if ident == "prepared" => match self.next()? {
+                Token::Ident(ident) if ident == "transactions" => Ok(ast::Statement::ShowPrepared),
+                token => Err(Error::Parse(format!("Unexpected token {}", token))),
+            },
+            token => Err(Error::Parse(format!("Unexpected token {}", token))),
+        }
+    }
+
+    /// Parses a KILL [SESSION | QUERY] statement, which kills the session by default. SESSION
+    /// and QUERY are not keywords, like STATUS.
+    fn parse_statement_kill(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Some(Keyword::Kill.into()))?;
+        let query = match self.next()? {
+            Token::Ident(ident) if ident == "session" => false,
+            Token::Ident(ident) if ident == "query" => true,
+            Token::Number(n) => {
+                return Ok(ast::Statement::Kill { session: n.parse()?, query: false })
+            }
+            token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+        };
+        match self.next()? {
+            Token::Number(n) => Ok(ast::Statement::Kill { session: n.parse()?, query }),
+            token => Err(Error::Parse(format!("Unexpected token {}", token))),
+        }
     }
 
     /// Parses an update statement
@@ -317,10 +695,16 @@ impl<'a> Parser<'a> {
             }
         }
 
-        Ok(ast::Statement::Update { table, set, r#where: self.parse_clause_where()? })
+        Ok(ast::Statement::Update {
+            table,
+            set,
+            r#where: self.parse_clause_where()?,
+            order: self.parse_clause_order()?,
+            limit: self.parse_clause_limit()?,
+        })
     }
 
-    /// Parses a transaction statement
+    /// Parses a transaction statement. PREPARE and PREPARED are not keywords, like STATUS.
     fn parse_transaction(&mut self) -> Result<ast::Statement> {
         match self.next()? {
             Token::Keyword(Keyword::Begin) => {
@@ -350,8 +734,22 @@ impl<'a> Parser<'a> {
                 }
                 Ok(ast::Statement::Begin { read_only: readonly, as_of: version })
             }
-            Token::Keyword(Keyword::Commit) => Ok(ast::Statement::Commit),
-            Token::Keyword(Keyword::Rollback) => Ok(ast::Statement::Rollback),
+            Token::Ident(ident) if ident == "prepare" => {
+                self.next_expect(Some(Keyword::Transaction.into()))?;
+                Ok(ast::Statement::Prepare(self.next_gid()?))
+            }
+            Token::Keyword(Keyword::Commit) => {
+                match self.next_if_token(Token::Ident("prepared".into())).is_some() {
+                    true => Ok(ast::Statement::CommitPrepared(self.next_gid()?)),
+                    false => Ok(ast::Statement::Commit),
+                }
+            }
+            Token::Keyword(Keyword::Rollback) => {
+                match self.next_if_token(Token::Ident("prepared".into())).is_some() {
+                    true => Ok(ast::Statement::RollbackPrepared(self.next_gid()?)),
+                    false => Ok(ast::Statement::Rollback),
+                }
+            }
             token => Err(Error::Parse(format!("Unexpected token {}", token))),
         }
     }
@@ -362,22 +760,36 @@ impl<'a> Parser<'a> {
         if self.next_if_token(Keyword::From.into()).is_none() {
             return Ok(from);
         }
+        let depth = self.depth;
         loop {
             let mut item = self.parse_clause_from_item()?;
-            while let Some(jointype) = self.parse_clause_from_jointype()? {
+            loop {
+                let natural = self.next_if_token(Keyword::Natural.into()).is_some();
+                let Some(r#type) = self.parse_clause_from_jointype()? else {
+                    if natural {
+                        self.next_expect(Some(Keyword::Join.into()))?;
+                    }
+                    break;
+                };
+                if natural && r#type == ast::JoinType::Cross {
+                    return Err(Error::Parse("NATURAL can't be used with CROSS JOIN".into()));
+                }
+                self.nest()?;
                 let left = Box::new(item);
                 let right = Box::new(self.parse_clause_from_item()?);
-                let predicate = match &jointype {
-                    ast::JoinType::Cross => None,
-                    _ => {
+                let (mut predicate, mut using) = (None, Vec::new());
+                if r#type != ast::JoinType::Cross && !natural {
+                    if self.next_if_token(Keyword::Using.into()).is_some() {
+                        using = self.parse_clause_from_using()?;
+                    } else {
                         self.next_expect(Some(Keyword::On.into()))?;
-                        Some(self.parse_expression(0)?)
+                        predicate = Some(self.parse_expression(0)?);
                     }
-                };
-                let r#type = jointype;
-                item = ast::FromItem::Join { left, right, r#type, predicate };
+                }
+                item = ast::FromItem::Join { left, right, r#type, predicate, using, natural };
             }
             from.push(item);
+            self.depth = depth;
             if self.next_if_token(Token::Comma).is_none() {
                 break;
             }
@@ -387,20 +799,119 @@ impl<'a> Parser<'a> {
 
     /// Parses a from clause item
     fn parse_clause_from_item(&mut self) -> Result<ast::FromItem> {
+        // LATERAL is implied for table functions, and not supported for other items. Lexer
+        // errors in the lookahead are left for the regular parsing to report at their position.
+        let lateral = self.next_if_token(Keyword::Lateral.into()).is_some();
+        let function = matches!(self.peek()?, Some(Token::Ident(_)))
+            && self.peek_ahead(1).unwrap_or_default().first() == Some(&Token::OpenParen);
+        if lateral && !function {
+            return Err(Error::Parse("LATERAL is only supported for table functions".into()));
+        }
+        if function {
+            return self.parse_clause_from_function();
+        }
+        if self.next_if_token(Token::OpenParen).is_some() {
+            return self.parse_clause_from_values();
+        }
         self.parse_clause_from_table()
     }
 
-    // Parses a from clause table
-    fn parse_clause_from_table(&mut self) -> Result<ast::FromItem> {
-        let name = self.next_ident()?;
-        let alias = if self.next_if_token(Keyword::As.into()).is_some() {
+    // Parses a from clause table alias, if any
+    fn parse_clause_from_alias(&mut self) -> Result<Option<String>> {
+        Ok(if self.next_if_token(Keyword::As.into()).is_some() {
             Some(self.next_ident()?)
         } else if let Some(Token::Ident(_)) = self.peek()? {
             Some(self.next_ident()?)
         } else {
             None
+        })
+    }
+
+    // Parses a from clause VALUES list, e.g. (VALUES (1, 'a'), (2, 'b')) AS v (id, name), after
+    // the opening parenthesis
+    fn parse_clause_from_values(&mut self) -> Result<ast::FromItem> {
+        self.next_expect(Some(Keyword::Values.into()))?;
+        let rows = self.parse_values()?;
+        self.next_expect(Some(Token::CloseParen))?;
+        let alias = self.parse_clause_from_alias()?;
+        let columns = self.parse_clause_from_columns(&alias)?;
+        Ok(ast::FromItem::Values { rows, alias, columns })
+    }
+
+    // Parses a from clause table function call, e.g. generate_series(1, 10) AS g (n)
+    fn parse_clause_from_function(&mut self) -> Result<ast::FromItem> {
+        let name = self.next_ident()?;
+        self.next_expect(Some(Token::OpenParen))?;
+        let mut args = Vec::new();
+        while self.next_if_token(Token::CloseParen).is_none() {
+            if !args.is_empty() {
+                self.next_expect(Some(Token::Comma))?;
+            }
+            args.push(self.parse_expression(0)?);
+        }
+        let alias = self.parse_clause_from_alias()?;
+        let columns = self.parse_clause_from_columns(&alias)?;
+        Ok(ast::FromItem::Function { name, args, alias, columns })
+    }
+
+    // Parses a parenthesized list of column names following a from clause alias, if any
+    fn parse_clause_from_columns(&mut self, alias: &Option<String>) -> Result<Vec<String>> {
+        let mut columns = Vec::new();
+        if alias.is_some() && self.next_if_token(Token::OpenParen).is_some() {
+            loop {
+                columns.push(self.next_ident()?);
+                match self.next()? {
+                    Token::CloseParen => break,
+                    Token::Comma => {}
+                    token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+                }
+            }
+        }
+        Ok(columns)
+    }
+
+    // Parses a from clause table
+    fn parse_clause_from_table(&mut self) -> Result<ast::FromItem> {
+        // System views are qualified by the SYSTEM keyword, and information schema views by the
+        // schema name.
+        let mut name = match self.next_if_token(Keyword::System.into()) {
+            Some(_) => {
+                self.next_expect(Some(Token::Period))?;
+                format!("system.{}", self.next_ident()?)
+            }
+            None => self.next_ident()?,
         };
-        Ok(ast::FromItem::Table { name, alias })
+        if name == "information_schema" && self.next_if_token(Token::Period).is_some() {
+            name = format!("{}.{}", name, self.next_ident()?);
+        }
+        let alias = self.parse_clause_from_alias()?;
+        let sample = self.parse_clause_from_sample()?;
+        Ok(ast::FromItem::Table { name, alias, sample })
+    }
+
+    // Parses a from clause table sample, if any, e.g. TABLESAMPLE SYSTEM (10) REPEATABLE (7).
+    // BERNOULLI and REPEATABLE are not reserved keywords.
+    fn parse_clause_from_sample(&mut self) -> Result<Option<ast::TableSample>> {
+        if self.next_if_token(Keyword::Tablesample.into()).is_none() {
+            return Ok(None);
+        }
+        let method = match self.next()? {
+            Token::Keyword(Keyword::System) => SampleMethod::System,
+            Token::Ident(ident) if ident == "bernoulli" => SampleMethod::Bernoulli,
+            token => {
+                return Err(Error::Parse(format!("Expected SYSTEM or BERNOULLI, found {}", token)))
+            }
+        };
+        self.next_expect(Some(Token::OpenParen))?;
+        let percent = self.parse_expression(0)?;
+        self.next_expect(Some(Token::CloseParen))?;
+        let mut seed = None;
+        if self.next_if_token(Token::Ident("repeatable".into())).is_some() {
+            self.next_expect(Some(Token::OpenParen))?;
+            seed = Some(self.parse_expression(0)?);
+            self.next_expect(Some(Token::CloseParen))?;
+        }
+        Ok(Some(ast::TableSample { method, percent, seed }))
     }
 
     // Parses a from clause join type
@@ -421,11 +932,29 @@ impl<'a> Parser<'a> {
             self.next_if_token(Keyword::Outer.into());
             self.next_expect(Some(Keyword::Join.into()))?;
             Ok(Some(ast::JoinType::Right))
+        } else if self.next_if_token(Keyword::Full.into()).is_some() {
+            self.next_if_token(Keyword::Outer.into());
+            self.next_expect(Some(Keyword::Join.into()))?;
+            Ok(Some(ast::JoinType::Full))
         } else {
             Ok(None)
         }
     }
 
+    // Parses the parenthesized column list of a USING join
+    fn parse_clause_from_using(&mut self) -> Result<Vec<String>> {
+        self.next_expect(Some(Token::OpenParen))?;
+        let mut columns = Vec::new();
+        loop {
+            columns.push(self.next_ident()?);
+            if self.next_if_token(Token::Comma).is_none() {
+                break;
+            }
+        }
+        self.next_expect(Some(Token::CloseParen))?;
+        Ok(columns)
+    }
+
     /// Parses a group by clause
     fn parse_clause_group_by(&mut self) -> Result<Vec<ast::Expression>> {
         let mut exprs = Vec::new();
@@ -442,6 +971,25 @@ impl<'a> Parser<'a> {
         Ok(exprs)
     }
 
+    /// Parses a DISTINCT clause, with optional DISTINCT ON expressions
+    fn parse_clause_distinct(&mut self) -> Result<Option<Vec<ast::Expression>>> {
+        if self.next_if_token(Keyword::Distinct.into()).is_none() {
+            return Ok(None);
+        }
+        let mut exprs = Vec::new();
+        if self.next_if_token(Keyword::On.into()).is_some() {
+            self.next_expect(Some(Token::OpenParen))?;
+            loop {
+                exprs.push(self.parse_expression(0)?);
+                if self.next_if_token(Token::Comma).is_none() {
+                    break;
+                }
+            }
+            self.next_expect(Some(Token::CloseParen))?;
+        }
+        Ok(Some(exprs))
+    }
+
     /// Parses a HAVING clause
     fn parse_clause_having(&mut self) -> Result<Option<ast::Expression>> {
         if self.next_if_token(Keyword::Having.into()).is_none() {
@@ -450,24 +998,48 @@ impl<'a> Parser<'a> {
         Ok(Some(self.parse_expression(0)?))
     }
 
+    /// Parses a LIMIT clause
+    fn parse_clause_limit(&mut self) -> Result<Option<ast::Expression>> {
+        if self.next_if_token(Keyword::Limit.into()).is_none() {
+            return Ok(None);
+        }
+        Ok(Some(self.parse_expression(0)?))
+    }
+
     /// Parses an order clause
-    fn parse_clause_order(&mut self) -> Result<Vec<(ast::Expression, ast::Order)>> {
+    fn parse_clause_order(
+        &mut self,
+    ) -> Result<Vec<(ast::Expression, ast::Order, Option<ast::Nulls>)>> {
         if self.next_if_token(Keyword::Order.into()).is_none() {
             return Ok(Vec::new());
         }
         self.next_expect(Some(Keyword::By.into()))?;
         let mut orders = Vec::new();
         loop {
-            orders.push((
-                self.parse_expression(0)?,
-                if self.next_if_token(Keyword::Asc.into()).is_some() {
-                    ast::Order::Ascending
-                } else if self.next_if_token(Keyword::Desc.into()).is_some() {
-                    ast::Order::Descending
-                } else {
-                    ast::Order::Ascending
-                },
-            ));
+            let expr = self.parse_expression(0)?;
+            let order = if self.next_if_token(Keyword::Asc.into()).is_some() {
+                ast::Order::Ascending
+            } else if self.next_if_token(Keyword::Desc.into()).is_some() {
+                ast::Order::Descending
+            } else {
+                ast::Order::Ascending
+            };
+            // NULLS, FIRST, and LAST aren't reserved keywords, to allow them as identifiers.
+            let nulls = if self.next_if_token(Token::Ident("nulls".into())).is_some() {
+                match self.next()? {
+                    Token::Ident(ident) if ident == "first" => Some(ast::Nulls::First),
+                    Token::Ident(ident) if ident == "last" => Some(ast::Nulls::Last),
+                    token => {
+                        return Err(Error::Parse(format!(
+                            "Expected FIRST or LAST, found {}",
+                            token
+                        )))
+                    }
+                }
+            } else {
+                None
+            };
+            orders.push((expr, order, nulls));
             if self.next_if_token(Token::Comma).is_none() {
                 break;
             }
@@ -475,18 +1047,66 @@ impl<'a> Parser<'a> {
         Ok(orders)
     }
 
-    /// Parses a select clause
+    /// Parses a WITH ORDINALITY clause, returning true if given. ORDINALITY isn't a reserved
+    /// keyword, to allow it as an identifier.
+    fn parse_clause_ordinality(&mut self) -> Result<bool> {
+        if self.next_if_token(Keyword::With.into()).is_none() {
+            return Ok(false);
+        }
+        match self.next()? {
+            Token::Ident(ident) if ident == "ordinality" => Ok(true),
+            token => Err(Error::Parse(format!("Expected ORDINALITY, found {}", token))),
+        }
+    }
+
+    /// Parses optimizer hints, if given, e.g. /*+ NO_INDEX(movies) HASH_JOIN */
+    fn parse_hints(&mut self) -> Result<Vec<ast::Hint>> {
+        let text = match self.next_if(|t| matches!(t, Token::Hint(_))) {
+            Some(Token::Hint(text)) => text,
+            _ => return Ok(Vec::new()),
+        };
+        let mut parser = Parser::new(&text);
+        let mut hints = Vec::new();
+        while parser.peek()?.is_some() {
+            hints.push(match parser.next_ident()?.as_str() {
+                "no_index" => {
+                    let mut tables = Vec::new();
+                    if parser.next_if_token(Token::OpenParen).is_some() {
+                        loop {
+                            tables.push(parser.next_ident()?);
+                            if parser.next_if_token(Token::Comma).is_none() {
+                                break;
+                            }
+                        }
+                        parser.next_expect(Some(Token::CloseParen))?;
+                    }
+                    ast::Hint::NoIndex(tables)
+                }
+                "hash_join" => ast::Hint::HashJoin,
+                "no_hash_join" => ast::Hint::NoHashJoin,
+                "no_join_order" => ast::Hint::NoJoinOrder,
+                hint => return Err(Error::Parse(format!("Unknown hint {}", hint))),
+            });
+            parser.next_if_token(Token::Comma);
+        }
+        Ok(hints)
+    }
+
+    /// Parses a select clause. The SELECT keyword has already been consumed. A lone * is
+    /// represented as an empty select clause, other wildcards as Wildcard expressions.
     fn parse_clause_select(&mut self) -> Result<Vec<(ast::Expression, Option<String>)>> {
         let mut select = Vec::new();
-        if self.next_if_token(Keyword::Select.into()).is_none() {
-            return Ok(select);
-        }
         loop {
-            if self.next_if_token(Token::Asterisk).is_some() && select.is_empty() {
-                break;
-            }
-            let expr = self.parse_expression(0)?;
+            let expr = if self.next_if_token(Token::Asterisk).is_some() {
+                if select.is_empty() && self.peek()? != Some(Token::Comma) {
+                    break;
+                }
+                ast::Expression::Wildcard(None)
+            } else {
+                self.parse_expression(0)?
+            };
             let label = match self.peek()? {
+                _ if matches!(expr, ast::Expression::Wildcard(_)) => None,
                 Some(Token::Keyword(Keyword::As)) => {
                     self.next()?;
                     Some(self.next_ident()?)
@@ -511,22 +1131,55 @@ impl<'a> Parser<'a> {
     }
 
     /// Parses an expression consisting of at least one atom operated on by any
-    /// number of operators, using the precedence climbing algorithm.
+    /// number of operators, using the precedence climbing algorithm. Each
+    /// nested expression and each operator applied to the left-hand side counts
+    /// towards the nesting depth.
     fn parse_expression(&mut self, min_prec: u8) -> Result<ast::Expression> {
+        let depth = self.depth;
+        self.nest()?;
         let mut lhs = if let Some(prefix) = self.next_if_operator::<PrefixOperator>(min_prec)? {
-            prefix.build(self.parse_expression(prefix.prec() + prefix.assoc())?)
+            match (&prefix, self.peek()?) {
+                // Minus binds tighter than any other operator, so a negative number literal can
+                // be folded. This also allows the minimum integer, whose magnitude is too large
+                // for a positive integer literal.
+                (PrefixOperator::Minus, Some(Token::Number(n))) => {
+                    self.next()?;
+                    Self::parse_number(&n, true)?.into()
+                }
+                _ => prefix.build(self.parse_expression(prefix.prec() + prefix.assoc())?),
+            }
         } else {
             self.parse_expression_atom()?
         };
         while let Some(postfix) = self.next_if_operator::<PostfixOperator>(min_prec)? {
+            self.nest()?;
             lhs = postfix.build(lhs)
         }
         while let Some(infix) = self.next_if_operator::<InfixOperator>(min_prec)? {
-            lhs = infix.build(lhs, self.parse_expression(infix.prec() + infix.assoc())?)
+            self.nest()?;
+            lhs = infix.build(lhs, self)?
         }
+        self.depth = depth;
         Ok(lhs)
     }
 
+    /// Parses a number literal, negating it if requested. Integers are given
+    /// in decimal, or in hexadecimal with a 0x prefix, and numbers with a
+    /// decimal point or exponent are floats.
+    fn parse_number(n: &str, negate: bool) -> Result<ast::Literal> {
+        let sign = if negate { "-" } else { "" };
+        if let Some(hex) = n.strip_prefix("0x") {
+            if hex.is_empty() {
+                return Err(Error::Parse(format!("Invalid hexadecimal literal {}", n)));
+            }
+            Ok(ast::Literal::Integer(i64::from_str_radix(&format!("{}{}", sign, hex), 16)?))
+        } else if n.chars().all(|c| c.is_ascii_digit()) {
+            Ok(ast::Literal::Integer(format!("{}{}", sign, n).parse()?))
+        } else {
+            Ok(ast::Literal::Float(format!("{}{}", sign, n).parse()?))
+        }
+    }
+
     /// Parses an expression atom
     fn parse_expression_atom(&mut self) -> Result<ast::Expression> {
         Ok(match self.next()? {
@@ -549,23 +1202,39 @@ impl<'a> Parser<'a> {
                     let mut relation = None;
                     let mut field = i;
                     if self.next_if_token(Token::Period).is_some() {
+                        if self.next_if_token(Token::Asterisk).is_some() {
+                            return Ok(ast::Expression::Wildcard(Some(field)));
+                        }
                         relation = Some(field);
                         field = self.next_ident()?;
                     }
                     ast::Expression::Field(relation, field)
                 }
             }
-            Token::Number(n) => {
-                if n.chars().all(|c| c.is_ascii_digit()) {
-                    ast::Literal::Integer(n.parse()?).into()
-                } else {
-                    ast::Literal::Float(n.parse()?).into()
-                }
+            Token::Number(n) => Self::parse_number(&n, false)?.into(),
+            Token::OpenParen if self.peek()? == Some(Keyword::Select.into()) => {
+                let subquery = self.parse_statement_select()?;
+                self.next_expect(Some(Token::CloseParen))?;
+                ast::Expression::Subquery(Box::new(subquery))
             }
+            // A parenthesized expression, or a row value if there are several.
             Token::OpenParen => {
-                let expr = self.parse_expression(0)?;
+                let mut exprs = vec![self.parse_expression(0)?];
+                while self.next_if_token(Token::Comma).is_some() {
+                    exprs.push(self.parse_expression(0)?);
+                }
+                self.next_expect(Some(Token::CloseParen))?;
+                match exprs.len() {
+                    1 => exprs.remove(0),
+                    _ => ast::Expression::Row(exprs),
+                }
+            }
+            Token::Keyword(Keyword::Exists) => {
+                self.next_expect(Some(Token::OpenParen))?;
+                let subquery = self.parse_statement_select()?;
                 self.next_expect(Some(Token::CloseParen))?;
-                expr
+                ast::Operation::Exists(Box::new(ast::Expression::Subquery(Box::new(subquery))))
+                    .into()
             }
             Token::String(s) => ast::Literal::String(s).into(),
             Token::Keyword(Keyword::False) => ast::Literal::Boolean(false).into(),
@@ -584,6 +1253,11 @@ trait Operator: Sized {
     fn from(token: &Token) -> Option<Self>;
     /// Augments an operator by allowing it to parse any modifiers.
     fn augment(self, parser: &mut Parser) -> Result<Self>;
+    /// Checks whether the operator applies when its token is followed by the given tokens, for
+    /// operators spanning several tokens
+    fn followed_by(&self, _tokens: &[Token]) -> bool {
+        true
+    }
     /// Returns the operator's associativity
     fn assoc(&self) -> u8;
     /// Returns the operator's precedence
@@ -636,11 +1310,14 @@ impl Operator for PrefixOperator {
 enum InfixOperator {
     Add,
     And,
+    Between { not: bool },
     Divide,
     Equal,
     Exponentiate,
     GreaterThan,
     GreaterThanOrEqual,
+    In { not: bool },
+    IsDistinctFrom { not: bool },
     LessThan,
     LessThanOrEqual,
     Like,
@@ -652,26 +1329,60 @@ enum InfixOperator {
 }
 
 impl InfixOperator {
-    fn build(&self, lhs: ast::Expression, rhs: ast::Expression) -> ast::Expression {
-        let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
-        match self {
-            Self::Add => ast::Operation::Add(lhs, rhs),
-            Self::And => ast::Operation::And(lhs, rhs),
-            Self::Divide => ast::Operation::Divide(lhs, rhs),
-            Self::Equal => ast::Operation::Equal(lhs, rhs),
-            Self::Exponentiate => ast::Operation::Exponentiate(lhs, rhs),
-            Self::GreaterThan => ast::Operation::GreaterThan(lhs, rhs),
-            Self::GreaterThanOrEqual => ast::Operation::GreaterThanOrEqual(lhs, rhs),
-            Self::LessThan => ast::Operation::LessThan(lhs, rhs),
-            Self::LessThanOrEqual => ast::Operation::LessThanOrEqual(lhs, rhs),
-            Self::Like => ast::Operation::Like(lhs, rhs),
-            Self::Modulo => ast::Operation::Modulo(lhs, rhs),
-            Self::Multiply => ast::Operation::Multiply(lhs, rhs),
-            Self::NotEqual => ast::Operation::NotEqual(lhs, rhs),
-            Self::Or => ast::Operation::Or(lhs, rhs),
-            Self::Subtract => ast::Operation::Subtract(lhs, rhs),
-        }
-        .into()
+    /// Parses the right-hand operand(s) and builds the operation.
+    fn build(&self, lhs: ast::Expression, parser: &mut Parser) -> Result<ast::Expression> {
+        let lhs = Box::new(lhs);
+        let prec = self.prec() + self.assoc();
+        let rhs = |parser: &mut Parser| parser.parse_expression(prec).map(Box::new);
+        let operation = match self {
+            Self::Add => ast::Operation::Add(lhs, rhs(parser)?),
+            Self::And => ast::Operation::And(lhs, rhs(parser)?),
+            // BETWEEN takes a lower and an upper bound, separated by AND.
+            Self::Between { .. } => {
+                let lower = rhs(parser)?;
+                parser.next_expect(Some(Keyword::And.into()))?;
+                ast::Operation::Between(lhs, lower, rhs(parser)?)
+            }
+            Self::Divide => ast::Operation::Divide(lhs, rhs(parser)?),
+            Self::Equal => ast::Operation::Equal(lhs, rhs(parser)?),
+            Self::Exponentiate => ast::Operation::Exponentiate(lhs, rhs(parser)?),
+            Self::GreaterThan => ast::Operation::GreaterThan(lhs, rhs(parser)?),
+            Self::GreaterThanOrEqual => ast::Operation::GreaterThanOrEqual(lhs, rhs(parser)?),
+            // IN takes either a subquery or a list of expressions, in parentheses.
+            Self::In { .. } => {
+                parser.next_expect(Some(Token::OpenParen))?;
+                let operation = if parser.peek()? == Some(Keyword::Select.into()) {
+                    let subquery =
+                        ast::Expression::Subquery(Box::new(parser.parse_statement_select()?));
+                    ast::Operation::In(lhs, Box::new(subquery))
+                } else {
+                    let mut list = vec![parser.parse_expression(0)?];
+                    while parser.next_if_token(Token::Comma).is_some() {
+                        list.push(parser.parse_expression(0)?);
+                    }
+                    ast::Operation::InList(lhs, list)
+                };
+                parser.next_expect(Some(Token::CloseParen))?;
+                operation
+            }
+            Self::IsDistinctFrom { .. } => ast::Operation::IsDistinctFrom(lhs, rhs(parser)?),
+            Self::LessThan => ast::Operation::LessThan(lhs, rhs(parser)?),
+            Self::LessThanOrEqual => ast::Operation::LessThanOrEqual(lhs, rhs(parser)?),
+            Self::Like => ast::Operation::Like(lhs, rhs(parser)?),
+            Self::Modulo => ast::Operation::Modulo(lhs, rhs(parser)?),
+            Self::Multiply => ast::Operation::Multiply(lhs, rhs(parser)?),
+            Self::NotEqual => ast::Operation::NotEqual(lhs, rhs(parser)?),
+            Self::Or => ast::Operation::Or(lhs, rhs(parser)?),
+            Self::Subtract => ast::Operation::Subtract(lhs, rhs(parser)?),
+        };
+        Ok(match self {
+            Self::Between { not: true }
+            | Self::In { not: true }
+            | Self::IsDistinctFrom { not: true } => {
+                ast::Operation::Not(Box::new(operation.into())).into()
+            }
+            _ => operation.into(),
+        })
     }
 }
 
@@ -684,7 +1395,11 @@ impl Operator for InfixOperator {
             Token::GreaterThan => Self::GreaterThan,
             Token::GreaterThanOrEqual => Self::GreaterThanOrEqual,
             Token::Keyword(Keyword::And) => Self::And,
+            Token::Keyword(Keyword::Between) => Self::Between { not: false },
+            Token::Keyword(Keyword::In) => Self::In { not: false },
+            Token::Keyword(Keyword::Is) => Self::IsDistinctFrom { not: false },
             Token::Keyword(Keyword::Like) => Self::Like,
+            Token::Keyword(Keyword::Not) => Self::In { not: true },
             Token::Keyword(Keyword::Or) => Self::Or,
             Token::LessOrGreaterThan => Self::NotEqual,
             Token::LessThan => Self::LessThan,
@@ -698,8 +1413,33 @@ impl Operator for InfixOperator {
         })
     }
 
-    fn augment(self, _parser: &mut Parser) -> Result<Self> {
-        Ok(self)
+    // NOT is parsed as NOT IN, but may also be NOT BETWEEN. IS is only parsed as an infix
+    // operator for IS [NOT] DISTINCT FROM, since IS [NOT] NULL is a postfix operator.
+    fn augment(self, parser: &mut Parser) -> Result<Self> {
+        match self {
+            Self::In { not: true } => match parser.next()? {
+                Token::Keyword(Keyword::Between) => Ok(Self::Between { not: true }),
+                Token::Keyword(Keyword::In) => Ok(self),
+                token => Err(Error::Parse(format!("Expected IN or BETWEEN, found {}", token))),
+            },
+            Self::IsDistinctFrom { .. } => {
+                let not = parser.next_if_token(Keyword::Not.into()).is_some();
+                parser.next_expect(Some(Keyword::Distinct.into()))?;
+                parser.next_expect(Some(Keyword::From.into()))?;
+                Ok(Self::IsDistinctFrom { not })
+            }
+            _ => Ok(self),
+        }
+    }
+
+    fn followed_by(&self, tokens: &[Token]) -> bool {
+        match self {
+            Self::In { not: true } => matches!(
+                tokens.first(),
+                Some(Token::Keyword(Keyword::In)) | Some(Token::Keyword(Keyword::Between))
+            ),
+            _ => true,
+        }
     }
 
     fn assoc(&self) -> u8 {
@@ -713,7 +1453,12 @@ impl Operator for InfixOperator {
         match self {
             Self::Or => 1,
             Self::And => 2,
-            Self::Equal | Self::NotEqual | Self::Like => 3,
+            Self::Between { .. }
+            | Self::Equal
+            | Self::In { .. }
+            | Self::IsDistinctFrom { .. }
+            | Self::NotEqual
+            | Self::Like => 3,
             Self::GreaterThan
             | Self::GreaterThanOrEqual
             | Self::LessThan
@@ -771,6 +1516,18 @@ impl Operator for PostfixOperator {
         Ok(self)
     }
 
+    // IS [NOT] DISTINCT FROM is an infix operator, so leave it to the infix parser.
+    fn followed_by(&self, tokens: &[Token]) -> bool {
+        match self {
+            Self::IsNull { .. } => !matches!(
+                tokens,
+                [Token::Keyword(Keyword::Distinct), ..]
+                    | [Token::Keyword(Keyword::Not), Token::Keyword(Keyword::Distinct), ..]
+            ),
+            Self::Factorial => true,
+        }
+    }
+
     fn assoc(&self) -> u8 {
         ASSOC_LEFT
     }
@@ -781,14 +1538,92 @@ impl Operator for PostfixOperator {
 }
 
 // Formats an identifier by quoting it as appropriate
-pub(super) fn format_ident(ident: &str) -> String {
-    lazy_static! {
-        static ref RE_IDENT: Regex = Regex::new(r#"^\w[\w_]*$"#).unwrap();
-    }
-
-    if RE_IDENT.is_match(ident) && Keyword::from_str(ident).is_none() {
+pub fn format_ident(ident: &str) -> String {
+    // Unquoted identifiers are lowercased by the lexer, so quote any uppercase ones, and any that
+    // the lexer wouldn't scan as a single identifier.
+    let mut chars = ident.chars();
+    if chars.next().is_some_and(|c| c.is_alphabetic())
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+        && ident.to_lowercase() == ident
+        && Keyword::from_str(ident).is_none()
+    {
         ident.to_string()
     } else {
         format!("\"{}\"", ident.replace('\"', "\"\""))
     }
 }
+
+/// Formats a value as a SQL literal.
+pub fn format_value(value: &Value) -> String {
+    match value {
+        Value::Null => "NULL".into(),
+        Value::Boolean(true) => "TRUE".into(),
+        Value::Boolean(false) => "FALSE".into(),
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) if f.is_nan() => "NAN".into(),
+        Value::Float(f) if f.is_infinite() && f.is_sign_positive() => "INFINITY".into(),
+        Value::Float(f) if f.is_infinite() => "-INFINITY".into(),
+        Value::Float(f) => format!("{:?}", f),
+        Value::String(s) => format!("'{}'", s.replace('\'', "''")),
+    }
+}
+
+/// Returns the byte position just past the first statement-terminating semicolon in the input,
+/// if any, ignoring semicolons inside string literals, quoted identifiers, and comments.
+pub fn statement_end(input: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '/') if chars.next_if(|(_, c)| *c == '*').is_some() => quote = Some('*'),
+            (None, '-') if chars.next_if(|(_, c)| *c == '-').is_some() => quote = Some('\n'),
+            (None, ';') => return Some(i + 1),
+            (Some('*'), '*') if chars.next_if(|(_, c)| *c == '/').is_some() => quote = None,
+            (Some(q), c) if c == q && q != '*' => quote = None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits the input into semicolon-terminated statements, skipping empty ones containing only
+/// whitespace and comments. The statements are trimmed but not parsed, so they may be invalid.
+pub fn split_statements(mut input: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    while !input.is_empty() {
+        let end = statement_end(input).unwrap_or(input.len());
+        let statement = &input[..end];
+        if Lexer::new(statement).any(|token| !matches!(token, Ok(Token::Semicolon))) {
+            statements.push(statement.trim());
+        }
+        input = &input[end..];
+    }
+    statements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split() {
+        assert_eq!(split_statements(""), Vec::<&str>::new());
+        assert_eq!(split_statements(" ;; /* comment; */ "), Vec::<&str>::new());
+        assert_eq!(split_statements("SELECT 1"), vec!["SELECT 1"]);
+        assert_eq!(
+            split_statements("SELECT 1; SELECT 2;\nSELECT 3"),
+            vec!["SELECT 1;", "SELECT 2;", "SELECT 3"]
+        );
+
+        // Semicolons in strings, quoted identifiers, and comments don't end statements.
+        assert_eq!(
+            split_statements("SELECT 1 -- a; b\n; SELECT '--'; -- c;"),
+            vec!["SELECT 1 -- a; b\n;", "SELECT '--';"]
+        );
+        assert_eq!(
+            split_statements("SELECT 'a;''b' AS \"c;\"\"d\" /* e; */; SELECT /*+ f; */ 1 /* g;*"),
+            vec!["SELECT 'a;''b' AS \"c;\"\"d\" /* e; */;", "SELECT /*+ f; */ 1 /* g;*"]
+        );
+    }
+}
@@ -1,21 +1,24 @@
 mod optimizer;
 mod planner;
-use optimizer::Optimizer as _;
+use optimizer::Optimizer;
+pub use optimizer::RULES;
 use planner::Planner;
 
 use super::engine::Transaction;
-use super::execution::{Executor, ResultSet};
+use super::execution::{Executor, Memory, ResultSet};
 use super::parser::ast;
-use super::schema::{Catalog, Table};
-use super::types::{Expression, Value};
+use super::schema::{Catalog, Function, Index, Table, Trigger};
+use super::types::{DataType, Expression, Range, Sample, Value};
 use crate::error::Result;
 
 use serde_derive::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fmt::{self, Display};
+use std::ops::Bound;
 
-/// A query plan
+/// A query plan, along with the query's optimizer hints
 #[derive(Debug)]
-pub struct Plan(pub Node);
+pub struct Plan(pub Node, pub Vec<ast::Hint>);
 
 impl Display for Plan {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -29,25 +32,103 @@ impl Plan {
         Planner::new(catalog).build(statement)
     }
 
-    /// Executes the plan, consuming it.
-    pub fn execute<T: Transaction + 'static>(self, txn: &mut T) -> Result<ResultSet> {
-        <dyn Executor<T>>::build(self.0).execute(txn)
+    /// Executes the plan, consuming it. Executors track buffered rows in the memory tracker.
+    pub fn execute<T: Transaction + 'static>(
+        self,
+        txn: &mut T,
+        memory: &Memory,
+    ) -> Result<ResultSet> {
+        let key_column = self.0.key_column(txn)?;
+        match <dyn Executor<T>>::build(self.0, memory)?.execute(txn)? {
+            ResultSet::Query { columns, rows, .. } => {
+                Ok(ResultSet::Query { columns, key_column, rows })
+            }
+            resultset => Ok(resultset),
+        }
     }
 
-    /// Optimizes the plan, consuming it.
-    pub fn optimize<C: Catalog>(self, catalog: &mut C) -> Result<Self> {
-        let mut root = self.0;
-        root = optimizer::ConstantFolder.optimize(root)?;
-        root = optimizer::FilterPushdown.optimize(root)?;
-        root = optimizer::IndexLookup::new(catalog).optimize(root)?;
-        root = optimizer::NoopCleaner.optimize(root)?;
-        root = optimizer::JoinType.optimize(root)?;
-        Ok(Plan(root))
+    /// Parallelizes table scans across the given number of workers, along with any filters,
+    /// projections, and aggregations directly above them, which are then run by each worker on
+    /// its partition of the table. A single worker leaves the plan unchanged.
+    pub fn parallelize(self, workers: usize) -> Result<Self> {
+        if workers <= 1 {
+            return Ok(self);
+        }
+        let Plan(root, hints) = self;
+        let root = root.transform(&Ok, &|n| {
+            Ok(match n {
+                n @ Node::Scan { .. } => Node::Parallel { source: Box::new(n), workers },
+                Node::Aggregation { source, aggregates } => match *source {
+                    Node::Parallel { source, workers } if !source.is_aggregation() => {
+                        let source = Box::new(Node::Aggregation { source, aggregates });
+                        Node::Parallel { source, workers }
+                    }
+                    source => Node::Aggregation { source: Box::new(source), aggregates },
+                },
+                Node::Filter { source, predicate } => match *source {
+                    Node::Parallel { source, workers } if !source.is_aggregation() => {
+                        let source = Box::new(Node::Filter { source, predicate });
+                        Node::Parallel { source, workers }
+                    }
+                    source => Node::Filter { source: Box::new(source), predicate },
+                },
+                Node::Projection { source, expressions } => match *source {
+                    Node::Parallel { source, workers } if !source.is_aggregation() => {
+                        let source = Box::new(Node::Projection { source, expressions });
+                        Node::Parallel { source, workers }
+                    }
+                    source => Node::Projection { source: Box::new(source), expressions },
+                },
+                n => n,
+            })
+        })?;
+        Ok(Plan(root, hints))
+    }
+
+    /// Optimizes the plan, consuming it. Rules named in disabled are skipped,
+    /// but the plan's hints take precedence.
+    pub fn optimize<C: Catalog>(self, catalog: &mut C, disabled: &HashSet<String>) -> Result<Self> {
+        let Plan(mut root, hints) = self;
+        let mut disabled = disabled.clone();
+        let mut no_index = HashSet::new();
+        for hint in &hints {
+            match hint {
+                ast::Hint::NoIndex(tables) if tables.is_empty() => {
+                    disabled.insert("index_lookup".into());
+                }
+                ast::Hint::NoIndex(tables) => no_index.extend(tables.iter().cloned()),
+                ast::Hint::HashJoin => {
+                    disabled.remove("hash_join");
+                }
+                ast::Hint::NoHashJoin => {
+                    disabled.insert("hash_join".into());
+                }
+                ast::Hint::NoJoinOrder => {
+                    disabled.insert("join_order".into());
+                }
+            }
+        }
+        let catalog = &*catalog;
+        let rules: [Box<dyn Optimizer + '_>; 9] = [
+            Box::new(optimizer::ConstantFolder),
+            Box::new(optimizer::FilterPushdown),
+            Box::new(optimizer::IndexLookup::new(catalog, no_index)),
+            Box::new(optimizer::OrderedScan::new(catalog)),
+            Box::new(optimizer::JoinOrder::new(catalog)),
+            Box::new(optimizer::NoopCleaner),
+            Box::new(optimizer::LimitPushdown),
+            Box::new(optimizer::JoinType),
+            Box::new(optimizer::ColumnPruning::new(catalog)),
+        ];
+        for rule in rules.iter().filter(|rule| !disabled.contains(rule.name())) {
+            root = rule.optimize(root)?;
+        }
+        Ok(Plan(root, hints))
     }
 }
 
 /// A plan node
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Node {
     Aggregation {
         source: Box<Node>,
@@ -73,6 +154,7 @@ pub enum Node {
         right: Box<Node>,
         right_field: (usize, Option<(Option<String>, String)>),
         outer: bool,
+        full: bool,
     },
     IndexLookup {
         table: String,
@@ -100,6 +182,7 @@ pub enum Node {
         right: Box<Node>,
         predicate: Option<Expression>,
         outer: bool,
+        full: bool,
     },
     Nothing,
     Offset {
@@ -108,7 +191,7 @@ pub enum Node {
     },
     Order {
         source: Box<Node>,
-        orders: Vec<(Expression, Direction)>,
+        orders: Vec<(Expression, Direction, Nulls)>,
     },
     Projection {
         source: Box<Node>,
@@ -118,15 +201,272 @@ pub enum Node {
         table: String,
         alias: Option<String>,
         filter: Option<Expression>,
+        /// The columns to read, if not all, see Transaction::scan_columns().
+        columns: Option<Vec<String>>,
     },
     Update {
         table: String,
         source: Box<Node>,
         expressions: Vec<(usize, Option<String>, Expression)>,
     },
+    Parallel {
+        source: Box<Node>,
+        workers: usize,
+    },
+    SemiJoin {
+        left: Box<Node>,
+        left_keys: Vec<Expression>,
+        right: Box<Node>,
+        anti: bool,
+        null_aware: bool,
+    },
+    KeyRangeScan {
+        table: String,
+        alias: Option<String>,
+        ranges: Vec<Range>,
+        reverse: bool,
+    },
+    IndexRangeScan {
+        table: String,
+        alias: Option<String>,
+        column: String,
+        ranges: Vec<Range>,
+        reverse: bool,
+        limit: Option<u64>,
+    },
+    IndexOnlyScan {
+        table: String,
+        alias: Option<String>,
+        column: String,
+        ranges: Vec<Range>,
+        reverse: bool,
+        limit: Option<u64>,
+    },
+    TopK {
+        source: Box<Node>,
+        orders: Vec<(Expression, Direction, Nulls)>,
+        limit: u64,
+    },
+    Distinct {
+        source: Box<Node>,
+        on: Vec<Expression>,
+    },
+    ScalarJoin {
+        left: Box<Node>,
+        left_keys: Vec<Expression>,
+        right: Box<Node>,
+        default: Value,
+    },
+    Values {
+        alias: Option<String>,
+        columns: Vec<String>,
+        rows: Vec<Vec<Expression>>,
+    },
+    TableFunction {
+        source: Box<Node>,
+        function: TableFunction,
+        args: Vec<Expression>,
+        alias: Option<String>,
+        column: String,
+    },
+    CreateTrigger {
+        trigger: Trigger,
+    },
+    DropTrigger {
+        table: String,
+        name: String,
+    },
+    CreateIndex {
+        index: Index,
+    },
+    DropIndex {
+        table: String,
+        name: String,
+    },
+    CreateFunction {
+        function: Function,
+    },
+    DropFunction {
+        name: String,
+    },
+    UndropTable {
+        table: String,
+    },
+    /// Appends the 1-based position of each row as an "ordinality" column.
+    Ordinality {
+        source: Box<Node>,
+    },
+    /// Scans a random sample of a table's rows, see Transaction::scan_sample().
+    SampleScan {
+        table: String,
+        alias: Option<String>,
+        sample: Sample,
+        filter: Option<Expression>,
+    },
+    /// Scans a table's rows in the order of an index created with CREATE INDEX, or in reverse.
+    IndexOrderScan {
+        table: String,
+        alias: Option<String>,
+        index: String,
+        reverse: bool,
+    },
+    /// Creates a table from the rows of a query, see CREATE TABLE AS. The datatypes are those
+    /// known from the query plan, see datatypes(), and the rest are inferred from the rows.
+    CreateTableAs {
+        table: String,
+        source: Box<Node>,
+        datatypes: Vec<Option<DataType>>,
+    },
 }
 
 impl Node {
+    /// Returns true if the node is an aggregation.
+    fn is_aggregation(&self) -> bool {
+        matches!(self, Self::Aggregation { .. })
+    }
+
+    /// Returns the index of the output column containing the primary key, if the node reads a
+    /// single table and passes its primary key column through. Used for keyset pagination.
+    pub fn key_column<C: Catalog + ?Sized>(&self, catalog: &C) -> Result<Option<usize>> {
+        Ok(match self {
+            Self::IndexLookup { table, .. }
+            | Self::IndexOnlyScan { table, .. }
+            | Self::IndexRangeScan { table, .. }
+            | Self::KeyLookup { table, .. }
+            | Self::KeyRangeScan { table, .. }
+            | Self::SampleScan { table, .. }
+            | Self::IndexOrderScan { table, .. }
+            | Self::Scan { table, .. } => {
+                catalog.must_read_table(table)?.columns.iter().position(|c| c.primary_key)
+            }
+            Self::Distinct { source, .. }
+            | Self::Filter { source, .. }
+            | Self::Limit { source, .. }
+            | Self::Offset { source, .. }
+            | Self::Order { source, .. }
+            | Self::Ordinality { source }
+            | Self::Parallel { source, .. }
+            | Self::TopK { source, .. } => source.key_column(catalog)?,
+            Self::ScalarJoin { left, .. } | Self::SemiJoin { left, .. } => {
+                left.key_column(catalog)?
+            }
+            Self::Projection { source, expressions } => match source.key_column(catalog)? {
+                Some(key) => expressions
+                    .iter()
+                    .position(|(e, _)| matches!(e, Expression::Field(i, _) if *i == key)),
+                None => None,
+            },
+            Self::Aggregation { .. }
+            | Self::CreateFunction { .. }
+            | Self::CreateTable { .. }
+            | Self::CreateTableAs { .. }
+            | Self::CreateTrigger { .. }
+            | Self::CreateIndex { .. }
+            | Self::Delete { .. }
+            | Self::DropFunction { .. }
+            | Self::UndropTable { .. }
+            | Self::DropTable { .. }
+            | Self::DropTrigger { .. }
+            | Self::DropIndex { .. }
+            | Self::HashJoin { .. }
+            | Self::Insert { .. }
+            | Self::NestedLoopJoin { .. }
+            | Self::Nothing
+            | Self::TableFunction { .. }
+            | Self::Update { .. }
+            | Self::Values { .. } => None,
+        })
+    }
+
+    /// Returns the datatypes of the node's output columns, where they can be determined from
+    /// table columns and expressions without executing the node. Returns an empty vector if
+    /// the number of columns isn't known either. Used by CREATE TABLE AS.
+    pub fn datatypes<C: Catalog + ?Sized>(&self, catalog: &C) -> Result<Vec<Option<DataType>>> {
+        Ok(match self {
+            Self::IndexLookup { table, .. }
+            | Self::IndexOnlyScan { table, .. }
+            | Self::IndexRangeScan { table, .. }
+            | Self::KeyLookup { table, .. }
+            | Self::KeyRangeScan { table, .. }
+            | Self::SampleScan { table, .. }
+            | Self::IndexOrderScan { table, .. }
+            | Self::Scan { table, .. } => catalog
+                .must_read_table(table)?
+                .columns
+                .into_iter()
+                .map(|c| Some(c.datatype))
+                .collect(),
+            Self::Distinct { source, .. }
+            | Self::Filter { source, .. }
+            | Self::Limit { source, .. }
+            | Self::Offset { source, .. }
+            | Self::Order { source, .. }
+            | Self::Parallel { source, .. }
+            | Self::TopK { source, .. } => source.datatypes(catalog)?,
+            Self::SemiJoin { left, .. } => left.datatypes(catalog)?,
+            Self::Ordinality { source } => {
+                let mut datatypes = source.datatypes(catalog)?;
+                if !datatypes.is_empty() {
+                    datatypes.push(Some(DataType::Integer));
+                }
+                datatypes
+            }
+            Self::TableFunction { source, .. } => {
+                let mut datatypes = source.datatypes(catalog)?;
+                if !datatypes.is_empty() {
+                    datatypes.push(None);
+                }
+                datatypes
+            }
+            Self::HashJoin { left, right, .. } | Self::NestedLoopJoin { left, right, .. } => {
+                match (left.datatypes(catalog)?, right.datatypes(catalog)?) {
+                    (left, right) if left.is_empty() || right.is_empty() => Vec::new(),
+                    (left, right) => left.into_iter().chain(right).collect(),
+                }
+            }
+            // The right rows hold the keys followed by the value.
+            Self::ScalarJoin { left, right, .. } => {
+                match (left.datatypes(catalog)?, right.datatypes(catalog)?.pop()) {
+                    (left, _) if left.is_empty() => Vec::new(),
+                    (left, value) => left.into_iter().chain([value.flatten()]).collect(),
+                }
+            }
+            Self::Projection { source, expressions } => {
+                let inputs = source.datatypes(catalog)?;
+                expressions.iter().map(|(e, _)| e.datatype(&inputs)).collect()
+            }
+            // The aggregate arguments are followed by the group values in both the input and
+            // output rows.
+            Self::Aggregation { source, aggregates } => {
+                let mut datatypes = source.datatypes(catalog)?;
+                for (datatype, aggregate) in datatypes.iter_mut().zip(aggregates) {
+                    match aggregate {
+                        Aggregate::Average => *datatype = Some(DataType::Float),
+                        Aggregate::Count => *datatype = Some(DataType::Integer),
+                        Aggregate::Max | Aggregate::Min | Aggregate::Sum => {}
+                    }
+                }
+                datatypes
+            }
+            // Rows may mix INTEGER and FLOAT values, so leave the types to execution.
+            Self::Values { columns, .. } => vec![None; columns.len()],
+            Self::CreateFunction { .. }
+            | Self::CreateTable { .. }
+            | Self::CreateTableAs { .. }
+            | Self::CreateTrigger { .. }
+            | Self::CreateIndex { .. }
+            | Self::Delete { .. }
+            | Self::DropFunction { .. }
+            | Self::UndropTable { .. }
+            | Self::DropTable { .. }
+            | Self::DropTrigger { .. }
+            | Self::DropIndex { .. }
+            | Self::Insert { .. }
+            | Self::Nothing
+            | Self::Update { .. } => Vec::new(),
+        })
+    }
+
     /// Recursively transforms nodes by applying functions before and after descending.
     pub fn transform<B, A>(mut self, before: &B, after: &A) -> Result<Self>
     where
@@ -134,57 +474,98 @@ impl Node {
         A: Fn(Self) -> Result<Self>,
     {
         self = before(self)?;
-        self = match self {
-            n @ Self::CreateTable { .. }
+        self = self.map_children(&|n| n.transform(before, after))?;
+        after(self)
+    }
+
+    /// Transforms the node's immediate children by applying a function to them.
+    fn map_children<F: Fn(Self) -> Result<Self>>(self, f: &F) -> Result<Self> {
+        Ok(match self {
+            n @ Self::CreateFunction { .. }
+            | n @ Self::CreateTable { .. }
+            | n @ Self::CreateTrigger { .. }
+            | n @ Self::CreateIndex { .. }
+            | n @ Self::DropFunction { .. }
+            | n @ Self::UndropTable { .. }
             | n @ Self::DropTable { .. }
+            | n @ Self::DropTrigger { .. }
+            | n @ Self::DropIndex { .. }
             | n @ Self::IndexLookup { .. }
+            | n @ Self::IndexOrderScan { .. }
+            | n @ Self::IndexOnlyScan { .. }
             | n @ Self::Insert { .. }
+            | n @ Self::IndexRangeScan { .. }
             | n @ Self::KeyLookup { .. }
+            | n @ Self::KeyRangeScan { .. }
             | n @ Self::Nothing
-            | n @ Self::Scan { .. } => n,
+            | n @ Self::SampleScan { .. }
+            | n @ Self::Scan { .. }
+            | n @ Self::Values { .. } => n,
 
             Self::Aggregation { source, aggregates } => {
-                Self::Aggregation { source: source.transform(before, after)?.into(), aggregates }
+                Self::Aggregation { source: f(*source)?.into(), aggregates }
             }
-            Self::Delete { table, source } => {
-                Self::Delete { table, source: source.transform(before, after)?.into() }
+            Self::Delete { table, source } => Self::Delete { table, source: f(*source)?.into() },
+            Self::CreateTableAs { table, source, datatypes } => {
+                Self::CreateTableAs { table, source: f(*source)?.into(), datatypes }
             }
             Self::Filter { source, predicate } => {
-                Self::Filter { source: source.transform(before, after)?.into(), predicate }
+                Self::Filter { source: f(*source)?.into(), predicate }
             }
-            Self::HashJoin { left, left_field, right, right_field, outer } => Self::HashJoin {
-                left: left.transform(before, after)?.into(),
-                left_field,
-                right: right.transform(before, after)?.into(),
-                right_field,
-                outer,
-            },
-            Self::Limit { source, limit } => {
-                Self::Limit { source: source.transform(before, after)?.into(), limit }
+            Self::HashJoin { left, left_field, right, right_field, outer, full } => {
+                Self::HashJoin {
+                    left: f(*left)?.into(),
+                    left_field,
+                    right: f(*right)?.into(),
+                    right_field,
+                    outer,
+                    full,
+                }
             }
-            Self::NestedLoopJoin { left, left_size, right, predicate, outer } => {
+            Self::Limit { source, limit } => Self::Limit { source: f(*source)?.into(), limit },
+            Self::NestedLoopJoin { left, left_size, right, predicate, outer, full } => {
                 Self::NestedLoopJoin {
-                    left: left.transform(before, after)?.into(),
+                    left: f(*left)?.into(),
                     left_size,
-                    right: right.transform(before, after)?.into(),
+                    right: f(*right)?.into(),
                     predicate,
                     outer,
+                    full,
                 }
             }
-            Self::Offset { source, offset } => {
-                Self::Offset { source: source.transform(before, after)?.into(), offset }
+            Self::Offset { source, offset } => Self::Offset { source: f(*source)?.into(), offset },
+            Self::Parallel { source, workers } => {
+                Self::Parallel { source: f(*source)?.into(), workers }
             }
-            Self::Order { source, orders } => {
-                Self::Order { source: source.transform(before, after)?.into(), orders }
+            Self::Order { source, orders } => Self::Order { source: f(*source)?.into(), orders },
+            Self::Ordinality { source } => Self::Ordinality { source: f(*source)?.into() },
+            Self::Distinct { source, on } => Self::Distinct { source: f(*source)?.into(), on },
+            Self::TopK { source, orders, limit } => {
+                Self::TopK { source: f(*source)?.into(), orders, limit }
             }
             Self::Projection { source, expressions } => {
-                Self::Projection { source: source.transform(before, after)?.into(), expressions }
+                Self::Projection { source: f(*source)?.into(), expressions }
+            }
+            Self::SemiJoin { left, left_keys, right, anti, null_aware } => Self::SemiJoin {
+                left: f(*left)?.into(),
+                left_keys,
+                right: f(*right)?.into(),
+                anti,
+                null_aware,
+            },
+            Self::ScalarJoin { left, left_keys, right, default } => Self::ScalarJoin {
+                left: f(*left)?.into(),
+                left_keys,
+                right: f(*right)?.into(),
+                default,
+            },
+            Self::TableFunction { source, function, args, alias, column } => {
+                Self::TableFunction { source: f(*source)?.into(), function, args, alias, column }
             }
             Self::Update { table, source, expressions } => {
-                Self::Update { table, source: source.transform(before, after)?.into(), expressions }
+                Self::Update { table, source: f(*source)?.into(), expressions }
             }
-        };
-        after(self)
+        })
     }
 
     /// Transforms all expressions in a node by calling .transform() on them with the given closure.
@@ -195,16 +576,31 @@ impl Node {
     {
         Ok(match self {
             n @ Self::Aggregation { .. }
+            | n @ Self::CreateFunction { .. }
             | n @ Self::CreateTable { .. }
+            | n @ Self::CreateTableAs { .. }
+            | n @ Self::CreateTrigger { .. }
+            | n @ Self::CreateIndex { .. }
             | n @ Self::Delete { .. }
+            | n @ Self::DropFunction { .. }
+            | n @ Self::UndropTable { .. }
             | n @ Self::DropTable { .. }
+            | n @ Self::DropTrigger { .. }
+            | n @ Self::DropIndex { .. }
             | n @ Self::HashJoin { .. }
             | n @ Self::IndexLookup { .. }
+            | n @ Self::IndexOrderScan { .. }
+            | n @ Self::IndexOnlyScan { .. }
+            | n @ Self::IndexRangeScan { .. }
             | n @ Self::KeyLookup { .. }
+            | n @ Self::KeyRangeScan { .. }
             | n @ Self::Limit { .. }
             | n @ Self::NestedLoopJoin { predicate: None, .. }
             | n @ Self::Nothing
             | n @ Self::Offset { .. }
+            | n @ Self::Ordinality { .. }
+            | n @ Self::Parallel { .. }
+            | n @ Self::SampleScan { filter: None, .. }
             | n @ Self::Scan { filter: None, .. } => n,
 
             Self::Filter { source, predicate } => {
@@ -222,18 +618,36 @@ impl Node {
                 source,
                 orders: orders
                     .into_iter()
-                    .map(|(e, o)| e.transform(before, after).map(|e| (e, o)))
+                    .map(|(e, o, n)| e.transform(before, after).map(|e| (e, o, n)))
                     .collect::<Result<_>>()?,
             },
-            Self::NestedLoopJoin { left, left_size, right, predicate: Some(predicate), outer } => {
-                Self::NestedLoopJoin {
-                    left,
-                    left_size,
-                    right,
-                    predicate: Some(predicate.transform(before, after)?),
-                    outer,
-                }
-            }
+            Self::TopK { source, orders, limit } => Self::TopK {
+                source,
+                orders: orders
+                    .into_iter()
+                    .map(|(e, o, n)| e.transform(before, after).map(|e| (e, o, n)))
+                    .collect::<Result<_>>()?,
+                limit,
+            },
+            Self::Distinct { source, on } => Self::Distinct {
+                source,
+                on: on.into_iter().map(|e| e.transform(before, after)).collect::<Result<_>>()?,
+            },
+            Self::NestedLoopJoin {
+                left,
+                left_size,
+                right,
+                predicate: Some(predicate),
+                outer,
+                full,
+            } => Self::NestedLoopJoin {
+                left,
+                left_size,
+                right,
+                predicate: Some(predicate.transform(before, after)?),
+                outer,
+                full,
+            },
             Self::Projection { source, expressions } => Self::Projection {
                 source,
                 expressions: expressions
@@ -241,9 +655,52 @@ impl Node {
                     .map(|(e, l)| Ok((e.transform(before, after)?, l)))
                     .collect::<Result<_>>()?,
             },
-            Self::Scan { table, alias, filter: Some(filter) } => {
-                Self::Scan { table, alias, filter: Some(filter.transform(before, after)?) }
+            Self::Scan { table, alias, filter: Some(filter), columns } => {
+                Self::Scan { table, alias, filter: Some(filter.transform(before, after)?), columns }
             }
+            Self::SampleScan { table, alias, sample, filter: Some(filter) } => Self::SampleScan {
+                table,
+                alias,
+                sample,
+                filter: Some(filter.transform(before, after)?),
+            },
+            Self::SemiJoin { left, left_keys, right, anti, null_aware } => Self::SemiJoin {
+                left,
+                left_keys: left_keys
+                    .into_iter()
+                    .map(|e| e.transform(before, after))
+                    .collect::<Result<_>>()?,
+                right,
+                anti,
+                null_aware,
+            },
+            Self::ScalarJoin { left, left_keys, right, default } => Self::ScalarJoin {
+                left,
+                left_keys: left_keys
+                    .into_iter()
+                    .map(|e| e.transform(before, after))
+                    .collect::<Result<_>>()?,
+                right,
+                default,
+            },
+            Self::TableFunction { source, function, args, alias, column } => Self::TableFunction {
+                source,
+                function,
+                args: args
+                    .into_iter()
+                    .map(|e| e.transform(before, after))
+                    .collect::<Result<_>>()?,
+                alias,
+                column,
+            },
+            Self::Values { alias, columns, rows } => Self::Values {
+                alias,
+                columns,
+                rows: rows
+                    .into_iter()
+                    .map(|exprs| exprs.into_iter().map(|e| e.transform(before, after)).collect())
+                    .collect::<Result<_>>()?,
+            },
             Self::Update { table, source, expressions } => Self::Update {
                 table,
                 source,
@@ -276,6 +733,10 @@ impl Node {
             Self::CreateTable { schema } => {
                 s += &format!("CreateTable: {}\n", schema.name);
             }
+            Self::CreateTableAs { table, source, .. } => {
+                s += &format!("CreateTableAs: {}\n", table);
+                s += &source.format(indent, false, true);
+            }
             Self::Delete { source, table } => {
                 s += &format!("Delete: {}\n", table);
                 s += &source.format(indent, false, true);
@@ -283,14 +744,39 @@ impl Node {
             Self::DropTable { table } => {
                 s += &format!("DropTable: {}\n", table);
             }
+            Self::CreateTrigger { trigger } => {
+                s += &format!("CreateTrigger: {} on {}\n", trigger.name, trigger.table);
+            }
+            Self::DropTrigger { table, name } => {
+                s += &format!("DropTrigger: {} on {}\n", name, table);
+            }
+            Self::CreateIndex { index } => {
+                s += &format!("CreateIndex: {} on {}\n", index.name, index.table);
+            }
+            Self::DropIndex { table, name } => {
+                s += &format!("DropIndex: {} on {}\n", name, table);
+            }
+            Self::CreateFunction { function } => {
+                s += &format!("CreateFunction: {}\n", function.name);
+            }
+            Self::DropFunction { name } => {
+                s += &format!("DropFunction: {}\n", name);
+            }
+            Self::UndropTable { table } => {
+                s += &format!("UndropTable: {}\n", table);
+            }
             Self::Filter { source, predicate } => {
                 s += &format!("Filter: {}\n", predicate);
                 s += &source.format(indent, false, true);
             }
-            Self::HashJoin { left, left_field, right, right_field, outer } => {
+            Self::HashJoin { left, left_field, right, right_field, outer, full } => {
                 s += &format!(
                     "HashJoin: {} on {} = {}\n",
-                    if *outer { "outer" } else { "inner" },
+                    match (outer, full) {
+                        (_, true) => "full",
+                        (true, false) => "outer",
+                        (false, false) => "inner",
+                    },
                     match left_field {
                         (_, Some((Some(t), n))) => format!("{}.{}", t, n),
                         (_, Some((None, n))) => n.clone(),
@@ -321,6 +807,45 @@ impl Node {
                 }
                 s += "\n";
             }
+            Self::IndexOnlyScan { table, alias, column, ranges, reverse, limit } => {
+                s += &format!("IndexOnlyScan: {}", table);
+                if let Some(alias) = alias {
+                    s += &format!(" as {}", alias);
+                }
+                s += &format!(" column {} {}", column, format_ranges(ranges));
+                if *reverse {
+                    s += " reverse";
+                }
+                if let Some(limit) = limit {
+                    s += &format!(" limit {}", limit);
+                }
+                s += "\n";
+            }
+            Self::IndexRangeScan { table, alias, column, ranges, reverse, limit } => {
+                s += &format!("IndexRangeScan: {}", table);
+                if let Some(alias) = alias {
+                    s += &format!(" as {}", alias);
+                }
+                s += &format!(" column {} {}", column, format_ranges(ranges));
+                if *reverse {
+                    s += " reverse";
+                }
+                if let Some(limit) = limit {
+                    s += &format!(" limit {}", limit);
+                }
+                s += "\n";
+            }
+            Self::IndexOrderScan { table, alias, index, reverse } => {
+                s += &format!("IndexOrderScan: {}", table);
+                if let Some(alias) = alias {
+                    s += &format!(" as {}", alias);
+                }
+                s += &format!(" index {}", index);
+                if *reverse {
+                    s += " reverse";
+                }
+                s += "\n";
+            }
             Self::Insert { table, columns: _, expressions } => {
                 s += &format!("Insert: {} ({} rows)\n", table, expressions.len());
             }
@@ -339,12 +864,27 @@ impl Node {
                 }
                 s += "\n";
             }
+            Self::KeyRangeScan { table, alias, ranges, reverse } => {
+                s += &format!("KeyRangeScan: {}", table);
+                if let Some(alias) = alias {
+                    s += &format!(" as {}", alias);
+                }
+                s += &format!(" {}", format_ranges(ranges));
+                s += if *reverse { " reverse\n" } else { "\n" };
+            }
             Self::Limit { source, limit } => {
                 s += &format!("Limit: {}\n", limit);
                 s += &source.format(indent, false, true);
             }
-            Self::NestedLoopJoin { left, left_size: _, right, predicate, outer } => {
-                s += &format!("NestedLoopJoin: {}", if *outer { "outer" } else { "inner" });
+            Self::NestedLoopJoin { left, left_size: _, right, predicate, outer, full } => {
+                s += &format!(
+                    "NestedLoopJoin: {}",
+                    match (outer, full) {
+                        (_, true) => "full",
+                        (true, false) => "outer",
+                        (false, false) => "inner",
+                    }
+                );
                 if let Some(expr) = predicate {
                     s += &format!(" on {}", expr);
                 }
@@ -359,14 +899,26 @@ impl Node {
                 s += &format!("Offset: {}\n", offset);
                 s += &source.format(indent, false, true);
             }
+            Self::Ordinality { source } => {
+                s += "Ordinality\n";
+                s += &source.format(indent, false, true);
+            }
+            Self::Parallel { source, workers } => {
+                s += &format!("Parallel: {} workers\n", workers);
+                s += &source.format(indent, false, true);
+            }
             Self::Order { source, orders } => {
+                s += &format!("Order: {}\n", format_orders(orders));
+                s += &source.format(indent, false, true);
+            }
+            Self::TopK { source, orders, limit } => {
+                s += &format!("TopK: {} by {}\n", limit, format_orders(orders));
+                s += &source.format(indent, false, true);
+            }
+            Self::Distinct { source, on } => {
                 s += &format!(
-                    "Order: {}\n",
-                    orders
-                        .iter()
-                        .map(|(expr, dir)| format!("{} {}", expr, dir))
-                        .collect::<Vec<_>>()
-                        .join(", ")
+                    "Distinct: {}\n",
+                    on.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ")
                 );
                 s += &source.format(indent, false, true);
             }
@@ -381,16 +933,88 @@ impl Node {
                 );
                 s += &source.format(indent, false, true);
             }
-            Self::Scan { table, alias, filter } => {
+            Self::Scan { table, alias, filter, columns } => {
                 s += &format!("Scan: {}", table);
                 if let Some(alias) = alias {
                     s += &format!(" as {}", alias);
                 }
+                if let Some(columns) = columns {
+                    s += &format!(" columns {}", columns.join(", "));
+                }
+                if let Some(expr) = filter {
+                    s += &format!(" ({})", expr);
+                }
+                s += "\n";
+            }
+            Self::SampleScan { table, alias, sample, filter } => {
+                s += &format!("SampleScan: {}", table);
+                if let Some(alias) = alias {
+                    s += &format!(" as {}", alias);
+                }
+                s += &format!(" {}", sample);
                 if let Some(expr) = filter {
                     s += &format!(" ({})", expr);
                 }
                 s += "\n";
             }
+            Self::SemiJoin { left, left_keys, right, anti, null_aware } => {
+                s += &format!("SemiJoin: {}", if *anti { "anti" } else { "semi" });
+                if *null_aware {
+                    s += " null-aware";
+                }
+                if !left_keys.is_empty() {
+                    s += &format!(
+                        " on {}",
+                        left_keys.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ")
+                    );
+                }
+                s += "\n";
+                s += &left.format(indent.clone(), false, false);
+                s += &right.format(indent, false, true);
+            }
+            Self::ScalarJoin { left, left_keys, right, default } => {
+                s += "ScalarJoin";
+                if !left_keys.is_empty() {
+                    s += &format!(
+                        " on {}",
+                        left_keys.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ")
+                    );
+                }
+                if *default != Value::Null {
+                    s += &format!(" default {}", default);
+                }
+                s += "\n";
+                s += &left.format(indent.clone(), false, false);
+                s += &right.format(indent, false, true);
+            }
+            Self::TableFunction { source, function, args, alias, column: _ } => {
+                s += &format!(
+                    "TableFunction: {}({})",
+                    function,
+                    args.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ")
+                );
+                if let Some(alias) = alias {
+                    s += &format!(" as {}", alias);
+                }
+                s += "\n";
+                s += &source.format(indent, false, true);
+            }
+            Self::Values { alias, columns: _, rows } => {
+                s += "Values";
+                if let Some(alias) = alias {
+                    s += &format!(" as {}", alias);
+                }
+                s += &format!(
+                    ": {}\n",
+                    rows.iter()
+                        .map(|row| format!(
+                            "({})",
+                            row.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ")
+                        ))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
             Self::Update { source, table, expressions } => {
                 s += &format!(
                     "Update: {} ({})\n",
@@ -421,8 +1045,47 @@ impl Display for Node {
     }
 }
 
+/// Formats sort orders, omitting NULL positions that are the default for the direction.
+fn format_orders(orders: &[(Expression, Direction, Nulls)]) -> String {
+    orders
+        .iter()
+        .map(|(expr, dir, nulls)| match nulls == &dir.default_nulls() {
+            true => format!("{} {}", expr, dir),
+            false => format!("{} {} {}", expr, dir, nulls),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Formats value ranges in interval notation, or as a single value for point ranges.
+fn format_ranges(ranges: &[Range]) -> String {
+    if ranges.is_empty() || ranges.len() >= 10 {
+        return format!("({} ranges)", ranges.len());
+    }
+    let ranges: Vec<_> = ranges
+        .iter()
+        .map(|range| match range {
+            (Bound::Included(start), Bound::Included(end)) if start == end => start.to_string(),
+            (start, end) => format!(
+                "{}, {}",
+                match start {
+                    Bound::Included(v) => format!("[{}", v),
+                    Bound::Excluded(v) => format!("({}", v),
+                    Bound::Unbounded => "(-inf".to_string(),
+                },
+                match end {
+                    Bound::Included(v) => format!("{}]", v),
+                    Bound::Excluded(v) => format!("{})", v),
+                    Bound::Unbounded => "inf)".to_string(),
+                },
+            ),
+        })
+        .collect();
+    ranges.join(", ")
+}
+
 /// An aggregate operation
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Aggregate {
     Average,
     Count,
@@ -449,13 +1112,41 @@ impl Display for Aggregate {
 
 pub type Aggregates = Vec<Aggregate>;
 
+/// A table function, which returns a set of values
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TableFunction {
+    GenerateSeries,
+}
+
+impl Display for TableFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::GenerateSeries => "generate_series",
+            }
+        )
+    }
+}
+
 /// A sort order direction
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Direction {
     Ascending,
     Descending,
 }
 
+impl Direction {
+    /// Returns the default position of NULL values, which sort before all other values.
+    pub fn default_nulls(&self) -> Nulls {
+        match self {
+            Self::Ascending => Nulls::First,
+            Self::Descending => Nulls::Last,
+        }
+    }
+}
+
 impl Display for Direction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -468,3 +1159,23 @@ impl Display for Direction {
         )
     }
 }
+
+/// The sort position of NULL values
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Nulls {
+    First,
+    Last,
+}
+
+impl Display for Nulls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::First => "nulls first",
+                Self::Last => "nulls last",
+            }
+        )
+    }
+}
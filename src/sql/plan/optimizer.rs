@@ -1,12 +1,54 @@
+use super::super::engine::Layout;
 use super::super::schema::Catalog;
-use super::super::types::{Expression, Value};
-use super::Node;
-use crate::error::Result;
+use super::super::types::{DataType, Expression, Range, Value};
+use super::{Direction, Node, Nulls};
+use crate::error::{Error, Result};
 
+use std::cell::Cell;
+use std::collections::HashSet;
 use std::mem::replace;
+use std::ops::Bound;
 
-/// A plan optimizer
+/// The names of all optimizer rules, in the order they're applied.
+pub const RULES: [&str; 9] = [
+    "constant_folding",
+    "filter_pushdown",
+    "index_lookup",
+    "ordered_scan",
+    "join_order",
+    "noop_cleanup",
+    "limit_pushdown",
+    "hash_join",
+    "column_pruning",
+];
+
+/// Estimated selectivities (the fraction of rows matched) of point, bounded, and half-bounded
+/// ranges, used to choose between index range scans and table scans. There are no table
+/// statistics, so these are fixed estimates.
+const SELECTIVITY_POINT: f64 = 0.05;
+const SELECTIVITY_BOUNDED: f64 = 0.25;
+const SELECTIVITY_HALF_BOUNDED: f64 = 1.0 / 3.0;
+
+/// The maximum estimated selectivity to use an index range scan for. Each index entry requires a
+/// separate row read, so a table scan is cheaper when fetching a larger fraction of rows.
+const MAX_INDEX_SELECTIVITY: f64 = 0.3;
+
+/// The estimated selectivity of other filter and join predicates.
+const SELECTIVITY_FILTER: f64 = 1.0 / 3.0;
+
+/// The estimated number of rows in a table, used for join ordering. Without table statistics,
+/// all tables are assumed to have the same size, so join order is determined by predicates.
+const TABLE_ROWS: f64 = 1000.0;
+
+/// The maximum number of joined sources to reorder. All left-deep join orders are searched, which
+/// is exponential in the number of sources, so larger joins are only reordered in parts.
+const MAX_JOIN_ORDER_SOURCES: usize = 8;
+
+/// A plan optimizer rule
 pub trait Optimizer {
+    /// The rule name, which can be used to disable it.
+    fn name(&self) -> &'static str;
+
     fn optimize(&self, node: Node) -> Result<Node>;
 }
 
@@ -15,6 +57,10 @@ pub trait Optimizer {
 pub struct ConstantFolder;
 
 impl Optimizer for ConstantFolder {
+    fn name(&self) -> &'static str {
+        "constant_folding"
+    }
+
     fn optimize(&self, node: Node) -> Result<Node> {
         node.transform(&Ok, &|n| {
             n.transform_expressions(
@@ -35,6 +81,10 @@ impl Optimizer for ConstantFolder {
 pub struct FilterPushdown;
 
 impl Optimizer for FilterPushdown {
+    fn name(&self) -> &'static str {
+        "filter_pushdown"
+    }
+
     fn optimize(&self, node: Node) -> Result<Node> {
         node.transform(
             &|n| match n {
@@ -42,7 +92,7 @@ impl Optimizer for FilterPushdown {
                     // We don't replace the filter node here, since doing so would cause transform()
                     // to skip the source as it won't reapply the transform to the "same" node.
                     // We leave a noop filter node instead, which will be cleaned up by NoopCleaner.
-                    if let Some(remainder) = self.pushdown(predicate, &mut source) {
+                    if let Some(remainder) = Self::pushdown(predicate, &mut source) {
                         Ok(Node::Filter { source, predicate: remainder })
                     } else {
                         Ok(Node::Filter {
@@ -51,15 +101,25 @@ impl Optimizer for FilterPushdown {
                         })
                     }
                 }
+                // Full join predicates can't be pushed into either side.
                 Node::NestedLoopJoin {
                     mut left,
                     left_size,
                     mut right,
                     predicate: Some(predicate),
                     outer,
+                    full: false,
                 } => {
-                    let predicate = self.pushdown_join(predicate, &mut left, &mut right, left_size);
-                    Ok(Node::NestedLoopJoin { left, left_size, right, predicate, outer })
+                    let predicate =
+                        self.pushdown_join(predicate, &mut left, &mut right, left_size, outer);
+                    Ok(Node::NestedLoopJoin {
+                        left,
+                        left_size,
+                        right,
+                        predicate,
+                        outer,
+                        full: false,
+                    })
                 }
                 n => Ok(n),
             },
@@ -70,20 +130,35 @@ impl Optimizer for FilterPushdown {
 
 impl FilterPushdown {
     /// Attempts to push an expression down into a target node, returns any remaining expression.
-    fn pushdown(&self, mut expression: Expression, target: &mut Node) -> Option<Expression> {
+    fn pushdown(mut expression: Expression, target: &mut Node) -> Option<Expression> {
         match target {
-            Node::Scan { ref mut filter, .. } => {
+            Node::Scan { ref mut filter, .. } | Node::SampleScan { ref mut filter, .. } => {
                 if let Some(filter) = filter.take() {
                     expression = Expression::And(Box::new(expression), Box::new(filter))
                 }
                 filter.replace(expression)
             }
-            Node::NestedLoopJoin { ref mut predicate, .. } => {
+            Node::NestedLoopJoin { ref mut predicate, outer: false, .. } => {
                 if let Some(predicate) = predicate.take() {
                     expression = Expression::And(Box::new(expression), Box::new(predicate));
                 }
                 predicate.replace(expression)
             }
+            // Filters can't be moved into outer join predicates, since that would null-extend
+            // rows instead of removing them, but filters on the left side of a left outer join
+            // can be pushed into the left side.
+            Node::NestedLoopJoin { ref mut left, left_size, outer: true, full: false, .. } => {
+                let (push, mut rest): (Vec<Expression>, Vec<Expression>) =
+                    expression.into_cnf_vec().into_iter().partition(|e| {
+                        !e.contains(&|e| matches!(e, Expression::Field(i, _) if i >= left_size))
+                    });
+                if let Some(push) = Expression::from_cnf_vec(push) {
+                    if let Some(remainder) = Self::pushdown(push, left) {
+                        rest.push(remainder)
+                    }
+                }
+                Expression::from_cnf_vec(rest)
+            }
             Node::Filter { ref mut predicate, .. } => {
                 let p = replace(predicate, Expression::Constant(Value::Null));
                 *predicate = Expression::And(Box::new(p), Box::new(expression));
@@ -94,13 +169,15 @@ impl FilterPushdown {
     }
 
     /// Attempts to partition a join predicate and push parts of it down into either source,
-    /// returning any remaining expression.
+    /// returning any remaining expression. Nothing is pushed into the left side of outer joins,
+    /// since unmatched left rows must be returned anyway.
     fn pushdown_join(
         &self,
         predicate: Expression,
         left: &mut Node,
         right: &mut Node,
         boundary: usize,
+        outer: bool,
     ) -> Option<Expression> {
         // Convert the predicate into conjunctive normal form, and partition into expressions
         // only referencing the left or right sources, leaving cross-source expressions.
@@ -108,7 +185,7 @@ impl FilterPushdown {
         let (mut push_left, cnf): (Vec<Expression>, Vec<Expression>) =
             cnf.into_iter().partition(|e| {
                 // Partition only if no expressions reference the right-hand source.
-                !e.contains(&|e| matches!(e, Expression::Field(i, _) if i >= &boundary))
+                !outer && !e.contains(&|e| matches!(e, Expression::Field(i, _) if i >= &boundary))
             });
         let (mut push_right, mut cnf): (Vec<Expression>, Vec<Expression>) =
             cnf.into_iter().partition(|e| {
@@ -125,7 +202,9 @@ impl FilterPushdown {
                     let (l, ln, r, rn) = if l > r { (r, rn, l, ln) } else { (l, ln, r, rn) };
                     if let Some(lvals) = push_left.iter().find_map(|e| e.as_lookup(*l)) {
                         push_right.push(Expression::from_lookup(*r, rn.clone(), lvals));
-                    } else if let Some(rvals) = push_right.iter().find_map(|e| e.as_lookup(*r)) {
+                    } else if let Some(rvals) =
+                        push_right.iter().find_map(|e| e.as_lookup(*r)).filter(|_| !outer)
+                    {
                         push_left.push(Expression::from_lookup(*l, ln.clone(), rvals));
                     }
                 }
@@ -134,7 +213,7 @@ impl FilterPushdown {
 
         // Push predicates down into the sources.
         if let Some(push_left) = Expression::from_cnf_vec(push_left) {
-            if let Some(remainder) = self.pushdown(push_left, left) {
+            if let Some(remainder) = Self::pushdown(push_left, left) {
                 cnf.push(remainder)
             }
         }
@@ -149,7 +228,7 @@ impl FilterPushdown {
                     &Ok,
                 )
                 .unwrap();
-            if let Some(remainder) = self.pushdown(push_right, right) {
+            if let Some(remainder) = Self::pushdown(push_right, right) {
                 cnf.push(remainder)
             }
         }
@@ -159,12 +238,82 @@ impl FilterPushdown {
 
 /// An index lookup optimizer, which converts table scans to index lookups.
 pub struct IndexLookup<'a, C: Catalog> {
-    catalog: &'a mut C,
+    catalog: &'a C,
+    /// Tables (or aliases) to keep scanning, from NO_INDEX hints.
+    exclude: HashSet<String>,
 }
 
 impl<'a, C: Catalog> IndexLookup<'a, C> {
-    pub fn new(catalog: &'a mut C) -> Self {
-        Self { catalog }
+    pub fn new(catalog: &'a C, exclude: HashSet<String>) -> Self {
+        Self { catalog, exclude }
+    }
+
+    // Combines all range conditions on a field in a CNF vector into a set of ranges, if any, and
+    // returns them along with the remaining conditions.
+    fn ranges(
+        &self,
+        cnf: &[Expression],
+        field: usize,
+        datatype: &DataType,
+    ) -> Option<(Vec<Range>, Vec<Expression>)> {
+        let (matched, rest): (Vec<_>, Vec<_>) =
+            cnf.iter().cloned().partition(|e| e.as_ranges(field, datatype).is_some());
+        let ranges = Expression::from_cnf_vec(matched)?.as_ranges(field, datatype)?;
+        Some((ranges, rest))
+    }
+
+    // Converts index lookups and range scans into index-only scans, if the index covers all fields
+    // used by the given expressions and any filters between them and the index node.
+    fn index_only(&self, node: Node, used: &[&Expression]) -> Result<Node> {
+        Ok(match node {
+            Node::Filter { source, predicate } => {
+                let used = [used, &[&predicate]].concat();
+                Node::Filter { source: Box::new(self.index_only(*source, &used)?), predicate }
+            }
+            Node::IndexLookup { table, alias, column, values } => {
+                if self.covers(&table, &column, used)? {
+                    let ranges = values
+                        .into_iter()
+                        .map(|v| (Bound::Included(v.clone()), Bound::Included(v)))
+                        .collect();
+                    Node::IndexOnlyScan {
+                        table,
+                        alias,
+                        column,
+                        ranges,
+                        reverse: false,
+                        limit: None,
+                    }
+                } else {
+                    Node::IndexLookup { table, alias, column, values }
+                }
+            }
+            Node::IndexRangeScan { table, alias, column, ranges, reverse, limit } => {
+                if self.covers(&table, &column, used)? {
+                    Node::IndexOnlyScan { table, alias, column, ranges, reverse, limit }
+                } else {
+                    Node::IndexRangeScan { table, alias, column, ranges, reverse, limit }
+                }
+            }
+            n => n,
+        })
+    }
+
+    // Checks if a column index includes all fields used by the given expressions.
+    fn covers(&self, table: &str, column: &str, used: &[&Expression]) -> Result<bool> {
+        let table = self.catalog.must_read_table(table)?;
+        let index = table.get_column(column)?;
+        if index.include.is_empty() {
+            return Ok(false);
+        }
+        let mut covered = vec![table.get_column_index(column)?];
+        covered.push(table.get_column_index(&table.get_primary_key()?.name)?);
+        for name in &index.include {
+            covered.push(table.get_column_index(name)?);
+        }
+        Ok(!used
+            .iter()
+            .any(|e| e.contains(&|e| matches!(e, Expression::Field(i, _) if !covered.contains(i)))))
     }
 
     // Wraps a node in a filter for the given CNF vector, if any, otherwise returns the bare node.
@@ -178,9 +327,16 @@ impl<'a, C: Catalog> IndexLookup<'a, C> {
 }
 
 impl<'a, C: Catalog> Optimizer for IndexLookup<'a, C> {
+    fn name(&self) -> &'static str {
+        "index_lookup"
+    }
+
     fn optimize(&self, node: Node) -> Result<Node> {
-        node.transform(&Ok, &|n| match n {
-            Node::Scan { table, alias, filter: Some(filter) } => {
+        let node = node.transform(&Ok, &|n| match n {
+            Node::Scan { table, alias, filter: Some(filter), columns: None }
+                if !self.exclude.contains(&table)
+                    && !alias.as_ref().is_some_and(|a| self.exclude.contains(a)) =>
+            {
                 let columns = self.catalog.must_read_table(&table)?.columns;
                 let pk = columns.iter().position(|c| c.primary_key).unwrap();
 
@@ -208,11 +364,708 @@ impl<'a, C: Catalog> Optimizer for IndexLookup<'a, C> {
                         }
                     }
                 }
-                Ok(Node::Scan { table, alias, filter: Some(filter) })
+
+                // Otherwise, combine range conditions on the primary key into a key range scan,
+                // which is never more expensive than a table scan, or on an indexed column into an
+                // index range scan if it's estimated to be sufficiently selective.
+                if let Some((ranges, cnf)) = self.ranges(&cnf, pk, &columns[pk].datatype) {
+                    return Ok(self.wrap_cnf(
+                        Node::KeyRangeScan { table, alias, ranges, reverse: false },
+                        cnf,
+                    ));
+                }
+                let mut best: Option<(f64, String, Vec<Range>, Vec<Expression>)> = None;
+                for (ci, column) in columns.iter().enumerate().filter(|(_, c)| c.index) {
+                    if let Some((ranges, cnf)) = self.ranges(&cnf, ci, &column.datatype) {
+                        let selectivity = selectivity(&ranges);
+                        if selectivity <= MAX_INDEX_SELECTIVITY
+                            && !matches!(best, Some((s, ..)) if s <= selectivity)
+                        {
+                            best = Some((selectivity, column.name.clone(), ranges, cnf));
+                        }
+                    }
+                }
+                if let Some((_, column, ranges, cnf)) = best {
+                    return Ok(self.wrap_cnf(
+                        Node::IndexRangeScan {
+                            table,
+                            alias,
+                            column,
+                            ranges,
+                            reverse: false,
+                            limit: None,
+                        },
+                        cnf,
+                    ));
+                }
+                Ok(Node::Scan { table, alias, filter: Some(filter), columns: None })
             }
             n => Ok(n),
+        })?;
+
+        // Use index-only scans where possible. Nodes above a projection only use its output
+        // columns, so only index nodes directly below a projection (or its filters) are
+        // considered, with the projection and filter expressions being the only fields used.
+        node.transform(
+            &|n| match n {
+                Node::Projection { source, expressions } => {
+                    let used: Vec<_> = expressions.iter().map(|(e, _)| e).collect();
+                    let source = Box::new(self.index_only(*source, &used)?);
+                    Ok(Node::Projection { source, expressions })
+                }
+                n => Ok(n),
+            },
+            &Ok,
+        )
+    }
+}
+
+/// An ordered scan optimizer, which removes sorts of rows that are already returned in order by a
+/// primary key or index range scan, reversing the scan for descending orders. Forward scans return
+/// NULL index values first. Table scans sorted in descending primary key order become reverse
+/// primary key range scans. Other table scans become scans of an index created with CREATE INDEX
+/// if the sort keys match the leading index columns, all in the index order or all reversed.
+pub struct OrderedScan<'a, C: Catalog> {
+    catalog: &'a C,
+}
+
+impl<'a, C: Catalog + 'a> OrderedScan<'a, C> {
+    pub fn new(catalog: &'a C) -> Self {
+        Self { catalog }
+    }
+
+    // Removes an order node if its source can be scanned in order.
+    fn order(&self, node: Node) -> Result<Node> {
+        let Node::Order { source, orders } = node else { return Ok(node) };
+        let Some(reverse) = (match orders.as_slice() {
+            [(Expression::Field(field, _), direction, nulls), rest @ ..] => {
+                self.reverse(&source, *field, direction, nulls, rest.is_empty())?
+            }
+            _ => None,
+        }) else {
+            return self.index_order(*source, orders);
+        };
+        Ok(Self::scan(*source, reverse))
+    }
+
+    // Replaces a sorted table scan with an index order scan, if the table has a matching index.
+    // Index entries have NULLs first for ascending columns and last for descending columns, so
+    // sort keys must use the default NULL position.
+    fn index_order(
+        &self,
+        source: Node,
+        orders: Vec<(Expression, Direction, Nulls)>,
+    ) -> Result<Node> {
+        let keys = orders
+            .iter()
+            .map(|(expr, direction, nulls)| match expr {
+                Expression::Field(field, _) if *nulls == direction.default_nulls() => {
+                    Some((*field, *direction))
+                }
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>();
+        let Some(keys) = keys else { return Ok(Node::Order { source: Box::new(source), orders }) };
+        let fields: Vec<_> = keys.iter().map(|(field, _)| *field).collect();
+        let Some((table, columns)) = self.scanned_columns(&source, &fields)? else {
+            return Ok(Node::Order { source: Box::new(source), orders });
+        };
+        for index in self.catalog.scan_indexes(&table)? {
+            if index.write_only
+                || index.columns.len() < keys.len()
+                || columns.iter().zip(&index.columns).any(|(column, (c, _))| column != c)
+            {
+                continue;
+            }
+            let same = keys.iter().zip(&index.columns).map(|((_, d), (_, i))| d == i);
+            let same = same.collect::<Vec<_>>();
+            if same.iter().all(|s| *s) || same.iter().all(|s| !s) {
+                return Ok(Self::index_scan(source, index.name, !same[0]));
+            }
+        }
+        Ok(Node::Order { source: Box::new(source), orders })
+    }
+
+    // Returns the table and column names of the given fields, if they're columns of a table scan
+    // below any filters and projections.
+    fn scanned_columns(
+        &self,
+        node: &Node,
+        fields: &[usize],
+    ) -> Result<Option<(String, Vec<String>)>> {
+        Ok(match node {
+            Node::Filter { source, .. } => self.scanned_columns(source, fields)?,
+            Node::Projection { source, expressions } => {
+                let fields = fields
+                    .iter()
+                    .map(|field| match expressions.get(*field) {
+                        Some((Expression::Field(i, _), _)) => Some(*i),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>();
+                match fields {
+                    Some(fields) => self.scanned_columns(source, &fields)?,
+                    None => None,
+                }
+            }
+            Node::Scan { table, .. } => {
+                let schema = self.catalog.must_read_table(table)?;
+                fields
+                    .iter()
+                    .map(|field| schema.columns.get(*field).map(|c| c.name.clone()))
+                    .collect::<Option<Vec<_>>>()
+                    .map(|columns| (table.clone(), columns))
+            }
+            _ => None,
+        })
+    }
+
+    // Replaces the table scan below any filters and projections with an index order scan.
+    fn index_scan(node: Node, index: String, reverse: bool) -> Node {
+        match node {
+            Node::Filter { source, predicate } => Node::Filter {
+                source: Box::new(Self::index_scan(*source, index, reverse)),
+                predicate,
+            },
+            Node::Projection { source, expressions } => Node::Projection {
+                source: Box::new(Self::index_scan(*source, index, reverse)),
+                expressions,
+            },
+            Node::Scan { table, alias, filter, .. } => {
+                let scan = Node::IndexOrderScan { table, alias, index, reverse };
+                match filter {
+                    Some(predicate) => Node::Filter { source: Box::new(scan), predicate },
+                    None => scan,
+                }
+            }
+            node => node,
+        }
+    }
+
+    // Checks whether a node's rows can be returned in the given order of a field by an ordered
+    // scan, and returns whether the scan must be reversed. Scans of non-unique index values only
+    // satisfy a single sort key, since rows with the same value aren't otherwise ordered.
+    fn reverse(
+        &self,
+        node: &Node,
+        field: usize,
+        direction: &Direction,
+        nulls: &Nulls,
+        single: bool,
+    ) -> Result<Option<bool>> {
+        let reverse = *direction == Direction::Descending;
+        Ok(match node {
+            Node::Filter { source, .. } => self.reverse(source, field, direction, nulls, single)?,
+            Node::Projection { source, expressions } => match expressions.get(field) {
+                Some((Expression::Field(i, _), _)) => {
+                    self.reverse(source, *i, direction, nulls, single)?
+                }
+                _ => None,
+            },
+            Node::Scan { table, .. } | Node::KeyRangeScan { table, .. } => {
+                let table = self.catalog.must_read_table(table)?;
+                table.columns.get(field).filter(|c| c.primary_key).map(|_| reverse)
+            }
+            Node::IndexRangeScan { table, column, ranges, .. }
+            | Node::IndexOnlyScan { table, column, ranges, .. } => {
+                let table = self.catalog.must_read_table(table)?;
+                let includes_null =
+                    matches!(ranges.first(), Some((Bound::Included(Value::Null), _)));
+                let nulls_ordered = !includes_null || (*nulls == Nulls::First) != reverse;
+                table
+                    .columns
+                    .get(field)
+                    .filter(|c| &c.name == column && single && nulls_ordered)
+                    .filter(|_| Self::sorted(ranges))
+                    .map(|_| reverse)
+            }
+            _ => None,
+        })
+    }
+
+    // Sets the scan direction of an ordered node, converting table scans to key range scans if
+    // they must be reversed.
+    fn scan(node: Node, reverse: bool) -> Node {
+        match node {
+            Node::Filter { source, predicate } => {
+                Node::Filter { source: Box::new(Self::scan(*source, reverse)), predicate }
+            }
+            Node::Projection { source, expressions } => {
+                Node::Projection { source: Box::new(Self::scan(*source, reverse)), expressions }
+            }
+            Node::Scan { table, alias, filter, .. } if reverse => {
+                let ranges = vec![(Bound::Unbounded, Bound::Unbounded)];
+                let scan = Node::KeyRangeScan { table, alias, ranges, reverse };
+                match filter {
+                    Some(predicate) => Node::Filter { source: Box::new(scan), predicate },
+                    None => scan,
+                }
+            }
+            Node::KeyRangeScan { table, alias, ranges, .. } => {
+                Node::KeyRangeScan { table, alias, ranges, reverse }
+            }
+            Node::IndexRangeScan { table, alias, column, ranges, limit, .. } => {
+                Node::IndexRangeScan { table, alias, column, ranges, reverse, limit }
+            }
+            Node::IndexOnlyScan { table, alias, column, ranges, limit, .. } => {
+                Node::IndexOnlyScan { table, alias, column, ranges, reverse, limit }
+            }
+            node => node,
+        }
+    }
+
+    // Checks that ranges are in ascending order, e.g. not lookup values in the order given.
+    fn sorted(ranges: &[Range]) -> bool {
+        ranges.windows(2).all(|w| match (&w[0].1, &w[1].0) {
+            (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b)) => {
+                a <= b
+            }
+            _ => false,
+        })
+    }
+}
+
+impl<'a, C: Catalog> Optimizer for OrderedScan<'a, C> {
+    fn name(&self) -> &'static str {
+        "ordered_scan"
+    }
+
+    fn optimize(&self, node: Node) -> Result<Node> {
+        node.transform(&Ok, &|n| self.order(n))
+    }
+}
+
+/// A limit pushdown optimizer, which pushes limits below projections and offsets, into index
+/// scans, and combines them with sorts into top-K sorts that only keep the limited rows.
+pub struct LimitPushdown;
+
+impl Optimizer for LimitPushdown {
+    fn name(&self) -> &'static str {
+        "limit_pushdown"
+    }
+
+    fn optimize(&self, node: Node) -> Result<Node> {
+        node.transform(&|n| Ok(Self::push(n)), &Ok)
+    }
+}
+
+impl LimitPushdown {
+    // Pushes a limit node down as far as possible.
+    fn push(node: Node) -> Node {
+        let Node::Limit { source, limit } = node else { return node };
+        match *source {
+            Node::Limit { source, limit: inner } => {
+                Self::push(Node::Limit { source, limit: limit.min(inner) })
+            }
+            Node::Projection { source, expressions } => Node::Projection {
+                source: Box::new(Self::push(Node::Limit { source, limit })),
+                expressions,
+            },
+            // Offsets are applied after the limit, so the offset rows must also be fetched.
+            Node::Offset { source, offset } => Node::Offset {
+                source: Box::new(Self::push(Node::Limit {
+                    source,
+                    limit: limit.saturating_add(offset),
+                })),
+                offset,
+            },
+            Node::Order { source, orders } => Node::TopK { source, orders, limit },
+            Node::TopK { source, orders, limit: inner } => {
+                Node::TopK { source, orders, limit: limit.min(inner) }
+            }
+            Node::IndexRangeScan { table, alias, column, ranges, reverse, limit: inner } => {
+                let limit = Some(inner.map_or(limit, |inner| inner.min(limit)));
+                Node::IndexRangeScan { table, alias, column, ranges, reverse, limit }
+            }
+            Node::IndexOnlyScan { table, alias, column, ranges, reverse, limit: inner } => {
+                let limit = Some(inner.map_or(limit, |inner| inner.min(limit)));
+                Node::IndexOnlyScan { table, alias, column, ranges, reverse, limit }
+            }
+            source => Node::Limit { source: Box::new(source), limit },
+        }
+    }
+}
+
+/// Estimates the selectivity of a set of ranges. Infinite FLOAT bounds are considered unbounded.
+fn selectivity(ranges: &[Range]) -> f64 {
+    let bounded = |bound: &Bound<Value>| match bound {
+        Bound::Included(Value::Float(f)) | Bound::Excluded(Value::Float(f)) => f.is_finite(),
+        Bound::Included(_) | Bound::Excluded(_) => true,
+        Bound::Unbounded => false,
+    };
+    let selectivity: f64 = ranges
+        .iter()
+        .map(|range| match range {
+            (Bound::Included(start), Bound::Included(end)) if start == end => SELECTIVITY_POINT,
+            (start, end) if bounded(start) && bounded(end) => SELECTIVITY_BOUNDED,
+            (start, end) if bounded(start) || bounded(end) => SELECTIVITY_HALF_BOUNDED,
+            _ => 1.0,
+        })
+        .sum();
+    selectivity.min(1.0)
+}
+
+/// A column label, as used in field expressions.
+type Label = Option<(Option<String>, String)>;
+
+/// A join order optimizer, which reorders trees of inner joins to minimize the estimated total
+/// size of intermediate results, by searching all left-deep join orders. Row counts are estimated
+/// from the join sources' lookups and filters. A projection restores the original column order.
+pub struct JoinOrder<'a, C: Catalog> {
+    catalog: &'a C,
+}
+
+/// A join source, with its column labels, estimated row count, and offset in the original join.
+struct JoinSource {
+    node: Node,
+    labels: Vec<Label>,
+    rows: f64,
+    offset: usize,
+}
+
+impl<'a, C: Catalog + 'a> JoinOrder<'a, C> {
+    pub fn new(catalog: &'a C) -> Self {
+        Self { catalog }
+    }
+
+    // Reorders all inner join trees in the node.
+    fn reorder(&self, node: Node) -> Result<Node> {
+        match node {
+            n @ Node::NestedLoopJoin { outer: false, .. } => self.reorder_joins(n),
+            n => n.map_children(&|n| self.reorder(n)),
+        }
+    }
+
+    // Reorders an inner join tree.
+    fn reorder_joins(&self, node: Node) -> Result<Node> {
+        let count = Self::count_sources(&node);
+        if !(3..=MAX_JOIN_ORDER_SOURCES).contains(&count) || self.labels(&node)?.is_none() {
+            return node.map_children(&|n| self.reorder(n));
+        }
+        let mut sources = Vec::new();
+        let mut predicates = Vec::new();
+        self.flatten(node, 0, &mut sources, &mut predicates)?;
+
+        // Find the sources referenced by each predicate, as a bitmask.
+        let width = sources.iter().map(|s| s.labels.len()).sum();
+        let mut source_of = vec![0; width];
+        for (i, source) in sources.iter().enumerate() {
+            source_of[source.offset..source.offset + source.labels.len()].fill(i);
+        }
+        let predicates: Vec<(u32, Expression)> = predicates
+            .into_iter()
+            .map(|p| {
+                let mask = Cell::new(0);
+                p.walk(&|e| {
+                    if let Expression::Field(i, _) = e {
+                        mask.set(mask.get() | 1 << source_of[*i]);
+                    }
+                    true
+                });
+                (mask.get(), p)
+            })
+            .collect();
+
+        let order = Self::search(&sources, &predicates, &source_of);
+        Ok(Self::build(sources, predicates, &order, width))
+    }
+
+    // Counts the sources of an inner join tree.
+    fn count_sources(node: &Node) -> usize {
+        match node {
+            Node::NestedLoopJoin { left, right, outer: false, .. } => {
+                Self::count_sources(left) + Self::count_sources(right)
+            }
+            _ => 1,
+        }
+    }
+
+    // Flattens an inner join tree into its (reordered) sources and the CNF conjuncts of its join
+    // predicates, with field references relative to the entire join.
+    fn flatten(
+        &self,
+        node: Node,
+        offset: usize,
+        sources: &mut Vec<JoinSource>,
+        predicates: &mut Vec<Expression>,
+    ) -> Result<()> {
+        match node {
+            Node::NestedLoopJoin { left, left_size, right, predicate, outer: false, .. } => {
+                if let Some(predicate) = predicate {
+                    let predicate = predicate.transform(
+                        &|e| match e {
+                            Expression::Field(i, label) => Ok(Expression::Field(i + offset, label)),
+                            e => Ok(e),
+                        },
+                        &Ok,
+                    )?;
+                    predicates.extend(predicate.into_cnf_vec());
+                }
+                self.flatten(*left, offset, sources, predicates)?;
+                self.flatten(*right, offset + left_size, sources, predicates)
+            }
+            node => {
+                let node = self.reorder(node)?;
+                let labels = self
+                    .labels(&node)?
+                    .ok_or_else(|| Error::Internal(format!("Unknown columns for {}", node)))?;
+                let rows = Self::rows(&node);
+                sources.push(JoinSource { node, labels, rows, offset });
+                Ok(())
+            }
+        }
+    }
+
+    // Returns the column labels of a node, if known.
+    fn labels(&self, node: &Node) -> Result<Option<Vec<Label>>> {
+        Ok(match node {
+            Node::Scan { table, alias, .. }
+            | Node::SampleScan { table, alias, .. }
+            | Node::IndexOrderScan { table, alias, .. }
+            | Node::KeyLookup { table, alias, .. }
+            | Node::KeyRangeScan { table, alias, .. }
+            | Node::IndexLookup { table, alias, .. }
+            | Node::IndexRangeScan { table, alias, .. }
+            | Node::IndexOnlyScan { table, alias, .. } => {
+                let name = alias.as_ref().unwrap_or(table);
+                let columns = self.catalog.must_read_table(table)?.columns;
+                Some(columns.into_iter().map(|c| Some((Some(name.clone()), c.name))).collect())
+            }
+            Node::Distinct { source, .. }
+            | Node::Filter { source, .. }
+            | Node::Limit { source, .. }
+            | Node::Offset { source, .. }
+            | Node::Order { source, .. }
+            | Node::TopK { source, .. }
+            | Node::Parallel { source, .. }
+            | Node::SemiJoin { left: source, .. } => self.labels(source)?,
+            Node::ScalarJoin { left, .. } => self.labels(left)?.map(|mut labels| {
+                labels.push(None);
+                labels
+            }),
+            Node::Ordinality { source } => self.labels(source)?.map(|mut labels| {
+                labels.push(Some((None, "ordinality".into())));
+                labels
+            }),
+            Node::HashJoin { left, right, .. } | Node::NestedLoopJoin { left, right, .. } => {
+                match (self.labels(left)?, self.labels(right)?) {
+                    (Some(left), Some(right)) => Some([left, right].concat()),
+                    _ => None,
+                }
+            }
+            Node::Projection { expressions, .. } => Some(
+                expressions
+                    .iter()
+                    .map(|(e, alias)| match (e, alias) {
+                        (_, Some(alias)) => Some((None, alias.clone())),
+                        (Expression::Field(_, label), None) => label.clone(),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            Node::Nothing => Some(Vec::new()),
+            Node::TableFunction { source, function, alias, column, .. } => {
+                self.labels(source)?.map(|mut labels| {
+                    let label = alias.clone().unwrap_or_else(|| function.to_string());
+                    labels.push(Some((Some(label), column.clone())));
+                    labels
+                })
+            }
+            Node::Values { alias, columns, .. } => {
+                Some(columns.iter().map(|c| Some((alias.clone(), c.clone()))).collect())
+            }
+            _ => None,
         })
     }
+
+    // Estimates the number of rows returned by a node.
+    fn rows(node: &Node) -> f64 {
+        match node {
+            Node::Scan { filter: None, .. } | Node::IndexOrderScan { .. } => TABLE_ROWS,
+            Node::Scan { filter: Some(_), .. } => TABLE_ROWS * SELECTIVITY_FILTER,
+            Node::SampleScan { sample, filter, .. } => {
+                let rows = TABLE_ROWS * sample.percent / 100.0;
+                if filter.is_some() {
+                    rows * SELECTIVITY_FILTER
+                } else {
+                    rows
+                }
+            }
+            Node::KeyLookup { keys, .. } => keys.len() as f64,
+            Node::IndexLookup { values, .. } => {
+                TABLE_ROWS * (SELECTIVITY_POINT * values.len() as f64).min(1.0)
+            }
+            Node::KeyRangeScan { ranges, .. }
+            | Node::IndexRangeScan { ranges, .. }
+            | Node::IndexOnlyScan { ranges, .. } => TABLE_ROWS * selectivity(ranges),
+            Node::Filter { source, .. } => Self::rows(source) * SELECTIVITY_FILTER,
+            Node::Limit { source, limit } | Node::TopK { source, limit, .. } => {
+                Self::rows(source).min(*limit as f64)
+            }
+            Node::Nothing => 1.0,
+            Node::Values { rows, .. } => rows.len() as f64,
+            Node::TableFunction { source, args, .. } => {
+                Self::rows(source)
+                    * match args.as_slice() {
+                        [Expression::Constant(Value::Integer(start)), Expression::Constant(Value::Integer(stop))] => {
+                            (*stop as f64 - *start as f64 + 1.0).max(0.0)
+                        }
+                        _ => TABLE_ROWS,
+                    }
+            }
+            Node::Distinct { source, .. }
+            | Node::Order { source, .. }
+            | Node::Offset { source, .. }
+            | Node::Ordinality { source }
+            | Node::Parallel { source, .. }
+            | Node::Projection { source, .. }
+            | Node::ScalarJoin { left: source, .. }
+            | Node::SemiJoin { left: source, .. } => Self::rows(source),
+            _ => TABLE_ROWS,
+        }
+    }
+
+    // Searches for the left-deep join order with the lowest total size of intermediate results,
+    // using dynamic programming over subsets of sources. Ties prefer the original order.
+    fn search(
+        sources: &[JoinSource],
+        predicates: &[(u32, Expression)],
+        source_of: &[usize],
+    ) -> Vec<usize> {
+        let n = sources.len();
+        let selectivities: Vec<f64> = predicates
+            .iter()
+            .map(|(_, p)| match p {
+                Expression::Equal(lhs, rhs) => match (&**lhs, &**rhs) {
+                    (Expression::Field(l, _), Expression::Field(r, _))
+                        if source_of[*l] != source_of[*r] =>
+                    {
+                        1.0 / sources[source_of[*l]].rows.max(sources[source_of[*r]].rows).max(1.0)
+                    }
+                    _ => SELECTIVITY_FILTER,
+                },
+                _ => SELECTIVITY_FILTER,
+            })
+            .collect();
+        let size = |set: u32| -> f64 {
+            let rows: f64 = (0..n).filter(|i| set & 1 << i != 0).map(|i| sources[i].rows).product();
+            let selectivity: f64 = predicates
+                .iter()
+                .zip(&selectivities)
+                .filter(|((mask, _), _)| mask & !set == 0)
+                .map(|(_, s)| s)
+                .product();
+            rows * selectivity
+        };
+
+        // The best (cost, order) for each subset of sources.
+        let mut best: Vec<Option<(f64, Vec<usize>)>> = vec![None; 1 << n];
+        for i in 0..n {
+            best[1 << i] = Some((0.0, vec![i]));
+        }
+        for set in 1..1u32 << n {
+            if set.count_ones() < 2 {
+                continue;
+            }
+            let size = size(set);
+            for i in (0..n).filter(|i| set & 1 << i != 0) {
+                let Some((cost, order)) = &best[(set & !(1 << i)) as usize] else { continue };
+                let cost = cost + size;
+                let order = [order.as_slice(), &[i]].concat();
+                let better = match &best[set as usize] {
+                    Some((c, o)) => {
+                        cost < c * (1.0 - 1e-9) || (cost <= c * (1.0 + 1e-9) && order < *o)
+                    }
+                    None => true,
+                };
+                if better {
+                    best[set as usize] = Some((cost, order));
+                }
+            }
+        }
+        best.pop().flatten().map(|(_, order)| order).unwrap_or_default()
+    }
+
+    // Builds a left-deep join tree for the given order, applying each predicate at the first join
+    // where all of its sources are available. Adds a projection to restore the original column
+    // order, if changed.
+    fn build(
+        sources: Vec<JoinSource>,
+        mut predicates: Vec<(u32, Expression)>,
+        order: &[usize],
+        width: usize,
+    ) -> Node {
+        let mut sources: Vec<_> = sources.into_iter().map(Some).collect();
+        let mut labels = vec![None; width];
+        let mut positions = vec![0; width];
+        let mut joined = 0;
+        let mut node: Option<Node> = None;
+        let mut left_size = 0;
+        for &i in order {
+            let Some(source) = sources[i].take() else { continue };
+            let size = source.labels.len();
+            for (j, label) in source.labels.into_iter().enumerate() {
+                positions[source.offset + j] = left_size + j;
+                labels[source.offset + j] = label;
+            }
+            joined |= 1 << i;
+            node = Some(match node {
+                None => source.node,
+                Some(left) => {
+                    let (ready, rest) =
+                        predicates.into_iter().partition(|(mask, _)| mask & !joined == 0);
+                    predicates = rest;
+                    let predicate = Expression::from_cnf_vec(
+                        ready.into_iter().map(|(_, p)| Self::remap(p, &positions)).collect(),
+                    );
+                    Node::NestedLoopJoin {
+                        left: Box::new(left),
+                        left_size,
+                        right: Box::new(source.node),
+                        predicate,
+                        outer: false,
+                        full: false,
+                    }
+                }
+            });
+            left_size += size;
+        }
+        let node = node.unwrap_or(Node::Nothing);
+        if order.iter().enumerate().all(|(i, o)| i == *o) {
+            return node;
+        }
+        Node::Projection {
+            source: Box::new(node),
+            expressions: positions
+                .into_iter()
+                .zip(labels)
+                .map(|(p, label)| (Expression::Field(p, label), None))
+                .collect(),
+        }
+    }
+
+    // Remaps field references in an expression to new positions.
+    fn remap(expression: Expression, positions: &[usize]) -> Expression {
+        expression
+            .transform(
+                &|e| match e {
+                    Expression::Field(i, label) => Ok(Expression::Field(positions[i], label)),
+                    e => Ok(e),
+                },
+                &Ok,
+            )
+            .unwrap()
+    }
+}
+
+impl<'a, C: Catalog> Optimizer for JoinOrder<'a, C> {
+    fn name(&self) -> &'static str {
+        "join_order"
+    }
+
+    fn optimize(&self, node: Node) -> Result<Node> {
+        self.reorder(node)
+    }
 }
 
 /// Cleans up noops, e.g. filters with constant true/false predicates.
@@ -221,26 +1074,30 @@ impl<'a, C: Catalog> Optimizer for IndexLookup<'a, C> {
 pub struct NoopCleaner;
 
 impl Optimizer for NoopCleaner {
+    fn name(&self) -> &'static str {
+        "noop_cleanup"
+    }
+
     fn optimize(&self, node: Node) -> Result<Node> {
         use Expression::*;
         node.transform(
-            // While descending the node tree, clean up boolean expressions.
+            // While descending the node tree, clean up boolean expressions. NULL operands can't
+            // be removed, since e.g. x AND NULL is NULL rather than FALSE when x is TRUE, which
+            // matters for projections and under NOT.
             &|n| {
                 n.transform_expressions(&Ok, &|e| match &e {
                     And(lhs, rhs) => match (&**lhs, &**rhs) {
                         (Constant(Value::Boolean(false)), _)
-                        | (Constant(Value::Null), _)
-                        | (_, Constant(Value::Boolean(false)))
-                        | (_, Constant(Value::Null)) => Ok(Constant(Value::Boolean(false))),
+                        | (_, Constant(Value::Boolean(false))) => {
+                            Ok(Constant(Value::Boolean(false)))
+                        }
                         (Constant(Value::Boolean(true)), e)
                         | (e, Constant(Value::Boolean(true))) => Ok(e.clone()),
                         _ => Ok(e),
                     },
                     Or(lhs, rhs) => match (&**lhs, &**rhs) {
                         (Constant(Value::Boolean(false)), e)
-                        | (Constant(Value::Null), e)
-                        | (e, Constant(Value::Boolean(false)))
-                        | (e, Constant(Value::Null)) => Ok(e.clone()),
+                        | (e, Constant(Value::Boolean(false))) => Ok(e.clone()),
                         (Constant(Value::Boolean(true)), _)
                         | (_, Constant(Value::Boolean(true))) => Ok(Constant(Value::Boolean(true))),
                         _ => Ok(e),
@@ -266,24 +1123,31 @@ impl Optimizer for NoopCleaner {
 pub struct JoinType;
 
 impl Optimizer for JoinType {
+    fn name(&self) -> &'static str {
+        "hash_join"
+    }
+
     fn optimize(&self, node: Node) -> Result<Node> {
         node.transform(
             &|n| match n {
-                // Replace nested-loop equijoins with hash joins.
+                // Replace nested-loop equijoins with hash joins, if the fields are on either side.
                 Node::NestedLoopJoin {
                     left,
                     left_size,
                     right,
                     predicate: Some(Expression::Equal(a, b)),
                     outer,
+                    full,
                 } => match (*a, *b) {
-                    (Expression::Field(a, a_label), Expression::Field(b, b_label)) => {
+                    (Expression::Field(a, a_label), Expression::Field(b, b_label))
+                        if (a < left_size) != (b < left_size) =>
+                    {
                         let (left_field, right_field) = if a < left_size {
                             ((a, a_label), (b - left_size, b_label))
                         } else {
                             ((b, b_label), (a - left_size, a_label))
                         };
-                        Ok(Node::HashJoin { left, left_field, right, right_field, outer })
+                        Ok(Node::HashJoin { left, left_field, right, right_field, outer, full })
                     }
                     (a, b) => Ok(Node::NestedLoopJoin {
                         left,
@@ -291,6 +1155,7 @@ impl Optimizer for JoinType {
                         right,
                         predicate: Some(Expression::Equal(a.into(), b.into())),
                         outer,
+                        full,
                     }),
                 },
                 n => Ok(n),
@@ -299,3 +1164,67 @@ impl Optimizer for JoinType {
         )
     }
 }
+
+/// A column pruning optimizer, which limits scans of columnar tables to the columns used by a
+/// projection directly above them and any filters between them, since nodes above a projection
+/// only use its output columns. The primary key is always read.
+pub struct ColumnPruning<'a, C: Catalog> {
+    catalog: &'a C,
+}
+
+impl<'a, C: Catalog> ColumnPruning<'a, C> {
+    pub fn new(catalog: &'a C) -> Self {
+        Self { catalog }
+    }
+
+    // Sets the columns to read for a columnar table scan, given the expressions using its fields.
+    fn prune(&self, node: Node, used: &[&Expression]) -> Result<Node> {
+        Ok(match node {
+            Node::Filter { source, predicate } => {
+                let used = [used, &[&predicate]].concat();
+                Node::Filter { source: Box::new(self.prune(*source, &used)?), predicate }
+            }
+            Node::Scan { table, alias, filter, columns: None } => {
+                let schema = self.catalog.must_read_table(&table)?;
+                let used = [used, &filter.iter().collect::<Vec<_>>()].concat();
+                let columns: Vec<String> = schema
+                    .columns
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, c)| {
+                        c.primary_key
+                            || used.iter().any(|e| {
+                                e.contains(&|e| matches!(e, Expression::Field(f, _) if f == i))
+                            })
+                    })
+                    .map(|(_, c)| c.name.clone())
+                    .collect();
+                let columns = Some(columns).filter(|columns| {
+                    schema.layout == Layout::Columnar && columns.len() < schema.columns.len()
+                });
+                Node::Scan { table, alias, filter, columns }
+            }
+            n => n,
+        })
+    }
+}
+
+impl<'a, C: Catalog> Optimizer for ColumnPruning<'a, C> {
+    fn name(&self) -> &'static str {
+        "column_pruning"
+    }
+
+    fn optimize(&self, node: Node) -> Result<Node> {
+        node.transform(
+            &|n| match n {
+                Node::Projection { source, expressions } => {
+                    let used: Vec<_> = expressions.iter().map(|(e, _)| e).collect();
+                    let source = Box::new(self.prune(*source, &used)?);
+                    Ok(Node::Projection { source, expressions })
+                }
+                n => Ok(n),
+            },
+            &Ok,
+        )
+    }
+}
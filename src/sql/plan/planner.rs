@@ -1,7 +1,9 @@
-use super::super::parser::ast;
-use super::super::schema::{Catalog, Column, Table};
-use super::super::types::{Expression, Value};
-use super::{Aggregate, Direction, Node, Plan};
+use super::super::engine::{Codec, Layout, SETTINGS};
+use super::super::parser::{ast, Parser};
+use super::super::schema::{Catalog, Column, Function, Index, Table, Trigger, TriggerEvent, Ttl};
+use super::super::types::DataType;
+use super::super::types::{Expression, Sample, Value};
+use super::{Aggregate, Direction, Node, Nulls, Plan, TableFunction};
 use crate::error::{Error, Result};
 
 use std::collections::{HashMap, HashSet};
@@ -12,7 +14,7 @@ pub struct Planner<'a, C: Catalog> {
     catalog: &'a mut C,
 }
 
-impl<'a, C: Catalog> Planner<'a, C> {
+impl<'a, C: Catalog + 'a> Planner<'a, C> {
     /// Creates a new planner.
     pub fn new(catalog: &'a mut C) -> Self {
         Self { catalog }
@@ -20,27 +22,60 @@ impl<'a, C: Catalog> Planner<'a, C> {
 
     /// Builds a plan for an AST statement.
     pub fn build(&mut self, statement: ast::Statement) -> Result<Plan> {
-        Ok(Plan(self.build_statement(statement)?))
+        let hints = match &statement {
+            ast::Statement::Select { hints, .. } => hints.clone(),
+            ast::Statement::CreateTableAs { query, .. } => match query.as_ref() {
+                ast::Statement::Select { hints, .. } => hints.clone(),
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
+        Ok(Plan(self.build_statement(statement)?, hints))
     }
 
     /// Builds a plan node for a statement.
     fn build_statement(&self, statement: ast::Statement) -> Result<Node> {
         Ok(match statement {
-            // Transaction control and explain statements should have been handled by session.
-            ast::Statement::Begin { .. } | ast::Statement::Commit | ast::Statement::Rollback => {
+            // Transaction control, explain, and SET statements should have been handled by
+            // session.
+            ast::Statement::Begin { .. }
+            | ast::Statement::Commit
+            | ast::Statement::Rollback
+            | ast::Statement::Prepare(_)
+            | ast::Statement::CommitPrepared(_)
+            | ast::Statement::RollbackPrepared(_)
+            | ast::Statement::ShowPrepared => {
                 return Err(Error::Internal(format!(
                     "Unexpected transaction statement {:?}",
                     statement
                 )))
             }
 
-            ast::Statement::Explain(_) => {
+            ast::Statement::Explain(_) | ast::Statement::ExplainAnalyze(_) => {
                 return Err(Error::Internal("Unexpected explain statement".into()))
             }
 
+            ast::Statement::Set { .. } | ast::Statement::SetClusterSetting { .. } => {
+                return Err(Error::Internal("Unexpected SET statement".into()))
+            }
+
+            ast::Statement::DeclareCursor { .. }
+            | ast::Statement::Fetch { .. }
+            | ast::Statement::CloseCursor(_) => {
+                return Err(Error::Internal("Unexpected cursor statement".into()))
+            }
+
+            ast::Statement::ShowSessions | ast::Statement::Kill { .. } => {
+                return Err(Error::Internal("Unexpected session administration statement".into()))
+            }
+
+            ast::Statement::CreateTenant(_) | ast::Statement::DropTenant(_) => {
+                return Err(Error::Internal("Unexpected tenant statement".into()))
+            }
+
             // DDL statements (schema changes).
-            ast::Statement::CreateTable { name, columns } => Node::CreateTable {
-                schema: Table::new(
+            ast::Statement::CreateTable { name, columns, options } => {
+                let mut schema = Table::new(
                     name,
                     columns
                         .into_iter()
@@ -58,27 +93,122 @@ impl<'a, C: Catalog> Planner<'a, C> {
                                 nullable,
                                 default,
                                 index: c.index && !c.primary_key,
+                                include: c.include,
                                 unique: c.unique || c.primary_key,
                                 references: c.references,
                             })
                         })
                         .collect::<Result<_>>()?,
-                )?,
-            },
+                )?;
+                self.build_table_options(&mut schema, options)?;
+                Node::CreateTable { schema }
+            }
+
+            ast::Statement::CreateTableAs { name, query } => {
+                let source = self.build_statement(*query)?;
+                let datatypes = source.datatypes(&*self.catalog)?;
+                Node::CreateTableAs { table: name, source: Box::new(source), datatypes }
+            }
 
             ast::Statement::DropTable(table) => Node::DropTable { table },
+            ast::Statement::UndropTable(table) => Node::UndropTable { table },
+
+            ast::Statement::CreateTrigger { name, table, timing, event, mut statement, sql } => {
+                if !matches!(
+                    *statement,
+                    ast::Statement::Insert { .. }
+                        | ast::Statement::Update { .. }
+                        | ast::Statement::Delete { .. }
+                        | ast::Statement::Select { .. }
+                ) {
+                    return Err(Error::Value(
+                        "Trigger statement must be INSERT, UPDATE, DELETE, or SELECT".into(),
+                    ));
+                }
+                let trigger = Trigger { name, table, timing, event, statement: sql };
+                // Check the row references by binding them to NULL rows.
+                let schema = self.catalog.must_read_table(&trigger.table)?;
+                let nulls = vec![Value::Null; schema.columns.len()];
+                let old = (event != TriggerEvent::Insert).then_some(nulls.as_slice());
+                let new = (event != TriggerEvent::Delete).then_some(nulls.as_slice());
+                trigger.bind(&mut statement, &schema, old, new)?;
+                Node::CreateTrigger { trigger }
+            }
+
+            ast::Statement::DropTrigger { name, table } => Node::DropTrigger { table, name },
+
+            ast::Statement::CreateIndex { name, table, columns } => {
+                let name = name.unwrap_or_else(|| {
+                    let names: Vec<_> = columns.iter().map(|(c, _)| c.as_str()).collect();
+                    format!("{}_{}_idx", table, names.join("_"))
+                });
+                let columns = columns
+                    .into_iter()
+                    .map(|(column, order)| match order {
+                        ast::Order::Ascending => (column, Direction::Ascending),
+                        ast::Order::Descending => (column, Direction::Descending),
+                    })
+                    .collect();
+                Node::CreateIndex { index: Index { name, table, columns, write_only: false } }
+            }
+
+            ast::Statement::DropIndex { name, table } => Node::DropIndex { table, name },
+
+            ast::Statement::CreateFunction { name, args, returns, body } => {
+                if self.aggregate_from_name(&name).is_some() {
+                    return Err(Error::Value(format!("Can't redefine built-in function {}", name)));
+                }
+                for (i, (arg, _)) in args.iter().enumerate() {
+                    if args[..i].iter().any(|(a, _)| a == arg) {
+                        return Err(Error::Value(format!("Duplicate argument {}", arg)));
+                    }
+                }
+                // Record the functions called by the body, and check it by building it with
+                // NULL arguments.
+                let mut dependencies = Vec::new();
+                let expr = parse_function_body(&body)?.transform(&mut Ok, &mut |e| match e {
+                    ast::Expression::Function(ref f, _) => {
+                        if !dependencies.contains(f) {
+                            dependencies.push(f.clone());
+                        }
+                        Ok(e)
+                    }
+                    ast::Expression::Subquery(_) => {
+                        Err(Error::Value("Function body can't contain subqueries".into()))
+                    }
+                    e => Ok(e),
+                })?;
+                let nulls = args.iter().map(|_| ast::Literal::Null.into()).collect();
+                self.build_expression(&mut Scope::constant(), bind_args(expr, &args, nulls)?)?;
+                Node::CreateFunction {
+                    function: Function { name, args, returns, body, dependencies },
+                }
+            }
+
+            ast::Statement::DropFunction(name) => Node::DropFunction { name },
+
+            ast::Statement::ShowTableStatus => {
+                let (columns, rows) = self.build_information_schema("information_schema.tables")?;
+                Node::Values { alias: None, columns, rows }
+            }
 
             // DML statements (mutations).
-            ast::Statement::Delete { table, r#where } => {
-                let scope = &mut Scope::from_table(self.catalog.must_read_table(&table)?)?;
-                Node::Delete {
+            // ORDER BY and LIMIT restrict the mutation to the first rows in the given order,
+            // allowing large mutations to be split into smaller transactions.
+            ast::Statement::Delete { table, r#where, order, limit } => {
+                let schema = self.catalog.must_read_table(&table)?;
+                let ttl = schema.ttl_filter(&table)?;
+                let scope = &mut Scope::from_table(schema)?;
+                let r#where = r#where.map(|e| self.build_expression(scope, e)).transpose()?;
+                let source = Node::Scan {
                     table: table.clone(),
-                    source: Box::new(Node::Scan {
-                        table,
-                        alias: None,
-                        filter: r#where.map(|e| self.build_expression(scope, e)).transpose()?,
-                    }),
-                }
+                    alias: None,
+                    filter: Expression::from_cnf_vec(r#where.into_iter().chain(ttl).collect()),
+                    columns: None,
+                };
+                let source = self.build_order_clause(scope, source, order)?;
+                let source = self.build_limit_clause(source, limit)?;
+                Node::Delete { table, source: Box::new(source) }
             }
 
             ast::Statement::Insert { table, columns, values } => Node::Insert {
@@ -95,15 +225,22 @@ impl<'a, C: Catalog> Planner<'a, C> {
                     .collect::<Result<_>>()?,
             },
 
-            ast::Statement::Update { table, set, r#where } => {
-                let scope = &mut Scope::from_table(self.catalog.must_read_table(&table)?)?;
-                Node::Update {
+            ast::Statement::Update { table, set, r#where, order, limit } => {
+                let schema = self.catalog.must_read_table(&table)?;
+                let ttl = schema.ttl_filter(&table)?;
+                let scope = &mut Scope::from_table(schema)?;
+                let r#where = r#where.map(|e| self.build_expression(scope, e)).transpose()?;
+                let source = Node::Scan {
                     table: table.clone(),
-                    source: Box::new(Node::Scan {
-                        table,
-                        alias: None,
-                        filter: r#where.map(|e| self.build_expression(scope, e)).transpose()?,
-                    }),
+                    alias: None,
+                    filter: Expression::from_cnf_vec(r#where.into_iter().chain(ttl).collect()),
+                    columns: None,
+                };
+                let source = self.build_order_clause(scope, source, order)?;
+                let source = self.build_limit_clause(source, limit)?;
+                Node::Update {
+                    table,
+                    source: Box::new(source),
                     expressions: set
                         .into_iter()
                         .map(|(c, e)| {
@@ -119,6 +256,8 @@ impl<'a, C: Catalog> Planner<'a, C> {
 
             // Queries.
             ast::Statement::Select {
+                hints: _,
+                mut distinct,
                 mut select,
                 from,
                 r#where,
@@ -127,7 +266,11 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 mut order,
                 offset,
                 limit,
+                ordinality,
             } => {
+                if ordinality && order.is_empty() {
+                    return Err(Error::Value("WITH ORDINALITY requires ORDER BY".into()));
+                }
                 let scope = &mut Scope::new();
 
                 // Build FROM clause.
@@ -141,23 +284,36 @@ impl<'a, C: Catalog> Planner<'a, C> {
 
                 // Build WHERE clause.
                 if let Some(expr) = r#where {
-                    node = Node::Filter {
-                        source: Box::new(node),
-                        predicate: self.build_expression(scope, expr)?,
-                    };
+                    node = self.build_where_clause(scope, node, expr)?;
                 };
 
+                // Expand any wildcards in the SELECT clause into the columns they match.
+                select = self.expand_wildcards(scope, select)?;
+
                 // Build SELECT clause.
                 let mut hidden = 0;
                 if !select.is_empty() {
+                    // Resolve GROUP BY ordinals, e.g. GROUP BY 1, to their SELECT expressions.
+                    let group_by = self.resolve_group_ordinals(&select, group_by)?;
+
                     // Inject hidden SELECT columns for fields and aggregates used in ORDER BY and
                     // HAVING expressions but not present in existing SELECT output. These will be
                     // removed again by a later projection.
                     if let Some(ref mut expr) = having {
-                        hidden += self.inject_hidden(expr, &mut select)?;
+                        hidden += self.inject_hidden(expr, &mut select, &group_by)?;
                     }
-                    for (expr, _) in order.iter_mut() {
-                        hidden += self.inject_hidden(expr, &mut select)?;
+                    for (expr, _, _) in order.iter_mut() {
+                        let order_hidden = self.inject_hidden(expr, &mut select, &group_by)?;
+                        if order_hidden > 0 && distinct.as_ref().is_some_and(|on| on.is_empty()) {
+                            return Err(Error::Value(
+                                "For SELECT DISTINCT, ORDER BY expressions must appear in select list"
+                                    .into(),
+                            ));
+                        }
+                        hidden += order_hidden;
+                    }
+                    for expr in distinct.iter_mut().flatten() {
+                        hidden += self.inject_hidden(expr, &mut select, &group_by)?;
                     }
 
                     // Extract any aggregate functions and GROUP BY expressions, replacing them with
@@ -181,6 +337,11 @@ impl<'a, C: Catalog> Planner<'a, C> {
                         node = self.build_aggregation(scope, node, groups, aggregates)?;
                     }
 
+                    // Build any scalar subqueries, joining their values onto the rows.
+                    for (expr, _) in select.iter_mut() {
+                        node = self.build_scalar_subqueries(scope, node, expr)?;
+                    }
+
                     // Build the remaining non-aggregate projection.
                     let expressions: Vec<(Expression, Option<String>)> = select
                         .into_iter()
@@ -198,23 +359,33 @@ impl<'a, C: Catalog> Planner<'a, C> {
                     };
                 };
 
-                // Build ORDER clause.
-                if !order.is_empty() {
-                    node = Node::Order {
-                        source: Box::new(node),
-                        orders: order
+                // Build DISTINCT clause. Plain DISTINCT deduplicates the SELECT output (excluding
+                // hidden columns) before ordering, while DISTINCT ON keeps the first row of each
+                // group after ordering.
+                let mut distinct_on = Vec::new();
+                match distinct {
+                    Some(on) if on.is_empty() => {
+                        node = Node::Distinct {
+                            source: Box::new(node),
+                            on: (0..(scope.len() - hidden))
+                                .map(|i| self.build_expression(scope, ast::Expression::Column(i)))
+                                .collect::<Result<_>>()?,
+                        };
+                    }
+                    Some(on) => {
+                        distinct_on = on
                             .into_iter()
-                            .map(|(e, o)| {
-                                Ok((
-                                    self.build_expression(scope, e)?,
-                                    match o {
-                                        ast::Order::Ascending => Direction::Ascending,
-                                        ast::Order::Descending => Direction::Descending,
-                                    },
-                                ))
-                            })
-                            .collect::<Result<_>>()?,
-                    };
+                            .map(|e| self.build_expression(scope, e))
+                            .collect::<Result<_>>()?;
+                    }
+                    None => {}
+                }
+
+                // Build ORDER clause.
+                node = self.build_order_clause(scope, node, order)?;
+
+                if !distinct_on.is_empty() {
+                    node = Node::Distinct { source: Box::new(node), on: distinct_on };
                 }
 
                 // Build OFFSET clause.
@@ -229,15 +400,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 }
 
                 // Build LIMIT clause.
-                if let Some(expr) = limit {
-                    node = Node::Limit {
-                        source: Box::new(node),
-                        limit: match self.evaluate_constant(expr)? {
-                            Value::Integer(i) if i >= 0 => Ok(i as u64),
-                            v => Err(Error::Value(format!("Invalid limit {}", v))),
-                        }?,
-                    }
-                }
+                node = self.build_limit_clause(node, limit)?;
 
                 // Remove any hidden columns.
                 if hidden > 0 {
@@ -249,6 +412,11 @@ impl<'a, C: Catalog> Planner<'a, C> {
                     }
                 }
 
+                // Build WITH ORDINALITY clause, numbering the final rows.
+                if ordinality {
+                    node = Node::Ordinality { source: Box::new(node) };
+                }
+
                 node
             }
         })
@@ -265,6 +433,11 @@ impl<'a, C: Catalog> Planner<'a, C> {
             None => return Err(Error::Value("No from items given".into())),
         };
         for item in items {
+            // Table functions can reference earlier items, and are built on top of them.
+            if let ast::FromItem::Function { name, args, alias, columns } = item {
+                node = self.build_table_function(scope, node, name, args, alias, columns)?;
+                continue;
+            }
             let mut right_scope = base_scope.clone();
             let right = self.build_from_item(&mut right_scope, item)?;
             node = Node::NestedLoopJoin {
@@ -273,6 +446,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 right: Box::new(right),
                 predicate: None,
                 outer: false,
+                full: false,
             };
             scope.merge(right_scope)?;
         }
@@ -285,15 +459,96 @@ impl<'a, C: Catalog> Planner<'a, C> {
     /// only be referenced by the query name (so if alias is given, cannot reference by name).
     fn build_from_item(&self, scope: &mut Scope, item: ast::FromItem) -> Result<Node> {
         Ok(match item {
-            ast::FromItem::Table { name, alias } => {
-                scope.add_table(
-                    alias.clone().unwrap_or_else(|| name.clone()),
-                    self.catalog.must_read_table(&name)?,
-                )?;
-                Node::Scan { table: name, alias, filter: None }
+            // Information schema and system views are computed from the catalog during
+            // planning, and are labeled by the view name without the schema name.
+            ast::FromItem::Table { name, alias, sample }
+                if name.starts_with("information_schema.") || name.starts_with("system.") =>
+            {
+                if sample.is_some() {
+                    return Err(Error::Value(format!("Can't sample {}", name)));
+                }
+                let (columns, rows) = self.build_information_schema(&name)?;
+                let label = alias.unwrap_or_else(|| name[name.find('.').unwrap() + 1..].into());
+                scope.add_relation(Some(label.clone()), &columns)?;
+                Node::Values { alias: Some(label), columns, rows }
+            }
+
+            // Expired rows of tables with a TTL are filtered out by the scan.
+            ast::FromItem::Table { name, alias, sample } => {
+                let label = alias.clone().unwrap_or_else(|| name.clone());
+                let table = self.catalog.must_read_table(&name)?;
+                let filter = table.ttl_filter(&label)?;
+                scope.add_table(label, table)?;
+                match sample {
+                    Some(sample) => {
+                        let sample = self.build_sample(sample)?;
+                        Node::SampleScan { table: name, alias, sample, filter }
+                    }
+                    None => Node::Scan { table: name, alias, filter, columns: None },
+                }
+            }
+
+            // VALUES columns are named column1, column2, etc. unless names are given.
+            ast::FromItem::Values { rows, alias, mut columns } => {
+                let size = rows.first().map(|row| row.len()).unwrap_or(0);
+                if rows.iter().any(|row| row.len() != size) {
+                    return Err(Error::Value(
+                        "VALUES rows must all have the same number of columns".into(),
+                    ));
+                }
+                if columns.len() > size {
+                    return Err(Error::Value(format!(
+                        "VALUES has {} columns but {} column names were given",
+                        size,
+                        columns.len()
+                    )));
+                }
+                columns.extend((columns.len() + 1..=size).map(|i| format!("column{}", i)));
+                scope.add_relation(alias.clone(), &columns)?;
+                let rows = rows
+                    .into_iter()
+                    .map(|row| {
+                        row.into_iter()
+                            .map(|e| self.build_expression(&mut Scope::constant(), e))
+                            .collect()
+                    })
+                    .collect::<Result<_>>()?;
+                Node::Values { alias, columns, rows }
+            }
+
+            ast::FromItem::Function { name, args, alias, columns } => {
+                self.build_table_function(scope, Node::Nothing, name, args, alias, columns)?
+            }
+
+            // Table functions can reference the left side of joins, and are built on top of it.
+            ast::FromItem::Join { left, right, r#type, predicate, using, natural }
+                if matches!(*right, ast::FromItem::Function { .. }) =>
+            {
+                if !matches!(r#type, ast::JoinType::Cross | ast::JoinType::Inner) {
+                    return Err(Error::Value(
+                        "Outer joins with table functions are not supported".into(),
+                    ));
+                }
+                if natural || !using.is_empty() {
+                    return Err(Error::Value(
+                        "USING and NATURAL joins with table functions are not supported".into(),
+                    ));
+                }
+                let ast::FromItem::Function { name, args, alias, columns } = *right else {
+                    return Err(Error::Internal("Expected table function".into()));
+                };
+                let left = self.build_from_item(scope, *left)?;
+                let node = self.build_table_function(scope, left, name, args, alias, columns)?;
+                match predicate {
+                    Some(predicate) => Node::Filter {
+                        source: Box::new(node),
+                        predicate: self.build_expression(scope, predicate)?,
+                    },
+                    None => node,
+                }
             }
 
-            ast::FromItem::Join { left, right, r#type, predicate } => {
+            ast::FromItem::Join { left, right, r#type, predicate, using, natural } => {
                 // Right outer joins are built as a left outer join with an additional projection
                 // to swap the resulting columns.
                 let (left, right) = match r#type {
@@ -303,25 +558,661 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 let left = Box::new(self.build_from_item(scope, *left)?);
                 let left_size = scope.len();
                 let right = Box::new(self.build_from_item(scope, *right)?);
-                let predicate = predicate.map(|e| self.build_expression(scope, e)).transpose()?;
-                let outer = match r#type {
-                    ast::JoinType::Cross | ast::JoinType::Inner => false,
-                    ast::JoinType::Left | ast::JoinType::Right => true,
+                let mut predicate =
+                    predicate.map(|e| self.build_expression(scope, e)).transpose()?;
+                let (outer, full) = match r#type {
+                    ast::JoinType::Cross | ast::JoinType::Inner => (false, false),
+                    ast::JoinType::Left | ast::JoinType::Right => (true, false),
+                    ast::JoinType::Full => (true, true),
                 };
-                let mut node = Node::NestedLoopJoin { left, left_size, right, predicate, outer };
-                if matches!(r#type, ast::JoinType::Right) {
-                    let expressions = (left_size..scope.len())
-                        .chain(0..left_size)
-                        .map(|i| Ok((Expression::Field(i, scope.get_label(i)?), None)))
-                        .collect::<Result<Vec<_>>>()?;
-                    scope.project(&expressions)?;
-                    node = Node::Projection { source: Box::new(node), expressions }
+
+                // USING joins (and NATURAL joins on all common columns) join on equality of the
+                // given columns, which are merged into a single column.
+                let using = if natural { scope.common_columns(left_size) } else { using };
+                let mut merged = Vec::new();
+                for name in using {
+                    if merged.iter().any(|(n, _, _)| n == &name) {
+                        return Err(Error::Value(format!("Duplicate join column {}", name)));
+                    }
+                    let l = scope.resolve_range(0..left_size, &name)?;
+                    let r = scope.resolve_range(left_size..scope.len(), &name)?;
+                    merged.push((name, l, r));
+                }
+                if !merged.is_empty() {
+                    predicate = Expression::from_cnf_vec(
+                        merged
+                            .iter()
+                            .map(|(_, l, r)| {
+                                Ok(Expression::Equal(
+                                    Expression::Field(*l, scope.get_label(*l)?).into(),
+                                    Expression::Field(*r, scope.get_label(*r)?).into(),
+                                ))
+                            })
+                            .collect::<Result<_>>()?,
+                    );
+                }
+
+                let mut node =
+                    Node::NestedLoopJoin { left, left_size, right, predicate, outer, full };
+                if matches!(r#type, ast::JoinType::Right) || !merged.is_empty() {
+                    node = self.build_join_projection(
+                        scope,
+                        node,
+                        left_size,
+                        matches!(r#type, ast::JoinType::Right),
+                        full,
+                        merged,
+                    )?;
                 }
                 node
             }
         })
     }
 
+    /// Builds a projection over a join, which swaps the columns of right joins (built as left joins)
+    /// back into place, and puts merged USING columns first. Merged columns take the value of the
+    /// left (i.e. preserved) side, or the first non-NULL value for full joins, and can be
+    /// referenced by either side's table name.
+    fn build_join_projection(
+        &self,
+        scope: &mut Scope,
+        node: Node,
+        left_size: usize,
+        swap: bool,
+        full: bool,
+        merged: Vec<(String, usize, usize)>,
+    ) -> Result<Node> {
+        let (first, second) = match swap {
+            true => (left_size..scope.len(), 0..left_size),
+            false => (0..left_size, left_size..scope.len()),
+        };
+        let mut expressions = Vec::new();
+        let mut qualifiers = Vec::new();
+        for (i, (name, l, r)) in merged.iter().enumerate() {
+            let (lhs, rhs) = (scope.get_label(*l)?, scope.get_label(*r)?);
+            qualifiers.extend(
+                [&lhs, &rhs].into_iter().flatten().filter_map(|(t, _)| t.clone()).map(|t| (t, i)),
+            );
+            let expression = match full {
+                true => Expression::Coalesce(
+                    Expression::Field(*l, lhs).into(),
+                    Expression::Field(*r, rhs).into(),
+                ),
+                false => Expression::Field(*l, lhs),
+            };
+            expressions.push((expression, Some(name.clone())));
+        }
+        for i in first.chain(second) {
+            if !merged.iter().any(|(_, l, r)| i == *l || i == *r) {
+                expressions.push((Expression::Field(i, scope.get_label(i)?), None));
+            }
+        }
+        scope.project(&expressions)?;
+        for (table, i) in qualifiers {
+            scope.add_qualified(table, merged[i].0.clone(), i);
+        }
+        Ok(Node::Projection { source: Box::new(node), expressions })
+    }
+
+    /// Builds the columns and rows of an information schema or system view. The views are:
+    ///
+    /// - information_schema.tables: the row count and the row and index size of each table.
+    /// - information_schema.indexes: the entry count and size of each secondary index.
+    /// - system.settings: the value of each cluster setting as a string, or NULL if unset, and
+    ///   its datatype and description.
+    /// - system.tenants: the number of keys and the size of each tenant's keyspace.
+    fn build_information_schema(&self, name: &str) -> Result<(Vec<String>, Vec<Vec<Expression>>)> {
+        let mut rows = Vec::new();
+        let columns = match name {
+            "information_schema.tables" => {
+                for table in self.catalog.scan_tables()? {
+                    let stats = self.catalog.table_stats(&table.name)?;
+                    rows.push(vec![
+                        Value::String(table.name),
+                        Value::Integer(stats.rows as i64),
+                        Value::Integer(stats.size as i64),
+                        Value::Integer(stats.indexes.iter().map(|i| i.size).sum::<u64>() as i64),
+                    ]);
+                }
+                vec!["table_name", "rows", "size", "index_size"]
+            }
+            "information_schema.indexes" => {
+                for table in self.catalog.scan_tables()? {
+                    for index in self.catalog.table_stats(&table.name)?.indexes {
+                        rows.push(vec![
+                            Value::String(table.name.clone()),
+                            Value::String(index.column),
+                            Value::Integer(index.entries as i64),
+                            Value::Integer(index.size as i64),
+                        ]);
+                    }
+                }
+                vec!["table_name", "column_name", "entries", "size"]
+            }
+            "system.settings" => {
+                let mut values: HashMap<_, _> = self.catalog.scan_settings()?.into_iter().collect();
+                for setting in SETTINGS {
+                    rows.push(vec![
+                        Value::String(setting.name.to_string()),
+                        values
+                            .remove(setting.name)
+                            .map_or(Value::Null, |value| Value::String(value.to_string())),
+                        Value::String(setting.datatype.to_string()),
+                        Value::String(setting.description.to_string()),
+                    ]);
+                }
+                vec!["name", "value", "type", "description"]
+            }
+            "system.tenants" => {
+                for tenant in self.catalog.scan_tenants()? {
+                    rows.push(vec![
+                        Value::String(tenant.name),
+                        Value::Integer(tenant.keys as i64),
+                        Value::Integer(tenant.size as i64),
+                    ]);
+                }
+                vec!["name", "keys", "size"]
+            }
+            _ => return Err(Error::Value(format!("Table {} does not exist", name))),
+        };
+        Ok((
+            columns.into_iter().map(String::from).collect(),
+            rows.into_iter()
+                .map(|row| row.into_iter().map(Expression::Constant).collect())
+                .collect(),
+        ))
+    }
+
+    /// Builds a table function call on top of a source node, calling the function for each source
+    /// row. The arguments can reference the source's columns, as with LATERAL. The function's
+    /// column is named by the given column name, the alias, or the function name, in that order.
+    fn build_table_function(
+        &self,
+        scope: &mut Scope,
+        source: Node,
+        name: String,
+        args: Vec<ast::Expression>,
+        alias: Option<String>,
+        mut columns: Vec<String>,
+    ) -> Result<Node> {
+        let function = match name.as_str() {
+            "generate_series" if (2..=3).contains(&args.len()) => TableFunction::GenerateSeries,
+            "generate_series" => {
+                return Err(Error::Value("generate_series takes 2 or 3 arguments".into()))
+            }
+            _ => return Err(Error::Value(format!("Unknown table function {}", name))),
+        };
+        if columns.len() > 1 {
+            return Err(Error::Value(format!(
+                "{} returns 1 column but {} column names were given",
+                name,
+                columns.len()
+            )));
+        }
+        let args =
+            args.into_iter().map(|e| self.build_expression(scope, e)).collect::<Result<_>>()?;
+        let column = columns.pop().or_else(|| alias.clone()).unwrap_or_else(|| name.clone());
+        scope.add_relation(Some(alias.clone().unwrap_or(name)), std::slice::from_ref(&column))?;
+        Ok(Node::TableFunction { source: Box::new(source), function, args, alias, column })
+    }
+
+    /// Builds a WHERE clause. Top-level (i.e. AND-ed) IN and EXISTS subquery conditions are built
+    /// as semi-joins above a filter for the remaining predicate, if any.
+    fn build_where_clause(
+        &self,
+        scope: &mut Scope,
+        mut node: Node,
+        expr: ast::Expression,
+    ) -> Result<Node> {
+        let mut subqueries = Vec::new();
+        if let Some(expr) = Self::extract_subqueries(expr, &mut subqueries) {
+            // Conditions with scalar subqueries are applied after joining the subquery values
+            // onto the rows, which are then removed again. Other conditions are applied first,
+            // allowing them to be pushed down.
+            let (scalar, plain): (Vec<_>, Vec<_>) = split_and(expr)
+                .into_iter()
+                .partition(|e| e.contains(&|e| matches!(e, ast::Expression::Subquery(_))));
+            if let Some(predicate) = join_and(plain) {
+                node = Node::Filter {
+                    source: Box::new(node),
+                    predicate: self.build_expression(scope, predicate)?,
+                };
+            }
+            if let Some(mut predicate) = join_and(scalar) {
+                let outer = scope.clone();
+                node = self.build_scalar_subqueries(scope, node, &mut predicate)?;
+                node = Node::Filter {
+                    source: Box::new(node),
+                    predicate: self.build_expression(scope, predicate)?,
+                };
+                *scope = outer;
+                node = Node::Projection {
+                    source: Box::new(node),
+                    expressions: (0..scope.len())
+                        .map(|i| Ok((Expression::Field(i, scope.get_label(i)?), None)))
+                        .collect::<Result<_>>()?,
+                };
+            }
+        }
+        for (lhs, subquery, anti) in subqueries {
+            node = self.build_semi_join(scope, node, lhs, subquery, anti)?;
+        }
+        Ok(node)
+    }
+
+    /// Builds an ORDER BY clause, if any.
+    fn build_order_clause(
+        &self,
+        scope: &mut Scope,
+        node: Node,
+        order: Vec<(ast::Expression, ast::Order, Option<ast::Nulls>)>,
+    ) -> Result<Node> {
+        if order.is_empty() {
+            return Ok(node);
+        }
+        Ok(Node::Order {
+            source: Box::new(node),
+            orders: order
+                .into_iter()
+                .map(|(e, o, n)| {
+                    let direction = match o {
+                        ast::Order::Ascending => Direction::Ascending,
+                        ast::Order::Descending => Direction::Descending,
+                    };
+                    let nulls = match n {
+                        Some(ast::Nulls::First) => Nulls::First,
+                        Some(ast::Nulls::Last) => Nulls::Last,
+                        None => direction.default_nulls(),
+                    };
+                    Ok((self.build_expression(scope, e)?, direction, nulls))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Builds a LIMIT clause, if any. The limit must be a non-negative constant integer.
+    fn build_limit_clause(&self, node: Node, limit: Option<ast::Expression>) -> Result<Node> {
+        let Some(expr) = limit else { return Ok(node) };
+        Ok(Node::Limit {
+            source: Box::new(node),
+            limit: match self.evaluate_constant(expr)? {
+                Value::Integer(i) if i >= 0 => Ok(i as u64),
+                v => Err(Error::Value(format!("Invalid limit {}", v))),
+            }?,
+        })
+    }
+
+    /// Builds a TABLESAMPLE clause. The percentage must be a constant number from 0 to 100, and
+    /// the seed a constant integer.
+    fn build_sample(&self, sample: ast::TableSample) -> Result<Sample> {
+        let percent = match self.evaluate_constant(sample.percent)? {
+            Value::Integer(i) if (0..=100).contains(&i) => i as f64,
+            Value::Float(f) if (0.0..=100.0).contains(&f) => f,
+            v => return Err(Error::Value(format!("Invalid TABLESAMPLE percentage {}", v))),
+        };
+        let seed = match sample.seed.map(|e| self.evaluate_constant(e)).transpose()? {
+            Some(Value::Integer(i)) => Some(i as u64),
+            Some(v) => return Err(Error::Value(format!("Invalid TABLESAMPLE seed {}", v))),
+            None => None,
+        };
+        Ok(Sample { method: sample.method, percent, seed })
+    }
+
+    /// Extracts AND-ed IN and EXISTS conditions from an expression as (lhs, subquery, anti)
+    /// tuples, returning the remaining expression if any.
+    #[allow(clippy::type_complexity)]
+    fn extract_subqueries(
+        expr: ast::Expression,
+        subqueries: &mut Vec<(Option<ast::Expression>, ast::Expression, bool)>,
+    ) -> Option<ast::Expression> {
+        use ast::Operation::*;
+        match expr {
+            ast::Expression::Operation(And(lhs, rhs)) => {
+                let lhs = Self::extract_subqueries(*lhs, subqueries);
+                let rhs = Self::extract_subqueries(*rhs, subqueries);
+                match (lhs, rhs) {
+                    (Some(lhs), Some(rhs)) => Some(And(lhs.into(), rhs.into()).into()),
+                    (lhs, rhs) => lhs.or(rhs),
+                }
+            }
+            ast::Expression::Operation(In(lhs, rhs)) => {
+                subqueries.push((Some(*lhs), *rhs, false));
+                None
+            }
+            ast::Expression::Operation(Exists(rhs)) => {
+                subqueries.push((None, *rhs, false));
+                None
+            }
+            ast::Expression::Operation(Not(expr)) => match *expr {
+                ast::Expression::Operation(In(lhs, rhs)) => {
+                    subqueries.push((Some(*lhs), *rhs, true));
+                    None
+                }
+                ast::Expression::Operation(Exists(rhs)) => {
+                    subqueries.push((None, *rhs, true));
+                    None
+                }
+                expr => Some(Not(expr.into()).into()),
+            },
+            expr => Some(expr),
+        }
+    }
+
+    /// Builds a semi-join (or anti-join) of a node against an IN or EXISTS subquery. The subquery
+    /// may be correlated with the outer query via equality conditions between inner and outer
+    /// fields AND-ed into its WHERE clause, which are used as join keys.
+    fn build_semi_join(
+        &self,
+        scope: &mut Scope,
+        node: Node,
+        lhs: Option<ast::Expression>,
+        subquery: ast::Expression,
+        anti: bool,
+    ) -> Result<Node> {
+        let ast::Expression::Subquery(statement) = subquery else {
+            return Err(Error::Value("IN requires a subquery".into()));
+        };
+        let ast::Statement::Select {
+            hints,
+            distinct,
+            mut select,
+            from,
+            r#where,
+            group_by,
+            having,
+            order,
+            offset,
+            limit,
+            ordinality,
+        } = *statement
+        else {
+            return Err(Error::Internal(format!("Unexpected subquery {:?}", statement)));
+        };
+        // Row values compare several columns, e.g. (a, b) IN (SELECT x, y FROM t).
+        let lhs = match lhs {
+            Some(ast::Expression::Row(exprs)) => exprs,
+            Some(expr) => vec![expr],
+            None => Vec::new(),
+        };
+        if lhs.len() == 1 && select.len() != 1 {
+            return Err(Error::Value("IN subquery must return a single column".into()));
+        }
+        if lhs.len() > 1 && select.len() != lhs.len() {
+            return Err(Error::Value(format!("IN subquery must return {} columns", lhs.len())));
+        }
+        if anti && lhs.len() > 1 {
+            return Err(Error::Value("NOT IN is not supported for row values".into()));
+        }
+        let (local, mut inner_keys, outer_keys) = self.split_correlated(scope, &from, r#where)?;
+
+        // Correlated subqueries are rewritten to select the inner join keys followed by the IN
+        // expression, if any, with the correlated conditions removed. Uncorrelated subqueries are
+        // used as-is.
+        let right = if !inner_keys.is_empty() {
+            if !group_by.is_empty()
+                || having.is_some()
+                || offset.is_some()
+                || limit.is_some()
+                || select.iter().any(|(e, _)| self.is_aggregate(e))
+            {
+                return Err(Error::Value(
+                    "Correlated subqueries can't use aggregates, GROUP BY, HAVING, LIMIT, or OFFSET"
+                        .into(),
+                ));
+            }
+            if distinct.as_ref().is_some_and(|on| !on.is_empty()) {
+                return Err(Error::Value("Correlated subqueries can't use DISTINCT ON".into()));
+            }
+            if !lhs.is_empty() {
+                inner_keys.append(&mut select);
+            }
+            ast::Statement::Select {
+                hints,
+                distinct,
+                select: inner_keys,
+                from,
+                r#where: local,
+                group_by,
+                having,
+                order: Vec::new(),
+                offset,
+                limit,
+                ordinality,
+            }
+        } else {
+            ast::Statement::Select {
+                hints,
+                distinct,
+                select,
+                from,
+                r#where: local,
+                group_by,
+                having,
+                order,
+                offset,
+                limit,
+                ordinality,
+            }
+        };
+        let null_aware = anti && !lhs.is_empty();
+        let left_keys = outer_keys
+            .into_iter()
+            .chain(lhs)
+            .map(|e| self.build_expression(scope, e))
+            .collect::<Result<_>>()?;
+        Ok(Node::SemiJoin {
+            left: Box::new(node),
+            left_keys,
+            right: Box::new(self.build_statement(right)?),
+            anti,
+            null_aware,
+        })
+    }
+
+    /// Splits a subquery's WHERE clause into local conditions and correlated equality conditions
+    /// between an inner and an outer field, returning the local conditions along with the inner
+    /// and outer join keys. Inner names shadow outer names.
+    #[allow(clippy::type_complexity)]
+    fn split_correlated(
+        &self,
+        scope: &Scope,
+        from: &[ast::FromItem],
+        r#where: Option<ast::Expression>,
+    ) -> Result<(
+        Option<ast::Expression>,
+        Vec<(ast::Expression, Option<String>)>,
+        Vec<ast::Expression>,
+    )> {
+        let mut inner = Scope::new();
+        if !from.is_empty() {
+            self.build_from_clause(&mut inner, from.to_vec())?;
+        }
+        let resolves = |scope: &Scope, expr: &ast::Expression| match expr {
+            ast::Expression::Field(table, name) => scope.resolve(table.as_deref(), name).is_ok(),
+            _ => false,
+        };
+        let is_inner = |e: &ast::Expression| {
+            e.walk(&|e| !matches!(e, ast::Expression::Field(..)) || resolves(&inner, e))
+        };
+        let is_outer = |e: &ast::Expression| {
+            e.contains(&|e| matches!(e, ast::Expression::Field(..)))
+                && e.walk(&|e| {
+                    !matches!(e, ast::Expression::Field(..))
+                        || !resolves(&inner, e) && resolves(scope, e)
+                })
+        };
+        let is_unknown = |e: &ast::Expression| {
+            e.contains(&|e| {
+                matches!(e, ast::Expression::Field(..))
+                    && !resolves(&inner, e)
+                    && !resolves(scope, e)
+            })
+        };
+
+        let conditions = r#where.map(split_and).unwrap_or_default();
+        let (mut local, mut inner_keys, mut outer_keys) = (Vec::new(), Vec::new(), Vec::new());
+        for expr in conditions {
+            if is_unknown(&expr) || is_inner(&expr) {
+                local.push(expr);
+                continue;
+            }
+            match expr {
+                ast::Expression::Operation(ast::Operation::Equal(a, b))
+                    if is_inner(&a) && is_outer(&b) =>
+                {
+                    inner_keys.push((*a, None));
+                    outer_keys.push(*b);
+                }
+                ast::Expression::Operation(ast::Operation::Equal(a, b))
+                    if is_outer(&a) && is_inner(&b) =>
+                {
+                    inner_keys.push((*b, None));
+                    outer_keys.push(*a);
+                }
+                _ => {
+                    return Err(Error::Value(
+                        "Correlated subquery conditions must be AND-ed equalities".into(),
+                    ))
+                }
+            }
+        }
+        Ok((join_and(local), inner_keys, outer_keys))
+    }
+
+    /// Builds scalar subqueries in an expression as scalar joins against the node, replacing
+    /// them with references to the joined subquery values.
+    fn build_scalar_subqueries(
+        &self,
+        scope: &mut Scope,
+        mut node: Node,
+        expr: &mut ast::Expression,
+    ) -> Result<Node> {
+        let base = scope.len();
+        let mut subqueries = Vec::new();
+        expr.transform_mut(
+            &mut |e| match e {
+                ast::Expression::Operation(ast::Operation::Exists(_))
+                | ast::Expression::Operation(ast::Operation::In(_, _)) => Err(Error::Value(
+                    "IN and EXISTS are only supported as AND-ed WHERE conditions".into(),
+                )),
+                ast::Expression::Subquery(statement) => {
+                    subqueries.push(*statement);
+                    Ok(ast::Expression::Column(base + subqueries.len() - 1))
+                }
+                e => Ok(e),
+            },
+            &mut Ok,
+        )?;
+        for statement in subqueries {
+            node = self.build_scalar_join(scope, node, statement)?;
+        }
+        Ok(node)
+    }
+
+    /// Builds a scalar join of a node against a scalar subquery, appending the subquery value as
+    /// a new column. Like IN and EXISTS subqueries, the subquery may be correlated via AND-ed
+    /// equality conditions, which are used as join keys. Aggregates in correlated subqueries are
+    /// grouped by the join keys, and rows without a group get the aggregate value for no rows
+    /// (i.e. 0 for COUNT). The single row and column requirements are checked during execution.
+    fn build_scalar_join(
+        &self,
+        scope: &mut Scope,
+        node: Node,
+        statement: ast::Statement,
+    ) -> Result<Node> {
+        let ast::Statement::Select {
+            hints,
+            distinct,
+            mut select,
+            from,
+            r#where,
+            group_by,
+            having,
+            order,
+            offset,
+            limit,
+            ordinality,
+        } = statement
+        else {
+            return Err(Error::Internal(format!("Unexpected subquery {:?}", statement)));
+        };
+        let (local, mut inner_keys, outer_keys) = self.split_correlated(scope, &from, r#where)?;
+
+        let mut aggregate = None;
+        let right = if !inner_keys.is_empty() {
+            if distinct.is_some()
+                || !group_by.is_empty()
+                || having.is_some()
+                || offset.is_some()
+                || limit.is_some()
+            {
+                return Err(Error::Value(
+                    "Correlated scalar subqueries can't use DISTINCT, GROUP BY, HAVING, LIMIT, or OFFSET"
+                        .into(),
+                ));
+            }
+            if select.len() != 1 {
+                return Err(Error::Value("Scalar subquery must return a single column".into()));
+            }
+            let (expr, label) = select.remove(0);
+            let mut group_by = Vec::new();
+            if self.is_aggregate(&expr) {
+                group_by = inner_keys.iter().map(|(e, _)| e.clone()).collect();
+                aggregate = Some(expr.clone());
+            }
+            inner_keys.push((expr, label));
+            ast::Statement::Select {
+                hints,
+                distinct,
+                select: inner_keys,
+                from,
+                r#where: local,
+                group_by,
+                having,
+                order: Vec::new(),
+                offset,
+                limit,
+                ordinality,
+            }
+        } else {
+            ast::Statement::Select {
+                hints,
+                distinct,
+                select,
+                from,
+                r#where: local,
+                group_by,
+                having,
+                order,
+                offset,
+                limit,
+                ordinality,
+            }
+        };
+        let right = self.build_statement(right)?;
+        let default = match aggregate {
+            Some(expr) => self.evaluate_constant(expr.transform(
+                &mut |e| match e {
+                    ast::Expression::Function(f, _) if f == "count" => {
+                        Ok(ast::Literal::Integer(0).into())
+                    }
+                    ast::Expression::Function(f, _) if self.aggregate_from_name(&f).is_some() => {
+                        Ok(ast::Literal::Null.into())
+                    }
+                    e => Ok(e),
+                },
+                &mut Ok,
+            )?)?,
+            None => Value::Null,
+        };
+        let left_keys = outer_keys
+            .into_iter()
+            .map(|e| self.build_expression(scope, e))
+            .collect::<Result<_>>()?;
+        scope.add_column(None, None);
+        Ok(Node::ScalarJoin { left: Box::new(node), left_keys, right: Box::new(right), default })
+    }
+
     /// Builds an aggregation node. All aggregate parameters and GROUP BY expressions are evaluated
     /// in a pre-projection, whose results are fed into an Aggregate node. This node computes the
     /// aggregates for the given groups, passing the group values through directly.
@@ -396,12 +1287,76 @@ impl<'a, C: Catalog> Planner<'a, C> {
         Ok(aggregates)
     }
 
+    /// Expands wildcards in a SELECT clause into fields for all columns in scope, or all columns of
+    /// the given table for qualified wildcards. Columns that can't be referenced by name, e.g.
+    /// ambiguous unqualified columns, are referenced by index.
+    fn expand_wildcards(
+        &self,
+        scope: &Scope,
+        select: Vec<(ast::Expression, Option<String>)>,
+    ) -> Result<Vec<(ast::Expression, Option<String>)>> {
+        let mut expanded = Vec::with_capacity(select.len());
+        for (expr, label) in select {
+            let ast::Expression::Wildcard(table) = expr else {
+                expanded.push((expr, label));
+                continue;
+            };
+            match &table {
+                Some(table) => scope.check_table(table)?,
+                None if scope.len() == 0 => {
+                    return Err(Error::Value("Can't select * without a table".into()))
+                }
+                None => {}
+            }
+            for (i, column) in scope.columns.iter().enumerate() {
+                if table.is_some() && column.0 != table {
+                    continue;
+                }
+                let expr = match column {
+                    (Some(table), Some(name)) => {
+                        ast::Expression::Field(Some(table.clone()), name.clone())
+                    }
+                    (None, Some(name)) if scope.resolve(None, name).ok() == Some(i) => {
+                        ast::Expression::Field(None, name.clone())
+                    }
+                    _ => ast::Expression::Column(i),
+                };
+                expanded.push((expr, None));
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Resolves GROUP BY ordinals to the SELECT expressions they refer to, counting from 1.
+    fn resolve_group_ordinals(
+        &self,
+        select: &[(ast::Expression, Option<String>)],
+        group_by: Vec<ast::Expression>,
+    ) -> Result<Vec<ast::Expression>> {
+        group_by
+            .into_iter()
+            .map(|g| match g {
+                ast::Expression::Literal(ast::Literal::Integer(i)) => usize::try_from(i)
+                    .ok()
+                    .and_then(|i| i.checked_sub(1))
+                    .and_then(|i| select.get(i))
+                    .map(|(expr, _)| expr.clone())
+                    .ok_or_else(|| {
+                        Error::Value(format!("GROUP BY position {} is not in select list", i))
+                    }),
+                g => Ok(g),
+            })
+            .collect()
+    }
+
     /// Extracts group by expressions, and replaces them with column references with the given
     /// offset. These can be either an arbitray expression, a reference to a SELECT column, or the
-    /// same expression as a SELECT column. The following are all valid:
+    /// same expression as a SELECT column, which may also be part of a larger SELECT expression.
+    /// The following are all valid:
     ///
     /// SELECT released / 100 AS century, COUNT(*) FROM movies GROUP BY century
     /// SELECT released / 100, COUNT(*) FROM movies GROUP BY released / 100
+    /// SELECT released / 100 + 1, COUNT(*) FROM movies GROUP BY released / 100
     /// SELECT COUNT(*) FROM movies GROUP BY released / 100
     fn extract_groups(
         &self,
@@ -432,13 +1387,26 @@ impl<'a, C: Catalog> Planner<'a, C> {
             // Otherwise, just use the group expression directly
             groups.push((g, None))
         }
-        // Make sure no group expressions contain Column references, which would be placed here
-        // during extract_aggregates().
+        // Make sure no group expressions contain aggregates, or Column references to aggregates
+        // which would be placed here during extract_aggregates().
         for (expr, _) in &groups {
-            if self.is_aggregate(expr) {
+            if self.is_aggregate(expr)
+                || expr.contains(&|e| matches!(e, ast::Expression::Column(_)))
+            {
                 return Err(Error::Value("Group expression cannot contain aggregates".into()));
             }
         }
+        // Replace group expressions that are part of larger SELECT expressions. Plain fields are
+        // instead resolved by name after the aggregation.
+        for (expr, _) in exprs.iter_mut() {
+            expr.transform_mut(
+                &mut |e| match groups.iter().position(|(g, _)| is_group_match(g, &e)) {
+                    Some(i) => Ok(ast::Expression::Column(offset + i)),
+                    None => Ok(e),
+                },
+                &mut Ok,
+            )?;
+        }
         Ok(groups)
     }
 
@@ -451,6 +1419,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
         &self,
         expr: &mut ast::Expression,
         select: &mut Vec<(ast::Expression, Option<String>)>,
+        group_by: &[ast::Expression],
     ) -> Result<usize> {
         // Replace any identical expressions or label references with column references.
         for (i, (sexpr, label)) in select.iter().enumerate() {
@@ -470,11 +1439,16 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 )?;
             }
         }
-        // Any remaining aggregate functions and field references must be extracted as hidden
-        // columns.
+        // Any remaining group expressions, aggregate functions, and field references must be
+        // extracted as hidden columns.
         let mut hidden = 0;
         expr.transform_mut(
             &mut |e| match &e {
+                _ if group_by.iter().any(|g| is_group_match(g, &e)) => {
+                    select.push((e, None));
+                    hidden += 1;
+                    Ok(ast::Expression::Column(select.len() - 1))
+                }
                 ast::Expression::Function(f, a) if self.aggregate_from_name(f).is_some() => {
                     if let ast::Expression::Column(c) = a[0] {
                         if self.is_aggregate(&select[c].0) {
@@ -519,8 +1493,25 @@ impl<'a, C: Catalog> Planner<'a, C> {
         })
     }
 
+    /// Inlines a call to a stored function, returning its body with argument references
+    /// replaced by the given argument expressions.
+    fn inline_function(&self, name: &str, args: Vec<ast::Expression>) -> Result<ast::Expression> {
+        let function = self
+            .catalog
+            .read_function(name)?
+            .ok_or_else(|| Error::Value(format!("Unknown function {}", name)))?;
+        if args.len() != function.args.len() {
+            return Err(Error::Value(format!(
+                "Function {} takes {} arguments, given {}",
+                name,
+                function.args.len(),
+                args.len()
+            )));
+        }
+        bind_args(parse_function_body(&function.body)?, &function.args, args)
+    }
+
     /// Builds an expression from an AST expression
-    #[allow(clippy::only_used_in_recursion)]
     fn build_expression(&self, scope: &mut Scope, expr: ast::Expression) -> Result<Expression> {
         use Expression::*;
         Ok(match expr {
@@ -535,8 +1526,25 @@ impl<'a, C: Catalog> Planner<'a, C> {
             ast::Expression::Field(table, name) => {
                 Field(scope.resolve(table.as_deref(), &name)?, Some((table, name)))
             }
-            ast::Expression::Function(name, _) => {
-                return Err(Error::Value(format!("Unknown function {}", name,)))
+            ast::Expression::Function(name, args) => {
+                let expr = self.inline_function(&name, args)?;
+                self.build_expression(scope, expr)?
+            }
+            ast::Expression::Wildcard(_) => {
+                return Err(Error::Value(
+                    "Wildcards are only supported in the SELECT clause".into(),
+                ))
+            }
+            ast::Expression::Row(_) => {
+                return Err(Error::Value("Row values are only supported in comparisons".into()))
+            }
+            ast::Expression::Operation(op) if is_row_comparison(&op) => {
+                self.build_expression(scope, expand_row_comparison(op)?)?
+            }
+            ast::Expression::Subquery(_) => {
+                return Err(Error::Value(
+                    "Subqueries are only supported in SELECT and WHERE clauses".into(),
+                ))
             }
             ast::Expression::Operation(op) => match op {
                 // Logical operators
@@ -551,6 +1559,18 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 ),
 
                 // Comparison operators
+                ast::Operation::Between(expr, lower, upper) => And(
+                    self.build_expression(
+                        scope,
+                        ast::Operation::GreaterThanOrEqual(expr.clone(), lower).into(),
+                    )?
+                    .into(),
+                    self.build_expression(
+                        scope,
+                        ast::Operation::LessThanOrEqual(expr, upper).into(),
+                    )?
+                    .into(),
+                ),
                 ast::Operation::Equal(lhs, rhs) => Equal(
                     self.build_expression(scope, *lhs)?.into(),
                     self.build_expression(scope, *rhs)?.into(),
@@ -571,6 +1591,24 @@ impl<'a, C: Catalog> Planner<'a, C> {
                     )
                     .into(),
                 ),
+                ast::Operation::InList(expr, list) => {
+                    let expr = self.build_expression(scope, *expr)?;
+                    Expression::from_dnf_vec(
+                        list.into_iter()
+                            .map(|e| {
+                                Ok(Equal(
+                                    expr.clone().into(),
+                                    self.build_expression(scope, e)?.into(),
+                                ))
+                            })
+                            .collect::<Result<_>>()?,
+                    )
+                    .ok_or_else(|| Error::Internal("Empty IN list".into()))?
+                }
+                ast::Operation::IsDistinctFrom(lhs, rhs) => IsDistinctFrom(
+                    self.build_expression(scope, *lhs)?.into(),
+                    self.build_expression(scope, *rhs)?.into(),
+                ),
                 ast::Operation::IsNull(expr) => IsNull(self.build_expression(scope, *expr)?.into()),
                 ast::Operation::LessThan(lhs, rhs) => LessThan(
                     self.build_expression(scope, *lhs)?.into(),
@@ -598,6 +1636,14 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 )
                 .into()),
 
+                // Subquery operators, which are only supported as AND-ed WHERE conditions and
+                // handled by build_where_clause().
+                ast::Operation::Exists(_) | ast::Operation::In(_, _) => {
+                    return Err(Error::Value(
+                        "IN and EXISTS are only supported as AND-ed WHERE conditions".into(),
+                    ))
+                }
+
                 // Mathematical operators
                 ast::Operation::Assert(expr) => Assert(self.build_expression(scope, *expr)?.into()),
                 ast::Operation::Add(lhs, rhs) => Add(
@@ -636,6 +1682,205 @@ impl<'a, C: Catalog> Planner<'a, C> {
     fn evaluate_constant(&self, expr: ast::Expression) -> Result<Value> {
         self.build_expression(&mut Scope::constant(), expr)?.evaluate(None)
     }
+
+    /// Applies CREATE TABLE options to a table schema. These are the row TTL, given as
+    /// ttl = 'duration' and ttl_column = column, the row codec as codec = 'name', and the storage
+    /// layout as layout = 'name'.
+    fn build_table_options(
+        &self,
+        schema: &mut Table,
+        options: Vec<(String, ast::Expression)>,
+    ) -> Result<()> {
+        let (mut seconds, mut column) = (None, None);
+        for (i, (option, expr)) in options.iter().enumerate() {
+            if options[..i].iter().any(|(o, _)| o == option) {
+                return Err(Error::Value(format!("Duplicate table option {}", option)));
+            }
+            match option.as_str() {
+                "ttl" => match self.evaluate_constant(expr.clone())? {
+                    Value::String(duration) => seconds = Some(Ttl::parse_duration(&duration)?),
+                    value => {
+                        return Err(Error::Value(format!(
+                            "TTL must be a duration string, found {}",
+                            value
+                        )))
+                    }
+                },
+                "ttl_column" => match expr {
+                    ast::Expression::Field(None, name) => column = Some(name.clone()),
+                    _ => return Err(Error::Value("TTL column must be a column name".into())),
+                },
+                "codec" => match self.evaluate_constant(expr.clone())? {
+                    Value::String(name) => schema.codec = Codec::from_name(&name)?,
+                    value => {
+                        return Err(Error::Value(format!(
+                            "Codec must be a string, found {}",
+                            value
+                        )))
+                    }
+                },
+                "layout" => match self.evaluate_constant(expr.clone())? {
+                    Value::String(name) => schema.layout = Layout::from_name(&name)?,
+                    value => {
+                        return Err(Error::Value(format!(
+                            "Layout must be a string, found {}",
+                            value
+                        )))
+                    }
+                },
+                _ => return Err(Error::Value(format!("Unknown table option {}", option))),
+            }
+        }
+        if schema.layout == Layout::Columnar && options.iter().any(|(o, _)| o == "codec") {
+            return Err(Error::Value("Table option codec requires the row layout".into()));
+        }
+        schema.ttl = match (seconds, column) {
+            (Some(seconds), Some(column)) => Some(Ttl { column, seconds }),
+            (None, None) => None,
+            _ => {
+                return Err(Error::Value(
+                    "Table options ttl and ttl_column must be given together".into(),
+                ))
+            }
+        };
+        Ok(())
+    }
+}
+
+/// Parses a stored function body, which must be a SELECT of a single expression.
+fn parse_function_body(body: &str) -> Result<ast::Expression> {
+    match Parser::new(body).parse()? {
+        ast::Statement::Select {
+            hints,
+            distinct: None,
+            mut select,
+            from,
+            r#where: None,
+            group_by,
+            having: None,
+            order,
+            offset: None,
+            limit: None,
+            ordinality: false,
+        } if hints.is_empty()
+            && select.len() == 1
+            && from.is_empty()
+            && group_by.is_empty()
+            && order.is_empty() =>
+        {
+            Ok(select.remove(0).0)
+        }
+        _ => Err(Error::Value(
+            "Function body must be a SELECT of a single expression, without other clauses".into(),
+        )),
+    }
+}
+
+/// Replaces argument references in a function body with the given argument expressions.
+fn bind_args(
+    body: ast::Expression,
+    params: &[(String, DataType)],
+    args: Vec<ast::Expression>,
+) -> Result<ast::Expression> {
+    let args: HashMap<&str, ast::Expression> =
+        params.iter().map(|(name, _)| name.as_str()).zip(args).collect();
+    body.transform(&mut Ok, &mut |e| match e {
+        ast::Expression::Field(None, name) if args.contains_key(name.as_str()) => {
+            Ok(args[name.as_str()].clone())
+        }
+        e => Ok(e),
+    })
+}
+
+/// Splits an expression into its AND-ed conditions.
+fn split_and(expr: ast::Expression) -> Vec<ast::Expression> {
+    match expr {
+        ast::Expression::Operation(ast::Operation::And(lhs, rhs)) => {
+            let mut conditions = split_and(*lhs);
+            conditions.extend(split_and(*rhs));
+            conditions
+        }
+        expr => vec![expr],
+    }
+}
+
+/// Joins conditions with AND, returning None if there are no conditions.
+fn join_and(conditions: Vec<ast::Expression>) -> Option<ast::Expression> {
+    conditions.into_iter().reduce(|lhs, rhs| ast::Operation::And(lhs.into(), rhs.into()).into())
+}
+
+/// Checks whether an operation compares row values.
+fn is_row_comparison(op: &ast::Operation) -> bool {
+    use ast::Operation::*;
+    let is_row = |expr: &ast::Expression| matches!(expr, ast::Expression::Row(_));
+    match op {
+        Equal(lhs, rhs)
+        | GreaterThan(lhs, rhs)
+        | GreaterThanOrEqual(lhs, rhs)
+        | IsDistinctFrom(lhs, rhs)
+        | LessThan(lhs, rhs)
+        | LessThanOrEqual(lhs, rhs)
+        | NotEqual(lhs, rhs) => is_row(lhs) || is_row(rhs),
+        InList(lhs, _) => is_row(lhs),
+        _ => false,
+    }
+}
+
+/// Expands a row value comparison into comparisons of the row elements. Rows are equal if all
+/// elements are equal, are distinct if any elements are distinct, and are ordered
+/// lexicographically, e.g. (a, b) < (1, 2) is expanded to a < 1 OR a = 1 AND b < 2.
+fn expand_row_comparison(op: ast::Operation) -> Result<ast::Expression> {
+    use ast::Operation::*;
+    let pairs = |lhs: Box<ast::Expression>, rhs: Box<ast::Expression>| match (*lhs, *rhs) {
+        (ast::Expression::Row(lhs), ast::Expression::Row(rhs)) if lhs.len() == rhs.len() => {
+            Ok(lhs.into_iter().zip(rhs).collect::<Vec<_>>())
+        }
+        _ => Err(Error::Value("Row values can only be compared with rows of the same size".into())),
+    };
+    let equal = |pairs: Vec<(ast::Expression, ast::Expression)>| {
+        join_and(pairs.into_iter().map(|(l, r)| Equal(l.into(), r.into()).into()).collect())
+            .ok_or_else(|| Error::Internal("Empty row value".into()))
+    };
+    // Builds a lexicographic comparison, using the strict operator for all but the last element.
+    type Operator = fn(Box<ast::Expression>, Box<ast::Expression>) -> ast::Operation;
+    let ordering = |pairs: Vec<(ast::Expression, ast::Expression)>,
+                    strict: Operator,
+                    last: Operator| {
+        let mut pairs = pairs.into_iter().rev();
+        let (l, r) = pairs.next().ok_or_else(|| Error::Internal("Empty row value".into()))?;
+        let mut expr: ast::Expression = last(l.into(), r.into()).into();
+        for (l, r) in pairs {
+            let equal = ast::Expression::from(Equal(l.clone().into(), r.clone().into()));
+            let and = ast::Expression::from(And(equal.into(), expr.into()));
+            expr = Or(ast::Expression::from(strict(l.into(), r.into())).into(), and.into()).into();
+        }
+        Ok(expr)
+    };
+    match op {
+        Equal(lhs, rhs) => equal(pairs(lhs, rhs)?),
+        NotEqual(lhs, rhs) => Ok(Not(equal(pairs(lhs, rhs)?)?.into()).into()),
+        IsDistinctFrom(lhs, rhs) => pairs(lhs, rhs)?
+            .into_iter()
+            .map(|(l, r)| ast::Expression::from(IsDistinctFrom(l.into(), r.into())))
+            .reduce(|lhs, rhs| Or(lhs.into(), rhs.into()).into())
+            .ok_or_else(|| Error::Internal("Empty row value".into())),
+        GreaterThan(lhs, rhs) => ordering(pairs(lhs, rhs)?, GreaterThan, GreaterThan),
+        GreaterThanOrEqual(lhs, rhs) => ordering(pairs(lhs, rhs)?, GreaterThan, GreaterThanOrEqual),
+        LessThan(lhs, rhs) => ordering(pairs(lhs, rhs)?, LessThan, LessThan),
+        LessThanOrEqual(lhs, rhs) => ordering(pairs(lhs, rhs)?, LessThan, LessThanOrEqual),
+        InList(lhs, list) => list
+            .into_iter()
+            .map(|expr| ast::Expression::from(Equal(lhs.clone(), expr.into())))
+            .reduce(|lhs, rhs| Or(lhs.into(), rhs.into()).into())
+            .ok_or_else(|| Error::Internal("Empty IN list".into())),
+        op => Err(Error::Internal(format!("Unexpected row comparison {:?}", op))),
+    }
+}
+
+/// Checks whether an expression structurally matches a non-field GROUP BY expression. Fields are
+/// not matched, since they're resolved by name after the aggregation.
+fn is_group_match(group: &ast::Expression, expr: &ast::Expression) -> bool {
+    !matches!(group, ast::Expression::Field(_, _)) && group == expr
 }
 
 /// Manages names available to expressions and executors, and maps them onto columns/fields.
@@ -643,8 +1888,10 @@ impl<'a, C: Catalog> Planner<'a, C> {
 pub struct Scope {
     // If true, the scope is constant and cannot contain any variables.
     constant: bool,
-    // Currently visible tables, by query name (i.e. alias or actual name).
-    tables: HashMap<String, Table>,
+    // Currently visible tables and other relations, by query name (i.e. alias or actual name).
+    tables: HashSet<String>,
+    // Aliases of aliased tables, by table name, for error messages.
+    aliases: HashMap<String, Vec<String>>,
     // Column labels, if any (qualified by table name when available)
     columns: Vec<(Option<String>, Option<String>)>,
     // Qualified names to column indexes.
@@ -660,7 +1907,8 @@ impl Scope {
     fn new() -> Self {
         Self {
             constant: false,
-            tables: HashMap::new(),
+            tables: HashSet::new(),
+            aliases: HashMap::new(),
             columns: Vec::new(),
             qualified: HashMap::new(),
             unqualified: HashMap::new(),
@@ -703,16 +1951,27 @@ impl Scope {
 
     /// Adds a table to the scope.
     fn add_table(&mut self, label: String, table: Table) -> Result<()> {
+        if label != table.name {
+            self.aliases.entry(table.name).or_default().push(label.clone());
+        }
+        let columns: Vec<String> = table.columns.into_iter().map(|c| c.name).collect();
+        self.add_relation(Some(label), &columns)
+    }
+
+    /// Adds a relation with the given columns to the scope, e.g. a table or VALUES list. Columns
+    /// can only be qualified if the relation has a label.
+    fn add_relation(&mut self, label: Option<String>, columns: &[String]) -> Result<()> {
         if self.constant {
             return Err(Error::Internal("Can't modify constant scope".into()));
         }
-        if self.tables.contains_key(&label) {
-            return Err(Error::Value(format!("Duplicate table name {}", label)));
+        if let Some(label) = &label {
+            if !self.tables.insert(label.clone()) {
+                return Err(Error::Value(format!("Duplicate table name {}", label)));
+            }
         }
-        for column in &table.columns {
-            self.add_column(Some(label.clone()), Some(column.name.clone()));
+        for column in columns {
+            self.add_column(label.clone(), Some(column.clone()));
         }
-        self.tables.insert(label, table);
         Ok(())
     }
 
@@ -743,11 +2002,13 @@ impl Scope {
         if self.constant {
             return Err(Error::Internal("Can't modify constant scope".into()));
         }
-        for (label, table) in scope.tables {
-            if self.tables.contains_key(&label) {
+        for label in scope.tables {
+            if !self.tables.insert(label.clone()) {
                 return Err(Error::Value(format!("Duplicate table name {}", label)));
             }
-            self.tables.insert(label, table);
+        }
+        for (table, aliases) in scope.aliases {
+            self.aliases.entry(table).or_default().extend(aliases);
         }
         for (table, label) in scope.columns {
             self.add_column(table, label);
@@ -764,15 +2025,13 @@ impl Scope {
             )));
         }
         if let Some(table) = table {
-            if !self.tables.contains_key(table) {
-                return Err(Error::Value(format!("Unknown table {}", table)));
-            }
+            self.check_table(table)?;
             self.qualified
                 .get(&(table.into(), name.into()))
                 .copied()
                 .ok_or_else(|| Error::Value(format!("Unknown field {}.{}", table, name)))
         } else if self.ambiguous.contains(name) {
-            Err(Error::Value(format!("Ambiguous field {}", name)))
+            Err(self.ambiguous_field(name, 0..self.len()))
         } else {
             self.unqualified
                 .get(name)
@@ -781,6 +2040,65 @@ impl Scope {
         }
     }
 
+    /// Resolves an unqualified name among the given column indexes, e.g. one side of a join.
+    fn resolve_range(&self, range: std::ops::Range<usize>, name: &str) -> Result<usize> {
+        let mut matches = range.clone().filter(|i| self.columns[*i].1.as_deref() == Some(name));
+        match (matches.next(), matches.next()) {
+            (Some(i), None) => Ok(i),
+            (Some(_), Some(_)) => Err(self.ambiguous_field(name, range)),
+            (None, _) => Err(Error::Value(format!("Unknown field {}", name))),
+        }
+    }
+
+    /// Checks that a table is visible in the scope, by its query name.
+    fn check_table(&self, table: &str) -> Result<()> {
+        if self.tables.contains(table) {
+            return Ok(());
+        }
+        match self.aliases.get(table) {
+            Some(aliases) => Err(Error::Value(format!(
+                "Unknown table {}, it must be referenced by its alias {}",
+                table,
+                aliases.join(" or ")
+            ))),
+            None => Err(Error::Value(format!("Unknown table {}", table))),
+        }
+    }
+
+    /// Returns an error for an ambiguous unqualified field, listing the columns it may refer to
+    /// among the given column indexes.
+    fn ambiguous_field(&self, name: &str, range: std::ops::Range<usize>) -> Error {
+        let candidates: Vec<String> = self.columns[range]
+            .iter()
+            .filter(|(_, n)| n.as_deref() == Some(name))
+            .map(|(t, _)| match t {
+                Some(t) => format!("{}.{}", t, name),
+                None => name.to_string(),
+            })
+            .collect();
+        Error::Value(format!("Ambiguous field {}, could be {}", name, candidates.join(" or ")))
+    }
+
+    /// Returns the names of columns before the boundary that also occur after it, in order, e.g.
+    /// the common columns of a natural join.
+    fn common_columns(&self, boundary: usize) -> Vec<String> {
+        let mut common: Vec<String> = Vec::new();
+        for (_, name) in &self.columns[..boundary] {
+            let Some(name) = name else { continue };
+            if !common.contains(name)
+                && self.columns[boundary..].iter().any(|(_, n)| n == &Some(name.clone()))
+            {
+                common.push(name.clone());
+            }
+        }
+        common
+    }
+
+    /// Makes a column resolvable by a qualified name, e.g. for merged USING join columns.
+    fn add_qualified(&mut self, table: String, name: String, index: usize) {
+        self.qualified.insert((table, name), index);
+    }
+
     /// Number of columns in the current scope.
     fn len(&self) -> usize {
         self.columns.len()
@@ -794,6 +2112,7 @@ impl Scope {
         }
         let mut new = Self::new();
         new.tables = self.tables.clone();
+        new.aliases = self.aliases.clone();
         for (expr, label) in projection {
             match (expr, label) {
                 (_, Some(label)) => new.add_column(None, Some(label.clone())),
@@ -1,12 +1,17 @@
-use super::engine::Transaction;
-use super::parser::format_ident;
-use super::types::{DataType, Value};
+use super::engine::{Codec, Layout, Transaction};
+use super::parser::{ast, format_ident, format_value};
+use super::plan::Direction;
+use super::types::{DataType, Expression, Value};
 use crate::error::{Error, Result};
 
 use serde_derive::{Deserialize, Serialize};
 use std::fmt::{self, Display};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-/// The catalog stores schema information
+/// The catalog stores schema information. It's implemented by SQL engine transactions, and
+/// schema changes are regular transactional writes: they're only visible to other transactions
+/// once committed, and are discarded on rollback, along with any other writes made in the same
+/// transaction.
 pub trait Catalog {
     /// Creates a new table
     fn create_table(&mut self, table: Table) -> Result<()>;
@@ -16,6 +21,55 @@ pub trait Catalog {
     fn read_table(&self, table: &str) -> Result<Option<Table>>;
     /// Iterates over all tables
     fn scan_tables(&self) -> Result<Tables>;
+    /// Drops an existing table at the given time in seconds since the Unix epoch, or errors if it
+    /// does not exist. Its data is retained until it's purged, and it can be restored until then.
+    fn drop_table(&mut self, table: &str, time: u64) -> Result<()>;
+    /// Restores a dropped table, or errors if it has not been dropped
+    fn undrop_table(&mut self, table: &str) -> Result<()>;
+    /// Permanently deletes a dropped table and its data, or errors if it has not been dropped
+    fn purge_table(&mut self, table: &str) -> Result<()>;
+    /// Fetches all dropped tables, ordered by name
+    fn scan_dropped_tables(&self) -> Result<Vec<DroppedTable>>;
+    /// Computes storage statistics for a table, or errors if it does not exist
+    fn table_stats(&self, table: &str) -> Result<TableStats>;
+
+    /// Creates a new trigger
+    fn create_trigger(&mut self, trigger: Trigger) -> Result<()>;
+    /// Deletes an existing trigger, or errors if it does not exist
+    fn delete_trigger(&mut self, table: &str, trigger: &str) -> Result<()>;
+    /// Fetches a table's triggers, ordered by name
+    fn scan_triggers(&self, table: &str) -> Result<Vec<Trigger>>;
+
+    /// Creates a new index, indexing any existing rows unless it is write-only
+    fn create_index(&mut self, index: Index) -> Result<()>;
+    /// Deletes an existing index and its entries, or errors if it does not exist
+    fn delete_index(&mut self, table: &str, index: &str) -> Result<()>;
+    /// Makes a write-only index public once it has been backfilled, such that queries use it
+    fn publish_index(&mut self, table: &str, index: &str) -> Result<()>;
+    /// Fetches a table's indexes created with CREATE INDEX, ordered by name
+    fn scan_indexes(&self, table: &str) -> Result<Vec<Index>>;
+
+    /// Creates a new function
+    fn create_function(&mut self, function: Function) -> Result<()>;
+    /// Deletes an existing function, or errors if it does not exist or is used by other functions
+    fn delete_function(&mut self, function: &str) -> Result<()>;
+    /// Reads a function, if it exists
+    fn read_function(&self, function: &str) -> Result<Option<Function>>;
+    /// Fetches all functions, ordered by name
+    fn scan_functions(&self) -> Result<Vec<Function>>;
+
+    /// Sets a cluster setting, or resets it if no value is given. The value must be valid for
+    /// the setting, see engine::Setting.
+    fn set_setting(&mut self, name: &str, value: Option<Value>) -> Result<()>;
+    /// Fetches the cluster settings that have been set, ordered by name
+    fn scan_settings(&self) -> Result<Vec<(String, Value)>>;
+
+    /// Creates a tenant with an empty keyspace, or errors if it already exists
+    fn create_tenant(&mut self, tenant: &str) -> Result<()>;
+    /// Deletes a tenant and all of its data, or errors if it does not exist
+    fn delete_tenant(&mut self, tenant: &str) -> Result<()>;
+    /// Fetches the tenants and their storage statistics, ordered by name
+    fn scan_tenants(&self) -> Result<Vec<TenantStats>>;
 
     /// Reads a table, and errors if it does not exist
     fn must_read_table(&self, table: &str) -> Result<Table> {
@@ -51,12 +105,19 @@ pub type Tables = Box<dyn DoubleEndedIterator<Item = Table> + Send>;
 pub struct Table {
     pub name: String,
     pub columns: Vec<Column>,
+    /// The time-to-live of rows, if any
+    pub ttl: Option<Ttl>,
+    /// The codec used to store rows
+    pub codec: Codec,
+    /// The storage layout of rows
+    pub layout: Layout,
 }
 
 impl Table {
     /// Creates a new table schema
     pub fn new(name: String, columns: Vec<Column>) -> Result<Self> {
-        let table = Self { name, columns };
+        let table =
+            Self { name, columns, ttl: None, codec: Codec::default(), layout: Layout::default() };
         Ok(table)
     }
 
@@ -107,9 +168,53 @@ impl Table {
         for column in &self.columns {
             column.validate(self, txn)?;
         }
+        if let Some(ttl) = &self.ttl {
+            let column = self.get_column(&ttl.column)?;
+            if column.datatype != DataType::Integer {
+                return Err(Error::Value(format!(
+                    "TTL column {} must be INTEGER, found {}",
+                    column.name, column.datatype
+                )));
+            }
+        }
         Ok(())
     }
 
+    /// Returns a filter expression for rows that have not expired, if the table has a TTL. The
+    /// fields are labeled with the given table name. Rows with a NULL TTL column never expire.
+    pub fn ttl_filter(&self, label: &str) -> Result<Option<Expression>> {
+        let Some((field, cutoff)) = self.ttl_cutoff(label)? else { return Ok(None) };
+        Ok(Some(Expression::Or(
+            Box::new(Expression::IsNull(Box::new(field.clone()))),
+            Box::new(Expression::GreaterThan(Box::new(field), Box::new(cutoff))),
+        )))
+    }
+
+    /// Returns a filter expression for expired rows, if the table has a TTL.
+    pub fn ttl_expired(&self) -> Result<Option<Expression>> {
+        let Some((field, cutoff)) = self.ttl_cutoff(&self.name)? else { return Ok(None) };
+        Ok(Some(Expression::Not(Box::new(Expression::GreaterThan(
+            Box::new(field),
+            Box::new(cutoff),
+        )))))
+    }
+
+    /// Returns the TTL column field and the cutoff time in seconds since the Unix epoch, at or
+    /// before which rows have expired.
+    fn ttl_cutoff(&self, label: &str) -> Result<Option<(Expression, Expression)>> {
+        let Some(ttl) = &self.ttl else { return Ok(None) };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| Error::Internal(err.to_string()))?
+            .as_secs();
+        let cutoff = now.saturating_sub(ttl.seconds) as i64;
+        let field = Expression::Field(
+            self.get_column_index(&ttl.column)?,
+            Some((Some(label.to_string()), ttl.column.clone())),
+        );
+        Ok(Some((field, Expression::Constant(Value::Integer(cutoff)))))
+    }
+
     /// Validates a row
     pub fn validate_row(&self, row: &[Value], txn: &mut dyn Transaction) -> Result<()> {
         if row.len() != self.columns.len() {
@@ -130,6 +235,115 @@ impl Display for Table {
             "CREATE TABLE {} (\n{}\n)",
             format_ident(&self.name),
             self.columns.iter().map(|c| format!("  {}", c)).collect::<Vec<String>>().join(",\n")
+        )?;
+        let mut options = Vec::new();
+        if let Some(ttl) = &self.ttl {
+            options.push(ttl.to_string());
+        }
+        if self.codec != Codec::default() {
+            options.push(format!("codec = '{}'", self.codec));
+        }
+        if self.layout != Layout::default() {
+            options.push(format!("layout = '{}'", self.layout));
+        }
+        if !options.is_empty() {
+            write!(f, " WITH ({})", options.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A dropped table, whose data is retained until it's purged
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct DroppedTable {
+    pub table: Table,
+    /// The time the table was dropped, in seconds since the Unix epoch
+    pub time: u64,
+}
+
+/// Storage statistics for a table. Sizes are the total size of the encoded keys and values as
+/// seen by the transaction, excluding old MVCC versions and storage engine overhead.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TableStats {
+    /// The number of rows, including expired rows that haven't been deleted yet
+    pub rows: u64,
+    /// The size of the rows in bytes
+    pub size: u64,
+    /// Statistics for the table's secondary indexes, in column order
+    pub indexes: Vec<IndexStats>,
+}
+
+/// Storage statistics for a tenant, i.e. the total size of its keyspace. Sizes are computed like
+/// for TableStats.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TenantStats {
+    /// The tenant name
+    pub name: String,
+    /// The number of keys in the tenant's keyspace
+    pub keys: u64,
+    /// The size of the tenant's keys and values in bytes
+    pub size: u64,
+}
+
+/// Storage statistics for a secondary index
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct IndexStats {
+    /// The indexed column
+    pub column: String,
+    /// The number of index entries, i.e. distinct indexed values
+    pub entries: u64,
+    /// The size of the index in bytes, including any included column values
+    pub size: u64,
+}
+
+/// A row time-to-live, after which rows expire according to a timestamp column
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Ttl {
+    /// The INTEGER column holding the row timestamp, in seconds since the Unix epoch
+    pub column: String,
+    /// The number of seconds after the timestamp at which a row expires
+    pub seconds: u64,
+}
+
+impl Ttl {
+    const UNITS: [(&'static str, u64); 5] =
+        [("week", 604800), ("day", 86400), ("hour", 3600), ("minute", 60), ("second", 1)];
+
+    /// Parses a duration such as '7 days' or '1 hour' into seconds.
+    pub fn parse_duration(duration: &str) -> Result<u64> {
+        let error = || Error::Value(format!("Invalid TTL duration {}", duration));
+        let mut parts = duration.split_whitespace();
+        let (Some(count), Some(unit), None) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(error());
+        };
+        let count: u64 = count.parse().map_err(|_| error())?;
+        let unit = unit.to_lowercase();
+        let unit = unit.strip_suffix('s').unwrap_or(&unit);
+        let (_, seconds) = Self::UNITS.iter().find(|(u, _)| *u == unit).ok_or_else(error)?;
+        match count.checked_mul(*seconds) {
+            Some(0) => Err(Error::Value("TTL duration must be positive".into())),
+            Some(seconds) => Ok(seconds),
+            None => Err(error()),
+        }
+    }
+}
+
+impl Display for Ttl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (unit, size) = Self::UNITS
+            .iter()
+            .find(|(_, size)| self.seconds.checked_rem(*size) == Some(0))
+            .copied()
+            .unwrap_or(("second", 1));
+        let count = self.seconds / size;
+        let plural = if count == 1 { "" } else { "s" };
+        write!(
+            f,
+            "ttl = '{} {}{}', ttl_column = {}",
+            count,
+            unit,
+            plural,
+            format_ident(&self.column)
         )
     }
 }
@@ -153,6 +367,8 @@ pub struct Column {
     pub references: Option<String>,
     /// Whether the column should be indexed
     pub index: bool,
+    /// Additional columns stored in the index, allowing index-only scans
+    pub include: Vec<String>,
 }
 
 impl Column {
@@ -188,6 +404,29 @@ impl Column {
             )));
         }
 
+        // Validate included columns
+        if !self.include.is_empty() && !self.index {
+            return Err(Error::Value(format!(
+                "Column {} must be indexed to include columns",
+                self.name
+            )));
+        }
+        for (i, name) in self.include.iter().enumerate() {
+            let column = table.get_column(name)?;
+            if column.name == self.name || column.primary_key {
+                return Err(Error::Value(format!(
+                    "Index on {} already includes column {}",
+                    self.name, column.name
+                )));
+            }
+            if self.include[..i].contains(name) {
+                return Err(Error::Value(format!(
+                    "Duplicate included column {} for index on {}",
+                    name, self.name
+                )));
+            }
+        }
+
         // Validate references
         if let Some(reference) = &self.references {
             let target = if reference == &table.name {
@@ -225,7 +464,10 @@ impl Column {
         // Validate datatype
         match value.datatype() {
             None if self.nullable => Ok(()),
-            None => Err(Error::Value(format!("NULL value not allowed for column {}", self.name))),
+            None => Err(Error::Constraint {
+                name: format!("{}_{}_not_null", table.name, self.name),
+                message: format!("NULL value not allowed for column {}", self.name),
+            }),
             Some(ref datatype) if datatype != &self.datatype => Err(Error::Value(format!(
                 "Invalid datatype {} for {} column {}",
                 datatype, self.datatype, self.name
@@ -247,10 +489,13 @@ impl Column {
                 Value::Null => Ok(()),
                 Value::Float(f) if f.is_nan() => Ok(()),
                 v if target == &table.name && v == pk => Ok(()),
-                v if txn.read(target, v)?.is_none() => Err(Error::Value(format!(
-                    "Referenced primary key {} in table {} does not exist",
-                    v, target,
-                ))),
+                v if txn.read(target, v)?.is_none() => Err(Error::Constraint {
+                    name: format!("{}_{}_fkey", table.name, self.name),
+                    message: format!(
+                        "Referenced primary key {} in table {} does not exist",
+                        v, target,
+                    ),
+                }),
                 _ => Ok(()),
             }?;
         }
@@ -263,10 +508,13 @@ impl Column {
                 if row.get(index).unwrap_or(&Value::Null) == value
                     && &table.get_row_key(&row)? != pk
                 {
-                    return Err(Error::Value(format!(
-                        "Unique value {} already exists for column {}",
-                        value, self.name
-                    )));
+                    return Err(Error::Constraint {
+                        name: format!("{}_{}_key", table.name, self.name),
+                        message: format!(
+                            "Unique value {} already exists for column {}",
+                            value, self.name
+                        ),
+                    });
                 }
             }
         }
@@ -286,17 +534,240 @@ impl Display for Column {
             sql += " NOT NULL";
         }
         if let Some(default) = &self.default {
-            sql += &format!(" DEFAULT {}", default);
+            sql += &format!(" DEFAULT {}", format_value(default));
         }
         if self.unique && !self.primary_key {
             sql += " UNIQUE";
         }
         if let Some(reference) = &self.references {
-            sql += &format!(" REFERENCES {}", reference);
+            sql += &format!(" REFERENCES {}", format_ident(reference));
         }
         if self.index {
             sql += " INDEX";
         }
+        if !self.include.is_empty() {
+            let include: Vec<_> = self.include.iter().map(|c| format_ident(c)).collect();
+            sql += &format!(" INCLUDE ({})", include.join(", "));
+        }
         write!(f, "{}", sql)
     }
 }
+
+/// A row-level trigger, which executes an SQL statement for each row written by an INSERT,
+/// UPDATE, or DELETE on the table, in the same transaction. The statement can refer to the
+/// new and old row values as new.column and old.column, where available.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Trigger {
+    /// Trigger name, unique per table
+    pub name: String,
+    /// The table the trigger is defined on
+    pub table: String,
+    /// Whether the trigger runs before or after each row is written
+    pub timing: TriggerTiming,
+    /// The row operation which fires the trigger
+    pub event: TriggerEvent,
+    /// The SQL statement to execute, parsed again every time the trigger fires
+    pub statement: String,
+}
+
+impl Trigger {
+    /// Validates the trigger
+    pub fn validate(&self, txn: &mut dyn Transaction) -> Result<()> {
+        txn.must_read_table(&self.table)?;
+        if txn.scan_triggers(&self.table)?.iter().any(|t| t.name == self.name) {
+            return Err(Error::Value(format!(
+                "Trigger {} already exists on table {}",
+                self.name, self.table
+            )));
+        }
+        Ok(())
+    }
+
+    /// Replaces references to the old and new row in a trigger statement, as old.column and
+    /// new.column, with the given row values. Errors if a row is not given, i.e. the old row
+    /// for INSERT triggers and the new row for DELETE triggers.
+    pub fn bind(
+        &self,
+        statement: &mut ast::Statement,
+        table: &Table,
+        old: Option<&[Value]>,
+        new: Option<&[Value]>,
+    ) -> Result<()> {
+        statement.transform_expressions(&mut |expr| match expr {
+            ast::Expression::Field(Some(row), column) if row == "old" || row == "new" => {
+                let values = if row == "old" { old } else { new }.ok_or_else(|| {
+                    Error::Value(format!("Row {} is not available in {} triggers", row, self.event))
+                })?;
+                Ok(ast::Expression::Literal(
+                    match values.get(table.get_column_index(&column)?).cloned() {
+                        Some(Value::Null) | None => ast::Literal::Null,
+                        Some(Value::Boolean(b)) => ast::Literal::Boolean(b),
+                        Some(Value::Integer(i)) => ast::Literal::Integer(i),
+                        Some(Value::Float(f)) => ast::Literal::Float(f),
+                        Some(Value::String(s)) => ast::Literal::String(s),
+                    },
+                ))
+            }
+            expr => Ok(expr),
+        })
+    }
+}
+
+impl Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CREATE TRIGGER {} {} {} ON {} FOR EACH ROW EXECUTE ({})",
+            format_ident(&self.name),
+            self.timing,
+            self.event,
+            format_ident(&self.table),
+            self.statement
+        )
+    }
+}
+
+/// A secondary index on one or more columns created with CREATE INDEX, each in ascending or
+/// descending order. Entries are ordered by the column values and then by primary key, such that
+/// rows can be read in the index order, or its reverse, without sorting them.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Index {
+    /// Index name, unique per table
+    pub name: String,
+    /// The table the index is defined on
+    pub table: String,
+    /// The indexed columns, in order of precedence
+    pub columns: Vec<(String, Direction)>,
+    /// Whether the index is write-only while being backfilled online: writes maintain its
+    /// entries, but queries don't use it until it is published.
+    pub write_only: bool,
+}
+
+impl Index {
+    /// Validates the index
+    pub fn validate(&self, txn: &mut dyn Transaction) -> Result<()> {
+        let table = txn.must_read_table(&self.table)?;
+        if self.columns.is_empty() {
+            return Err(Error::Value(format!("Index {} has no columns", self.name)));
+        }
+        for (i, (column, _)) in self.columns.iter().enumerate() {
+            table.get_column(column)?;
+            if self.columns[..i].iter().any(|(c, _)| c == column) {
+                return Err(Error::Value(format!(
+                    "Column {} is indexed more than once in index {}",
+                    column, self.name
+                )));
+            }
+        }
+        if txn.scan_indexes(&self.table)?.iter().any(|i| i.name == self.name) {
+            return Err(Error::Value(format!(
+                "Index {} already exists on table {}",
+                self.name, self.table
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Display for Index {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let columns: Vec<_> = self
+            .columns
+            .iter()
+            .map(|(column, direction)| match direction {
+                Direction::Ascending => format_ident(column),
+                Direction::Descending => format!("{} DESC", format_ident(column)),
+            })
+            .collect();
+        write!(
+            f,
+            "CREATE INDEX {} ON {} ({})",
+            format_ident(&self.name),
+            format_ident(&self.table),
+            columns.join(", ")
+        )
+    }
+}
+
+/// When a trigger runs, relative to the row write
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum TriggerTiming {
+    Before,
+    After,
+}
+
+impl Display for TriggerTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Before => "BEFORE",
+            Self::After => "AFTER",
+        })
+    }
+}
+
+/// The row operation which fires a trigger
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum TriggerEvent {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl Display for TriggerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Insert => "INSERT",
+            Self::Update => "UPDATE",
+            Self::Delete => "DELETE",
+        })
+    }
+}
+
+/// A stored SQL function, whose body is a SELECT of a single expression over its arguments. Calls
+/// are inlined by the planner, replacing argument references in the body with the call's
+/// argument expressions.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Function {
+    /// Function name
+    pub name: String,
+    /// Argument names and datatypes
+    pub args: Vec<(String, DataType)>,
+    /// The datatype of the return value
+    pub returns: DataType,
+    /// The SQL body, parsed again at every call site
+    pub body: String,
+    /// Other functions called by the body, which can't be dropped while this function exists
+    pub dependencies: Vec<String>,
+}
+
+impl Function {
+    /// Validates the function
+    pub fn validate(&self, txn: &mut dyn Transaction) -> Result<()> {
+        if txn.read_function(&self.name)?.is_some() {
+            return Err(Error::Value(format!("Function {} already exists", self.name)));
+        }
+        for dependency in &self.dependencies {
+            if txn.read_function(dependency)?.is_none() {
+                return Err(Error::Value(format!("Unknown function {}", dependency)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CREATE FUNCTION {}({}) RETURNS {} AS {}",
+            format_ident(&self.name),
+            self.args
+                .iter()
+                .map(|(name, datatype)| format!("{} {}", format_ident(name), datatype))
+                .collect::<Vec<_>>()
+                .join(", "),
+            self.returns,
+            format_value(&Value::String(self.body.clone())),
+        )
+    }
+}
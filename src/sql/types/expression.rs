@@ -1,10 +1,12 @@
-use super::{Row, Value};
+use super::{DataType, Range, Row, Value};
 use crate::error::{Error, Result};
 
 use regex::Regex;
 use serde_derive::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::fmt::{self, Display};
 use std::mem::replace;
+use std::ops::Bound;
 
 /// An expression, made up of constants and operations
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -21,6 +23,7 @@ pub enum Expression {
     // Comparisons operations (GTE, LTE, and NEQ are composite operations)
     Equal(Box<Expression>, Box<Expression>),
     GreaterThan(Box<Expression>, Box<Expression>),
+    IsDistinctFrom(Box<Expression>, Box<Expression>),
     IsNull(Box<Expression>),
     LessThan(Box<Expression>, Box<Expression>),
 
@@ -37,6 +40,9 @@ pub enum Expression {
 
     // String operations
     Like(Box<Expression>, Box<Expression>),
+
+    // Conditional operations
+    Coalesce(Box<Expression>, Box<Expression>),
 }
 
 impl Expression {
@@ -113,6 +119,22 @@ impl Expression {
                     return Err(Error::Value(format!("Can't compare {} and {}", lhs, rhs)))
                 }
             },
+            // Like =, but treats NULLs as equal to each other and distinct from other values,
+            // so it never returns NULL.
+            Self::IsDistinctFrom(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+                (Null, Null) => Boolean(false),
+                (Null, _) | (_, Null) => Boolean(true),
+                (lhs, rhs) => {
+                    match Self::Equal(Self::Constant(lhs).into(), Self::Constant(rhs).into())
+                        .evaluate(None)?
+                    {
+                        Boolean(equal) => Boolean(!equal),
+                        value => {
+                            return Err(Error::Internal(format!("Unexpected value {}", value)))
+                        }
+                    }
+                }
+            },
             Self::IsNull(expr) => match expr.evaluate(row)? {
                 Null => Boolean(true),
                 _ => Boolean(false),
@@ -217,7 +239,9 @@ impl Expression {
                 }
             },
             Self::Negate(expr) => match expr.evaluate(row)? {
-                Integer(i) => Integer(-i),
+                Integer(i) => {
+                    Integer(i.checked_neg().ok_or_else(|| Error::Value("Integer overflow".into()))?)
+                }
                 Float(f) => Float(-f),
                 Null => Null,
                 value => return Err(Error::Value(format!("Can't negate {}", value))),
@@ -256,9 +280,58 @@ impl Expression {
                 (Null, String(_)) => Null,
                 (lhs, rhs) => return Err(Error::Value(format!("Can't LIKE {} and {}", lhs, rhs))),
             },
+
+            // Conditional operations
+            Self::Coalesce(lhs, rhs) => match lhs.evaluate(row)? {
+                Null => rhs.evaluate(row)?,
+                value => value,
+            },
         })
     }
 
+    /// Returns the datatype of the expression's values, given the datatypes of the input row's
+    /// columns, if it can be determined without evaluating it. INTEGER exponentiation is left
+    /// undetermined, since a negative exponent yields FLOAT values.
+    pub fn datatype(&self, inputs: &[Option<DataType>]) -> Option<DataType> {
+        use DataType::*;
+        match self {
+            Self::Constant(value) => value.datatype(),
+            Self::Field(i, _) => inputs.get(*i).cloned().flatten(),
+
+            Self::And(_, _)
+            | Self::Not(_)
+            | Self::Or(_, _)
+            | Self::Equal(_, _)
+            | Self::GreaterThan(_, _)
+            | Self::IsDistinctFrom(_, _)
+            | Self::IsNull(_)
+            | Self::LessThan(_, _)
+            | Self::Like(_, _) => Some(Boolean),
+
+            Self::Exponentiate(lhs, rhs) => match (lhs.datatype(inputs)?, rhs.datatype(inputs)?) {
+                (Integer, Integer) => None,
+                (Integer | Float, Integer | Float) => Some(Float),
+                _ => None,
+            },
+            Self::Add(lhs, rhs)
+            | Self::Divide(lhs, rhs)
+            | Self::Modulo(lhs, rhs)
+            | Self::Multiply(lhs, rhs)
+            | Self::Subtract(lhs, rhs) => match (lhs.datatype(inputs)?, rhs.datatype(inputs)?) {
+                (Integer, Integer) => Some(Integer),
+                (Integer | Float, Integer | Float) => Some(Float),
+                _ => None,
+            },
+            Self::Assert(expr) | Self::Negate(expr) => expr.datatype(inputs),
+            Self::Factorial(_) => Some(Integer),
+
+            Self::Coalesce(lhs, rhs) => match (lhs.datatype(inputs), rhs.datatype(inputs)) {
+                (Some(Integer), Some(Float)) | (Some(Float), Some(Integer)) => Some(Float),
+                (lhs, rhs) => lhs.or(rhs),
+            },
+        }
+    }
+
     /// Walks the expression tree while calling a closure. Returns true as soon as the closure
     /// returns true. This is the inverse of walk().
     pub fn contains<F: Fn(&Expression) -> bool>(&self, visitor: &F) -> bool {
@@ -284,10 +357,12 @@ impl Expression {
         match &mut self {
             Self::Add(lhs, rhs)
             | Self::And(lhs, rhs)
+            | Self::Coalesce(lhs, rhs)
             | Self::Divide(lhs, rhs)
             | Self::Equal(lhs, rhs)
             | Self::Exponentiate(lhs, rhs)
             | Self::GreaterThan(lhs, rhs)
+            | Self::IsDistinctFrom(lhs, rhs)
             | Self::LessThan(lhs, rhs)
             | Self::Like(lhs, rhs)
             | Self::Modulo(lhs, rhs)
@@ -315,10 +390,12 @@ impl Expression {
             && match self {
                 Self::Add(lhs, rhs)
                 | Self::And(lhs, rhs)
+                | Self::Coalesce(lhs, rhs)
                 | Self::Divide(lhs, rhs)
                 | Self::Equal(lhs, rhs)
                 | Self::Exponentiate(lhs, rhs)
                 | Self::GreaterThan(lhs, rhs)
+                | Self::IsDistinctFrom(lhs, rhs)
                 | Self::LessThan(lhs, rhs)
                 | Self::Like(lhs, rhs)
                 | Self::Modulo(lhs, rhs)
@@ -464,21 +541,35 @@ impl Expression {
     }
 
     // Checks if the expression is a field lookup, and returns the list of values looked up.
-    // Expressions must be a combination of =, IS NULL, OR to be converted.
+    // Expressions must be a combination of =, IS NULL, IS NOT DISTINCT FROM, OR to be converted.
+    // Comparing with NULL using = never matches, not even NULL values, so it looks up nothing.
+    // Similarly, NaN is never equal to anything, even though NaN keys may be stored.
     pub fn as_lookup(&self, field: usize) -> Option<Vec<Value>> {
         use Expression::*;
         // FIXME This should use a single match level, but since the child expressions are boxed
         // that would require box patterns, which are unstable.
+        let constant = |lhs: &Expression, rhs: &Expression| match (lhs, rhs) {
+            (Field(i, _), Constant(v)) if i == &field => Some(v.clone()),
+            (Constant(v), Field(i, _)) if i == &field => Some(v.clone()),
+            (_, _) => None,
+        };
         match &self {
-            Equal(lhs, rhs) => match (&**lhs, &**rhs) {
-                (Field(i, _), Constant(v)) if i == &field => Some(vec![v.clone()]),
-                (Constant(v), Field(i, _)) if i == &field => Some(vec![v.clone()]),
-                (_, _) => None,
+            Equal(lhs, rhs) => match constant(lhs, rhs)? {
+                Value::Null => Some(vec![]),
+                Value::Float(f) if f.is_nan() => Some(vec![]),
+                value => Some(vec![value]),
             },
             IsNull(e) => match &**e {
                 Field(i, _) if i == &field => Some(vec![Value::Null]),
                 _ => None,
             },
+            Not(e) => match &**e {
+                IsDistinctFrom(lhs, rhs) => match constant(lhs, rhs)? {
+                    Value::Float(f) if f.is_nan() => Some(vec![]),
+                    value => Some(vec![value]),
+                },
+                _ => None,
+            },
             Or(lhs, rhs) => match (lhs.as_lookup(field), rhs.as_lookup(field)) {
                 (Some(mut lvalues), Some(mut rvalues)) => {
                     lvalues.append(&mut rvalues);
@@ -490,6 +581,74 @@ impl Expression {
         }
     }
 
+    // Checks if the expression is a field range condition, and returns the sorted, non-overlapping
+    // value ranges it matches. Expressions must be a combination of =, >, <, AND, and OR comparing
+    // the field with non-NULL constants of its datatype to be converted, although INTEGER
+    // constants are converted for FLOAT fields. FLOAT ranges are bounded by infinity, to exclude
+    // NaN values.
+    pub fn as_ranges(&self, field: usize, datatype: &DataType) -> Option<Vec<Range>> {
+        use Expression::*;
+        let constant = |expr: &Expression| match expr {
+            Constant(Value::Integer(i)) if datatype == &DataType::Float => {
+                Some(Value::Float(*i as f64))
+            }
+            Constant(Value::Float(f)) if f.is_nan() => None,
+            Constant(v) if v.datatype().as_ref() == Some(datatype) => Some(v.clone()),
+            _ => None,
+        };
+        // Returns the constant compared with the field, and whether the field is on the left.
+        let compare = |lhs: &Expression, rhs: &Expression| match (lhs, rhs) {
+            (Field(i, _), rhs) if i == &field => Some((constant(rhs)?, true)),
+            (lhs, Field(i, _)) if i == &field => Some((constant(lhs)?, false)),
+            _ => None,
+        };
+        let (min, max) = match datatype {
+            DataType::Float => (
+                Bound::Included(Value::Float(f64::NEG_INFINITY)),
+                Bound::Included(Value::Float(f64::INFINITY)),
+            ),
+            _ => (Bound::Unbounded, Bound::Unbounded),
+        };
+        match self {
+            Equal(lhs, rhs) => {
+                let (value, _) = compare(lhs, rhs)?;
+                Some(vec![(Bound::Included(value.clone()), Bound::Included(value))])
+            }
+            GreaterThan(lhs, rhs) => Some(vec![match compare(lhs, rhs)? {
+                (value, true) => (Bound::Excluded(value), max),
+                (value, false) => (min, Bound::Excluded(value)),
+            }]),
+            LessThan(lhs, rhs) => Some(vec![match compare(lhs, rhs)? {
+                (value, true) => (min, Bound::Excluded(value)),
+                (value, false) => (Bound::Excluded(value), max),
+            }]),
+            And(lhs, rhs) => {
+                let rhs = rhs.as_ranges(field, datatype)?;
+                let mut ranges = Vec::new();
+                for l in lhs.as_ranges(field, datatype)? {
+                    for r in &rhs {
+                        let start = match cmp_start(&l.0, &r.0) {
+                            Ordering::Less => r.0.clone(),
+                            _ => l.0.clone(),
+                        };
+                        let end = match cmp_end(&l.1, &r.1) {
+                            Ordering::Greater => r.1.clone(),
+                            _ => l.1.clone(),
+                        };
+                        ranges.push((start, end));
+                    }
+                }
+                Some(normalize_ranges(ranges))
+            }
+            Or(lhs, rhs) => {
+                let mut ranges = lhs.as_ranges(field, datatype)?;
+                ranges.extend(rhs.as_ranges(field, datatype)?);
+                Some(normalize_ranges(ranges))
+            }
+            _ => None,
+        }
+    }
+
     // Creates an expression from a list of field lookup values.
     pub fn from_lookup(
         field: usize,
@@ -517,6 +676,74 @@ impl Expression {
     }
 }
 
+// Compares range values, which must have the same datatype and not be NaN.
+fn cmp_values(a: &Value, b: &Value) -> Ordering {
+    a.partial_cmp(b).unwrap_or(Ordering::Equal)
+}
+
+// Compares range start bounds.
+fn cmp_start(a: &Bound<Value>, b: &Bound<Value>) -> Ordering {
+    match (a, b) {
+        (Bound::Unbounded, Bound::Unbounded) => Ordering::Equal,
+        (Bound::Unbounded, _) => Ordering::Less,
+        (_, Bound::Unbounded) => Ordering::Greater,
+        (Bound::Included(a), Bound::Excluded(b)) => cmp_values(a, b).then(Ordering::Less),
+        (Bound::Excluded(a), Bound::Included(b)) => cmp_values(a, b).then(Ordering::Greater),
+        (Bound::Included(a), Bound::Included(b)) | (Bound::Excluded(a), Bound::Excluded(b)) => {
+            cmp_values(a, b)
+        }
+    }
+}
+
+// Compares range end bounds.
+fn cmp_end(a: &Bound<Value>, b: &Bound<Value>) -> Ordering {
+    match (a, b) {
+        (Bound::Unbounded, Bound::Unbounded) => Ordering::Equal,
+        (Bound::Unbounded, _) => Ordering::Greater,
+        (_, Bound::Unbounded) => Ordering::Less,
+        (Bound::Included(a), Bound::Excluded(b)) => cmp_values(a, b).then(Ordering::Greater),
+        (Bound::Excluded(a), Bound::Included(b)) => cmp_values(a, b).then(Ordering::Less),
+        (Bound::Included(a), Bound::Included(b)) | (Bound::Excluded(a), Bound::Excluded(b)) => {
+            cmp_values(a, b)
+        }
+    }
+}
+
+// Checks whether a range end lies before a range start, such that they don't touch.
+fn is_before(end: &Bound<Value>, start: &Bound<Value>) -> bool {
+    match (end, start) {
+        (Bound::Unbounded, _) | (_, Bound::Unbounded) => false,
+        (Bound::Excluded(e), Bound::Excluded(s)) => cmp_values(e, s) != Ordering::Greater,
+        (Bound::Included(e), Bound::Included(s))
+        | (Bound::Included(e), Bound::Excluded(s))
+        | (Bound::Excluded(e), Bound::Included(s)) => cmp_values(e, s) == Ordering::Less,
+    }
+}
+
+// Sorts ranges and merges overlapping or adjacent ones, removing empty ranges.
+fn normalize_ranges(mut ranges: Vec<Range>) -> Vec<Range> {
+    ranges.retain(|(start, end)| match (start, end) {
+        (Bound::Included(s), Bound::Included(e)) => cmp_values(s, e) != Ordering::Greater,
+        (Bound::Included(s), Bound::Excluded(e))
+        | (Bound::Excluded(s), Bound::Included(e))
+        | (Bound::Excluded(s), Bound::Excluded(e)) => cmp_values(s, e) == Ordering::Less,
+        _ => true,
+    });
+    ranges.sort_by(|a, b| cmp_start(&a.0, &b.0));
+    let mut normalized: Vec<Range> = Vec::new();
+    for (start, end) in ranges {
+        match normalized.last_mut() {
+            Some(last) if !is_before(&last.1, &start) => {
+                if cmp_end(&end, &last.1) == Ordering::Greater {
+                    last.1 = end
+                }
+            }
+            _ => normalized.push((start, end)),
+        }
+    }
+    normalized
+}
+
 impl Display for Expression {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = match self {
@@ -531,6 +758,7 @@ impl Display for Expression {
 
             Self::Equal(lhs, rhs) => format!("{} = {}", lhs, rhs),
             Self::GreaterThan(lhs, rhs) => format!("{} > {}", lhs, rhs),
+            Self::IsDistinctFrom(lhs, rhs) => format!("{} IS DISTINCT FROM {}", lhs, rhs),
             Self::LessThan(lhs, rhs) => format!("{} < {}", lhs, rhs),
             Self::IsNull(expr) => format!("{} IS NULL", expr),
 
@@ -545,6 +773,8 @@ impl Display for Expression {
             Self::Subtract(lhs, rhs) => format!("{} - {}", lhs, rhs),
 
             Self::Like(lhs, rhs) => format!("{} LIKE {}", lhs, rhs),
+
+            Self::Coalesce(lhs, rhs) => format!("COALESCE({}, {})", lhs, rhs),
         };
         write!(f, "{}", s)
     }
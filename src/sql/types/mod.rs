@@ -7,6 +7,7 @@ use serde_derive::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::hash::{Hash, Hasher};
+use std::ops::Bound;
 
 /// A datatype
 #[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize)]
@@ -45,7 +46,7 @@ impl Hash for Value {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.datatype().hash(state);
         match self {
-            Value::Null => self.hash(state),
+            Value::Null => {}
             Value::Boolean(v) => v.hash(state),
             Value::Integer(v) => v.hash(state),
             Value::Float(v) => v.to_be_bytes().hash(state),
@@ -109,6 +110,20 @@ impl Value {
             v => Err(Error::Value(format!("Not a string: {:?}", v))),
         }
     }
+
+    /// Compares values for sorting. This is like partial_cmp(), except NaN is equal to itself
+    /// and greater than any other number, as in the key encoding. Returns None for values of
+    /// different datatypes.
+    pub fn sort_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Float(a), Self::Float(b)) if a.is_nan() || b.is_nan() => {
+                Some(a.is_nan().cmp(&b.is_nan()))
+            }
+            (Self::Float(a), Self::Integer(_)) if a.is_nan() => Some(Ordering::Greater),
+            (Self::Integer(_), Self::Float(b)) if b.is_nan() => Some(Ordering::Less),
+            (a, b) => a.partial_cmp(b),
+        }
+    }
 }
 
 impl std::fmt::Display for Value {
@@ -177,6 +192,9 @@ impl From<&str> for Value {
 /// A row of values
 pub type Row = Vec<Value>;
 
+/// A range of values. Unbounded ranges never include NULL values.
+pub type Range = (Bound<Value>, Bound<Value>);
+
 /// A row iterator
 pub type Rows = Box<dyn Iterator<Item = Result<Row>> + Send>;
 
@@ -188,3 +206,58 @@ pub struct Column {
 
 /// A set of columns
 pub type Columns = Vec<Column>;
+
+/// A table sampling method, see Sample.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SampleMethod {
+    /// Samples blocks of rows, skipping the others without reading them. Cheaper, but rows
+    /// stored close together are sampled together.
+    System,
+    /// Samples each row independently. Reads all rows, but gives a uniform sample.
+    Bernoulli,
+}
+
+impl std::fmt::Display for SampleMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::System => "SYSTEM",
+            Self::Bernoulli => "BERNOULLI",
+        })
+    }
+}
+
+/// A random sample of a table's rows, i.e. TABLESAMPLE.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub method: SampleMethod,
+    /// The percentage of rows (or blocks) to sample, from 0 to 100.
+    pub percent: f64,
+    /// The random seed, for repeatable samples. Otherwise, a random seed is used.
+    pub seed: Option<u64>,
+}
+
+impl std::fmt::Display for Sample {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}%", self.method, self.percent)?;
+        if let Some(seed) = self.seed {
+            write!(f, " seed {}", seed)?;
+        }
+        Ok(())
+    }
+}
+
+impl Sample {
+    /// Returns the probability of sampling a row or block, from 0.0 to 1.0.
+    pub fn probability(&self) -> f64 {
+        (self.percent / 100.0).clamp(0.0, 1.0)
+    }
+
+    /// Returns a random number generator for the sample, seeded if requested.
+    pub fn rng(&self) -> rand::rngs::StdRng {
+        use rand::SeedableRng as _;
+        match self.seed {
+            Some(seed) => rand::rngs::StdRng::seed_from_u64(seed),
+            None => rand::rngs::StdRng::from_entropy(),
+        }
+    }
+}
@@ -5,6 +5,7 @@ use std::collections::HashSet;
 use super::bincode;
 use super::engine::{self, ScanIterator, Status};
 use super::mvcc::{self, TransactionState};
+use crate::clock::Timestamp;
 use crate::error::Result;
 
 /// Formats a raw byte string, either as a UTF-8 string (if valid and
@@ -85,6 +86,24 @@ pub fn format_key_value(key: &[u8], value: &Option<Vec<u8>>) -> (String, Option<
             mvcc::Key::Unversioned(userkey) => {
                 fkey = format!("Unversioned({})", format_raw(&userkey));
             }
+            mvcc::Key::TxnSavepoint(_) => {}
+            mvcc::Key::TxnUndo(version, userkey) => {
+                fkey = format!("TxnUndo({}, {})", version, format_raw(&userkey));
+                if let Some(ref v) = value {
+                    match bincode::deserialize::<Option<Vec<u8>>>(v) {
+                        Ok(Some(v)) => fvalue = Some(format_raw(&v)),
+                        Ok(None) => fvalue = Some(String::from("None")),
+                        Err(_) => {}
+                    }
+                }
+            }
+            mvcc::Key::TxnCommitTimestamp(_) | mvcc::Key::LastCommitTimestamp => {
+                if let Some(ref v) = value {
+                    if let Ok(timestamp) = bincode::deserialize::<Timestamp>(v) {
+                        fvalue = Some(format!("{}", timestamp))
+                    }
+                }
+            }
         }
     }
 
@@ -119,7 +138,11 @@ impl<E: engine::Engine> Engine<E> {
 }
 
 impl<E: engine::Engine> engine::Engine for Engine<E> {
-    type ScanIterator<'a> = E::ScanIterator<'a> where E: 'a;
+    type ScanIterator<'a>
+        = E::ScanIterator<'a>
+    where
+        E: 'a;
+    type Snapshot = E::Snapshot;
 
     fn flush(&mut self) -> Result<()> {
         self.inner.flush()
@@ -152,6 +175,10 @@ impl<E: engine::Engine> engine::Engine for Engine<E> {
         Ok(())
     }
 
+    fn snapshot(&mut self) -> Result<Self::Snapshot> {
+        self.inner.snapshot()
+    }
+
     fn status(&mut self) -> Result<Status> {
         self.inner.status()
     }
@@ -1,9 +1,12 @@
 use super::{Engine, Status};
-use crate::error::Result;
+use crate::error::{Error, Result};
 
 use fs4::FileExt;
+use std::collections::VecDeque;
+use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 /// A very simple variant of BitCask, itself a very simple log-structured
 /// key-value engine used e.g. by the Riak database. It is not compatible with
@@ -33,10 +36,20 @@ use std::path::PathBuf;
 ///   expected to be small, so the hint files would be nearly as large as
 ///   the compacted log files themselves.
 ///
-/// - Log entries don't contain timestamps or checksums.
+/// - Log entries don't contain timestamps.
+///
+/// Values are read from the log file with positioned reads, or optionally via
+/// a read-only memory map of the file (see with_mmap()). Scans can optionally
+/// read values in batches via io_uring on Linux (see with_io_uring()).
+/// Snapshots share the keydir and file with the engine. The keydir is a
+/// persistent B-tree, so a snapshot is an O(1) clone and later writes only copy
+/// the O(log n) nodes along their path, and the append-only file only grows, so
+/// the snapshot's value positions remain valid. Compaction replaces the file, but
+/// snapshots keep the old one open.
 ///
 /// The structure of a log entry is:
 ///
+/// - CRC32 checksum of the rest of the entry as big-endian u32.
 /// - Key length as big-endian u32.
 /// - Value length as big-endian i32, or -1 for tombstones.
 /// - Key as raw bytes (max 2 GB).
@@ -44,12 +57,19 @@ use std::path::PathBuf;
 pub struct BitCask {
     /// The active append-only log file.
     log: Log,
-    /// Maps keys to a value position and length in the log file.
+    /// Maps keys to a value position and length in the log file. Shared with
+    /// snapshots via structural sharing.
     keydir: KeyDir,
 }
 
+/// The size of a log entry header: the checksum, key length, and value length.
+const ENTRY_HEADER_SIZE: u64 = 4 + 4 + 4;
+
+/// The maximum number of values a scan reads in a single io_uring batch.
+const URING_BATCH_SIZE: usize = 64;
+
 /// Maps keys to a value position and length in the log file.
-type KeyDir = std::collections::BTreeMap<Vec<u8>, (u64, u32)>;
+type KeyDir = im::OrdMap<Vec<u8>, (u64, u32)>;
 
 impl BitCask {
     /// Opens or creates a BitCask database in the given file.
@@ -84,6 +104,35 @@ impl BitCask {
 
         Ok(s)
     }
+
+    /// Enables or disables memory-mapped reads, such that reading a value is a
+    /// memory copy rather than a read syscall. This is only supported on Unix.
+    ///
+    /// The map covers the log file as of when it was mapped, and is remapped
+    /// as the file grows, reading values beyond it from the file in the
+    /// meanwhile. The file must not be truncated or modified by others while
+    /// mapped, which the exclusive file lock guards against.
+    pub fn with_mmap(mut self, mmap: bool) -> Result<Self> {
+        self.log.set_mmap(mmap)?;
+        Ok(self)
+    }
+
+    /// Enables or disables batched io_uring reads for scans, which submit the
+    /// reads of up to URING_BATCH_SIZE values at once rather than one read
+    /// syscall per value. Values within the memory map, if enabled, are still
+    /// read from it. This is only supported on Linux, with the io-uring crate
+    /// feature, and errors if the kernel doesn't support io_uring.
+    pub fn with_io_uring(mut self, io_uring: bool) -> Result<Self> {
+        self.log.uring = if io_uring { Some(Arc::new(Uring::new()?)) } else { None };
+        Ok(self)
+    }
+
+    /// Verifies the checksums of a BitCask database file without opening or
+    /// modifying it, returning a description of each problem found. Errors if
+    /// the database is open.
+    pub fn verify(path: &Path) -> Result<Vec<String>> {
+        Log::verify(path)
+    }
 }
 
 impl std::fmt::Display for BitCask {
@@ -94,6 +143,7 @@ impl std::fmt::Display for BitCask {
 
 impl Engine for BitCask {
     type ScanIterator<'a> = ScanIterator<'a>;
+    type Snapshot = Snapshot;
 
     fn delete(&mut self, key: &[u8]) -> Result<()> {
         self.log.write_entry(key, None)?;
@@ -114,7 +164,12 @@ impl Engine for BitCask {
     }
 
     fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
-        ScanIterator { inner: self.keydir.range(range), log: &mut self.log }
+        ScanIterator::new(
+            self.keydir.range(range),
+            &self.log.file,
+            self.log.mmap.as_deref(),
+            self.log.uring.as_deref(),
+        )
     }
 
     fn scan_dyn(
@@ -127,10 +182,20 @@ impl Engine for BitCask {
     fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
         let (pos, len) = self.log.write_entry(key, Some(&*value))?;
         let value_len = value.len() as u32;
-        self.keydir.insert(key.to_vec(), (pos + len as u64 - value_len as u64, value_len));
+        let value_pos = pos + len as u64 - value_len as u64;
+        self.keydir.insert(key.to_vec(), (value_pos, value_len));
         Ok(())
     }
 
+    fn snapshot(&mut self) -> Result<Self::Snapshot> {
+        Ok(Snapshot {
+            keydir: self.keydir.clone(),
+            file: self.log.file.clone(),
+            mmap: self.log.mmap.clone(),
+            uring: self.log.uring.clone(),
+        })
+    }
+
     fn status(&mut self) -> Result<Status> {
         let keys = self.keydir.len() as u64;
         let size = self
@@ -138,7 +203,7 @@ impl Engine for BitCask {
             .iter()
             .fold(0, |size, (key, (_, value_len))| size + key.len() as u64 + *value_len as u64);
         let total_disk_size = self.log.file.metadata()?.len();
-        let live_disk_size = size + 8 * keys; // account for length prefixes
+        let live_disk_size = size + ENTRY_HEADER_SIZE * keys; // account for entry headers
         let garbage_disk_size = total_disk_size - live_disk_size;
         Ok(Status {
             name: self.to_string(),
@@ -151,15 +216,93 @@ impl Engine for BitCask {
     }
 }
 
+/// A BitCask snapshot.
+pub struct Snapshot {
+    keydir: KeyDir,
+    file: Arc<File>,
+    mmap: Option<Arc<Mmap>>,
+    uring: Option<Arc<Uring>>,
+}
+
+impl super::Snapshot for Snapshot {
+    type ScanIterator<'a> = ScanIterator<'a>;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some((value_pos, value_len)) = self.keydir.get(key) {
+            Ok(Some(Log::read_value_at(&self.file, self.mmap.as_deref(), *value_pos, *value_len)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn scan(&self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
+        ScanIterator::new(
+            self.keydir.range(range),
+            &self.file,
+            self.mmap.as_deref(),
+            self.uring.as_deref(),
+        )
+    }
+}
+
 pub struct ScanIterator<'a> {
-    inner: std::collections::btree_map::Range<'a, Vec<u8>, (u64, u32)>,
-    log: &'a mut Log,
+    inner: im::ordmap::Iter<'a, Vec<u8>, (u64, u32)>,
+    file: &'a File,
+    mmap: Option<&'a Mmap>,
+    /// The io_uring pool to read values in batches with, if enabled.
+    uring: Option<&'a Uring>,
+    /// Items read ahead from the front and back of the range via io_uring, in
+    /// key order.
+    front: VecDeque<Result<(Vec<u8>, Vec<u8>)>>,
+    back: VecDeque<Result<(Vec<u8>, Vec<u8>)>>,
 }
 
 impl<'a> ScanIterator<'a> {
+    fn new(
+        inner: im::ordmap::Iter<'a, Vec<u8>, (u64, u32)>,
+        file: &'a File,
+        mmap: Option<&'a Mmap>,
+        uring: Option<&'a Uring>,
+    ) -> Self {
+        Self { inner, file, mmap, uring, front: VecDeque::new(), back: VecDeque::new() }
+    }
+
     fn map(&mut self, item: (&Vec<u8>, &(u64, u32))) -> <Self as Iterator>::Item {
         let (key, (value_pos, value_len)) = item;
-        Ok((key.clone(), self.log.read_value(*value_pos, *value_len)?))
+        Ok((key.clone(), Log::read_value_at(self.file, self.mmap, *value_pos, *value_len)?))
+    }
+
+    /// Reads the values of a batch of keydir entries, submitting the reads of
+    /// values outside of the memory map to io_uring at once. Returns the items
+    /// in the given order, or a single error.
+    fn read_batch(
+        &mut self,
+        uring: &Uring,
+        batch: Vec<(&Vec<u8>, &(u64, u32))>,
+    ) -> VecDeque<Result<(Vec<u8>, Vec<u8>)>> {
+        let mapped = self.mmap.map_or(0, |mmap| mmap.len() as u64);
+        let reads: Vec<(u64, u32)> = batch
+            .iter()
+            .map(|(_, (pos, len))| (*pos, *len))
+            .filter(|(pos, len)| pos + *len as u64 > mapped)
+            .collect();
+        if reads.len() <= 1 {
+            return batch.into_iter().map(|item| self.map(item)).collect();
+        }
+        let mut values = match uring.read(self.file, &reads) {
+            Ok(values) => values.into_iter(),
+            Err(error) => return VecDeque::from([Err(error)]),
+        };
+        batch
+            .into_iter()
+            .map(|(key, (pos, len))| match self.mmap {
+                Some(mmap) if pos + *len as u64 <= mapped => Ok((
+                    key.clone(),
+                    mmap.as_slice()[*pos as usize..(pos + *len as u64) as usize].to_vec(),
+                )),
+                _ => Ok((key.clone(), values.next().expect("missing io_uring value"))),
+            })
+            .collect()
     }
 }
 
@@ -167,29 +310,69 @@ impl<'a> Iterator for ScanIterator<'a> {
     type Item = Result<(Vec<u8>, Vec<u8>)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|item| self.map(item))
+        let Some(uring) = self.uring else {
+            return self.inner.next().map(|item| self.map(item));
+        };
+        if self.front.is_empty() {
+            let batch = self.inner.by_ref().take(URING_BATCH_SIZE).collect();
+            self.front = self.read_batch(uring, batch);
+        }
+        self.front.pop_front().or_else(|| self.back.pop_front())
     }
 }
 
 impl<'a> DoubleEndedIterator for ScanIterator<'a> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        self.inner.next_back().map(|item| self.map(item))
+        let Some(uring) = self.uring else {
+            return self.inner.next_back().map(|item| self.map(item));
+        };
+        if self.back.is_empty() {
+            let mut batch: Vec<_> = self.inner.by_ref().rev().take(URING_BATCH_SIZE).collect();
+            batch.reverse();
+            self.back = self.read_batch(uring, batch);
+        }
+        self.back.pop_back().or_else(|| self.front.pop_back())
     }
 }
 
 impl BitCask {
     /// Compacts the current log file by writing out a new log file containing
     /// only live keys and replacing the current file with it.
+    ///
+    /// Snapshots may still hold the current log file open, and Windows can't
+    /// rename a file over one that is open. The current file is therefore
+    /// first renamed aside, which Windows allows since Rust opens files with
+    /// FILE_SHARE_DELETE, then the new one is renamed into place and the old
+    /// one is removed. Open snapshots keep reading the old file, which the OS
+    /// reclaims once they're dropped. If the new file can't be renamed into
+    /// place, the old one is moved back and the database is left unchanged.
+    /// If enabled, the new file is mapped once it's fully written.
     pub fn compact(&mut self) -> Result<()> {
         let mut tmp_path = self.log.path.clone();
         tmp_path.set_extension("new");
         let (mut new_log, new_keydir) = self.write_log(tmp_path)?;
+        new_log.set_mmap(self.log.mmap_enabled)?;
+        new_log.uring = self.log.uring.clone();
+
+        // Remove any old file left behind by an earlier compaction.
+        let mut old_path = self.log.path.clone();
+        old_path.set_extension("old");
+        match std::fs::remove_file(&old_path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
 
-        std::fs::rename(&new_log.path, &self.log.path)?;
+        std::fs::rename(&self.log.path, &old_path)?;
+        if let Err(err) = std::fs::rename(&new_log.path, &self.log.path) {
+            std::fs::rename(&old_path, &self.log.path)?;
+            return Err(err.into());
+        }
         new_log.path = self.log.path.clone();
-
-        self.log = new_log;
+        self.log = new_log; // closes the old file, unless snapshots hold it
         self.keydir = new_keydir;
+        if let Err(err) = std::fs::remove_file(&old_path) {
+            log::warn!("Failed to remove old log file {}: {}", old_path.display(), err);
+        }
         Ok(())
     }
 
@@ -220,6 +403,7 @@ impl Drop for BitCask {
 /// A BitCask append-only log file, containing a sequence of key/value
 /// entries encoded as follows;
 ///
+/// - CRC32 checksum of the rest of the entry as big-endian u32.
 /// - Key length as big-endian u32.
 /// - Value length as big-endian i32, or -1 for tombstones.
 /// - Key as raw bytes (max 2 GB).
@@ -227,71 +411,66 @@ impl Drop for BitCask {
 struct Log {
     /// Path to the log file.
     path: PathBuf,
-    /// The opened file containing the log.
-    file: std::fs::File,
+    /// The opened file containing the log. Shared with snapshots.
+    file: Arc<File>,
+    /// Whether to read values via a memory map of the file.
+    mmap_enabled: bool,
+    /// A memory map of the start of the file, if enabled and non-empty. Shared
+    /// with snapshots.
+    mmap: Option<Arc<Mmap>>,
+    /// The io_uring pool for batched scan reads, if enabled. Shared with
+    /// snapshots.
+    uring: Option<Arc<Uring>>,
 }
 
 impl Log {
     /// Opens a log file, or creates one if it does not exist. Takes out an
     /// exclusive lock on the file until it is closed, or errors if the lock is
-    /// already held.
+    /// already held. On Windows the lock is mandatory, so other processes can't
+    /// read the file either while it's open.
     fn new(path: PathBuf) -> Result<Self> {
         if let Some(dir) = path.parent() {
             std::fs::create_dir_all(dir)?
         }
         let file = std::fs::OpenOptions::new().read(true).write(true).create(true).open(&path)?;
         file.try_lock_exclusive()?;
-        Ok(Self { path, file })
+        Ok(Self { path, file: Arc::new(file), mmap_enabled: false, mmap: None, uring: None })
     }
 
     /// Builds a keydir by scanning the log file. If an incomplete entry is
     /// encountered, it is assumed to be caused by an incomplete write operation
-    /// and the remainder of the file is truncated.
+    /// and the remainder of the file is truncated. This also applies to a
+    /// checksum mismatch in the last entry, but a checksum mismatch anywhere
+    /// else means the file is corrupt, and returns an error.
     fn build_keydir(&mut self) -> Result<KeyDir> {
-        let mut len_buf = [0u8; 4];
         let mut keydir = KeyDir::new();
         let file_len = self.file.metadata()?.len();
-        let mut r = BufReader::new(&mut self.file);
+        let mut r = BufReader::new(&*self.file);
         let mut pos = r.seek(SeekFrom::Start(0))?;
 
         while pos < file_len {
-            // Read the next entry from the file, returning the key, value
-            // position, and value length or None for tombstones.
-            let result = || -> std::result::Result<(Vec<u8>, u64, Option<u32>), std::io::Error> {
-                r.read_exact(&mut len_buf)?;
-                let key_len = u32::from_be_bytes(len_buf);
-                r.read_exact(&mut len_buf)?;
-                let value_len_or_tombstone = match i32::from_be_bytes(len_buf) {
-                    l if l >= 0 => Some(l as u32),
-                    _ => None, // -1 for tombstones
-                };
-                let value_pos = pos + 4 + 4 + key_len as u64;
-
-                let mut key = vec![0; key_len as usize];
-                r.read_exact(&mut key)?;
-
-                if let Some(value_len) = value_len_or_tombstone {
-                    if value_pos + value_len as u64 > file_len {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::UnexpectedEof,
-                            "value extends beyond end of file",
-                        ));
+            match Self::read_entry(&mut r, pos, file_len) {
+                Ok((key, value, valid)) => {
+                    let value_pos = pos + ENTRY_HEADER_SIZE + key.len() as u64;
+                    let end = value_pos + value.as_ref().map_or(0, |v| v.len() as u64);
+                    if !valid && end < file_len {
+                        return Err(Error::Internal(format!(
+                            "Checksum mismatch for entry at offset {} in {}",
+                            pos,
+                            self.path.display()
+                        )));
+                    } else if !valid {
+                        log::error!("Found corrupt last entry at offset {}, truncating file", pos);
+                        self.file.set_len(pos)?;
+                        break;
                     }
-                    r.seek_relative(value_len as i64)?; // avoids discarding buffer
-                }
-
-                Ok((key, value_pos, value_len_or_tombstone))
-            }();
-
-            match result {
-                // Populate the keydir with the entry, or remove it on tombstones.
-                Ok((key, value_pos, Some(value_len))) => {
-                    keydir.insert(key, (value_pos, value_len));
-                    pos = value_pos + value_len as u64;
-                }
-                Ok((key, value_pos, None)) => {
-                    keydir.remove(&key);
-                    pos = value_pos;
+                    // Populate the keydir with the entry, or remove it on tombstones.
+                    if let Some(value) = value {
+                        keydir.insert(key, (value_pos, value.len() as u32));
+                    } else {
+                        keydir.remove(&key);
+                    }
+                    pos = end;
                 }
                 // If an incomplete entry was found at the end of the file, assume an
                 // incomplete write and truncate the file.
@@ -307,24 +486,154 @@ impl Log {
         Ok(keydir)
     }
 
+    /// Verifies the checksums of all entries in a log file without modifying
+    /// it, and returns a description of each corrupt or incomplete entry. Takes
+    /// out a shared lock on the file, so it errors if the database is open.
+    fn verify(path: &Path) -> Result<Vec<String>> {
+        let file = std::fs::File::open(path)?;
+        FileExt::try_lock_shared(&file).map_err(|err| {
+            Error::Internal(format!("Can't lock {}, is it in use? {}", path.display(), err))
+        })?;
+        let file_len = file.metadata()?.len();
+        let mut r = BufReader::new(file);
+        let mut pos = 0;
+        let mut errors = Vec::new();
+
+        while pos < file_len {
+            match Self::read_entry(&mut r, pos, file_len) {
+                Ok((key, value, valid)) => {
+                    if !valid {
+                        errors.push(format!("checksum mismatch for entry at offset {}", pos));
+                    }
+                    pos += ENTRY_HEADER_SIZE
+                        + key.len() as u64
+                        + value.as_ref().map_or(0, |v| v.len() as u64);
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    errors.push(format!("incomplete or corrupt entry at offset {}", pos));
+                    break;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(errors)
+    }
+
+    /// Reads the entry at the given position from a reader positioned at it,
+    /// returning the key, the value or None for tombstones, and whether the
+    /// checksum matched. Errors with UnexpectedEof if the entry is incomplete.
+    fn read_entry(
+        r: &mut impl Read,
+        pos: u64,
+        file_len: u64,
+    ) -> std::io::Result<(Vec<u8>, Option<Vec<u8>>, bool)> {
+        let mut header = [0u8; ENTRY_HEADER_SIZE as usize];
+        r.read_exact(&mut header)?;
+        let checksum = u32::from_be_bytes(header[0..4].try_into().unwrap());
+        let key_len = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        let value_len_or_tombstone = i32::from_be_bytes(header[8..12].try_into().unwrap());
+        let value_len = value_len_or_tombstone.max(0) as u32; // -1 for tombstones
+
+        // Check the entry length before allocating buffers, in case it's corrupt.
+        if pos + ENTRY_HEADER_SIZE + key_len as u64 + value_len as u64 > file_len {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "entry extends beyond end of file",
+            ));
+        }
+        let mut key = vec![0; key_len as usize];
+        r.read_exact(&mut key)?;
+        let mut value = vec![0; value_len as usize];
+        r.read_exact(&mut value)?;
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&header[4..]);
+        hasher.update(&key);
+        hasher.update(&value);
+        let valid = hasher.finalize() == checksum;
+        Ok((key, (value_len_or_tombstone >= 0).then_some(value), valid))
+    }
+
     /// Reads a value from the log file.
-    fn read_value(&mut self, value_pos: u64, value_len: u32) -> Result<Vec<u8>> {
+    fn read_value(&self, value_pos: u64, value_len: u32) -> Result<Vec<u8>> {
+        Self::read_value_at(&self.file, self.mmap.as_deref(), value_pos, value_len)
+    }
+
+    /// Reads a value from a log file, via the given memory map if the value is
+    /// within it, or otherwise a positioned read (pread) rather than a seek and
+    /// read, so it only needs a shared reference and concurrent readers don't
+    /// contend on the file cursor.
+    fn read_value_at(
+        file: &File,
+        mmap: Option<&Mmap>,
+        value_pos: u64,
+        value_len: u32,
+    ) -> Result<Vec<u8>> {
+        let end = value_pos + value_len as u64;
+        if let Some(mmap) = mmap.filter(|mmap| end <= mmap.len() as u64) {
+            return Ok(mmap.as_slice()[value_pos as usize..end as usize].to_vec());
+        }
         let mut value = vec![0; value_len as usize];
-        self.file.seek(SeekFrom::Start(value_pos))?;
-        self.file.read_exact(&mut value)?;
+        Self::read_exact_at(file, &mut value, value_pos)?;
         Ok(value)
     }
 
+    /// Enables or disables the memory map, (re)mapping the entire file.
+    fn set_mmap(&mut self, enabled: bool) -> Result<()> {
+        self.mmap_enabled = enabled;
+        self.mmap = None; // unmap before remapping, unless used by snapshots
+        if enabled {
+            self.mmap = Mmap::new(&self.file, self.file.metadata()?.len())?.map(Arc::new);
+        }
+        Ok(())
+    }
+
+    /// Fills the buffer from the file at the given position.
+    #[cfg(unix)]
+    fn read_exact_at(file: &std::fs::File, buf: &mut [u8], pos: u64) -> std::io::Result<()> {
+        std::os::unix::fs::FileExt::read_exact_at(file, buf, pos)
+    }
+
+    /// Fills the buffer from the file at the given position. Windows has no
+    /// read_exact_at(), and seek_read() also moves the file cursor, but writes
+    /// always seek to the end first so that's fine.
+    #[cfg(windows)]
+    fn read_exact_at(
+        file: &std::fs::File,
+        mut buf: &mut [u8],
+        mut pos: u64,
+    ) -> std::io::Result<()> {
+        while !buf.is_empty() {
+            match std::os::windows::fs::FileExt::seek_read(file, buf, pos) {
+                Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    pos += n as u64;
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
     /// Appends a key/value entry to the log file, using a None value for
     /// tombstones. It returns the position and length of the entry.
     fn write_entry(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<(u64, u32)> {
         let key_len = key.len() as u32;
         let value_len = value.map_or(0, |v| v.len() as u32);
         let value_len_or_tombstone = value.map_or(-1, |v| v.len() as i32);
-        let len = 4 + 4 + key_len + value_len;
+        let len = ENTRY_HEADER_SIZE as u32 + key_len + value_len;
 
-        let pos = self.file.seek(SeekFrom::End(0))?;
-        let mut w = BufWriter::with_capacity(len as usize, &mut self.file);
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&key_len.to_be_bytes());
+        hasher.update(&value_len_or_tombstone.to_be_bytes());
+        hasher.update(key);
+        hasher.update(value.unwrap_or_default());
+
+        let pos = (&*self.file).seek(SeekFrom::End(0))?;
+        let mut w = BufWriter::with_capacity(len as usize, &*self.file);
+        w.write_all(&hasher.finalize().to_be_bytes())?;
         w.write_all(&key_len.to_be_bytes())?;
         w.write_all(&value_len_or_tombstone.to_be_bytes())?;
         w.write_all(key)?;
@@ -332,6 +641,14 @@ impl Log {
             w.write_all(value)?;
         }
         w.flush()?;
+        drop(w);
+
+        // Remap the file once it has doubled in size since it was mapped, such
+        // that the cost of remapping is amortized across writes.
+        let mapped = self.mmap.as_ref().map_or(0, |mmap| mmap.len() as u64);
+        if self.mmap_enabled && pos + len as u64 > 2 * mapped {
+            self.set_mmap(true)?;
+        }
 
         Ok((pos, len))
     }
@@ -341,13 +658,16 @@ impl Log {
     fn print<W: Write>(&mut self, w: &mut W) -> Result<()> {
         let mut len_buf = [0u8; 4];
         let file_len = self.file.metadata()?.len();
-        let mut r = BufReader::new(&mut self.file);
+        let mut r = BufReader::new(&*self.file);
         let mut pos = r.seek(SeekFrom::Start(0))?;
         let mut idx = 0;
 
         while pos < file_len {
             writeln!(w, "entry = {}, offset {}", idx, pos)?;
 
+            r.read_exact(&mut len_buf)?;
+            writeln!(w, "crc   = {} {:x?}", u32::from_be_bytes(len_buf), len_buf)?;
+
             r.read_exact(&mut len_buf)?;
             let key_len = u32::from_be_bytes(len_buf);
             writeln!(w, "klen  = {} {:x?}", key_len, len_buf)?;
@@ -377,13 +697,174 @@ impl Log {
             }
             write!(w, "{:x?}\n\n", value)?;
 
-            pos += 4 + 4 + key_len as u64 + value_len as u64;
+            pos += ENTRY_HEADER_SIZE + key_len as u64 + value_len as u64;
             idx += 1;
         }
         Ok(())
     }
 }
 
+/// A read-only memory map of a file.
+struct Mmap {
+    ptr: *const u8,
+    len: usize,
+}
+
+// The map is read-only, so it can be shared across threads.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Maps the first len bytes of the file, or returns None if len is 0.
+    #[cfg(unix)]
+    fn new(file: &std::fs::File, len: u64) -> Result<Option<Self>> {
+        use std::os::unix::io::AsRawFd as _;
+        if len == 0 {
+            return Ok(None);
+        }
+        let len = usize::try_from(len)?;
+        // SAFETY: maps a read-only view of the file, which is only read within
+        // len. The caller must ensure the file isn't truncated while mapped.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Some(Self { ptr: ptr as *const u8, len }))
+    }
+
+    /// Memory maps aren't supported on this platform.
+    #[cfg(not(unix))]
+    fn new(_: &std::fs::File, _: u64) -> Result<Option<Self>> {
+        Err(Error::Config("Memory-mapped reads are only supported on Unix".into()))
+    }
+
+    /// Returns the length of the map.
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the mapped bytes.
+    fn as_slice(&self) -> &[u8] {
+        // SAFETY: the map is valid for len bytes until dropped.
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    #[cfg(unix)]
+    fn drop(&mut self) {
+        // SAFETY: the map was created by mmap() with this length, and isn't
+        // referenced after this.
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+
+    #[cfg(not(unix))]
+    fn drop(&mut self) {}
+}
+
+/// A pool of io_uring instances, for batched value reads. Each batch takes an
+/// instance from the pool and returns it once done, such that concurrent scans
+/// don't contend on a single ring, and rings are only set up once.
+struct Uring {
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    rings: std::sync::Mutex<Vec<io_uring::IoUring>>,
+}
+
+impl Uring {
+    /// Creates a new pool, setting up an initial ring to check that the kernel
+    /// supports io_uring.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    fn new() -> Result<Self> {
+        let ring = io_uring::IoUring::new(URING_BATCH_SIZE as u32)?;
+        Ok(Self { rings: std::sync::Mutex::new(vec![ring]) })
+    }
+
+    /// io_uring isn't supported on this platform or without the feature.
+    #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+    fn new() -> Result<Self> {
+        Err(Error::Config("io_uring reads require Linux and the io-uring feature".into()))
+    }
+
+    /// Reads the values at the given positions and lengths in the file, as a
+    /// single batch of at most URING_BATCH_SIZE reads.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    fn read(&self, file: &File, reads: &[(u64, u32)]) -> Result<Vec<Vec<u8>>> {
+        use io_uring::{opcode, types};
+        use std::os::unix::io::AsRawFd as _;
+
+        let mut ring = match self.rings.lock()?.pop() {
+            Some(ring) => ring,
+            None => io_uring::IoUring::new(URING_BATCH_SIZE as u32)?,
+        };
+        let mut values: Vec<Vec<u8>> =
+            reads.iter().map(|(_, len)| vec![0; *len as usize]).collect();
+        for (i, ((pos, _), value)) in reads.iter().zip(values.iter_mut()).enumerate() {
+            let read = opcode::Read::new(
+                types::Fd(file.as_raw_fd()),
+                value.as_mut_ptr(),
+                value.len() as u32,
+            )
+            .offset(*pos)
+            .build()
+            .user_data(i as u64);
+            // SAFETY: the buffer isn't moved or freed until the read completes,
+            // since all reads are waited for below. If the push fails, the ring
+            // is dropped without submitting the queued reads.
+            unsafe { ring.submission().push(&read) }
+                .map_err(|_| Error::Internal("io_uring submission queue is full".into()))?;
+        }
+
+        let (mut completed, mut error, mut short) = (0, None, Vec::new());
+        while completed < reads.len() {
+            match ring.submit_and_wait(reads.len() - completed) {
+                Ok(_) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    // Reads may still be in flight and write to the buffers, so
+                    // leak them and the ring rather than free them.
+                    std::mem::forget(values);
+                    std::mem::forget(ring);
+                    return Err(err.into());
+                }
+            }
+            for entry in ring.completion() {
+                completed += 1;
+                let i = entry.user_data() as usize;
+                match entry.result() {
+                    n if n < 0 => error = Some(std::io::Error::from_raw_os_error(-n)),
+                    n if (n as usize) < values[i].len() => short.push((i, n as usize)),
+                    _ => {}
+                }
+            }
+        }
+        self.rings.lock()?.push(ring);
+        if let Some(error) = error {
+            return Err(error.into());
+        }
+        // Reads may return fewer bytes than requested, so finish them with
+        // positioned reads.
+        for (i, n) in short {
+            Log::read_exact_at(file, &mut values[i][n..], reads[i].0 + n as u64)?;
+        }
+        Ok(values)
+    }
+
+    /// io_uring isn't supported on this platform or without the feature.
+    #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+    fn read(&self, _: &File, _: &[(u64, u32)]) -> Result<Vec<Vec<u8>>> {
+        Err(Error::Config("io_uring reads require Linux and the io-uring feature".into()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -494,6 +975,10 @@ mod tests {
         assert_eq!(expect, s.scan(..).collect::<Result<Vec<_>>>()?,);
         s.log.print(&mut mint.new_goldenfile("compact-after")?)?;
 
+        // The compacted file replaced the original, and is still locked.
+        assert!(!path.with_extension("new").exists());
+        assert!(BitCask::new(path.clone()).is_err());
+
         // Reopen the log file and assert that the contents are the same.
         drop(s);
         let mut s = BitCask::new(path)?;
@@ -502,6 +987,46 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    /// Tests that compaction succeeds while a snapshot holds the old log file
+    /// open, and that the snapshot keeps reading it.
+    fn compact_snapshot() -> Result<()> {
+        use crate::storage::engine::Snapshot as _;
+
+        let dir = tempdir::TempDir::new("toydb")?;
+        let path = dir.path().join("toydb");
+        let mut s = BitCask::new(path.clone())?;
+        setup_log(&mut s)?;
+
+        let expect = s.scan(..).collect::<Result<Vec<_>>>()?;
+        let snapshot = s.snapshot()?;
+        let mut scan = snapshot.scan(..);
+        assert_eq!(scan.next().transpose()?, Some(expect[0].clone()));
+
+        // Compact and write to the new file while the snapshot is scanning.
+        s.compact()?;
+        s.set(b"a", vec![0xff])?;
+        assert_eq!(s.get(b"a")?, Some(vec![0xff]));
+        assert!(!path.with_extension("new").exists());
+        assert!(!path.with_extension("old").exists());
+
+        // The snapshot still reads the old file.
+        assert_eq!(expect[1..], scan.collect::<Result<Vec<_>>>()?);
+        assert_eq!(expect, snapshot.scan(..).collect::<Result<Vec<_>>>()?);
+        drop(snapshot);
+
+        // Compacting again works, and the compacted file reopens.
+        let snapshot = s.snapshot()?;
+        s.compact()?;
+        drop(snapshot);
+        let expect = s.scan(..).collect::<Result<Vec<_>>>()?;
+        drop(s);
+        let mut s = BitCask::new(path)?;
+        assert_eq!(expect, s.scan(..).collect::<Result<Vec<_>>>()?);
+
+        Ok(())
+    }
+
     #[test]
     /// Tests that new_compact() will automatically compact the file when appropriate.
     fn new_compact() -> Result<()> {
@@ -542,6 +1067,31 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    /// Tests that values can be read concurrently from several threads, since
+    /// reads don't use the file cursor.
+    fn read_concurrent() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let mut log = Log::new(dir.path().join("toydb"))?;
+        let mut values = Vec::new();
+        for i in 0..100u8 {
+            let (pos, len) = log.write_entry(&[i], Some(&[i; 10]))?;
+            values.push((i, pos + len as u64 - 10));
+        }
+
+        let log = &log;
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for (i, pos) in values.iter().rev() {
+                        assert_eq!(log.read_value(*pos, 10).unwrap(), vec![*i; 10]);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
     #[test]
     /// Tests that exclusive locks are taken out on log files, released when the
     /// database is closed, and that an error is returned if a lock is already
@@ -613,11 +1163,64 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    /// Tests that corrupt entries are detected via checksums, both by verify()
+    /// and when opening the file. A corrupt last entry is truncated like an
+    /// incomplete write, but corruption elsewhere is an error.
+    fn corruption() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let path = dir.path().join("toydb");
+        let corrupt = |offset: usize| -> Result<()> {
+            let mut data = std::fs::read(&path)?;
+            data[offset] ^= 0x01;
+            Ok(std::fs::write(&path, data)?)
+        };
+
+        let mut s = BitCask::new(path.clone())?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        assert!(BitCask::verify(&path).is_err()); // locked while open
+        drop(s);
+        assert_eq!(BitCask::verify(&path)?, Vec::<String>::new());
+
+        // Corrupt the value of the last entry, which is truncated on open.
+        corrupt(27)?;
+        assert_eq!(BitCask::verify(&path)?, vec!["checksum mismatch for entry at offset 14"]);
+        let mut s = BitCask::new(path.clone())?;
+        assert_eq!(s.scan(..).collect::<Result<Vec<_>>>()?, vec![(b"a".to_vec(), vec![0x01])]);
+        s.set(b"b", vec![0x02])?;
+        drop(s);
+        assert_eq!(BitCask::verify(&path)?, Vec::<String>::new());
+
+        // Corrupt the key of the first entry, which errors on open.
+        corrupt(12)?;
+        assert_eq!(BitCask::verify(&path)?, vec!["checksum mismatch for entry at offset 0"]);
+        assert_eq!(
+            BitCask::new(path.clone()).err(),
+            Some(Error::Internal(format!(
+                "Checksum mismatch for entry at offset 0 in {}",
+                path.display()
+            )))
+        );
+
+        // A truncated entry is reported too.
+        corrupt(12)?;
+        let f = std::fs::OpenOptions::new().write(true).open(&path)?;
+        f.set_len(20)?;
+        drop(f);
+        assert_eq!(BitCask::verify(&path)?, vec!["incomplete or corrupt entry at offset 14"]);
+
+        Ok(())
+    }
+
     #[test]
     /// Tests status(), both for a log file with known garbage, and
     /// after compacting it when the live size must equal the file size.
     fn status_full() -> Result<()> {
-        let mut s = setup()?;
+        // NB: Don't use setup(), because the tempdir (and log file) would be
+        // removed before compaction renames the log file aside.
+        let dir = tempdir::TempDir::new("toydb")?;
+        let mut s = BitCask::new(dir.path().join("toydb"))?;
         setup_log(&mut s)?;
 
         // Before compaction.
@@ -627,9 +1230,9 @@ mod tests {
                 name: "bitcask".to_string(),
                 keys: 5,
                 size: 8,
-                total_disk_size: 114,
-                live_disk_size: 48,
-                garbage_disk_size: 66
+                total_disk_size: 162,
+                live_disk_size: 68,
+                garbage_disk_size: 94
             }
         );
 
@@ -641,12 +1244,100 @@ mod tests {
                 name: "bitcask".to_string(),
                 keys: 5,
                 size: 8,
-                total_disk_size: 48,
-                live_disk_size: 48,
+                total_disk_size: 68,
+                live_disk_size: 68,
                 garbage_disk_size: 0,
             }
         );
 
         Ok(())
     }
+
+    #[test]
+    #[cfg(unix)]
+    /// Tests memory-mapped reads, both of values within the map and beyond it
+    /// before the file is remapped, across compaction and reopening.
+    fn mmap() -> Result<()> {
+        let path = tempdir::TempDir::new("toydb")?.path().join("toydb");
+        let mut s = BitCask::new(path.clone())?.with_mmap(true)?;
+        assert!(s.log.mmap.is_none()); // empty file
+
+        let mut expect = Vec::new();
+        for i in 0..100u8 {
+            s.set(&[i], vec![i; 10])?;
+            s.set(&[i], vec![i; 20])?; // garbage for compaction
+            expect.push((vec![i], vec![i; 20]));
+            let mapped = s.log.mmap.as_ref().map_or(0, |mmap| mmap.len() as u64);
+            assert!(mapped > 0 && s.log.file.metadata()?.len() <= 2 * mapped);
+            assert_eq!(s.scan(..).collect::<Result<Vec<_>>>()?, expect);
+        }
+
+        s.compact()?;
+        let status = s.status()?;
+        assert_eq!(s.log.mmap.as_ref().map(|mmap| mmap.len() as u64), Some(status.total_disk_size));
+        assert_eq!(s.scan(..).collect::<Result<Vec<_>>>()?, expect);
+
+        drop(s);
+        let mut s = BitCask::new(path)?.with_mmap(true)?;
+        assert_eq!(s.scan(..).collect::<Result<Vec<_>>>()?, expect);
+
+        Ok(())
+    }
+
+    /// Runs the engine test suite with memory-mapped reads.
+    #[cfg(unix)]
+    mod mmap_engine {
+        use super::*;
+
+        super::super::super::tests::test_engine!({
+            let path = tempdir::TempDir::new("toydb")?.path().join("toydb");
+            BitCask::new(path)?.with_mmap(true)?
+        });
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    /// Tests batched io_uring scan reads in both directions, across batches and
+    /// with or without a memory map covering part of the file.
+    fn io_uring() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        for mmap in [false, true] {
+            let path = dir.path().join(format!("toydb-{}", mmap));
+            let mut s = BitCask::new(path)?.with_mmap(mmap)?.with_io_uring(true)?;
+            let mut expect = Vec::new();
+            for i in 0..200u8 {
+                s.set(&[i], vec![i; i as usize])?;
+                expect.push((vec![i], vec![i; i as usize]));
+            }
+            assert_eq!(s.scan(..).collect::<Result<Vec<_>>>()?, expect);
+            let reverse: Vec<_> = expect.iter().rev().cloned().collect();
+            assert_eq!(s.scan(..).rev().collect::<Result<Vec<_>>>()?, reverse);
+
+            // Alternate between the front and back of the range.
+            let mut scan = s.scan(vec![10]..vec![150]);
+            let (mut front, mut back) = (Vec::new(), Vec::new());
+            for i in 0.. {
+                let item = if i % 3 == 0 { scan.next_back() } else { scan.next() };
+                match item.transpose()? {
+                    Some(item) if i % 3 == 0 => back.insert(0, item),
+                    Some(item) => front.push(item),
+                    None => break,
+                }
+            }
+            front.extend(back);
+            assert_eq!(front, expect[10..150]);
+        }
+        Ok(())
+    }
+
+    /// Runs the engine test suite with io_uring reads.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    mod io_uring_engine {
+        use super::*;
+
+        super::super::super::tests::test_engine!({
+            let path = tempdir::TempDir::new("toydb")?.path().join("toydb");
+            BitCask::new(path)?.with_io_uring(true)?
+        });
+    }
 }
@@ -1,16 +1,19 @@
 use super::{Engine, Status};
 use crate::error::Result;
 
-/// An in-memory key/value storage engine using the Rust standard library B-tree
-/// implementation. Data is not persisted.
+/// An in-memory key/value storage engine using a persistent B-tree. Data is not
+/// persisted to disk.
+///
+/// The B-tree is shared with snapshots via structural sharing: a snapshot is an
+/// O(1) clone, and later writes only copy the O(log n) nodes along their path.
 pub struct Memory {
-    data: std::collections::BTreeMap<Vec<u8>, Vec<u8>>,
+    data: im::OrdMap<Vec<u8>, Vec<u8>>,
 }
 
 impl Memory {
     /// Creates a new Memory key-value storage engine.
     pub fn new() -> Self {
-        Self { data: std::collections::BTreeMap::new() }
+        Self { data: im::OrdMap::new() }
     }
 }
 
@@ -22,6 +25,7 @@ impl std::fmt::Display for Memory {
 
 impl Engine for Memory {
     type ScanIterator<'a> = ScanIterator<'a>;
+    type Snapshot = Snapshot;
 
     fn flush(&mut self) -> Result<()> {
         Ok(())
@@ -52,6 +56,10 @@ impl Engine for Memory {
         Ok(())
     }
 
+    fn snapshot(&mut self) -> Result<Self::Snapshot> {
+        Ok(Snapshot { data: self.data.clone() })
+    }
+
     fn status(&mut self) -> Result<Status> {
         Ok(Status {
             name: self.to_string(),
@@ -64,8 +72,25 @@ impl Engine for Memory {
     }
 }
 
+/// A Memory engine snapshot.
+pub struct Snapshot {
+    data: im::OrdMap<Vec<u8>, Vec<u8>>,
+}
+
+impl super::Snapshot for Snapshot {
+    type ScanIterator<'a> = ScanIterator<'a>;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.data.get(key).cloned())
+    }
+
+    fn scan(&self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
+        ScanIterator { inner: self.data.range(range) }
+    }
+}
+
 pub struct ScanIterator<'a> {
-    inner: std::collections::btree_map::Range<'a, Vec<u8>, Vec<u8>>,
+    inner: im::ordmap::Iter<'a, Vec<u8>, Vec<u8>>,
 }
 
 impl<'a> ScanIterator<'a> {
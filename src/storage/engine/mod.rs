@@ -14,15 +14,21 @@ use serde::{Deserialize, Serialize};
 /// strings between 0 B and 2 GB, stored in lexicographical key order. Writes
 /// are only guaranteed durable after calling flush().
 ///
-/// Only supports single-threaded use since all methods (including reads) take a
-/// mutable reference -- serialized access can't be avoided anyway, since both
-/// Raft execution and file access is serial.
+/// All methods (including reads) take a mutable reference, so the engine
+/// itself only supports serial access. However, snapshot() returns a read-only
+/// snapshot that doesn't borrow the engine, such that several scans can be
+/// open at once while writes proceed.
 pub trait Engine: std::fmt::Display + Send + Sync {
     /// The iterator returned by scan().
     type ScanIterator<'a>: ScanIterator + 'a
     where
         Self: Sized + 'a; // omit in trait objects, for object safety
 
+    /// The snapshot returned by snapshot().
+    type Snapshot: Snapshot + 'static
+    where
+        Self: Sized; // omit in trait objects, for object safety
+
     /// Deletes a key, or does nothing if it does not exist.
     fn delete(&mut self, key: &[u8]) -> Result<()>;
 
@@ -49,23 +55,56 @@ pub trait Engine: std::fmt::Display + Send + Sync {
     where
         Self: Sized, // omit in trait objects, for object safety
     {
-        let start = std::ops::Bound::Included(prefix.to_vec());
-        let end = match prefix.iter().rposition(|b| *b != 0xff) {
-            Some(i) => std::ops::Bound::Excluded(
-                prefix.iter().take(i).copied().chain(std::iter::once(prefix[i] + 1)).collect(),
-            ),
-            None => std::ops::Bound::Unbounded,
-        };
-        self.scan((start, end))
+        self.scan(prefix_range(prefix))
     }
 
     /// Sets a value for a key, replacing the existing value if any.
     fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()>;
 
+    /// Returns a read-only snapshot of the engine's current contents, which
+    /// isn't affected by later writes. Taking a snapshot is cheap, and writes
+    /// while a snapshot exists don't copy the engine's entire in-memory index.
+    fn snapshot(&mut self) -> Result<Self::Snapshot>
+    where
+        Self: Sized; // omit in trait objects, for object safety
+
     /// Returns engine status.
     fn status(&mut self) -> Result<Status>;
 }
 
+/// A read-only point-in-time snapshot of an engine, see Engine::snapshot().
+/// It only borrows itself, so it can be read from while the engine is written
+/// to, and can be shared across threads.
+pub trait Snapshot: Send + Sync {
+    /// The iterator returned by scan().
+    type ScanIterator<'a>: ScanIterator + 'a
+    where
+        Self: 'a;
+
+    /// Gets a value for a key, if it exists.
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Iterates over an ordered range of key/value pairs.
+    fn scan(&self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_>;
+
+    /// Iterates over all key/value pairs starting with prefix.
+    fn scan_prefix(&self, prefix: &[u8]) -> Self::ScanIterator<'_> {
+        self.scan(prefix_range(prefix))
+    }
+}
+
+/// Returns the key range covering all keys starting with prefix.
+fn prefix_range(prefix: &[u8]) -> (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>) {
+    let start = std::ops::Bound::Included(prefix.to_vec());
+    let end = match prefix.iter().rposition(|b| *b != 0xff) {
+        Some(i) => std::ops::Bound::Excluded(
+            prefix.iter().take(i).copied().chain(std::iter::once(prefix[i] + 1)).collect(),
+        ),
+        None => std::ops::Bound::Unbounded,
+    };
+    (start, end)
+}
+
 /// A scan iterator, with a blanket implementation (in lieu of trait aliases).
 pub trait ScanIterator: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>> {}
 
@@ -452,6 +491,45 @@ mod tests {
                 Ok(())
             }
 
+            #[test]
+            /// Tests snapshots, which aren't affected by later writes and can
+            /// be scanned while writing.
+            fn snapshot() -> Result<()> {
+                use crate::storage::engine::Snapshot as _;
+
+                let mut s = $setup;
+                s.set(b"a", vec![1])?;
+                s.set(b"b", vec![2])?;
+                s.set(b"c", vec![3])?;
+
+                let snapshot = s.snapshot()?;
+                let mut scan = snapshot.scan(..);
+                assert_eq!(scan.next().transpose()?, Some((b"a".to_vec(), vec![1])));
+
+                // Write while scanning the snapshot, and take another snapshot.
+                s.set(b"b", vec![0x0b])?;
+                s.delete(b"c")?;
+                s.set(b"d", vec![4])?;
+                let other = s.snapshot()?;
+                s.set(b"a", vec![0x0a])?;
+
+                assert_scan(scan, vec![(b"b", vec![2]), (b"c", vec![3])])?;
+                assert_eq!(snapshot.get(b"a")?, Some(vec![1]));
+                assert_eq!(snapshot.get(b"d")?, None);
+                assert_scan(snapshot.scan_prefix(b"b"), vec![(b"b", vec![2])])?;
+                assert_scan(
+                    other.scan(..).rev(),
+                    vec![(b"d", vec![4]), (b"b", vec![0x0b]), (b"a", vec![1])],
+                )?;
+
+                // The engine sees the latest writes.
+                assert_scan(
+                    s.scan(..),
+                    vec![(b"a", vec![0x0a]), (b"b", vec![0x0b]), (b"d", vec![4])],
+                )?;
+                Ok(())
+            }
+
             #[test]
             /// Tests implementation-independent aspects of Status.
             fn status() -> Result<()> {
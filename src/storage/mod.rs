@@ -1,5 +1,4 @@
 pub mod bincode;
 pub mod debug;
 pub mod engine;
-pub mod keycode;
 pub mod mvcc;
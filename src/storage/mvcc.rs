@@ -130,6 +130,31 @@
 //! current active set, storing the snapshot in memory only. Read-only queries
 //! do not increment the version sequence number in Key::NextVersion.
 //!
+//! STATEMENT SAVEPOINTS
+//! ====================
+//!
+//! A read-write transaction can set a savepoint, and later roll back to it,
+//! undoing only the writes made since. The SQL engine sets one before each
+//! statement, such that a failed statement doesn't leave partial writes behind.
+//! A transaction has at most one savepoint, and setting a new one replaces it.
+//!
+//! While a savepoint is set, marked by Key::TxnSavepoint(version), the first
+//! write to each key since the savepoint records the transaction's previous
+//! version of the key (if any) as Key::TxnUndo(version, key). Rolling back to
+//! the savepoint restores these previous versions, or removes the versions and
+//! their TxnWrite records if there were none.
+//!
+//! COMMIT TIMESTAMPS
+//! =================
+//!
+//! Versions are logical, and say nothing about when a transaction committed.
+//! A transaction can be given a hybrid logical clock timestamp to commit at
+//! (see crate::clock), which is then recorded as Key::TxnCommitTimestamp. To
+//! make commit timestamps increase in commit order, even if the given
+//! timestamps don't, a timestamp at or below the last commit timestamp (stored
+//! as Key::LastCommitTimestamp) is bumped past it. Since the bumping only
+//! depends on the stored state, all Raft replicas record the same timestamps.
+//!
 //! GARBAGE COLLECTION
 //! ==================
 //!
@@ -139,7 +164,10 @@
 //! forever, both out of laziness and also because it allows unlimited time
 //! travel queries (it's a feature, not a bug!).
 
-use super::{bincode, engine::Engine, keycode};
+use super::bincode;
+use super::engine::{Engine, Snapshot as _};
+use crate::clock::Timestamp;
+use crate::encoding::keycode;
 use crate::error::{Error, Result};
 
 use serde::{Deserialize, Serialize};
@@ -188,6 +216,21 @@ pub enum Key<'a> {
         #[serde(borrow)]
         Cow<'a, [u8]>,
     ),
+    /// The commit timestamp of a committed transaction, by version. Only
+    /// written for transactions committed with a timestamp.
+    TxnCommitTimestamp(Version),
+    /// The latest commit timestamp.
+    LastCommitTimestamp,
+    /// Marks that an active transaction has a savepoint set.
+    TxnSavepoint(Version),
+    /// The transaction's own version of a key before its first write since
+    /// the savepoint, if any, used to roll back to the savepoint.
+    TxnUndo(
+        Version,
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+    ),
 }
 
 impl<'a> Key<'a> {
@@ -214,6 +257,10 @@ enum KeyPrefix<'a> {
         Cow<'a, [u8]>,
     ),
     Unversioned,
+    TxnCommitTimestamp,
+    LastCommitTimestamp,
+    TxnSavepoint,
+    TxnUndo(Version),
 }
 
 impl<'a> KeyPrefix<'a> {
@@ -267,6 +314,21 @@ impl<E: Engine> MVCC<E> {
         Transaction::resume(self.engine.clone(), state)
     }
 
+    /// Returns the commit timestamp of the transaction at the given version, if
+    /// it was committed with a timestamp.
+    pub fn commit_timestamp(&self, version: Version) -> Result<Option<Timestamp>> {
+        self.engine
+            .lock()?
+            .get(&Key::TxnCommitTimestamp(version).encode()?)?
+            .map(|v| bincode::deserialize(&v))
+            .transpose()
+    }
+
+    /// Fetches the versions of all active read-write transactions.
+    pub fn scan_active(&self) -> Result<HashSet<Version>> {
+        Transaction::scan_active(&mut self.engine.lock()?)
+    }
+
     /// Fetches the value of an unversioned key.
     pub fn get_unversioned(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         self.engine.lock()?.get(&Key::Unversioned(key.into()).encode()?)
@@ -277,6 +339,77 @@ impl<E: Engine> MVCC<E> {
         self.engine.lock()?.set(&Key::Unversioned(key.into()).encode()?, value)
     }
 
+    /// Deletes an unversioned key.
+    pub fn delete_unversioned(&self, key: &[u8]) -> Result<()> {
+        self.engine.lock()?.delete(&Key::Unversioned(key.into()).encode()?)
+    }
+
+    /// Scans unversioned keys with the given prefix, returning key/value pairs.
+    pub fn scan_unversioned_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        // Like in Transaction::scan_prefix, chop off the KeyCode terminator to
+        // match all keys with the prefix.
+        let mut prefix = Key::Unversioned(prefix.into()).encode()?;
+        prefix.truncate(prefix.len() - 2);
+        let pairs = self.engine.lock()?.scan_prefix(&prefix).collect::<Result<Vec<_>>>()?;
+        pairs
+            .into_iter()
+            .map(|(key, value)| match Key::decode(&key)? {
+                Key::Unversioned(key) => Ok((key.into_owned(), value)),
+                key => Err(Error::Internal(format!("Expected Key::Unversioned got {:?}", key))),
+            })
+            .collect()
+    }
+
+    /// Rewrites all versions of keys under the given prefix in place, by applying the given
+    /// function to each value. This includes historical and uncommitted versions, while deletion
+    /// tombstones are left as is. It does not create a new version, and is not transactional,
+    /// so it should only be used for offline storage format migrations. Returns the number of
+    /// rewritten values.
+    pub fn rewrite_prefix<F>(&self, prefix: &[u8], mut f: F) -> Result<u64>
+    where
+        F: FnMut(&[u8], Vec<u8>) -> Result<Vec<u8>>,
+    {
+        let mut engine = self.engine.lock()?;
+        // Like in Transaction::scan_prefix, chop off the KeyCode terminator to match all keys
+        // with the prefix.
+        let mut prefix = KeyPrefix::Version(prefix.into()).encode()?;
+        prefix.truncate(prefix.len() - 2);
+        let versions = engine.scan_prefix(&prefix).collect::<Result<Vec<_>>>()?;
+        let mut count = 0;
+        for (key, value) in versions {
+            let Key::Version(user_key, _) = Key::decode(&key)? else {
+                return Err(Error::Internal(format!("Expected Key::Version got {:?}", key)));
+            };
+            if let Some(value) = bincode::deserialize::<Option<Vec<u8>>>(&value)? {
+                let value = f(&user_key, value)?;
+                engine.set(&key, bincode::serialize(&Some(value))?)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Exports all raw key/value pairs from the storage engine, including
+    /// unversioned keys and transaction metadata, e.g. to snapshot the state.
+    pub fn export(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.engine.lock()?.scan(..).collect()
+    }
+
+    /// Replaces the entire storage engine contents with raw key/value pairs, as
+    /// returned by export(). It's not transactional, so there must not be any
+    /// concurrent transactions. The storage engine is flushed afterwards.
+    pub fn import(&self, pairs: Vec<(Vec<u8>, Vec<u8>)>) -> Result<()> {
+        let mut engine = self.engine.lock()?;
+        let keys = engine.scan(..).map(|r| r.map(|(k, _)| k)).collect::<Result<Vec<_>>>()?;
+        for key in keys {
+            engine.delete(&key)?;
+        }
+        for (key, value) in pairs {
+            engine.set(&key, value)?;
+        }
+        engine.flush()
+    }
+
     /// Returns the status of the MVCC and storage engines.
     pub fn status(&self) -> Result<Status> {
         let mut engine = self.engine.lock()?;
@@ -289,6 +422,19 @@ impl<E: Engine> MVCC<E> {
     }
 }
 
+/// A write to a key by a transaction, see Transaction::scan_changes().
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// The key.
+    pub key: Vec<u8>,
+    /// The version of the transaction that wrote the key.
+    pub version: Version,
+    /// The previous visible value, if any.
+    pub before: Option<Vec<u8>>,
+    /// The written value, or None if the key was deleted.
+    pub after: Option<Vec<u8>>,
+}
+
 /// MVCC engine status.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Status {
@@ -306,6 +452,8 @@ pub struct Transaction<E: Engine> {
     engine: Arc<Mutex<E>>,
     /// The transaction state.
     st: TransactionState,
+    /// The timestamp to commit at, if any.
+    commit_timestamp: Option<Timestamp>,
 }
 
 /// A Transaction's state, which determines its write version and isolation. It
@@ -357,6 +505,14 @@ impl TransactionState {
             version <= self.version
         }
     }
+
+    /// Returns the last version at or below which all transactions have
+    /// completed, as seen by this transaction, which includes its own version
+    /// for read-write transactions. No further changes at or below it can
+    /// become visible, so it can be used as a change data capture checkpoint.
+    pub fn resolved_version(&self) -> Version {
+        self.active.iter().copied().min().unwrap_or(self.version).min(self.version) - 1
+    }
 }
 
 impl<E: Engine> Transaction<E> {
@@ -382,7 +538,11 @@ impl<E: Engine> Transaction<E> {
         session.set(&Key::TxnActive(version).encode()?, vec![])?;
         drop(session);
 
-        Ok(Self { engine, st: TransactionState { version, read_only: false, active } })
+        Ok(Self {
+            engine,
+            st: TransactionState { version, read_only: false, active },
+            commit_timestamp: None,
+        })
     }
 
     /// Begins a new read-only transaction. If version is given it will see the
@@ -416,7 +576,11 @@ impl<E: Engine> Transaction<E> {
 
         drop(session);
 
-        Ok(Self { engine, st: TransactionState { version, read_only: true, active } })
+        Ok(Self {
+            engine,
+            st: TransactionState { version, read_only: true, active },
+            commit_timestamp: None,
+        })
     }
 
     /// Resumes a transaction from the given state.
@@ -426,7 +590,7 @@ impl<E: Engine> Transaction<E> {
         if !s.read_only && engine.lock()?.get(&Key::TxnActive(s.version).encode()?)?.is_none() {
             return Err(Error::Internal(format!("No active transaction at version {}", s.version)));
         }
-        Ok(Self { engine, st: s })
+        Ok(Self { engine, st: s, commit_timestamp: None })
     }
 
     /// Fetches the set of currently active transactions.
@@ -458,6 +622,13 @@ impl<E: Engine> Transaction<E> {
         &self.st
     }
 
+    /// Sets a hybrid logical clock timestamp to commit the transaction at, which
+    /// is recorded on commit. See the module documentation for details.
+    pub fn with_commit_timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.commit_timestamp = Some(timestamp);
+        self
+    }
+
     /// Commits the transaction, by removing it from the active set. This will
     /// immediately make its writes visible to subsequent transactions. Also
     /// removes its TxnWrite records, which are no longer needed.
@@ -466,6 +637,36 @@ impl<E: Engine> Transaction<E> {
             return Ok(());
         }
         let mut session = self.engine.lock()?;
+        self.commit_locked(&mut session)
+    }
+
+    /// Commits the transaction like commit(), but only if the given key hasn't
+    /// been written by a concurrent transaction since this transaction began,
+    /// checked atomically with the commit. Otherwise, the transaction is rolled
+    /// back and a serialization error is returned.
+    pub fn commit_unchanged(self, key: &[u8]) -> Result<()> {
+        if self.st.read_only {
+            return Ok(());
+        }
+        let mut session = self.engine.lock()?;
+        if let Err(err) = self.check_conflict(&mut session, key) {
+            drop(session);
+            self.rollback()?;
+            return Err(err);
+        }
+        self.commit_locked(&mut session)
+    }
+
+    /// Commits the transaction while holding the engine lock.
+    fn commit_locked(&self, session: &mut MutexGuard<E>) -> Result<()> {
+        if let Some(mut timestamp) = self.commit_timestamp {
+            if let Some(last) = session.get(&Key::LastCommitTimestamp.encode()?)? {
+                timestamp = timestamp.max(bincode::deserialize::<Timestamp>(&last)?.next());
+            }
+            let value = bincode::serialize(&timestamp)?;
+            session.set(&Key::TxnCommitTimestamp(self.st.version).encode()?, value.clone())?;
+            session.set(&Key::LastCommitTimestamp.encode()?, value)?;
+        }
         let remove = session
             .scan_prefix(&KeyPrefix::TxnWrite(self.st.version).encode()?)
             .map(|r| r.map(|(k, _)| k))
@@ -473,6 +674,7 @@ impl<E: Engine> Transaction<E> {
         for key in remove {
             session.delete(&key)?
         }
+        self.clear_savepoint(session)?;
         session.delete(&Key::TxnActive(self.st.version).encode()?)
     }
 
@@ -499,9 +701,69 @@ impl<E: Engine> Transaction<E> {
         for key in rollback.into_iter() {
             session.delete(&key)?;
         }
+        self.clear_savepoint(&mut session)?;
         session.delete(&Key::TxnActive(self.st.version).encode()?) // remove from active set
     }
 
+    /// Sets a savepoint, replacing any existing one. Writes made after it can
+    /// be undone with rollback_to_savepoint(). Does nothing for read-only
+    /// transactions, which don't write.
+    pub fn savepoint(&self) -> Result<()> {
+        if self.st.read_only {
+            return Ok(());
+        }
+        let mut session = self.engine.lock()?;
+        self.clear_savepoint(&mut session)?;
+        session.set(&Key::TxnSavepoint(self.st.version).encode()?, vec![])
+    }
+
+    /// Rolls back the writes made since the savepoint, restoring the
+    /// transaction's previous versions of the written keys. The savepoint
+    /// remains set, as if it was just set. Errors if there is no savepoint.
+    pub fn rollback_to_savepoint(&self) -> Result<()> {
+        if self.st.read_only {
+            return Ok(());
+        }
+        let mut session = self.engine.lock()?;
+        if session.get(&Key::TxnSavepoint(self.st.version).encode()?)?.is_none() {
+            return Err(Error::Internal("No savepoint set".into()));
+        }
+        let undo = session
+            .scan_prefix(&KeyPrefix::TxnUndo(self.st.version).encode()?)
+            .collect::<Result<Vec<_>>>()?;
+        for (undo_key, value) in undo {
+            let Key::TxnUndo(_, key) = Key::decode(&undo_key)? else {
+                return Err(Error::Internal(format!("Expected TxnUndo, got {:?}", undo_key)));
+            };
+            let version = Key::Version(key.clone(), self.st.version).encode()?;
+            match bincode::deserialize::<Option<Vec<u8>>>(&value)? {
+                Some(previous) => session.set(&version, previous)?,
+                None => {
+                    session.delete(&version)?;
+                    session.delete(&Key::TxnWrite(self.st.version, key).encode()?)?;
+                }
+            }
+            session.delete(&undo_key)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the savepoint and its undo records, if any.
+    fn clear_savepoint(&self, session: &mut MutexGuard<E>) -> Result<()> {
+        let savepoint = Key::TxnSavepoint(self.st.version).encode()?;
+        if session.get(&savepoint)?.is_none() {
+            return Ok(());
+        }
+        let remove = session
+            .scan_prefix(&KeyPrefix::TxnUndo(self.st.version).encode()?)
+            .map(|r| r.map(|(k, _)| k))
+            .collect::<Result<Vec<_>>>()?;
+        for key in remove {
+            session.delete(&key)?
+        }
+        session.delete(&savepoint)
+    }
+
     /// Deletes a key.
     pub fn delete(&self, key: &[u8]) -> Result<()> {
         self.write_version(key, None)
@@ -521,11 +783,39 @@ impl<E: Engine> Transaction<E> {
             return Err(Error::ReadOnly);
         }
         let mut session = self.engine.lock()?;
+        self.check_conflict(&mut session, key)?;
+
+        // If a savepoint is set, record our previous version of the key on the
+        // first write to it since the savepoint.
+        if session.get(&Key::TxnSavepoint(self.st.version).encode()?)?.is_some() {
+            let undo = Key::TxnUndo(self.st.version, key.into()).encode()?;
+            if session.get(&undo)?.is_none() {
+                let previous = session.get(&Key::Version(key.into(), self.st.version).encode()?)?;
+                session.set(&undo, bincode::serialize(&previous)?)?;
+            }
+        }
 
-        // Check for write conflicts, i.e. if the latest key is invisible to us
-        // (either a newer version, or an uncommitted version in our past). We
-        // can only conflict with the latest key, since all transactions enforce
-        // the same invariant.
+        // Write the new version and its write record.
+        //
+        // NB: TxnWrite contains the provided user key, not the encoded engine
+        // key, since we can construct the engine key using the version.
+        session.set(&Key::TxnWrite(self.st.version, key.into()).encode()?, vec![])?;
+        session
+            .set(&Key::Version(key.into(), self.st.version).encode()?, bincode::serialize(&value)?)
+    }
+
+    /// Checks that the given key hasn't been written by a concurrent
+    /// transaction since this transaction began, i.e. that a write to it
+    /// wouldn't conflict, returning a serialization error otherwise.
+    pub fn check_unchanged(&self, key: &[u8]) -> Result<()> {
+        self.check_conflict(&mut self.engine.lock()?, key)
+    }
+
+    /// Checks for write conflicts, i.e. if the latest key is invisible to us
+    /// (either a newer version, or an uncommitted version in our past). We can
+    /// only conflict with the latest key, since all transactions enforce the
+    /// same invariant.
+    fn check_conflict(&self, session: &mut MutexGuard<E>, key: &[u8]) -> Result<()> {
         let from = Key::Version(
             key.into(),
             self.st.active.iter().min().copied().unwrap_or(self.st.version + 1),
@@ -542,14 +832,7 @@ impl<E: Engine> Transaction<E> {
                 key => return Err(Error::Internal(format!("Expected Key::Version got {:?}", key))),
             }
         }
-
-        // Write the new version and its write record.
-        //
-        // NB: TxnWrite contains the provided user key, not the encoded engine
-        // key, since we can construct the engine key using the version.
-        session.set(&Key::TxnWrite(self.st.version, key.into()).encode()?, vec![])?;
-        session
-            .set(&Key::Version(key.into(), self.st.version).encode()?, bincode::serialize(&value)?)
+        Ok(())
     }
 
     /// Fetches a key's value, or None if it does not exist.
@@ -584,7 +867,7 @@ impl<E: Engine> Transaction<E> {
             Bound::Included(k) => Bound::Included(Key::Version(k.into(), u64::MAX).encode()?),
             Bound::Unbounded => Bound::Excluded(KeyPrefix::Unversioned.encode()?),
         };
-        Ok(Scan::new(self.engine.lock()?, self.state(), start, end))
+        Ok(Scan::new(self.engine.lock()?.snapshot()?, self.state(), start, end))
     }
 
     /// Scans keys under a given prefix.
@@ -594,20 +877,63 @@ impl<E: Engine> Transaction<E> {
         // the KeyCode byte slice terminator 0x0000 at the end.
         let mut prefix = KeyPrefix::Version(prefix.into()).encode()?;
         prefix.truncate(prefix.len() - 2);
-        Ok(Scan::new_prefix(self.engine.lock()?, self.state(), prefix))
+        Ok(Scan::new_prefix(self.engine.lock()?.snapshot()?, self.state(), prefix))
+    }
+
+    /// Returns the writes to keys under the given prefix that are visible to
+    /// the transaction and were made at versions after since, ordered by
+    /// version and key, along with each key's previous visible value. Deletes
+    /// of missing keys are omitted. This reads all versions under the prefix,
+    /// so it's intended for change data capture rather than regular reads.
+    ///
+    /// The last version up to which no more changes can become visible is
+    /// given by resolved_version().
+    pub fn scan_changes(&self, prefix: &[u8], since: Version) -> Result<Vec<Change>> {
+        let mut prefix = KeyPrefix::Version(prefix.into()).encode()?;
+        prefix.truncate(prefix.len() - 2);
+        let snapshot = self.engine.lock()?.snapshot()?;
+        let mut changes = Vec::new();
+        let mut last: Option<(Vec<u8>, Option<Vec<u8>>)> = None;
+        for result in VersionIterator::<E>::new(&self.st, snapshot.scan_prefix(&prefix)) {
+            let (key, version, value) = result?;
+            let after: Option<Vec<u8>> = bincode::deserialize(&value)?;
+            let before = match last.take() {
+                Some((last_key, value)) if last_key == key => value,
+                _ => None,
+            };
+            if version > since && (before.is_some() || after.is_some()) {
+                changes.push(Change {
+                    key: key.clone(),
+                    version,
+                    before: before.clone(),
+                    after: after.clone(),
+                });
+            }
+            last = Some((key, after));
+        }
+        changes.sort_by_key(|change| change.version);
+        Ok(changes)
+    }
+
+    /// Returns the last version at or below which all transactions have
+    /// completed, as seen by this transaction, see
+    /// TransactionState::resolved_version().
+    pub fn resolved_version(&self) -> Version {
+        self.st.resolved_version()
     }
 }
 
 /// A scan result. Can produce an iterator or collect an owned Vec.
 ///
-/// This intermediate struct is unfortunately needed to hold the MutexGuard for
-/// the scan() caller, since placing it in ScanIterator along with the inner
-/// iterator borrowing from it would create a self-referential struct.
-///
-/// TODO: is there a better way?
+/// The scan reads from an engine snapshot taken when the scan was created, so
+/// it doesn't hold the engine lock: several scans can be open at once, and the
+/// transaction can write while scanning (the writes aren't visible to the
+/// scan). This intermediate struct is needed to hold the snapshot, since
+/// placing it in ScanIterator along with the inner iterator borrowing from it
+/// would create a self-referential struct.
 pub struct Scan<'a, E: Engine + 'a> {
-    /// Access to the locked engine.
-    engine: MutexGuard<'a, E>,
+    /// The engine snapshot.
+    snapshot: E::Snapshot,
     /// The transaction state.
     txn: &'a TransactionState,
     /// The scan type and parameter.
@@ -622,24 +948,24 @@ enum ScanType {
 impl<'a, E: Engine + 'a> Scan<'a, E> {
     /// Creates a new range scan.
     fn new(
-        engine: MutexGuard<'a, E>,
+        snapshot: E::Snapshot,
         txn: &'a TransactionState,
         start: Bound<Vec<u8>>,
         end: Bound<Vec<u8>>,
     ) -> Self {
-        Self { engine, txn, param: ScanType::Range((start, end)) }
+        Self { snapshot, txn, param: ScanType::Range((start, end)) }
     }
 
     /// Creates a new prefix scan.
-    fn new_prefix(engine: MutexGuard<'a, E>, txn: &'a TransactionState, prefix: Vec<u8>) -> Self {
-        Self { engine, txn, param: ScanType::Prefix(prefix) }
+    fn new_prefix(snapshot: E::Snapshot, txn: &'a TransactionState, prefix: Vec<u8>) -> Self {
+        Self { snapshot, txn, param: ScanType::Prefix(prefix) }
     }
 
     /// Returns an iterator over the result.
     pub fn iter(&mut self) -> ScanIterator<'_, E> {
         let inner = match &self.param {
-            ScanType::Range(range) => self.engine.scan(range.clone()),
-            ScanType::Prefix(prefix) => self.engine.scan_prefix(prefix),
+            ScanType::Range(range) => self.snapshot.scan(range.clone()),
+            ScanType::Prefix(prefix) => self.snapshot.scan_prefix(prefix),
         };
         ScanIterator::new(self.txn, inner)
     }
@@ -650,6 +976,9 @@ impl<'a, E: Engine + 'a> Scan<'a, E> {
     }
 }
 
+/// The scan iterator of an engine's snapshots.
+type SnapshotScan<'a, E> = <<E as Engine>::Snapshot as super::engine::Snapshot>::ScanIterator<'a>;
+
 /// An iterator over the latest live and visible key/value pairs at the txn
 /// version.
 pub struct ScanIterator<'a, E: Engine + 'a> {
@@ -663,7 +992,7 @@ pub struct ScanIterator<'a, E: Engine + 'a> {
 
 impl<'a, E: Engine + 'a> ScanIterator<'a, E> {
     /// Creates a new scan iterator.
-    fn new(txn: &'a TransactionState, inner: E::ScanIterator<'a>) -> Self {
+    fn new(txn: &'a TransactionState, inner: SnapshotScan<'a, E>) -> Self {
         Self { inner: VersionIterator::new(txn, inner).peekable(), last_back: None }
     }
 
@@ -724,14 +1053,14 @@ impl<'a, E: Engine> DoubleEndedIterator for ScanIterator<'a, E> {
 struct VersionIterator<'a, E: Engine + 'a> {
     /// The transaction the scan is running in.
     txn: &'a TransactionState,
-    /// The inner engine scan iterator.
-    inner: E::ScanIterator<'a>,
+    /// The inner engine snapshot scan iterator.
+    inner: SnapshotScan<'a, E>,
 }
 
 #[allow(clippy::type_complexity)]
 impl<'a, E: Engine + 'a> VersionIterator<'a, E> {
     /// Creates a new MVCC version iterator for the given engine iterator.
-    fn new(txn: &'a TransactionState, inner: E::ScanIterator<'a>) -> Self {
+    fn new(txn: &'a TransactionState, inner: SnapshotScan<'a, E>) -> Self {
         Self { txn, inner }
     }
 
@@ -972,7 +1301,11 @@ pub mod tests {
         /// commit/rollback consumes it. We don't want to allow this in general,
         /// since a commit/rollback will invalidate the cloned transactions.
         fn clone(&self) -> Self {
-            let txn = Transaction { engine: self.txn.engine.clone(), st: self.txn.st.clone() };
+            let txn = Transaction {
+                engine: self.txn.engine.clone(),
+                st: self.txn.st.clone(),
+                commit_timestamp: self.txn.commit_timestamp,
+            };
             Self { id: self.id, txn, file: self.file.clone() }
         }
     }
@@ -988,12 +1321,36 @@ pub mod tests {
             result
         }
 
+        fn commit_at(self, timestamp: Timestamp) -> Result<()> {
+            let result = self.clone().txn.with_commit_timestamp(timestamp).commit();
+            self.print_mutation(&format!("commit at {}", timestamp), &result)?;
+            result
+        }
+
+        fn commit_unchanged(self, key: &[u8]) -> Result<()> {
+            let result = self.clone().txn.commit_unchanged(key);
+            self.print_mutation(&format!("commit unchanged {}", debug::format_raw(key)), &result)?;
+            result
+        }
+
         fn rollback(self) -> Result<()> {
             let result = self.clone().txn.rollback(); // clone to retain self.txn for printing
             self.print_mutation("rollback", &result)?;
             result
         }
 
+        fn savepoint(&self) -> Result<()> {
+            let result = self.txn.savepoint();
+            self.print_mutation("savepoint", &result)?;
+            result
+        }
+
+        fn rollback_to_savepoint(&self) -> Result<()> {
+            let result = self.txn.rollback_to_savepoint();
+            self.print_mutation("rollback to savepoint", &result)?;
+            result
+        }
+
         fn delete(&self, key: &[u8]) -> Result<()> {
             let result = self.txn.delete(key);
             self.print_mutation(&format!("del {}", debug::format_raw(key)), &result)?;
@@ -1050,6 +1407,34 @@ pub mod tests {
             Ok(scan)
         }
 
+        fn scan_changes(&self, prefix: &[u8], since: Version) -> Result<Vec<Change>> {
+            let changes = self.txn.scan_changes(prefix, since)?;
+            let mut f = self.file.lock()?;
+            writeln!(
+                f,
+                "T{}: scan changes {} since {}",
+                self.id,
+                debug::format_raw(prefix),
+                since
+            )?;
+            let format = |value: &Option<Vec<u8>>| match value {
+                Some(value) => debug::format_raw(value),
+                None => "None".to_string(),
+            };
+            for change in &changes {
+                writeln!(
+                    f,
+                    "    v{} {}: {} → {}",
+                    change.version,
+                    debug::format_raw(&change.key),
+                    format(&change.before),
+                    format(&change.after)
+                )?;
+            }
+            writeln!(f)?;
+            Ok(changes)
+        }
+
         /// Prints the result of a mutation to the golden file.
         fn print_mutation(&self, name: &str, result: &Result<()>) -> Result<()> {
             let mut f = self.file.lock()?;
@@ -1129,6 +1514,10 @@ pub mod tests {
                 Key::Version(b"foo".as_slice().into(), 1),
             ),
             (KeyPrefix::Unversioned, Key::Unversioned(b"foo".as_slice().into())),
+            (KeyPrefix::TxnCommitTimestamp, Key::TxnCommitTimestamp(1)),
+            (KeyPrefix::LastCommitTimestamp, Key::LastCommitTimestamp),
+            (KeyPrefix::TxnSavepoint, Key::TxnSavepoint(1)),
+            (KeyPrefix::TxnUndo(1), Key::TxnUndo(1, b"foo".as_slice().into())),
         ];
 
         for (prefix, key) in cases {
@@ -1611,6 +2000,37 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    /// Scans read from an engine snapshot taken when the scan begins, so
+    /// several scans can be open at once and the transaction can write while
+    /// scanning. Writes after the scan began aren't visible to it.
+    fn scan_snapshot() -> Result<()> {
+        let mut mvcc = Schedule::new("scan_snapshot")?;
+        mvcc.setup(vec![(b"a", 1, Some(&[1])), (b"b", 1, Some(&[1])), (b"c", 1, Some(&[1]))])?;
+
+        let t1 = mvcc.begin()?;
+        let mut scan = t1.scan(..)?;
+        let mut iter = scan.iter();
+        assert_eq!(iter.next().transpose()?, Some((b"a".to_vec(), vec![1])));
+
+        t1.set(b"b", vec![2])?;
+        t1.delete(b"c")?;
+        let mut other = t1.scan(..)?;
+        t1.set(b"a", vec![3])?;
+
+        assert_eq!(
+            iter.collect::<Result<Vec<_>>>()?,
+            vec![(b"b".to_vec(), vec![1]), (b"c".to_vec(), vec![1])]
+        );
+        assert_eq!(other.to_vec()?, vec![(b"a".to_vec(), vec![1]), (b"b".to_vec(), vec![2])]);
+        assert_scan!(t1.scan(..)? => {
+            b"a" => [3],
+            b"b" => [2],
+        });
+
+        Ok(())
+    }
+
     #[test]
     /// Tests that the key encoding is resistant to key/version overlap.
     /// For example, a naïve concatenation of keys and versions would
@@ -1684,6 +2104,69 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    /// Conditional commits should fail and roll back the transaction if the
+    /// key has been written by a concurrent transaction, committed or not, but
+    /// not if it was written by the transaction itself or before it began.
+    fn commit_unchanged() -> Result<()> {
+        let mut mvcc = Schedule::new("commit_unchanged")?;
+        mvcc.setup(vec![(b"a", 1, Some(&[0]))])?;
+
+        let t1 = mvcc.begin()?;
+        let t2 = mvcc.begin()?;
+        let t3 = mvcc.begin()?;
+        let t4 = mvcc.begin()?;
+
+        t1.set(b"b", vec![1])?;
+        t2.set(b"a", vec![2])?;
+        t2.commit()?;
+        t4.set(b"c", vec![4])?;
+
+        assert_eq!(t1.clone().txn.check_unchanged(b"a"), Err(Error::Serialization));
+        assert_eq!(t1.commit_unchanged(b"a"), Err(Error::Serialization)); // committed
+        assert_eq!(t3.commit_unchanged(b"c"), Err(Error::Serialization)); // uncommitted
+        t4.commit_unchanged(b"c")?; // own write
+
+        let t5 = mvcc.begin()?;
+        t5.commit_unchanged(b"a")?; // before begin
+
+        let t6 = mvcc.begin_read_only()?;
+        assert_scan!(t6.scan(..)? => {
+            b"a" => [2],
+            b"c" => [4],
+        });
+
+        Ok(())
+    }
+
+    #[test]
+    /// Commit timestamps are recorded for transactions committed with one, and
+    /// increase in commit order even if the given timestamps don't.
+    fn commit_timestamp() -> Result<()> {
+        let mut mvcc = Schedule::new("commit_timestamp")?;
+
+        let t1 = mvcc.begin()?;
+        let t2 = mvcc.begin()?;
+        let t3 = mvcc.begin()?;
+        let t4 = mvcc.begin()?;
+        let t5 = mvcc.begin()?;
+
+        t1.set(b"a", vec![1])?;
+        t1.commit_at(Timestamp::new(100, 0))?;
+        t3.commit_at(Timestamp::new(90, 3))?; // bumped past t1
+        t2.commit()?; // no timestamp
+        t4.commit_at(Timestamp::new(200, 0))?;
+        t5.rollback()?;
+
+        assert_eq!(mvcc.mvcc.commit_timestamp(1)?, Some(Timestamp::new(100, 0)));
+        assert_eq!(mvcc.mvcc.commit_timestamp(2)?, None);
+        assert_eq!(mvcc.mvcc.commit_timestamp(3)?, Some(Timestamp::new(100, 1)));
+        assert_eq!(mvcc.mvcc.commit_timestamp(4)?, Some(Timestamp::new(200, 0)));
+        assert_eq!(mvcc.mvcc.commit_timestamp(5)?, None);
+
+        Ok(())
+    }
+
     #[test]
     /// Tests that transaction rollback properly rolls back uncommitted writes,
     /// allowing other concurrent transactions to write the keys.
@@ -1738,6 +2221,49 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    /// Tests that rolling back to a savepoint undoes only the writes made since
+    /// it, restoring the transaction's previous versions of the keys.
+    fn savepoint() -> Result<()> {
+        let mut mvcc = Schedule::new("savepoint")?;
+        mvcc.setup(vec![(b"a", 1, Some(&[1])), (b"b", 1, Some(&[1]))])?;
+
+        let t1 = mvcc.begin()?;
+        assert_eq!(t1.rollback_to_savepoint(), Err(Error::Internal("No savepoint set".into())));
+        t1.set(b"a", vec![2])?;
+        t1.savepoint()?;
+        t1.set(b"a", vec![3])?;
+        t1.delete(b"b")?;
+        t1.set(b"c", vec![3])?;
+        t1.set(b"c", vec![4])?;
+        assert_scan!(t1.scan(..)? => { b"a" => [3], b"c" => [4] });
+
+        t1.rollback_to_savepoint()?;
+        assert_scan!(t1.scan(..)? => { b"a" => [2], b"b" => [1] });
+
+        // The savepoint remains set, so later writes can be rolled back too.
+        t1.set(b"b", vec![5])?;
+        t1.rollback_to_savepoint()?;
+        assert_scan!(t1.scan(..)? => { b"a" => [2], b"b" => [1] });
+
+        // A new savepoint retains the earlier writes. The rolled back write to
+        // c no longer conflicts with other transactions.
+        t1.set(b"b", vec![6])?;
+        t1.savepoint()?;
+        t1.set(b"c", vec![6])?;
+        t1.rollback_to_savepoint()?;
+        let t2 = mvcc.begin()?;
+        t2.set(b"c", vec![7])?;
+        t2.commit()?;
+
+        // Committing removes the savepoint and its undo records.
+        t1.commit()?;
+        let t3 = mvcc.begin_read_only()?;
+        assert_scan!(t3.scan(..)? => { b"a" => [2], b"b" => [6], b"c" => [7] });
+
+        Ok(())
+    }
+
     #[test]
     // A dirty write is when t2 overwrites an uncommitted value written by t1.
     // Snapshot isolation prevents this.
@@ -1879,6 +2405,55 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    /// Change scans return visible writes after the given version with the
+    /// previous values, ordered by version, and the resolved version stops
+    /// before active transactions.
+    fn scan_changes() -> Result<()> {
+        let mut mvcc = Schedule::new("scan_changes")?;
+        mvcc.setup(vec![
+            (b"a", 1, Some(&[1])),
+            (b"b", 1, Some(&[1])),
+            (b"a", 2, Some(&[2])),
+            (b"b", 2, None),
+            (b"c", 2, None),
+        ])?;
+
+        let t3 = mvcc.begin()?;
+        t3.set(b"a", vec![3])?;
+        let t4 = mvcc.begin()?;
+        t4.set(b"c", vec![4])?;
+        t4.commit()?;
+
+        let t5 = mvcc.begin_read_only()?;
+        let change = |key: &[u8], version, before: Option<&[u8]>, after: Option<&[u8]>| Change {
+            key: key.to_vec(),
+            version,
+            before: before.map(|v| v.to_vec()),
+            after: after.map(|v| v.to_vec()),
+        };
+        assert_eq!(
+            t5.scan_changes(&[], 0)?,
+            vec![
+                change(b"a", 1, None, Some(&[1])),
+                change(b"b", 1, None, Some(&[1])),
+                change(b"a", 2, Some(&[1]), Some(&[2])),
+                change(b"b", 2, Some(&[1]), None),
+                change(b"c", 4, None, Some(&[4])),
+            ]
+        );
+        assert_eq!(t5.scan_changes(b"a", 1)?, vec![change(b"a", 2, Some(&[1]), Some(&[2]))]);
+        assert_eq!(t5.txn.resolved_version(), 2);
+
+        // Once t3 commits, its change becomes visible to new transactions.
+        t3.commit()?;
+        let t6 = mvcc.begin_read_only()?;
+        assert_eq!(t6.scan_changes(b"a", 2)?, vec![change(b"a", 3, Some(&[2]), Some(&[3]))]);
+        assert_eq!(t6.txn.resolved_version(), 4);
+
+        Ok(())
+    }
+
     #[test]
     /// Tests unversioned key/value pairs, via set/get_unversioned().
     fn unversioned() -> Result<()> {
@@ -1914,6 +2489,16 @@ pub mod tests {
         mvcc.set_unversioned(b"a", vec![1])?;
         assert_eq!(mvcc.get_unversioned(b"a")?, Some(vec![1]));
 
+        // Prefix scans only see unversioned keys, and deletes remove them.
+        mvcc.set_unversioned(b"ba", vec![2])?;
+        assert_eq!(
+            mvcc.mvcc.scan_unversioned_prefix(b"b")?,
+            vec![(b"b".to_vec(), vec![0]), (b"ba".to_vec(), vec![2])]
+        );
+        mvcc.mvcc.delete_unversioned(b"b")?;
+        assert_eq!(mvcc.get_unversioned(b"b")?, None);
+        assert_eq!(mvcc.mvcc.scan_unversioned_prefix(b"b")?, vec![(b"ba".to_vec(), vec![2])]);
+
         Ok(())
     }
 }
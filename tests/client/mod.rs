@@ -3,16 +3,22 @@ mod pool;
 use super::{assert_row, assert_rows, setup};
 
 use toydb::error::{Error, Result};
+use toydb::protocol;
 use toydb::raft;
-use toydb::sql::engine::Status;
+use toydb::server::{Request, Response};
+use toydb::sql::engine::{Codec, Layout, Status};
 use toydb::sql::execution::ResultSet;
 use toydb::sql::schema;
 use toydb::sql::types::{Column, DataType, Value};
 use toydb::storage::{engine, mvcc};
-use toydb::Client;
+use toydb::{Client, Server};
 
+use futures::future::FutureExt as _;
+use futures::{SinkExt as _, TryStreamExt as _};
 use pretty_assertions::assert_eq;
 use serial_test::serial;
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 #[serial]
@@ -37,6 +43,7 @@ async fn get_table() -> Result<()> {
                     unique: true,
                     index: false,
                     references: None,
+                    include: vec![],
                 },
                 schema::Column {
                     name: "title".into(),
@@ -47,6 +54,7 @@ async fn get_table() -> Result<()> {
                     unique: false,
                     index: false,
                     references: None,
+                    include: vec![],
                 },
                 schema::Column {
                     name: "studio_id".into(),
@@ -57,6 +65,7 @@ async fn get_table() -> Result<()> {
                     unique: false,
                     index: false,
                     references: Some("studios".into()),
+                    include: vec![],
                 },
                 schema::Column {
                     name: "genre_id".into(),
@@ -67,6 +76,7 @@ async fn get_table() -> Result<()> {
                     unique: false,
                     index: false,
                     references: Some("genres".into()),
+                    include: vec![],
                 },
                 schema::Column {
                     name: "released".into(),
@@ -77,6 +87,7 @@ async fn get_table() -> Result<()> {
                     unique: false,
                     index: false,
                     references: None,
+                    include: vec![],
                 },
                 schema::Column {
                     name: "rating".into(),
@@ -87,6 +98,7 @@ async fn get_table() -> Result<()> {
                     unique: false,
                     index: false,
                     references: None,
+                    include: vec![],
                 },
                 schema::Column {
                     name: "ultrahd".into(),
@@ -97,8 +109,12 @@ async fn get_table() -> Result<()> {
                     unique: false,
                     index: false,
                     references: None,
+                    include: vec![],
                 },
-            ]
+            ],
+            ttl: None,
+            codec: Codec::Bincode,
+            layout: Layout::Row,
         }
     );
     Ok(())
@@ -113,13 +129,85 @@ async fn list_tables() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn kv() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+    let pairs = |pairs: &[(&[u8], &[u8])]| -> Vec<(Vec<u8>, Vec<u8>)> {
+        pairs.iter().map(|(k, v)| (k.to_vec(), v.to_vec())).collect()
+    };
+
+    // Key/value pairs are namespaced, and separate from SQL tables.
+    assert_eq!(c.kv_get("movies", b"a").await?, None);
+    c.kv_set("movies", b"a", b"1".to_vec()).await?;
+    c.kv_set("movies", b"b", b"2".to_vec()).await?;
+    c.kv_set("movies", b"c", vec![]).await?;
+    c.kv_set("movie", b"b", b"x".to_vec()).await?;
+    c.kv_set("moviesx", b"", b"y".to_vec()).await?;
+    assert_eq!(c.kv_get("movies", b"a").await?, Some(b"1".to_vec()));
+    assert_eq!(c.kv_get("movie", b"a").await?, None);
+    assert_eq!(c.list_tables().await?, vec!["countries", "genres", "movies", "studios"]);
+    assert_eq!(c.execute("SELECT COUNT(*) FROM movies").await?.into_value()?, Value::Integer(10));
+
+    assert_eq!(c.kv_scan("movies", ..).await?, pairs(&[(b"a", b"1"), (b"b", b"2"), (b"c", b"")]));
+    assert_eq!(c.kv_scan("movies", b"b".to_vec()..).await?, pairs(&[(b"b", b"2"), (b"c", b"")]));
+    assert_eq!(c.kv_scan("movies", ..b"b".to_vec()).await?, pairs(&[(b"a", b"1")]));
+    assert_eq!(c.kv_scan("movies", ..=b"b".to_vec()).await?, pairs(&[(b"a", b"1"), (b"b", b"2")]));
+    assert_eq!(c.kv_scan("movie", ..).await?, pairs(&[(b"b", b"x")]));
+    assert_eq!(c.kv_scan("none", ..).await?, pairs(&[]));
+
+    c.kv_delete("movies", b"b").await?;
+    c.kv_delete("movies", b"x").await?;
+    assert_eq!(c.kv_scan("movies", ..).await?, pairs(&[(b"a", b"1"), (b"c", b"")]));
+
+    // Key/value operations use the session's transaction.
+    c.execute("BEGIN").await?;
+    c.kv_set("movies", b"a", b"2".to_vec()).await?;
+    c.execute("DELETE FROM movies").await?;
+    assert_eq!(c.kv_get("movies", b"a").await?, Some(b"2".to_vec()));
+    c.execute("ROLLBACK").await?;
+    assert_eq!(c.kv_get("movies", b"a").await?, Some(b"1".to_vec()));
+
+    c.execute("BEGIN READ ONLY").await?;
+    assert_eq!(c.kv_set("movies", b"a", b"2".to_vec()).await, Err(Error::ReadOnly));
+    c.execute("COMMIT").await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn protocol_version() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::simple()).await?;
+    assert_eq!(c.protocol_version(), protocol::VERSION);
+
+    // A version 1 client doesn't send a handshake, and is disconnected.
+    let mut conn = tokio_serde::Framed::new(
+        Framed::new(TcpStream::connect("127.0.0.1:9605").await?, LengthDelimitedCodec::new()),
+        tokio_serde::formats::Bincode::<Result<Response>, Request>::default(),
+    );
+    conn.send(Request::ListTables).await?;
+    match conn.try_next().await {
+        Ok(None) | Err(_) => {}
+        Ok(Some(response)) => panic!("unexpected response {:?}", response),
+    }
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 #[serial]
 async fn status() -> Result<()> {
     let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
 
+    // The single node became leader immediately on startup.
+    let mut status = c.status().await?;
+    let events: Vec<_> = std::mem::take(&mut status.raft.history)
+        .into_iter()
+        .map(|entry| (entry.term, entry.event))
+        .collect();
+    assert_eq!(events, vec![(1, raft::HistoryEvent::Candidate), (1, raft::HistoryEvent::Leader)]);
+
     assert_eq!(
-        c.status().await?,
+        status,
         Status {
             raft: raft::Status {
                 server: 1,
@@ -129,15 +217,18 @@ async fn status() -> Result<()> {
                 commit_index: 27,
                 apply_index: 27,
                 storage: "bitcask".into(),
-                storage_size: 1324,
+                storage_size: 1713,
+                diverged: false,
+                checksum_mismatches: 0,
+                history: Vec::new(),
             },
             mvcc: mvcc::Status {
                 versions: 1,
                 active_txns: 0,
                 storage: engine::Status {
                     name: "memory".to_string(),
-                    keys: 26,
-                    size: 1630,
+                    keys: 31,
+                    size: 1742,
                     total_disk_size: 0,
                     live_disk_size: 0,
                     garbage_disk_size: 0
@@ -159,6 +250,7 @@ async fn execute() -> Result<()> {
         result,
         ResultSet::Query {
             columns: vec![Column { name: Some("id".into()) }, Column { name: Some("name".into()) }],
+            key_column: None,
             rows: Box::new(std::iter::empty()),
         }
     );
@@ -176,6 +268,7 @@ async fn execute() -> Result<()> {
         result,
         ResultSet::Query {
             columns: vec![Column { name: Some("id".into()) }, Column { name: Some("name".into()) }],
+            key_column: None,
             rows: Box::new(std::iter::empty()),
         }
     );
@@ -189,7 +282,10 @@ async fn execute() -> Result<()> {
     // INSERT
     assert_eq!(
         c.execute("INSERT INTO genres VALUES (1, 'Western')").await,
-        Err(Error::Value("Primary key 1 already exists for table genres".into())),
+        Err(Error::Constraint {
+            name: "genres_pkey".into(),
+            message: "Primary key 1 already exists for table genres".into()
+        }),
     );
     assert_eq!(
         c.execute("INSERT INTO genres VALUES (9, 'Western')").await,
@@ -211,7 +307,10 @@ async fn execute() -> Result<()> {
     );
     assert_eq!(
         c.execute("UPDATE genres SET id = 1 WHERE id = 9").await,
-        Err(Error::Value("Primary key 1 already exists for table genres".into()))
+        Err(Error::Constraint {
+            name: "genres_pkey".into(),
+            message: "Primary key 1 already exists for table genres".into()
+        })
     );
 
     // DELETE
@@ -231,6 +330,217 @@ async fn execute() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn execute_with() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+
+    // Rows are passed to the closure as they arrive, and not returned.
+    let mut rows = Vec::new();
+    let result = c
+        .execute_with("SELECT * FROM genres", |columns, row| {
+            assert_eq!(columns.len(), 2);
+            rows.push(row);
+            Ok(())
+        })
+        .await?;
+    assert_rows(result, Vec::new());
+    assert_eq!(
+        rows,
+        vec![
+            vec![Value::Integer(1), Value::String("Science Fiction".into())],
+            vec![Value::Integer(2), Value::String("Action".into())],
+            vec![Value::Integer(3), Value::String("Comedy".into())],
+        ]
+    );
+
+    // Streamed rows preserve the server's ORDER BY order across batches, numbered in the same
+    // order by WITH ORDINALITY.
+    let mut rows = Vec::new();
+    c.execute_with(
+        "SELECT n FROM generate_series(1, 3000) AS n ORDER BY n DESC WITH ORDINALITY",
+        |_, row| {
+            rows.push(row);
+            Ok(())
+        },
+    )
+    .await?;
+    assert_eq!(
+        rows,
+        (1..=3000).map(|i| vec![Value::Integer(3001 - i), Value::Integer(i)]).collect::<Vec<_>>()
+    );
+
+    // Closure errors are returned after the remaining rows are discarded, and the client
+    // remains usable.
+    let mut count = 0;
+    assert_eq!(
+        c.execute_with("SELECT * FROM genres", |_, _| {
+            count += 1;
+            Err(Error::Value("stop".into()))
+        })
+        .await
+        .err(),
+        Some(Error::Value("stop".into()))
+    );
+    assert_eq!(count, 1);
+    assert_row(
+        c.execute("SELECT * FROM genres WHERE id = 2").await?,
+        vec![Value::Integer(2), Value::String("Action".into())],
+    );
+
+    // Non-query statements don't call the closure.
+    assert_eq!(
+        c.execute_with("INSERT INTO genres VALUES (9, 'Western')", |_, _| panic!("unexpected row"))
+            .await,
+        Ok(ResultSet::Create { count: 1 })
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn execute_many() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+
+    // Statements are executed in order, and query rows are buffered. Semicolons in strings and
+    // comments don't split statements, and empty statements are skipped.
+    let mut results = c
+        .execute_many(
+            "INSERT INTO genres VALUES (4, 'a;b'); /* c; */;
+            SELECT name FROM genres WHERE id >= 3;
+            DELETE FROM genres WHERE id = 4",
+        )
+        .await?
+        .into_iter();
+    assert_eq!(results.next(), Some(Ok(ResultSet::Create { count: 1 })));
+    assert_rows(
+        results.next().unwrap()?,
+        vec![vec![Value::String("Comedy".into())], vec![Value::String("a;b".into())]],
+    );
+    assert_eq!(results.next(), Some(Ok(ResultSet::Delete { count: 1 })));
+    assert_eq!(results.next(), None);
+
+    assert_eq!(c.execute_many(" ; /* nothing */ ").await?, Vec::new());
+
+    // Execution stops at the first error, which is returned as the last result. Preceding
+    // statements take effect, including transaction statements.
+    assert_eq!(
+        c.execute_many(
+            "BEGIN; INSERT INTO genres VALUES (4, 'a'); SELECT * FROM x; DELETE FROM genres"
+        )
+        .await?,
+        vec![
+            Ok(ResultSet::Begin { version: 4, read_only: false }),
+            Ok(ResultSet::Create { count: 1 }),
+            Err(Error::Value("Table x does not exist".into())),
+        ]
+    );
+    assert_eq!(c.txn(), Some((4, false)));
+
+    // Syntax error positions are relative to the statement.
+    assert_eq!(
+        c.execute_many("SELECT COUNT(*) FROM genres; ROLLBACK; SELEC").await?,
+        vec![
+            Ok(ResultSet::Query {
+                columns: vec![Column { name: None }],
+                key_column: None,
+                rows: Box::new(std::iter::empty()),
+            }),
+            Ok(ResultSet::Rollback { version: 4 }),
+            Err(Error::Syntax { message: "Unexpected token selec".into(), line: 1, column: 1 }),
+        ]
+    );
+    assert_eq!(c.txn(), None);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn last_key() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+
+    // Keyset pagination returns the same pages as LIMIT and OFFSET.
+    let mut pages = Vec::new();
+    let mut last_key = None;
+    loop {
+        let query = match &last_key {
+            Some(key) => {
+                format!("SELECT id, title FROM movies WHERE id > {} ORDER BY id LIMIT 4", key)
+            }
+            None => "SELECT id, title FROM movies ORDER BY id LIMIT 4".to_string(),
+        };
+        let mut page = Vec::new();
+        c.execute_with(&query, |_, row| {
+            page.push(row);
+            Ok(())
+        })
+        .await?;
+        if page.is_empty() {
+            assert_eq!(c.last_key(), None);
+            break;
+        }
+        last_key = c.last_key();
+        assert_eq!(last_key.as_ref(), page.last().map(|row| &row[0]));
+        pages.push(page);
+    }
+    assert_eq!(pages.len(), 3);
+    for (i, page) in pages.into_iter().enumerate() {
+        let query = format!("SELECT id, title FROM movies ORDER BY id LIMIT 4 OFFSET {}", i * 4);
+        assert_rows(c.execute(&query).await?, page);
+    }
+
+    // The key is returned through projections and filters, in any column position and order.
+    c.execute("SELECT title, id FROM movies WHERE rating > 8 ORDER BY title").await?;
+    assert_eq!(c.last_key(), Some(Value::Integer(1)));
+
+    // Queries that don't return a single table's primary key don't have a last key.
+    c.execute("SELECT title FROM movies").await?;
+    assert_eq!(c.last_key(), None);
+    c.execute("SELECT COUNT(*) FROM movies").await?;
+    assert_eq!(c.last_key(), None);
+    c.execute("SELECT m.id FROM movies m JOIN genres g ON m.genre_id = g.id").await?;
+    assert_eq!(c.last_key(), None);
+    c.execute("INSERT INTO genres VALUES (9, 'Western')").await?;
+    assert_eq!(c.last_key(), None);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn command_tag() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+
+    // Mutations and queries include their affected or returned row counts.
+    for (query, tag) in [
+        ("SELECT * FROM genres", "SELECT 3"),
+        ("SELECT * FROM genres WHERE id > 10", "SELECT 0"),
+        ("INSERT INTO genres VALUES (4, 'a'), (5, 'b')", "INSERT 2"),
+        ("UPDATE genres SET name = 'c' WHERE id >= 4", "UPDATE 2"),
+        ("DELETE FROM genres WHERE id = 5", "DELETE 1"),
+        ("BEGIN", "BEGIN"),
+        ("CREATE TABLE t (id INTEGER PRIMARY KEY)", "CREATE TABLE"),
+        ("COMMIT", "COMMIT"),
+        ("EXPLAIN SELECT * FROM t", "EXPLAIN"),
+    ] {
+        c.execute(query).await?;
+        assert_eq!(c.command_tag().map(|tag| tag.to_string()), Some(tag.to_string()), "{}", query);
+    }
+
+    // Failed statements don't have a command tag.
+    assert!(c.execute("SELECT * FROM missing").await.is_err());
+    assert_eq!(c.command_tag(), None);
+
+    // Neither do queries whose row closure fails, although their rows are drained.
+    let result = c.execute_with("SELECT * FROM genres", |_, _| Err(Error::Abort)).await;
+    assert_eq!(result, Err(Error::Abort));
+    assert_eq!(c.command_tag(), None);
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 #[serial]
 async fn execute_txn() -> Result<()> {
@@ -262,7 +572,8 @@ async fn execute_txn() -> Result<()> {
     assert_rows(c.execute("SELECT * FROM genres WHERE id = 5").await?, Vec::new());
     assert_eq!(c.txn(), None);
 
-    // Starting a read-only txn should block writes
+    // Starting a read-only txn should block writes. It doesn't write to the Raft log.
+    let index = c.status().await?.raft.commit_index;
     assert_eq!(
         c.execute("BEGIN READ ONLY").await?,
         ResultSet::Begin { version: 4, read_only: true }
@@ -278,6 +589,7 @@ async fn execute_txn() -> Result<()> {
         vec![Value::Integer(4), Value::String("Drama".into())],
     );
     assert_eq!(c.execute("COMMIT").await?, ResultSet::Commit { version: 4 });
+    assert_eq!(c.status().await?.raft.commit_index, index);
 
     // Starting a time-travel txn should work, it shouldn't see recent changes, and it should
     // block writes
@@ -302,9 +614,13 @@ async fn execute_txn() -> Result<()> {
     c.execute("INSERT INTO genres VALUES (5, 'Horror')").await?;
     assert_eq!(
         c.execute("INSERT INTO genres VALUES (5, 'Musical')").await,
-        Err(Error::Value("Primary key 5 already exists for table genres".into()))
+        Err(Error::Constraint {
+            name: "genres_pkey".into(),
+            message: "Primary key 5 already exists for table genres".into()
+        })
     );
     assert_eq!(c.txn(), Some((4, false)));
+    assert!(!c.txn_aborted());
     c.execute("INSERT INTO genres VALUES (6, 'Western')").await?;
     assert_eq!(c.execute("COMMIT").await?, ResultSet::Commit { version: 4 });
     assert_rows(
@@ -322,6 +638,173 @@ async fn execute_txn() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn execute_cursor() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+    let genre = |id: i64, name: &str| vec![Value::Integer(id), Value::String(name.into())];
+
+    // Cursors require a transaction.
+    assert_eq!(
+        c.execute("DECLARE g CURSOR FOR SELECT * FROM genres").await,
+        Err(Error::Value("Cursors can only be used in a transaction".into()))
+    );
+
+    // Rows are fetched incrementally, and an exhausted cursor returns no rows. The cursor
+    // doesn't see writes made after it was declared.
+    c.execute("BEGIN").await?;
+    assert_eq!(
+        c.execute("DECLARE g CURSOR FOR SELECT * FROM genres ORDER BY id").await?,
+        ResultSet::DeclareCursor { name: "g".into() }
+    );
+    assert_eq!(
+        c.execute("DECLARE g CURSOR FOR SELECT * FROM genres").await,
+        Err(Error::Value("Cursor g already exists".into()))
+    );
+    c.execute("INSERT INTO genres VALUES (4, 'Drama')").await?;
+    assert_rows(
+        c.execute("FETCH 2 FROM g").await?,
+        vec![genre(1, "Science Fiction"), genre(2, "Action")],
+    );
+    assert_rows(c.execute("FETCH FROM g").await?, vec![genre(3, "Comedy")]);
+    assert_rows(c.execute("FETCH ALL FROM g").await?, Vec::new());
+    assert_eq!(c.command_tag().map(|tag| tag.to_string()), Some("SELECT 0".into()));
+
+    // Closed cursors can't be fetched from.
+    assert_eq!(c.execute("CLOSE g").await?, ResultSet::CloseCursor { name: "g".into() });
+    assert_eq!(
+        c.execute("FETCH ALL FROM g").await,
+        Err(Error::Value("Cursor g does not exist".into()))
+    );
+
+    // Cursors are closed when the transaction ends.
+    c.execute("DECLARE g CURSOR FOR SELECT name FROM genres WHERE id > 2").await?;
+    assert_rows(c.execute("FETCH 1 FROM g").await?, vec![vec![Value::String("Comedy".into())]]);
+    c.execute("COMMIT").await?;
+    c.execute("BEGIN READ ONLY").await?;
+    assert_eq!(
+        c.execute("FETCH ALL FROM g").await,
+        Err(Error::Value("Cursor g does not exist".into()))
+    );
+    c.execute("ROLLBACK").await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn execute_sessions() -> Result<()> {
+    let (a, _teardown) = setup::server_with_client(setup::movies()).await?;
+    let b = Client::new("127.0.0.1:9605").await?;
+
+    // Lists the sessions with their current statement and transaction, omitting the durations.
+    let show_sessions = || async {
+        let ResultSet::Query { columns, rows, .. } = a.execute("SHOW SESSIONS").await? else {
+            panic!("expected query result");
+        };
+        assert_eq!(
+            columns.into_iter().map(|c| c.name.unwrap()).collect::<Vec<_>>(),
+            vec![
+                "id",
+                "client",
+                "statement",
+                "statement_seconds",
+                "transaction",
+                "transaction_seconds"
+            ]
+        );
+        rows.map(|row| {
+            let row = row?;
+            Ok(vec![row[0].clone(), row[2].clone(), row[4].clone()])
+        })
+        .collect::<Result<Vec<_>>>()
+    };
+
+    b.execute("BEGIN").await?;
+    assert_eq!(
+        show_sessions().await?,
+        vec![
+            vec![Value::Integer(1), Value::String("SHOW SESSIONS".into()), Value::Null],
+            vec![Value::Integer(2), Value::Null, Value::Integer(2)],
+        ]
+    );
+
+    // Killing the query of an idle session doesn't affect it.
+    assert_eq!(a.execute("KILL QUERY 2").await?, ResultSet::Kill { session: 2, query: true });
+    assert_eq!(a.command_tag().map(|tag| tag.to_string()), Some("KILL".into()));
+    b.execute("INSERT INTO genres VALUES (4, 'Drama')").await?;
+
+    // Killing a running query cancels it, here the session's own.
+    let results = a.execute_many("KILL QUERY 1; SELECT * FROM genres").await?;
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[1], Err(Error::Cancelled));
+    a.execute("SELECT * FROM genres").await?;
+
+    // Killing a session disconnects it and rolls back its transaction.
+    assert_eq!(a.execute("KILL 2").await?, ResultSet::Kill { session: 2, query: false });
+    assert!(b.execute("SELECT 1").await.is_err());
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(
+        show_sessions().await?,
+        vec![vec![Value::Integer(1), Value::String("SHOW SESSIONS".into()), Value::Null]]
+    );
+    assert_rows(a.execute("SELECT * FROM genres WHERE id = 4").await?, Vec::new());
+
+    assert_eq!(
+        a.execute("KILL SESSION 2").await,
+        Err(Error::Value("Session 2 does not exist".into()))
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn execute_memory() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+
+    // EXPLAIN ANALYZE reports the returned rows and the memory buffered by the sort.
+    let ResultSet::ExplainAnalyze { rows, memory, .. } =
+        c.execute("EXPLAIN ANALYZE SELECT * FROM movies ORDER BY title").await?
+    else {
+        panic!("expected explain analyze result");
+    };
+    assert_eq!(rows, 10);
+    assert!(memory > 0);
+    assert_eq!(c.command_tag().map(|tag| tag.to_string()), Some("EXPLAIN".into()));
+
+    // Writes are applied and committed, and report the affected rows.
+    let ResultSet::ExplainAnalyze { rows, .. } =
+        c.execute("EXPLAIN ANALYZE UPDATE movies SET rating = 10.0 WHERE id = 1").await?
+    else {
+        panic!("expected explain analyze result");
+    };
+    assert_eq!(rows, 1);
+    assert_row(
+        c.execute("SELECT rating FROM movies WHERE id = 1").await?,
+        vec![Value::Float(10.0)],
+    );
+
+    // Statements exceeding the memory limit error, while streaming queries don't buffer rows.
+    c.execute("SET memory_limit = 100").await?;
+    assert_eq!(
+        c.execute("SELECT * FROM movies ORDER BY title").await,
+        Err(Error::Value("Statement exceeded the memory limit of 100 bytes".into()))
+    );
+    assert!(c.execute("SELECT * FROM movies").await.is_ok());
+    assert_eq!(
+        c.execute("SET memory_limit = -1").await,
+        Err(Error::Value("memory_limit must be a non-negative integer".into()))
+    );
+    c.execute("SET memory_limit = 0").await?;
+    assert!(c.execute("SELECT * FROM movies ORDER BY title").await.is_ok());
+
+    // The server metrics include the aggregate memory usage.
+    let metrics = c.metrics().await?;
+    assert!(metrics.memory.peak > 0);
+    assert!(metrics.memory.exceeded >= 1);
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 #[serial]
 async fn execute_txn_concurrent() -> Result<()> {
@@ -350,8 +833,19 @@ async fn execute_txn_concurrent() -> Result<()> {
         Err(Error::Serialization)
     );
 
+    // The serialization failure aborts b's txn, which must be rolled back.
+    assert_eq!(b.txn(), Some((3, false)));
+    assert!(b.txn_aborted());
+    assert_eq!(
+        b.execute("SELECT * FROM genres WHERE id = 1").await.err(),
+        Some(Error::Value("Transaction aborted, must roll back".into()))
+    );
+    assert!(b.txn_aborted());
+
     assert_eq!(a.execute("COMMIT").await, Ok(ResultSet::Commit { version: 2 }));
     assert_eq!(b.execute("ROLLBACK").await, Ok(ResultSet::Rollback { version: 3 }));
+    assert_eq!(b.txn(), None);
+    assert!(!b.txn_aborted());
 
     assert_row(
         a.execute("SELECT * FROM genres WHERE id = 1").await?,
@@ -360,3 +854,225 @@ async fn execute_txn_concurrent() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn execute_txn_ddl() -> Result<()> {
+    let (a, _teardown) = setup::server_with_client(setup::movies()).await?;
+    let b = Client::new("127.0.0.1:9605").await?;
+
+    // Schema changes are only visible to other transactions once committed.
+    a.execute("BEGIN").await?;
+    a.execute("CREATE TABLE test (id INTEGER PRIMARY KEY)").await?;
+    a.execute("INSERT INTO test VALUES (1)").await?;
+    assert_eq!(
+        b.execute("SELECT * FROM test").await,
+        Err(Error::Value("Table test does not exist".into()))
+    );
+    assert_eq!(b.list_tables().await?, vec!["countries", "genres", "movies", "studios"]);
+
+    // Concurrent schema changes conflict, even on unrelated tables.
+    b.execute("BEGIN").await?;
+    assert_eq!(
+        b.execute("CREATE TABLE other (id INTEGER PRIMARY KEY)").await,
+        Err(Error::Serialization)
+    );
+    b.execute("ROLLBACK").await?;
+
+    a.execute("COMMIT").await?;
+    assert_row(b.execute("SELECT * FROM test").await?, vec![Value::Integer(1)]);
+
+    // Rolling back discards the schema changes along with the data.
+    a.execute("BEGIN").await?;
+    a.execute("DROP TABLE test").await?;
+    a.execute("CREATE TABLE test (id INTEGER PRIMARY KEY, value STRING)").await?;
+    a.execute("ROLLBACK").await?;
+    assert_row(b.execute("SELECT * FROM test").await?, vec![Value::Integer(1)]);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn execute_txn_statement_atomicity() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+    let duplicate = Error::Constraint {
+        name: "genres_pkey".into(),
+        message: "Primary key 1 already exists for table genres".into(),
+    };
+
+    // A failed statement's writes are rolled back, but the transaction's earlier writes and
+    // later statements are retained.
+    c.execute("BEGIN").await?;
+    c.execute("INSERT INTO genres VALUES (4, 'Drama')").await?;
+    assert_eq!(
+        c.execute("INSERT INTO genres VALUES (5, 'Horror'), (1, 'Western')").await,
+        Err(duplicate.clone())
+    );
+    assert!(!c.txn_aborted());
+    assert!(c
+        .execute("UPDATE genres SET name = 'Sci-Fi', id = id + 1 WHERE id < 3")
+        .await
+        .is_err());
+    c.execute("INSERT INTO genres VALUES (6, 'Western')").await?;
+    c.execute("COMMIT").await?;
+    assert_rows(
+        c.execute("SELECT * FROM genres").await?,
+        vec![
+            vec![Value::Integer(1), Value::String("Science Fiction".into())],
+            vec![Value::Integer(2), Value::String("Action".into())],
+            vec![Value::Integer(3), Value::String("Comedy".into())],
+            vec![Value::Integer(4), Value::String("Drama".into())],
+            vec![Value::Integer(6), Value::String("Western".into())],
+        ],
+    );
+
+    // With write batching, the failed statement's buffered writes are discarded.
+    c.execute("SET write_batching = TRUE").await?;
+    c.execute("BEGIN").await?;
+    c.execute("INSERT INTO genres VALUES (7, 'Musical')").await?;
+    assert_eq!(
+        c.execute("INSERT INTO genres VALUES (8, 'Horror'), (9)").await,
+        Err(Error::Value("No default value for column name".into()))
+    );
+    c.execute("COMMIT").await?;
+    assert_rows(
+        c.execute("SELECT id FROM genres WHERE id > 6").await?,
+        vec![vec![Value::Integer(7)]],
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn execute_txn_write_batching() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+    c.execute("SET write_batching = TRUE").await?;
+
+    // Writes are buffered until the txn reads rows (including the update's scan) or commits,
+    // taking a single Raft proposal each in addition to the begin.
+    let index = c.status().await?.raft.commit_index;
+    c.execute("BEGIN").await?;
+    c.execute("INSERT INTO genres VALUES (4, 'Drama')").await?;
+    c.execute("INSERT INTO genres VALUES (5, 'Horror')").await?;
+    c.execute("UPDATE genres SET name = 'Sci-Fi' WHERE id = 1").await?;
+    assert_rows(
+        c.execute("SELECT * FROM genres WHERE id >= 4").await?,
+        vec![
+            vec![Value::Integer(4), Value::String("Drama".into())],
+            vec![Value::Integer(5), Value::String("Horror".into())],
+        ],
+    );
+    c.execute("INSERT INTO genres VALUES (6, 'Western')").await?;
+    c.execute("COMMIT").await?;
+    assert_eq!(c.status().await?.raft.commit_index, index + 4);
+    assert_rows(
+        c.execute("SELECT * FROM genres").await?,
+        vec![
+            vec![Value::Integer(1), Value::String("Sci-Fi".into())],
+            vec![Value::Integer(2), Value::String("Action".into())],
+            vec![Value::Integer(3), Value::String("Comedy".into())],
+            vec![Value::Integer(4), Value::String("Drama".into())],
+            vec![Value::Integer(5), Value::String("Horror".into())],
+            vec![Value::Integer(6), Value::String("Western".into())],
+        ],
+    );
+
+    // Write errors are returned on commit, which rolls back the txn.
+    let duplicate = Error::Constraint {
+        name: "genres_pkey".into(),
+        message: "Primary key 5 already exists for table genres".into(),
+    };
+    c.execute("BEGIN").await?;
+    c.execute("INSERT INTO genres VALUES (7, 'Musical')").await?;
+    c.execute("INSERT INTO genres VALUES (5, 'Musical')").await?;
+    assert_eq!(c.execute("COMMIT").await, Err(duplicate.clone()));
+    assert_eq!(c.txn(), None);
+    assert_rows(c.execute("SELECT * FROM genres WHERE id = 7").await?, Vec::new());
+
+    // Or when a read submits them, after which the txn must be rolled back.
+    c.execute("BEGIN").await?;
+    c.execute("INSERT INTO genres VALUES (5, 'Musical')").await?;
+    c.execute("INSERT INTO genres VALUES (7, 'Musical')").await?;
+    assert_eq!(c.execute("SELECT * FROM genres").await, Err(duplicate.clone()));
+    assert_eq!(c.execute("SELECT * FROM genres").await, Err(Error::Abort));
+    assert!(c.txn_aborted());
+    c.execute("ROLLBACK").await?;
+    assert_rows(c.execute("SELECT * FROM genres WHERE id = 7").await?, Vec::new());
+
+    // Autocommit statements submit their writes along with the commit.
+    let index = c.status().await?.raft.commit_index;
+    c.execute("INSERT INTO genres VALUES (7, 'Musical'), (8, 'Fantasy')").await?;
+    assert_eq!(c.status().await?.raft.commit_index, index + 2);
+
+    // Large writes are submitted in chunks of 1000 writes.
+    let index = c.status().await?.raft.commit_index;
+    let values: Vec<String> = (100..2600).map(|id| format!("({}, 'Genre {}')", id, id)).collect();
+    c.execute(&format!("INSERT INTO genres VALUES {}", values.join(", "))).await?;
+    assert_eq!(c.status().await?.raft.commit_index, index + 4);
+    assert_row(
+        c.execute("SELECT COUNT(*) FROM genres WHERE id >= 100").await?,
+        vec![Value::Integer(2500)],
+    );
+
+    // Disabling write batching submits writes immediately again.
+    c.execute("SET write_batching = FALSE").await?;
+    c.execute("BEGIN").await?;
+    assert_eq!(c.execute("INSERT INTO genres VALUES (5, 'Musical')").await, Err(duplicate));
+    assert!(!c.txn_aborted());
+    c.execute("ROLLBACK").await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn execute_chunked_scan() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(Vec::new()).await?;
+    c.execute("CREATE TABLE test (id INTEGER PRIMARY KEY, value INTEGER)").await?;
+    let values: Vec<String> = (1..=2500).map(|i| format!("({}, {})", i, i % 10)).collect();
+    c.execute(&format!("INSERT INTO test VALUES {}", values.join(", "))).await?;
+
+    // Table scans larger than a chunk return all rows in order, also in reverse and filtered.
+    let ids = |resultset: ResultSet| -> Result<Vec<i64>> {
+        let ResultSet::Query { rows, .. } = resultset else { panic!("expected query") };
+        rows.map(|row| match row?.as_slice() {
+            [Value::Integer(id)] => Ok(*id),
+            row => panic!("unexpected row {:?}", row),
+        })
+        .collect()
+    };
+    assert_eq!(ids(c.execute("SELECT id FROM test").await?)?, (1..=2500).collect::<Vec<_>>());
+    assert_eq!(
+        ids(c.execute("SELECT id FROM test ORDER BY id DESC").await?)?,
+        (1..=2500).rev().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        ids(c.execute("SELECT id FROM test WHERE value = 0 AND id > 1500").await?)?,
+        (1510..=2500).step_by(10).collect::<Vec<_>>()
+    );
+
+    // Updates that move rows ahead of the scan process each row once.
+    assert_eq!(
+        c.execute("UPDATE test SET id = id + 2500").await?,
+        ResultSet::Update { count: 2500 }
+    );
+    assert_eq!(ids(c.execute("SELECT MIN(id) FROM test").await?)?, vec![2501]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+// An in-memory server listening on port 0 should be usable right away.
+async fn server_memory() -> Result<()> {
+    let server = Server::memory().await?.listen("127.0.0.1:0", "127.0.0.1:0").await?;
+    let addr = server.sql_addr()?;
+    let (task, _abort) = server.serve().remote_handle();
+    tokio::spawn(task);
+
+    let client = Client::new(addr).await?;
+    assert_eq!(client.status().await?.raft.leader, 1);
+    client.execute("CREATE TABLE test (id INTEGER PRIMARY KEY)").await?;
+    client.execute("INSERT INTO test VALUES (1)").await?;
+    assert_row(client.execute("SELECT * FROM test").await?, vec![Value::Integer(1)]);
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use toydb::audit;
+use toydb::error::Result;
+use toydb::raft;
+use toydb::storage::engine::Memory;
+use toydb::{sql, Client, Server};
+
+use futures_util::future::FutureExt as _;
+use serial_test::serial;
+use std::collections::HashMap;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// DDL statements and privileged operations are recorded in the audit log,
+// including failed ones, while other statements are not.
+async fn audit() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let server = Server::new(
+        1,
+        HashMap::new(),
+        raft::Log::new(Memory::new(), false)?,
+        Box::new(sql::engine::Raft::new_state(Memory::new())?),
+    )
+    .await?
+    .audit(audit::Log::new(dir.path().into(), 0, 0)?);
+    let (task, _server) =
+        server.listen("127.0.0.1:9605", "127.0.0.1:9705").await?.serve().remote_handle();
+    tokio::spawn(task);
+
+    let client = Client::new("127.0.0.1:9605").await?;
+    client.execute("CREATE TABLE test (id INTEGER PRIMARY KEY)").await?;
+    client.execute("INSERT INTO test VALUES (1)").await?;
+    client.execute("SELECT * FROM test").await?;
+    client.execute_many("CREATE INDEX test_id ON test (id); DROP TABLE test").await?;
+    assert!(client.execute("DROP TABLE missing").await.is_err());
+    client.execute("SET CLUSTER SETTING ttl_expiry = FALSE").await?;
+    assert!(client.promote().await.is_err());
+
+    let content = std::fs::read_to_string(dir.path().join(format!("{:020}.log", 1)))?;
+    let records: Vec<_> = content.lines().collect();
+    let expect = [
+        ("CREATE TABLE", "\"CREATE TABLE test (id INTEGER PRIMARY KEY)\"", false),
+        ("CREATE INDEX", "\"CREATE INDEX test_id ON test (id);\"", false),
+        ("DROP TABLE", "\"DROP TABLE test\"", false),
+        ("DROP TABLE", "\"DROP TABLE missing\"", true),
+        ("SET CLUSTER SETTING", "\"SET CLUSTER SETTING ttl_expiry = FALSE\"", false),
+        ("PROMOTE", "null", true),
+    ];
+    assert_eq!(records.len(), expect.len(), "{}", content);
+    for (record, (action, statement, failed)) in records.iter().zip(expect) {
+        let fields = format!(",\"action\":\"{}\",\"statement\":{},", action, statement);
+        assert!(record.contains(&fields), "{}", record);
+        assert!(record.contains("\"client\":\"127.0.0.1:"), "{}", record);
+        assert_eq!(!record.contains("\"error\":null"), failed, "{}", record);
+    }
+    assert_eq!(audit::Log::verify(dir.path())?.0, 6);
+    Ok(())
+}
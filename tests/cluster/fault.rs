@@ -0,0 +1,478 @@
+use super::super::{assert_row, assert_rows};
+
+use toydb::error::{Error, Result};
+use toydb::fault::{self, Fault, Link, Network};
+use toydb::raft::{self, Event, NodeID};
+use toydb::sql::execution::ResultSet;
+use toydb::sql::types::Value;
+use toydb::storage::engine::BitCask;
+use toydb::{sql, Client, Server};
+
+use futures_util::future::{try_join_all, FutureExt as _, RemoteHandle};
+use rand::{Rng as _, SeedableRng as _};
+use serial_test::serial;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tempdir::TempDir;
+
+/// The number of nodes in the test cluster.
+const NODES: NodeID = 3;
+
+fn sql_addr(id: NodeID) -> String {
+    format!("127.0.0.1:{}", 9605 + id as u16)
+}
+
+fn raft_addr(id: NodeID) -> String {
+    format!("127.0.0.1:{}", 9705 + id as u16)
+}
+
+/// A test cluster with persistent storage, whose nodes can crash and be
+/// restarted. Each node's Raft log is wrapped in a fault injection engine
+/// named log{id}. Faults are cleared when the cluster is created and dropped.
+/// Nodes communicate via TCP, or via a simulated network if given.
+struct Cluster {
+    dir: TempDir,
+    nodes: HashMap<NodeID, RemoteHandle<Result<()>>>,
+    network: Option<Network>,
+}
+
+impl Cluster {
+    /// Starts a cluster with a test table, and waits for it to have a leader.
+    async fn new() -> Result<Self> {
+        Self::with_network(None).await
+    }
+
+    /// Starts a cluster like new(), using the given network if any.
+    async fn with_network(network: Option<Network>) -> Result<Self> {
+        fault::clear_all();
+        let mut cluster = Self { dir: TempDir::new("toydb")?, nodes: HashMap::new(), network };
+        for id in 1..=NODES {
+            cluster.start(id).await?;
+        }
+        cluster.execute(1, "CREATE TABLE test (id INTEGER PRIMARY KEY, value STRING)").await?;
+        Ok(cluster)
+    }
+
+    /// Starts (or restarts) a node. Retries while the files or ports of a
+    /// crashed node are still held by its shutting down tasks.
+    async fn start(&mut self, id: NodeID) -> Result<()> {
+        let dir = self.dir.path().join(format!("toydb{}", id));
+        for attempt in 1.. {
+            match Self::server(id, &dir, self.network.as_ref()).await {
+                Ok(server) => {
+                    let (task, handle) = server.serve().remote_handle();
+                    tokio::spawn(task);
+                    self.nodes.insert(id, handle);
+                    break;
+                }
+                Err(err) if attempt >= 50 => return Err(err),
+                Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+        Ok(())
+    }
+
+    async fn server(id: NodeID, dir: &Path, network: Option<&Network>) -> Result<Server> {
+        let peers = (1..=NODES).filter(|p| *p != id).map(|p| (p, raft_addr(p))).collect();
+        let log = fault::Engine::new(&format!("log{}", id), BitCask::new(dir.join("log"))?);
+        let state = sql::engine::Raft::new_state(BitCask::new(dir.join("state"))?)?;
+        let server = Server::new(id, peers, raft::Log::new(log, false)?, Box::new(state)).await?;
+        match network {
+            Some(network) => server.listen_transport(&sql_addr(id), network.transport(id)).await,
+            None => server.listen(&sql_addr(id), &raft_addr(id)).await,
+        }
+    }
+
+    /// Waits for a node to crash, returning its error.
+    async fn crashed(&mut self, id: NodeID) -> Error {
+        let handle = self.nodes.remove(&id).expect("node not running");
+        match tokio::time::timeout(Duration::from_secs(10), handle).await {
+            Ok(Err(err)) => err,
+            Ok(Ok(())) => panic!("node {} exited without error", id),
+            Err(_) => panic!("node {} did not crash", id),
+        }
+    }
+
+    /// Executes a statement on a node, retrying errors e.g. during elections.
+    async fn execute(&self, id: NodeID, statement: &str) -> Result<ResultSet> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let result = async { Client::new(sql_addr(id)).await?.execute(statement).await }.await;
+            match result {
+                Ok(resultset) => return Ok(resultset),
+                Err(err) if attempt >= 50 => return Err(err),
+                Err(_) => tokio::time::sleep(Duration::from_millis(200)).await,
+            }
+        }
+    }
+
+    /// Returns a node's Raft status.
+    async fn status(&self, id: NodeID) -> Result<raft::Status> {
+        Ok(Client::new(sql_addr(id)).await?.status().await?.raft)
+    }
+
+    /// Returns the current leader, as seen by the given node.
+    async fn leader(&self, id: NodeID) -> Result<NodeID> {
+        for _ in 0..50 {
+            match self.status(id).await {
+                Ok(status) if status.leader > 0 => return Ok(status.leader),
+                _ => tokio::time::sleep(Duration::from_millis(200)).await,
+            }
+        }
+        Err(Error::Internal(format!("Node {} found no leader", id)))
+    }
+
+    /// Waits for a node to apply the given index.
+    async fn wait_applied(&self, id: NodeID, index: raft::Index) -> Result<()> {
+        for _ in 0..50 {
+            match self.status(id).await {
+                Ok(status) if status.apply_index >= index => return Ok(()),
+                _ => tokio::time::sleep(Duration::from_millis(200)).await,
+            }
+        }
+        Err(Error::Internal(format!("Node {} did not apply index {}", id, index)))
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        fault::clear_all();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// An isolated leader should be replaced, and catch up once the partition heals.
+async fn drop_messages_partition() -> Result<()> {
+    let cluster = Cluster::new().await?;
+    let leader = cluster.leader(1).await?;
+    let other = (1..=NODES).find(|id| *id != leader).unwrap();
+
+    fault::inject("out", Fault::DropMessages { from: Some(leader), to: None, filter: |_| true });
+    fault::inject("in", Fault::DropMessages { from: None, to: Some(leader), filter: |_| true });
+    cluster.execute(other, "INSERT INTO test VALUES (1, 'a')").await?;
+    let status = cluster.status(other).await?;
+    assert_ne!(status.leader, leader);
+
+    fault::clear_all();
+    cluster.wait_applied(leader, status.commit_index).await?;
+    assert_row(
+        cluster.execute(leader, "SELECT * FROM test").await?,
+        vec![Value::Integer(1), Value::String("a".into())],
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// Delayed replication should slow down writes, but not fail them.
+async fn delay_messages() -> Result<()> {
+    let cluster = Cluster::new().await?;
+    let leader = cluster.leader(1).await?;
+    let client = Client::new(sql_addr(leader)).await?;
+
+    let delay = Duration::from_millis(300);
+    let filter = |e: &Event| matches!(e, Event::AppendEntries { .. });
+    fault::inject("delay", Fault::DelayMessages { from: None, to: None, filter, delay });
+    let start = Instant::now();
+    client.execute("INSERT INTO test VALUES (1, 'a')").await?;
+    assert!(start.elapsed() >= delay);
+
+    fault::clear("delay");
+    assert_row(
+        client.execute("SELECT * FROM test").await?,
+        vec![Value::Integer(1), Value::String("a".into())],
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// A follower whose log writes fail should crash without affecting the quorum,
+// and catch up when restarted.
+async fn fail_writes_restart() -> Result<()> {
+    let mut cluster = Cluster::new().await?;
+    let leader = cluster.leader(1).await?;
+    let follower = (1..=NODES).find(|id| *id != leader).unwrap();
+
+    let engine = format!("log{}", follower);
+    fault::inject("writes", Fault::FailWrites { engine, probability: 1.0 });
+    cluster.execute(leader, "INSERT INTO test VALUES (1, 'a')").await?;
+    assert!(
+        matches!(cluster.crashed(follower).await, Error::Internal(e) if e.contains("Injected"))
+    );
+    cluster.execute(leader, "INSERT INTO test VALUES (2, 'b')").await?;
+
+    fault::clear("writes");
+    cluster.start(follower).await?;
+    cluster.wait_applied(follower, cluster.status(leader).await?.commit_index).await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// A leader crashing after committing an entry should not lose committed writes,
+// and should recover when restarted.
+async fn crash_leader_commit() -> Result<()> {
+    let mut cluster = Cluster::new().await?;
+    let leader = cluster.leader(1).await?;
+    let other = (1..=NODES).find(|id| *id != leader).unwrap();
+    cluster.execute(other, "INSERT INTO test VALUES (1, 'a')").await?;
+
+    // The leader crashes once it commits the insert's transaction begin, so
+    // the outcome of the insert is unknown to the client.
+    fault::inject("crash", Fault::Crash { node: leader, point: "leader.commit".into() });
+    Client::new(sql_addr(other)).await?.execute("INSERT INTO test VALUES (2, 'b')").await.ok();
+    assert!(matches!(cluster.crashed(leader).await, Error::Internal(e) if e.contains("Injected")));
+
+    cluster.execute(other, "INSERT INTO test VALUES (3, 'c')").await?;
+    assert_ne!(cluster.leader(other).await?, leader);
+    cluster.start(leader).await?;
+    cluster.wait_applied(leader, cluster.status(other).await?.commit_index).await?;
+    assert_rows(
+        cluster.execute(leader, "SELECT * FROM test WHERE id != 2").await?,
+        vec![
+            vec![Value::Integer(1), Value::String("a".into())],
+            vec![Value::Integer(3), Value::String("c".into())],
+        ],
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// A prepared transaction should survive the loss of the leader it was prepared on, and be
+// committed via the new leader.
+async fn prepared_leader_failover() -> Result<()> {
+    let mut cluster = Cluster::new().await?;
+    let leader = cluster.leader(1).await?;
+    let other = (1..=NODES).find(|id| *id != leader).unwrap();
+
+    let client = Client::new(sql_addr(leader)).await?;
+    client.execute("BEGIN").await?;
+    client.execute("INSERT INTO test VALUES (1, 'a')").await?;
+    let version = match client.execute("PREPARE TRANSACTION 'x'").await? {
+        ResultSet::Prepare { version, .. } => version,
+        result => panic!("unexpected result {:?}", result),
+    };
+    drop(client);
+    cluster.wait_applied(other, cluster.status(leader).await?.commit_index).await?;
+    cluster.nodes.remove(&leader);
+    assert_ne!(cluster.leader(other).await?, leader);
+
+    assert_rows(
+        cluster.execute(other, "SHOW PREPARED TRANSACTIONS").await?,
+        vec![vec![Value::String("x".into()), Value::Integer(version as i64)]],
+    );
+    assert_rows(cluster.execute(other, "SELECT * FROM test").await?, Vec::new());
+    assert_eq!(cluster.execute(other, "COMMIT PREPARED 'x'").await?, ResultSet::Commit { version });
+    assert_rows(
+        cluster.execute(other, "SELECT * FROM test").await?,
+        vec![vec![Value::Integer(1), Value::String("a".into())]],
+    );
+    assert_rows(cluster.execute(other, "SHOW PREPARED TRANSACTIONS").await?, Vec::new());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// A leader that can't reach its followers, but still hears from them, should
+// be replaced, and step down once it hears from the new leader.
+async fn network_asymmetric_partition() -> Result<()> {
+    let network = Network::new();
+    let cluster = Cluster::with_network(Some(network.clone())).await?;
+    let leader = cluster.leader(1).await?;
+    let other = (1..=NODES).find(|id| *id != leader).unwrap();
+
+    for id in (1..=NODES).filter(|id| *id != leader) {
+        network.partition(leader, id);
+    }
+    cluster.execute(other, "INSERT INTO test VALUES (1, 'a')").await?;
+    let status = cluster.status(other).await?;
+    assert_ne!(status.leader, leader);
+
+    network.heal();
+    assert_eq!(cluster.leader(leader).await?, status.leader);
+    cluster.wait_applied(leader, status.commit_index).await?;
+    assert_row(
+        cluster.execute(leader, "SELECT * FROM test").await?,
+        vec![Value::Integer(1), Value::String("a".into())],
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// Latency jitter, message loss, and duplicate delivery, which also reorder
+// messages, should not lose acknowledged writes or prevent the nodes from
+// converging.
+async fn network_unreliable() -> Result<()> {
+    let network = Network::new();
+    let cluster = Cluster::with_network(Some(network.clone())).await?;
+    let leader = cluster.leader(1).await?;
+    let client = Client::new(sql_addr(leader)).await?;
+    // Lost forwarded requests or responses are only detected via timeouts.
+    client.set_timeout(Some(Duration::from_secs(2)))?;
+
+    let latency = Duration::ZERO..Duration::from_millis(20);
+    let link = Link { latency, loss: 0.1, duplicate: 0.5, ..Default::default() };
+    network.set_links(&(1..=NODES).collect::<Vec<_>>(), link);
+    let mut written = Vec::new();
+    for id in 1..=10 {
+        if client.execute(&format!("INSERT INTO test VALUES ({}, 'a')", id)).await.is_ok() {
+            written.push(vec![Value::Integer(id)]);
+        }
+    }
+    network.heal();
+
+    let leader = cluster.leader(leader).await?;
+    let status = cluster.status(leader).await?;
+    for id in 1..=NODES {
+        cluster.wait_applied(id, status.commit_index).await?;
+    }
+    let rows = match cluster.execute(leader, "SELECT id FROM test").await? {
+        ResultSet::Query { rows, .. } => rows.collect::<Result<Vec<_>>>()?,
+        r => panic!("Unexpected result {:?}", r),
+    };
+    assert!(!written.is_empty());
+    assert!(written.iter().all(|row| rows.contains(row)), "lost writes");
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// A bandwidth cap should delay the replication of large writes.
+async fn network_bandwidth() -> Result<()> {
+    let network = Network::new();
+    let cluster = Cluster::with_network(Some(network.clone())).await?;
+    let leader = cluster.leader(1).await?;
+    let client = Client::new(sql_addr(leader)).await?;
+
+    // 1 KB at 2 KB/s takes at least 500 ms to replicate.
+    let link = Link { bandwidth: Some(2_000), ..Default::default() };
+    network.set_links(&(1..=NODES).collect::<Vec<_>>(), link);
+    let value = "x".repeat(1_000);
+    let start = Instant::now();
+    client.execute(&format!("INSERT INTO test VALUES (1, '{}')", value)).await?;
+    assert!(start.elapsed() >= Duration::from_millis(500));
+
+    network.heal();
+    assert_row(
+        client.execute("SELECT * FROM test").await?,
+        vec![Value::Integer(1), Value::String(value)],
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+#[serial]
+// A Jepsen-style bank workload: concurrent transfers between accounts via all
+// nodes, while the leader is isolated and later crashed, should keep the total
+// balance constant and never overdraw an account.
+async fn bank() -> Result<()> {
+    const ACCOUNTS: i64 = 10;
+    const BALANCE: i64 = 100;
+    const WORKERS: NodeID = 6;
+
+    let network = Network::new();
+    let mut cluster = Cluster::with_network(Some(network.clone())).await?;
+    cluster
+        .execute(1, "CREATE TABLE account (id INTEGER PRIMARY KEY, balance INTEGER NOT NULL)")
+        .await?;
+    let values: Vec<String> = (1..=ACCOUNTS).map(|id| format!("({}, {})", id, BALANCE)).collect();
+    cluster.execute(1, &format!("INSERT INTO account VALUES {}", values.join(", "))).await?;
+
+    // Each worker makes random transfers via a node until stopped, moving no
+    // more than the sender's balance. Errors are expected while faults are
+    // injected, and the outcome of a failed COMMIT is unknown, but neither
+    // should break the invariants. Returns the number of committed transfers.
+    let stop = AtomicBool::new(false);
+    let workers = try_join_all((0..WORKERS).map(|worker| {
+        let stop = &stop;
+        let node = worker % NODES + 1;
+        async move {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(worker as u64);
+            let mut committed = 0;
+            while !stop.load(Ordering::Relaxed) {
+                let Ok(client) = Client::new(sql_addr(node)).await else {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                };
+                client.set_timeout(Some(Duration::from_secs(2)))?;
+                while !stop.load(Ordering::Relaxed) {
+                    let from = rng.gen_range(1..=ACCOUNTS);
+                    let to = (from + rng.gen_range(1..ACCOUNTS) - 1) % ACCOUNTS + 1;
+                    let amount = rng.gen_range(1..=BALANCE / 2);
+                    let result = async {
+                        client.execute("BEGIN").await?;
+                        let balance = client
+                            .execute(&format!("SELECT balance FROM account WHERE id = {}", from))
+                            .await?
+                            .into_value()?
+                            .integer()?;
+                        if balance >= amount {
+                            for (id, delta) in [(from, -amount), (to, amount)] {
+                                client
+                                    .execute(&format!(
+                                        "UPDATE account SET balance = balance + {} WHERE id = {}",
+                                        delta, id
+                                    ))
+                                    .await?;
+                            }
+                        }
+                        client.execute("COMMIT").await
+                    }
+                    .await;
+                    match result {
+                        Ok(_) => committed += 1,
+                        Err(Error::Serialization | Error::Abort | Error::NotLeader(_)) => {
+                            client.execute("ROLLBACK").await.ok();
+                        }
+                        // Reconnect on other errors, e.g. timeouts or crashed nodes.
+                        Err(_) => break,
+                    }
+                }
+            }
+            Ok::<u64, Error>(committed)
+        }
+    }));
+
+    let nemesis = async {
+        // Isolate the leader, then heal the partition.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let leader = cluster.leader(1).await?;
+        network.isolate(leader);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        network.heal();
+
+        // Crash the current leader when it next commits, then restart it.
+        let leader = cluster.leader(leader).await?;
+        fault::inject("crash", Fault::Crash { node: leader, point: "leader.commit".into() });
+        assert!(
+            matches!(cluster.crashed(leader).await, Error::Internal(e) if e.contains("Injected"))
+        );
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        cluster.start(leader).await?;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        stop.store(true, Ordering::Relaxed);
+        Ok(leader)
+    };
+
+    let (committed, leader) = tokio::try_join!(workers, nemesis)?;
+    assert!(committed.iter().sum::<u64>() > 0, "no transfers committed");
+
+    // All nodes should converge on a state that satisfies the invariants.
+    let leader = cluster.leader(leader).await?;
+    let status = cluster.status(leader).await?;
+    for id in 1..=NODES {
+        cluster.wait_applied(id, status.commit_index).await?;
+        assert_row(
+            cluster.execute(id, "SELECT SUM(balance), MIN(balance) >= 0 FROM account").await?,
+            vec![Value::Integer(ACCOUNTS * BALANCE), Value::Boolean(true)],
+        );
+    }
+    Ok(())
+}
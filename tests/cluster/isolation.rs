@@ -141,4 +141,67 @@ async fn anomaly_phantom_read() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// A transaction that was planned against a stale schema, because a concurrent transaction changed
+// it, should abort both when executing further statements and when committing.
+async fn schema_change() -> Result<()> {
+    let (a, b, c, _teardown) = setup::cluster_simple().await?;
+
+    a.execute("BEGIN").await?;
+    a.execute("INSERT INTO test VALUES (1, 'a')").await?;
+    b.execute("CREATE TABLE other (id INTEGER PRIMARY KEY)").await?;
+    assert_eq!(a.execute("INSERT INTO test VALUES (2, 'a')").await, Err(Error::Serialization));
+    a.execute("ROLLBACK").await?;
+
+    a.execute("BEGIN").await?;
+    a.execute("INSERT INTO test VALUES (1, 'a')").await?;
+    b.execute("DROP TABLE other").await?;
+    assert_eq!(a.execute("COMMIT").await, Err(Error::Serialization));
+    assert_rows(c.execute("SELECT * FROM test").await?, vec![]);
+
+    // Concurrent schema changes conflict, while read-only transactions are unaffected.
+    a.execute("BEGIN").await?;
+    b.execute("BEGIN READ ONLY").await?;
+    a.execute("CREATE TABLE other (id INTEGER PRIMARY KEY)").await?;
+    assert_eq!(
+        c.execute("CREATE TABLE another (id INTEGER PRIMARY KEY)").await,
+        Err(Error::Serialization)
+    );
+    a.execute("COMMIT").await?;
+    assert_rows(b.execute("SELECT * FROM test").await?, vec![]);
+    b.execute("COMMIT").await?;
+
+    Ok(())
+}
+
 // FIXME We should test write skew, but we need to implement serializable snapshot isolation first.
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// With stale reads served by followers, clients should still see their own
+// writes, and the writes of clients whose session token they're given.
+async fn stale_read_your_writes() -> Result<()> {
+    let (a, b, c, _teardown) = setup::cluster_simple().await?;
+    for client in [&a, &b, &c] {
+        client.set_stale_reads(true)?;
+    }
+
+    for id in 1..=10 {
+        a.execute(&format!("INSERT INTO test VALUES ({}, 'a')", id)).await?;
+        assert_eq!(a.execute("SELECT COUNT(*) FROM test").await?.into_value()?, Value::Integer(id));
+        b.set_token(a.token());
+        assert_eq!(b.execute("SELECT COUNT(*) FROM test").await?.into_value()?, Value::Integer(id));
+    }
+
+    // Explicit read-only transactions are also stale, and don't change the token.
+    let token = b.token();
+    assert!(token > 0);
+    c.set_token(token);
+    c.execute("BEGIN READ ONLY").await?;
+    assert_eq!(c.execute("SELECT COUNT(*) FROM test").await?.into_value()?, Value::Integer(10));
+    c.execute("COMMIT").await?;
+    assert_eq!(c.token(), token);
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use super::super::setup;
+
+use toydb::error::Result;
+use toydb::raft::{self, NodeID};
+use toydb::sql::engine::LivenessState;
+use toydb::storage::engine::Memory;
+use toydb::{sql, Client, Server};
+
+use futures_util::future::FutureExt as _;
+use serial_test::serial;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// Nodes heartbeat into the replicated state, and a node that stops is reported
+// as dead by the others once it misses enough heartbeats.
+async fn liveness() -> Result<()> {
+    let sql_addr = |id: NodeID| format!("127.0.0.1:{}", 9605 + id as u16);
+    let raft_addr = |id: NodeID| format!("127.0.0.1:{}", 9705 + id as u16);
+    let mut handles = HashMap::new();
+    for id in 1..=3 {
+        let peers = (1..=3).filter(|p| *p != id).map(|p| (p, raft_addr(p))).collect();
+        let server = Server::new(
+            id,
+            peers,
+            raft::Log::new(Memory::new(), false)?,
+            Box::new(sql::engine::Raft::new_state(Memory::new())?),
+        )
+        .await?
+        .liveness(Duration::from_millis(100))
+        .listen(&sql_addr(id), &raft_addr(id))
+        .await?;
+        let (task, handle) = server.serve().remote_handle();
+        tokio::spawn(task);
+        handles.insert(id, handle);
+    }
+
+    // Waits for the nodes to have the given liveness states, as seen by the
+    // given node, returning the last states seen.
+    let wait = |id: NodeID, expect: Vec<LivenessState>| async move {
+        let mut states = Vec::new();
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let Ok(client) = Client::new(&sql_addr(id)).await else { continue };
+            let Ok(nodes) = client.liveness().await else { continue };
+            states = nodes.iter().map(|n| n.state).collect();
+            if states == expect {
+                break;
+            }
+        }
+        states
+    };
+
+    // All nodes become alive.
+    let alive = vec![LivenessState::Alive; 3];
+    assert_eq!(wait(1, alive.clone()).await, alive);
+
+    // Stopping a follower makes it dead, as seen by the other nodes.
+    let leader = Client::new(&sql_addr(1)).await?.status().await?.raft.leader;
+    let follower = (1..=3).find(|id| *id != leader).unwrap();
+    handles.remove(&follower);
+
+    let mut expect = alive.clone();
+    expect[follower as usize - 1] = LivenessState::Dead;
+    assert_eq!(wait(leader, expect.clone()).await, expect);
+    let other = (1..=3).find(|id| *id != leader && *id != follower).unwrap();
+    assert_eq!(wait(other, expect.clone()).await, expect);
+
+    let nodes = Client::new(&sql_addr(leader)).await?.liveness().await?;
+    assert!(nodes[follower as usize - 1].since.unwrap() >= Duration::from_millis(500));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// Liveness requests fail if heartbeats are disabled, which is the default.
+async fn liveness_disabled() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(Vec::new()).await?;
+    assert!(c.liveness().await.is_err());
+    Ok(())
+}
@@ -1,2 +1,12 @@
+mod audit;
+#[cfg(feature = "fault-injection")]
+mod fault;
 mod isolation;
+mod liveness;
+mod proxy;
+mod queue;
+mod ratelimit;
 mod recovery;
+mod redirect;
+mod settings;
+mod standby;
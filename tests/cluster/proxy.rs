@@ -0,0 +1,122 @@
+use super::super::assert_rows;
+
+use toydb::error::Result;
+use toydb::proxy::Proxy;
+use toydb::raft::{self, NodeID};
+use toydb::sql::types::Value;
+use toydb::storage::engine::Memory;
+use toydb::{sql, Client, Server};
+
+use futures_util::future::FutureExt as _;
+use serial_test::serial;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const PROXY_ADDR: &str = "127.0.0.1:9604";
+
+fn sql_addr(id: NodeID) -> String {
+    format!("127.0.0.1:{}", 9605 + id as u16)
+}
+
+fn raft_addr(id: NodeID) -> String {
+    format!("127.0.0.1:{}", 9705 + id as u16)
+}
+
+/// Returns the leader, as seen by the given node.
+async fn leader(id: NodeID) -> NodeID {
+    for _ in 0..50 {
+        if let Ok(status) = async { Client::new(&sql_addr(id)).await?.status().await }.await {
+            if status.raft.leader > 0 {
+                return status.raft.leader;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("node {} found no leader", id)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// A proxy routes writes to the leader and stale reads to followers, and follows
+// the leader when it changes.
+async fn proxy() -> Result<()> {
+    let mut nodes = HashMap::new();
+    for id in 1..=3 {
+        let peers = (1..=3).filter(|p| *p != id).map(|p| (p, raft_addr(p))).collect();
+        let server = Server::new(
+            id,
+            peers,
+            raft::Log::new(Memory::new(), false)?,
+            Box::new(sql::engine::Raft::new_state(Memory::new())?),
+        )
+        .await?
+        .listen(&sql_addr(id), &raft_addr(id))
+        .await?;
+        let (task, handle) = server.serve().remote_handle();
+        tokio::spawn(task);
+        nodes.insert(id, handle);
+    }
+    let proxy = Proxy::new((1..=3).map(sql_addr).collect())
+        .interval(Duration::from_millis(100))
+        .listen(PROXY_ADDR)
+        .await?;
+    let (task, _proxy) = proxy.serve().remote_handle();
+    tokio::spawn(task);
+    let leader = leader(1).await;
+
+    // Statements are routed to the leader, retrying until the proxy finds it.
+    let c = Client::new(PROXY_ADDR).await?;
+    c.execute("CREATE TABLE test (id INTEGER PRIMARY KEY)").await?;
+    c.execute("INSERT INTO test VALUES (1)").await?;
+    assert_eq!(c.status().await?.raft.server, leader);
+
+    // Transactions stay on the node they began on.
+    c.execute("BEGIN").await?;
+    c.execute("INSERT INTO test VALUES (2)").await?;
+    assert_eq!(c.status().await?.raft.server, leader);
+    c.execute("COMMIT").await?;
+
+    // Stale reads are routed to followers, and see the client's writes via
+    // the session token.
+    let s = Client::new(PROXY_ADDR).await?;
+    s.set_stale_reads(true)?;
+    s.set_token(c.status().await?.raft.commit_index);
+    for _ in 0..2 {
+        assert_rows(
+            s.execute("SELECT * FROM test").await?,
+            vec![vec![Value::Integer(1)], vec![Value::Integer(2)]],
+        );
+        assert_ne!(s.status().await?.raft.server, leader);
+    }
+
+    // When the leader is lost, the proxy routes to the new leader.
+    nodes.remove(&leader);
+    let other = (1..=3).find(|id| *id != leader).unwrap();
+    let new_leader = leader_except(other, leader).await;
+    for _ in 0..50 {
+        if c.status().await.is_ok_and(|status| status.raft.server == new_leader) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    c.execute("INSERT INTO test VALUES (3)").await?;
+    assert_rows(
+        c.execute("SELECT * FROM test").await?,
+        vec![vec![Value::Integer(1)], vec![Value::Integer(2)], vec![Value::Integer(3)]],
+    );
+    assert_eq!(c.status().await?.raft.server, new_leader);
+
+    Ok(())
+}
+
+/// Returns the leader as seen by the given node, once it's not the given old
+/// leader.
+async fn leader_except(id: NodeID, old: NodeID) -> NodeID {
+    for _ in 0..50 {
+        match leader(id).await {
+            leader if leader != old => return leader,
+            _ => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+    panic!("node {} found no new leader", id)
+}
@@ -0,0 +1,110 @@
+use toydb::error::{Error, Result};
+use toydb::protocol;
+use toydb::server::{Request, Response};
+use toydb::sql::types::Value;
+use toydb::{Client, Server};
+
+use futures::{SinkExt as _, TryStreamExt as _};
+use futures_util::future::FutureExt as _;
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+// Requests beyond the concurrency limit wait in the query queue and time out
+// with a retryable error, unless the session has system priority.
+async fn query_queue() -> Result<()> {
+    let server = Server::memory()
+        .await?
+        .query_queue(1, Duration::from_millis(200))
+        .admin_hosts(HashSet::from(["127.0.0.1".parse()?]));
+    let server = server.listen("127.0.0.1:0", "127.0.0.1:0").await?;
+    let addr = server.sql_addr()?;
+    let (task, _abort) = server.serve().remote_handle();
+    tokio::spawn(task);
+
+    // Start a large query without reading its rows, so the session holds the
+    // only slot while it's blocked on sending them.
+    let mut socket = TcpStream::connect(addr).await?;
+    let mut handshake = b"toyd".to_vec();
+    handshake.extend(protocol::MIN_VERSION.to_be_bytes());
+    handshake.extend(protocol::VERSION.to_be_bytes());
+    socket.write_all(&handshake).await?;
+    socket.read_exact(&mut [0; 12]).await?;
+    let mut blocker = tokio_serde::Framed::new(
+        Framed::new(socket, LengthDelimitedCodec::new()),
+        tokio_serde::formats::Bincode::<Result<Response>, Request>::default(),
+    );
+    blocker.send(Request::Execute("SELECT * FROM generate_series(1, 10000000)".into())).await?;
+    match blocker.try_next().await? {
+        Some(Ok(Response::Execute(..))) => {}
+        response => panic!("unexpected response {:?}", response),
+    }
+
+    // Other sessions time out in the queue, but can still set variables.
+    let c = Client::new(addr).await?;
+    assert_eq!(c.execute("SELECT 1").await, Err(Error::Overloaded));
+    assert_eq!(
+        c.execute("SET priority = 'urgent'").await,
+        Err(Error::Value("Unknown priority urgent".into()))
+    );
+    c.execute("SET priority = 'high'").await?;
+    assert_eq!(c.execute("SELECT 1").await, Err(Error::Overloaded));
+
+    // System priority bypasses the queue, for sessions from admin hosts.
+    c.execute("SET priority = 'system'").await?;
+    assert_eq!(c.execute("SELECT 1").await?.into_value()?, Value::Integer(1));
+
+    // Once the blocking session disconnects, its slot is released.
+    std::mem::drop(blocker);
+    c.execute("SET priority = 'low'").await?;
+    for attempt in 0.. {
+        match c.execute("SELECT 1").await {
+            Ok(result) => {
+                assert_eq!(result.into_value()?, Value::Integer(1));
+                break;
+            }
+            Err(Error::Overloaded) if attempt < 50 => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+// System priority is only allowed for sessions from admin hosts, in the system
+// tenant.
+async fn query_queue_system_priority() -> Result<()> {
+    for admin_hosts in [vec![], vec!["127.0.0.1".parse()?]] {
+        let privileged = !admin_hosts.is_empty();
+        let server = Server::memory().await?.admin_hosts(HashSet::from_iter(admin_hosts));
+        let server = server.listen("127.0.0.1:0", "127.0.0.1:0").await?;
+        let addr = server.sql_addr()?;
+        let (task, _abort) = server.serve().remote_handle();
+        tokio::spawn(task);
+
+        let c = Client::new(addr).await?;
+        if !privileged {
+            assert_eq!(
+                c.execute("SET priority = 'system'").await,
+                Err(Error::Value("System priority requires a privileged session".into()))
+            );
+            continue;
+        }
+        c.execute("CREATE TENANT t").await?;
+        c.execute("SET tenant = 't'").await?;
+        assert_eq!(
+            c.execute("SET priority = 'system'").await,
+            Err(Error::Value("System priority requires the system tenant".into()))
+        );
+        c.execute("SET tenant = NULL").await?;
+        c.execute("SET priority = 'system'").await?;
+        assert_eq!(
+            c.execute("SET tenant = 't'").await,
+            Err(Error::Value("Can't switch to a tenant with system priority".into()))
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use toydb::error::{Error, Result};
+use toydb::ratelimit::RateLimits;
+use toydb::sql::execution::ResultSet;
+use toydb::{Client, Server};
+
+use futures_util::future::FutureExt as _;
+use std::time::Duration;
+
+/// Executes a query until it's rate limited, returning the number of
+/// successful executions and the time to wait before retrying.
+async fn execute_until_limited(client: &Client, query: &str) -> Result<(u64, Duration)> {
+    for count in 0..100 {
+        match client.execute(query).await {
+            Ok(_) => {}
+            Err(Error::RateLimited(wait)) => return Ok((count, wait)),
+            Err(error) => return Err(error),
+        }
+    }
+    panic!("{} was never rate limited", query)
+}
+
+/// Executes a query, retrying it once the server allows it if rate limited.
+async fn execute_retry(client: &Client, query: &str) -> Result<ResultSet> {
+    loop {
+        match client.execute(query).await {
+            Err(Error::RateLimited(wait)) => tokio::time::sleep(wait).await,
+            result => return result,
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+// Sessions and client hosts are limited to a number of queries and rows written
+// per second, and rejected requests can be retried later. COMMIT and ROLLBACK
+// are never limited, and cluster settings override the server's limits.
+async fn rate_limits() -> Result<()> {
+    let limits = RateLimits { session_qps: 3, session_rows: 10, client_qps: 4, client_rows: 0 };
+    let server = Server::memory().await?.rate_limits(limits).listen("127.0.0.1:0", "127.0.0.1:0");
+    let server = server.await?;
+    let addr = server.sql_addr()?;
+    let (task, _abort) = server.serve().remote_handle();
+    tokio::spawn(task);
+
+    // A session is limited to a burst of 3 queries, but can still commit.
+    let a = Client::new(addr).await?;
+    a.execute("BEGIN").await?;
+    let (count, wait) = execute_until_limited(&a, "SELECT 1").await?;
+    assert!((2..=3).contains(&count), "{}", count);
+    assert!(wait <= Duration::from_millis(334), "{:?}", wait);
+    a.execute("COMMIT").await?;
+
+    // Another session on the same host shares the host's limit of 4 queries,
+    // and is limited before its own burst of 3.
+    let b = Client::new(addr).await?;
+    let (count, _) = execute_until_limited(&b, "SELECT 1").await?;
+    assert!(count < 3, "{}", count);
+
+    // Writing 20 rows exceeds the session's 10 rows per second, such that the
+    // session must wait about a second.
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    a.execute("CREATE TABLE test (id INTEGER PRIMARY KEY)").await?;
+    let values: Vec<_> = (1..=20).map(|i| format!("({})", i)).collect();
+    a.execute(&format!("INSERT INTO test VALUES {}", values.join(", "))).await?;
+    match a.execute("SELECT COUNT(*) FROM test").await {
+        Err(Error::RateLimited(wait)) => {
+            assert!(wait > Duration::from_millis(800) && wait <= Duration::from_secs(1))
+        }
+        result => panic!("expected rate limit error, got {:?}", result),
+    }
+    assert_eq!(
+        execute_retry(&a, "SELECT COUNT(*) FROM test").await?.into_value()?,
+        toydb::sql::types::Value::Integer(20)
+    );
+
+    // Cluster settings override the server's limits, and disable them with 0.
+    for setting in ["rate_limit_session_qps", "rate_limit_session_rows", "rate_limit_client_qps"] {
+        execute_retry(&a, &format!("SET CLUSTER SETTING {} = 0", setting)).await?;
+    }
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    for _ in 0..20 {
+        a.execute("SELECT 1").await?;
+    }
+    Ok(())
+}
@@ -1,9 +1,15 @@
-use super::super::{assert_row, setup};
+use super::super::{assert_row, assert_rows, setup};
 
 use toydb::error::{Error, Result};
+use toydb::raft::{self, NodeID};
 use toydb::sql::types::Value;
+use toydb::storage::engine::Memory;
+use toydb::{sql, Client, Server};
 
+use futures_util::future::FutureExt as _;
 use serial_test::serial;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 #[serial]
@@ -45,3 +51,226 @@ async fn client_commit_error() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// Requests to a leader that has lost its quorum should time out rather than
+// hang, once the client sets a timeout.
+async fn request_timeout() -> Result<()> {
+    let addrs: HashMap<NodeID, (String, String)> = (1..=3)
+        .map(|id| {
+            (
+                id,
+                (
+                    format!("127.0.0.1:{}", 9605 + id as u16),
+                    format!("127.0.0.1:{}", 9705 + id as u16),
+                ),
+            )
+        })
+        .collect();
+    let mut nodes = HashMap::new();
+    for (id, (addr_sql, addr_raft)) in &addrs {
+        let peers = addrs.iter().filter(|(i, _)| *i != id).map(|(i, (_, r))| (*i, r.clone()));
+        nodes.insert(*id, setup::server(*id, addr_sql, addr_raft, peers.collect()).await?);
+    }
+
+    // Find the leader and create a table, then stop the followers. Retry while
+    // the initial election settles.
+    let mut leader = 0;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let result = async {
+            leader = Client::new(&addrs[&1].0).await?.status().await?.raft.leader;
+            let c = Client::new(&addrs[&leader].0).await?;
+            c.execute("CREATE TABLE test (id INTEGER PRIMARY KEY)").await
+        };
+        match result.await {
+            Ok(_) => break,
+            Err(Error::NotLeader(_) | Error::Abort) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    let c = Client::new(&addrs[&leader].0).await?;
+    nodes.retain(|id, _| *id == leader);
+
+    let timeout = Duration::from_millis(500);
+    c.set_timeout(Some(timeout))?;
+    let start = Instant::now();
+    assert!(matches!(c.execute("INSERT INTO test VALUES (1)").await, Err(Error::Timeout)));
+    assert!(matches!(c.execute("SELECT * FROM test").await, Err(Error::Timeout)));
+    assert!(start.elapsed() >= 2 * timeout);
+    assert!(start.elapsed() < 4 * timeout);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// A node that joins after the log has been compacted should catch up via a
+// snapshot of the leader's state.
+async fn snapshot_catchup() -> Result<()> {
+    let sql_addr = |id: NodeID| format!("127.0.0.1:{}", 9605 + id as u16);
+    let raft_addr = |id: NodeID| format!("127.0.0.1:{}", 9705 + id as u16);
+    let mut nodes = HashMap::new();
+    let start = |id: NodeID| async move {
+        let peers = (1..=3).filter(|p| *p != id).map(|p| (p, raft_addr(p))).collect();
+        let mut log = raft::Log::new(Memory::new(), false)?;
+        log.set_snapshot_interval(5);
+        let state = sql::engine::Raft::new_state(Memory::new())?;
+        let server = Server::new(id, peers, log, Box::new(state))
+            .await?
+            .listen(&sql_addr(id), &raft_addr(id))
+            .await?;
+        let (task, handle) = server.serve().remote_handle();
+        tokio::spawn(task);
+        Ok::<_, Error>((id, handle))
+    };
+
+    // Start nodes 1 and 2, and write enough data to compact their logs.
+    for id in 1..=2 {
+        let (id, handle) = start(id).await?;
+        nodes.insert(id, handle);
+    }
+    let mut c = None;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let result = async {
+            let c = Client::new(&sql_addr(1)).await?;
+            c.execute("CREATE TABLE test (id INTEGER PRIMARY KEY)").await?;
+            Ok::<_, Error>(c)
+        };
+        match result.await {
+            Ok(client) => {
+                c = Some(client);
+                break;
+            }
+            Err(Error::NotLeader(_) | Error::Abort) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    let c = c.expect("no leader elected");
+    for id in 1..=20 {
+        c.execute(&format!("INSERT INTO test VALUES ({})", id)).await?;
+    }
+    let commit_index = c.status().await?.raft.commit_index;
+
+    // Start node 3 with an empty log, and wait for it to catch up.
+    let (id, handle) = start(3).await?;
+    nodes.insert(id, handle);
+    let mut applied = false;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        if let Ok(status) = async { Client::new(&sql_addr(3)).await?.status().await }.await {
+            if status.raft.apply_index >= commit_index {
+                applied = true;
+                break;
+            }
+        }
+    }
+    assert!(applied, "node 3 did not catch up to index {}", commit_index);
+
+    // Stop the leader. Node 3 must now take part in the quorum, and the data
+    // is intact.
+    let leader = c.status().await?.raft.leader;
+    nodes.remove(&leader);
+    let other = (1..=3).find(|id| *id != leader && *id != 3).unwrap();
+    let mut result = Err(Error::Abort);
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        result = async { Client::new(&sql_addr(other)).await?.execute("SELECT * FROM test").await }
+            .await;
+        if result.is_ok() {
+            break;
+        }
+    }
+    assert_rows(result?, (1..=20).map(|id| vec![Value::Integer(id)]).collect());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// A node that joins far behind the log should catch up with a catch-up
+// bandwidth limit, while foreground writes continue.
+async fn throttled_catchup() -> Result<()> {
+    let sql_addr = |id: NodeID| format!("127.0.0.1:{}", 9605 + id as u16);
+    let raft_addr = |id: NodeID| format!("127.0.0.1:{}", 9705 + id as u16);
+    let mut nodes = HashMap::new();
+    let start = |id: NodeID| async move {
+        let peers = (1..=3).filter(|p| *p != id).map(|p| (p, raft_addr(p))).collect();
+        let log = raft::Log::new(Memory::new(), false)?;
+        let state = sql::engine::Raft::new_state(Memory::new())?;
+        let server = Server::new(id, peers, log, Box::new(state))
+            .await?
+            .catchup_bandwidth(50_000)
+            .listen(&sql_addr(id), &raft_addr(id))
+            .await?;
+        let (task, handle) = server.serve().remote_handle();
+        tokio::spawn(task);
+        Ok::<_, Error>((id, handle))
+    };
+
+    // Start nodes 1 and 2, and write enough data that node 3 lags far behind.
+    for id in 1..=2 {
+        let (id, handle) = start(id).await?;
+        nodes.insert(id, handle);
+    }
+    let mut c = None;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let result = async {
+            let c = Client::new(&sql_addr(1)).await?;
+            c.execute("CREATE TABLE test (id INTEGER PRIMARY KEY)").await?;
+            Ok::<_, Error>(c)
+        };
+        match result.await {
+            Ok(client) => {
+                c = Some(client);
+                break;
+            }
+            Err(Error::NotLeader(_) | Error::Abort) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    let c = c.expect("no leader elected");
+    for id in 1..=200 {
+        c.execute(&format!("INSERT INTO test VALUES ({})", id)).await?;
+    }
+
+    // Start node 3 with an empty log. Writes continue while it catches up.
+    let (id, handle) = start(3).await?;
+    nodes.insert(id, handle);
+    for id in 201..=220 {
+        c.execute(&format!("INSERT INTO test VALUES ({})", id)).await?;
+    }
+    let commit_index = c.status().await?.raft.commit_index;
+    let mut applied = false;
+    for _ in 0..100 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        if let Ok(status) = async { Client::new(&sql_addr(3)).await?.status().await }.await {
+            if status.raft.apply_index >= commit_index {
+                applied = true;
+                break;
+            }
+        }
+    }
+    assert!(applied, "node 3 did not catch up to index {}", commit_index);
+
+    // Stop the leader. Node 3 must now take part in the quorum, and the data
+    // is intact.
+    let leader = c.status().await?.raft.leader;
+    nodes.remove(&leader);
+    let other = (1..=3).find(|id| *id != leader && *id != 3).unwrap();
+    let mut result = Err(Error::Abort);
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        result = async { Client::new(&sql_addr(other)).await?.execute("SELECT * FROM test").await }
+            .await;
+        if result.is_ok() {
+            break;
+        }
+    }
+    assert_rows(result?, (1..=220).map(|id| vec![Value::Integer(id)]).collect());
+
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use super::super::assert_rows;
+
+use toydb::error::Result;
+use toydb::raft::{self, NodeID};
+use toydb::sql::types::Value;
+use toydb::storage::engine::Memory;
+use toydb::{sql, Client, Server};
+
+use futures_util::future::FutureExt as _;
+use serial_test::serial;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// Followers configured with the peers' SQL addresses redirect clients to the
+// leader, which they transparently reconnect to. Stale reads are still served
+// by the follower.
+async fn redirect_to_leader() -> Result<()> {
+    let sql_addr = |id: NodeID| format!("127.0.0.1:{}", 9605 + id as u16);
+    let raft_addr = |id: NodeID| format!("127.0.0.1:{}", 9705 + id as u16);
+    let mut handles = Vec::new();
+    for id in 1..=3 {
+        let peers = (1..=3).filter(|p| *p != id).map(|p| (p, raft_addr(p))).collect();
+        let server = Server::new(
+            id,
+            peers,
+            raft::Log::new(Memory::new(), false)?,
+            Box::new(sql::engine::Raft::new_state(Memory::new())?),
+        )
+        .await?
+        .redirect((1..=3).map(|p| (p, sql_addr(p))).collect::<HashMap<_, _>>())
+        .listen(&sql_addr(id), &raft_addr(id))
+        .await?;
+        let (task, handle) = server.serve().remote_handle();
+        tokio::spawn(task);
+        handles.push(handle);
+    }
+
+    // Wait for a leader, and connect to a follower.
+    let mut leader = 0;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        if let Ok(status) = async { Client::new(&sql_addr(1)).await?.status().await }.await {
+            leader = status.raft.leader;
+            if leader > 0 {
+                break;
+            }
+        }
+    }
+    assert!(leader > 0, "no leader elected");
+    let follower = (1..=3).find(|id| *id != leader).unwrap();
+    let c = Client::new(&sql_addr(follower)).await?;
+    assert_eq!(c.status().await?.raft.server, follower);
+
+    // Statements are redirected to the leader, and the client stays there.
+    c.execute("CREATE TABLE test (id INTEGER PRIMARY KEY)").await?;
+    assert_eq!(c.status().await?.raft.server, leader);
+    c.execute("INSERT INTO test VALUES (1)").await?;
+
+    // Stale reads are served by the follower without redirects.
+    let s = Client::new(&sql_addr(follower)).await?;
+    s.set_stale_reads(true)?;
+    s.set_token(c.status().await?.raft.commit_index);
+    assert_rows(s.execute("SELECT * FROM test").await?, vec![vec![Value::Integer(1)]]);
+    assert_eq!(s.status().await?.raft.server, follower);
+
+    Ok(())
+}
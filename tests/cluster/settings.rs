@@ -0,0 +1,49 @@
+use super::super::setup;
+
+use toydb::error::{Error, Result};
+use toydb::Client;
+
+use serial_test::serial;
+use std::time::Duration;
+
+/// Inserts rows with a 100-byte value and increasing IDs after the given one,
+/// until the result matches the expectation. Returns the last result.
+async fn insert_until(client: &Client, id: &mut i64, ok: bool) -> Result<()> {
+    let mut result = Ok(());
+    for _ in 0..50 {
+        *id += 1;
+        let query = format!("INSERT INTO test VALUES ({}, '{}')", id, "x".repeat(100));
+        result = client.execute(&query).await.map(|_| ());
+        if result.is_ok() == ok {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    result
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// Cluster settings take effect on all nodes without restarts.
+async fn settings() -> Result<()> {
+    let (clients, _teardown) = setup::cluster_with_clients(
+        3,
+        vec!["CREATE TABLE test (id INTEGER PRIMARY KEY, value STRING)"],
+    )
+    .await?;
+
+    let mut id = 0;
+    clients[0].execute("SET CLUSTER SETTING max_row_size = 64").await?;
+    for client in &clients {
+        match insert_until(client, &mut id, false).await {
+            Err(Error::Value(message)) => assert!(message.starts_with("Row size"), "{}", message),
+            result => panic!("expected row size error, got {:?}", result),
+        }
+    }
+
+    clients[1].execute("SET CLUSTER SETTING max_row_size = DEFAULT").await?;
+    for client in &clients {
+        insert_until(client, &mut id, true).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use toydb::error::{Error, Result};
+use toydb::raft;
+use toydb::sql::types::Value;
+use toydb::storage::engine::Memory;
+use toydb::{sql, Client, Server};
+
+use futures_util::future::{FutureExt as _, RemoteHandle};
+use serial_test::serial;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Starts a single-node server, optionally as a standby of the given primary.
+async fn start(
+    sql_addr: &str,
+    raft_addr: &str,
+    standby_of: Option<&str>,
+) -> Result<RemoteHandle<Result<()>>> {
+    let mut log = raft::Log::new(Memory::new(), false)?;
+    log.set_snapshot_interval(5);
+    let mut server =
+        Server::new(1, HashMap::new(), log, Box::new(sql::engine::Raft::new_state(Memory::new())?))
+            .await?;
+    if let Some(primary) = standby_of {
+        server = server.standby_of(primary);
+    }
+    let (task, handle) = server.listen(sql_addr, raft_addr).await?.serve().remote_handle();
+    tokio::spawn(task);
+    Ok(handle)
+}
+
+/// Waits for the standby to return the given rows for the query.
+async fn wait_rows(client: &Client, query: &str, expect: Vec<Vec<Value>>) -> Result<()> {
+    let mut rows = Vec::new();
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        rows = match client.execute(query).await {
+            Ok(sql::execution::ResultSet::Query { rows, .. }) => rows.collect::<Result<_>>()?,
+            Ok(resultset) => panic!("Unexpected result {:?}", resultset),
+            Err(_) => continue,
+        };
+        if rows == expect {
+            return Ok(());
+        }
+    }
+    assert_eq!(rows, expect);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// A standby cluster replicates a primary cluster's writes, starting from a
+// snapshot if the primary has compacted its log, and only serves reads until
+// it's promoted.
+async fn standby() -> Result<()> {
+    let _primary = start("127.0.0.1:9605", "127.0.0.1:9705", None).await?;
+    let primary = Client::new("127.0.0.1:9605").await?;
+    primary.execute("CREATE TABLE test (id INTEGER PRIMARY KEY, value STRING)").await?;
+    for id in 1..=10 {
+        primary.execute(&format!("INSERT INTO test VALUES ({}, 'a')", id)).await?;
+    }
+
+    // The primary compacted its log, so the standby begins from a snapshot.
+    let (snapshot, entries) = primary.read_log(1, 10).await?;
+    assert!(snapshot.is_some());
+    assert!(entries.len() <= 10);
+
+    let _standby = start("127.0.0.1:9606", "127.0.0.1:9706", Some("127.0.0.1:9605")).await?;
+    let standby = Client::new("127.0.0.1:9606").await?;
+    let count = "SELECT COUNT(*) FROM test";
+    wait_rows(&standby, count, vec![vec![Value::Integer(10)]]).await?;
+
+    // Later writes are replicated, but the standby rejects its own.
+    primary.execute("UPDATE test SET value = 'b' WHERE id = 1").await?;
+    primary.execute("INSERT INTO test VALUES (11, 'c')").await?;
+    wait_rows(&standby, count, vec![vec![Value::Integer(11)]]).await?;
+    wait_rows(
+        &standby,
+        "SELECT value FROM test WHERE id = 1",
+        vec![vec![Value::String("b".into())]],
+    )
+    .await?;
+    assert_eq!(standby.execute("INSERT INTO test VALUES (12, 'd')").await, Err(Error::ReadOnly));
+
+    // Once promoted, the standby takes writes and stops replicating.
+    assert!(standby.promote().await? > 0);
+    assert!(standby.promote().await.is_err());
+    standby.execute("INSERT INTO test VALUES (12, 'd')").await?;
+    primary.execute("INSERT INTO test VALUES (13, 'e')").await?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    wait_rows(
+        &standby,
+        "SELECT id FROM test WHERE id > 10",
+        vec![vec![Value::Integer(11)], vec![Value::Integer(12)]],
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use super::{assert_row, assert_rows};
+
+use toydb::error::{Error, Result};
+use toydb::sql::types::Value;
+use toydb::Database;
+
+use pretty_assertions::assert_eq;
+use tempdir::TempDir;
+
+#[test]
+// Data written to a database file should be visible when it's reopened.
+fn open_reopen() -> Result<()> {
+    let dir = TempDir::new("toydb")?;
+    let path = dir.path().join("data").join("toydb.db");
+
+    let db = Database::open(&path)?;
+    db.execute("CREATE TABLE movies (id INTEGER PRIMARY KEY, title STRING NOT NULL)")?;
+    db.execute("INSERT INTO movies VALUES (1, 'Sicario'), (2, 'Stalker')")?;
+    drop(db);
+
+    let db = Database::open(&path)?;
+    assert_eq!(db.list_tables()?, vec!["movies".to_string()]);
+    assert_eq!(db.get_table("movies")?.columns.len(), 2);
+    assert_rows(
+        db.execute("SELECT * FROM movies")?,
+        vec![
+            vec![Value::Integer(1), Value::String("Sicario".into())],
+            vec![Value::Integer(2), Value::String("Stalker".into())],
+        ],
+    );
+    Ok(())
+}
+
+#[test]
+// with_txn() should commit on success and roll back on errors.
+fn with_txn() -> Result<()> {
+    let db = Database::memory();
+    db.execute("CREATE TABLE test (id INTEGER PRIMARY KEY)")?;
+
+    let version = db.with_txn(|session| {
+        session.execute("INSERT INTO test VALUES (1), (2)")?;
+        Ok(session.transaction().unwrap().version)
+    })?;
+    assert!(version > 0);
+
+    let result: Result<()> = db.with_txn(|session| {
+        session.execute("INSERT INTO test VALUES (3)")?;
+        session.execute("INSERT INTO test VALUES (1)")?;
+        Ok(())
+    });
+    assert!(matches!(result, Err(Error::Constraint { name, .. }) if name == "test_pkey"));
+    assert_row(db.execute("SELECT COUNT(*) FROM test")?, vec![Value::Integer(2)]);
+    Ok(())
+}
+
+#[test]
+// Concurrent sessions should be isolated, and with_txn() should retry
+// serialization failures.
+fn concurrent() -> Result<()> {
+    let db = Database::memory();
+    db.execute("CREATE TABLE counter (id INTEGER PRIMARY KEY, value INTEGER)")?;
+    db.execute("INSERT INTO counter VALUES (1, 0)")?;
+
+    let mut a = db.session()?;
+    a.execute("BEGIN")?;
+    a.execute("UPDATE counter SET value = value + 1")?;
+    assert!(matches!(
+        db.execute("UPDATE counter SET value = value + 1"),
+        Err(Error::Serialization)
+    ));
+    a.execute("COMMIT")?;
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let db = db.clone();
+            std::thread::spawn(move || {
+                db.with_txn(|session| session.execute("UPDATE counter SET value = value + 1"))
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap()?;
+    }
+    assert_row(db.execute("SELECT value FROM counter")?, vec![Value::Integer(5)]);
+    Ok(())
+}
@@ -0,0 +1,306 @@
+//! Runs SQL logic test files (in the sqllogictest format) under tests/slt/
+//! against an in-process cluster, dropping all tables between files. This
+//! supports the commonly used subset of the format:
+//!
+//! - `statement ok` and `statement error [message]`, followed by a statement.
+//! - `query <types> [nosort|rowsort|valuesort] [label]`, followed by a query,
+//!   a `----` separator, and the expected results. Results are given either as
+//!   one value per line, one space-separated row per line, or as
+//!   `<n> values hashing to <md5>`. Column types are I (integer), R (float
+//!   with 3 decimals), and T (text).
+//! - `skipif <db>` and `onlyif <db>` conditions, where toyDB's name is toydb.
+//! - `halt`, to stop processing the file. `hash-threshold` is ignored.
+//!
+//! Set TOYDB_SLT to a file or directory path to run other files instead, e.g.
+//! an external test suite.
+
+use super::setup;
+
+use toydb::error::{Error, Result};
+use toydb::sql::execution::ResultSet;
+use toydb::sql::parser::format_ident;
+use toydb::sql::types::Value;
+use toydb::Client;
+
+use serial_test::serial;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn slt() -> Result<()> {
+    let path = match std::env::var("TOYDB_SLT") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/slt"),
+    };
+    let mut files = match path.is_dir() {
+        true => std::fs::read_dir(&path)?
+            .map(|entry| Ok(entry?.path()))
+            .filter(|p| !matches!(p, Ok(p) if p.extension() != Some("slt".as_ref())))
+            .collect::<Result<Vec<_>>>()?,
+        false => vec![path],
+    };
+    files.sort();
+    assert!(!files.is_empty(), "no .slt files found");
+
+    // Files run on the same cluster, but any open transaction is rolled back
+    // and all tables are dropped between them.
+    let (mut clients, _teardown) = setup::cluster_with_clients(3, vec![]).await?;
+    let client = clients.remove(0);
+    let mut failures = Vec::new();
+    for file in files {
+        let script = std::fs::read_to_string(&file)?;
+        for record in parse(&script) {
+            if let Err(message) = record.run(&client).await {
+                failures.push(format!("{}:{}: {}", file.display(), record.line, message));
+            }
+        }
+        if client.txn().is_some() {
+            client.execute("ROLLBACK").await?;
+        }
+        drop_tables(&client).await?;
+    }
+    assert!(failures.is_empty(), "{} failures:\n\n{}", failures.len(), failures.join("\n\n"));
+    Ok(())
+}
+
+/// Drops all tables. Tables referenced by other tables can't be dropped, so
+/// this keeps retrying the remaining tables until all are dropped.
+async fn drop_tables(client: &Client) -> Result<()> {
+    let mut tables = client.list_tables().await?;
+    while !tables.is_empty() {
+        let mut remaining = Vec::new();
+        for table in &tables {
+            if client.execute(&format!("DROP TABLE {}", format_ident(table))).await.is_err() {
+                remaining.push(table.clone());
+            }
+        }
+        if remaining.len() == tables.len() {
+            return Err(Error::Internal(format!("Can't drop tables {:?}", remaining)));
+        }
+        tables = remaining;
+    }
+    Ok(())
+}
+
+/// A test record.
+struct Record {
+    /// The line number of the record.
+    line: usize,
+    /// The SQL statement or query.
+    sql: String,
+    kind: RecordKind,
+}
+
+enum RecordKind {
+    /// A statement expected to succeed.
+    Ok,
+    /// A statement expected to fail, optionally with an error message
+    /// containing the given string.
+    Error(Option<String>),
+    /// A query with the given column types, sort mode, and expected results.
+    Query { types: Vec<char>, sort: Sort, expect: Vec<String> },
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Sort {
+    None,
+    Row,
+    Value,
+}
+
+/// Parses test records from a script, skipping records that don't apply to
+/// toyDB. Panics on syntax errors.
+fn parse(script: &str) -> Vec<Record> {
+    let mut records = Vec::new();
+    let mut lines = script.lines().enumerate().map(|(i, l)| (i + 1, l.trim_end())).peekable();
+    let mut skip = false;
+    while let Some((line, text)) = lines.next() {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let kind = match words.as_slice() {
+            [] => continue,
+            [w, ..] if w.starts_with('#') => continue,
+            ["halt", ..] => break,
+            ["hash-threshold", ..] => continue,
+            ["skipif", db, ..] => {
+                skip |= *db == "toydb";
+                continue;
+            }
+            ["onlyif", db, ..] => {
+                skip |= *db != "toydb";
+                continue;
+            }
+            ["statement", "ok", ..] => RecordKind::Ok,
+            ["statement", "error", message @ ..] => {
+                RecordKind::Error(Some(message.join(" ")).filter(|m| !m.is_empty()))
+            }
+            ["query", types, rest @ ..] => {
+                let sort = match rest.first() {
+                    Some(&"rowsort") => Sort::Row,
+                    Some(&"valuesort") => Sort::Value,
+                    _ => Sort::None,
+                };
+                RecordKind::Query { types: types.chars().collect(), sort, expect: Vec::new() }
+            }
+            _ => panic!("line {}: invalid record {:?}", line, text),
+        };
+
+        let mut sql = Vec::new();
+        while let Some((_, text)) = lines.next_if(|(_, l)| !l.is_empty() && *l != "----") {
+            sql.push(text);
+        }
+        let mut record = Record { line, sql: sql.join("\n"), kind };
+        if let RecordKind::Query { expect, .. } = &mut record.kind {
+            if lines.next_if(|(_, l)| *l == "----").is_some() {
+                while let Some((_, text)) = lines.next_if(|(_, l)| !l.is_empty()) {
+                    expect.push(text.trim().to_string());
+                }
+            }
+        }
+        if !std::mem::take(&mut skip) {
+            records.push(record);
+        }
+    }
+    records
+}
+
+impl Record {
+    /// Runs the record, returning a failure message if it fails.
+    async fn run(&self, client: &Client) -> std::result::Result<(), String> {
+        let result = client.execute(&self.sql).await;
+        match (&self.kind, result) {
+            (RecordKind::Ok, Ok(_)) => Ok(()),
+            (RecordKind::Ok, Err(err)) => Err(format!("{}\nunexpected error: {}", self.sql, err)),
+
+            (RecordKind::Error(None), Err(_)) => Ok(()),
+            (RecordKind::Error(Some(m)), Err(err)) if err.to_string().contains(m) => Ok(()),
+            (RecordKind::Error(Some(m)), Err(err)) => {
+                Err(format!("{}\nexpected error {:?}, got: {}", self.sql, m, err))
+            }
+            (RecordKind::Error(_), Ok(_)) => Err(format!("{}\nexpected error", self.sql)),
+
+            (RecordKind::Query { types, sort, expect }, Ok(ResultSet::Query { rows, .. })) => {
+                let rows = rows.collect::<Result<Vec<_>>>().map_err(|e| e.to_string())?;
+                let mut rows: Vec<Vec<String>> = rows
+                    .into_iter()
+                    .map(|row| {
+                        row.iter()
+                            .enumerate()
+                            .map(|(i, v)| format_value(v, types.get(i).copied().unwrap_or('T')))
+                            .collect()
+                    })
+                    .collect();
+                if *sort == Sort::Row {
+                    rows.sort();
+                }
+                let mut values: Vec<String> = rows.iter().flatten().cloned().collect();
+                if *sort == Sort::Value {
+                    values.sort();
+                }
+                if matches(expect, &rows, &values, *sort) {
+                    return Ok(());
+                }
+                Err(format!(
+                    "{}\nexpected:\n{}\ngot:\n{}",
+                    self.sql,
+                    expect.join("\n"),
+                    rows.iter().map(|r| r.join(" ")).collect::<Vec<_>>().join("\n")
+                ))
+            }
+            (RecordKind::Query { .. }, Ok(r)) => {
+                Err(format!("{}\nunexpected result {:?}", self.sql, r))
+            }
+            (RecordKind::Query { .. }, Err(err)) => {
+                Err(format!("{}\nunexpected error: {}", self.sql, err))
+            }
+        }
+    }
+}
+
+/// Checks whether query results match the expected results, in any of the
+/// supported forms.
+fn matches(expect: &[String], rows: &[Vec<String>], values: &[String], sort: Sort) -> bool {
+    if let [line] = expect {
+        if let [n, "values", "hashing", "to", hash] = line.split(' ').collect::<Vec<_>>()[..] {
+            let data: String = values.iter().map(|v| v.clone() + "\n").collect();
+            return n == values.len().to_string() && hash == md5(data.as_bytes());
+        }
+    }
+    if expect == values {
+        return true;
+    }
+    // Rows can't be compared when values are sorted individually.
+    sort != Sort::Value
+        && expect.iter().eq(rows.iter().map(|r| r.join(" ")).collect::<Vec<_>>().iter())
+}
+
+/// Formats a value for the given column type.
+fn format_value(value: &Value, datatype: char) -> String {
+    match (value, datatype) {
+        (Value::Null, _) => "NULL".into(),
+        (Value::Boolean(b), 'I') => (*b as i64).to_string(),
+        (Value::Boolean(b), _) => b.to_string(),
+        (Value::Integer(i), 'R') => format!("{:.3}", *i as f64),
+        (Value::Integer(i), _) => i.to_string(),
+        (Value::Float(f), 'I') => (f.trunc() as i64).to_string(),
+        (Value::Float(f), _) => format!("{:.3}", f),
+        (Value::String(s), _) if s.is_empty() => "(empty)".into(),
+        (Value::String(s), _) => s.chars().map(|c| if c.is_control() { '@' } else { c }).collect(),
+    }
+}
+
+/// Computes the hex-encoded MD5 digest of the given data, as used for result
+/// hashes in sqllogictest files.
+fn md5(data: &[u8]) -> String {
+    const S: [u32; 64] = [
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5,
+        9, 14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10,
+        15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+    ];
+    let k: Vec<u32> =
+        (0..64).map(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32).collect();
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend(((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    for chunk in message.chunks(64) {
+        let m: Vec<u32> =
+            chunk.chunks(4).map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]])).collect();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(k[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(S[i]));
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+    state.iter().flat_map(|s| s.to_le_bytes()).fold(String::new(), |mut hex, b| {
+        write!(hex, "{:02x}", b).expect("write failed");
+        hex
+    })
+}
+
+#[test]
+fn md5_digest() {
+    assert_eq!(md5(b""), "d41d8cd98f00b204e9800998ecf8427e");
+    assert_eq!(
+        md5(b"The quick brown fox jumps over the lazy dog"),
+        "9e107d9d372bb6826bd81d3542a419d6"
+    );
+    assert_eq!(md5(&[b'a'; 100]), "36a92cc94a9e0fa21f625f8bfb007adf");
+}
@@ -1,6 +1,7 @@
 //! Evaluates SQL expressions and compares with expectations.
 use toydb::error::{Error, Result};
 use toydb::sql::engine::Engine;
+use toydb::sql::execution::ResultSet;
 use toydb::sql::types::Value;
 
 fn eval_expr(expr: &str) -> Result<Value> {
@@ -35,13 +36,20 @@ test_expr! {
     const_case: "TrUe" => Ok(Boolean(true)),
     const_false: "FALSE" => Ok(Boolean(false)),
     const_infinity: "INFINITY" => Ok(Float(std::f64::INFINITY)),
+    const_infinity_case: "Infinity" => Ok(Float(std::f64::INFINITY)),
+    const_infinity_neg: "-INFINITY" => Ok(Float(std::f64::NEG_INFINITY)),
     const_nan: "NAN" => Ok(Float(std::f64::NAN)),
+    const_nan_case: "NaN" => Ok(Float(std::f64::NAN)),
     const_null: "NULL" => Ok(Null),
     const_true: "TRUE" => Ok(Boolean(true)),
 
     lit_float: "3.72" => Ok(Float(3.72)),
     lit_float_exp: "3.14e3" => Ok(Float(3140.0)),
     lit_float_exp_neg: "2.718E-2" => Ok(Float(0.02718)),
+    lit_float_exp_plus: "2.5e+3" => Ok(Float(2500.0)),
+    lit_float_exp_integer: "5e2" => Ok(Float(500.0)),
+    lit_float_exp_empty: "5e" => Err(Error::Syntax { message: "invalid float literal".into(), line: 1, column: 8 }),
+    lit_float_exp_negated: "-1.5e3" => Ok(Float(-1500.0)),
     lit_float_no_decimal: "3." => Ok(Float(3.0)),
     lit_float_zero_decimal: "3.0" => Ok(Float(3.0)),
     lit_float_max: "1.23456789012345e308" => Ok(Float(1.234_567_890_123_45e308)),
@@ -58,8 +66,18 @@ test_expr! {
     lit_integer_zeroprefix: "03" => Ok(Integer(3)),
     lit_integer_max: "9223372036854775807" => Ok(Integer(9_223_372_036_854_775_807)),
     lit_integer_min: "-9223372036854775807" => Ok(Integer(-9_223_372_036_854_775_807)),
-    lit_integer_overflow: "9223372036854775808" => Err(Error::Parse("number too large to fit in target type".into())),
-    lit_integer_underflow: "-9223372036854775808" => Err(Error::Parse("number too large to fit in target type".into())),
+    lit_integer_overflow: "9223372036854775808" => Err(Error::Syntax { message: "number too large to fit in target type".into(), line: 1, column: 8 }),
+    lit_integer_min_exact: "-9223372036854775808" => Ok(Integer(i64::MIN)),
+    lit_integer_min_negated: "- -9223372036854775808" => Err(Error::Value("Integer overflow".into())),
+    lit_integer_min_paren: "-(9223372036854775808)" => Err(Error::Syntax { message: "number too large to fit in target type".into(), line: 1, column: 10 }),
+    lit_integer_underflow: "-9223372036854775809" => Err(Error::Syntax { message: "number too small to fit in target type".into(), line: 1, column: 9 }),
+    lit_integer_negated_exponent: "-2 ^ 2" => Ok(Integer(4)),
+    lit_integer_hex: "0x1F" => Ok(Integer(31)),
+    lit_integer_hex_upper: "0XfF" => Ok(Integer(255)),
+    lit_integer_hex_max: "0x7fffffffffffffff" => Ok(Integer(i64::MAX)),
+    lit_integer_hex_min: "-0x8000000000000000" => Ok(Integer(i64::MIN)),
+    lit_integer_hex_overflow: "0x8000000000000000" => Err(Error::Syntax { message: "number too large to fit in target type".into(), line: 1, column: 8 }),
+    lit_integer_hex_empty: "0x" => Err(Error::Syntax { message: "Invalid hexadecimal literal 0x".into(), line: 1, column: 8 }),
 
     lit_string: "'Hi! 👋'" => Ok(String("Hi! 👋".into())),
     lit_string_escape: r#"'Try \n newlines and \t tabs'"# => Ok(String(r#"Try \n newlines and \t tabs"#.into())),
@@ -72,8 +90,8 @@ test_expr! {
     func_unknown_case: "UnKnown ( )" => Err(Error::Value("Unknown function unknown".into())),
     func_unknown_space: "unknown ( )" => Err(Error::Value("Unknown function unknown".into())),
     func_unknown_args: "unknown(a, b, c)" => Err(Error::Value("Unknown function unknown".into())),
-    func_unknown_open: "unknown(a, b, c" => Err(Error::Parse("Unexpected end of input".into())),
-    func_unknown_trailing_comma: "unknown(a, b, c,)" => Err(Error::Parse("Expected expression atom, found )".into())),
+    func_unknown_open: "unknown(a, b, c" => Err(Error::Syntax { message: "Unexpected end of input".into(), line: 1, column: 23 }),
+    func_unknown_trailing_comma: "unknown(a, b, c,)" => Err(Error::Syntax { message: "Expected expression atom, found )".into(), line: 1, column: 24 }),
 
     // Logical operators
     op_and_true_true: "TRUE AND TRUE" => Ok(Boolean(true)),
@@ -288,7 +306,77 @@ test_expr! {
     op_null_not: "NULL IS NOT NULL" => Ok(Boolean(false)),
     op_null_bool: "TRUE IS NULL" => Ok(Boolean(false)),
     op_null_bool_not: "TRUE IS NOT NULL" => Ok(Boolean(true)),
-    op_null_rhs_bool: "NULL IS TRUE" => Err(Error::Parse("Expected token NULL, found TRUE".into())),
+    op_null_rhs_bool: "NULL IS TRUE" => Err(Error::Syntax { message: "Expected token NULL, found TRUE".into(), line: 1, column: 16 }),
+
+    op_distinct: "1 IS DISTINCT FROM 2" => Ok(Boolean(true)),
+    op_distinct_equal: "1 IS DISTINCT FROM 1" => Ok(Boolean(false)),
+    op_distinct_float_int: "1.0 IS DISTINCT FROM 1" => Ok(Boolean(false)),
+    op_distinct_string: "'a' IS DISTINCT FROM 'b'" => Ok(Boolean(true)),
+    op_distinct_null: "NULL IS DISTINCT FROM NULL" => Ok(Boolean(false)),
+    op_distinct_null_int: "NULL IS DISTINCT FROM 1" => Ok(Boolean(true)),
+    op_distinct_int_null: "1 IS DISTINCT FROM NULL" => Ok(Boolean(true)),
+    op_distinct_not: "1 IS NOT DISTINCT FROM 1" => Ok(Boolean(true)),
+    op_distinct_not_null: "NULL IS NOT DISTINCT FROM NULL" => Ok(Boolean(true)),
+    op_distinct_not_null_int: "NULL IS NOT DISTINCT FROM 1" => Ok(Boolean(false)),
+    op_distinct_expr: "1 + 1 IS DISTINCT FROM 2 AND TRUE" => Ok(Boolean(false)),
+    op_distinct_is_null: "NULL IS NULL IS DISTINCT FROM NULL" => Ok(Boolean(true)),
+    op_distinct_row: "(1, NULL) IS DISTINCT FROM (1, NULL)" => Ok(Boolean(false)),
+    op_distinct_row_not: "(1, NULL) IS NOT DISTINCT FROM (1, 2)" => Ok(Boolean(false)),
+    op_distinct_missing_from: "1 IS DISTINCT 2" => Err(Error::Syntax { message: "Expected token FROM, found 2".into(), line: 1, column: 22 }),
+    op_distinct_conflict: "1 IS DISTINCT FROM 'a'" => Err(Error::Value("Can't compare 1 and a".into())),
+
+    op_between: "2 BETWEEN 1 AND 3" => Ok(Boolean(true)),
+    op_between_lower: "1 BETWEEN 1 AND 3" => Ok(Boolean(true)),
+    op_between_upper: "3 BETWEEN 1 AND 3" => Ok(Boolean(true)),
+    op_between_outside: "4 BETWEEN 1 AND 3" => Ok(Boolean(false)),
+    op_between_reversed: "2 BETWEEN 3 AND 1" => Ok(Boolean(false)),
+    op_between_float_int: "1.5 BETWEEN 1 AND 2" => Ok(Boolean(true)),
+    op_between_string: "'b' BETWEEN 'a' AND 'c'" => Ok(Boolean(true)),
+    op_between_null: "NULL BETWEEN 1 AND 3" => Ok(Null),
+    op_between_null_bound: "2 BETWEEN 1 AND NULL" => Ok(Null),
+    op_between_null_bound_false: "0 BETWEEN 1 AND NULL" => Ok(Boolean(false)),
+    op_between_not: "4 NOT BETWEEN 1 AND 3" => Ok(Boolean(true)),
+    op_between_not_inside: "2 NOT BETWEEN 1 AND 3" => Ok(Boolean(false)),
+    op_between_expr: "1 + 1 BETWEEN 0 + 1 AND 2 * 2 AND TRUE" => Ok(Boolean(true)),
+    op_between_missing_and: "2 BETWEEN 1" => Err(Error::Syntax { message: "Unexpected end of input".into(), line: 1, column: 19 }),
+    op_between_conflict: "1 BETWEEN 'a' AND 'b'" => Err(Error::Value("Can't compare 1 and a".into())),
+
+    op_in: "2 IN (1, 2, 3)" => Ok(Boolean(true)),
+    op_in_single: "2 IN (2)" => Ok(Boolean(true)),
+    op_in_missing: "4 IN (1, 2, 3)" => Ok(Boolean(false)),
+    op_in_expr: "2 IN (1 + 1, 5)" => Ok(Boolean(true)),
+    op_in_float_int: "2.0 IN (1, 2)" => Ok(Boolean(true)),
+    op_in_null: "NULL IN (1, 2)" => Ok(Null),
+    op_in_null_list: "2 IN (1, NULL, 2)" => Ok(Boolean(true)),
+    op_in_null_list_missing: "3 IN (1, NULL)" => Ok(Null),
+    op_in_not: "4 NOT IN (1, 2, 3)" => Ok(Boolean(true)),
+    op_in_not_found: "2 NOT IN (1, 2, 3)" => Ok(Boolean(false)),
+    op_in_not_null_list: "3 NOT IN (1, NULL)" => Ok(Null),
+    op_in_empty: "1 IN ()" => Err(Error::Syntax { message: "Expected expression atom, found )".into(), line: 1, column: 14 }),
+    op_in_no_parens: "1 IN 1" => Err(Error::Syntax { message: "Expected token (, found 1".into(), line: 1, column: 13 }),
+
+    op_row_eq: "(1, 'a') = (1, 'a')" => Ok(Boolean(true)),
+    op_row_eq_not: "(1, 'a') = (1, 'b')" => Ok(Boolean(false)),
+    op_row_eq_null: "(1, NULL) = (1, 2)" => Ok(Null),
+    op_row_eq_null_not: "(1, NULL) = (2, 2)" => Ok(Boolean(false)),
+    op_row_eq_nested: "((1, 2), 3) = ((1, 2), 3)" => Ok(Boolean(true)),
+    op_row_neq: "(1, 2) != (1, 3)" => Ok(Boolean(true)),
+    op_row_neq_not: "(1, 2) <> (1, 2)" => Ok(Boolean(false)),
+    op_row_lt: "(1, 2) < (1, 3)" => Ok(Boolean(true)),
+    op_row_lt_first: "(1, 5) < (2, 0)" => Ok(Boolean(true)),
+    op_row_lt_eq: "(1, 2) < (1, 2)" => Ok(Boolean(false)),
+    op_row_lte_eq: "(1, 2) <= (1, 2)" => Ok(Boolean(true)),
+    op_row_gt: "(2, 0, 0) > (1, 5, 5)" => Ok(Boolean(true)),
+    op_row_gt_not: "(1, 2, 3) > (1, 2, 4)" => Ok(Boolean(false)),
+    op_row_gte: "(1, 2, 4) >= (1, 2, 4)" => Ok(Boolean(true)),
+    op_row_between: "(1, 5) BETWEEN (1, 2) AND (2, 0)" => Ok(Boolean(true)),
+    op_row_in: "(1, 'b') IN ((1, 'a'), (1, 'b'))" => Ok(Boolean(true)),
+    op_row_in_missing: "(2, 'b') IN ((1, 'a'), (1, 'b'))" => Ok(Boolean(false)),
+    op_row_in_not: "(2, 'b') NOT IN ((1, 'a'), (1, 'b'))" => Ok(Boolean(true)),
+    op_row_size: "(1, 2) = (1, 2, 3)" => Err(Error::Value("Row values can only be compared with rows of the same size".into())),
+    op_row_scalar: "(1, 2) = 1" => Err(Error::Value("Row values can only be compared with rows of the same size".into())),
+    op_row_standalone: "(1, 2)" => Err(Error::Value("Row values are only supported in comparisons".into())),
+    op_row_arithmetic: "(1, 2) + 1" => Err(Error::Value("Row values are only supported in comparisons".into())),
 
     // Math operators
     op_add_float_float: "3.1 + 2.71" => Ok(Float(3.1 + 2.71)),
@@ -409,16 +497,17 @@ test_expr! {
     op_multiply_error_strings: "'a' * 'b'" => Err(Error::Value("Can't multiply a and b".into())),
 
     op_negate: "-1" => Ok(Integer(-1)),
-    op_negate_double: "--1" => Ok(Integer(1)),
+    op_negate_double: "- -1" => Ok(Integer(1)),
     op_negate_float: "-3.72" => Ok(Float(-3.72)),
     op_negate_mixed: "-+-+-1" => Ok(Integer(-1)),
-    op_negate_multi: "---1" => Ok(Integer(-1)),
+    op_negate_multi: "- - -1" => Ok(Integer(-1)),
     op_negate_null: "-NULL" => Ok(Null),
     op_negate_infinity: "-INFINITY" => Ok(Float(-std::f64::INFINITY)),
     op_negate_nan: "-NAN" => Ok(Float(std::f64::NAN)),
     op_negate_error_bool: "-TRUE" => Err(Error::Value("Can't negate TRUE".into())),
     op_negate_error_string: "-'abc'" => Err(Error::Value("Can't negate abc".into())),
 
+    op_subtract_comment: "3 --2" => Ok(Integer(3)),
     op_subtract_float_float: "3.1 - 2.71" => Ok(Float(3.1 - 2.71)),
     op_subtract_float_int: "3.72 - 1" => Ok(Float(3.72 - 1.0)),
     op_subtract_float_null: "3.14 - NULL" => Ok(Null),
@@ -585,3 +674,169 @@ test_expr! {
     op_prec_and_or: "FALSE AND TRUE OR TRUE" => Ok(Boolean(true)),
     op_prec_and_or_paren: "FALSE AND (TRUE OR TRUE)" => Ok(Boolean(false)),
 }
+
+#[test]
+// Expressions can be nested up to the maximum depth, beyond which they error
+// rather than risking a stack overflow.
+fn nesting_max_depth() -> Result<()> {
+    let depth = toydb::sql::parser::MAX_DEPTH;
+    let nested = |prefix: &str, atom: &str, suffix: &str, n: usize| {
+        format!("{}{}{}", prefix.repeat(n), atom, suffix.repeat(n))
+    };
+    let exceeded = |result: Result<Value>| match result {
+        Err(Error::Syntax { message, .. }) => message.starts_with("Nesting exceeds maximum depth"),
+        _ => false,
+    };
+
+    // The innermost minus is folded into the literal, so it doesn't nest.
+    assert_eq!(eval_expr(&nested("- ", "1", "", depth))?, Integer(1));
+    assert!(exceeded(eval_expr(&nested("- ", "1", "", depth + 1))));
+
+    assert_eq!(eval_expr(&nested("(", "1", ")", depth - 1))?, Integer(1));
+    assert!(exceeded(eval_expr(&nested("(", "1", ")", depth))));
+
+    assert_eq!(eval_expr(&nested("", "1", " + 1", depth - 2))?, Integer(depth as i64 - 1));
+    assert!(exceeded(eval_expr(&nested("", "1", " + 1", depth - 1))));
+    Ok(())
+}
+
+#[test]
+fn statement_max_length() -> Result<()> {
+    let length = toydb::sql::parser::MAX_STATEMENT_LENGTH;
+    let string = |n: usize| format!("'{}'", "a".repeat(n - "SELECT ''".len()));
+    assert_eq!(eval_expr(&string(length))?.to_string().len(), length - "SELECT ''".len());
+    assert_eq!(
+        eval_expr(&string(length + 1)),
+        Err(Error::Parse(format!(
+            "Statement length {} exceeds maximum of {} bytes",
+            length + 1,
+            length
+        )))
+    );
+    Ok(())
+}
+
+#[test]
+// Checks the logical, comparison, and NULL operators against the three-valued logic truth
+// tables, for all combinations of TRUE, FALSE, and NULL. They're evaluated as constants, as table
+// columns, and as columns compared with constants, in projections and in WHERE clauses, where only
+// TRUE predicates match and NOT of a NULL predicate still doesn't.
+fn truth_table() -> Result<()> {
+    fn and(a: &Value, b: &Value) -> Value {
+        match (a, b) {
+            (Boolean(false), _) | (_, Boolean(false)) => Boolean(false),
+            (Null, _) | (_, Null) => Null,
+            _ => Boolean(true),
+        }
+    }
+    fn or(a: &Value, b: &Value) -> Value {
+        match (a, b) {
+            (Boolean(true), _) | (_, Boolean(true)) => Boolean(true),
+            (Null, _) | (_, Null) => Null,
+            _ => Boolean(false),
+        }
+    }
+    fn equal(a: &Value, b: &Value) -> Value {
+        match (a, b) {
+            (Null, _) | (_, Null) => Null,
+            (a, b) => Boolean(a == b),
+        }
+    }
+    fn not_equal(a: &Value, b: &Value) -> Value {
+        not(&equal(a, b))
+    }
+    fn distinct(a: &Value, b: &Value) -> Value {
+        Boolean(a != b)
+    }
+    fn not_distinct(a: &Value, b: &Value) -> Value {
+        Boolean(a == b)
+    }
+    fn not(a: &Value) -> Value {
+        match a {
+            Boolean(a) => Boolean(!a),
+            _ => Null,
+        }
+    }
+    fn is_null(a: &Value) -> Value {
+        Boolean(a == &Null)
+    }
+    fn is_not_null(a: &Value) -> Value {
+        Boolean(a != &Null)
+    }
+    type Binary = fn(&Value, &Value) -> Value;
+    type Unary = fn(&Value) -> Value;
+    let binary: [(&str, Binary); 7] = [
+        ("{} AND {}", and),
+        ("{} OR {}", or),
+        ("{} = {}", equal),
+        ("{} != {}", not_equal),
+        ("{} <> {}", not_equal),
+        ("{} IS DISTINCT FROM {}", distinct),
+        ("{} IS NOT DISTINCT FROM {}", not_distinct),
+    ];
+    let unary: [(&str, Unary); 3] =
+        [("NOT {}", not), ("{} IS NULL", is_null), ("{} IS NOT NULL", is_not_null)];
+
+    let values = [Boolean(true), Boolean(false), Null];
+    let literal = |v: &Value| match v {
+        Boolean(true) => "TRUE",
+        Boolean(false) => "FALSE",
+        _ => "NULL",
+    };
+    let mut inserts = Vec::new();
+    let mut cases = Vec::new(); // (id, a, b)
+    for (i, a) in values.iter().enumerate() {
+        for (j, b) in values.iter().enumerate() {
+            let id = (i * values.len() + j) as i64;
+            inserts.push(format!("INSERT INTO t VALUES ({}, {}, {})", id, literal(a), literal(b)));
+            cases.push((id, a, b));
+        }
+    }
+    let mut setup = vec!["CREATE TABLE t (id INTEGER PRIMARY KEY, a BOOLEAN, b BOOLEAN)"];
+    setup.extend(inserts.iter().map(|s| s.as_str()));
+    let engine = super::setup(setup)?;
+    let query = |query: &str| -> Result<Vec<Vec<Value>>> {
+        match engine.session()?.execute(query)? {
+            ResultSet::Query { rows, .. } => rows.collect(),
+            result => panic!("Unexpected result {:?}", result),
+        }
+    };
+
+    let check = |format: &str, expect: &dyn Fn(&Value, &Value) -> Value| -> Result<()> {
+        let expr = |a: &str, b: &str| format.replacen("{}", a, 1).replacen("{}", b, 1);
+        for (_, a, b) in &cases {
+            let constant = expr(literal(a), literal(b));
+            assert_eq!(eval_expr(&constant)?, expect(a, b), "{}", constant);
+        }
+        // Compare columns with each other, and with each constant value.
+        let mut operands = vec![("b", None)];
+        operands.extend(values.iter().map(|v| (literal(v), Some(v))));
+        for (rhs, constant) in operands {
+            let expect = |a: &Value, b: &Value| expect(a, constant.unwrap_or(b));
+            let e = expr("a", rhs);
+            let projected = query(&format!("SELECT id, {} FROM t ORDER BY id", e))?;
+            let filtered = query(&format!("SELECT id FROM t WHERE {} ORDER BY id", e))?;
+            let negated = query(&format!("SELECT id FROM t WHERE NOT ({}) ORDER BY id", e))?;
+            let matching = |v: Value| {
+                cases
+                    .iter()
+                    .filter(|(_, a, b)| expect(a, b) == v)
+                    .map(|(id, _, _)| vec![Integer(*id)])
+                    .collect::<Vec<_>>()
+            };
+            let expect_projected: Vec<_> =
+                cases.iter().map(|(id, a, b)| vec![Integer(*id), expect(a, b)]).collect();
+            assert_eq!(projected, expect_projected, "SELECT {}", e);
+            assert_eq!(filtered, matching(Boolean(true)), "WHERE {}", e);
+            assert_eq!(negated, matching(Boolean(false)), "WHERE NOT ({})", e);
+        }
+        Ok(())
+    };
+    for (format, expect) in binary {
+        check(format, &expect)?;
+    }
+    for (format, expect) in unary {
+        check(format, &|a, _| expect(a))?;
+    }
+    Ok(())
+}
@@ -2,6 +2,7 @@
 use toydb::error::Result;
 use toydb::sql::engine::{Engine as _, Transaction as _};
 use toydb::sql::schema::Catalog as _;
+use toydb::sql::types::Value;
 
 use goldenfile::Mint;
 use std::io::Write;
@@ -69,6 +70,7 @@ test_mutation! { with [
     delete_all: "DELETE FROM test",
     delete_where: "DELETE FROM test WHERE id = 1",
     delete_where_and: "DELETE FROM test WHERE id = 1 AND name = 'a'",
+    delete_where_range: "DELETE FROM test WHERE id BETWEEN 2 AND 3",
     delete_where_expr: "DELETE FROM test WHERE id = 3 - 2 AND name LIKE 'a%'",
     delete_where_true: "DELETE FROM test WHERE TRUE",
     delete_where_false: "DELETE FROM test WHERE FALSE",
@@ -83,6 +85,15 @@ test_mutation! { with [
     delete_bare: "DELETE",
     delete_bare_from: "DELETE FROM",
     delete_bare_where: "DELETE FROM test WHERE",
+    delete_order: "DELETE FROM test ORDER BY id DESC",
+    delete_limit: "DELETE FROM test LIMIT 2",
+    delete_limit_zero: "DELETE FROM test LIMIT 0",
+    delete_limit_neg: "DELETE FROM test LIMIT -1",
+    delete_limit_dynamic: "DELETE FROM test LIMIT id",
+    delete_order_limit: "DELETE FROM test ORDER BY value DESC LIMIT 2",
+    delete_where_order_limit: "DELETE FROM test WHERE id > 1 ORDER BY name DESC LIMIT 1",
+    delete_order_missing_column: "DELETE FROM test ORDER BY missing LIMIT 1",
+    delete_bare_limit: "DELETE FROM test LIMIT",
 }
 
 test_mutation! { with [
@@ -132,6 +143,7 @@ test_mutation! { with [
     update_all: "UPDATE test SET name = 'x', value = 999",
     update_where: "UPDATE test SET name = 'x' WHERE id = 1",
     update_where_and: "UPDATE test SET name = 'x' WHERE id = 1 AND name = 'a'",
+    update_where_range: "UPDATE test SET name = 'x' WHERE id > 1",
     update_where_expr: "UPDATE test SET name = 'x' WHERE id = 3 - 2 AND name LIKE 'a%'",
     update_where_true: "UPDATE test SET name = 'x' WHERE TRUE",
     update_where_false: "UPDATE test SET name = 'x' WHERE FALSE",
@@ -149,4 +161,55 @@ test_mutation! { with [
     update_bare_set: "UPDATE test SET",
     update_bare_where: "UPDATE test SET name = 'x' WHERE",
     update_bare_no_table: "UPDATE",
+    update_limit: "UPDATE test SET name = 'x' LIMIT 2",
+    update_limit_neg: "UPDATE test SET name = 'x' LIMIT -1",
+    update_order_limit: "UPDATE test SET value = value + 1 ORDER BY value DESC LIMIT 2",
+    update_order_limit_key: "UPDATE test SET id = id + 10 ORDER BY id DESC LIMIT 2",
+    update_where_order_limit: "UPDATE test SET name = 'x' WHERE id < 3 ORDER BY id DESC LIMIT 1",
+}
+
+// Rows expire once their created column is more than a day in the past. 0 has long expired,
+// while 4102444800 is in the year 2100.
+test_mutation! { with [
+        "CREATE TABLE test (
+            id INTEGER PRIMARY KEY,
+            created INTEGER,
+            value STRING INDEX
+        ) WITH (ttl = '1 day', ttl_column = created)",
+        "INSERT INTO test VALUES (1, 0, 'a'), (2, 4102444800, 'a'), (3, NULL, 'b'), (4, 0, 'b')",
+    ];
+
+    ttl_delete: "DELETE FROM test",
+    ttl_delete_where: "DELETE FROM test WHERE id = 1 OR id = 2",
+    ttl_update: "UPDATE test SET value = 'x'",
+    ttl_update_index: "UPDATE test SET value = 'x' WHERE value = 'a'",
+    ttl_update_expire: "UPDATE test SET created = 0 WHERE id = 2",
+    ttl_insert_expired: "INSERT INTO test VALUES (1, 4102444800, 'c')",
+}
+
+/// Expired rows are physically deleted in batches.
+#[test]
+fn ttl_expire() -> Result<()> {
+    let engine = super::setup(vec![
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl = '7 days', ttl_column = created)",
+        "INSERT INTO test VALUES (1, 0), (2, 4102444800), (3, NULL), (4, 0), (5, 0)",
+        "CREATE TABLE other (id INTEGER PRIMARY KEY, created INTEGER)",
+        "INSERT INTO other VALUES (1, 0)",
+    ])?;
+    let mut session = engine.session()?;
+
+    let scan = |table: &str| -> Result<Vec<Value>> {
+        let txn = engine.begin_read_only()?;
+        let ids = txn.scan(table, None)?.map(|r| Ok(r?[0].clone())).collect();
+        txn.rollback()?;
+        ids
+    };
+
+    assert_eq!(session.expire(2)?, 2);
+    assert_eq!(scan("test")?, vec![Value::Integer(2), Value::Integer(3), Value::Integer(5)]);
+    assert_eq!(session.expire(2)?, 1);
+    assert_eq!(session.expire(2)?, 0);
+    assert_eq!(scan("test")?, vec![Value::Integer(2), Value::Integer(3)]);
+    assert_eq!(scan("other")?, vec![Value::Integer(1)]);
+    Ok(())
 }
@@ -1,13 +1,14 @@
 //! Tests for the SQL query engine. Runs SQL queries against an in-memory database,
 //! and compares the results with golden files stored under tests/sql/query/
 use toydb::error::{Error, Result};
-use toydb::sql::engine::{Engine, Transaction};
-use toydb::sql::execution::ResultSet;
+use toydb::sql::engine::{Engine, Session, Transaction, ROW_GROUP_SIZE};
+use toydb::sql::execution::{Memory, ResultSet};
 use toydb::sql::parser::Parser;
 use toydb::sql::plan::Plan;
 use toydb::sql::types::Row;
 
 use goldenfile::Mint;
+use std::collections::HashSet;
 use std::io::Write;
 
 macro_rules! test_query {
@@ -81,14 +82,14 @@ macro_rules! test_query {
             // First, just try to generate a plan and execute it
             let result = Parser::new($query).parse()
                 .and_then(|ast| Plan::build(ast, &mut txn))
-                .and_then(|plan| plan.optimize(&mut txn))
+                .and_then(|plan| plan.optimize(&mut txn, &HashSet::new()))
                 .and_then(|plan| {
                     write!(f, "Explain:\n{}\n\n", plan)?;
-                    plan.execute(&mut txn)
+                    plan.execute(&mut txn, &Memory::default())
                 });
 
             match result {
-                Ok(ResultSet::Query{columns, rows}) => {
+                Ok(ResultSet::Query{columns, rows, ..}) => {
                     let rows: Vec<Row> = match rows.collect() {
                         Ok(rows) => rows,
                         Err(err) => {
@@ -138,7 +139,7 @@ macro_rules! test_query {
             write!(f, "{:#?}\n\n", plan)?;
 
             write!(f, "Optimized plan: ")?;
-            let plan = match plan.optimize(&mut txn) {
+            let plan = match plan.optimize(&mut txn, &HashSet::new()) {
                 Ok(plan) => plan,
                 Err(err) => {
                     write!(f, "{:?}", err)?;
@@ -171,6 +172,22 @@ test_query! {
     field_unknown_qualified: "SELECT movies.unknown FROM movies",
     field_unknown_table: "SELECT unknown.id FROM movies",
     field_aliased: "SELECT m.id, g.id FROM movies AS m, genres g",
+    field_ambiguous_self_join: "SELECT name FROM genres a JOIN genres b ON a.id = b.id",
+    field_self_join: "SELECT a.name, b.name FROM genres a JOIN genres b ON a.id < b.id ORDER BY a.id, b.id",
+    field_unknown_self_join: "SELECT genres.name FROM genres a JOIN genres b ON a.id = b.id",
+
+    wildcard_qualified: "SELECT g.* FROM movies m JOIN genres g ON m.genre_id = g.id WHERE m.id <= 3",
+    wildcard_qualified_multi: "SELECT m.title, g.*, m.id FROM movies m JOIN genres g ON m.genre_id = g.id ORDER BY m.id LIMIT 3",
+    wildcard_self_join: "SELECT a.*, b.name FROM genres a JOIN genres b ON a.id = b.id - 1",
+    wildcard_mixed: "SELECT *, id * 10 AS tens FROM genres",
+    wildcard_mixed_after: "SELECT g.id * 10 AS tens, * FROM genres g, countries c WHERE g.id = 1",
+    wildcard_order: "SELECT g.* FROM genres g ORDER BY name DESC",
+    wildcard_values: "SELECT v.* FROM (VALUES (1, 'a')) AS v",
+    wildcard_alias: "SELECT g.* AS x FROM genres g",
+    wildcard_expression: "SELECT g.* + 1 FROM genres g",
+    wildcard_unknown: "SELECT x.* FROM genres",
+    wildcard_unknown_aliased: "SELECT genres.* FROM genres g",
+    wildcard_no_table: "SELECT *, 1",
 
     expr_dynamic: "SELECT 2020 - year AS age FROM movies",
     expr_static: "SELECT 1 + 2 * 3, 'abc' LIKE 'x%' AS nope",
@@ -188,11 +205,29 @@ test_query! {
     from_alias_duplicate: "SELECT * FROM movies a, genres a",
     from_alias_duplicate_join: "SELECT * FROM movies a JOIN genres a ON TRUE",
     from_duplicate: "SELECT * FROM movies, movies",
+    from_sample_system: "SELECT id, title FROM movies TABLESAMPLE SYSTEM (100)",
+    from_sample_bernoulli: "SELECT id, title FROM movies m TABLESAMPLE BERNOULLI (0) WHERE m.id > 3",
+    from_sample_repeatable: "SELECT COUNT(*) FROM movies TABLESAMPLE bernoulli (50.0) REPEATABLE (1 + 1)",
+    from_sample_join: "SELECT m.id, g.name FROM movies m TABLESAMPLE SYSTEM (100) JOIN genres g TABLESAMPLE BERNOULLI (100) ON m.genre_id = g.id WHERE g.id = 1",
+    from_sample_method: "SELECT * FROM movies TABLESAMPLE RANDOM (10)",
+    from_sample_percent_high: "SELECT * FROM movies TABLESAMPLE SYSTEM (101)",
+    from_sample_percent_negative: "SELECT * FROM movies TABLESAMPLE SYSTEM (-1)",
+    from_sample_percent_null: "SELECT * FROM movies TABLESAMPLE SYSTEM (NULL)",
+    from_sample_percent_field: "SELECT * FROM movies TABLESAMPLE SYSTEM (id)",
+    from_sample_seed_string: "SELECT * FROM movies TABLESAMPLE SYSTEM (10) REPEATABLE ('a')",
+    from_sample_information_schema: "SELECT * FROM information_schema.tables TABLESAMPLE SYSTEM (10)",
 
     where_bare: "SELECT * FROM movies WHERE",
     where_true: "SELECT * FROM movies WHERE TRUE",
     where_false: "SELECT * FROM movies WHERE FALSE",
     where_null: "SELECT * FROM movies WHERE NULL",
+    where_null_not_and: "SELECT id, ultrahd FROM movies WHERE NOT (ultrahd AND NULL)",
+    where_null_not_or: "SELECT id, ultrahd FROM movies WHERE NOT (ultrahd OR NULL)",
+    where_pk_eq_null: "SELECT * FROM movies WHERE id = NULL",
+    where_pk_eq_null_or: "SELECT * FROM movies WHERE id = NULL OR id = 1",
+    where_distinct: "SELECT id, ultrahd FROM movies WHERE ultrahd IS DISTINCT FROM TRUE",
+    where_distinct_not: "SELECT id, ultrahd FROM movies WHERE ultrahd IS NOT DISTINCT FROM NULL",
+    where_pk_distinct_not: "SELECT id, title FROM movies WHERE id IS NOT DISTINCT FROM 3",
     where_expr: "SELECT * FROM movies WHERE released >= 2000 AND ultrahd",
     where_float: "SELECT * FROM movies WHERE 3.14",
     where_integer: "SELECT * FROM movies WHERE 7",
@@ -228,6 +263,42 @@ test_query! {
     order_aggregate: "SELECT studio_id, MAX(rating) FROM movies GROUP BY studio_id ORDER BY MAX(rating)",
     order_aggregate_noselect: "SELECT studio_id, MAX(rating) FROM movies GROUP BY studio_id ORDER BY MIN(rating)",
     order_group_by_noselect: "SELECT MAX(rating) FROM movies GROUP BY studio_id ORDER BY studio_id",
+    order_nulls_first: "SELECT id, ultrahd FROM movies ORDER BY ultrahd NULLS FIRST, id",
+    order_nulls_last: "SELECT id, ultrahd FROM movies ORDER BY ultrahd ASC NULLS LAST, id",
+    order_nulls_lowercase: "SELECT id, ultrahd FROM movies ORDER BY ultrahd nulls last, id",
+    order_desc_nulls_first: "SELECT id, ultrahd FROM movies ORDER BY ultrahd DESC NULLS FIRST, id",
+    order_desc_nulls_last: "SELECT id, ultrahd FROM movies ORDER BY ultrahd DESC NULLS LAST, id",
+    order_multi_nulls: "SELECT id, ultrahd, rating FROM movies ORDER BY ultrahd DESC NULLS LAST, rating ASC, id DESC",
+    order_nulls_missing: "SELECT * FROM movies ORDER BY id NULLS",
+    order_nulls_invalid: "SELECT * FROM movies ORDER BY id NULLS MIDDLE",
+    order_key: "SELECT id, title FROM movies ORDER BY id",
+    order_key_desc: "SELECT id, title FROM movies ORDER BY id DESC",
+    order_key_desc_filter: "SELECT id, title, rating FROM movies WHERE rating > 8 ORDER BY id DESC",
+    order_key_desc_multi: "SELECT id, title FROM movies ORDER BY id DESC, title ASC",
+    order_key_range_desc: "SELECT id, title FROM movies WHERE id >= 3 AND id < 8 OR id > 9 ORDER BY id DESC LIMIT 4",
+    order_ordinality: "SELECT id, title FROM movies ORDER BY released DESC, id WITH ORDINALITY",
+    order_ordinality_offset: "SELECT id, title FROM movies ORDER BY id LIMIT 3 OFFSET 2 WITH ORDINALITY",
+    order_ordinality_hidden: "SELECT title FROM movies ORDER BY rating DESC WITH ORDINALITY",
+    order_ordinality_unordered: "SELECT * FROM movies WITH ORDINALITY",
+    order_ordinality_missing: "SELECT * FROM movies ORDER BY id WITH",
+    order_index_range: "SELECT id, studio_id FROM movies WHERE studio_id BETWEEN 2 AND 3 ORDER BY studio_id",
+    order_index_range_desc: "SELECT id, studio_id FROM movies WHERE studio_id BETWEEN 2 AND 3 ORDER BY studio_id DESC",
+    order_index_range_multi: "SELECT id, studio_id FROM movies WHERE studio_id BETWEEN 2 AND 3 ORDER BY studio_id DESC, id",
+}
+test_query! { with [
+        "CREATE TABLE items (id INTEGER PRIMARY KEY, category STRING, price FLOAT)",
+        "INSERT INTO items VALUES (1, 'b', 3.0), (2, 'a', 1.5), (3, NULL, 2.0), (4, 'b', NULL), (5, 'a', 4.0), (6, 'b', 1.0)",
+        "CREATE INDEX items_category_price ON items (category, price DESC)",
+    ];
+    order_index_ordered: "SELECT * FROM items ORDER BY category, price DESC",
+    order_index_ordered_reverse: "SELECT * FROM items ORDER BY category DESC, price ASC",
+    order_index_ordered_prefix: "SELECT id, category FROM items ORDER BY category DESC",
+    order_index_ordered_filter: "SELECT * FROM items WHERE price > 1.2 ORDER BY category, price DESC",
+    order_index_ordered_projection: "SELECT price, category FROM items ORDER BY category, price DESC",
+    order_index_ordered_limit: "SELECT * FROM items ORDER BY category DESC, price LIMIT 3",
+    order_index_ordered_mixed: "SELECT * FROM items ORDER BY category, price",
+    order_index_ordered_nulls: "SELECT * FROM items ORDER BY category NULLS LAST, price DESC",
+    order_index_ordered_second: "SELECT * FROM items ORDER BY price DESC",
 }
 test_query! { with [
         "CREATE TABLE booleans (id INTEGER PRIMARY KEY, value BOOLEAN)",
@@ -279,6 +350,17 @@ test_query! {
     limit_boolean: "SELECT * FROM movies LIMIT TRUE",
     limit_float: "SELECT * FROM movies LIMIT 3.14",
     limit_string: "SELECT * FROM movies LIMIT 'abc'",
+    limit_order: "SELECT id, title, rating FROM movies ORDER BY rating DESC, id LIMIT 3",
+    limit_order_offset: "SELECT id, title, rating FROM movies ORDER BY rating DESC, id LIMIT 3 OFFSET 2",
+    limit_order_offset_large: "SELECT id, rating FROM movies ORDER BY rating LIMIT 9223372036854775807 OFFSET 8",
+    limit_order_zero: "SELECT id, title FROM movies ORDER BY rating LIMIT 0",
+    limit_order_ties: "SELECT id, ultrahd FROM movies ORDER BY ultrahd DESC LIMIT 4",
+    limit_order_hidden: "SELECT title FROM movies ORDER BY rating DESC LIMIT 2",
+    limit_order_aggregate: "SELECT studio_id, COUNT(*) FROM movies GROUP BY studio_id ORDER BY COUNT(*) DESC, studio_id LIMIT 2",
+    limit_order_key: "SELECT id, title FROM movies ORDER BY id DESC LIMIT 2 OFFSET 1",
+    limit_order_index: "SELECT id, studio_id FROM movies WHERE studio_id BETWEEN 2 AND 3 ORDER BY studio_id DESC LIMIT 2",
+    limit_order_index_offset: "SELECT id, studio_id FROM movies WHERE studio_id BETWEEN 2 AND 3 ORDER BY studio_id LIMIT 1 OFFSET 1",
+    limit_order_index_filter: "SELECT id, studio_id FROM movies WHERE studio_id BETWEEN 2 AND 3 AND rating > 7.5 ORDER BY studio_id LIMIT 1",
 
     offset: "SELECT * FROM movies OFFSET 3",
     offset_zero: "SELECT * FROM movies OFFSET 0",
@@ -290,6 +372,23 @@ test_query! {
     offset_null: "SELECT * FROM movies OFFSET NULL",
     offset_boolean: "SELECT * FROM movies OFFSET TRUE",
     offset_float: "SELECT * FROM movies OFFSET 3.14",
+
+    distinct: "SELECT DISTINCT studio_id FROM movies ORDER BY studio_id",
+    distinct_multi: "SELECT DISTINCT studio_id, genre_id FROM movies ORDER BY studio_id, genre_id",
+    distinct_lowercase: "select distinct genre_id from movies order by genre_id",
+    distinct_star: "SELECT DISTINCT * FROM movies WHERE id < 3",
+    distinct_nulls: "SELECT DISTINCT ultrahd FROM movies ORDER BY ultrahd",
+    distinct_expr: "SELECT DISTINCT released / 10 * 10 AS decade FROM movies ORDER BY decade",
+    distinct_aggregate: "SELECT DISTINCT COUNT(*) AS count FROM movies GROUP BY studio_id ORDER BY count",
+    distinct_limit: "SELECT DISTINCT genre_id FROM movies ORDER BY genre_id DESC LIMIT 2 OFFSET 1",
+    distinct_order_noselect: "SELECT DISTINCT studio_id FROM movies ORDER BY rating",
+    distinct_on: "SELECT DISTINCT ON (studio_id) studio_id, title, rating FROM movies ORDER BY studio_id, rating DESC",
+    distinct_on_noselect: "SELECT DISTINCT ON (genre_id) title FROM movies ORDER BY genre_id, released",
+    distinct_on_multi: "SELECT DISTINCT ON (studio_id, genre_id) studio_id, genre_id, title FROM movies ORDER BY studio_id, genre_id, id DESC",
+    distinct_on_expr: "SELECT DISTINCT ON (released / 10) released, title FROM movies ORDER BY released / 10 DESC, rating",
+    distinct_on_limit: "SELECT DISTINCT ON (studio_id) studio_id, title FROM movies ORDER BY studio_id, id LIMIT 2",
+    distinct_on_empty: "SELECT DISTINCT ON () title FROM movies",
+    distinct_on_unknown: "SELECT DISTINCT ON (unknown) title FROM movies",
     offset_string: "SELECT * FROM movies OFFSET 'abc'",
 
     join_cross: "SELECT * FROM movies CROSS JOIN genres",
@@ -329,6 +428,20 @@ test_query! {
         WHERE m.studio_id = s.id
         ORDER BY m.rating DESC, m.released ASC, m.id ASC
     "#,
+    join_order_cross: r#"
+        SELECT m.title, c.name AS country, s.name AS studio
+        FROM movies m, countries c, studios s
+        WHERE m.studio_id = s.id AND s.country_id = c.id
+        ORDER BY m.title"#,
+    join_order_lookup: r#"
+        SELECT m.title, g.name AS genre, s.name AS studio
+        FROM movies m, genres g, studios s
+        WHERE m.genre_id = g.id AND m.studio_id = s.id AND s.id = 4
+        ORDER BY m.title"#,
+    join_order_written: r#"
+        SELECT m.title, g.name AS genre, s.name AS studio
+        FROM movies m JOIN genres g ON m.genre_id = g.id JOIN studios s ON m.studio_id = s.id
+        ORDER BY m.title"#,
 
     join_left: "SELECT m.id AS movie_id, g.id AS genre_id FROM movies m LEFT JOIN genres g ON m.id = g.id",
     join_left_all: "SELECT * FROM movies m LEFT JOIN genres g ON m.id = g.id",
@@ -340,6 +453,26 @@ test_query! {
     join_right_outer: "SELECT g.id AS genre_id, m.id AS movie_id FROM genres g RIGHT OUTER JOIN movies m ON m.id = g.id",
     join_right_truncate: "SELECT m.id AS movie_id, g.id AS genre_id FROM movies m RIGHT JOIN genres g ON m.id = g.id",
 
+    join_full: "SELECT g.id AS genre_id, m.id AS movie_id FROM genres g FULL JOIN movies m ON m.id = g.id + 2",
+    join_full_hash: "SELECT * FROM genres g FULL OUTER JOIN (VALUES (2, 'b'), (4, 'd'), (NULL, 'n')) AS v (id, label) ON g.id = v.id",
+    join_full_where: "SELECT g.id AS genre_id, m.id AS movie_id FROM genres g FULL JOIN movies m ON m.id = g.id + 2 WHERE g.id IS NULL OR m.id IS NULL",
+    join_left_on_left: "SELECT m.id AS movie_id, g.id AS genre_id FROM movies m LEFT JOIN genres g ON m.id = g.id AND m.id > 1",
+    join_left_where_null: "SELECT m.id AS movie_id, g.id AS genre_id FROM movies m LEFT JOIN genres g ON m.id = g.id WHERE g.id IS NULL",
+
+    join_using: "SELECT id, g.name, m.title FROM genres g JOIN movies m USING (id)",
+    join_using_all: "SELECT * FROM genres g LEFT JOIN (VALUES (2, 'b'), (3, 'c')) AS v (id, label) USING (id)",
+    join_using_right: "SELECT * FROM (VALUES (2, 'b'), (4, 'd')) AS v (id, label) RIGHT JOIN genres g USING (id)",
+    join_using_full: "SELECT * FROM genres g FULL JOIN (VALUES (2, 'b'), (4, 'd')) AS v (id, label) USING (id) ORDER BY id",
+    join_using_qualified: "SELECT g.id, v.id, v.label FROM genres g JOIN (VALUES (2, 'b')) AS v (id, label) USING (id)",
+    join_using_multi: "SELECT * FROM genres a JOIN genres b USING (id, name) JOIN genres c USING (id)",
+    join_using_ambiguous: "SELECT * FROM genres a JOIN genres b USING (id) JOIN genres c USING (id, name)",
+    join_using_duplicate: "SELECT * FROM genres a JOIN genres b USING (id, id)",
+    join_using_unknown: "SELECT * FROM genres g JOIN movies m USING (title)",
+    join_natural: "SELECT * FROM genres NATURAL JOIN (VALUES (1, 'Action'), (2, 'Action')) AS v (id, name)",
+    join_natural_left: "SELECT * FROM genres NATURAL LEFT JOIN (VALUES (2, 'Action')) AS v (id, name)",
+    join_natural_none: "SELECT * FROM genres NATURAL JOIN (VALUES (1)) AS v (x)",
+    join_natural_cross: "SELECT * FROM genres NATURAL CROSS JOIN movies",
+
     agg_count_star: "SELECT COUNT(*) FROM movies",
     agg_expr: "SELECT SUM(rating * 10) / COUNT(*) FROM movies",
     agg_nested: "SELECT MAX(MIN(rating)) FROM movies",
@@ -401,6 +534,16 @@ test_query! {
     group_expr_aggr_selfref: "SELECT studio_id, SUM(rating * 10) / COUNT(*) + studio_id FROM movies GROUP BY studio_id ORDER BY studio_id",
     group_expr_aggr_nogroupref: "SELECT studio_id, SUM(rating * 10) / COUNT(*) + id FROM movies GROUP BY studio_id ORDER BY studio_id",
     group_expr_multigroup: "SELECT studio_id + genre_id AS multi, MAX(rating) AS rating FROM movies GROUP BY studio_id, genre_id ORDER BY rating, multi",
+    group_expr_nested: "SELECT (released / 10) * 10 AS decade, COUNT(*) FROM movies GROUP BY released / 10 ORDER BY decade",
+    group_expr_order: "SELECT COUNT(*) FROM movies GROUP BY released / 10 ORDER BY released / 10 DESC",
+    group_expr_having: "SELECT COUNT(*) AS count FROM movies GROUP BY released / 10 HAVING released / 10 > 200 ORDER BY count",
+    group_expr_unknown: "SELECT released FROM movies GROUP BY released / 10",
+    group_aggregate_label: "SELECT COUNT(*) AS count FROM movies GROUP BY count",
+    group_ordinal: "SELECT studio_id, MAX(rating) FROM movies GROUP BY 1 ORDER BY studio_id",
+    group_ordinal_expr: "SELECT genre_id, studio_id * 2 AS twice, COUNT(*) FROM movies GROUP BY 2, 1 ORDER BY genre_id, twice",
+    group_ordinal_aggregate: "SELECT studio_id, MAX(rating) FROM movies GROUP BY 2",
+    group_ordinal_zero: "SELECT studio_id FROM movies GROUP BY 0",
+    group_ordinal_range: "SELECT studio_id FROM movies GROUP BY 2",
 
     having: "SELECT studio_id, MAX(rating) AS rating FROM movies GROUP BY studio_id HAVING rating > 8 ORDER BY studio_id",
     having_aggr: "SELECT studio_id, MAX(rating) FROM movies GROUP BY studio_id HAVING MIN(rating) > 7 ORDER BY studio_id",
@@ -410,3 +553,298 @@ test_query! {
     having_noselect: "SELECT studio_id FROM movies GROUP BY studio_id HAVING MAX(rating) > 8 ORDER BY studio_id",
     having_noaggr: "SELECT studio_id, MAX(rating) AS rating FROM movies GROUP BY studio_id HAVING studio_id >= 3 ORDER BY studio_id",
 }
+test_query! {
+    comment: "SELECT /* a comment */ id FROM movies /* another */ WHERE id = 1",
+    comment_unterminated: "SELECT id FROM movies /* a comment",
+    comment_line: "SELECT id, -- a comment\n title FROM movies -- another /* comment\n WHERE id = 1 -- end",
+    comment_line_string: "SELECT '-- not a comment' AS \"-- nor this\" FROM movies WHERE id = 1",
+
+    quoted_ident: r#"SELECT id AS "Select", title AS "Mixed ""Case""" FROM movies AS "Order" WHERE "Order".ID = 1"#,
+    quoted_ident_case: r#"SELECT "ID" FROM movies"#,
+
+    hint_no_index: "SELECT /*+ NO_INDEX(movies) */ * FROM movies WHERE id = 3 AND genre_id = 1",
+    hint_no_index_all: "SELECT /*+ NO_INDEX */ * FROM movies WHERE id = 3 AND genre_id = 1",
+    hint_no_index_alias: "SELECT /*+ NO_INDEX(m) */ m.id, g.name FROM movies m JOIN genres g ON m.genre_id = g.id WHERE m.id = 3 AND g.id = 1",
+    hint_no_hash_join: "SELECT /*+ NO_HASH_JOIN */ m.id, g.name FROM movies m JOIN genres g ON m.genre_id = g.id",
+    hint_multiple: "SELECT /*+ NO_INDEX(movies, genres), NO_HASH_JOIN */ movies.id, genres.name FROM movies JOIN genres ON movies.genre_id = genres.id WHERE genres.id = 1",
+    hint_no_join_order: r#"
+        SELECT /*+ NO_JOIN_ORDER */ m.title, c.name AS country, s.name AS studio
+        FROM movies m, countries c, studios s
+        WHERE m.studio_id = s.id AND s.country_id = c.id
+        ORDER BY m.title"#,
+    hint_override: "SELECT /*+ NO_HASH_JOIN HASH_JOIN */ m.id, g.name FROM movies m JOIN genres g ON m.genre_id = g.id",
+    hint_unknown: "SELECT /*+ UNKNOWN */ * FROM movies",
+    hint_invalid: "SELECT /*+ NO_INDEX(movies */ * FROM movies",
+    hint_misplaced: "SELECT * FROM movies /*+ NO_INDEX */",
+}
+test_query! {
+    subquery_in: "SELECT * FROM movies WHERE studio_id IN (SELECT id FROM studios WHERE country_id = 'us') ORDER BY id",
+    subquery_in_and: "SELECT id, title FROM movies WHERE rating > 8 AND genre_id IN (SELECT id FROM genres WHERE name = 'Science Fiction')",
+    subquery_in_correlated: "SELECT name FROM studios s WHERE 1 IN (SELECT genre_id FROM movies WHERE studio_id = s.id AND rating > 7)",
+    subquery_in_multiple_columns: "SELECT * FROM movies WHERE studio_id IN (SELECT id, name FROM studios)",
+    subquery_in_nested: "SELECT name FROM countries WHERE id IN (SELECT country_id FROM studios WHERE id IN (SELECT studio_id FROM movies WHERE rating > 8))",
+    subquery_in_not_subquery: "SELECT * FROM movies WHERE id IN id",
+    subquery_in_or: "SELECT * FROM movies WHERE id = 1 OR id IN (SELECT id FROM genres)",
+    subquery_not_in: "SELECT name FROM studios WHERE id NOT IN (SELECT studio_id FROM movies WHERE genre_id = 3)",
+    subquery_exists: "SELECT name FROM genres g WHERE EXISTS (SELECT * FROM movies WHERE genre_id = g.id AND rating > 8)",
+    subquery_exists_uncorrelated: "SELECT name FROM genres WHERE EXISTS (SELECT * FROM movies WHERE rating > 9)",
+    subquery_not_exists: "SELECT name FROM countries c WHERE NOT EXISTS (SELECT * FROM studios s JOIN movies m ON m.studio_id = s.id WHERE s.country_id = c.id AND m.released < 2000)",
+    subquery_correlated_aggregate: "SELECT name FROM genres g WHERE EXISTS (SELECT MAX(rating) FROM movies WHERE genre_id = g.id)",
+    subquery_correlated_inequality: "SELECT name FROM genres g WHERE EXISTS (SELECT * FROM movies WHERE genre_id > g.id)",
+    subquery_correlated_distinct: "SELECT name FROM studios s WHERE 1 IN (SELECT DISTINCT genre_id FROM movies WHERE studio_id = s.id) ORDER BY name",
+    subquery_correlated_distinct_on: "SELECT name FROM studios s WHERE 1 IN (SELECT DISTINCT ON (rating) genre_id FROM movies WHERE studio_id = s.id)",
+    subquery_select: "SELECT (SELECT 1) FROM movies",
+    subquery_scalar_correlated: "SELECT title, (SELECT name FROM genres WHERE id = m.genre_id) AS genre FROM movies m ORDER BY id",
+    subquery_scalar_correlated_count: "SELECT name, (SELECT COUNT(*) FROM movies WHERE studio_id = s.id) AS movies FROM studios s ORDER BY id",
+    subquery_scalar_correlated_limit: "SELECT name, (SELECT title FROM movies WHERE studio_id = s.id LIMIT 1) FROM studios s",
+    subquery_scalar_correlated_max: "SELECT name, (SELECT MAX(rating) FROM movies WHERE genre_id = g.id AND rating > 8) AS best FROM genres g ORDER BY id",
+    subquery_scalar_correlated_multiple_rows: "SELECT name, (SELECT title FROM movies WHERE studio_id = s.id) FROM studios s",
+    subquery_scalar_empty: "SELECT id, (SELECT name FROM genres WHERE id > 10) FROM movies WHERE id < 3",
+    subquery_scalar_expression: "SELECT id, rating - (SELECT MIN(rating) FROM movies) AS diff FROM movies WHERE id < 4",
+    subquery_scalar_group: "SELECT studio_id, COUNT(*) * (SELECT 10) FROM movies GROUP BY studio_id ORDER BY studio_id",
+    subquery_scalar_having: "SELECT studio_id FROM movies GROUP BY studio_id HAVING COUNT(*) > (SELECT 1)",
+    subquery_scalar_in: "SELECT (SELECT 1) IN (SELECT id FROM genres) FROM movies",
+    subquery_scalar_multiple_columns: "SELECT (SELECT id, name FROM genres WHERE id = 1) FROM movies",
+    subquery_scalar_multiple_rows: "SELECT (SELECT id FROM genres) FROM movies",
+    subquery_scalar_where: "SELECT title, rating FROM movies WHERE released > 2000 AND rating > (SELECT AVG(rating) FROM movies) ORDER BY id",
+    subquery_scalar_where_correlated: "SELECT title FROM movies m WHERE rating = (SELECT MAX(rating) FROM movies WHERE genre_id = m.genre_id) ORDER BY title",
+    subquery_scalar_where_or: "SELECT id FROM movies WHERE id = 1 OR rating > (SELECT MAX(rating) - 0.5 FROM movies)",
+}
+test_query! {
+    range_key: "SELECT id, title FROM movies WHERE id >= 3 AND id < 6",
+    range_key_between: "SELECT id, title FROM movies WHERE id BETWEEN 3 AND 5",
+    range_key_not_between: "SELECT id, title FROM movies WHERE id NOT BETWEEN 3 AND 8",
+    range_key_multiple: "SELECT id, title FROM movies WHERE id BETWEEN 2 AND 3 OR id > 8 OR id = 5",
+    range_key_empty: "SELECT id, title FROM movies WHERE id > 5 AND id < 3",
+    range_key_mismatched_type: "SELECT id, title FROM movies WHERE id > 2.5 AND id < 5",
+    range_key_alias: "SELECT m.id, g.name FROM movies m JOIN genres g ON m.genre_id = g.id WHERE m.id > 8",
+    range_index: "SELECT id, title, studio_id FROM movies WHERE studio_id BETWEEN 2 AND 3",
+    range_index_unselective: "SELECT id, title, studio_id FROM movies WHERE studio_id > 2",
+    range_index_string: "SELECT * FROM studios WHERE country_id >= 'a' AND country_id < 's'",
+    range_index_no_index: "SELECT /*+ NO_INDEX */ id, title, studio_id FROM movies WHERE studio_id BETWEEN 2 AND 3",
+    in_list_key: "SELECT id, title FROM movies WHERE id IN (1, 3, 5)",
+    in_list_index: "SELECT id, title FROM movies WHERE genre_id IN (2, 3) ORDER BY id",
+    in_list_filter: "SELECT id, title FROM movies WHERE title IN ('Heat', 'Primer', 'Unknown')",
+    in_list_not: "SELECT id, title FROM movies WHERE genre_id NOT IN (1, 2)",
+    row_eq: "SELECT id, title FROM movies WHERE (studio_id, genre_id) = (4, 3)",
+    row_in: "SELECT id, title FROM movies WHERE (studio_id, genre_id) IN ((1, 1), (4, 3)) ORDER BY id",
+    row_keyset: "SELECT id, title, released FROM movies WHERE (released, id) > (2010, 3) ORDER BY released, id",
+    row_in_subquery: "SELECT id, title FROM movies WHERE (studio_id, genre_id) IN (SELECT id, 1 FROM studios WHERE country_id = 'us') ORDER BY id",
+    row_in_subquery_columns: "SELECT id FROM movies WHERE (studio_id, genre_id) IN (SELECT id FROM studios)",
+    row_not_in_subquery: "SELECT id FROM movies WHERE (studio_id, genre_id) NOT IN (SELECT id, 1 FROM studios)",
+    values: "SELECT * FROM (VALUES (1, 'a'), (2, 'b'), (3, NULL))",
+    values_alias: "SELECT v.id, v.name FROM (VALUES (1, 'a'), (2, 'b')) AS v (id, name) WHERE id > 1",
+    values_alias_partial: "SELECT * FROM (VALUES (1, 'a', TRUE)) v (id)",
+    values_expression: "SELECT column1 * 2 FROM (VALUES (1 + 1), (2 * 3)) ORDER BY column1 DESC",
+    values_join: "SELECT m.title, v.label FROM movies m JOIN (VALUES (1, 'first'), (2, 'second')) AS v (id, label) ON m.id = v.id ORDER BY m.id",
+    values_aggregate: "SELECT grp, SUM(n) FROM (VALUES ('a', 1), ('b', 2), ('a', 3)) AS v (grp, n) GROUP BY grp ORDER BY grp",
+    values_mismatched: "SELECT * FROM (VALUES (1, 'a'), (2))",
+    values_too_many_names: "SELECT * FROM (VALUES (1)) AS v (a, b)",
+    values_field: "SELECT * FROM (VALUES (id)) AS v",
+
+    generate_series: "SELECT * FROM generate_series(1, 5)",
+    generate_series_step: "SELECT * FROM generate_series(10, 1, -3) AS s (n)",
+    generate_series_alias: "SELECT s, s * 2 FROM generate_series(1, 3) s",
+    generate_series_empty: "SELECT * FROM generate_series(5, 1)",
+    generate_series_null: "SELECT * FROM generate_series(1, NULL)",
+    generate_series_lateral: "SELECT g.id, s.n FROM genres g, LATERAL generate_series(1, g.id) AS s (n) WHERE g.id <= 2 ORDER BY g.id, s.n",
+    generate_series_join: "SELECT m.id, n FROM movies m JOIN generate_series(1, m.genre_id) AS s (n) ON n > 1 WHERE m.id <= 3 ORDER BY m.id, n",
+    generate_series_outer_join: "SELECT * FROM movies m LEFT JOIN generate_series(1, 2) ON TRUE",
+    generate_series_zero_step: "SELECT * FROM generate_series(1, 5, 0)",
+    generate_series_float: "SELECT * FROM generate_series(1, 2.5)",
+    generate_series_arguments: "SELECT * FROM generate_series(1)",
+    generate_series_columns: "SELECT * FROM generate_series(1, 2) AS s (a, b)",
+    table_function_unknown: "SELECT * FROM unknown(1, 2)",
+    lateral_table: "SELECT * FROM movies, LATERAL genres",
+}
+test_query! { with [
+        "CREATE TABLE cover (id INTEGER PRIMARY KEY, name STRING INDEX INCLUDE (value), value INTEGER, other INTEGER)",
+        "INSERT INTO cover VALUES (1, 'a', 10, 1), (2, 'b', 20, 2), (3, 'b', 30, 3), (4, 'c', 40, 4), (5, NULL, 50, 5), (6, 'd', NULL, 6)",
+    ];
+    index_only_lookup: "SELECT id, name, value FROM cover WHERE name = 'b'",
+    index_only_lookup_multiple: "SELECT id, value FROM cover WHERE name = 'c' OR name = 'a'",
+    index_only_lookup_null: "SELECT id, value FROM cover WHERE name IS NULL",
+    index_only_lookup_eq_null: "SELECT id, value FROM cover WHERE name = NULL",
+    index_only_lookup_distinct: "SELECT id, value FROM cover WHERE name IS NOT DISTINCT FROM 'b' OR name IS NOT DISTINCT FROM NULL",
+    index_only_distinct: "SELECT id, name FROM cover WHERE name IS DISTINCT FROM 'b' ORDER BY id",
+    index_only_range: "SELECT name, value FROM cover WHERE name BETWEEN 'b' AND 'c'",
+    index_only_filter: "SELECT id, value * 2 FROM cover WHERE name = 'b' AND value > 20",
+    index_only_order: "SELECT id, value FROM cover WHERE name = 'b' ORDER BY value DESC",
+    index_only_order_index: "SELECT name, value FROM cover WHERE name BETWEEN 'b' AND 'c' ORDER BY name DESC",
+    index_only_order_nulls_first: "SELECT id, name FROM cover WHERE name IS NULL OR name IN ('c', 'd') ORDER BY name",
+    index_only_order_nulls_last: "SELECT id, name FROM cover WHERE name IS NULL OR name IN ('c', 'd') ORDER BY name NULLS LAST",
+    index_only_order_limit: "SELECT name, value FROM cover WHERE name BETWEEN 'b' AND 'c' ORDER BY name DESC LIMIT 2",
+    index_only_order_unsorted: "SELECT id, name FROM cover WHERE name IN ('d', 'c') ORDER BY name",
+    index_only_order_desc_nulls_last: "SELECT id, name FROM cover WHERE name IS NULL OR name IN ('c', 'd') ORDER BY name DESC",
+    index_only_aggregate: "SELECT name, SUM(value) FROM cover WHERE name IN ('a', 'b') GROUP BY name ORDER BY name",
+    index_only_uncovered: "SELECT id, other FROM cover WHERE name = 'b' ORDER BY id",
+    index_only_uncovered_filter: "SELECT id FROM cover WHERE name = 'b' AND other > 2",
+    index_only_all: "SELECT * FROM cover WHERE name = 'b' ORDER BY id",
+    index_only_join: "SELECT c.id, g.name FROM cover c JOIN genres g ON c.id = g.id WHERE c.name = 'b' ORDER BY c.id",
+    index_only_no_index: "SELECT /*+ NO_INDEX */ id, value FROM cover WHERE name = 'b'",
+}
+test_query! { with [
+        "CREATE TABLE ranges (id INTEGER PRIMARY KEY, f FLOAT INDEX)",
+        "INSERT INTO ranges VALUES (1, -INFINITY), (2, -1.5), (3, 0.0), (4, 1.0), (5, 2.5), (6, INFINITY), (7, NAN), (8, NULL)",
+    ];
+    range_index_float: "SELECT * FROM ranges WHERE f >= -1 AND f < 2",
+    range_index_float_nan: "SELECT * FROM ranges WHERE f > 0 AND f <= INFINITY",
+    order_float_nan: "SELECT /*+ NO_INDEX */ * FROM ranges ORDER BY f",
+    order_float_nan_desc: "SELECT * FROM ranges ORDER BY f DESC NULLS LAST",
+}
+test_query! { with [
+        "CREATE TABLE nulls (id INTEGER PRIMARY KEY, value INTEGER)",
+        "INSERT INTO nulls VALUES (1, 1), (2, NULL), (3, 3)",
+    ];
+    subquery_in_null: "SELECT id FROM nulls WHERE value IN (SELECT value FROM nulls WHERE id > 1)",
+    subquery_not_in_null: "SELECT id FROM genres WHERE id NOT IN (SELECT value FROM nulls)",
+    subquery_not_in_nonnull: "SELECT id FROM genres WHERE id NOT IN (SELECT value FROM nulls WHERE value IS NOT NULL)",
+    subquery_not_in_empty: "SELECT id FROM nulls WHERE value NOT IN (SELECT id FROM genres WHERE id > 3)",
+    subquery_not_in_correlated: "SELECT id FROM nulls n WHERE id NOT IN (SELECT id FROM genres WHERE id = n.value)",
+}
+test_query! { with [
+        "CREATE FUNCTION twice(x INTEGER) RETURNS INTEGER AS 'SELECT x * 2'",
+        "CREATE FUNCTION decade(year INTEGER) RETURNS INTEGER AS 'SELECT year - year % 10'",
+        "CREATE FUNCTION within(x INTEGER, lo INTEGER, hi INTEGER) RETURNS BOOLEAN AS 'SELECT x >= lo AND x <= hi'",
+    ];
+    function: "SELECT id, twice(id) FROM movies WHERE id <= 3",
+    function_nested: "SELECT id, twice(twice(id)) AS quad FROM movies WHERE twice(id) < 6",
+    function_aggregate: "SELECT decade(released) AS d, COUNT(*) FROM movies GROUP BY decade(released) ORDER BY d",
+    function_aggregate_arg: "SELECT twice(MAX(rating)) FROM movies",
+    function_where: "SELECT title, released FROM movies WHERE within(released, 2000, 2010)",
+    function_subquery: "SELECT title FROM movies WHERE twice(genre_id) IN (SELECT twice(id) FROM genres WHERE id > 1)",
+    function_args_few: "SELECT twice() FROM movies",
+    function_args_many: "SELECT twice(1, 2) FROM movies",
+    function_unknown: "SELECT triple(id) FROM movies",
+}
+test_query! { with [
+        "CREATE TABLE metrics (id INTEGER PRIMARY KEY, host STRING INDEX, value FLOAT, note STRING) WITH (layout = 'columnar')",
+        "INSERT INTO metrics VALUES (1, 'a', 1.5, 'x'), (2, 'a', 2.5, NULL), (3, 'b', 3.0, 'y'), (4, 'b', NULL, 'z')",
+    ];
+    columnar_all: "SELECT * FROM metrics",
+    columnar_column: "SELECT value FROM metrics",
+    columnar_filter: "SELECT host FROM metrics WHERE value > 2",
+    columnar_aggregate: "SELECT host, SUM(value) FROM metrics GROUP BY host ORDER BY host",
+    columnar_index: "SELECT note FROM metrics WHERE host = 'b' ORDER BY id",
+    columnar_join: "SELECT m.id, g.name FROM metrics m JOIN genres g ON m.id = g.id",
+}
+
+/// Columnar tables must return the same results as row tables, across row group splits, updates,
+/// and deletes, with both serial and parallel execution.
+#[test]
+fn columnar() -> Result<()> {
+    let engine = super::setup(vec![
+        "CREATE TABLE r (id INTEGER PRIMARY KEY, k INTEGER NOT NULL INDEX, v FLOAT, s STRING)",
+        "CREATE TABLE c (id INTEGER PRIMARY KEY, k INTEGER NOT NULL INDEX, v FLOAT, s STRING) \
+         WITH (layout = 'columnar')",
+    ])?;
+    let mut session = engine.session()?;
+
+    // Insert rows in a scattered order, such that row groups are split in the middle.
+    let n = (ROW_GROUP_SIZE + ROW_GROUP_SIZE / 4) as i64;
+    let values: Vec<String> = (0..n)
+        .map(|i| i * 7919 % n)
+        .map(|i| match i % 10 {
+            0 => format!("({}, {}, NULL, 's{}')", i, i % 4, i % 3),
+            _ => format!("({}, {}, {:.1}, 's{}')", i, i % 4, (i % 7) as f64 / 2.0, i % 3),
+        })
+        .collect();
+    for table in ["r", "c"] {
+        for chunk in values.chunks(500) {
+            session.execute(&format!("INSERT INTO {} VALUES {}", table, chunk.join(", ")))?;
+        }
+        session
+            .execute(&format!("UPDATE {} SET v = v * 2, s = 'updated' WHERE id % 5 = 0", table))?;
+        session.execute(&format!("DELETE FROM {} WHERE id % 3 = 0", table))?;
+    }
+
+    let run = |session: &mut Session<_>, query: &str| -> Result<Vec<String>> {
+        match session.execute(query)? {
+            ResultSet::Query { rows, .. } => {
+                rows.map(|r| r.map(|r| format!("{:?}", r))).collect::<Result<Vec<_>>>()
+            }
+            r => Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        }
+    };
+    for query in [
+        "SELECT * FROM {}",
+        "SELECT v FROM {}",
+        "SELECT id, s FROM {} WHERE v > 2 AND k != 1",
+        "SELECT k, COUNT(*), COUNT(v), SUM(v), MIN(s) FROM {} GROUP BY k ORDER BY k",
+        "SELECT id, v FROM {} WHERE k = 3 ORDER BY id",
+        "SELECT * FROM {} WHERE id = 1 OR id = 1024",
+        "SELECT * FROM {} WHERE id >= 1000 AND id < 1100",
+        "SELECT id FROM {} ORDER BY id DESC LIMIT 5",
+    ] {
+        session.execute("SET parallelism = 1")?;
+        let expect = run(&mut session, &query.replace("{}", "r"))?;
+        assert!(!expect.is_empty(), "{}", query);
+        assert_eq!(run(&mut session, &query.replace("{}", "c"))?, expect, "{}", query);
+        session.execute("SET parallelism = 4")?;
+        assert_eq!(run(&mut session, &query.replace("{}", "c"))?, expect, "{}", query);
+    }
+    Ok(())
+}
+
+/// Parallel execution must return the same results as serial execution, in the same order
+/// unless aggregated.
+#[test]
+fn parallel() -> Result<()> {
+    let values: Vec<String> = (1..=100)
+        .map(|i| match i % 10 {
+            0 => format!("({}, {}, NULL)", i, i % 4),
+            _ => format!("({}, {}, {:.1})", i, i % 4, (i % 7) as f64 / 2.0),
+        })
+        .collect();
+    let insert = format!("INSERT INTO t VALUES {}", values.join(", "));
+    let engine = super::setup(vec![
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, k INTEGER NOT NULL, v FLOAT)",
+        &insert,
+    ])?;
+    let mut session = engine.session()?;
+
+    fn run<E: Engine + 'static>(
+        session: &mut Session<E>,
+        query: &str,
+        sort: bool,
+    ) -> Result<Vec<String>> {
+        let mut rows = match session.execute(query)? {
+            ResultSet::Query { rows, .. } => {
+                rows.map(|r| r.map(|r| format!("{:?}", r))).collect::<Result<Vec<_>>>()?
+            }
+            r => return Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        };
+        if sort {
+            rows.sort();
+        }
+        Ok(rows)
+    }
+
+    for (query, sort) in [
+        ("SELECT * FROM t", false),
+        ("SELECT id, v * 2 AS double FROM t WHERE k > 1 AND v IS NOT NULL", false),
+        ("SELECT k, COUNT(*), COUNT(v), SUM(v), AVG(v), MIN(v), MAX(id) FROM t GROUP BY k", true),
+        ("SELECT k, MAX(v) AS m FROM t WHERE id > 50 GROUP BY k HAVING m > 2 ORDER BY k", false),
+        ("SELECT COUNT(*), SUM(id) FROM t WHERE id < 0", false),
+        ("SELECT a.id, b.id FROM t a JOIN t b ON a.k = b.id ORDER BY a.id DESC LIMIT 10", false),
+        ("SELECT * FROM t WHERE id / (k - 2) > 100", false),
+    ] {
+        session.execute("SET parallelism = 1")?;
+        let expect = run(&mut session, query, sort);
+        session.execute("SET parallelism = 4")?;
+        assert_eq!(run(&mut session, query, sort), expect, "{}", query);
+    }
+
+    match session.execute("EXPLAIN SELECT k, COUNT(*) FROM t WHERE v > 1 GROUP BY k")? {
+        ResultSet::Explain(plan) => assert_eq!(
+            plan.to_string(),
+            "Projection: t.k, #0\n\
+             └─ Parallel: 4 workers\n   \
+                └─ Aggregation: count\n      \
+                   └─ Projection: TRUE, k\n         \
+                      └─ Scan: t (v > 1)"
+        ),
+        r => return Err(Error::Internal(format!("Unexpected result {:?}", r))),
+    }
+    Ok(())
+}
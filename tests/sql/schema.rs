@@ -2,9 +2,11 @@
 use toydb::error::Result;
 use toydb::sql::engine::{Engine as _, Transaction as _};
 use toydb::sql::schema::Catalog as _;
+use toydb::sql::types::Value;
 
 use goldenfile::Mint;
 use std::io::Write;
+use std::ops::Bound;
 
 macro_rules! test_schema {
     ( $( $name:ident: $query:expr, )* ) => {
@@ -44,6 +46,31 @@ macro_rules! test_schema {
                             write!(f, "{:?} => {:?}\n", value, pks)?;
                         }
                     }
+
+                    for column in table.columns.iter().filter(|c| !c.include.is_empty()) {
+                        write!(f, "\nCovering index {}.{}\n", table.name, column.name)?;
+                        let range = (Bound::Included(Value::Null), Bound::Unbounded);
+                        for row in txn.scan_index_cover(&table.name, &column.name, range)? {
+                            write!(f, "{:?}\n", row?)?;
+                        }
+                    }
+
+                    for index in txn.scan_indexes(&table.name)? {
+                        write!(f, "\n{}\n", index)?;
+                        for row in txn.scan_index_order(&table.name, &index.name, false)? {
+                            write!(f, "{:?}\n", row?)?;
+                        }
+                    }
+
+                    for trigger in txn.scan_triggers(&table.name)? {
+                        write!(f, "\n{}\n", trigger)?;
+                    }
+                }
+                for function in txn.scan_functions()? {
+                    write!(f, "\n{}\n", function)?;
+                }
+                for dropped in txn.scan_dropped_tables()? {
+                    write!(f, "\nDropped table {}\n", dropped.table.name)?;
                 }
                 txn.rollback()?;
 
@@ -80,6 +107,7 @@ test_schema! {
     create_table_name_japanese: "CREATE TABLE 表 (身元 INTEGER PRIMARY KEY, 名前 STRING)",
     create_table_name_keyword: "CREATE TABLE table (id INTEGER PRIMARY KEY)",
     create_table_name_keyword_quoted: r#"CREATE TABLE "table" (id INTEGER PRIMARY KEY)"#,
+    create_table_name_case_quoted: r#"CREATE TABLE "Movies" ("ID" INTEGER PRIMARY KEY, "Select" STRING, lower STRING)"#,
     create_table_name_missing: "CREATE TABLE (id INTEGER PRIMARY KEY)",
     create_table_name_quote_single: r#"CREATE TABLE 'name' (id INTEGER PRIMARY KEY)"#,
     create_table_name_quote_double: r#"CREATE TABLE "name" (id INTEGER PRIMARY KEY)"#,
@@ -115,6 +143,14 @@ test_schema! {
 
     create_table_index: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING INDEX)",
     create_table_index_pk: "CREATE TABLE name (id INTEGER PRIMARY KEY INDEX, value STRING)",
+    create_table_index_include: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING INDEX INCLUDE (a, b), a INTEGER, b BOOLEAN)",
+    create_table_index_include_empty: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING INDEX INCLUDE (), a INTEGER)",
+    create_table_index_include_missing: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING INDEX INCLUDE (a, missing), a INTEGER)",
+    create_table_index_include_self: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING INDEX INCLUDE (value))",
+    create_table_index_include_pk: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING INDEX INCLUDE (id))",
+    create_table_index_include_duplicate: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING INDEX INCLUDE (a, a), a INTEGER)",
+    create_table_index_include_pk_index: "CREATE TABLE name (id INTEGER PRIMARY KEY INDEX INCLUDE (value), value STRING)",
+    create_table_index_include_no_index: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING INCLUDE (a), a INTEGER)",
 
     create_table_unique: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING UNIQUE)",
     create_table_unique_null: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING NULL UNIQUE)",
@@ -149,6 +185,45 @@ test_schema! { with [
     drop_table_bare: "DROP TABLE",
     drop_table_missing: "DROP TABLE name",
     drop_table_multiple: "DROP TABLE a, c",
+    undrop_table_missing: "UNDROP TABLE a",
+}
+test_schema! { with [
+        "CREATE TABLE a (id INTEGER PRIMARY KEY, value STRING INDEX)",
+        "INSERT INTO a VALUES (11, 'x'), (12, 'y'), (13, 'x')",
+        "DROP TABLE a",
+    ];
+    undrop_table: "UNDROP TABLE a",
+    undrop_table_restore: "RESTORE TABLE a",
+    undrop_table_bare: "UNDROP a",
+    undrop_table_create: "CREATE TABLE a (id INTEGER PRIMARY KEY)",
+    undrop_table_dropped: "DROP TABLE a",
+}
+test_schema! { with [
+        "CREATE TABLE target (id INTEGER PRIMARY KEY)",
+        "INSERT INTO target VALUES (1), (2)",
+        "CREATE TABLE source (id INTEGER PRIMARY KEY, target_id INTEGER REFERENCES target)",
+        "INSERT INTO source VALUES (1, 1), (2, NULL)",
+        "DROP TABLE source",
+    ];
+    undrop_table_ref: "UNDROP TABLE source",
+}
+test_schema! { with [
+        "CREATE TABLE target (id INTEGER PRIMARY KEY)",
+        "INSERT INTO target VALUES (1), (2)",
+        "CREATE TABLE source (id INTEGER PRIMARY KEY, target_id INTEGER REFERENCES target)",
+        "INSERT INTO source VALUES (1, 1), (2, NULL)",
+        "DROP TABLE source",
+        "DELETE FROM target WHERE id = 1",
+    ];
+    undrop_table_ref_deleted: "UNDROP TABLE source",
+}
+test_schema! { with [
+        "CREATE TABLE target (id INTEGER PRIMARY KEY)",
+        "CREATE TABLE source (id INTEGER PRIMARY KEY, target_id INTEGER REFERENCES target)",
+        "DROP TABLE source",
+        "DROP TABLE target",
+    ];
+    undrop_table_ref_dropped: "UNDROP TABLE source",
 }
 test_schema! { with [
         "CREATE TABLE target (id INTEGER PRIMARY KEY)",
@@ -160,6 +235,148 @@ test_schema! { with [
     drop_table_ref_self: "DROP TABLE self",
 }
 
+test_schema! {
+    create_table_ttl: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl = '7 days', ttl_column = created)",
+    create_table_ttl_units: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl_column = created, ttl = '90 MINUTES')",
+    create_table_ttl_singular: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl = '1 week', ttl_column = created)",
+    create_table_ttl_seconds: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl = '3601 seconds', ttl_column = created)",
+    create_table_ttl_pk: "CREATE TABLE name (created INTEGER PRIMARY KEY) WITH (ttl = '1 hour', ttl_column = created)",
+    create_table_ttl_quoted: r#"CREATE TABLE name (id INTEGER PRIMARY KEY, "created at" INTEGER) WITH (ttl = '1 hour', ttl_column = "created at")"#,
+    create_table_ttl_zero: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl = '0 days', ttl_column = created)",
+    create_table_ttl_negative: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl = '-1 days', ttl_column = created)",
+    create_table_ttl_unit_missing: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl = '7', ttl_column = created)",
+    create_table_ttl_unit_unknown: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl = '7 fortnights', ttl_column = created)",
+    create_table_ttl_overflow: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl = '99999999999999999 weeks', ttl_column = created)",
+    create_table_ttl_integer: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl = 7, ttl_column = created)",
+    create_table_ttl_column_missing: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl = '7 days')",
+    create_table_ttl_column_only: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl_column = created)",
+    create_table_ttl_column_unknown: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl = '7 days', ttl_column = missing)",
+    create_table_ttl_column_string: "CREATE TABLE name (id INTEGER PRIMARY KEY, created STRING) WITH (ttl = '7 days', ttl_column = created)",
+    create_table_ttl_column_literal: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl = '7 days', ttl_column = 'created')",
+    create_table_ttl_duplicate: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (ttl = '7 days', ttl = '1 day', ttl_column = created)",
+    create_table_codec: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING) WITH (codec = 'compact')",
+    create_table_codec_default: "CREATE TABLE name (id INTEGER PRIMARY KEY) WITH (codec = 'BINCODE')",
+    create_table_codec_ttl: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (codec = 'compact', ttl = '7 days', ttl_column = created)",
+    create_table_codec_unknown: "CREATE TABLE name (id INTEGER PRIMARY KEY) WITH (codec = 'protobuf')",
+    create_table_codec_integer: "CREATE TABLE name (id INTEGER PRIMARY KEY) WITH (codec = 1)",
+    create_table_layout: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING) WITH (layout = 'columnar')",
+    create_table_layout_row: "CREATE TABLE name (id INTEGER PRIMARY KEY) WITH (layout = 'ROW')",
+    create_table_layout_ttl: "CREATE TABLE name (id INTEGER PRIMARY KEY, created INTEGER) WITH (layout = 'columnar', ttl = '7 days', ttl_column = created)",
+    create_table_layout_codec: "CREATE TABLE name (id INTEGER PRIMARY KEY) WITH (layout = 'columnar', codec = 'compact')",
+    create_table_layout_unknown: "CREATE TABLE name (id INTEGER PRIMARY KEY) WITH (layout = 'parquet')",
+    create_table_option_unknown: "CREATE TABLE name (id INTEGER PRIMARY KEY) WITH (foo = 1)",
+    create_table_option_empty: "CREATE TABLE name (id INTEGER PRIMARY KEY) WITH ()",
+    create_table_option_no_paren: "CREATE TABLE name (id INTEGER PRIMARY KEY) WITH ttl = '1 day'",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, value STRING)",
+        "CREATE TABLE audit (id INTEGER PRIMARY KEY, op STRING, value STRING)",
+    ];
+    create_trigger: "CREATE TRIGGER audit_insert AFTER INSERT ON test FOR EACH ROW EXECUTE (INSERT INTO audit VALUES (new.id, 'insert', new.value))",
+    create_trigger_before: "CREATE TRIGGER check_delete BEFORE DELETE ON test FOR EACH ROW EXECUTE (SELECT 1 / old.id)",
+    create_trigger_update: "CREATE TRIGGER audit_update AFTER UPDATE ON test FOR EACH ROW EXECUTE (UPDATE audit SET value = new.value WHERE id = old.id)",
+    create_trigger_subquery: "CREATE TRIGGER audit_insert AFTER INSERT ON test FOR EACH ROW EXECUTE (DELETE FROM audit WHERE id IN (SELECT id FROM test WHERE value = new.value))",
+    create_trigger_missing_table: "CREATE TRIGGER audit_insert AFTER INSERT ON missing FOR EACH ROW EXECUTE (SELECT 1)",
+    create_trigger_missing_column: "CREATE TRIGGER audit_insert AFTER INSERT ON test FOR EACH ROW EXECUTE (SELECT new.missing)",
+    create_trigger_insert_old: "CREATE TRIGGER audit_insert AFTER INSERT ON test FOR EACH ROW EXECUTE (SELECT old.id)",
+    create_trigger_delete_new: "CREATE TRIGGER audit_delete AFTER DELETE ON test FOR EACH ROW EXECUTE (SELECT new.id)",
+    create_trigger_ddl: "CREATE TRIGGER audit_insert AFTER INSERT ON test FOR EACH ROW EXECUTE (DROP TABLE audit)",
+    create_trigger_timing_missing: "CREATE TRIGGER audit_insert INSERT ON test FOR EACH ROW EXECUTE (SELECT 1)",
+    create_trigger_row_missing: "CREATE TRIGGER audit_insert AFTER INSERT ON test EXECUTE (SELECT 1)",
+    create_trigger_unterminated: "CREATE TRIGGER audit_insert AFTER INSERT ON test FOR EACH ROW EXECUTE (SELECT 1",
+}
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, value STRING)",
+        "CREATE TABLE audit (id INTEGER PRIMARY KEY, op STRING, value STRING)",
+        "CREATE TRIGGER audit_insert AFTER INSERT ON test FOR EACH ROW EXECUTE (INSERT INTO audit VALUES (new.id, 'insert', new.value))",
+    ];
+    create_trigger_exists: "CREATE TRIGGER audit_insert BEFORE INSERT ON test FOR EACH ROW EXECUTE (SELECT 1)",
+    create_trigger_other_table: "CREATE TRIGGER audit_insert AFTER INSERT ON audit FOR EACH ROW EXECUTE (SELECT new.op)",
+    drop_trigger: "DROP TRIGGER audit_insert ON test",
+    drop_trigger_missing: "DROP TRIGGER missing ON test",
+    drop_trigger_table_missing: "DROP TRIGGER audit_insert ON missing",
+    drop_table_trigger: "DROP TABLE test",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, a INTEGER, b STRING)",
+        "INSERT INTO test VALUES (1, 2, 'b'), (2, NULL, 'a'), (3, 1, 'c'), (4, 2, 'a'), (5, 1, NULL)",
+    ];
+    create_index: "CREATE INDEX test_a_b ON test (a DESC, b ASC)",
+    create_index_unnamed: "CREATE INDEX ON test (b, a)",
+    create_index_pk: "CREATE INDEX test_id ON test (id DESC)",
+    create_index_missing_table: "CREATE INDEX ON missing (a)",
+    create_index_missing_column: "CREATE INDEX ON test (a, missing)",
+    create_index_duplicate_column: "CREATE INDEX ON test (a, a DESC)",
+    create_index_empty: "CREATE INDEX ON test ()",
+    create_index_on_missing: "CREATE INDEX test_a test (a)",
+}
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, a INTEGER, b STRING)",
+        "INSERT INTO test VALUES (1, 2, 'b'), (2, NULL, 'a'), (3, 1, 'c'), (4, 2, 'a'), (5, 1, NULL)",
+        "CREATE INDEX test_a_b ON test (a DESC, b)",
+    ];
+    create_index_exists: "CREATE INDEX test_a_b ON test (b)",
+    drop_index: "DROP INDEX test_a_b ON test",
+    drop_index_missing: "DROP INDEX missing ON test",
+    drop_index_table_missing: "DROP INDEX test_a_b ON missing",
+    drop_table_index: "DROP TABLE test",
+    index_insert: "INSERT INTO test VALUES (6, 3, 'z'), (7, NULL, NULL), (8, 1, 'b')",
+    index_update: "UPDATE test SET a = 3 WHERE b = 'a'",
+    index_update_pk: "UPDATE test SET id = 9 WHERE id = 1",
+    index_delete: "DELETE FROM test WHERE a = 1",
+}
+
+test_schema! { with [
+        "CREATE TABLE src (id INTEGER PRIMARY KEY, name STRING, price FLOAT, active BOOLEAN NOT NULL)",
+        "INSERT INTO src VALUES (1, 'a', 1.5, TRUE), (2, 'b', NULL, FALSE), (3, NULL, 3.0, TRUE)",
+    ];
+    create_table_as: "CREATE TABLE dst AS SELECT * FROM src WHERE active",
+    create_table_as_expressions: "CREATE TABLE dst AS SELECT id * 10 AS id, name IS NULL AS unnamed, price + 1 AS price, NOT active AS inactive FROM src",
+    create_table_as_infer: "CREATE TABLE dst AS SELECT id, 2 ^ (id - 2) AS power FROM src",
+    create_table_as_values: "CREATE TABLE dst AS SELECT * FROM (VALUES (1, 2), (2, 2.5)) AS v (id, value)",
+    create_table_as_values_conflict: "CREATE TABLE dst AS SELECT * FROM (VALUES (1, 2), (2, 'b')) AS v (id, value)",
+    create_table_as_aggregate: "CREATE TABLE dst AS SELECT active, COUNT(*) AS count, AVG(price) AS avg FROM src GROUP BY active",
+    create_table_as_empty: "CREATE TABLE dst AS SELECT * FROM src WHERE FALSE",
+    create_table_as_exists: "CREATE TABLE src AS SELECT * FROM src",
+    create_table_as_unnamed: "CREATE TABLE dst AS SELECT id, price * 2 FROM src",
+    create_table_as_duplicate_column: "CREATE TABLE dst AS SELECT id, name, name FROM src",
+    create_table_as_duplicate_key: "CREATE TABLE dst AS SELECT active, id FROM src",
+    select_into: "SELECT id, name INTO dst FROM src WHERE price IS NOT NULL",
+    select_into_order: "SELECT name, id INTO dst FROM src WHERE name IS NOT NULL ORDER BY id DESC LIMIT 1",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, value STRING)",
+    ];
+    create_function: "CREATE FUNCTION twice(x INTEGER) RETURNS INTEGER AS 'SELECT x * 2'",
+    create_function_noargs: "CREATE FUNCTION answer() RETURNS INTEGER AS 'SELECT 42'",
+    create_function_multiple: "CREATE FUNCTION within(x INTEGER, lo INTEGER, hi INTEGER) RETURNS BOOLEAN AS 'SELECT x >= lo AND x <= hi'",
+    create_function_quoted: "CREATE FUNCTION blank(s STRING) RETURNS BOOLEAN AS 'SELECT s IS NULL OR s = '''''",
+    create_function_subquery: "CREATE FUNCTION count_test() RETURNS INTEGER AS 'SELECT (SELECT COUNT(*) FROM test)'",
+    create_function_builtin: "CREATE FUNCTION count(x INTEGER) RETURNS INTEGER AS 'SELECT x'",
+    create_function_duplicate_arg: "CREATE FUNCTION f(x INTEGER, x INTEGER) RETURNS INTEGER AS 'SELECT x'",
+    create_function_from: "CREATE FUNCTION f(x INTEGER) RETURNS INTEGER AS 'SELECT id FROM test'",
+    create_function_multiple_columns: "CREATE FUNCTION f(x INTEGER) RETURNS INTEGER AS 'SELECT x, x'",
+    create_function_insert: "CREATE FUNCTION f(x INTEGER) RETURNS INTEGER AS 'INSERT INTO test VALUES (x, NULL)'",
+    create_function_unknown_column: "CREATE FUNCTION f(x INTEGER) RETURNS INTEGER AS 'SELECT y'",
+    create_function_unknown_function: "CREATE FUNCTION f(x INTEGER) RETURNS INTEGER AS 'SELECT g(x)'",
+    create_function_recursive: "CREATE FUNCTION f(x INTEGER) RETURNS INTEGER AS 'SELECT f(x - 1)'",
+    create_function_body_missing: "CREATE FUNCTION f(x INTEGER) RETURNS INTEGER AS SELECT x",
+    create_function_returns_missing: "CREATE FUNCTION f(x INTEGER) AS 'SELECT x'",
+}
+test_schema! { with [
+        "CREATE FUNCTION twice(x INTEGER) RETURNS INTEGER AS 'SELECT x * 2'",
+        "CREATE FUNCTION quadruple(x INTEGER) RETURNS INTEGER AS 'SELECT twice(twice(x))'",
+    ];
+    create_function_exists: "CREATE FUNCTION twice(y FLOAT) RETURNS FLOAT AS 'SELECT y + y'",
+    create_function_dependency: "CREATE FUNCTION octuple(x INTEGER) RETURNS INTEGER AS 'SELECT twice(quadruple(x))'",
+    drop_function: "DROP FUNCTION quadruple",
+    drop_function_missing: "DROP FUNCTION missing",
+    drop_function_dependency: "DROP FUNCTION twice",
+}
+
 test_schema! { with [
         r#"CREATE TABLE types (
             id INTEGER PRIMARY KEY,
@@ -444,3 +661,18 @@ test_schema! { with [
     update_index_pk: "UPDATE test SET id = 4 WHERE id = 1",
     update_index_null: "UPDATE test SET name = NULL WHERE id = 3",
 }
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING INDEX INCLUDE (value), value INTEGER, other INTEGER)",
+        "INSERT INTO test VALUES (1, 'a', 101, 1), (2, 'b', 102, 2), (3, 'b', 103, 3)",
+    ];
+
+    delete_index_include: "DELETE FROM test WHERE id = 2",
+    delete_index_include_all: "DELETE FROM test",
+    insert_index_include: "INSERT INTO test VALUES (4, 'b', 104, 4)",
+    insert_index_include_null: "INSERT INTO test VALUES (4, NULL, NULL, 4)",
+    update_index_include_value: "UPDATE test SET value = 0 WHERE id = 3",
+    update_index_include_name: "UPDATE test SET name = 'c' WHERE id = 3",
+    update_index_include_other: "UPDATE test SET other = 0 WHERE id = 3",
+    update_index_include_pk: "UPDATE test SET id = 4 WHERE id = 1",
+}
@@ -2,7 +2,9 @@
 
 mod client;
 mod cluster;
+mod database;
 mod setup;
+mod slt;
 mod sql;
 
 use toydb::sql::execution::ResultSet;